@@ -0,0 +1,93 @@
+//! The steps the output of an engine goes through during an analysis: reading its
+//! `info` lines, converting the moves of a line to SAN, and the whole pipeline run on
+//! the output of a search, from reading it a line at a time to emitting the sets of
+//! lines, the emission itself being left out.
+//!
+//! `fixtures/stockfish_startpos_multipv3.txt` is shaped like the output of Stockfish 16
+//! analyzing the starting position for 5 seconds with MultiPV 3: 32 depths of lines,
+//! with the `currmove` lines and the lines failing high it also sends.
+
+use std::io::BufRead;
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use shakmaty::Chess;
+
+#[allow(dead_code)]
+#[path = "../src/uci.rs"]
+mod uci;
+
+const TRANSCRIPT: &[u8] = include_bytes!("fixtures/stockfish_startpos_multipv3.txt");
+
+/// A 40 moves line of the starting position
+const LONG_PV: &str = "e2e4 e7e5 g1f3 b8c6 f1b5 a7a6 b5a4 g8f6 e1g1 f8e7 f1e1 b7b5 a4b3 d7d6 \
+                       c2c3 e8g8 h2h3 c6a5 b3c2 c7c5 d2d4 d8c7 b1d2 c5d4 c3d4 a5c6 d2b3 a6a5 \
+                       c1e3 a5a4 b3d2 c8d7 a1c1 c7b7 d1e2 f8e8 c2d3 e7f8 d2f1 h7h6";
+
+const SHORT_LINE: &str =
+    "info depth 1 seldepth 1 multipv 1 score cp 18 nodes 20 nps 10000 tbhits 0 time 2 pv e2e4";
+
+fn long_line() -> String {
+    format!(
+        "info depth 40 seldepth 58 multipv 1 score cp 31 wdl 62 902 36 nodes 104857600 \
+         nps 1500000 hashfull 1000 tbhits 0 time 69905 pv {LONG_PV}"
+    )
+}
+
+/// The lines emitted for the transcript, converted like the analysis converts them
+fn process_transcript(mut output: &[u8], pos: &Chess) -> usize {
+    let mut sets = uci::LineSets::new(3);
+    let mut buffer = String::new();
+    let mut emitted = 0;
+    loop {
+        buffer.clear();
+        if output.read_line(&mut buffer).unwrap() == 0 {
+            break;
+        }
+        let line = buffer.trim_end_matches(&['\r', '\n'][..]);
+        if line.starts_with("bestmove") {
+            break;
+        }
+        let Some(line) = uci::parse_info(line).and_then(uci::EngineLine::from_info) else {
+            continue;
+        };
+        if sets.push(line) {
+            for line in sets.take_complete().unwrap() {
+                let moves = uci::pv_moves(pos, &line.pv, usize::MAX).unwrap();
+                black_box((moves, line.score, line.nodes, line.nps));
+                emitted += 1;
+            }
+        }
+    }
+    emitted
+}
+
+fn analysis_bench(c: &mut Criterion) {
+    let long_line = long_line();
+    let pos = Chess::default();
+    assert_eq!(uci::parse_info(&long_line).unwrap().pv, LONG_PV);
+    assert_eq!(
+        uci::pv_moves(&pos, LONG_PV, usize::MAX).unwrap().1.len(),
+        40
+    );
+    assert_eq!(process_transcript(TRANSCRIPT, &pos), 32 * 3);
+
+    let mut group = c.benchmark_group("parse_info");
+    group.bench_function("short line", |b| {
+        b.iter(|| uci::parse_info(black_box(SHORT_LINE)).map(|info| info.depth))
+    });
+    group.bench_function("40 moves line", |b| {
+        b.iter(|| uci::parse_info(black_box(&long_line)).map(|info| info.depth))
+    });
+    group.finish();
+
+    c.bench_function("SAN of a 40 moves line", |b| {
+        b.iter(|| uci::pv_moves(&pos, black_box(LONG_PV), usize::MAX))
+    });
+
+    c.bench_function("5 seconds of Stockfish output", |b| {
+        b.iter(|| process_transcript(black_box(TRANSCRIPT), &pos))
+    });
+}
+
+criterion_group!(benches, analysis_bench);
+criterion_main!(benches);
@@ -0,0 +1,63 @@
+//! Import of a file of 1000 games like the ones exported by online sites into a new
+//! database, read as a stream and mapped in memory, the way `convert_pgn` imports a
+//! file into a database.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use en_croissant_lib::import_into_memory;
+
+const OPENINGS: [&str; 4] = [
+    "1. e4 { [%clk 0:03:00] } e5 { [%clk 0:03:00] } 2. Nf3 Nc6 3. Bb5 a6 4. Ba4 Nf6 5. O-O Be7 \
+     6. Re1 b5 7. Bb3 d6 8. c3 O-O 9. h3 Na5 10. Bc2 c5 11. d4 Qc7 12. Nbd2 cxd4 13. cxd4 Nc6 \
+     14. Nb3 a5 15. Be3 a4 16. Nbd2 Bd7 17. Rc1 Qb7 18. Qe2 Rfe8 19. Bd3 Bf8 20. Nf1 h6",
+    "1. d4 Nf6 2. c4 e6 3. Nc3 Bb4 4. e3 O-O 5. Bd3 d5",
+    "1. e4 c5 2. Nf3 d6 3. d4 cxd4 4. Nxd4 Nf6 5. Nc3 a6 (5... g6 6. Be3 Bg7) 6. Be3 e5",
+    "1. c4 e5 2. Nc3 Nf6 3. g3 d5 4. cxd5 Nxd5 5. Bg2 Nb6",
+];
+
+const RESULTS: [&str; 3] = ["1-0", "0-1", "1/2-1/2"];
+
+fn generated_pgn(count: usize) -> String {
+    let mut pgn = String::new();
+    for index in 0..count {
+        let result = RESULTS[index % 3];
+        pgn += &format!(
+            "[Event \"Rated blitz game\"]\n[Site \"https://example.org/{index}\"]\n\
+             [Date \"2024.{:02}.{:02}\"]\n[Round \"-\"]\n[White \"player{}\"]\n\
+             [Black \"player{}\"]\n[Result \"{result}\"]\n[WhiteElo \"{}\"]\n\
+             [BlackElo \"{}\"]\n[TimeControl \"180+2\"]\n\n{} {result}\n\n",
+            1 + index % 12,
+            1 + index % 28,
+            index % 300,
+            (index * 7 + 1) % 300,
+            1500 + index % 700,
+            1500 + index * 3 % 700,
+            OPENINGS[index % OPENINGS.len()],
+        );
+    }
+    pgn
+}
+
+fn import_games(pgn: &[u8], mapped: bool) -> usize {
+    let (_, summary) = import_into_memory(pgn, mapped).expect("games are imported");
+    summary.imported
+}
+
+fn pgn_bench(c: &mut Criterion) {
+    let pgn = generated_pgn(1000);
+    assert_eq!(import_games(pgn.as_bytes(), false), 1000);
+    assert_eq!(import_games(pgn.as_bytes(), true), 1000);
+
+    let mut group = c.benchmark_group("PGN import");
+    group.throughput(Throughput::Bytes(pgn.len() as u64));
+    group.sample_size(10);
+    group.bench_function("1000 games, streamed", |b| {
+        b.iter(|| import_games(black_box(pgn.as_bytes()), false))
+    });
+    group.bench_function("1000 games, mapped", |b| {
+        b.iter(|| import_games(black_box(pgn.as_bytes()), true))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, pgn_bench);
+criterion_main!(benches);
@@ -0,0 +1,162 @@
+//! Reading of 10k `info` lines like the ones an engine sends while analyzing with
+//! MultiPV 5, by the parser of the analysis and by the generic UCI parser it replaced.
+//! The analysis only converts to SAN the sets of lines it emits, so the lines are also
+//! read without converting them, as the reader of the engine's output does, and from
+//! the output itself, each line in a new string or all of them in the same one.
+
+use std::io::BufRead;
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use shakmaty::{fen::Fen, san::SanPlus, uci::Uci, CastlingMode, Chess};
+use vampirc_uci::{parse_one, UciInfoAttribute, UciMessage};
+
+#[allow(dead_code)]
+#[path = "../src/uci.rs"]
+mod uci;
+
+const START: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+/// Lines of the starting position, one for each MultiPV
+const PVS: [&str; 5] = [
+    "e2e4 e7e5 g1f3 b8c6 f1b5 a7a6 b5a4 g8f6 e1g1 f8e7 f1e1 b7b5 a4b3 d7d6 c2c3 e8g8 h2h3 c6a5 b3c2 c7c5",
+    "d2d4 g8f6 c2c4 e7e6 g1f3 d7d5 b1c3 f8e7 c1f4 e8g8 e2e3 c7c5 d4c5 e7c5",
+    "e2e4 c7c5 g1f3 d7d6 d2d4 c5d4 f3d4 g8f6 b1c3 a7a6 c1e3 e7e5 d4b3 c8e6 f2f3 f8e7 d1d2 e8g8 e1c1 b8d7",
+    "c2c4 e7e5 b1c3 g8f6 g2g3 d7d5 c4d5 f6d5 f1g2 d5b6 g1f3 b8c6 e1g1 f8e7",
+    "g1f3 d7d5 g2g3 g8f6 f1g2 e7e6 e1g1 f8e7 d2d3 e8g8 b1d2 c7c5 e2e4 b8c6",
+];
+
+/// 2000 sets of lines, their depth and length growing up to a depth of 40
+fn info_lines() -> Vec<String> {
+    (0..2000)
+        .flat_map(|i| {
+            let depth = 1 + i % 40;
+            PVS.iter().enumerate().map(move |(index, pv)| {
+                let pv: Vec<_> = pv.split(' ').take(4 + depth / 2).collect();
+                format!(
+                    "info depth {depth} seldepth {} multipv {} score cp {} nodes {} nps 1500000 \
+                     hashfull {} tbhits 0 time {} pv {}",
+                    depth + 6,
+                    index + 1,
+                    30 - 7 * index as i32,
+                    depth * 150_000,
+                    depth * 20,
+                    depth * 100,
+                    pv.join(" ")
+                )
+            })
+        })
+        .collect()
+}
+
+/// The lines read the way the analysis did before, the position being parsed for each
+fn read_with_vampirc(lines: &[String]) -> usize {
+    let mut moves = 0;
+    for line in lines {
+        let UciMessage::Info(attrs) = parse_one(line) else {
+            continue;
+        };
+        let fen: Fen = START.parse().unwrap();
+        let mut pos: Chess = fen.into_position(CastlingMode::Standard).unwrap();
+        for attr in attrs {
+            if let UciInfoAttribute::Pv(pv) = attr {
+                for mv in pv {
+                    let uci: Uci = mv.to_string().parse().unwrap();
+                    let m = uci.to_move(&pos).unwrap();
+                    let san = SanPlus::from_move_and_play_unchecked(&mut pos, &m);
+                    black_box((san.to_string(), uci.to_string()));
+                    moves += 1;
+                }
+            }
+        }
+    }
+    moves
+}
+
+fn read_with_tokens(lines: &[String]) -> usize {
+    let pos = Chess::default();
+    let mut moves = 0;
+    for line in lines {
+        let Some(info) = uci::parse_info(line) else {
+            continue;
+        };
+        let (uci_moves, san_moves) = uci::pv_moves(&pos, info.pv, usize::MAX).unwrap();
+        moves += uci_moves.len();
+        black_box((uci_moves, san_moves, info.score));
+    }
+    moves
+}
+
+/// The lines read as the reader of the engine's output does, keeping their moves in UCI
+fn read_without_san(lines: &[String]) -> usize {
+    let mut moves = 0;
+    for line in lines {
+        let Some(info) = uci::parse_info(line) else {
+            continue;
+        };
+        moves += info.pv.split_ascii_whitespace().count();
+        black_box((info.pv.to_string(), info.score));
+    }
+    moves
+}
+
+/// The output read a line at a time like `next_line` does, allocating each line
+fn read_new_lines(output: &[u8]) -> usize {
+    let mut moves = 0;
+    for line in output.lines() {
+        let line = line.unwrap();
+        if let Some(info) = uci::parse_info(&line) {
+            moves += info.pv.split_ascii_whitespace().count();
+            black_box(info.score);
+        }
+    }
+    moves
+}
+
+/// The output read into the same buffer, as the reader of the engine's output does
+fn read_into_buffer(mut output: &[u8]) -> usize {
+    let mut moves = 0;
+    let mut buffer = String::new();
+    loop {
+        buffer.clear();
+        if output.read_line(&mut buffer).unwrap() == 0 {
+            break;
+        }
+        let line = buffer.trim_end_matches(&['\r', '\n'][..]);
+        if let Some(info) = uci::parse_info(line) {
+            moves += info.pv.split_ascii_whitespace().count();
+            black_box(info.score);
+        }
+    }
+    moves
+}
+
+fn info_lines_bench(c: &mut Criterion) {
+    let lines = info_lines();
+    assert_eq!(read_with_vampirc(&lines), read_with_tokens(&lines));
+    assert_eq!(read_with_vampirc(&lines), read_without_san(&lines));
+    let output = lines.join("\n").into_bytes();
+    assert_eq!(read_without_san(&lines), read_new_lines(&output));
+    assert_eq!(read_without_san(&lines), read_into_buffer(&output));
+
+    let mut group = c.benchmark_group("10k info lines");
+    group.bench_function("vampirc-uci", |b| {
+        b.iter(|| read_with_vampirc(black_box(&lines)))
+    });
+    group.bench_function("tokens", |b| b.iter(|| read_with_tokens(black_box(&lines))));
+    group.bench_function("tokens without SAN", |b| {
+        b.iter(|| read_without_san(black_box(&lines)))
+    });
+    group.finish();
+
+    let mut group = c.benchmark_group("10k info lines of output");
+    group.bench_function("a string per line", |b| {
+        b.iter(|| read_new_lines(black_box(&output)))
+    });
+    group.bench_function("one buffer", |b| {
+        b.iter(|| read_into_buffer(black_box(&output)))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, info_lines_bench);
+criterion_main!(benches);
@@ -0,0 +1,1964 @@
+use std::collections::HashSet;
+
+use serde::{Deserialize, Serialize};
+use shakmaty::{
+    fen::Fen, Bitboard, Board, ByColor, Chess, Color, EnPassantMode, Move, Outcome, Position, Role,
+    Square,
+};
+use specta::Type;
+use vampirc_uci::uci::{Score, ScoreValue};
+
+use crate::{
+    chess::{parse_position, BestMoves},
+    db::{PositionPopularity, PositionStats},
+    error::Error,
+    opening::{get_opening_from_setup, OpeningClassification},
+};
+
+/// Centipawn evaluations are clamped to this value before converting them to win
+/// probabilities. Mates count as this value too, like on lichess.
+const CP_CEILING: i32 = 1000;
+
+/// Default clamp for evaluations in the average centipawn loss
+pub const DEFAULT_ACPL_CEILING: u32 = 1000;
+
+/// An evaluation from White's point of view
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Eval {
+    Cp(i32),
+    /// Moves until mate, positive when White is mating
+    Mate(i32),
+    /// The game is over, with the winner if there is one
+    Over(Option<Color>),
+}
+
+impl Eval {
+    pub fn from_score(score: &Score) -> Self {
+        match score.value {
+            ScoreValue::Cp(cp) => Eval::Cp(cp),
+            ScoreValue::Mate(moves) => Eval::Mate(moves),
+        }
+    }
+
+    pub fn from_outcome(outcome: Outcome) -> Self {
+        Eval::Over(outcome.winner())
+    }
+
+    /// Side that has a forced mate or has already won
+    fn mating_side(self) -> Option<Color> {
+        match self {
+            Eval::Mate(moves) if moves > 0 => Some(Color::White),
+            Eval::Mate(moves) if moves < 0 => Some(Color::Black),
+            Eval::Over(winner) => winner,
+            _ => None,
+        }
+    }
+
+    /// Centipawns from `color`'s point of view, `None` for mates and won games
+    fn cp_for(self, color: Color) -> Option<i32> {
+        let cp = match self {
+            Eval::Cp(cp) => cp,
+            Eval::Over(None) => 0,
+            _ => return None,
+        };
+        Some(if color.is_white() { cp } else { -cp })
+    }
+}
+
+/// Chance of White winning in percent, using lichess' model fitted on rated games.
+pub fn win_percent(eval: Eval) -> f64 {
+    let cp = match eval {
+        Eval::Cp(cp) => cp.clamp(-CP_CEILING, CP_CEILING),
+        Eval::Mate(moves) => CP_CEILING * moves.signum(),
+        Eval::Over(Some(Color::White)) => return 100.0,
+        Eval::Over(Some(Color::Black)) => return 0.0,
+        Eval::Over(None) => 0,
+    };
+    50.0 + 50.0 * (2.0 / (1.0 + (-0.00368208 * cp as f64).exp()) - 1.0)
+}
+
+/// Win percent of `color` given White's win percent
+fn win_percent_for(white_percent: f64, color: Color) -> f64 {
+    if color.is_white() {
+        white_percent
+    } else {
+        100.0 - white_percent
+    }
+}
+
+/// Classifications are ordered by severity
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Type)]
+pub enum Classification {
+    #[serde(rename = "?!")]
+    Inaccuracy,
+    #[serde(rename = "?")]
+    Mistake,
+    #[serde(rename = "??")]
+    Blunder,
+}
+
+impl Classification {
+    pub fn nag(self) -> u8 {
+        match self {
+            Classification::Mistake => 2,
+            Classification::Blunder => 4,
+            Classification::Inaccuracy => 6,
+        }
+    }
+}
+
+/// Minimum loss of win percent, from the mover's point of view, for each classification.
+/// The defaults match lichess.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Type)]
+pub struct ClassificationThresholds {
+    pub inaccuracy: f64,
+    pub mistake: f64,
+    pub blunder: f64,
+}
+
+impl Default for ClassificationThresholds {
+    fn default() -> Self {
+        Self {
+            inaccuracy: 5.0,
+            mistake: 10.0,
+            blunder: 15.0,
+        }
+    }
+}
+
+/// Classifies the move `mover` played between the positions evaluated as `before` and
+/// `after`. Losing or allowing a mate is judged by how good the position still is,
+/// everything else by the drop in win probability.
+pub fn classify(
+    before: Eval,
+    after: Eval,
+    mover: Color,
+    thresholds: &ClassificationThresholds,
+) -> Option<Classification> {
+    let had_mate = before.mating_side() == Some(mover);
+    let allows_mate = after.mating_side() == Some(!mover);
+
+    if allows_mate && before.mating_side().is_none() {
+        // Allowing a mate in a lost position hurts less than in an equal one
+        let cp = before.cp_for(mover).unwrap_or(0);
+        return Some(if cp < -999 {
+            Classification::Inaccuracy
+        } else if cp < -700 {
+            Classification::Mistake
+        } else {
+            Classification::Blunder
+        });
+    }
+    if had_mate && allows_mate {
+        return Some(Classification::Blunder);
+    }
+    if had_mate && after.mating_side().is_none() {
+        // Missing a mate while staying completely winning is only an inaccuracy
+        let cp = after.cp_for(mover).unwrap_or(0);
+        return Some(if cp > 999 {
+            Classification::Inaccuracy
+        } else if cp > 700 {
+            Classification::Mistake
+        } else {
+            Classification::Blunder
+        });
+    }
+    if before.mating_side().is_some() || after.mating_side().is_some() {
+        return None;
+    }
+
+    let loss =
+        win_percent_for(win_percent(before), mover) - win_percent_for(win_percent(after), mover);
+    if loss >= thresholds.blunder {
+        Some(Classification::Blunder)
+    } else if loss >= thresholds.mistake {
+        Some(Classification::Mistake)
+    } else if loss >= thresholds.inaccuracy {
+        Some(Classification::Inaccuracy)
+    } else {
+        None
+    }
+}
+
+/// Classifies every move of a game given the evaluation of each position. The result
+/// is aligned with the positions, so the first entry is always `None`; moves from or to
+/// a position without an evaluation aren't classified.
+pub fn classify_moves(
+    evals: &[Option<Eval>],
+    first_to_move: Color,
+    thresholds: &ClassificationThresholds,
+) -> Vec<Option<Classification>> {
+    let mut classifications = vec![None; evals.len()];
+    let mut mover = first_to_move;
+    for i in 1..evals.len() {
+        if let (Some(before), Some(after)) = (evals[i - 1], evals[i]) {
+            classifications[i] = classify(before, after, mover, thresholds);
+        }
+        mover = !mover;
+    }
+    classifications
+}
+
+/// Accuracy in percent of a move that took the mover's win percent from `before` to
+/// `after`, using lichess' exponential fit. Moves that don't lose anything are 100.
+pub fn move_accuracy(before: f64, after: f64) -> f64 {
+    let loss = (before - after).max(0.0);
+    let accuracy = 103.1668100711649 * (-0.04354415386753951 * loss).exp() - 3.166924740191411;
+    // The +1 is lichess' bonus for the uncertainty of the analysis
+    (accuracy + 1.0).clamp(0.0, 100.0)
+}
+
+/// Accuracy of every move, aligned with the positions like [`classify_moves`].
+pub fn move_accuracies(evals: &[Option<Eval>], first_to_move: Color) -> Vec<Option<f64>> {
+    let mut accuracies = vec![None; evals.len()];
+    let mut mover = first_to_move;
+    for i in 1..evals.len() {
+        if let (Some(before), Some(after)) = (evals[i - 1], evals[i]) {
+            accuracies[i] = Some(move_accuracy(
+                win_percent_for(win_percent(before), mover),
+                win_percent_for(win_percent(after), mover),
+            ));
+        }
+        mover = !mover;
+    }
+    accuracies
+}
+
+fn standard_deviation(values: &[f64]) -> f64 {
+    let mean = values.iter().sum::<f64>() / values.len() as f64;
+    let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / values.len() as f64;
+    variance.sqrt()
+}
+
+/// Accuracy of each player over the whole game, computed like lichess: the mean of
+/// the harmonic mean of their move accuracies and a mean weighted by how volatile the
+/// game was around each move, so moves in quiet positions count less. `None` for a
+/// player without any evaluated move.
+pub fn game_accuracy(evals: &[Option<Eval>], first_to_move: Color) -> ByColor<Option<f64>> {
+    let accuracies = move_accuracies(evals, first_to_move);
+    let percents: Vec<Option<f64>> = evals.iter().map(|e| e.map(win_percent)).collect();
+    let window = (evals.len() / 10).clamp(2, 8);
+
+    let mut moves: ByColor<Vec<(f64, f64)>> = ByColor::default();
+    let mut mover = first_to_move;
+    for (i, accuracy) in accuracies.iter().enumerate().skip(1) {
+        if let Some(accuracy) = accuracy {
+            // The first moves share the first full window
+            let end = (i + 1).max(window).min(percents.len());
+            let start = end.saturating_sub(window);
+            let values: Vec<f64> = percents[start..end].iter().flatten().copied().collect();
+            let weight = standard_deviation(&values).clamp(0.5, 12.0);
+            moves.get_mut(mover).push((*accuracy, weight));
+        }
+        mover = !mover;
+    }
+
+    moves.map(|moves| {
+        if moves.is_empty() {
+            return None;
+        }
+        let weighted = moves.iter().map(|(a, w)| a * w).sum::<f64>()
+            / moves.iter().map(|(_, w)| w).sum::<f64>();
+        let harmonic =
+            moves.len() as f64 / moves.iter().map(|(a, _)| 1.0 / a.max(0.001)).sum::<f64>();
+        Some((weighted + harmonic) / 2.0)
+    })
+}
+
+/// Win percent the second best move must lose compared to the best one for a position
+/// to be critical. This is what 150 centipawns cost in an equal position.
+const ONLY_MOVE_GAP: f64 = 13.0;
+/// Win percent the best move must keep, since finding the only move that loses slower
+/// in a lost position isn't critical
+const ONLY_MOVE_MIN_WIN_PERCENT: f64 = 25.0;
+
+/// Whether there is a single move that keeps the evaluation for the side to move, given
+/// the evaluations of the two best lines.
+pub fn is_only_move(best: Eval, second: Eval, turn: Color) -> bool {
+    let best = win_percent_for(win_percent(best), turn);
+    let second = win_percent_for(win_percent(second), turn);
+    best >= ONLY_MOVE_MIN_WIN_PERCENT && best - second >= ONLY_MOVE_GAP
+}
+
+/// Plies that are always part of the opening, even out of book
+pub const OPENING_PLIES: usize = 20;
+/// Major and minor pieces left on the board when a trade-heavy opening becomes a middlegame
+const MIDDLEGAME_PIECES: u32 = 10;
+/// Major and minor pieces left on the board when the endgame starts
+const ENDGAME_PIECES: u32 = 6;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default, Type)]
+#[serde(rename_all = "camelCase")]
+pub enum Phase {
+    #[default]
+    Opening,
+    Middlegame,
+    Endgame,
+}
+
+fn pieces(pos: &Chess) -> u32 {
+    let board = pos.board();
+    (board.occupied() ^ board.pawns() ^ board.kings()).count() as u32
+}
+
+fn in_book(pos: &Chess) -> bool {
+    get_opening_from_setup(pos.clone().into_setup(EnPassantMode::Legal)).is_ok()
+}
+
+/// Half-moves played before `pos`, going by its move counter so games from a custom
+/// position count the moves before it
+fn game_ply(pos: &Chess) -> usize {
+    (pos.fullmoves().get() as usize - 1) * 2 + pos.turn().fold_wb(0, 1)
+}
+
+/// Phase of every position of a game. The opening lasts for the first
+/// [`OPENING_PLIES`] plies and then as long as the positions are in the opening table,
+/// unless trades leave 10 or fewer major and minor pieces. The endgame starts once 6
+/// or fewer are left. A game never goes back to an earlier phase.
+pub fn game_phases(positions: &[Chess]) -> Vec<Phase> {
+    let mut phase = Phase::Opening;
+    positions
+        .iter()
+        .map(|pos| {
+            let ply = game_ply(pos);
+            let pieces = pieces(pos);
+            if pieces <= ENDGAME_PIECES {
+                phase = Phase::Endgame;
+            } else if phase == Phase::Opening
+                && (pieces <= MIDDLEGAME_PIECES || (ply >= OPENING_PLIES && !in_book(pos)))
+            {
+                phase = Phase::Middlegame;
+            }
+            phase
+        })
+        .collect()
+}
+
+/// Index of the last position of the opening table that a game reaches before leaving
+/// it. The move played there is the first one out of book.
+pub fn last_book_ply(positions: &[Chess]) -> usize {
+    positions
+        .iter()
+        .take_while(|pos| in_book(pos))
+        .count()
+        .saturating_sub(1)
+}
+
+/// Centipawns from `color`'s point of view clamped to `ceiling`, counting mates and won
+/// games as the ceiling.
+fn clamped_cp(eval: Eval, color: Color, ceiling: i32) -> i32 {
+    let cp = match eval {
+        Eval::Cp(cp) => cp.clamp(-ceiling, ceiling),
+        Eval::Mate(moves) => ceiling * moves.signum(),
+        Eval::Over(Some(winner)) => winner.fold_wb(ceiling, -ceiling),
+        Eval::Over(None) => 0,
+    };
+    color.fold_wb(cp, -cp)
+}
+
+/// Average centipawn loss of a player, over the game and in each phase. A move
+/// belongs to the phase of the position it was played in.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Type)]
+pub struct Acpl {
+    pub game: Option<f64>,
+    pub opening: Option<f64>,
+    pub middlegame: Option<f64>,
+    pub endgame: Option<f64>,
+}
+
+/// Average centipawn loss of each player. Evaluations are clamped to `ceiling` first so
+/// a single hung piece in a lost position doesn't dominate the average.
+pub fn acpl(
+    evals: &[Option<Eval>],
+    phases: &[Phase],
+    first_to_move: Color,
+    ceiling: i32,
+) -> ByColor<Acpl> {
+    // Sum and count of losses for the whole game and each phase
+    let mut losses: ByColor<[(i64, u32); 4]> = ByColor::default();
+    let mut mover = first_to_move;
+    for i in 1..evals.len() {
+        if let (Some(before), Some(after)) = (evals[i - 1], evals[i]) {
+            let loss =
+                (clamped_cp(before, mover, ceiling) - clamped_cp(after, mover, ceiling)).max(0);
+            let losses = losses.get_mut(mover);
+            for index in [0, phases[i - 1] as usize + 1] {
+                losses[index].0 += loss as i64;
+                losses[index].1 += 1;
+            }
+        }
+        mover = !mover;
+    }
+
+    losses.map(|losses| {
+        let average = |(sum, count): (i64, u32)| (count > 0).then(|| sum as f64 / count as f64);
+        Acpl {
+            game: average(losses[0]),
+            opening: average(losses[1]),
+            middlegame: average(losses[2]),
+            endgame: average(losses[3]),
+        }
+    })
+}
+
+/// Mistakes a player made and win percent they lost in one phase. The ACPL of each
+/// phase is part of [`Acpl`].
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct PhaseStats {
+    pub inaccuracies: u32,
+    pub mistakes: u32,
+    pub blunders: u32,
+    pub win_percent_lost: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct PlayerPhases {
+    pub opening: PhaseStats,
+    pub middlegame: PhaseStats,
+    pub endgame: PhaseStats,
+    /// Phase the player lost the most win percent in, `None` if they lost none
+    pub worst: Option<Phase>,
+}
+
+impl PlayerPhases {
+    fn get_mut(&mut self, phase: Phase) -> &mut PhaseStats {
+        match phase {
+            Phase::Opening => &mut self.opening,
+            Phase::Middlegame => &mut self.middlegame,
+            Phase::Endgame => &mut self.endgame,
+        }
+    }
+}
+
+/// Statistics of each player in each phase. Like for [`acpl`], a move belongs to the
+/// phase of the position it was played in.
+pub fn phase_stats(
+    evals: &[Option<Eval>],
+    classifications: &[Option<Classification>],
+    phases: &[Phase],
+    first_to_move: Color,
+) -> ByColor<PlayerPhases> {
+    let mut stats: ByColor<PlayerPhases> = ByColor::default();
+    let mut mover = first_to_move;
+    for i in 1..evals.len() {
+        let phase = stats.get_mut(mover).get_mut(phases[i - 1]);
+        match classifications.get(i).copied().flatten() {
+            Some(Classification::Inaccuracy) => phase.inaccuracies += 1,
+            Some(Classification::Mistake) => phase.mistakes += 1,
+            Some(Classification::Blunder) => phase.blunders += 1,
+            None => {}
+        }
+        if let (Some(before), Some(after)) = (evals[i - 1], evals[i]) {
+            let loss = win_percent_for(win_percent(before), mover)
+                - win_percent_for(win_percent(after), mover);
+            phase.win_percent_lost += loss.max(0.0);
+        }
+        mover = !mover;
+    }
+
+    stats.map(|mut player| {
+        player.worst = [Phase::Opening, Phase::Middlegame, Phase::Endgame]
+            .into_iter()
+            .map(|phase| (phase, player.get_mut(phase).win_percent_lost))
+            .filter(|(_, lost)| *lost > 0.0)
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(phase, _)| phase);
+        player
+    })
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Type)]
+pub struct MoveAnalysis {
+    /// Number of half-moves played before this position
+    pub ply: usize,
+    pub fen: String,
+    /// Move that led to this position
+    pub san: Option<String>,
+    /// Evaluation from white's point of view, `None` if the game is already over
+    pub score: Option<Score>,
+    pub best_move: Option<String>,
+    pub pv: Vec<String>,
+    pub best: Vec<BestMoves>,
+    /// Classification of the move that led to this position
+    pub classification: Option<Classification>,
+    /// Accuracy in percent of the move that led to this position
+    pub accuracy: Option<f64>,
+    pub phase: Phase,
+    /// Second best line when only the best move keeps the evaluation
+    pub only_move: Option<BestMoves>,
+    /// Whether the move that led to this position was a sound sacrifice
+    pub brilliant: bool,
+    pub novelty: bool,
+    pub is_sacrifice: bool,
+}
+
+impl MoveAnalysis {
+    /// Evaluation of the position, from the engine or from the result if the game is over
+    pub fn eval(&self) -> Option<Eval> {
+        if let Some(score) = &self.score {
+            return Some(Eval::from_score(score));
+        }
+        let fen: Fen = self.fen.parse().ok()?;
+        let outcome = parse_position(&fen, &[]).ok()?.outcome()?;
+        Some(Eval::from_outcome(outcome))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct GameAnalysis {
+    pub moves: Vec<MoveAnalysis>,
+    pub white_accuracy: Option<f64>,
+    pub black_accuracy: Option<f64>,
+    pub white_acpl: Acpl,
+    pub black_acpl: Acpl,
+    pub white_phases: PlayerPhases,
+    pub black_phases: PlayerPhases,
+    pub missed_wins: Vec<MissedWin>,
+    pub graph: Vec<GraphPoint>,
+    pub win_chances: Vec<WinChance>,
+    /// Moves that swung the win percent the most
+    pub turning_points: Vec<WinChance>,
+    pub novelty: Option<Novelty>,
+    /// First and last analyzed positions. Classifications, accuracy and ACPL only cover
+    /// the moves between them.
+    pub analyzed_from: usize,
+    pub analyzed_to: usize,
+    pub comparison: Option<EngineComparison>,
+    pub opening: Option<OpeningClassification>,
+    /// Seconds spent on the move that led to each position
+    pub time_usage: Vec<Option<f64>>,
+    pub time_moments: Vec<TimeMoment>,
+}
+
+/// Material that has to be given up for a move to count as a sacrifice. Pawn sacrifices
+/// are too common to be brilliant.
+const SACRIFICE_MIN: i32 = 200;
+/// Win percent a brilliant move may lose, to allow for noise in the analysis
+const BRILLIANT_TOLERANCE: f64 = 2.0;
+
+fn see_value(role: Role) -> i32 {
+    match role {
+        Role::Pawn => 100,
+        Role::Knight | Role::Bishop => 300,
+        Role::Rook => 500,
+        Role::Queen => 900,
+        Role::King => 10000,
+    }
+}
+
+/// Static exchange evaluation: material `attacker` wins by capturing on `square` when
+/// both sides keep recapturing with their least valuable piece and stop as soon as it
+/// doesn't pay off. Pins are ignored.
+pub fn see(board: &Board, square: Square, attacker: Color) -> i32 {
+    let Some(target) = board.role_at(square) else {
+        return 0;
+    };
+    let least_valuable = |side: Color, occupied: Bitboard| {
+        let attackers = board.attacks_to(square, side, occupied) & board.by_color(side) & occupied;
+        Role::ALL.into_iter().find_map(|role| {
+            (attackers & board.by_role(role))
+                .first()
+                .map(|sq| (sq, role))
+        })
+    };
+
+    let mut occupied = board.occupied();
+    let mut gains = vec![see_value(target)];
+    let mut side = attacker;
+    let Some((mut from, mut role)) = least_valuable(side, occupied) else {
+        return 0;
+    };
+    // Each entry is the balance of the side making that capture if it isn't answered
+    loop {
+        let d = gains.len();
+        gains.push(see_value(role) - gains[d - 1]);
+        occupied.discard(from);
+        side = !side;
+        match least_valuable(side, occupied) {
+            Some((next_from, next_role)) => (from, role) = (next_from, next_role),
+            None => break,
+        }
+    }
+    // The last entry assumes a recapture that nobody can make
+    gains.pop();
+    while gains.len() > 1 {
+        let last = gains.pop().unwrap();
+        let previous = gains.last_mut().unwrap();
+        *previous = -(-*previous).max(last);
+    }
+    gains[0]
+}
+
+/// Whether `mv` gives up material: what the opponent can win by capturing one of the
+/// mover's pieces afterwards is worth more than what the move captured or promoted to.
+pub fn gives_up_material(pos: &Chess, mv: &Move) -> bool {
+    let mover = pos.turn();
+    let gained = mv.capture().map_or(0, see_value)
+        + mv.promotion()
+            .map_or(0, |role| see_value(role) - see_value(Role::Pawn));
+    let mut after = pos.clone();
+    after.play_unchecked(mv);
+    let board = after.board();
+    let lost = (board.by_color(mover) & !board.kings())
+        .into_iter()
+        .map(|square| see(board, square, !mover))
+        .max()
+        .unwrap_or(0);
+    lost - gained >= SACRIFICE_MIN
+}
+
+/// Whether a move is brilliant: the engine's best move, a sacrifice and the mover's
+/// win percent stays the same or improves.
+pub fn is_brilliant(before: Eval, after: Eval, mover: Color, best: bool, sacrifice: bool) -> bool {
+    let before = win_percent_for(win_percent(before), mover);
+    let after = win_percent_for(win_percent(after), mover);
+    best && sacrifice && after >= before - BRILLIANT_TOLERANCE
+}
+
+/// Evaluation in centipawns from which a position counts as won
+const WINNING_CP: i32 = 300;
+
+/// A move that gave away a forced mate or a winning position
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Type)]
+pub struct MissedWin {
+    /// Ply of the move that missed the win
+    pub ply: usize,
+    /// Moves to mate that were available, if there was a forced mate
+    pub mate: Option<i32>,
+    /// Best line of the engine before the move, in SAN
+    pub line: Vec<String>,
+    pub played: String,
+}
+
+/// Finds the moves after which a player no longer had the forced mate or winning
+/// position they had before. Mates are reported over winning evaluations.
+pub fn missed_wins(
+    moves: &[MoveAnalysis],
+    evals: &[Option<Eval>],
+    first_to_move: Color,
+) -> Vec<MissedWin> {
+    let mut missed = Vec::new();
+    let mut mover = first_to_move;
+    for i in 1..evals.len().min(moves.len()) {
+        if let (Some(before), Some(after)) = (evals[i - 1], evals[i]) {
+            let still_mating = after.mating_side() == Some(mover);
+            let mate = match before {
+                Eval::Mate(moves) if before.mating_side() == Some(mover) => Some(moves.abs()),
+                _ => None,
+            };
+            let was_winning =
+                mate.is_some() || before.cp_for(mover).is_some_and(|cp| cp > WINNING_CP);
+            let is_winning = still_mating || after.cp_for(mover).is_some_and(|cp| cp > WINNING_CP);
+            if (was_winning && !is_winning) || (mate.is_some() && !still_mating) {
+                missed.push(MissedWin {
+                    ply: i,
+                    mate,
+                    line: moves[i - 1]
+                        .best
+                        .first()
+                        .map(|line| line.san_moves.clone())
+                        .unwrap_or_default(),
+                    played: moves[i].san.clone().unwrap_or_default(),
+                });
+            }
+        }
+        mover = !mover;
+    }
+    missed
+}
+
+/// First move of a game that left the reference database
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Type)]
+pub struct Novelty {
+    pub ply: usize,
+    /// Reference games that reached the position after the novelty
+    pub games: usize,
+    /// Most common moves of the reference games in the position before the novelty
+    pub alternatives: Vec<PositionStats>,
+}
+
+/// Number of alternatives listed for a novelty
+const NOVELTY_ALTERNATIVES: usize = 5;
+
+/// Finds the first move leading to a position that fewer than `threshold` reference
+/// games reached. There's no novelty if the starting position itself is that rare.
+pub fn find_novelty(
+    popularity: &[PositionPopularity],
+    moves: &[MoveAnalysis],
+    threshold: usize,
+) -> Option<Novelty> {
+    if popularity.first()?.games < threshold {
+        return None;
+    }
+    let ply = popularity.iter().position(|p| p.games < threshold)?;
+    let played = moves.get(ply).and_then(|m| m.san.as_deref());
+    Some(Novelty {
+        ply,
+        games: popularity[ply].games,
+        alternatives: popularity[ply - 1]
+            .moves
+            .iter()
+            .filter(|stats| Some(stats.move_.as_str()) != played)
+            .take(NOVELTY_ALTERNATIVES)
+            .cloned()
+            .collect(),
+    })
+}
+
+/// Evaluations in the graph are clamped to this many pawns, and mates are drawn at it
+const GRAPH_CAP: f64 = 10.0;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Type)]
+pub struct GraphPoint {
+    pub ply: usize,
+    /// Evaluation in pawns from White's point of view, `None` if it wasn't analyzed
+    pub value: Option<f64>,
+    pub classification: Option<Classification>,
+}
+
+/// Value of an evaluation in the graph, in pawns from White's point of view
+pub fn graph_value(eval: Eval) -> f64 {
+    match eval {
+        Eval::Cp(cp) => (cp as f64 / 100.0).clamp(-GRAPH_CAP, GRAPH_CAP),
+        Eval::Mate(moves) => GRAPH_CAP * moves.signum() as f64,
+        Eval::Over(Some(winner)) => winner.fold_wb(GRAPH_CAP, -GRAPH_CAP),
+        Eval::Over(None) => 0.0,
+    }
+}
+
+/// Series for the evaluation graph, with one point per position
+pub fn eval_graph(
+    evals: &[Option<Eval>],
+    classifications: &[Option<Classification>],
+) -> Vec<GraphPoint> {
+    evals
+        .iter()
+        .enumerate()
+        .map(|(ply, eval)| GraphPoint {
+            ply,
+            value: eval.map(graph_value),
+            classification: classifications.get(ply).copied().flatten(),
+        })
+        .collect()
+}
+
+/// Swings smaller than this many win percent aren't turning points
+const MIN_SWING: f64 = 10.0;
+/// Number of turning points of a report
+pub const TURNING_POINTS: usize = 5;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Type)]
+pub struct WinChance {
+    pub ply: usize,
+    /// White's win percent, `None` without an evaluation
+    pub white: Option<f64>,
+    /// Change of the win percent of the player whose move led to this position.
+    /// Negative when the move threw chances away.
+    pub delta: Option<f64>,
+}
+
+/// Expected score series of a game, which unlike centipawns hardly moves once a
+/// position is won
+pub fn win_chances(evals: &[Option<Eval>], first_to_move: Color) -> Vec<WinChance> {
+    let percents: Vec<Option<f64>> = evals.iter().map(|eval| eval.map(win_percent)).collect();
+    let mut mover = !first_to_move;
+    percents
+        .iter()
+        .enumerate()
+        .map(|(ply, white)| {
+            let delta = match ply {
+                0 => None,
+                _ => percents[ply - 1].zip(*white).map(|(before, after)| {
+                    win_percent_for(after, mover) - win_percent_for(before, mover)
+                }),
+            };
+            mover = !mover;
+            WinChance {
+                ply,
+                white: *white,
+                delta,
+            }
+        })
+        .collect()
+}
+
+/// The moves with the largest swings in win percent, biggest first
+pub fn turning_points(chances: &[WinChance], count: usize) -> Vec<WinChance> {
+    let mut points: Vec<WinChance> = chances
+        .iter()
+        .filter(|c| c.delta.is_some_and(|delta| delta.abs() >= MIN_SWING))
+        .cloned()
+        .collect();
+    points.sort_by(|a, b| {
+        let swing = |c: &WinChance| c.delta.unwrap_or_default().abs();
+        swing(b).total_cmp(&swing(a)).then(a.ply.cmp(&b.ply))
+    });
+    points.truncate(count);
+    points
+}
+
+/// Win percent two engines' evaluations may differ by before they disagree
+pub const DEFAULT_DISAGREEMENT: f64 = 10.0;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct ComparedPly {
+    pub ply: usize,
+    /// Evaluations from white's point of view
+    pub score: Option<Score>,
+    pub other_score: Option<Score>,
+    pub best_move: Option<String>,
+    pub other_best_move: Option<String>,
+    pub disagree: bool,
+}
+
+/// Analysis of the same positions by a second engine
+#[derive(Serialize, Deserialize, Debug, Clone, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct EngineComparison {
+    pub engine: String,
+    pub plies: Vec<ComparedPly>,
+    /// Plies where the evaluations differ by more than the threshold or the best
+    /// moves aren't the same
+    pub disagreements: Vec<usize>,
+    /// Evaluation graph of the second engine
+    pub graph: Vec<GraphPoint>,
+}
+
+/// Compares the analysis of `engine` with the main one, ply by ply
+pub fn compare_engines(
+    engine: &str,
+    moves: &[MoveAnalysis],
+    other: &[MoveAnalysis],
+    threshold: f64,
+) -> EngineComparison {
+    let plies: Vec<ComparedPly> = moves
+        .iter()
+        .zip(other)
+        .map(|(a, b)| {
+            let evals = a.score.as_ref().zip(b.score.as_ref()).map(|(a, b)| {
+                (
+                    win_percent(Eval::from_score(a)),
+                    win_percent(Eval::from_score(b)),
+                )
+            });
+            let apart = evals.is_some_and(|(a, b)| (a - b).abs() > threshold);
+            let different = matches!(
+                (&a.best_move, &b.best_move),
+                (Some(a), Some(b)) if a != b
+            );
+            ComparedPly {
+                ply: a.ply,
+                score: a.score.clone(),
+                other_score: b.score.clone(),
+                best_move: a.best_move.clone(),
+                other_best_move: b.best_move.clone(),
+                disagree: apart || different,
+            }
+        })
+        .collect();
+    let evals: Vec<Option<Eval>> = other.iter().map(MoveAnalysis::eval).collect();
+    EngineComparison {
+        engine: engine.to_string(),
+        disagreements: plies.iter().filter(|p| p.disagree).map(|p| p.ply).collect(),
+        plies,
+        graph: eval_graph(&evals, &[]),
+    }
+}
+
+/// Moves played faster than this many seconds that blunder are flagged
+pub const DEFAULT_FAST_MOVE_SECONDS: f64 = 5.0;
+/// A move took a long think when it used this many times the player's average
+const LONG_THINK_FACTOR: f64 = 3.0;
+
+/// Seconds of an `h:mm:ss` duration. Minutes and seconds without hours, and fractions
+/// of seconds, are accepted too.
+pub fn parse_duration(duration: &str) -> Option<f64> {
+    duration.trim().split(':').try_fold(0.0, |total, part| {
+        Some(total * 60.0 + part.parse::<f64>().ok()?)
+    })
+}
+
+/// Remaining time in seconds of a `[%clk h:mm:ss]` command in the bytes of a comment,
+/// which don't need to be valid UTF-8 around the command
+pub fn parse_clock(comment: &[u8]) -> Option<f64> {
+    const COMMAND: &[u8] = b"[%clk";
+    let start = comment
+        .windows(COMMAND.len())
+        .position(|window| window == COMMAND)?
+        + COMMAND.len();
+    let end = start + comment[start..].iter().position(|&byte| byte == b']')?;
+    parse_duration(std::str::from_utf8(&comment[start..end]).ok()?)
+}
+
+/// Base time and increment in seconds of a `TimeControl` header like `300+2`
+pub fn parse_time_control(time_control: &str) -> Option<(f64, f64)> {
+    // Only the first period matters for the start of the game
+    let period = time_control.split(':').next()?;
+    let (base, increment) = period.split_once('+').unwrap_or((period, "0"));
+    let base = base.rsplit('/').next()?;
+    Some((base.parse().ok()?, increment.parse().ok()?))
+}
+
+/// Seconds spent on the move that led to each position, from the clock after each
+/// move. The first move of each player relies on the base time of the time control.
+/// Moves without a clock, or whose player's previous clock is missing, are `None`.
+pub fn time_spent(clocks: &[Option<f64>], time_control: Option<(f64, f64)>) -> Vec<Option<f64>> {
+    let increment = time_control.map_or(0.0, |(_, increment)| increment);
+    let mut spent = vec![None];
+    for (i, clock) in clocks.iter().enumerate() {
+        let previous = match i {
+            0 | 1 => time_control.map(|(base, _)| base),
+            _ => clocks[i - 2],
+        };
+        spent.push(
+            previous
+                .zip(*clock)
+                .map(|(previous, clock)| (previous - clock + increment).max(0.0)),
+        );
+    }
+    spent
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Type)]
+#[serde(rename_all = "camelCase")]
+pub enum TimeMomentKind {
+    /// A blunder played too quickly
+    FastBlunder,
+    /// A mistake or blunder after a long think
+    LongThink,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Type)]
+pub struct TimeMoment {
+    pub ply: usize,
+    pub kind: TimeMomentKind,
+    pub seconds: f64,
+    /// Win percent the mover lost
+    pub loss: f64,
+}
+
+/// Mistakes whose thinking time stands out: blunders in under `fast_seconds`, and
+/// mistakes or blunders that took [`LONG_THINK_FACTOR`] times the player's average.
+pub fn time_moments(
+    evals: &[Option<Eval>],
+    classifications: &[Option<Classification>],
+    spent: &[Option<f64>],
+    first_to_move: Color,
+    fast_seconds: f64,
+) -> Vec<TimeMoment> {
+    let mover = |ply: usize| {
+        if ply % 2 == 1 {
+            first_to_move
+        } else {
+            !first_to_move
+        }
+    };
+    let mut totals: ByColor<(f64, u32)> = ByColor::default();
+    for (ply, seconds) in spent.iter().enumerate() {
+        if let Some(seconds) = seconds {
+            let total = totals.get_mut(mover(ply));
+            total.0 += seconds;
+            total.1 += 1;
+        }
+    }
+    let averages = totals.map(|(sum, count)| sum / count.max(1) as f64);
+
+    let mut moments = Vec::new();
+    for ply in 1..evals.len() {
+        let (Some(before), Some(after), Some(seconds), Some(classification)) = (
+            evals[ply - 1],
+            evals[ply],
+            spent.get(ply).copied().flatten(),
+            classifications.get(ply).copied().flatten(),
+        ) else {
+            continue;
+        };
+        let color = mover(ply);
+        let kind = if classification == Classification::Blunder && seconds < fast_seconds {
+            TimeMomentKind::FastBlunder
+        } else if classification >= Classification::Mistake
+            && seconds >= averages.get(color) * LONG_THINK_FACTOR
+        {
+            TimeMomentKind::LongThink
+        } else {
+            continue;
+        };
+        moments.push(TimeMoment {
+            ply,
+            kind,
+            seconds,
+            loss: win_percent_for(win_percent(before), color)
+                - win_percent_for(win_percent(after), color),
+        });
+    }
+    moments
+}
+
+/// A position before a mistake to practice finding the engine's move
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Type)]
+pub struct Exercise {
+    pub game: String,
+    pub ply: usize,
+    pub fen: String,
+    /// Best line of the engine in UCI notation
+    pub solution: Vec<String>,
+    pub played: String,
+    pub classification: Classification,
+    /// Win percent the mover lost with the played move
+    pub eval_swing: f64,
+}
+
+/// Turns the mistakes of an analyzed game at least as bad as `min_severity` into
+/// exercises. A position that comes up again with the same side to move, as in a
+/// repetition, only gives one exercise.
+#[tauri::command]
+#[specta::specta]
+pub fn export_mistakes(
+    analysis: GameAnalysis,
+    min_severity: Classification,
+    game: String,
+) -> Result<Vec<Exercise>, Error> {
+    let mut seen = HashSet::new();
+    let mut exercises = Vec::new();
+    for i in 1..analysis.moves.len() {
+        let (before, after) = (&analysis.moves[i - 1], &analysis.moves[i]);
+        let Some(classification) = after.classification.filter(|c| *c >= min_severity) else {
+            continue;
+        };
+        let Some(line) = before.best.first() else {
+            continue;
+        };
+        // Piece placement and side to move
+        let position: String = before.fen.split(' ').take(2).collect();
+        if !seen.insert(position) {
+            continue;
+        }
+        let mover = before.fen.parse::<Fen>()?.as_setup().turn;
+        let eval_swing = match (before.eval(), after.eval()) {
+            (Some(before), Some(after)) => {
+                win_percent_for(win_percent(before), mover)
+                    - win_percent_for(win_percent(after), mover)
+            }
+            _ => 0.0,
+        };
+        exercises.push(Exercise {
+            game: game.clone(),
+            ply: i - 1,
+            fen: before.fen.clone(),
+            solution: line.uci_moves.clone(),
+            played: after.san.clone().unwrap_or_default(),
+            classification,
+            eval_swing,
+        });
+    }
+    Ok(exercises)
+}
+
+#[derive(Deserialize, Debug, Clone, Default, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct AnnotateOptions {
+    /// Add the engine's best line as a variation to every classified move
+    pub variations: bool,
+    /// Maximum number of moves in each variation
+    pub variation_length: usize,
+    /// Comments of the original game, aligned with the positions, to keep next to the evals
+    pub comments: Option<Vec<String>>,
+}
+
+/// Comment in the `[%eval ...]` format used by lichess
+pub fn eval_comment(eval: Eval) -> Option<String> {
+    match eval {
+        Eval::Cp(cp) => Some(format!("[%eval {:.2}]", cp as f64 / 100.0)),
+        Eval::Mate(moves) => Some(format!("[%eval #{}]", moves)),
+        Eval::Over(_) => None,
+    }
+}
+
+fn push_move_number(pgn: &mut String, turn: Color, fullmoves: u32, force: bool) {
+    if turn.is_white() {
+        pgn.push_str(&format!("{}. ", fullmoves));
+    } else if force {
+        pgn.push_str(&format!("{}... ", fullmoves));
+    }
+}
+
+/// Writes the moves of an analyzed game as PGN with the evaluations as comments, NAGs
+/// for classified moves and optionally the engine's line at each of them.
+#[tauri::command]
+#[specta::specta]
+pub fn annotate_game(analysis: GameAnalysis, options: AnnotateOptions) -> Result<String, Error> {
+    let first = analysis.moves.first().ok_or(Error::NoMovesFound)?;
+    let start: Fen = first.fen.parse()?;
+    let last: Fen = analysis
+        .moves
+        .last()
+        .ok_or(Error::NoMovesFound)?
+        .fen
+        .parse()?;
+    let outcome = parse_position(&last, &[])?.outcome();
+    let comments = options.comments.unwrap_or_default();
+    let comment = |i: usize| {
+        comments
+            .get(i)
+            .map(|c| c.trim().replace('}', ""))
+            .filter(|c| !c.is_empty())
+    };
+
+    let mut pgn = String::new();
+    if start != Fen::default() {
+        pgn.push_str(&format!("[SetUp \"1\"]\n[FEN \"{}\"]\n", start));
+    }
+    let result = outcome.map_or("*".to_string(), |o| o.to_string());
+    pgn.push_str(&format!("[Result \"{}\"]\n\n", result));
+
+    let setup = start.as_setup();
+    let mut turn = setup.turn;
+    let mut fullmoves = setup.fullmoves.get();
+    let mut force_number = true;
+    if let Some(c) = comment(0) {
+        pgn.push_str(&format!("{{ {} }} ", c));
+    }
+
+    for i in 1..analysis.moves.len() {
+        let analysis_move = &analysis.moves[i];
+        let san = analysis_move.san.as_deref().ok_or(Error::NoMovesFound)?;
+        push_move_number(&mut pgn, turn, fullmoves, force_number);
+        pgn.push_str(san);
+        force_number = false;
+
+        if let Some(classification) = analysis_move.classification {
+            pgn.push_str(&format!(" ${}", classification.nag()));
+        }
+
+        let eval = analysis_move
+            .score
+            .as_ref()
+            .and_then(|score| eval_comment(Eval::from_score(score)));
+        let text: Vec<String> = comment(i).into_iter().chain(eval).collect();
+        if !text.is_empty() {
+            pgn.push_str(&format!(" {{ {} }}", text.join(" ")));
+            force_number = true;
+        }
+
+        let best_line = analysis.moves[i - 1].best.first();
+        if let (true, Some(_), Some(line)) =
+            (options.variations, analysis_move.classification, best_line)
+        {
+            pgn.push_str(" (");
+            let (mut line_turn, mut line_fullmoves) = (turn, fullmoves);
+            for (j, san) in line
+                .san_moves
+                .iter()
+                .take(options.variation_length)
+                .enumerate()
+            {
+                if j > 0 {
+                    pgn.push(' ');
+                }
+                push_move_number(&mut pgn, line_turn, line_fullmoves, j == 0);
+                pgn.push_str(san);
+                if line_turn.is_black() {
+                    line_fullmoves += 1;
+                }
+                line_turn = !line_turn;
+            }
+            pgn.push(')');
+            force_number = true;
+        }
+
+        pgn.push(' ');
+        if turn.is_black() {
+            fullmoves += 1;
+        }
+        turn = !turn;
+    }
+    pgn.push_str(&result);
+    pgn.push('\n');
+    Ok(pgn)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn classify_white(before: Eval, after: Eval) -> Option<Classification> {
+        classify(
+            before,
+            after,
+            Color::White,
+            &ClassificationThresholds::default(),
+        )
+    }
+
+    #[test]
+    fn win_percent_bounds() {
+        assert_eq!(win_percent(Eval::Cp(0)), 50.0);
+        assert_eq!(win_percent(Eval::Cp(5000)), win_percent(Eval::Cp(1000)));
+        assert_eq!(win_percent(Eval::Mate(3)), win_percent(Eval::Cp(1000)));
+        assert_eq!(win_percent(Eval::Over(Some(Color::Black))), 0.0);
+        assert!((win_percent(Eval::Cp(300)) + win_percent(Eval::Cp(-300)) - 100.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn classify_centipawn_losses() {
+        assert_eq!(classify_white(Eval::Cp(0), Eval::Cp(-20)), None);
+        assert_eq!(
+            classify_white(Eval::Cp(0), Eval::Cp(-60)),
+            Some(Classification::Inaccuracy)
+        );
+        assert_eq!(
+            classify_white(Eval::Cp(0), Eval::Cp(-120)),
+            Some(Classification::Mistake)
+        );
+        assert_eq!(
+            classify_white(Eval::Cp(0), Eval::Cp(-300)),
+            Some(Classification::Blunder)
+        );
+        assert_eq!(
+            classify(
+                Eval::Cp(0),
+                Eval::Cp(300),
+                Color::Black,
+                &ClassificationThresholds::default()
+            ),
+            Some(Classification::Blunder)
+        );
+    }
+
+    #[test]
+    fn classify_same_swing_when_winning() {
+        assert_eq!(classify_white(Eval::Cp(800), Eval::Cp(500)), None);
+        assert_eq!(
+            classify_white(Eval::Cp(150), Eval::Cp(-150)),
+            Some(Classification::Blunder)
+        );
+    }
+
+    #[test]
+    fn classify_threshold_boundary() {
+        let thresholds = ClassificationThresholds {
+            inaccuracy: 5.0,
+            mistake: 10.0,
+            blunder: 15.0,
+        };
+        let before = Eval::Cp(0);
+        let mut cp = 0;
+        while 50.0 - win_percent(Eval::Cp(cp)) < thresholds.inaccuracy {
+            cp -= 1;
+        }
+        assert_eq!(
+            classify(before, Eval::Cp(cp + 1), Color::White, &thresholds),
+            None
+        );
+        assert_eq!(
+            classify(before, Eval::Cp(cp), Color::White, &thresholds),
+            Some(Classification::Inaccuracy)
+        );
+    }
+
+    #[test]
+    fn classify_mate_transitions() {
+        assert_eq!(
+            classify_white(Eval::Cp(50), Eval::Mate(-3)),
+            Some(Classification::Blunder)
+        );
+        assert_eq!(
+            classify_white(Eval::Cp(-1200), Eval::Mate(-3)),
+            Some(Classification::Inaccuracy)
+        );
+        assert_eq!(
+            classify_white(Eval::Mate(2), Eval::Cp(1500)),
+            Some(Classification::Inaccuracy)
+        );
+        assert_eq!(
+            classify_white(Eval::Mate(2), Eval::Cp(200)),
+            Some(Classification::Blunder)
+        );
+        assert_eq!(
+            classify_white(Eval::Mate(2), Eval::Mate(-1)),
+            Some(Classification::Blunder)
+        );
+        assert_eq!(classify_white(Eval::Mate(2), Eval::Mate(1)), None);
+        assert_eq!(
+            classify_white(Eval::Mate(1), Eval::Over(Some(Color::White))),
+            None
+        );
+        assert_eq!(classify_white(Eval::Mate(-2), Eval::Mate(-1)), None);
+    }
+
+    #[test]
+    fn classify_moves_alternates_mover() {
+        let evals = [
+            Some(Eval::Cp(20)),
+            Some(Eval::Cp(-300)),
+            None,
+            Some(Eval::Cp(0)),
+            Some(Eval::Cp(400)),
+        ];
+        let classifications =
+            classify_moves(&evals, Color::White, &ClassificationThresholds::default());
+        assert_eq!(
+            classifications,
+            vec![
+                None,
+                Some(Classification::Blunder),
+                None,
+                None,
+                Some(Classification::Blunder)
+            ]
+        );
+    }
+
+    #[test]
+    fn accuracy_of_perfect_moves() {
+        assert_eq!(move_accuracy(50.0, 50.0), 100.0);
+        assert_eq!(move_accuracy(50.0, 60.0), 100.0);
+        assert!(move_accuracy(50.0, 40.0) < 70.0);
+        assert_eq!(move_accuracy(100.0, 0.0), 0.0);
+    }
+
+    #[test]
+    fn accuracy_per_player() {
+        let evals = [
+            Some(Eval::Cp(20)),
+            Some(Eval::Cp(20)),
+            Some(Eval::Cp(20)),
+            Some(Eval::Cp(-400)),
+            Some(Eval::Cp(-400)),
+        ];
+        let accuracy = game_accuracy(&evals, Color::White);
+        assert_eq!(accuracy.black, Some(100.0));
+        assert!(accuracy.white.unwrap() < 100.0);
+    }
+
+    #[test]
+    fn accuracy_of_short_games() {
+        let accuracy = game_accuracy(&[Some(Eval::Cp(0))], Color::White);
+        assert_eq!((accuracy.white, accuracy.black), (None, None));
+        let accuracy = game_accuracy(
+            &[Some(Eval::Mate(1)), Some(Eval::Over(Some(Color::White)))],
+            Color::White,
+        );
+        assert_eq!(accuracy.white, Some(100.0));
+        assert_eq!(accuracy.black, None);
+    }
+
+    #[test]
+    fn phases_of_a_game() {
+        let mut positions = vec![Chess::default(); 30];
+        positions.push(
+            "r3k3/pp3ppp/8/8/8/8/PPP2PPP/3RK2R w - - 0 1"
+                .parse::<shakmaty::fen::Fen>()
+                .unwrap()
+                .into_position(shakmaty::CastlingMode::Standard)
+                .unwrap(),
+        );
+        positions.push(Chess::default());
+        let phases = game_phases(&positions);
+        assert_eq!(phases[19], Phase::Opening);
+        // The starting position is in the opening table
+        assert_eq!(phases[29], Phase::Opening);
+        assert_eq!(phases[30], Phase::Endgame);
+        assert_eq!(phases[31], Phase::Endgame);
+    }
+
+    #[test]
+    fn phases_from_a_custom_position() {
+        let middlegame: Chess = "r1bq1rk1/pp2bppp/2n1pn2/3p4/2PP4/2N2N2/PP2BPPP/R2QKB1R b KQ - 3 9"
+            .parse::<shakmaty::fen::Fen>()
+            .unwrap()
+            .into_position(shakmaty::CastlingMode::Standard)
+            .unwrap();
+        assert_eq!(game_ply(&middlegame), 17);
+        assert_eq!(game_phases(&[middlegame]), vec![Phase::Opening]);
+
+        let later: Chess = "r1bq1rk1/pp2bppp/2n1pn2/3p4/2PP4/2N2N2/PP2BPPP/R2QKB1R b KQ - 3 15"
+            .parse::<shakmaty::fen::Fen>()
+            .unwrap()
+            .into_position(shakmaty::CastlingMode::Standard)
+            .unwrap();
+        assert_eq!(game_phases(&[later]), vec![Phase::Middlegame]);
+    }
+
+    #[test]
+    fn annotate_from_a_custom_position() {
+        let moves = vec![
+            analyzed_move(
+                "4k3/8/8/8/8/8/4P3/4K3 b - - 0 30",
+                "",
+                Some(ScoreValue::Cp(0)),
+            ),
+            analyzed_move(
+                "8/3k4/8/8/8/8/4P3/4K3 w - - 1 31",
+                "Kd7",
+                Some(ScoreValue::Cp(0)),
+            ),
+            analyzed_move(
+                "8/3k4/8/8/4P3/8/8/4K3 b - - 0 31",
+                "e4",
+                Some(ScoreValue::Cp(0)),
+            ),
+        ];
+        let analysis = GameAnalysis {
+            moves,
+            ..Default::default()
+        };
+        let options = AnnotateOptions {
+            variations: false,
+            variation_length: 0,
+            comments: None,
+        };
+        assert_eq!(
+            annotate_game(analysis, options).unwrap(),
+            "[SetUp \"1\"]\n[FEN \"4k3/8/8/8/8/8/4P3/4K3 b - - 0 30\"]\n[Result \"*\"]\n\n\
+             30... Kd7 { [%eval 0.00] } 31. e4 { [%eval 0.00] } *\n"
+        );
+    }
+
+    #[test]
+    fn acpl_is_capped() {
+        let evals = [
+            Some(Eval::Cp(0)),
+            Some(Eval::Cp(-100)),
+            Some(Eval::Cp(-100)),
+            Some(Eval::Cp(-3000)),
+            Some(Eval::Mate(-2)),
+        ];
+        let phases = [
+            Phase::Opening,
+            Phase::Opening,
+            Phase::Middlegame,
+            Phase::Middlegame,
+            Phase::Middlegame,
+        ];
+        let acpl = acpl(&evals, &phases, Color::White, 1000);
+        assert_eq!(acpl.white.game, Some(500.0));
+        assert_eq!(acpl.white.opening, Some(100.0));
+        assert_eq!(acpl.white.middlegame, Some(900.0));
+        assert_eq!(acpl.white.endgame, None);
+        assert_eq!(acpl.black.game, Some(0.0));
+    }
+
+    fn analyzed_move(fen: &str, san: &str, score: Option<ScoreValue>) -> MoveAnalysis {
+        MoveAnalysis {
+            fen: fen.to_string(),
+            san: Some(san.to_string()),
+            score: score.map(|value| Score {
+                value,
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn annotate_scholars_mate() {
+        let mut moves = vec![
+            analyzed_move(
+                "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+                "",
+                Some(ScoreValue::Cp(20)),
+            ),
+            analyzed_move(
+                "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1",
+                "e4",
+                Some(ScoreValue::Cp(30)),
+            ),
+            analyzed_move(
+                "rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR w KQkq - 0 2",
+                "e5",
+                Some(ScoreValue::Cp(25)),
+            ),
+            analyzed_move(
+                "rnbqkbnr/pppp1ppp/8/4p3/2B1P3/8/PPPP1PPP/RNBQK1NR b KQkq - 1 2",
+                "Bc4",
+                Some(ScoreValue::Cp(20)),
+            ),
+            analyzed_move(
+                "rnbqkb1r/pppp1ppp/5n2/4p3/2B1P3/8/PPPP1PPP/RNBQK1NR w KQkq - 2 3",
+                "Nf6",
+                Some(ScoreValue::Cp(30)),
+            ),
+            analyzed_move(
+                "rnbqkb1r/pppp1ppp/5n2/4p2Q/2B1P3/8/PPPP1PPP/RNB1K1NR b KQkq - 3 3",
+                "Qh5",
+                Some(ScoreValue::Cp(-40)),
+            ),
+            analyzed_move(
+                "r1bqkb1r/pppp1ppp/2n2n2/4p2Q/2B1P3/8/PPPP1PPP/RNB1K1NR w KQkq - 4 4",
+                "Nc6",
+                Some(ScoreValue::Mate(1)),
+            ),
+            analyzed_move(
+                "r1bqkb1r/pppp1Qpp/2n2n2/4p3/2B1P3/8/PPPP1PPP/RNB1K1NR b KQkq - 0 4",
+                "Qxf7#",
+                None,
+            ),
+        ];
+        moves[6].classification = Some(Classification::Blunder);
+        moves[5].best = vec![BestMoves {
+            san_moves: vec!["Nxh5".to_string(), "Nf3".to_string(), "Nc6".to_string()],
+            ..Default::default()
+        }];
+        let analysis = GameAnalysis {
+            moves,
+            ..Default::default()
+        };
+        let options = AnnotateOptions {
+            variations: true,
+            variation_length: 2,
+            comments: Some(vec![String::new(), "King's pawn".to_string()]),
+        };
+
+        let pgn = annotate_game(analysis, options).unwrap();
+        assert_eq!(
+            pgn,
+            "[Result \"1-0\"]\n\n\
+             1. e4 { King's pawn [%eval 0.30] } 1... e5 { [%eval 0.25] } \
+             2. Bc4 { [%eval 0.20] } 2... Nf6 { [%eval 0.30] } 3. Qh5 { [%eval -0.40] } \
+             3... Nc6 $4 { [%eval #1] } (3... Nxh5 4. Nf3) 4. Qxf7# 1-0\n"
+        );
+
+        struct Counter(usize, usize);
+        impl pgn_reader::Visitor for Counter {
+            type Result = (usize, usize);
+            fn san(&mut self, _: pgn_reader::SanPlus) {
+                self.0 += 1;
+            }
+            fn nag(&mut self, _: pgn_reader::Nag) {
+                self.1 += 1;
+            }
+            fn end_game(&mut self) -> Self::Result {
+                (self.0, self.1)
+            }
+        }
+        let mut reader = pgn_reader::BufferedReader::new(pgn.as_bytes());
+        let counts = reader.read_game(&mut Counter(0, 0)).unwrap();
+        assert_eq!(counts, Some((9, 1)));
+    }
+
+    #[test]
+    fn only_moves() {
+        assert!(is_only_move(Eval::Cp(0), Eval::Cp(-200), Color::White));
+        assert!(!is_only_move(Eval::Cp(0), Eval::Cp(-100), Color::White));
+        assert!(is_only_move(Eval::Cp(0), Eval::Cp(200), Color::Black));
+        // Both moves keep a winning position
+        assert!(!is_only_move(Eval::Mate(3), Eval::Cp(900), Color::White));
+        // The position is lost anyway
+        assert!(!is_only_move(Eval::Cp(-500), Eval::Mate(-2), Color::White));
+        assert!(is_only_move(Eval::Cp(50), Eval::Mate(-2), Color::White));
+    }
+
+    #[test]
+    fn missed_mates_and_wins() {
+        let played = |san: &str| MoveAnalysis {
+            san: Some(san.to_string()),
+            best: vec![BestMoves {
+                san_moves: vec!["Qh7#".to_string()],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let moves: Vec<MoveAnalysis> = ["", "Qh5", "Kf8", "Qf3", "Ke8", "Qe2", "Kd8"]
+            .into_iter()
+            .map(played)
+            .collect();
+        let evals = [
+            Some(Eval::Mate(1)),
+            Some(Eval::Cp(800)),
+            Some(Eval::Mate(2)),
+            Some(Eval::Mate(4)),
+            Some(Eval::Cp(500)),
+            Some(Eval::Cp(150)),
+            Some(Eval::Cp(150)),
+        ];
+        let missed = missed_wins(&moves, &evals, Color::White);
+        assert_eq!(
+            missed,
+            vec![
+                MissedWin {
+                    ply: 1,
+                    mate: Some(1),
+                    line: vec!["Qh7#".to_string()],
+                    played: "Qh5".to_string(),
+                },
+                MissedWin {
+                    ply: 5,
+                    mate: None,
+                    line: vec!["Qh7#".to_string()],
+                    played: "Qe2".to_string(),
+                },
+            ]
+        );
+    }
+
+    fn sacrifice(fen: &str, uci: &str) -> bool {
+        let pos: Chess = fen
+            .parse::<Fen>()
+            .unwrap()
+            .into_position(shakmaty::CastlingMode::Standard)
+            .unwrap();
+        let mv = uci
+            .parse::<shakmaty::uci::Uci>()
+            .unwrap()
+            .to_move(&pos)
+            .unwrap();
+        gives_up_material(&pos, &mv)
+    }
+
+    #[test]
+    fn see_exchanges() {
+        let board: Board = "4k3/8/3p4/4n3/8/8/4R3/4K3".parse().unwrap();
+        assert_eq!(see(&board, Square::E5, Color::White), 300 - 500);
+        let board: Board = "4k3/8/8/4n3/8/8/4R3/4K3".parse().unwrap();
+        assert_eq!(see(&board, Square::E5, Color::White), 300);
+        // The rook behind the first one recaptures through the x-ray
+        let board: Board = "4k3/8/3p4/4n3/8/8/4R3/4R1K1".parse().unwrap();
+        assert_eq!(see(&board, Square::E5, Color::White), 300 - 500 + 100);
+        let board: Board = "4k3/8/8/4q3/3P4/8/8/4K3".parse().unwrap();
+        assert_eq!(see(&board, Square::E5, Color::White), 900);
+        assert_eq!(see(&board, Square::D4, Color::Black), 100);
+    }
+
+    #[test]
+    fn sacrifices() {
+        // Bishop for a pawn
+        assert!(sacrifice(
+            "r1bqkbnr/pppp1ppp/2n5/4p3/2B1P3/5N2/PPPP1PPP/RNBQK2R w KQkq - 4 4",
+            "c4f7"
+        ));
+        // Queen trade
+        assert!(!sacrifice("3qk3/8/8/8/8/8/8/3QK3 w - - 0 1", "d1d8"));
+        // Promoting to a queen that's taken only loses the pawn
+        assert!(!sacrifice("r6k/4P3/8/8/8/8/8/4K3 w - - 0 1", "e7e8q"));
+        // Recapturing after en passant is a pawn trade
+        assert!(!sacrifice("4k3/1p6/8/pP6/8/8/8/4K3 w - a6 0 2", "b5a6"));
+    }
+
+    #[test]
+    fn brilliant_moves() {
+        assert!(is_brilliant(
+            Eval::Cp(50),
+            Eval::Cp(200),
+            Color::White,
+            true,
+            true
+        ));
+        assert!(is_brilliant(
+            Eval::Cp(50),
+            Eval::Cp(40),
+            Color::White,
+            true,
+            true
+        ));
+        assert!(!is_brilliant(
+            Eval::Cp(50),
+            Eval::Cp(-200),
+            Color::White,
+            true,
+            true
+        ));
+        assert!(!is_brilliant(
+            Eval::Cp(50),
+            Eval::Cp(200),
+            Color::White,
+            false,
+            true
+        ));
+        assert!(!is_brilliant(
+            Eval::Cp(50),
+            Eval::Cp(200),
+            Color::White,
+            true,
+            false
+        ));
+    }
+
+    #[test]
+    fn graph_series() {
+        let evals = [
+            Some(Eval::Cp(35)),
+            None,
+            Some(Eval::Cp(-1500)),
+            Some(Eval::Mate(-3)),
+            Some(Eval::Over(Some(Color::Black))),
+        ];
+        let classifications = [None, None, Some(Classification::Blunder), None, None];
+        let values: Vec<Option<f64>> = eval_graph(&evals, &classifications)
+            .iter()
+            .map(|point| point.value)
+            .collect();
+        assert_eq!(
+            values,
+            vec![Some(0.35), None, Some(-10.0), Some(-10.0), Some(-10.0)]
+        );
+        assert_eq!(
+            eval_graph(&evals, &classifications)[2].classification,
+            Some(Classification::Blunder)
+        );
+    }
+
+    #[test]
+    fn novelty_after_theory() {
+        let stats = |san: &str, white: i32| PositionStats {
+            move_: san.to_string(),
+            white,
+            draw: 0,
+            black: 0,
+        };
+        let popularity = vec![
+            PositionPopularity {
+                games: 10,
+                moves: vec![stats("e4", 6), stats("d4", 4)],
+            },
+            PositionPopularity {
+                games: 6,
+                moves: vec![stats("c5", 4), stats("e5", 1), stats("e6", 1)],
+            },
+            PositionPopularity {
+                games: 0,
+                moves: vec![],
+            },
+        ];
+        let moves: Vec<MoveAnalysis> = [None, Some("e4"), Some("a6")]
+            .into_iter()
+            .map(|san| MoveAnalysis {
+                san: san.map(str::to_string),
+                ..Default::default()
+            })
+            .collect();
+
+        let novelty = find_novelty(&popularity, &moves, 1).unwrap();
+        assert_eq!(novelty.ply, 2);
+        assert_eq!(novelty.alternatives.len(), 3);
+        assert_eq!(novelty.alternatives[0].move_, "c5");
+
+        let novelty = find_novelty(&popularity, &moves, 8).unwrap();
+        assert_eq!(novelty.ply, 1);
+        assert_eq!(novelty.alternatives.len(), 1);
+        assert_eq!(novelty.alternatives[0].move_, "d4");
+
+        assert_eq!(find_novelty(&popularity, &moves, 20), None);
+    }
+
+    #[test]
+    fn exercises_from_mistakes() {
+        let position = |fen: &str, san: Option<&str>, cp: i32| MoveAnalysis {
+            fen: fen.to_string(),
+            san: san.map(str::to_string),
+            score: Some(Score {
+                value: ScoreValue::Cp(cp),
+                ..Default::default()
+            }),
+            best: vec![BestMoves {
+                uci_moves: vec!["g1f3".to_string()],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let start = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+        let knight_out = "rnbqkbnr/pppppppp/8/8/8/5N2/PPPPPPPP/RNBQKB1R b KQkq - 1 1";
+        let knights_out = "rnbqkb1r/pppppppp/5n2/8/8/5N2/PPPPPPPP/RNBQKB1R w KQkq - 2 2";
+        let knight_back = "rnbqkb1r/pppppppp/5n2/8/8/8/PPPPPPPP/RNBQKBNR b KQkq - 3 2";
+        let knights_back = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 4 3";
+        let mut moves = vec![
+            position(start, None, 20),
+            position(knight_out, Some("Nf3"), -300),
+            position(knights_out, Some("Nf6"), -300),
+            position(knight_back, Some("Ng1"), -360),
+            position(knights_back, Some("Ng8"), -360),
+            position(knight_out, Some("Nf3"), -1000),
+        ];
+        moves[1].classification = Some(Classification::Blunder);
+        moves[3].classification = Some(Classification::Inaccuracy);
+        moves[5].classification = Some(Classification::Blunder);
+        let analysis = GameAnalysis {
+            moves,
+            ..Default::default()
+        };
+
+        let exercises =
+            export_mistakes(analysis, Classification::Mistake, "Game".to_string()).unwrap();
+        assert_eq!(exercises.len(), 1);
+        assert_eq!(exercises[0].ply, 0);
+        assert_eq!(exercises[0].fen, start);
+        assert_eq!(exercises[0].solution, vec!["g1f3".to_string()]);
+        assert_eq!(exercises[0].played, "Nf3");
+        assert!(exercises[0].eval_swing > 30.0);
+    }
+
+    #[test]
+    fn book_moves() {
+        let mut pos = Chess::default();
+        let mut positions = vec![pos.clone()];
+        for san in ["e4", "e5", "Nf3", "Nc6", "Bb5", "a6", "h4", "Nf6"] {
+            let mv = san
+                .parse::<shakmaty::san::San>()
+                .unwrap()
+                .to_move(&pos)
+                .unwrap();
+            pos.play_unchecked(&mv);
+            positions.push(pos.clone());
+        }
+        assert_eq!(last_book_ply(&positions), 6);
+        assert_eq!(last_book_ply(&positions[7..]), 0);
+    }
+
+    #[test]
+    fn engine_disagreements() {
+        let ply = |ply: usize, cp: i32, best: &str| MoveAnalysis {
+            ply,
+            score: Some(Score {
+                value: ScoreValue::Cp(cp),
+                ..Default::default()
+            }),
+            best_move: Some(best.to_string()),
+            ..Default::default()
+        };
+        let first = vec![ply(0, 20, "e2e4"), ply(1, 30, "e7e5"), ply(2, 40, "g1f3")];
+        let second = vec![ply(0, 35, "e2e4"), ply(1, 30, "c7c5"), ply(2, 400, "g1f3")];
+        let comparison = compare_engines("lc0", &first, &second, DEFAULT_DISAGREEMENT);
+        assert_eq!(comparison.engine, "lc0");
+        assert_eq!(comparison.disagreements, vec![1, 2]);
+        assert!(!comparison.plies[0].disagree);
+        assert_eq!(comparison.graph[2].value, Some(4.0));
+    }
+
+    #[test]
+    fn clocks() {
+        assert_eq!(parse_clock(b"[%clk 0:02:31]"), Some(151.0));
+        assert_eq!(
+            parse_clock(b"good move [%eval 0.3] [%clk 1:00:05.5]"),
+            Some(3605.5)
+        );
+        assert_eq!(parse_clock(b"[%clk 2:31]"), Some(151.0));
+        assert_eq!(parse_clock(b"no clock"), None);
+        assert_eq!(parse_clock(b"[%clk a:b]"), None);
+        assert_eq!(parse_clock(b"\xe9chec [%clk 0:01:00]"), Some(60.0));
+
+        assert_eq!(parse_time_control("300+2"), Some((300.0, 2.0)));
+        assert_eq!(parse_time_control("600"), Some((600.0, 0.0)));
+        assert_eq!(
+            parse_time_control("40/5400+30:1800+30"),
+            Some((5400.0, 30.0))
+        );
+        assert_eq!(parse_time_control("-"), None);
+
+        let clocks = [Some(299.0), Some(295.0), None, Some(290.0), Some(280.0)];
+        assert_eq!(
+            time_spent(&clocks, Some((300.0, 2.0))),
+            vec![None, Some(3.0), Some(7.0), None, Some(7.0), None]
+        );
+        assert_eq!(time_spent(&clocks, None)[1], None);
+    }
+
+    #[test]
+    fn fast_blunders_and_long_thinks() {
+        let mut evals = vec![Some(Eval::Cp(0)); 3];
+        evals.extend([Some(Eval::Cp(-500)); 2]);
+        evals.extend([Some(Eval::Cp(-1000)); 7]);
+        let classifications =
+            classify_moves(&evals, Color::White, &ClassificationThresholds::default());
+        let spent: Vec<Option<f64>> = [
+            0.0, 5.0, 10.0, 2.0, 10.0, 60.0, 10.0, 5.0, 10.0, 5.0, 10.0, 5.0,
+        ]
+        .into_iter()
+        .enumerate()
+        .map(|(ply, seconds)| (ply > 0).then_some(seconds))
+        .collect();
+        let moments = time_moments(
+            &evals,
+            &classifications,
+            &spent,
+            Color::White,
+            DEFAULT_FAST_MOVE_SECONDS,
+        );
+        assert_eq!(moments.len(), 2);
+        assert_eq!(moments[0].ply, 3);
+        assert_eq!(moments[0].kind, TimeMomentKind::FastBlunder);
+        assert!(moments[0].loss > 30.0);
+        assert_eq!(moments[1].ply, 5);
+        assert_eq!(moments[1].kind, TimeMomentKind::LongThink);
+        assert_eq!(moments[1].seconds, 60.0);
+    }
+
+    #[test]
+    fn win_chance_swings() {
+        let evals = [
+            Some(Eval::Cp(0)),
+            Some(Eval::Cp(30)),
+            None,
+            Some(Eval::Mate(3)),
+            Some(Eval::Mate(-2)),
+            Some(Eval::Over(Some(Color::Black))),
+        ];
+        let chances = win_chances(&evals, Color::White);
+        assert_eq!(chances[0].white, Some(50.0));
+        assert_eq!(chances[0].delta, None);
+        // White's move improved their chances
+        assert!(chances[1].delta.unwrap() > 0.0);
+        assert_eq!(chances[2].delta, None);
+        assert_eq!(chances[3].delta, None);
+        assert!((chances[3].white.unwrap() - 97.5).abs() < 0.1);
+        // Black's move turned getting mated into mating
+        assert!((chances[4].delta.unwrap() - 95.1).abs() < 0.1);
+        assert!((chances[5].delta.unwrap() + 2.5).abs() < 0.1);
+
+        let points = turning_points(&chances, TURNING_POINTS);
+        assert_eq!(points.len(), 1);
+        assert_eq!(points[0].ply, 4);
+
+        // The same swing is White's when Black moved first
+        let chances = win_chances(&evals, Color::Black);
+        assert!((chances[4].delta.unwrap() + 95.1).abs() < 0.1);
+    }
+
+    #[test]
+    fn stats_by_phase() {
+        let evals = [
+            Some(Eval::Cp(0)),
+            Some(Eval::Cp(-100)),
+            Some(Eval::Cp(-100)),
+            Some(Eval::Cp(-400)),
+            Some(Eval::Cp(0)),
+            None,
+            Some(Eval::Mate(-1)),
+        ];
+        let classifications = [
+            None,
+            Some(Classification::Inaccuracy),
+            None,
+            Some(Classification::Blunder),
+            Some(Classification::Blunder),
+            None,
+            None,
+        ];
+        let phases = [
+            Phase::Opening,
+            Phase::Opening,
+            Phase::Middlegame,
+            Phase::Middlegame,
+            Phase::Endgame,
+            Phase::Endgame,
+            Phase::Endgame,
+        ];
+        let stats = phase_stats(&evals, &classifications, &phases, Color::White);
+        assert_eq!(stats.white.opening.inaccuracies, 1);
+        assert_eq!(stats.white.middlegame.blunders, 1);
+        assert_eq!(stats.white.endgame, PhaseStats::default());
+        assert_eq!(stats.white.worst, Some(Phase::Middlegame));
+        assert_eq!(stats.black.middlegame.blunders, 1);
+        assert!(stats.black.middlegame.win_percent_lost > 30.0);
+        assert_eq!(stats.black.opening.win_percent_lost, 0.0);
+        assert_eq!(stats.black.worst, Some(Phase::Middlegame));
+
+        let stats = phase_stats(&evals[..2], &classifications[..2], &phases, Color::Black);
+        assert_eq!(stats.white.worst, None);
+        assert_eq!(stats.black.opening.inaccuracies, 1);
+        assert_eq!(stats.black.worst, None);
+    }
+}
@@ -0,0 +1,199 @@
+use std::{collections::HashMap, mem::size_of, sync::Arc};
+
+use serde::Serialize;
+use specta::Type;
+
+use crate::{chess::BestMoves, AppState};
+
+/// Positions kept at most
+const MAX_ENTRIES: usize = 1000;
+
+/// Bytes the lines kept take at most, roughly
+const MAX_SIZE: usize = 32 * 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct AnalysisCacheKey {
+    /// The position without move counters, so transpositions share an entry
+    pub fen: String,
+    pub engine: String,
+    /// Hash of the options the lines depend on
+    pub options: u64,
+}
+
+#[derive(Debug)]
+struct CachedAnalysis {
+    lines: Arc<Vec<BestMoves>>,
+    size: usize,
+    /// Value of the counter of the cache when the entry was last used
+    used: u64,
+}
+
+/// The deepest lines emitted for the positions analyzed recently, so going back to one
+/// shows them while the engine starts again. The least recently used ones are dropped
+/// when there are more than `MAX_ENTRIES` of them or they take more than `MAX_SIZE`.
+#[derive(Debug, Default)]
+pub struct AnalysisCache {
+    entries: HashMap<AnalysisCacheKey, CachedAnalysis>,
+    size: usize,
+    counter: u64,
+}
+
+/// Bytes taken by `lines`, counting their moves but not what the allocator adds
+fn lines_size(lines: &[BestMoves]) -> usize {
+    lines
+        .iter()
+        .map(|line| {
+            let moves = line.uci_moves.iter().chain(&line.san_moves);
+            size_of::<BestMoves>() + moves.map(|m| size_of::<String>() + m.len()).sum::<usize>()
+        })
+        .sum()
+}
+
+fn depth(lines: &[BestMoves]) -> u32 {
+    lines.first().map_or(0, |line| line.depth)
+}
+
+impl AnalysisCache {
+    fn tick(&mut self) -> u64 {
+        self.counter += 1;
+        self.counter
+    }
+
+    pub fn get(&mut self, key: &AnalysisCacheKey) -> Option<Arc<Vec<BestMoves>>> {
+        let used = self.tick();
+        let entry = self.entries.get_mut(key)?;
+        entry.used = used;
+        Some(Arc::clone(&entry.lines))
+    }
+
+    /// Keeps `lines` for the position unless the cache has deeper ones
+    pub fn insert(&mut self, key: AnalysisCacheKey, lines: Arc<Vec<BestMoves>>) {
+        if lines.is_empty() {
+            return;
+        }
+        let used = self.tick();
+        if let Some(entry) = self.entries.get_mut(&key) {
+            entry.used = used;
+            if depth(&entry.lines) > depth(&lines) {
+                return;
+            }
+            self.size -= entry.size;
+        }
+        let size = lines_size(&lines);
+        self.size += size;
+        self.entries
+            .insert(key, CachedAnalysis { lines, size, used });
+        while self.entries.len() > MAX_ENTRIES || self.size > MAX_SIZE {
+            let Some(oldest) = self
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.used)
+                .map(|(key, _)| key.clone())
+            else {
+                break;
+            };
+            if let Some(entry) = self.entries.remove(&oldest) {
+                self.size -= entry.size;
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct CachedAnalysisInfo {
+    pub fen: String,
+    pub engine: String,
+    pub depth: u32,
+    pub lines: usize,
+    /// Bytes taken by the lines, roughly
+    pub size: usize,
+}
+
+#[derive(Serialize, Debug, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct AnalysisCacheStats {
+    /// Positions analyzed, the most recently used first
+    pub positions: Vec<CachedAnalysisInfo>,
+    /// Bytes taken by the lines of all of them, roughly
+    pub size: usize,
+}
+
+#[tauri::command]
+#[specta::specta]
+pub fn get_analysis_cache_stats(state: tauri::State<'_, AppState>) -> AnalysisCacheStats {
+    let cache = state.analysis_cache.lock().unwrap();
+    let mut entries: Vec<_> = cache.entries.iter().collect();
+    entries.sort_by_key(|(_, entry)| std::cmp::Reverse(entry.used));
+    AnalysisCacheStats {
+        positions: entries
+            .into_iter()
+            .map(|(key, entry)| CachedAnalysisInfo {
+                fen: key.fen.clone(),
+                engine: key.engine.clone(),
+                depth: depth(&entry.lines),
+                lines: entry.lines.len(),
+                size: entry.size,
+            })
+            .collect(),
+        size: cache.size,
+    }
+}
+
+#[tauri::command]
+#[specta::specta]
+pub fn clear_analysis_cache(state: tauri::State<'_, AppState>) {
+    *state.analysis_cache.lock().unwrap() = AnalysisCache::default();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(fen: &str) -> AnalysisCacheKey {
+        AnalysisCacheKey {
+            fen: fen.to_string(),
+            engine: "stockfish".to_string(),
+            options: 0,
+        }
+    }
+
+    fn lines(depth: u32, moves: usize) -> Arc<Vec<BestMoves>> {
+        Arc::new(vec![BestMoves {
+            depth,
+            uci_moves: vec!["e2e4".to_string(); moves],
+            ..Default::default()
+        }])
+    }
+
+    #[test]
+    fn keeps_the_deepest_lines() {
+        let mut cache = AnalysisCache::default();
+        cache.insert(key("a"), lines(20, 1));
+        cache.insert(key("a"), lines(12, 1));
+        assert_eq!(cache.get(&key("a")).map(|lines| lines[0].depth), Some(20));
+        cache.insert(key("a"), lines(24, 3));
+        assert_eq!(cache.get(&key("a")).map(|lines| lines[0].depth), Some(24));
+        assert_eq!(cache.size, lines_size(&lines(24, 3)));
+        assert!(cache.get(&key("b")).is_none());
+    }
+
+    #[test]
+    fn drops_the_least_recently_used() {
+        let mut cache = AnalysisCache::default();
+        for index in 0..MAX_ENTRIES {
+            cache.insert(key(&index.to_string()), lines(10, 1));
+        }
+        cache.get(&key("0"));
+        cache.insert(key("new"), lines(10, 1));
+        assert_eq!(cache.entries.len(), MAX_ENTRIES);
+        assert!(cache.get(&key("0")).is_some());
+        assert!(cache.get(&key("1")).is_none());
+
+        // Lines larger than the whole cache aren't kept
+        let long = MAX_SIZE / (size_of::<String>() + 4);
+        cache.insert(key("long"), lines(10, long));
+        assert!(cache.size <= MAX_SIZE);
+        assert!(cache.get(&key("long")).is_none());
+    }
+}
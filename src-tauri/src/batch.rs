@@ -0,0 +1,735 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufReader, BufWriter},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Mutex,
+    },
+};
+
+use derivative::Derivative;
+use log::{error, info};
+use pgn_reader::{BufferedReader, RawComment, RawHeader, SanPlus, Skip, Visitor};
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use tauri::{
+    api::path::{resolve_path, BaseDirectory},
+    AppHandle, Manager,
+};
+use tauri_specta::Event;
+use tokio::sync::Notify;
+
+use crate::{
+    analysis::{parse_clock, GameAnalysis},
+    chess::{
+        analyze_game_with, quit_sessions, with_chess960, with_threads, AnalysisOptions,
+        EngineOption, EngineSessions, GoMode,
+    },
+    db::get_game_moves,
+    error::Error,
+    pgn::is_chess960,
+    AppState,
+};
+
+/// Upper bound for the number of games analyzed at the same time
+pub const MAX_BATCH_CONCURRENCY: usize = 4;
+
+const QUEUE_FILE: &str = "batch/queue.json";
+const REPORTS_DIR: &str = "batch/reports";
+
+/// Games to add to the queue
+#[derive(Deserialize, Debug, Clone, Type)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum BatchGame {
+    Database {
+        file: PathBuf,
+        id: i32,
+    },
+    /// Every game of the PGN gets its own job
+    Pgn {
+        pgn: String,
+    },
+}
+
+/// Where the game of a job comes from
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Type)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum JobSource {
+    Database {
+        file: PathBuf,
+        id: i32,
+    },
+    Pgn {
+        index: usize,
+        white: Option<String>,
+        black: Option<String>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Type)]
+#[serde(rename_all = "camelCase")]
+pub enum JobStatus {
+    Pending,
+    Running,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchJob {
+    /// Also the id of the `ReportProgress` events of the job
+    pub id: String,
+    pub source: JobSource,
+    pub engine: String,
+    pub go_mode: GoMode,
+    pub uci_options: Vec<EngineOption>,
+    pub options: AnalysisOptions,
+    pub status: JobStatus,
+    /// Worker analyzing the job, while it's running
+    #[serde(default)]
+    pub worker: Option<usize>,
+}
+
+#[derive(Derivative)]
+#[derivative(Default)]
+pub struct BatchQueue {
+    jobs: Mutex<Vec<BatchJob>>,
+    next_id: AtomicU64,
+    paused: AtomicBool,
+    #[derivative(Default(value = "AtomicUsize::new(1)"))]
+    concurrency: AtomicUsize,
+    /// Threads of the engine of each worker, 0 keeping the option of the jobs
+    threads_per_worker: AtomicUsize,
+    /// Engines of the idle workers, kept for their next game
+    engines: Mutex<HashMap<usize, EngineSessions>>,
+    notify: Notify,
+}
+
+/// The queue as it's written to disk. Running jobs are saved as pending, so they
+/// start over after a restart.
+#[derive(Serialize, Deserialize, Default)]
+struct SavedQueue {
+    next_id: u64,
+    paused: bool,
+    jobs: Vec<BatchJob>,
+    #[serde(default)]
+    threads_per_worker: usize,
+}
+
+/// Sent whenever jobs are added, start, finish or are moved. The position of a job
+/// in the queue is its index in `jobs`.
+#[derive(Clone, Type, Serialize, Event)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchQueueChanged {
+    pub paused: bool,
+    pub concurrency: usize,
+    pub threads_per_worker: usize,
+    /// Workers analyzing games at the same time, fewer than `concurrency` when their
+    /// threads would take more cores than there are. What each one does is the
+    /// `worker` of its running job.
+    pub workers: usize,
+    pub jobs: Vec<BatchJob>,
+}
+
+#[derive(Clone, Type, Serialize, Event)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchJobFinished {
+    pub id: String,
+    /// File the report was saved to
+    pub report: Option<PathBuf>,
+    pub error: Option<String>,
+}
+
+/// Workers for `concurrency` jobs at the same time, whose engines have `threads` threads
+/// each on a machine with `cores` cores. There is always at least one.
+fn worker_count(concurrency: usize, threads: usize, cores: usize) -> usize {
+    match threads {
+        0 => concurrency,
+        threads => concurrency.min(cores / threads).max(1),
+    }
+}
+
+impl BatchQueue {
+    fn snapshot(&self) -> BatchQueueChanged {
+        BatchQueueChanged {
+            paused: self.paused.load(Ordering::Relaxed),
+            concurrency: self.concurrency.load(Ordering::Relaxed),
+            threads_per_worker: self.threads_per_worker.load(Ordering::Relaxed),
+            workers: self.workers(),
+            jobs: self.jobs.lock().unwrap().clone(),
+        }
+    }
+
+    fn workers(&self) -> usize {
+        let cores = std::thread::available_parallelism().map_or(1, |cores| cores.get());
+        worker_count(
+            self.concurrency.load(Ordering::Relaxed),
+            self.threads_per_worker.load(Ordering::Relaxed),
+            cores,
+        )
+    }
+
+    /// Marks the next pending job as running on the first idle worker, unless the
+    /// queue is paused or every worker is busy
+    fn start_next(&self) -> Option<BatchJob> {
+        if self.paused.load(Ordering::Relaxed) {
+            return None;
+        }
+        let mut jobs = self.jobs.lock().unwrap();
+        let worker = (0..self.workers()).find(|&worker| {
+            jobs.iter()
+                .all(|job| job.status != JobStatus::Running || job.worker != Some(worker))
+        })?;
+        let job = jobs
+            .iter_mut()
+            .find(|job| job.status == JobStatus::Pending)?;
+        job.status = JobStatus::Running;
+        job.worker = Some(worker);
+        Some(job.clone())
+    }
+
+    fn take_engines(&self, worker: usize) -> EngineSessions {
+        let mut engines = self.engines.lock().unwrap();
+        engines.remove(&worker).unwrap_or_default()
+    }
+
+    /// Keeps the engines of `worker` for its next game. Gives back the engines to quit,
+    /// which are those of every idle worker when no game is about to start.
+    fn keep_engines(&self, worker: usize, sessions: EngineSessions) -> Vec<EngineSessions> {
+        let pending = self
+            .jobs
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|job| job.status == JobStatus::Pending);
+        let mut engines = self.engines.lock().unwrap();
+        if !pending || self.paused.load(Ordering::Relaxed) {
+            let mut idle: Vec<_> = engines.drain().map(|(_, sessions)| sessions).collect();
+            idle.push(sessions);
+            return idle;
+        }
+        if worker >= self.workers() || engines.contains_key(&worker) {
+            return vec![sessions];
+        }
+        engines.insert(worker, sessions);
+        Vec::new()
+    }
+
+    fn remove(&self, id: &str) -> Option<BatchJob> {
+        let mut jobs = self.jobs.lock().unwrap();
+        let index = jobs.iter().position(|job| job.id == id)?;
+        Some(jobs.remove(index))
+    }
+}
+
+/// Id of a new background task, like `batch-3` or `import-4`. Batch analysis jobs,
+/// imports and the other tasks share the counter of the queue, so the frontend can
+/// list them all by id.
+pub fn new_job_id(state: &AppState, kind: &str) -> String {
+    let id = state.batch.next_id.fetch_add(1, Ordering::Relaxed);
+    format!("{kind}-{id}")
+}
+
+fn resolve(app: &AppHandle, path: &str) -> Result<PathBuf, Error> {
+    Ok(resolve_path(
+        &app.config(),
+        app.package_info(),
+        &app.env(),
+        path,
+        Some(BaseDirectory::AppData),
+    )?)
+}
+
+fn save_queue(app: &AppHandle, queue: &BatchQueue) -> Result<(), Error> {
+    let mut jobs = queue.jobs.lock().unwrap().clone();
+    for job in jobs.iter_mut() {
+        job.status = JobStatus::Pending;
+        job.worker = None;
+    }
+    let saved = SavedQueue {
+        next_id: queue.next_id.load(Ordering::Relaxed),
+        paused: queue.paused.load(Ordering::Relaxed),
+        jobs,
+        threads_per_worker: queue.threads_per_worker.load(Ordering::Relaxed),
+    };
+    let file = File::create(resolve(app, QUEUE_FILE)?)?;
+    serde_json::to_writer(BufWriter::new(file), &saved).map_err(std::io::Error::from)?;
+    Ok(())
+}
+
+/// Saves the queue and tells the frontend about the change
+fn queue_changed(app: &AppHandle, queue: &BatchQueue) -> Result<(), Error> {
+    save_queue(app, queue)?;
+    queue.snapshot().emit_all(app)?;
+    Ok(())
+}
+
+/// Loads the jobs left from the last session and starts the worker
+pub fn start_batch_worker(app: AppHandle) {
+    let state = app.state::<AppState>();
+    if let Ok(file) = resolve(&app, QUEUE_FILE).and_then(|path| Ok(File::open(path)?)) {
+        match serde_json::from_reader::<_, SavedQueue>(BufReader::new(file)) {
+            Ok(saved) => {
+                info!("Restoring {} batch analysis jobs", saved.jobs.len());
+                state.batch.next_id.store(saved.next_id, Ordering::Relaxed);
+                state.batch.paused.store(saved.paused, Ordering::Relaxed);
+                state
+                    .batch
+                    .threads_per_worker
+                    .store(saved.threads_per_worker, Ordering::Relaxed);
+                *state.batch.jobs.lock().unwrap() = saved.jobs;
+            }
+            Err(e) => error!("Failed to read the batch analysis queue: {}", e),
+        }
+    }
+    tauri::async_runtime::spawn(run_worker(app.clone()));
+}
+
+async fn run_worker(app: AppHandle) {
+    let state = app.state::<AppState>();
+    loop {
+        match state.batch.start_next() {
+            Some(job) => {
+                if let Err(e) = queue_changed(&app, &state.batch) {
+                    error!("Failed to update the batch analysis queue: {}", e);
+                }
+                tauri::async_runtime::spawn(run_job(app.clone(), job));
+            }
+            None => state.batch.notify.notified().await,
+        }
+    }
+}
+
+/// Analyzes the game of `job` with the engines its worker kept from its last game
+async fn run_job(app: AppHandle, job: BatchJob) {
+    let state = app.state::<AppState>();
+    let worker = job.worker.unwrap_or_default();
+    let mut sessions = state.batch.take_engines(worker);
+    let uci_options = match state.batch.threads_per_worker.load(Ordering::Relaxed) {
+        0 => job.uci_options.clone(),
+        threads => with_threads(job.uci_options.clone(), threads),
+    };
+    let result = analyze_game_with(
+        job.id.clone(),
+        job.engine.clone(),
+        job.go_mode.clone(),
+        job.options.clone(),
+        uci_options,
+        &mut sessions,
+        state.clone(),
+        app.clone(),
+    )
+    .await
+    .and_then(|report| save_report(&app, &job.id, &report));
+
+    state.batch.remove(&job.id);
+    for sessions in state.batch.keep_engines(worker, sessions) {
+        quit_sessions(sessions).await;
+    }
+    let event = match result {
+        Ok(path) => BatchJobFinished {
+            id: job.id,
+            report: Some(path),
+            error: None,
+        },
+        Err(e) => {
+            error!("Batch analysis job {} failed: {}", job.id, e);
+            BatchJobFinished {
+                id: job.id,
+                report: None,
+                error: Some(e.to_string()),
+            }
+        }
+    };
+    if let Err(e) = event
+        .emit_all(&app)
+        .map_err(Error::from)
+        .and_then(|_| queue_changed(&app, &state.batch))
+    {
+        error!("Failed to update the batch analysis queue: {}", e);
+    }
+    state.batch.notify.notify_one();
+}
+
+fn save_report(app: &AppHandle, id: &str, report: &GameAnalysis) -> Result<PathBuf, Error> {
+    let path = resolve(app, REPORTS_DIR)?.join(format!("{id}.json"));
+    let file = File::create(&path)?;
+    serde_json::to_writer(BufWriter::new(file), report).map_err(std::io::Error::from)?;
+    Ok(path)
+}
+
+#[derive(Default)]
+struct MainLine {
+    fen: Option<String>,
+    white: Option<String>,
+    black: Option<String>,
+    time_control: Option<String>,
+    /// Set by the `Variant` tag, so the engine is told to castle the Chess960 way
+    chess960: bool,
+    moves: Vec<String>,
+    /// Clock after each move, aligned with `moves`
+    clocks: Vec<Option<f64>>,
+}
+
+struct MainLineReader {
+    game: MainLine,
+}
+
+impl Visitor for MainLineReader {
+    type Result = MainLine;
+
+    fn begin_game(&mut self) {
+        self.game = MainLine::default();
+    }
+
+    fn header(&mut self, key: &[u8], value: RawHeader<'_>) {
+        let value = Some(value.decode_utf8_lossy().into_owned());
+        match key {
+            b"FEN" => self.game.fen = value,
+            b"White" => self.game.white = value,
+            b"Black" => self.game.black = value,
+            b"TimeControl" => self.game.time_control = value,
+            b"Variant" => self.game.chess960 = value.as_deref().is_some_and(is_chess960),
+            _ => {}
+        }
+    }
+
+    fn san(&mut self, san: SanPlus) {
+        self.game.moves.push(san.to_string());
+        self.game.clocks.push(None);
+    }
+
+    fn comment(&mut self, comment: RawComment<'_>) {
+        if let Some(clock) = self.game.clocks.last_mut() {
+            if let Some(time) = parse_clock(comment.as_bytes()) {
+                *clock = Some(time);
+            }
+        }
+    }
+
+    fn begin_variation(&mut self) -> Skip {
+        Skip(true)
+    }
+
+    fn end_game(&mut self) -> Self::Result {
+        std::mem::take(&mut self.game)
+    }
+}
+
+fn read_main_lines(pgn: &str) -> Result<Vec<MainLine>, Error> {
+    let mut reader = BufferedReader::new(pgn.as_bytes());
+    let mut visitor = MainLineReader {
+        game: MainLine::default(),
+    };
+    let mut games = Vec::new();
+    while let Some(game) = reader.read_game(&mut visitor)? {
+        games.push(game);
+    }
+    Ok(games)
+}
+
+/// Adds games to the end of the queue and returns the ids of their jobs
+#[tauri::command]
+#[specta::specta]
+pub async fn queue_analysis(
+    games: Vec<BatchGame>,
+    engine: String,
+    go_mode: GoMode,
+    uci_options: Vec<EngineOption>,
+    state: tauri::State<'_, AppState>,
+    app: AppHandle,
+) -> Result<Vec<String>, Error> {
+    let mut sources = Vec::new();
+    for game in games {
+        match game {
+            BatchGame::Database { file, id } => {
+                let (fen, moves) = get_game_moves(&file, id, &state)?;
+                let options = AnalysisOptions {
+                    fen,
+                    moves,
+                    ..Default::default()
+                };
+                sources.push((JobSource::Database { file, id }, options, false));
+            }
+            BatchGame::Pgn { pgn } => {
+                for (index, game) in read_main_lines(&pgn)?.into_iter().enumerate() {
+                    let source = JobSource::Pgn {
+                        index,
+                        white: game.white,
+                        black: game.black,
+                    };
+                    let options = AnalysisOptions {
+                        fen: game
+                            .fen
+                            .unwrap_or_else(|| shakmaty::fen::Fen::default().to_string()),
+                        moves: game.moves,
+                        clocks: game.clocks,
+                        time_control: game.time_control,
+                        ..Default::default()
+                    };
+                    sources.push((source, options, game.chess960));
+                }
+            }
+        }
+    }
+
+    let jobs: Vec<BatchJob> = sources
+        .into_iter()
+        .map(|(source, options, chess960)| BatchJob {
+            id: new_job_id(&state, "batch"),
+            source,
+            engine: engine.clone(),
+            go_mode: go_mode.clone(),
+            uci_options: if chess960 {
+                with_chess960(uci_options.clone())
+            } else {
+                uci_options.clone()
+            },
+            options,
+            status: JobStatus::Pending,
+            worker: None,
+        })
+        .collect();
+    let ids = jobs.iter().map(|job| job.id.clone()).collect();
+    state.batch.jobs.lock().unwrap().extend(jobs);
+    queue_changed(&app, &state.batch)?;
+    state.batch.notify.notify_one();
+    Ok(ids)
+}
+
+#[tauri::command]
+#[specta::specta]
+pub fn get_batch_queue(state: tauri::State<'_, AppState>) -> BatchQueueChanged {
+    state.batch.snapshot()
+}
+
+/// A paused queue doesn't start new jobs, but lets the running ones finish
+#[tauri::command]
+#[specta::specta]
+pub fn set_batch_paused(
+    paused: bool,
+    state: tauri::State<'_, AppState>,
+    app: AppHandle,
+) -> Result<(), Error> {
+    state.batch.paused.store(paused, Ordering::Relaxed);
+    queue_changed(&app, &state.batch)?;
+    state.batch.notify.notify_one();
+    Ok(())
+}
+
+/// Sets the threads of the engine of each worker, 0 to use the option of each job. The
+/// jobs running keep the threads they started with.
+#[tauri::command]
+#[specta::specta]
+pub fn set_batch_threads(
+    threads: usize,
+    state: tauri::State<'_, AppState>,
+    app: AppHandle,
+) -> Result<(), Error> {
+    state
+        .batch
+        .threads_per_worker
+        .store(threads, Ordering::Relaxed);
+    queue_changed(&app, &state.batch)?;
+    state.batch.notify.notify_one();
+    Ok(())
+}
+
+#[tauri::command]
+#[specta::specta]
+pub fn set_batch_concurrency(
+    concurrency: usize,
+    state: tauri::State<'_, AppState>,
+    app: AppHandle,
+) -> Result<(), Error> {
+    state.batch.concurrency.store(
+        concurrency.clamp(1, MAX_BATCH_CONCURRENCY),
+        Ordering::Relaxed,
+    );
+    queue_changed(&app, &state.batch)?;
+    state.batch.notify.notify_one();
+    Ok(())
+}
+
+/// Moves a job to `position` in the queue, or to the end if it's out of range
+fn move_job(jobs: &mut Vec<BatchJob>, id: &str, position: usize) -> Result<(), Error> {
+    let index = jobs
+        .iter()
+        .position(|job| job.id == id)
+        .ok_or(Error::NoBatchJob)?;
+    let job = jobs.remove(index);
+    jobs.insert(position.min(jobs.len()), job);
+    Ok(())
+}
+
+#[tauri::command]
+#[specta::specta]
+pub fn move_batch_job(
+    id: String,
+    position: usize,
+    state: tauri::State<'_, AppState>,
+    app: AppHandle,
+) -> Result<(), Error> {
+    move_job(&mut state.batch.jobs.lock().unwrap(), &id, position)?;
+    queue_changed(&app, &state.batch)
+}
+
+/// Stops the engines of a running job. `run_job` reports the job as finished once
+/// their search stops, and its worker quits them.
+async fn stop_job(state: &AppState, job: &BatchJob) -> Result<(), Error> {
+    let processes: Vec<_> = state
+        .engine_processes
+        .iter()
+        .filter(|process| process.key().0 == job.id)
+        .map(|process| process.value().clone())
+        .collect();
+    for process in processes {
+        process.lock().await.stop().await?;
+    }
+    Ok(())
+}
+
+/// Removes a pending job, or stops the engine of a running one
+#[tauri::command]
+#[specta::specta]
+pub async fn cancel_batch_job(
+    id: String,
+    state: tauri::State<'_, AppState>,
+    app: AppHandle,
+) -> Result<(), Error> {
+    let job = state.batch.remove(&id).ok_or(Error::NoBatchJob)?;
+    if job.status == JobStatus::Running {
+        stop_job(&state, &job).await?;
+    }
+    queue_changed(&app, &state.batch)
+}
+
+/// Empties the queue, stopping every worker. The reports of the jobs that finished
+/// are kept.
+#[tauri::command]
+#[specta::specta]
+pub async fn cancel_batch(state: tauri::State<'_, AppState>, app: AppHandle) -> Result<(), Error> {
+    let jobs = std::mem::take(&mut *state.batch.jobs.lock().unwrap());
+    let idle = std::mem::take(&mut *state.batch.engines.lock().unwrap());
+    for sessions in idle.into_values() {
+        quit_sessions(sessions).await;
+    }
+    for job in jobs.iter().filter(|job| job.status == JobStatus::Running) {
+        stop_job(&state, job).await?;
+    }
+    queue_changed(&app, &state.batch)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn job(id: &str) -> BatchJob {
+        BatchJob {
+            id: id.to_string(),
+            source: JobSource::Database {
+                file: PathBuf::from("games.db3"),
+                id: 1,
+            },
+            engine: "stockfish".to_string(),
+            go_mode: GoMode::Depth(20),
+            uci_options: Vec::new(),
+            options: AnalysisOptions::default(),
+            status: JobStatus::Pending,
+            worker: None,
+        }
+    }
+
+    fn ids(jobs: &[BatchJob]) -> Vec<&str> {
+        jobs.iter().map(|job| job.id.as_str()).collect()
+    }
+
+    #[test]
+    fn main_lines_of_pgn() {
+        let pgn = r#"[White "Alice"]
+[Black "Bob"]
+
+1. e4 { [%clk 0:03:00] } (1. d4 { [%clk 0:02:00] } d5) e5 2. Nf3 { [%clk 0:02:55.5] } 1-0
+
+[FEN "8/8/8/8/8/3k4/8/3K3R w - - 0 1"]
+
+1. Rh3+ *
+
+[Variant "Chess960"]
+[FEN "bqnbrkrn/pppppppp/8/8/8/8/PPPPPPPP/BQNBRKRN w KQkq - 0 1"]
+
+1. O-O *
+"#;
+        let games = read_main_lines(pgn).unwrap();
+        assert_eq!(games.len(), 3);
+        assert_eq!(games[0].white.as_deref(), Some("Alice"));
+        assert_eq!(games[0].black.as_deref(), Some("Bob"));
+        assert_eq!(games[0].fen, None);
+        assert_eq!(games[0].moves, vec!["e4", "e5", "Nf3"]);
+        assert_eq!(games[0].clocks, vec![Some(180.0), None, Some(175.5)]);
+        assert_eq!(
+            games[1].fen.as_deref(),
+            Some("8/8/8/8/8/3k4/8/3K3R w - - 0 1")
+        );
+        assert_eq!(games[1].moves, vec!["Rh3+"]);
+        assert!(!games[1].chess960);
+        assert!(games[2].chess960);
+    }
+
+    #[test]
+    fn start_next_respects_concurrency() {
+        let queue = BatchQueue::default();
+        *queue.jobs.lock().unwrap() = vec![job("a"), job("b")];
+        assert_eq!(queue.start_next().unwrap().id, "a");
+        assert!(queue.start_next().is_none());
+
+        queue.concurrency.store(2, Ordering::Relaxed);
+        let next = queue.start_next().unwrap();
+        assert_eq!((next.id.as_str(), next.worker), ("b", Some(1)));
+        assert!(queue.start_next().is_none());
+
+        // The worker of a finished job picks up the next one
+        queue.remove("a");
+        queue.jobs.lock().unwrap().push(job("c"));
+        queue.paused.store(true, Ordering::Relaxed);
+        assert!(queue.start_next().is_none());
+        queue.paused.store(false, Ordering::Relaxed);
+        let next = queue.start_next().unwrap();
+        assert_eq!((next.id.as_str(), next.worker), ("c", Some(0)));
+    }
+
+    #[test]
+    fn workers_fit_the_cores() {
+        assert_eq!(worker_count(4, 0, 2), 4);
+        assert_eq!(worker_count(4, 2, 16), 4);
+        assert_eq!(worker_count(4, 4, 8), 2);
+        assert_eq!(worker_count(2, 8, 4), 1);
+    }
+
+    #[test]
+    fn idle_workers_keep_their_engines_while_jobs_are_left() {
+        let queue = BatchQueue::default();
+        queue.concurrency.store(2, Ordering::Relaxed);
+        *queue.jobs.lock().unwrap() = vec![job("a")];
+        assert!(queue.keep_engines(1, EngineSessions::new()).is_empty());
+        assert!(queue.engines.lock().unwrap().contains_key(&1));
+        // Workers beyond the concurrency don't
+        assert_eq!(queue.keep_engines(2, EngineSessions::new()).len(), 1);
+
+        // and the last one to finish quits the engines of all
+        queue.remove("a");
+        assert_eq!(queue.keep_engines(0, EngineSessions::new()).len(), 2);
+        assert!(queue.engines.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn reorder_jobs() {
+        let mut jobs = vec![job("a"), job("b"), job("c")];
+        move_job(&mut jobs, "c", 0).unwrap();
+        assert_eq!(ids(&jobs), vec!["c", "a", "b"]);
+        move_job(&mut jobs, "c", 10).unwrap();
+        assert_eq!(ids(&jobs), vec!["a", "b", "c"]);
+        assert!(move_job(&mut jobs, "d", 0).is_err());
+    }
+}
@@ -0,0 +1,498 @@
+use std::{
+    fs::File,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use memmap2::Mmap;
+use rand::{distributions::WeightedIndex, prelude::Distribution, Rng};
+use serde::{Deserialize, Serialize};
+use shakmaty::{
+    fen::Fen,
+    san::SanPlus,
+    uci::Uci,
+    zobrist::{Zobrist64, ZobristHash},
+    CastlingMode, Chess, EnPassantMode, File as BoardFile, Move, Piece, Position, Rank, Role,
+    Square,
+};
+use specta::Type;
+use tauri::{
+    api::path::{resolve_path, BaseDirectory},
+    AppHandle, Manager,
+};
+
+use crate::error::Error;
+
+/// Bytes of an entry of a Polyglot book: the key, the move, the weight and the learn
+/// value, big endian
+const ENTRY_SIZE: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BookEntry {
+    pub key: u64,
+    pub mov: u16,
+    pub weight: u16,
+    pub learn: u32,
+}
+
+fn read_entry(bytes: &[u8]) -> BookEntry {
+    BookEntry {
+        key: u64::from_be_bytes(bytes[0..8].try_into().unwrap()),
+        mov: u16::from_be_bytes(bytes[8..10].try_into().unwrap()),
+        weight: u16::from_be_bytes(bytes[10..12].try_into().unwrap()),
+        learn: u32::from_be_bytes(bytes[12..16].try_into().unwrap()),
+    }
+}
+
+/// Polyglot key of the position. The en passant file only counts when a pawn of the
+/// side to move stands next to the pawn that was pushed, even if taking it is illegal,
+/// which is the pseudo-legal mode of shakmaty and not the legal one of the database
+/// index. Castling rights and the side to move are hashed as in the other keys.
+pub fn book_key(pos: &Chess) -> u64 {
+    pos.zobrist_hash::<Zobrist64>(EnPassantMode::PseudoLegal).0
+}
+
+/// Entries of `key` in `book`, whose entries are sorted by key
+fn find_entries(book: &[u8], key: u64) -> impl Iterator<Item = BookEntry> + '_ {
+    let count = book.len() / ENTRY_SIZE;
+    let entry = move |index: usize| read_entry(&book[index * ENTRY_SIZE..][..ENTRY_SIZE]);
+    let (mut low, mut high) = (0, count);
+    while low < high {
+        let middle = low + (high - low) / 2;
+        if entry(middle).key < key {
+            low = middle + 1;
+        } else {
+            high = middle;
+        }
+    }
+    (low..count)
+        .map(entry)
+        .take_while(move |entry| entry.key == key)
+}
+
+/// The move of an entry, packed as the destination file and rank, the origin file and
+/// rank and the promotion in groups of 3 bits. Castling is stored as the king taking its
+/// own rook, `e1h1` for white's short castling. None for moves that aren't legal, which
+/// only hash collisions have.
+fn decode_move(pos: &Chess, mov: u16) -> Option<Move> {
+    let square = |bits: u16| {
+        Square::from_coords(
+            BoardFile::new(u32::from(bits & 7)),
+            Rank::new(u32::from((bits >> 3) & 7)),
+        )
+    };
+    let to = square(mov);
+    let from = square(mov >> 6);
+    let promotion = match (mov >> 12) & 7 {
+        0 => None,
+        1 => Some(Role::Knight),
+        2 => Some(Role::Bishop),
+        3 => Some(Role::Rook),
+        4 => Some(Role::Queen),
+        _ => return None,
+    };
+
+    let board = pos.board();
+    let own_rook = Piece {
+        color: pos.turn(),
+        role: Role::Rook,
+    };
+    if board.role_at(from) == Some(Role::King) && board.piece_at(to) == Some(own_rook) {
+        let castle = Move::Castle {
+            king: from,
+            rook: to,
+        };
+        return pos.is_legal(&castle).then_some(castle);
+    }
+    Uci::Normal {
+        from,
+        to,
+        promotion,
+    }
+    .to_move(pos)
+    .ok()
+}
+
+/// `m` packed as in the entries, the reverse of `decode_move`
+pub fn encode_move(m: &Move) -> u16 {
+    let to = match *m {
+        Move::Castle { rook, .. } => rook,
+        _ => m.to(),
+    };
+    let from = m.from().unwrap_or(to);
+    let promotion = match m.promotion() {
+        Some(Role::Knight) => 1,
+        Some(Role::Bishop) => 2,
+        Some(Role::Rook) => 3,
+        Some(Role::Queen) => 4,
+        _ => 0,
+    };
+    (promotion << 12) | (u16::from(from) << 6) | u16::from(to)
+}
+
+/// Writes `entries` as a Polyglot book, sorted by key as the format requires and by
+/// weight within a key
+pub fn write_book(writer: &mut impl Write, mut entries: Vec<BookEntry>) -> std::io::Result<()> {
+    entries.sort_by(|a, b| a.key.cmp(&b.key).then(b.weight.cmp(&a.weight)));
+    for entry in entries {
+        writer.write_all(&entry.key.to_be_bytes())?;
+        writer.write_all(&entry.mov.to_be_bytes())?;
+        writer.write_all(&entry.weight.to_be_bytes())?;
+        writer.write_all(&entry.learn.to_be_bytes())?;
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Serialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct BookMove {
+    pub uci: String,
+    pub san: String,
+    pub weight: u16,
+    pub learn: u32,
+    /// Share of the weights of the moves of the position, in percent
+    pub percentage: f64,
+}
+
+/// Legal moves the book has for `pos`, the heaviest first
+pub fn book_moves(book: &[u8], pos: &Chess) -> Vec<BookMove> {
+    let entries: Vec<_> = find_entries(book, book_key(pos))
+        .filter_map(|entry| Some((entry, decode_move(pos, entry.mov)?)))
+        .collect();
+    let total: u32 = entries
+        .iter()
+        .map(|(entry, _)| u32::from(entry.weight))
+        .sum();
+    let mut moves: Vec<_> = entries
+        .into_iter()
+        .map(|(entry, m)| BookMove {
+            uci: m.to_uci(CastlingMode::Standard).to_string(),
+            san: SanPlus::from_move(pos.clone(), &m).to_string(),
+            weight: entry.weight,
+            learn: entry.learn,
+            percentage: if total == 0 {
+                0.0
+            } else {
+                f64::from(entry.weight) * 100.0 / f64::from(total)
+            },
+        })
+        .collect();
+    moves.sort_by(|a, b| b.weight.cmp(&a.weight));
+    moves
+}
+
+/// A move of `moves`, sorted by weight, drawn with a `randomness` between 0, which always
+/// gives the heaviest move, and 1, which draws them in proportion to their weight. Moves
+/// weighing nothing are never played.
+pub fn choose_book_move<'a>(
+    moves: &'a [BookMove],
+    randomness: f64,
+    rng: &mut impl Rng,
+) -> Option<&'a BookMove> {
+    let moves: Vec<_> = moves.iter().filter(|m| m.weight > 0).collect();
+    let heaviest = f64::from(moves.first()?.weight);
+    let randomness = randomness.clamp(0.0, 1.0);
+    if randomness == 0.0 {
+        return moves.first().copied();
+    }
+    let weights = moves
+        .iter()
+        .map(|m| (f64::from(m.weight) / heaviest).powf(1.0 / randomness));
+    let index = WeightedIndex::new(weights).ok()?.sample(rng);
+    Some(moves[index])
+}
+
+pub fn open_book(path: &Path) -> Result<Mmap, Error> {
+    let file = File::open(path)?;
+    // SAFETY: books are only read, and aren't expected to change while they are open
+    Ok(unsafe { Mmap::map(&file)? })
+}
+
+/// A book move for `pos`, None when it's out of the book
+pub fn book_move(path: &Path, pos: &Chess, randomness: f64) -> Result<Option<BookMove>, Error> {
+    let moves = book_moves(&open_book(path)?, pos);
+    Ok(choose_book_move(&moves, randomness, &mut rand::thread_rng()).cloned())
+}
+
+/// A Polyglot book to play the opening from
+#[derive(Debug, Clone, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct BookOptions {
+    pub path: PathBuf,
+    /// From 0, to always play the heaviest move, to 1, to play moves in proportion to
+    /// their weight
+    pub randomness: f64,
+}
+
+/// Moves the Polyglot book at `book_path` has for the position `fen`, the heaviest
+/// first, with their weights and learn values
+#[tauri::command]
+#[specta::specta]
+pub async fn get_book_moves(book_path: PathBuf, fen: String) -> Result<Vec<BookMove>, Error> {
+    let pos: Chess = Fen::from_ascii(fen.as_bytes())?.into_position(CastlingMode::Standard)?;
+    Ok(book_moves(&open_book(&book_path)?, &pos))
+}
+
+/// Draws a move of the Polyglot book at `book_path` for the position `fen` by weight,
+/// with a `randomness` from 0, for the heaviest move, to 1, for moves in proportion to
+/// their weight. None when the position is out of the book.
+#[tauri::command]
+#[specta::specta]
+pub async fn pick_book_move(
+    book_path: PathBuf,
+    fen: String,
+    randomness: f64,
+) -> Result<Option<BookMove>, Error> {
+    let pos: Chess = Fen::from_ascii(fen.as_bytes())?.into_position(CastlingMode::Standard)?;
+    book_move(&book_path, &pos, randomness)
+}
+
+#[derive(Debug, Clone, Serialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct BookInfo {
+    pub name: String,
+    pub path: PathBuf,
+    pub size: u64,
+    pub entries: u64,
+}
+
+/// Polyglot books of the books folder of the app data, by name
+#[tauri::command]
+#[specta::specta]
+pub async fn list_books(app: AppHandle) -> Result<Vec<BookInfo>, Error> {
+    let dir = resolve_path(
+        &app.config(),
+        app.package_info(),
+        &app.env(),
+        "books",
+        Some(BaseDirectory::AppData),
+    )?;
+    books_in(&dir)
+}
+
+/// Polyglot books of `dir`, by name, none when it doesn't exist yet
+fn books_in(dir: &Path) -> Result<Vec<BookInfo>, Error> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut books = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        let is_book = path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("bin"));
+        if !is_book || !path.is_file() {
+            continue;
+        }
+        let size = path.metadata()?.len();
+        books.push(BookInfo {
+            name: path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            path,
+            size,
+            entries: size / ENTRY_SIZE as u64,
+        });
+    }
+    books.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(books)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lists_books_by_name() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(books_in(&dir.path().join("books")).unwrap().is_empty());
+        std::fs::write(dir.path().join("Titans.BIN"), vec![0; 2 * ENTRY_SIZE]).unwrap();
+        std::fs::write(dir.path().join("notes.txt"), "").unwrap();
+        std::fs::write(dir.path().join("Elo2400.bin"), vec![0; ENTRY_SIZE]).unwrap();
+        let books = books_in(dir.path()).unwrap();
+        let names: Vec<&str> = books.iter().map(|book| book.name.as_str()).collect();
+        assert_eq!(names, ["Elo2400", "Titans"]);
+        assert_eq!(books[1].entries, 2);
+    }
+
+    fn play(moves: &[&str]) -> Chess {
+        moves.iter().fold(Chess::default(), |pos, uci| {
+            let m = uci.parse::<Uci>().unwrap().to_move(&pos).unwrap();
+            pos.play(&m).unwrap()
+        })
+    }
+
+    fn entry(key: u64, mov: u16, weight: u16, learn: u32) -> Vec<u8> {
+        let mut bytes = key.to_be_bytes().to_vec();
+        bytes.extend(mov.to_be_bytes());
+        bytes.extend(weight.to_be_bytes());
+        bytes.extend(learn.to_be_bytes());
+        bytes
+    }
+
+    /// Polyglot encoding of a move between two squares, without promotion
+    fn encode(from: Square, to: Square) -> u16 {
+        (u16::from(from) << 6) | u16::from(to)
+    }
+
+    #[test]
+    fn computes_polyglot_keys() {
+        // The examples of the specification of the format
+        let keys: [(&[&str], u64); 9] = [
+            (&[], 0x463b96181691fc9c),
+            (&["e2e4"], 0x823c9b50fd114196),
+            (&["e2e4", "d7d5"], 0x0756b94461c50fb0),
+            (&["e2e4", "d7d5", "e4e5"], 0x662fafb965db29d4),
+            (&["e2e4", "d7d5", "e4e5", "f7f5"], 0x22a48b5a8e47ff78),
+            (
+                &["e2e4", "d7d5", "e4e5", "f7f5", "e1e2"],
+                0x652a607ca3f242c1,
+            ),
+            (
+                &["e2e4", "d7d5", "e4e5", "f7f5", "e1e2", "e8f7"],
+                0x00fdd303c946bdd9,
+            ),
+            (
+                &["a2a4", "b7b5", "h2h4", "b5b4", "c2c4"],
+                0x3c8123ea7b067637,
+            ),
+            (
+                &["a2a4", "b7b5", "h2h4", "b5b4", "c2c4", "b4c3", "a1a3"],
+                0x5c3f9b829b279560,
+            ),
+        ];
+        for (moves, key) in keys {
+            assert_eq!(book_key(&play(moves)), key, "{moves:?}");
+        }
+    }
+
+    #[test]
+    fn reads_book_moves() {
+        let start = Chess::default();
+        let castling: Chess = Fen::from_ascii(b"r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1")
+            .unwrap()
+            .into_position(CastlingMode::Standard)
+            .unwrap();
+        let mut book = Vec::new();
+        let mut entries = vec![
+            (book_key(&start), encode(Square::E2, Square::E4), 30, 7),
+            (book_key(&start), encode(Square::D2, Square::D4), 10, 0),
+            (book_key(&start), encode(Square::E2, Square::E5), 50, 0),
+            (book_key(&castling), encode(Square::E1, Square::H1), 4, 0),
+            (book_key(&castling), encode(Square::E1, Square::A1), 0, 0),
+        ];
+        entries.sort_by_key(|entry| entry.0);
+        for (key, mov, weight, learn) in entries {
+            book.extend(entry(key, mov, weight, learn));
+        }
+
+        let moves = book_moves(&book, &start);
+        let found: Vec<_> = moves
+            .iter()
+            .map(|m| (m.uci.as_str(), m.san.as_str(), m.weight, m.learn))
+            .collect();
+        assert_eq!(found, [("e2e4", "e4", 30, 7), ("d2d4", "d4", 10, 0)]);
+        assert_eq!(moves[0].percentage, 75.0);
+
+        let castles: Vec<_> = book_moves(&book, &castling)
+            .into_iter()
+            .map(|m| (m.uci, m.san))
+            .collect();
+        assert_eq!(
+            castles,
+            [
+                ("e1g1".to_string(), "O-O".to_string()),
+                ("e1c1".to_string(), "O-O-O".to_string())
+            ]
+        );
+        assert!(book_moves(&book, &play(&["e2e4"])).is_empty());
+        assert!(book_moves(&[], &start).is_empty());
+    }
+
+    #[test]
+    fn draws_moves_by_weight() {
+        let m = |uci: &str, weight| BookMove {
+            uci: uci.to_string(),
+            san: String::new(),
+            weight,
+            learn: 0,
+            percentage: 0.0,
+        };
+        let moves = [m("e2e4", 60), m("d2d4", 30), m("c2c4", 10), m("g2g4", 0)];
+        let mut rng = <rand::rngs::StdRng as rand::SeedableRng>::seed_from_u64(7);
+        let mut draw = |randomness| {
+            let mut counts = std::collections::HashMap::new();
+            for _ in 0..2000 {
+                let m = choose_book_move(&moves, randomness, &mut rng).unwrap();
+                *counts.entry(m.uci.as_str()).or_insert(0) += 1;
+            }
+            counts
+        };
+
+        assert_eq!(draw(0.0).get("e2e4"), Some(&2000));
+        let proportional = draw(1.0);
+        assert!((1050..1350).contains(&proportional["e2e4"]));
+        assert!((450..750).contains(&proportional["d2d4"]));
+        assert!(!proportional.contains_key("g2g4"));
+        let sharper = draw(0.3);
+        assert!(sharper["e2e4"] > proportional["e2e4"]);
+
+        assert!(choose_book_move(&[], 1.0, &mut rng).is_none());
+        assert!(choose_book_move(&moves[3..], 1.0, &mut rng).is_none());
+    }
+
+    #[test]
+    fn writes_books() {
+        let castling: Chess = Fen::from_ascii(b"r3k2r/8/8/8/8/8/8/R3K2R b KQkq - 0 1")
+            .unwrap()
+            .into_position(CastlingMode::Standard)
+            .unwrap();
+        let start = Chess::default();
+        let m = |uci: &str, pos: &Chess| uci.parse::<Uci>().unwrap().to_move(pos).unwrap();
+        let entries = vec![
+            BookEntry {
+                key: book_key(&start),
+                mov: encode_move(&m("g1f3", &start)),
+                weight: 5,
+                learn: 0,
+            },
+            BookEntry {
+                key: book_key(&castling),
+                mov: encode_move(&m("e8c8", &castling)),
+                weight: 3,
+                learn: 0,
+            },
+            BookEntry {
+                key: book_key(&start),
+                mov: encode_move(&m("e2e4", &start)),
+                weight: 9,
+                learn: 0,
+            },
+        ];
+        assert_eq!(entries[1].mov, encode(Square::E8, Square::A8));
+        let mut book = Vec::new();
+        write_book(&mut book, entries).unwrap();
+        assert_eq!(book.len(), 3 * ENTRY_SIZE);
+
+        let moves: Vec<_> = book_moves(&book, &start)
+            .into_iter()
+            .map(|m| (m.uci, m.weight))
+            .collect();
+        assert_eq!(moves, [("e2e4".to_string(), 9), ("g1f3".to_string(), 5)]);
+        assert_eq!(book_moves(&book, &castling)[0].san, "O-O-O");
+    }
+
+    #[test]
+    fn decodes_promotions() {
+        let pos: Chess = Fen::from_ascii(b"8/P7/8/8/8/8/8/k1K5 w - - 0 1")
+            .unwrap()
+            .into_position(CastlingMode::Standard)
+            .unwrap();
+        let m = decode_move(&pos, encode(Square::A7, Square::A8) | (4 << 12)).unwrap();
+        assert_eq!(m.to_uci(CastlingMode::Standard).to_string(), "a7a8q");
+        assert!(decode_move(&pos, encode(Square::A7, Square::A8) | (5 << 12)).is_none());
+    }
+}
@@ -1,30 +1,61 @@
-use std::{fmt::Display, path::PathBuf, process::Stdio, sync::Arc, time::Instant};
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap, VecDeque},
+    fmt::Display,
+    hash::{Hash, Hasher},
+    path::PathBuf,
+    process::Stdio,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
 
 use derivative::Derivative;
 use governor::{Quota, RateLimiter};
-use log::{error, info};
+use log::{error, info, warn};
 use nonzero_ext::*;
 use serde::{Deserialize, Serialize};
 use shakmaty::{
-    fen::Fen, san::SanPlus, uci::Uci, ByColor, CastlingMode, Chess, Color, EnPassantMode, Position,
-    Role,
+    fen::{Epd, Fen, ParseFenError},
+    san::SanPlus,
+    uci::Uci,
+    ByColor, CastlingMode, Chess, Color, EnPassantMode, FromSetup, Move, Piece, Position,
+    PositionError, Role,
 };
 use specta::Type;
+use tauri::Manager;
 use tauri_specta::Event;
 use tokio::{
     io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines},
     process::{Child, ChildStdin, ChildStdout, Command},
-    sync::Mutex,
+    sync::{oneshot, Mutex, Notify},
 };
 use vampirc_uci::{
     parse_one,
     uci::{Score, ScoreValue},
-    UciInfoAttribute, UciMessage, UciOptionConfig,
+    UciMessage, UciOptionConfig,
 };
 
 use crate::{
-    db::{is_position_in_db, GameQuery, PositionQuery},
+    analysis::{
+        acpl, classify_moves, compare_engines, eval_graph, find_novelty, game_accuracy,
+        game_phases, gives_up_material, is_brilliant, is_only_move, last_book_ply, missed_wins,
+        move_accuracies, parse_time_control, phase_stats, time_moments, time_spent, turning_points,
+        win_chances, ClassificationThresholds, Eval, GameAnalysis, MoveAnalysis,
+        DEFAULT_ACPL_CEILING, DEFAULT_DISAGREEMENT, DEFAULT_FAST_MOVE_SECONDS, TURNING_POINTS,
+    },
+    analysis_cache::AnalysisCacheKey,
+    book::{book_moves, choose_book_move, open_book, BookOptions},
+    db::{cache_eval, count_positions, get_cached_eval, CachedEval},
     error::Error,
+    online::{cloud_eval, CloudEvalOptions},
+    opening::classify_setups,
+    tablebase::{
+        annotate_lines, probe_with, LineProbes, TablebaseAnnotation, TablebaseOptions,
+        TablebaseResult,
+    },
+    uci::{parse_info, pv_moves, EngineLine, LineSets},
     AppState,
 };
 
@@ -37,17 +68,31 @@ pub enum EngineLog {
 
 #[derive(Debug)]
 pub struct EngineProcess {
+    child: Child,
     stdin: ChildStdin,
-    last_depth: u32,
-    best_moves: Vec<BestMoves>,
-    last_best_moves: Vec<BestMoves>,
+    /// Lines of the search, whose last complete set `last_lines` converts
+    line_sets: LineSets,
+    /// Last complete set of lines converted, shared with the payloads emitted with it
+    last_best_moves: Arc<Vec<BestMoves>>,
     last_progress: f32,
     options: EngineOptions,
+    /// Position of `options`, kept so the lines of the engine don't parse it again
+    position: Chess,
     go_mode: GoMode,
     running: bool,
-    real_multipv: u16,
-    logs: Vec<EngineLog>,
+    /// Set by `stop`, so the owner of a session running several searches in a row, like
+    /// the analysis of a game, can tell it was stopped between two of them
+    stop_requested: bool,
+    /// Last `MAX_ENGINE_LOGS` lines sent and received
+    logs: VecDeque<EngineLog>,
     start: Instant,
+    pending_searches: u32,
+    move_sender: Option<oneshot::Sender<EngineMove>>,
+    latest_request: u64,
+    /// Tablebase result of the position searched, with `options.tablebase`
+    tablebase: Option<TablebaseResult>,
+    /// Positions of the lines of the search probed in the local tablebases so far
+    line_probes: LineProbes,
 }
 
 impl EngineProcess {
@@ -57,7 +102,9 @@ impl EngineProcess {
         command
             .stdin(Stdio::piped())
             .stdout(Stdio::piped())
-            .stderr(Stdio::piped());
+            .stderr(Stdio::piped())
+            // so that no path that drops a session leaves the engine searching
+            .kill_on_drop(true);
 
         #[cfg(target_os = "windows")]
         command.creation_flags(CREATE_NO_WINDOW);
@@ -68,8 +115,9 @@ impl EngineProcess {
 
         let mut stdin = child.stdin.take().ok_or(Error::NoStdin)?;
 
+        let stderr = child.stderr.take().unwrap();
         tokio::spawn(async move {
-            let mut stderr = BufReader::new(child.stderr.take().unwrap()).lines();
+            let mut stderr = BufReader::new(stderr).lines();
             while let Some(line) = stderr.next_line().await.unwrap() {
                 error!("{}", &line);
             }
@@ -79,69 +127,87 @@ impl EngineProcess {
 
         let _ = stdin.write_all("uci\n".as_bytes()).await;
         logs.push(EngineLog::Gui("uci\n".to_string()));
-        while let Some(line) = lines.next_line().await? {
-            logs.push(EngineLog::Engine(line.clone()));
-            if line == "uciok" {
-                let _ = stdin.write_all("isready\n".as_bytes()).await;
-                logs.push(EngineLog::Gui("isready\n".to_string()));
-                while let Some(line_is_ready) = lines.next_line().await? {
-                    logs.push(EngineLog::Engine(line_is_ready.clone()));
-                    if line_is_ready == "readyok" {
-                        break;
-                    }
-                }
+        let mut output = Vec::new();
+        let config = read_uci_config(&mut lines, &mut output).await?;
+        logs.extend(output.iter().cloned().map(EngineLog::Engine));
+        if config.is_none() {
+            return Err(exit_error(&mut child, &output).await.into());
+        }
+        let _ = stdin.write_all("isready\n".as_bytes()).await;
+        logs.push(EngineLog::Gui("isready\n".to_string()));
+        while let Some(line_is_ready) = lines.next_line().await? {
+            logs.push(EngineLog::Engine(line_is_ready.clone()));
+            if line_is_ready == "readyok" {
                 break;
             }
         }
 
         Ok((
             Self {
+                child,
                 stdin,
-                last_depth: 0,
-                best_moves: Vec::new(),
-                last_best_moves: Vec::new(),
+                line_sets: LineSets::default(),
+                last_best_moves: Arc::default(),
                 last_progress: 0.0,
-                logs,
+                logs: logs.into(),
                 options: EngineOptions::default(),
-                real_multipv: 0,
+                position: Chess::default(),
                 go_mode: GoMode::Infinite,
                 running: false,
+                stop_requested: false,
                 start: Instant::now(),
+                pending_searches: 0,
+                move_sender: None,
+                latest_request: 0,
+                tablebase: None,
+                line_probes: LineProbes::new(),
             },
             lines,
         ))
     }
 
+    /// Adds to the logs, dropping the oldest line when they're full
+    fn log(&mut self, entry: EngineLog) {
+        if self.logs.len() >= MAX_ENGINE_LOGS {
+            self.logs.pop_front();
+        }
+        self.logs.push_back(entry);
+    }
+
+    /// Adds a line of the engine to the logs. Once they're full the line dropped lends
+    /// it its buffer, so logging the output of a search doesn't allocate.
+    fn log_engine(&mut self, line: &str) {
+        let dropped = if self.logs.len() >= MAX_ENGINE_LOGS {
+            self.logs.pop_front()
+        } else {
+            None
+        };
+        let text = match dropped {
+            Some(EngineLog::Gui(mut text) | EngineLog::Engine(mut text)) => {
+                text.clear();
+                text.push_str(line);
+                text
+            }
+            None => line.to_string(),
+        };
+        self.logs.push_back(EngineLog::Engine(text));
+    }
+
     async fn set_option<T>(&mut self, name: &str, value: T) -> Result<(), Error>
     where
         T: Display,
     {
         let msg = format!("setoption name {} value {}\n", name, value);
         self.stdin.write_all(msg.as_bytes()).await?;
-        self.logs.push(EngineLog::Gui(msg));
+        self.log(EngineLog::Gui(msg));
 
         Ok(())
     }
 
     async fn set_options(&mut self, options: EngineOptions) -> Result<(), Error> {
         let fen: Fen = options.fen.parse()?;
-        let mut pos: Chess = match fen.into_position(CastlingMode::Chess960) {
-            Ok(p) => p,
-            Err(e) => e.ignore_too_much_material()?,
-        };
-        for m in &options.moves {
-            let uci = Uci::from_ascii(m.as_bytes())?;
-            let mv = uci.to_move(&pos)?;
-            pos.play_unchecked(&mv);
-        }
-        let multipv = options
-            .extra_options
-            .iter()
-            .find(|x| x.name == "MultiPV")
-            .map(|x| x.value.parse().unwrap_or(1))
-            .unwrap_or(1);
-
-        self.real_multipv = multipv.min(pos.legal_moves().len() as u16);
+        let pos = parse_position(&fen, &options.moves)?;
+        let multipv = real_multipv(&pos, &options.extra_options);
 
         for option in &options.extra_options {
             if !self.options.extra_options.contains(option) {
@@ -152,10 +218,11 @@ impl EngineProcess {
         if options.fen != self.options.fen || options.moves != self.options.moves {
             self.set_position(&options.fen, &options.moves).await?;
         }
-        self.last_depth = 0;
+        self.line_sets = LineSets::new(multipv);
         self.options = options.clone();
-        self.best_moves.clear();
-        self.last_best_moves.clear();
+        self.position = pos;
+        self.last_best_moves = Arc::default();
+        self.line_probes.clear();
         Ok(())
     }
 
@@ -169,86 +236,237 @@ impl EngineProcess {
         self.stdin.write_all(msg.as_bytes()).await?;
         self.options.fen = fen.to_string();
         self.options.moves = moves.clone();
-        self.logs.push(EngineLog::Gui(msg));
+        self.log(EngineLog::Gui(msg));
         Ok(())
     }
 
     async fn go(&mut self, mode: &GoMode) -> Result<(), Error> {
-        self.go_mode = mode.clone();
-        let msg = match mode {
-            GoMode::Depth(depth) => format!("go depth {}\n", depth),
-            GoMode::Time(time) => format!("go movetime {}\n", time),
-            GoMode::Nodes(nodes) => format!("go nodes {}\n", nodes),
-            GoMode::PlayersTime(PlayersTime {
-                white,
-                black,
-                winc,
-                binc,
-            }) => {
-                format!(
-                    "go wtime {} btime {} winc {} binc {}\n",
-                    white, black, winc, binc
-                )
+        if let GoMode::Clock(clock) = mode {
+            let fen: Fen = self.options.fen.parse()?;
+            let turn = parse_position(&fen, &self.options.moves)?.turn();
+            if clock.time_left(turn) == 0 {
+                return Err(Error::NoTimeLeft);
             }
-            GoMode::Infinite => "go infinite\n".to_string(),
-        };
+        }
+        self.go_mode = mode.clone();
+        let msg = mode.to_command();
         self.stdin.write_all(msg.as_bytes()).await?;
-        self.logs.push(EngineLog::Gui(msg));
+        self.log(EngineLog::Gui(msg));
         self.running = true;
+        self.pending_searches += 1;
         self.start = Instant::now();
         Ok(())
     }
 
+    /// The last complete set of lines. Their moves are converted to SAN the first time
+    /// they're asked for, so the sets that are never emitted aren't.
+    fn last_lines(&mut self) -> Arc<Vec<BestMoves>> {
+        if let Some(lines) = self.line_sets.take_complete() {
+            let max_length = self.options.max_pv_length;
+            self.last_best_moves = Arc::new(
+                lines
+                    .into_iter()
+                    .filter_map(|line| line.into_best_moves(&self.position, max_length))
+                    .collect(),
+            );
+        }
+        Arc::clone(&self.last_best_moves)
+    }
+
+    fn progress(&self, depth: u32, nodes: u32) -> f64 {
+        match self.go_mode {
+            GoMode::Depth(target) => (depth as f64 / target as f64) * 100.0,
+            GoMode::Time(time) => (self.start.elapsed().as_millis() as f64 / time as f64) * 100.0,
+            GoMode::Nodes(target) => (nodes as f64 / target as f64) * 100.0,
+            GoMode::PlayersTime(_) | GoMode::Clock(_) => 99.99,
+            GoMode::Infinite => 99.99,
+        }
+    }
+
+    /// Tablebase results of the position searched and of its `lines`, when the session
+    /// asks for them
+    fn tablebase_annotation(
+        &mut self,
+        state: &AppState,
+        lines: &[BestMoves],
+    ) -> Option<TablebaseAnnotation> {
+        let options = self.options.tablebase.as_ref()?;
+        Some(annotate_lines(
+            state,
+            &options.dirs,
+            &self.position,
+            self.tablebase.as_ref(),
+            lines,
+            &mut self.line_probes,
+        ))
+    }
+
+    /// Called for every `bestmove` the engine sends. Stopped searches also answer with a
+    /// `bestmove`, so the waiting `play_move` caller is only notified once every search
+    /// started so far has finished.
+    fn finish_search(&mut self, best_move: Option<String>) {
+        self.pending_searches = self.pending_searches.saturating_sub(1);
+        if self.pending_searches > 0 {
+            return;
+        }
+        if let Some(sender) = self.move_sender.take() {
+            let san = best_move.as_deref().and_then(|m| {
+                let uci: Uci = m.parse().ok()?;
+                let mv = uci.to_move(&self.position).ok()?;
+                Some(SanPlus::from_move(self.position.clone(), &mv).to_string())
+            });
+            let lines = self.last_lines();
+            let best_line = lines.first();
+            let _ = sender.send(EngineMove {
+                uci: best_move,
+                san,
+                score: best_line.map(|b| b.score.clone()),
+                depth: best_line.map(|b| b.depth).unwrap_or(0),
+                time_ms: self.start.elapsed().as_millis() as u32,
+                from_book: false,
+                book_exit: None,
+            });
+        }
+    }
+
     async fn stop(&mut self) -> Result<(), Error> {
         self.stdin.write_all(b"stop\n").await?;
-        self.logs.push(EngineLog::Gui("stop\n".to_string()));
+        self.log(EngineLog::Gui("stop\n".to_string()));
         self.running = false;
+        self.stop_requested = true;
         Ok(())
     }
 
     async fn kill(&mut self) -> Result<(), Error> {
         self.stdin.write_all(b"quit\n").await?;
-        self.logs.push(EngineLog::Gui("quit\n".to_string()));
+        self.log(EngineLog::Gui("quit\n".to_string()));
         self.running = false;
         Ok(())
     }
+
+    /// Tells the engine that the next searches are of another game
+    async fn new_game(&mut self) -> Result<(), Error> {
+        self.stdin.write_all(b"ucinewgame\n").await?;
+        self.log(EngineLog::Gui("ucinewgame\n".to_string()));
+        // The position has to be sent again after it
+        self.options.fen.clear();
+        self.stop_requested = false;
+        Ok(())
+    }
+}
+
+/// An engine process whose output is read by its owner, which keeps it between the
+/// games it analyzes
+pub struct EngineSession {
+    process: Arc<Mutex<EngineProcess>>,
+    reader: Lines<BufReader<ChildStdout>>,
+    /// Options of the game it was started for, which the next ones must have
+    options: Vec<EngineOption>,
+}
+
+/// Engine sessions by the path of their engine
+pub type EngineSessions = HashMap<String, EngineSession>;
+
+impl EngineSession {
+    async fn start(engine: &str, options: &[EngineOption]) -> Result<EngineSession, Error> {
+        let (process, reader) = EngineProcess::new(PathBuf::from(engine)).await?;
+        Ok(EngineSession {
+            process: Arc::new(Mutex::new(process)),
+            reader,
+            options: options.to_vec(),
+        })
+    }
+
+    async fn quit(self) -> Result<(), Error> {
+        self.process.lock().await.kill().await
+    }
+}
+
+/// Quits the engines of `sessions`, which are only logged when they fail to
+pub async fn quit_sessions(sessions: EngineSessions) {
+    for (engine, session) in sessions {
+        if let Err(e) = session.quit().await {
+            error!("Failed to quit {}: {}", engine, e);
+        }
+    }
+}
+
+/// The session of `engine` in `sessions` for a new game with `options`. An engine kept
+/// with other options is started again, since the options the new game leaves out, like
+/// `UCI_Chess960`, would otherwise keep the values of the last one.
+async fn take_session(
+    sessions: &mut EngineSessions,
+    engine: &str,
+    options: &[EngineOption],
+) -> Result<EngineSession, Error> {
+    if let Some(session) = sessions.remove(engine) {
+        if session.options == options {
+            session.process.lock().await.new_game().await?;
+            return Ok(session);
+        }
+        if let Err(e) = session.quit().await {
+            error!("Failed to quit {}: {}", engine, e);
+        }
+    }
+    EngineSession::start(engine, options).await
 }
 
 #[cfg(target_os = "windows")]
 const CREATE_NO_WINDOW: u32 = 0x08000000;
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
-pub struct AnalysisCacheKey {
-    pub tab: String,
-    pub fen: String,
-    pub engine: String,
-    pub multipv: u16,
-}
+/// Lines kept in the logs of an engine, which a fast one sends in a fraction of a second
+const MAX_ENGINE_LOGS: usize = 10_000;
+
+/// Payloads waiting to be emitted at most. When the queue is full the oldest set of
+/// lines is dropped for the new one, the final results of searches never being dropped.
+const PAYLOAD_QUEUE_SIZE: usize = 8;
 
-#[derive(Clone, Serialize, Debug, Derivative, Type)]
+#[derive(Clone, Serialize, Deserialize, Debug, Derivative, Type)]
 #[derivative(Default)]
 pub struct BestMoves {
-    nodes: u32,
-    depth: u32,
-    score: Score,
+    pub nodes: u32,
+    pub depth: u32,
+    pub score: Score,
     #[serde(rename = "uciMoves")]
-    uci_moves: Vec<String>,
+    pub uci_moves: Vec<String>,
     #[serde(rename = "sanMoves")]
-    san_moves: Vec<String>,
+    pub san_moves: Vec<String>,
     #[derivative(Default(value = "1"))]
-    multipv: u16,
-    nps: u32,
+    pub multipv: u16,
+    pub nps: u32,
+    /// Moves the engine gave for the line when it was cut to the `max_pv_length` of the
+    /// analysis, None when it's whole
+    #[serde(rename = "truncatedFrom", default)]
+    pub truncated_from: Option<u32>,
+}
+
+/// Where the lines of a `BestMovesPayload` come from
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, Type)]
+#[serde(rename_all = "camelCase")]
+pub enum LinesSource {
+    Engine,
+    /// The evaluation cache
+    Cache,
+    /// The lichess cloud evaluation, shown until the engine gets deeper
+    Cloud,
+    /// The deepest lines emitted when the position was analyzed recently
+    Recent,
 }
 
 #[derive(Serialize, Debug, Clone, Type, Event)]
 #[serde(rename_all = "camelCase")]
 pub struct BestMovesPayload {
-    pub best_lines: Vec<BestMoves>,
+    pub best_lines: Arc<Vec<BestMoves>>,
     pub engine: String,
     pub tab: String,
     pub fen: String,
     pub moves: Vec<String>,
     pub progress: f64,
+    /// The lines come from the evaluation cache
+    pub cached: bool,
+    pub source: LinesSource,
+    /// Tablebase results of the position and its lines, when the session asks for them
+    pub tablebase: Option<TablebaseAnnotation>,
 }
 
 fn invert_score(score: Score) -> Score {
@@ -264,72 +482,156 @@ fn invert_score(score: Score) -> Score {
     }
 }
 
-fn parse_uci_attrs(
-    attrs: Vec<UciInfoAttribute>,
-    fen: &Fen,
-    moves: &Vec<String>,
-) -> Result<BestMoves, Error> {
-    let mut best_moves = BestMoves::default();
+/// Checks that the FEN describes a position the engine can be given, so errors can be
+/// shown next to the FEN input instead of confusing the engine.
+pub fn validate_fen(fen: &str) -> Result<Fen, Error> {
+    let invalid = |reason: String| Error::InvalidFen { reason };
+    let fen: Fen = fen
+        .parse()
+        .map_err(|e: ParseFenError| invalid(e.to_string()))?;
+    let pos: Chess = fen
+        .clone()
+        .into_position(CastlingMode::Chess960)
+        .or_else(PositionError::ignore_too_much_material)
+        .map_err(|e| invalid(e.to_string()))?;
+    for color in Color::ALL {
+        let pawns = pos.board().by_piece(Piece {
+            color,
+            role: Role::Pawn,
+        });
+        if pawns.count() > 8 {
+            let side = if color.is_white() { "White" } else { "Black" };
+            return Err(invalid(format!("{side} has more than 8 pawns")));
+        }
+    }
+    Ok(fen)
+}
 
+pub fn parse_position(fen: &Fen, moves: &[String]) -> Result<Chess, Error> {
     let mut pos: Chess = match fen.clone().into_position(CastlingMode::Chess960) {
         Ok(p) => p,
         Err(e) => e.ignore_too_much_material()?,
     };
     for m in moves {
         let uci = Uci::from_ascii(m.as_bytes())?;
+        if uci == Uci::Null {
+            pos = pos.swap_turn()?;
+            continue;
+        }
         let mv = uci.to_move(&pos)?;
         pos.play_unchecked(&mv);
     }
-    let turn = pos.turn();
-
-    for a in attrs {
-        match a {
-            UciInfoAttribute::Pv(m) => {
-                for mv in m {
-                    let uci: Uci = mv.to_string().parse()?;
-                    let m = uci.to_move(&pos)?;
-                    let san = SanPlus::from_move_and_play_unchecked(&mut pos, &m);
-                    best_moves.san_moves.push(san.to_string());
-                    best_moves.uci_moves.push(uci.to_string());
-                }
-            }
-            UciInfoAttribute::Nps(nps) => {
-                best_moves.nps = nps as u32;
-            }
-            UciInfoAttribute::Nodes(nodes) => {
-                best_moves.nodes = nodes as u32;
-            }
-            UciInfoAttribute::Depth(depth) => {
-                best_moves.depth = depth;
-            }
-            UciInfoAttribute::MultiPv(multipv) => {
-                best_moves.multipv = multipv;
-            }
-            UciInfoAttribute::Score(score) => {
-                best_moves.score = score;
-            }
-            _ => (),
-        }
-    }
+    Ok(pos)
+}
 
-    if best_moves.san_moves.is_empty() {
-        return Err(Error::NoMovesFound);
-    }
+/// Moves converted to the other notation, with the position they lead to
+#[derive(Serialize, Debug, Clone, PartialEq, Eq, Type)]
+pub struct ConvertedMoves {
+    pub moves: Vec<String>,
+    pub fen: String,
+}
 
-    if turn == Color::Black {
-        best_moves.score = invert_score(best_moves.score);
+/// Plays `moves` from `fen`, reading each one with `read` and writing it with `write`.
+/// Fails at the first move that can't be read or played instead of converting a prefix.
+/// Castling is written the Chess960 way only when the position isn't standard chess.
+fn convert_moves(
+    fen: &str,
+    moves: &[String],
+    read: impl Fn(&str, &Chess) -> Result<Move, String>,
+    write: impl Fn(&Move, &mut Chess, CastlingMode) -> String,
+) -> Result<ConvertedMoves, Error> {
+    let setup = validate_fen(fen)?.into_setup();
+    let (mut pos, mode) = match Chess::from_setup(setup.clone(), CastlingMode::Standard)
+        .or_else(PositionError::ignore_too_much_material)
+    {
+        Ok(pos) => (pos, CastlingMode::Standard),
+        Err(_) => (
+            Chess::from_setup(setup, CastlingMode::Chess960)
+                .or_else(PositionError::ignore_too_much_material)?,
+            CastlingMode::Chess960,
+        ),
+    };
+    let mut converted = Vec::with_capacity(moves.len());
+    for (index, m) in moves.iter().enumerate() {
+        let mv = read(m, &pos).map_err(|reason| Error::IllegalMove {
+            index,
+            mov: m.clone(),
+            reason,
+        })?;
+        converted.push(write(&mv, &mut pos, mode));
     }
+    Ok(ConvertedMoves {
+        moves: converted,
+        fen: Fen::from_position(pos, EnPassantMode::Legal).to_string(),
+    })
+}
+
+#[tauri::command]
+#[specta::specta]
+pub fn san_to_uci(fen: String, moves: Vec<String>) -> Result<ConvertedMoves, Error> {
+    convert_moves(
+        &fen,
+        &moves,
+        |m, pos| {
+            let san = SanPlus::from_ascii(m.as_bytes()).map_err(|e| e.to_string())?;
+            san.san.to_move(pos).map_err(|e| e.to_string())
+        },
+        |mv, pos, mode| {
+            let uci = mv.to_uci(mode).to_string();
+            pos.play_unchecked(mv);
+            uci
+        },
+    )
+}
 
-    Ok(best_moves)
+#[tauri::command]
+#[specta::specta]
+pub fn uci_to_san(fen: String, moves: Vec<String>) -> Result<ConvertedMoves, Error> {
+    convert_moves(
+        &fen,
+        &moves,
+        |m, pos| {
+            let uci = Uci::from_ascii(m.as_bytes()).map_err(|e| e.to_string())?;
+            uci.to_move(pos).map_err(|e| e.to_string())
+        },
+        |mv, pos, _| SanPlus::from_move_and_play_unchecked(pos, mv).to_string(),
+    )
+}
+
+impl EngineLine {
+    /// The line searched from `pos`, scored from White's point of view and cut to
+    /// `max_length` moves. None when one of the moves kept isn't legal.
+    fn into_best_moves(self, pos: &Chess, max_length: Option<u32>) -> Option<BestMoves> {
+        let max_length = max_length.map_or(usize::MAX, |max| max as usize);
+        let (uci_moves, san_moves) = pv_moves(pos, &self.pv, max_length)?;
+        let truncated_from = (uci_moves.len() == max_length)
+            .then(|| self.pv.split_ascii_whitespace().count() as u32)
+            .filter(|&length| length as usize > max_length);
+        Some(BestMoves {
+            nodes: self.nodes,
+            depth: self.depth,
+            score: if pos.turn() == Color::Black {
+                invert_score(self.score)
+            } else {
+                self.score
+            },
+            uci_moves,
+            san_moves,
+            multipv: self.multipv,
+            nps: self.nps,
+            truncated_from,
+        })
+    }
 }
 
-fn start_engine(path: PathBuf) -> Result<Child, Error> {
+pub fn start_engine(path: PathBuf) -> Result<Child, Error> {
     let mut command = Command::new(&path);
     command.current_dir(path.parent().unwrap());
     command
         .stdin(Stdio::piped())
         .stdout(Stdio::piped())
-        .stderr(Stdio::piped());
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
 
     #[cfg(target_os = "windows")]
     command.creation_flags(CREATE_NO_WINDOW);
@@ -346,13 +648,6 @@ fn get_handles(child: &mut Child) -> Result<(ChildStdin, Lines<BufReader<ChildSt
     Ok((stdin, stdout))
 }
 
-async fn send_command(stdin: &mut ChildStdin, command: impl AsRef<str>) {
-    stdin
-        .write_all(command.as_ref().as_bytes())
-        .await
-        .expect("Failed to write command");
-}
-
 #[derive(Deserialize, Debug, Clone, Type, Derivative, Eq, PartialEq)]
 #[serde(rename_all = "camelCase")]
 #[derivative(Default)]
@@ -360,25 +655,44 @@ pub struct EngineOptions {
     pub fen: String,
     pub moves: Vec<String>,
     pub extra_options: Vec<EngineOption>,
+    /// Annotates the lines with the results of the tablebases, the online ones adding
+    /// their latency to the start of each search
+    #[serde(default)]
+    pub tablebase: Option<TablebaseOptions>,
+    /// Shows the lichess cloud evaluation of the position until the engine gets deeper
+    #[serde(default)]
+    pub cloud_eval: Option<CloudEvalOptions>,
+    /// Moves kept of each line, the others not even being converted to SAN. None keeps
+    /// whole lines.
+    #[serde(default = "default_max_pv_length")]
+    #[derivative(Default(value = "default_max_pv_length()"))]
+    pub max_pv_length: Option<u32>,
+}
+
+/// Many more moves than the board shows, which long lines at high depths would add to
+/// every payload
+fn default_max_pv_length() -> Option<u32> {
+    Some(30)
 }
 
-#[derive(Deserialize, Debug, Clone, Type, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Debug, Clone, Type, PartialEq, Eq, Hash)]
 pub struct EngineOption {
     name: String,
     value: String,
 }
 
-#[derive(Deserialize, Debug, Clone, Type, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Debug, Clone, Type, PartialEq, Eq)]
 #[serde(tag = "t", content = "c")]
 pub enum GoMode {
     PlayersTime(PlayersTime),
+    Clock(Clock),
     Depth(u32),
     Time(u32),
     Nodes(u32),
     Infinite,
 }
 
-#[derive(Deserialize, Debug, Clone, Type, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Debug, Clone, Type, PartialEq, Eq)]
 pub struct PlayersTime {
     white: u32,
     black: u32,
@@ -386,6 +700,70 @@ pub struct PlayersTime {
     binc: u32,
 }
 
+/// Remaining time and increments in milliseconds, as sent with `go wtime ... btime ...`
+#[derive(Serialize, Deserialize, Debug, Clone, Type, PartialEq, Eq)]
+pub struct Clock {
+    pub wtime: u32,
+    pub btime: u32,
+    pub winc: u32,
+    pub binc: u32,
+    pub movestogo: Option<u32>,
+}
+
+impl Clock {
+    fn time_left(&self, color: Color) -> u32 {
+        match color {
+            Color::White => self.wtime,
+            Color::Black => self.btime,
+        }
+    }
+}
+
+impl GoMode {
+    /// Whether searching with `self` goes further than a finished search with `previous`
+    fn extends(&self, previous: &GoMode) -> bool {
+        match (self, previous) {
+            (GoMode::Depth(new), GoMode::Depth(old)) => new > old,
+            (GoMode::Time(new), GoMode::Time(old)) => new > old,
+            (GoMode::Nodes(new), GoMode::Nodes(old)) => new > old,
+            (GoMode::Infinite, GoMode::Infinite) => false,
+            (GoMode::Infinite, _) => true,
+            _ => false,
+        }
+    }
+
+    fn to_command(&self) -> String {
+        match self {
+            GoMode::Depth(depth) => format!("go depth {}\n", depth),
+            GoMode::Time(time) => format!("go movetime {}\n", time),
+            GoMode::Nodes(nodes) => format!("go nodes {}\n", nodes),
+            GoMode::PlayersTime(PlayersTime {
+                white,
+                black,
+                winc,
+                binc,
+            }) => {
+                format!(
+                    "go wtime {} btime {} winc {} binc {}\n",
+                    white, black, winc, binc
+                )
+            }
+            GoMode::Clock(clock) => {
+                let mut msg = format!(
+                    "go wtime {} btime {} winc {} binc {}",
+                    clock.wtime, clock.btime, clock.winc, clock.binc
+                );
+                if let Some(movestogo) = clock.movestogo {
+                    msg.push_str(&format!(" movestogo {}", movestogo));
+                }
+                msg.push('\n');
+                msg
+            }
+            GoMode::Infinite => "go infinite\n".to_string(),
+        }
+    }
+}
+
 #[tauri::command]
 #[specta::specta]
 pub async fn kill_engines(tab: String, state: tauri::State<'_, AppState>) -> Result<(), Error> {
@@ -407,6 +785,117 @@ pub async fn kill_engines(tab: String, state: tauri::State<'_, AppState>) -> Res
     Ok(())
 }
 
+#[derive(Serialize, Debug, Default, Type)]
+pub struct StopSummary {
+    pub stopped: u32,
+    /// Engines that didn't acknowledge the stop in time
+    pub killed: u32,
+}
+
+/// Stops every engine session, optionally quitting the engines too, which removes their
+/// sessions.
+pub async fn stop_engines(state: &AppState, quit: bool) -> StopSummary {
+    let sessions: Vec<_> = state
+        .engine_processes
+        .iter()
+        .map(|x| (x.key().clone(), x.value().clone()))
+        .collect();
+
+    let mut summary = StopSummary::default();
+    for (_, process) in &sessions {
+        let mut process = process.lock().await;
+        if process.running {
+            summary.stopped += 1;
+        }
+        // a failed write means the engine is already gone
+        let _ = process.stop().await;
+        if quit {
+            let _ = process.kill().await;
+        }
+    }
+
+    let deadline = Instant::now() + std::time::Duration::from_millis(500);
+    for (key, process) in &sessions {
+        loop {
+            let mut process = process.lock().await;
+            let done = if quit {
+                matches!(process.child.try_wait(), Ok(Some(_)))
+            } else {
+                process.pending_searches == 0
+            };
+            if done {
+                if quit {
+                    state.engine_processes.remove(key);
+                }
+                break;
+            }
+            if Instant::now() >= deadline {
+                let _ = process.child.start_kill();
+                state.engine_processes.remove(key);
+                summary.killed += 1;
+                break;
+            }
+            drop(process);
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+    }
+    summary
+}
+
+#[tauri::command]
+#[specta::specta]
+pub async fn stop_all_engines(
+    quit: bool,
+    state: tauri::State<'_, AppState>,
+) -> Result<StopSummary, Error> {
+    Ok(stop_engines(&state, quit).await)
+}
+
+/// Quits the sessions of the engine at `engine`, unless it's searching in one of them.
+/// Returns the number of sessions quit.
+pub async fn quit_idle_sessions(state: &AppState, engine: &str) -> Result<usize, Error> {
+    let sessions: Vec<_> = state
+        .engine_processes
+        .iter()
+        .filter(|x| x.key().1 == engine)
+        .map(|x| (x.key().clone(), x.value().clone()))
+        .collect();
+
+    let mut searching = 0;
+    for (_, process) in &sessions {
+        if process.lock().await.running {
+            searching += 1;
+        }
+    }
+    if searching > 0 {
+        return Err(Error::EngineInUse { tabs: searching });
+    }
+    for (key, process) in &sessions {
+        // a failed write means the engine is already gone
+        let _ = process.lock().await.kill().await;
+        state.engine_processes.remove(key);
+    }
+    Ok(sessions.len())
+}
+
+/// Makes every session send the option `name` again with its next search, for the
+/// engines to reload the files it points to
+pub async fn resend_option(state: &AppState, name: &str) {
+    let sessions: Vec<_> = state
+        .engine_processes
+        .iter()
+        .map(|x| x.value().clone())
+        .collect();
+    for process in sessions {
+        process
+            .lock()
+            .await
+            .options
+            .extra_options
+            .retain(|option| option.name != name);
+    }
+}
+
 #[tauri::command]
 #[specta::specta]
 pub async fn kill_engine(
@@ -414,8 +903,13 @@ pub async fn kill_engine(
     tab: String,
     state: tauri::State<'_, AppState>,
 ) -> Result<(), Error> {
+    kill_session(&state, tab, engine).await
+}
+
+/// Quits the engine session of the tab, whose output task then removes it
+pub async fn kill_session(state: &AppState, tab: String, engine: String) -> Result<(), Error> {
     let key = (tab, engine);
-    if let Some(process) = state.engine_processes.get(&key) {
+    if let Some(process) = state.engine_processes.get(&key).map(|p| p.clone()) {
         let mut process = process.lock().await;
         process.kill().await?;
     }
@@ -436,6 +930,41 @@ pub async fn stop_engine(
     Ok(())
 }
 
+/// Continues a session's analysis with a larger limit. The engine keeps its hash and
+/// lines are only emitted once they go past the depth already reached.
+#[tauri::command]
+#[specta::specta]
+pub async fn extend_analysis(
+    engine: String,
+    tab: String,
+    go_mode: GoMode,
+    state: tauri::State<'_, AppState>,
+) -> Result<(), Error> {
+    let key = (tab, engine);
+    let process = state
+        .engine_processes
+        .get(&key)
+        .map(|p| p.clone())
+        .ok_or(Error::NoEngineSession)?;
+    let mut process = process.lock().await;
+    if !go_mode.extends(&process.go_mode) {
+        return Err(Error::InvalidExtension);
+    }
+    if process.last_progress < 100.0 {
+        process.stop().await?;
+    }
+    process.go(&go_mode).await?;
+    Ok(())
+}
+
+/// Sets how long `get_best_moves` waits before redirecting a running engine to a new
+/// position, so that quickly stepping through moves doesn't restart the search every time.
+#[tauri::command]
+#[specta::specta]
+pub fn set_analysis_debounce(ms: u32, state: tauri::State<'_, AppState>) {
+    state.analysis_debounce.store(ms as u64, Ordering::Relaxed);
+}
+
 #[tauri::command]
 #[specta::specta]
 pub async fn get_engine_logs(
@@ -446,7 +975,7 @@ pub async fn get_engine_logs(
     let key = (tab, engine);
     if let Some(process) = state.engine_processes.get(&key) {
         let process = process.lock().await;
-        Ok(process.logs.clone())
+        Ok(process.logs.iter().cloned().collect())
     } else {
         Ok(Vec::new())
     }
@@ -463,128 +992,930 @@ pub async fn get_best_moves(
     app: tauri::AppHandle,
     state: tauri::State<'_, AppState>,
 ) -> Result<Option<(f32, Vec<BestMoves>)>, Error> {
+    let fen = validate_fen(&options.fen)?;
+    let pos = parse_position(&fen, &options.moves)?;
     let path = PathBuf::from(&engine);
 
     let key = (tab.clone(), engine.clone());
 
-    if state.engine_processes.contains_key(&key) {
+    let cached = match cached_lines(&app, &engine, &pos) {
+        Some(cached) if cached.satisfies(&go_mode, real_multipv(&pos, &options.extra_options)) => {
+            if let Some(process) = state.engine_processes.get(&key).map(|p| p.clone()) {
+                process.lock().await.stop().await?;
+            }
+            return Ok(Some((100.0, cached.lines)));
+        }
+        cached => cached,
+    };
+    let recent = state
+        .analysis_cache
+        .lock()
+        .unwrap()
+        .get(&analysis_cache_key(&pos, &engine, &options));
+    // The deepest of them is shown until the engine gets past it
+    let shown = match (cached, recent) {
+        (Some(cached), recent)
+            if recent
+                .as_ref()
+                .and_then(|lines| lines.first())
+                .map_or(true, |line| cached.depth >= line.depth) =>
         {
-            let process = state.engine_processes.get_mut(&key).unwrap();
+            Some((Arc::new(cached.lines), LinesSource::Cache))
+        }
+        (_, Some(recent)) => Some((recent, LinesSource::Recent)),
+        _ => None,
+    };
+    if let Some((best_lines, source)) = shown {
+        BestMovesPayload {
+            best_lines,
+            engine: id.clone(),
+            tab: tab.clone(),
+            fen: options.fen.clone(),
+            moves: options.moves.clone(),
+            progress: 0.0,
+            cached: true,
+            source,
+            tablebase: None,
+        }
+        .emit_all(&app)?;
+    }
+    spawn_cloud_eval(&id, &tab, &engine, &pos, &options, &app);
+
+    if let Some(process) = state.engine_processes.get(&key).map(|p| p.clone()) {
+        let request = {
             let mut process = process.lock().await;
             if options == process.options && go_mode == process.go_mode && process.running {
-                return Ok(Some((
-                    process.last_progress,
-                    process.last_best_moves.clone(),
-                )));
+                return Ok(Some((process.last_progress, process.last_lines().to_vec())));
             }
             process.stop().await?;
-        }
-        // give time for engine to stop and process previous lines
-        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+            process.latest_request += 1;
+            process.latest_request
+        };
+        // give time for engine to stop and let rapid position changes settle,
+        // only the latest request redirects the engine
+        let debounce = state.analysis_debounce.load(Ordering::Relaxed);
+        tokio::time::sleep(std::time::Duration::from_millis(debounce)).await;
+        let tablebase = root_tablebase(&state, &pos, &options).await;
         {
-            let process = state.engine_processes.get_mut(&key).unwrap();
             let mut process = process.lock().await;
-            process.set_options(options.clone()).await?;
-            process.go(&go_mode).await?;
+            if process.latest_request == request {
+                process.set_options(options.clone()).await?;
+                process.tablebase = tablebase;
+                process.go(&go_mode).await?;
+            }
         }
         return Ok(None);
     }
 
-    let (mut process, mut reader) = EngineProcess::new(path).await?;
+    let (mut process, reader) = EngineProcess::new(path).await?;
     process.set_options(options.clone()).await?;
+    process.tablebase = root_tablebase(&state, &pos, &options).await;
     process.go(&go_mode).await?;
 
     let process = Arc::new(Mutex::new(process));
 
-    state.engine_processes.insert(key.clone(), process.clone());
-
-    let lim = RateLimiter::direct(Quota::per_second(nonzero!(5u32)));
+    state.engine_processes.insert(key, process.clone());
 
-    while let Some(line) = reader.next_line().await? {
-        let mut proc = process.lock().await;
-        match parse_one(&line) {
-            UciMessage::Info(attrs) => {
-                if let Ok(best_moves) =
-                    parse_uci_attrs(attrs, &proc.options.fen.parse()?, &proc.options.moves)
-                {
-                    let multipv = best_moves.multipv;
-                    let cur_depth = best_moves.depth;
-                    let cur_nodes = best_moves.nodes;
-                    if multipv as usize == proc.best_moves.len() + 1 {
-                        proc.best_moves.push(best_moves);
-                        if multipv == proc.real_multipv {
-                            if proc.best_moves.iter().all(|x| x.depth == cur_depth)
-                                && cur_depth >= proc.last_depth
-                                && lim.check().is_ok()
-                            {
-                                let progress = match proc.go_mode {
-                                    GoMode::Depth(depth) => {
-                                        (cur_depth as f64 / depth as f64) * 100.0
-                                    }
-                                    GoMode::Time(time) => {
-                                        (proc.start.elapsed().as_millis() as f64 / time as f64)
-                                            * 100.0
-                                    }
-                                    GoMode::Nodes(nodes) => {
-                                        (cur_nodes as f64 / nodes as f64) * 100.0
-                                    }
-                                    GoMode::PlayersTime(_) => 99.99,
-                                    GoMode::Infinite => 99.99,
-                                };
-                                BestMovesPayload {
-                                    best_lines: proc.best_moves.clone(),
-                                    engine: id.clone(),
-                                    tab: tab.clone(),
-                                    fen: proc.options.fen.clone(),
-                                    moves: proc.options.moves.clone(),
-                                    progress,
-                                }
-                                .emit_all(&app)?;
-                                proc.last_depth = cur_depth;
-                                proc.last_best_moves = proc.best_moves.clone();
-                                proc.last_progress = progress as f32;
-                            }
-                            proc.best_moves.clear();
-                        }
-                    }
-                }
-            }
-            UciMessage::BestMove { .. } => {
-                BestMovesPayload {
-                    best_lines: proc.last_best_moves.clone(),
-                    engine: id.clone(),
-                    tab: tab.clone(),
-                    fen: proc.options.fen.clone(),
-                    moves: proc.options.moves.clone(),
-                    progress: 100.0,
-                }
-                .emit_all(&app)?;
-                proc.last_progress = 100.0;
-            }
-            _ => {}
-        }
-        proc.logs.push(EngineLog::Engine(line));
-    }
-    info!("Engine process finished: tab: {}, engine: {}", tab, engine);
-    state.engine_processes.remove(&key);
+    process_engine_output(id, tab, engine, process, reader, app).await?;
     Ok(None)
 }
 
-#[derive(Serialize, Debug, Default, Type)]
-pub struct MoveAnalysis {
-    best: Vec<BestMoves>,
-    novelty: bool,
-    is_sacrifice: bool,
-}
-
-#[derive(Deserialize, Debug, Default, Type)]
+/// Emits the lichess cloud evaluation of the position of `options` in the background,
+/// when they ask for one and it is deep enough, unless the engine already got as deep
+/// in the same position
+fn spawn_cloud_eval(
+    id: &str,
+    tab: &str,
+    engine: &str,
+    pos: &Chess,
+    options: &EngineOptions,
+    app: &tauri::AppHandle,
+) {
+    let Some(cloud) = options.cloud_eval.clone() else {
+        return;
+    };
+    let (id, key, pos, options, app) = (
+        id.to_string(),
+        (tab.to_string(), engine.to_string()),
+        pos.clone(),
+        options.clone(),
+        app.clone(),
+    );
+    tauri::async_runtime::spawn(async move {
+        let state = app.state::<AppState>();
+        let multipv = real_multipv(&pos, &options.extra_options);
+        let Some(lines) = cloud_eval(&state, &pos, multipv).await else {
+            return;
+        };
+        let depth = lines.first().map_or(0, |line| line.depth);
+        if depth < cloud.min_depth {
+            return;
+        }
+        if let Some(process) = state.engine_processes.get(&key).map(|p| p.clone()) {
+            let mut process = process.lock().await;
+            let deeper = process
+                .last_lines()
+                .first()
+                .is_some_and(|line| line.depth >= depth);
+            if process.options == options && deeper {
+                return;
+            }
+        }
+        let _ = BestMovesPayload {
+            best_lines: Arc::new(lines),
+            engine: id,
+            tab: key.0,
+            fen: options.fen.clone(),
+            moves: options.moves.clone(),
+            progress: 0.0,
+            cached: false,
+            source: LinesSource::Cloud,
+            tablebase: None,
+        }
+        .emit_all(&app);
+    });
+}
+
+/// Tablebase result of the position of `options`, when they ask for one and it has one
+async fn root_tablebase(
+    state: &AppState,
+    pos: &Chess,
+    options: &EngineOptions,
+) -> Option<TablebaseResult> {
+    probe_with(state, pos, options.tablebase.as_ref()?)
+        .await
+        .ok()
+}
+
+/// Payloads waiting for the emitter, with whether they're the final result of a search
+#[derive(Default)]
+struct PayloadQueue {
+    payloads: std::sync::Mutex<VecDeque<(BestMovesPayload, bool)>>,
+    ready: Notify,
+    closed: AtomicBool,
+}
+
+impl PayloadQueue {
+    /// Queues `payload`, dropping the oldest set of lines that isn't final when the queue
+    /// has `PAYLOAD_QUEUE_SIZE` payloads
+    fn push(&self, payload: BestMovesPayload, last: bool) {
+        let mut payloads = self.payloads.lock().unwrap();
+        if payloads.len() >= PAYLOAD_QUEUE_SIZE {
+            if let Some(oldest) = payloads.iter().position(|(_, last)| !last) {
+                payloads.remove(oldest);
+            }
+        }
+        payloads.push_back((payload, last));
+        drop(payloads);
+        self.ready.notify_one();
+    }
+
+    fn pop(&self) -> Option<BestMovesPayload> {
+        let mut payloads = self.payloads.lock().unwrap();
+        payloads.pop_front().map(|(payload, _)| payload)
+    }
+}
+
+/// Sends payloads to the emitter task, which ends once it's dropped and all of them
+/// were emitted
+struct PayloadSender(Arc<PayloadQueue>);
+
+impl PayloadSender {
+    fn send(&self, payload: BestMovesPayload, last: bool) {
+        self.0.push(payload, last);
+    }
+}
+
+impl Drop for PayloadSender {
+    fn drop(&mut self) {
+        self.0.closed.store(true, Ordering::Release);
+        self.0.ready.notify_one();
+    }
+}
+
+/// Emits the payloads sent to it in another task, so the engine's output is read on
+/// while they are serialized
+fn spawn_emitter(app: &tauri::AppHandle) -> PayloadSender {
+    let app = app.clone();
+    spawn_payload_task(move |payload| {
+        if let Err(e) = payload.emit_all(&app) {
+            error!("Couldn't emit the best moves: {e}");
+        }
+    })
+}
+
+/// Hands the payloads sent to it to `emit` in another task
+fn spawn_payload_task(mut emit: impl FnMut(BestMovesPayload) + Send + 'static) -> PayloadSender {
+    let queue = Arc::new(PayloadQueue::default());
+    let emitted = queue.clone();
+    tauri::async_runtime::spawn(async move {
+        loop {
+            // Read first, so the payloads sent before the sender was dropped are emitted
+            let closed = emitted.closed.load(Ordering::Acquire);
+            while let Some(payload) = emitted.pop() {
+                emit(payload);
+            }
+            if closed {
+                break;
+            }
+            emitted.ready.notified().await;
+        }
+    });
+    PayloadSender(queue)
+}
+
+/// Reads the next line of `reader` into `buffer`, giving it without its line ending.
+/// None once the engine exits. Engines send thousands of lines a second, which reading
+/// them into the same buffer keeps from allocating.
+async fn read_engine_line<'a>(
+    reader: &mut BufReader<ChildStdout>,
+    buffer: &'a mut String,
+) -> std::io::Result<Option<&'a str>> {
+    buffer.clear();
+    if reader.read_line(buffer).await? == 0 {
+        return Ok(None);
+    }
+    Ok(Some(buffer.trim_end_matches(&['\r', '\n'][..])))
+}
+
+/// Reads the engine's stdout until the process exits, emitting `BestMovesPayload`s
+/// and answering pending `play_move` requests. Removes the session when done.
+async fn process_engine_output(
+    id: String,
+    tab: String,
+    engine: String,
+    process: Arc<Mutex<EngineProcess>>,
+    reader: Lines<BufReader<ChildStdout>>,
+    app: tauri::AppHandle,
+) -> Result<(), Error> {
+    let lim = RateLimiter::direct(Quota::per_second(nonzero!(5u32)));
+    let payloads = spawn_emitter(&app);
+
+    let mut reader = reader.into_inner();
+    let mut buffer = String::new();
+    while let Some(line) = read_engine_line(&mut reader, &mut buffer).await? {
+        let mut proc = process.lock().await;
+        if let Some(info) = parse_info(line) {
+            if let Some(engine_line) = EngineLine::from_info(info) {
+                let cur_depth = engine_line.depth;
+                let cur_nodes = engine_line.nodes;
+                // Only the sets of lines emitted are converted to SAN
+                if proc.line_sets.push(engine_line) && lim.check().is_ok() {
+                    let progress = proc.progress(cur_depth, cur_nodes);
+                    let best_lines = proc.last_lines();
+                    if proc.pending_searches == 1 {
+                        remember_lines(&app, &engine, &proc, &best_lines);
+                    }
+                    payloads.send(
+                        BestMovesPayload {
+                            tablebase: proc
+                                .tablebase_annotation(&app.state::<AppState>(), &best_lines),
+                            best_lines,
+                            engine: id.clone(),
+                            tab: tab.clone(),
+                            fen: proc.options.fen.clone(),
+                            moves: proc.options.moves.clone(),
+                            progress,
+                            cached: false,
+                            source: LinesSource::Engine,
+                        },
+                        false,
+                    );
+                    proc.last_progress = progress as f32;
+                }
+            }
+        } else if line.starts_with("bestmove") {
+            match parse_one(line) {
+                UciMessage::BestMove { best_move, .. } => {
+                    let best_lines = proc.last_lines();
+                    // With more searches pending the lines may belong to a newer position
+                    if proc.pending_searches == 1 {
+                        remember_lines(&app, &engine, &proc, &best_lines);
+                        store_lines(&app, &engine, &proc.position, &best_lines);
+                    }
+                    payloads.send(
+                        BestMovesPayload {
+                            tablebase: proc
+                                .tablebase_annotation(&app.state::<AppState>(), &best_lines),
+                            best_lines,
+                            engine: id.clone(),
+                            tab: tab.clone(),
+                            fen: proc.options.fen.clone(),
+                            moves: proc.options.moves.clone(),
+                            progress: 100.0,
+                            cached: false,
+                            source: LinesSource::Engine,
+                        },
+                        true,
+                    );
+                    proc.last_progress = 100.0;
+                    proc.finish_search(Some(best_move.to_string()));
+                }
+                // `bestmove (none)` is sent in mated or stalemated positions
+                _ => proc.finish_search(None),
+            }
+        }
+        proc.log_engine(line);
+    }
+    info!("Engine process finished: tab: {}, engine: {}", tab, engine);
+    app.state::<AppState>()
+        .engine_processes
+        .remove(&(tab, engine));
+    Ok(())
+}
+
+#[derive(Serialize, Debug, Clone, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct EngineMove {
+    /// `None` when the engine answered `bestmove (none)`
+    pub uci: Option<String>,
+    pub san: Option<String>,
+    pub score: Option<Score>,
+    pub depth: u32,
+    pub time_ms: u32,
+    /// Played from the opening book instead of searched
+    pub from_book: bool,
+    /// Ply of the position the opening book had no move for, where theory ended
+    pub book_exit: Option<u32>,
+}
+
+/// Ply of the position since the start of the game, from its move number
+fn ply(pos: &Chess) -> u32 {
+    (pos.fullmoves().get() - 1) * 2 + u32::from(pos.turn().is_black())
+}
+
+/// Asks the engine for a single move in the given position, reusing the tab's
+/// engine session if there is one. With a `book`, positions it has moves for are
+/// answered right away with one of them instead, and a book that can't be read is
+/// left out.
+#[tauri::command]
+#[specta::specta]
+#[allow(clippy::too_many_arguments)]
+pub async fn play_move(
+    id: String,
+    engine: String,
+    tab: String,
+    go_mode: GoMode,
+    options: EngineOptions,
+    book: Option<BookOptions>,
+    app: tauri::AppHandle,
+    state: tauri::State<'_, AppState>,
+) -> Result<EngineMove, Error> {
+    let fen = validate_fen(&options.fen)?;
+    let mut book_exit = None;
+    match book.as_ref().map(|book| (book, open_book(&book.path))) {
+        Some((book, Ok(entries))) => {
+            let pos = parse_position(&fen, &options.moves)?;
+            let moves = book_moves(&entries, &pos);
+            if let Some(m) = choose_book_move(&moves, book.randomness, &mut rand::thread_rng()) {
+                return Ok(EngineMove {
+                    uci: Some(m.uci.clone()),
+                    san: Some(m.san.clone()),
+                    score: None,
+                    depth: 0,
+                    time_ms: 0,
+                    from_book: true,
+                    book_exit: None,
+                });
+            }
+            // Theory ended here unless the book had no move for the previous position
+            // the engine played from either
+            let previous = match options.moves.len().checked_sub(2) {
+                Some(len) => Some(parse_position(&fen, &options.moves[..len])?),
+                None => None,
+            };
+            if previous.map_or(true, |previous| !book_moves(&entries, &previous).is_empty()) {
+                book_exit = Some(ply(&pos));
+            }
+        }
+        Some((book, Err(e))) => warn!("Playing without the book {}: {e}", book.path.display()),
+        None => {}
+    }
+    let mut engine_move = search_move(id, engine, tab, &go_mode, options, app, &state).await?;
+    engine_move.book_exit = book_exit;
+    Ok(engine_move)
+}
+
+/// Searches a move with the engine session of the tab, started if there is none yet
+pub async fn search_move(
+    id: String,
+    engine: String,
+    tab: String,
+    go_mode: &GoMode,
+    options: EngineOptions,
+    app: tauri::AppHandle,
+    state: &AppState,
+) -> Result<EngineMove, Error> {
+    let key = (tab.clone(), engine.clone());
+
+    let process = match state.engine_processes.get(&key).map(|p| p.clone()) {
+        Some(process) => process,
+        None => {
+            let (process, reader) = EngineProcess::new(PathBuf::from(&engine)).await?;
+            let process = Arc::new(Mutex::new(process));
+            state.engine_processes.insert(key, process.clone());
+
+            let output_process = process.clone();
+            tauri::async_runtime::spawn(async move {
+                if let Err(e) =
+                    process_engine_output(id, tab, engine, output_process, reader, app).await
+                {
+                    error!("Engine session failed: {}", e);
+                }
+            });
+            process
+        }
+    };
+
+    let (sender, receiver) = oneshot::channel();
+    {
+        let mut process = process.lock().await;
+        if process.running {
+            process.stop().await?;
+        }
+        process.set_options(options).await?;
+        process.move_sender = Some(sender);
+        process.go(go_mode).await?;
+    }
+
+    receiver.await.map_err(|_| Error::SearchStopped)
+}
+
+/// Number of lines a search with these options gives in `pos`
+fn real_multipv(pos: &Chess, options: &[EngineOption]) -> u16 {
+    let multipv = options
+        .iter()
+        .find(|x| x.name == "MultiPV")
+        .map(|x| x.value.parse().unwrap_or(1))
+        .unwrap_or(1);
+    multipv.min(pos.legal_moves().len() as u16)
+}
+
+/// Key of the lines of `pos` in the analysis cache, which depend on the options of the
+/// engine
+fn analysis_cache_key(pos: &Chess, engine: &str, options: &EngineOptions) -> AnalysisCacheKey {
+    let mut hasher = DefaultHasher::new();
+    options.extra_options.hash(&mut hasher);
+    options.max_pv_length.hash(&mut hasher);
+    AnalysisCacheKey {
+        fen: Epd::from_position(pos.clone(), EnPassantMode::Legal).to_string(),
+        engine: engine.to_string(),
+        options: hasher.finish(),
+    }
+}
+
+/// Writes finished search lines to the evaluation cache. Failures are only logged,
+/// since the search itself succeeded.
+fn store_lines(app: &tauri::AppHandle, engine: &str, pos: &Chess, lines: &[BestMoves]) {
+    if let Err(e) = cache_eval(app, pos, engine, lines) {
+        error!("Failed to cache evaluation: {}", e);
+    }
+}
+
+/// Keeps the lines emitted for the position of `proc` in the analysis cache
+fn remember_lines(
+    app: &tauri::AppHandle,
+    engine: &str,
+    proc: &EngineProcess,
+    lines: &Arc<Vec<BestMoves>>,
+) {
+    let key = analysis_cache_key(&proc.position, engine, &proc.options);
+    let state = app.state::<AppState>();
+    state
+        .analysis_cache
+        .lock()
+        .unwrap()
+        .insert(key, Arc::clone(lines));
+}
+
+fn cached_lines(app: &tauri::AppHandle, engine: &str, pos: &Chess) -> Option<CachedEval> {
+    get_cached_eval(app, pos, engine).unwrap_or_else(|e| {
+        error!("Failed to read evaluation cache: {}", e);
+        None
+    })
+}
+
+fn with_multipv(mut options: Vec<EngineOption>, multipv: u16) -> Vec<EngineOption> {
+    options.retain(|x| x.name != "MultiPV");
+    options.push(EngineOption {
+        name: "MultiPV".to_string(),
+        value: multipv.to_string(),
+    });
+    options
+}
+
+/// Options with the engine using `threads` threads
+pub fn with_threads(mut options: Vec<EngineOption>, threads: usize) -> Vec<EngineOption> {
+    options.retain(|x| x.name != "Threads");
+    options.push(EngineOption {
+        name: "Threads".to_string(),
+        value: threads.to_string(),
+    });
+    options
+}
+
+/// Options with `UCI_Chess960` on, for games with Chess960 castling
+pub fn with_chess960(mut options: Vec<EngineOption>) -> Vec<EngineOption> {
+    options.retain(|x| x.name != "UCI_Chess960");
+    options.push(EngineOption {
+        name: "UCI_Chess960".to_string(),
+        value: "true".to_string(),
+    });
+    options
+}
+
+/// Reads engine output until the current search finishes, for processes whose
+/// output isn't consumed by `process_engine_output`. Returns `None` if the search
+/// was stopped with `stop_engine` or the engine exited.
+async fn search_to_end(
+    process: &Mutex<EngineProcess>,
+    reader: &mut Lines<BufReader<ChildStdout>>,
+) -> Result<Option<Vec<BestMoves>>, Error> {
+    let reader = reader.get_mut();
+    let mut buffer = String::new();
+    while let Some(line) = read_engine_line(reader, &mut buffer).await? {
+        let mut proc = process.lock().await;
+        proc.log_engine(line);
+        if let Some(info) = parse_info(line) {
+            if let Some(engine_line) = EngineLine::from_info(info) {
+                proc.line_sets.push(engine_line);
+            }
+            continue;
+        }
+        match parse_one(line) {
+            UciMessage::BestMove { .. } => {
+                proc.finish_search(None);
+                return Ok(proc.running.then(|| proc.last_lines().to_vec()));
+            }
+            UciMessage::Unknown(..) if line.starts_with("bestmove") => {
+                proc.finish_search(None);
+                return Ok(proc.running.then(Vec::new));
+            }
+            _ => {}
+        }
+    }
+    Ok(None)
+}
+
+#[derive(Serialize, Debug, Clone, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct CandidateLine {
+    pub uci: String,
+    pub san: String,
+    /// Best line for the opponent after the candidate, `None` until it's analyzed
+    pub reply: Option<BestMoves>,
+}
+
+#[derive(Serialize, Debug, Clone, Type, Event)]
+#[serde(rename_all = "camelCase")]
+pub struct CandidatesPayload {
+    pub id: String,
+    pub fen: String,
+    pub candidates: Vec<CandidateLine>,
+    pub progress: f64,
+}
+
+/// Analyzes the best reply to each candidate move of a position. Candidates default to
+/// the engine's top `top_n` moves. The whole analysis can be cancelled with
+/// `stop_engine(engine, id)`.
+#[tauri::command]
+#[specta::specta]
+#[allow(clippy::too_many_arguments)]
+pub async fn analyze_candidates(
+    id: String,
+    engine: String,
+    fen: String,
+    candidates: Option<Vec<String>>,
+    top_n: u16,
+    go_mode: GoMode,
+    uci_options: Vec<EngineOption>,
+    app: tauri::AppHandle,
+    state: tauri::State<'_, AppState>,
+) -> Result<Vec<CandidateLine>, Error> {
+    let key = (id.clone(), engine.clone());
+    let (process, mut reader) = EngineProcess::new(PathBuf::from(&engine)).await?;
+    let process = Arc::new(Mutex::new(process));
+    state.engine_processes.insert(key.clone(), process.clone());
+
+    let result = search_candidates(
+        &id,
+        &fen,
+        candidates,
+        top_n,
+        &go_mode,
+        uci_options,
+        &process,
+        &mut reader,
+        &app,
+    )
+    .await;
+
+    state.engine_processes.remove(&key);
+    process.lock().await.kill().await?;
+    result
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn search_candidates(
+    id: &str,
+    fen: &str,
+    candidates: Option<Vec<String>>,
+    top_n: u16,
+    go_mode: &GoMode,
+    uci_options: Vec<EngineOption>,
+    process: &Mutex<EngineProcess>,
+    reader: &mut Lines<BufReader<ChildStdout>>,
+    app: &tauri::AppHandle,
+) -> Result<Vec<CandidateLine>, Error> {
+    let root = parse_position(&fen.parse()?, &[])?;
+
+    let candidates = match candidates {
+        Some(candidates) => candidates,
+        None => {
+            {
+                let mut proc = process.lock().await;
+                proc.set_options(EngineOptions {
+                    fen: fen.to_string(),
+                    moves: Vec::new(),
+                    extra_options: with_multipv(uci_options.clone(), top_n.max(1)),
+                    tablebase: None,
+                    cloud_eval: None,
+                    max_pv_length: None,
+                })
+                .await?;
+                proc.go(go_mode).await?;
+            }
+            let lines = search_to_end(process, reader)
+                .await?
+                .ok_or(Error::SearchStopped)?;
+            lines
+                .into_iter()
+                .filter_map(|line| line.uci_moves.into_iter().next())
+                .collect()
+        }
+    };
+
+    let mut lines = candidates
+        .into_iter()
+        .map(|uci| -> Result<CandidateLine, Error> {
+            let m = Uci::from_ascii(uci.as_bytes())?.to_move(&root)?;
+            let san = SanPlus::from_move(root.clone(), &m).to_string();
+            Ok(CandidateLine {
+                uci,
+                san,
+                reply: None,
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    for i in 0..lines.len() {
+        {
+            let mut proc = process.lock().await;
+            proc.set_options(EngineOptions {
+                fen: fen.to_string(),
+                moves: vec![lines[i].uci.clone()],
+                extra_options: with_multipv(uci_options.clone(), 1),
+                tablebase: None,
+                cloud_eval: None,
+                max_pv_length: None,
+            })
+            .await?;
+            proc.go(go_mode).await?;
+        }
+        let replies = search_to_end(process, reader)
+            .await?
+            .ok_or(Error::SearchStopped)?;
+        lines[i].reply = replies.into_iter().next();
+
+        CandidatesPayload {
+            id: id.to_string(),
+            fen: fen.to_string(),
+            candidates: lines.clone(),
+            progress: ((i + 1) as f64 / lines.len() as f64) * 100.0,
+        }
+        .emit_all(app)?;
+    }
+
+    Ok(lines)
+}
+
+#[derive(Serialize, Debug, Clone, Type)]
+#[serde(tag = "type", content = "value", rename_all = "camelCase")]
+pub enum Threat {
+    /// A null move is illegal when in check, so there is no threat to show
+    InCheck,
+    Line(BestMoves),
+}
+
+/// Depth searched for a threat asked with an infinite search, which nothing would end
+const THREAT_DEPTH: u32 = 20;
+
+/// Shows what the opponent would play if the side to move passed.
+///
+/// The engine runs as the session `{tab}:threat`, so it can be stopped with
+/// `stop_engine` and is killed with the other engines of the tab.
+#[tauri::command]
+#[specta::specta]
+pub async fn analyze_threat(
+    tab: String,
+    engine: String,
+    fen: String,
+    go_mode: GoMode,
+    uci_options: Vec<EngineOption>,
+    state: tauri::State<'_, AppState>,
+) -> Result<Threat, Error> {
+    let fen = validate_fen(&fen)?;
+    let pos = parse_position(&fen, &[])?;
+    if pos.is_check() {
+        return Ok(Threat::InCheck);
+    }
+    let null_fen = Fen::from_position(pos.swap_turn()?, EnPassantMode::Legal);
+    let go_mode = match go_mode {
+        GoMode::Infinite => GoMode::Depth(THREAT_DEPTH),
+        go_mode => go_mode,
+    };
+
+    let key = (format!("{tab}:threat"), engine.clone());
+    let (process, mut reader) = EngineProcess::new(PathBuf::from(&engine)).await?;
+    let process = Arc::new(Mutex::new(process));
+    state.engine_processes.insert(key.clone(), process.clone());
+
+    let result = search_threat(
+        &null_fen.to_string(),
+        &go_mode,
+        uci_options,
+        &process,
+        &mut reader,
+    )
+    .await;
+
+    state.engine_processes.remove(&key);
+    process.lock().await.kill().await?;
+    result
+}
+
+async fn search_threat(
+    fen: &str,
+    go_mode: &GoMode,
+    uci_options: Vec<EngineOption>,
+    process: &Mutex<EngineProcess>,
+    reader: &mut Lines<BufReader<ChildStdout>>,
+) -> Result<Threat, Error> {
+    {
+        let mut proc = process.lock().await;
+        proc.set_options(EngineOptions {
+            fen: fen.to_string(),
+            moves: Vec::new(),
+            extra_options: with_multipv(uci_options, 1),
+            tablebase: None,
+            cloud_eval: None,
+            max_pv_length: None,
+        })
+        .await?;
+        proc.go(go_mode).await?;
+    }
+    let lines = search_to_end(process, reader)
+        .await?
+        .ok_or(Error::SearchStopped)?;
+    lines
+        .into_iter()
+        .next()
+        .map(Threat::Line)
+        .ok_or(Error::NoMovesFound)
+}
+
+/// How strong a hint should be. Each level searches a fixed number of nodes on a
+/// single thread, so the same position gives the same hint on every machine.
+#[derive(Deserialize, Debug, Clone, Copy, Type, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum HintStrength {
+    /// 1,000 nodes, roughly what a beginner would find
+    Casual,
+    /// 10,000 nodes, around a 1600 club player
+    Club,
+    /// 100,000 nodes, enough to find most tactics
+    Expert,
+}
+
+impl HintStrength {
+    fn nodes(self) -> u32 {
+        match self {
+            HintStrength::Casual => 1_000,
+            HintStrength::Club => 10_000,
+            HintStrength::Expert => 100_000,
+        }
+    }
+}
+
+/// Number of moves of the principal variation included in a hint
+const HINT_PV_LENGTH: usize = 4;
+
+#[derive(Serialize, Debug, Clone, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct Hint {
+    pub uci: String,
+    pub san: String,
+    pub score: Score,
+    pub pv: Vec<String>,
+}
+
+/// Suggests a move found with a small node budget instead of the full-strength line.
+#[tauri::command]
+#[specta::specta]
+pub async fn get_hint(engine: String, fen: String, strength: HintStrength) -> Result<Hint, Error> {
+    validate_fen(&fen)?;
+    let (process, mut reader) = EngineProcess::new(PathBuf::from(&engine)).await?;
+    let process = Mutex::new(process);
+    let lines = search_hint(fen, strength, &process, &mut reader).await;
+    process.lock().await.kill().await?;
+
+    let line = lines?
+        .and_then(|lines| lines.into_iter().next())
+        .ok_or(Error::NoMovesFound)?;
+    let (Some(uci), Some(san)) = (line.uci_moves.first(), line.san_moves.first()) else {
+        return Err(Error::NoMovesFound);
+    };
+    Ok(Hint {
+        uci: uci.clone(),
+        san: san.clone(),
+        score: line.score,
+        pv: line.san_moves.into_iter().take(HINT_PV_LENGTH).collect(),
+    })
+}
+
+async fn search_hint(
+    fen: String,
+    strength: HintStrength,
+    process: &Mutex<EngineProcess>,
+    reader: &mut Lines<BufReader<ChildStdout>>,
+) -> Result<Option<Vec<BestMoves>>, Error> {
+    {
+        let mut proc = process.lock().await;
+        proc.set_options(EngineOptions {
+            fen,
+            moves: Vec::new(),
+            extra_options: vec![
+                EngineOption {
+                    name: "Threads".to_string(),
+                    value: "1".to_string(),
+                },
+                EngineOption {
+                    name: "MultiPV".to_string(),
+                    value: "1".to_string(),
+                },
+            ],
+            tablebase: None,
+            cloud_eval: None,
+            max_pv_length: None,
+        })
+        .await?;
+        proc.go(&GoMode::Nodes(strength.nodes())).await?;
+    }
+    search_to_end(process, reader).await
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, Type)]
 #[serde(rename_all = "camelCase")]
 pub struct AnalysisOptions {
     pub fen: String,
+    /// Moves in SAN or UCI notation
     pub moves: Vec<String>,
     pub annotate_novelties: bool,
     pub reference_db: Option<PathBuf>,
+    /// The first move to a position reached by fewer reference games is the novelty
+    pub novelty_threshold: Option<usize>,
     pub reversed: bool,
+    pub thresholds: Option<ClassificationThresholds>,
+    /// Centipawn evaluations are clamped to this for the average centipawn loss
+    pub acpl_ceiling: Option<u32>,
+    /// Analyze with MultiPV 1 instead of 2, which is faster but leaves out the positions
+    /// where only one move keeps the evaluation
+    #[serde(default)]
+    pub single_line: bool,
+    /// Positions before `from_ply` and after `to_ply` aren't analyzed
+    pub from_ply: Option<usize>,
+    pub to_ply: Option<usize>,
+    /// Start the analysis at the last position of the opening table
+    #[serde(default)]
+    pub skip_book: bool,
+    /// Second engine to analyze the same positions with, after the first one
+    pub compare_engine: Option<CompareEngine>,
+    /// Remaining time in seconds after each move, from the `[%clk]` comments
+    #[serde(default)]
+    pub clocks: Vec<Option<f64>>,
+    pub time_control: Option<String>,
+    /// Blunders played faster than this are flagged
+    pub fast_move_seconds: Option<f64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct CompareEngine {
+    pub engine: String,
+    #[serde(default)]
+    pub uci_options: Vec<EngineOption>,
+    /// Difference in win percent above which the engines disagree
+    pub threshold: Option<f64>,
 }
 
 #[derive(Clone, Type, serde::Serialize, Event)]
@@ -592,8 +1923,43 @@ pub struct ReportProgress {
     pub progress: f64,
     pub id: String,
     pub finished: bool,
+    /// Number of positions analyzed so far
+    pub analyzed: usize,
+    pub total: usize,
+    /// Evaluation of the position that was just analyzed
+    pub eval: Option<Score>,
+    /// The evaluation came from the evaluation cache
+    pub cached: bool,
 }
 
+/// Converts moves in SAN or UCI notation to the UCI moves sent to the engine. Null
+/// moves, `--` or `0000`, pass the turn and are sent as `0000`.
+fn moves_to_uci(pos: &Chess, moves: &[String], mode: CastlingMode) -> Result<Vec<String>, Error> {
+    let mut pos = pos.clone();
+    moves
+        .iter()
+        .map(|m| -> Result<String, Error> {
+            if m == "--" || m == "0000" {
+                pos = pos.clone().swap_turn()?;
+                return Ok(Uci::Null.to_string());
+            }
+            let mv = match Uci::from_ascii(m.as_bytes())
+                .ok()
+                .and_then(|uci| uci.to_move(&pos).ok())
+            {
+                Some(mv) => mv,
+                None => SanPlus::from_ascii(m.as_bytes())?.san.to_move(&pos)?,
+            };
+            let uci = mv.to_uci(mode).to_string();
+            pos.play_unchecked(&mv);
+            Ok(uci)
+        })
+        .collect()
+}
+
+/// Analyzes every position of a game with a single engine process. Positions where the
+/// game is already over are returned without a score. The analysis can be cancelled
+/// with `stop_engine(engine, id)`.
 #[tauri::command]
 #[specta::specta]
 pub async fn analyze_game(
@@ -604,137 +1970,426 @@ pub async fn analyze_game(
     uci_options: Vec<EngineOption>,
     state: tauri::State<'_, AppState>,
     app: tauri::AppHandle,
-) -> Result<Vec<MoveAnalysis>, Error> {
-    let path = PathBuf::from(&engine);
-    let mut analysis: Vec<MoveAnalysis> = Vec::new();
-
-    let (mut proc, mut reader) = EngineProcess::new(path).await?;
-
-    let fen = Fen::from_ascii(options.fen.as_bytes())?;
-
-    let mut chess: Chess = fen.clone().into_position(CastlingMode::Chess960)?;
-    let mut fens: Vec<(Fen, Vec<String>, bool)> = vec![(fen, vec![], false)];
+) -> Result<GameAnalysis, Error> {
+    let mut sessions = EngineSessions::new();
+    let analysis = analyze_game_with(
+        id,
+        engine,
+        go_mode,
+        options,
+        uci_options,
+        &mut sessions,
+        state,
+        app,
+    )
+    .await;
+    quit_sessions(sessions).await;
+    analysis
+}
 
-    options.moves.iter().enumerate().for_each(|(i, m)| {
-        let uci = Uci::from_ascii(m.as_bytes()).unwrap();
-        let m = uci.to_move(&chess).unwrap();
-        let previous_pos = chess.clone();
+/// Like [`analyze_game`], with the engines of `sessions` when they're there. The
+/// engines are left in `sessions` for the next games, unless their search failed or
+/// was stopped.
+#[allow(clippy::too_many_arguments)]
+pub async fn analyze_game_with(
+    id: String,
+    engine: String,
+    go_mode: GoMode,
+    options: AnalysisOptions,
+    uci_options: Vec<EngineOption>,
+    sessions: &mut EngineSessions,
+    state: tauri::State<'_, AppState>,
+    app: tauri::AppHandle,
+) -> Result<GameAnalysis, Error> {
+    let fen = validate_fen(&options.fen)?;
+    let mut chess = parse_position(&fen, &[])?;
+    let castling_mode = if uci_options
+        .iter()
+        .any(|x| x.name == "UCI_Chess960" && x.value == "true")
+    {
+        CastlingMode::Chess960
+    } else {
+        CastlingMode::Standard
+    };
+    let moves = moves_to_uci(&chess, &options.moves, castling_mode)?;
+
+    let mut analysis = vec![MoveAnalysis {
+        fen: options.fen.clone(),
+        ..Default::default()
+    }];
+    let first_to_move = chess.turn();
+    let mut positions = vec![chess.clone()];
+    let mut sacrifices = vec![false];
+    for (i, m) in moves.iter().enumerate() {
+        let m = match Uci::from_ascii(m.as_bytes())? {
+            Uci::Null => {
+                sacrifices.push(false);
+                chess = chess.swap_turn()?;
+                analysis.push(MoveAnalysis {
+                    ply: i + 1,
+                    fen: Fen::from_position(chess.clone(), EnPassantMode::Legal).to_string(),
+                    san: Some("--".to_string()),
+                    ..Default::default()
+                });
+                positions.push(chess.clone());
+                continue;
+            }
+            uci => uci.to_move(&chess)?,
+        };
+        sacrifices.push(gives_up_material(&chess, &m));
+        let prev_eval = naive_eval(&chess);
+        let san = SanPlus::from_move(chess.clone(), &m).to_string();
         chess.play_unchecked(&m);
-        let current_pos = chess.clone();
-        if !chess.is_game_over() {
-            let prev_eval = naive_eval(&previous_pos);
-            let cur_eval = -naive_eval(&current_pos);
-            fens.push((
-                Fen::from_position(current_pos, EnPassantMode::Legal),
-                options.moves.clone().into_iter().take(i + 1).collect(),
-                prev_eval > cur_eval + 100,
-            ));
-        }
-    });
+        // The naive eval of a finished game is `i32::MIN`, which can't be negated
+        let is_sacrifice = !chess.is_game_over() && prev_eval > -naive_eval(&chess) + 100;
+        analysis.push(MoveAnalysis {
+            ply: i + 1,
+            fen: Fen::from_position(chess.clone(), EnPassantMode::Legal).to_string(),
+            san: Some(san),
+            is_sacrifice,
+            ..Default::default()
+        });
+        positions.push(chess.clone());
+    }
 
+    let mut from = options.from_ply.unwrap_or(0);
+    if options.skip_book {
+        from = from.max(last_book_ply(&positions));
+    }
+    let to = options.to_ply.unwrap_or(moves.len()).min(moves.len());
+    let mut order: Vec<usize> = (from..=to).collect();
     if options.reversed {
-        fens.reverse();
+        order.reverse();
     }
 
-    let mut novelty_found = false;
-
-    for (i, (_, moves, _)) in fens.iter().enumerate() {
-        ReportProgress {
-            progress: (i as f64 / fens.len() as f64) * 100.0,
-            id: id.clone(),
-            finished: false,
-        }
-        .emit_all(&app)?;
+    let multipv = if options.single_line { 1 } else { 2 };
+    let comparison_plies = if options.compare_engine.is_some() {
+        order.len()
+    } else {
+        0
+    };
+    let search = GameSearch {
+        id: &id,
+        fen: &options.fen,
+        moves: &moves,
+        positions: &positions,
+        order: &order,
+        go_mode: &go_mode,
+        total: order.len() + comparison_plies,
+    };
+    search
+        .run(
+            &engine,
+            &uci_options,
+            multipv,
+            &mut analysis,
+            0,
+            sessions,
+            &state,
+            &app,
+        )
+        .await?;
 
-        let mut extra_options = uci_options.clone();
-        if !extra_options.iter().any(|x| x.name == "MultiPV") {
-            extra_options.push(EngineOption {
-                name: "MultiPV".to_string(),
-                value: "2".to_string(),
-            });
-        } else {
-            extra_options.iter_mut().for_each(|x| {
-                if x.name == "MultiPV" {
-                    x.value = "2".to_string();
-                }
-            });
+    let comparison = match &options.compare_engine {
+        Some(other) => {
+            let mut other_analysis: Vec<MoveAnalysis> = analysis
+                .iter()
+                .map(|a| MoveAnalysis {
+                    ply: a.ply,
+                    fen: a.fen.clone(),
+                    san: a.san.clone(),
+                    ..Default::default()
+                })
+                .collect();
+            search
+                .run(
+                    &other.engine,
+                    &other.uci_options,
+                    1,
+                    &mut other_analysis,
+                    order.len(),
+                    sessions,
+                    &state,
+                    &app,
+                )
+                .await?;
+            Some(compare_engines(
+                &other.engine,
+                &analysis,
+                &other_analysis,
+                other.threshold.unwrap_or(DEFAULT_DISAGREEMENT),
+            ))
         }
+        None => None,
+    };
 
-        proc.set_options(EngineOptions {
-            fen: options.fen.clone(),
-            moves: moves.clone(),
-            extra_options,
+    let evals: Vec<Option<Eval>> = analysis
+        .iter()
+        .zip(&positions)
+        .enumerate()
+        .map(|(ply, (analysis, pos))| match pos.outcome() {
+            _ if !(from..=to).contains(&ply) => None,
+            Some(outcome) => Some(Eval::from_outcome(outcome)),
+            None => analysis.score.as_ref().map(Eval::from_score),
         })
-        .await?;
-
-        proc.go(&go_mode).await?;
-
-        let mut current_analysis = MoveAnalysis::default();
-        while let Ok(Some(line)) = reader.next_line().await {
-            match parse_one(&line) {
-                UciMessage::Info(attrs) => {
-                    if let Ok(best_moves) =
-                        parse_uci_attrs(attrs, &proc.options.fen.parse()?, moves)
-                    {
-                        let multipv = best_moves.multipv;
-                        let cur_depth = best_moves.depth;
-                        if multipv as usize == proc.best_moves.len() + 1 {
-                            proc.best_moves.push(best_moves);
-                            if multipv == proc.real_multipv {
-                                if proc.best_moves.iter().all(|x| x.depth == cur_depth)
-                                    && cur_depth >= proc.last_depth
-                                {
-                                    current_analysis.best = proc.best_moves.clone();
-                                    proc.last_depth = cur_depth;
-                                }
-                                assert_eq!(proc.best_moves.len(), proc.real_multipv as usize);
-                                proc.best_moves.clear();
-                            }
-                        }
-                    }
-                }
-                UciMessage::BestMove { .. } => {
-                    break;
-                }
-                _ => {}
+        .collect();
+    let classifications = classify_moves(
+        &evals,
+        first_to_move,
+        &options.thresholds.unwrap_or_default(),
+    );
+    let accuracies = move_accuracies(&evals, first_to_move);
+    let phases = game_phases(&positions);
+    let graph = eval_graph(&evals, &classifications);
+    let win_chances = win_chances(&evals, first_to_move);
+    let turning_points = turning_points(&win_chances, TURNING_POINTS);
+    for (i, analysis) in analysis.iter_mut().enumerate() {
+        analysis.classification = classifications[i];
+        analysis.accuracy = accuracies[i];
+        analysis.phase = phases[i];
+        if let [best, second, ..] = analysis.best.as_slice() {
+            let turn = positions[i].turn();
+            if is_only_move(
+                Eval::from_score(&best.score),
+                Eval::from_score(&second.score),
+                turn,
+            ) {
+                analysis.only_move = Some(second.clone());
             }
         }
-        analysis.push(current_analysis);
     }
-
-    if options.reversed {
-        analysis.reverse();
-        fens.reverse();
+    for i in 1..analysis.len() {
+        let best = analysis[i - 1]
+            .best
+            .first()
+            .and_then(|line| line.san_moves.first());
+        if let (Some(before), Some(after)) = (evals[i - 1], evals[i]) {
+            analysis[i].brilliant = is_brilliant(
+                before,
+                after,
+                positions[i - 1].turn(),
+                best.is_some() && best == analysis[i].san.as_ref(),
+                sacrifices[i],
+            );
+        }
     }
-
-    for (i, analysis) in analysis.iter_mut().enumerate() {
-        let fen = &fens[i].0;
-        let query = PositionQuery::exact_from_fen(&fen.to_string())?;
-
-        analysis.is_sacrifice = fens[i].2;
-        if options.annotate_novelties && !novelty_found {
-            if let Some(reference) = options.reference_db.clone() {
-                analysis.novelty = !is_position_in_db(
-                    reference,
-                    GameQuery::new().position(query.clone()).clone(),
-                    state.clone(),
-                )
-                .await?;
-                if analysis.novelty {
-                    novelty_found = true;
-                }
-            } else {
-                return Err(Error::MissingReferenceDatabase);
-            }
+    let accuracy = game_accuracy(&evals, first_to_move);
+    let missed_wins = missed_wins(&analysis, &evals, first_to_move);
+    let mut time_usage = time_spent(
+        &options.clocks,
+        options.time_control.as_deref().and_then(parse_time_control),
+    );
+    time_usage.resize(analysis.len(), None);
+    let time_moments = time_moments(
+        &evals,
+        &classifications,
+        &time_usage,
+        first_to_move,
+        options
+            .fast_move_seconds
+            .unwrap_or(DEFAULT_FAST_MOVE_SECONDS),
+    );
+    let acpl = acpl(
+        &evals,
+        &phases,
+        first_to_move,
+        options.acpl_ceiling.unwrap_or(DEFAULT_ACPL_CEILING) as i32,
+    );
+    let phase_stats = phase_stats(&evals, &classifications, &phases, first_to_move);
+
+    let mut novelty = None;
+    if options.annotate_novelties {
+        let reference = options
+            .reference_db
+            .clone()
+            .ok_or(Error::MissingReferenceDatabase)?;
+        let popularity = count_positions(reference, &positions, state.clone()).await?;
+        novelty = find_novelty(
+            &popularity,
+            &analysis,
+            options.novelty_threshold.unwrap_or(1),
+        );
+        if let Some(novelty) = &novelty {
+            analysis[novelty.ply].novelty = true;
         }
     }
+
     ReportProgress {
         progress: 100.0,
         id: id.clone(),
         finished: true,
+        analyzed: search.total,
+        total: search.total,
+        eval: None,
+        cached: false,
     }
     .emit_all(&app)?;
-    Ok(analysis)
+    Ok(GameAnalysis {
+        moves: analysis,
+        white_accuracy: accuracy.white,
+        black_accuracy: accuracy.black,
+        white_acpl: acpl.white,
+        black_acpl: acpl.black,
+        white_phases: phase_stats.white,
+        black_phases: phase_stats.black,
+        missed_wins,
+        graph,
+        win_chances,
+        turning_points,
+        novelty,
+        analyzed_from: from,
+        analyzed_to: to,
+        comparison,
+        opening: classify_setups(
+            positions
+                .iter()
+                .map(|pos| pos.clone().into_setup(EnPassantMode::Legal)),
+        ),
+        time_usage,
+        time_moments,
+    })
+}
+
+/// The positions of a game analyzed by each engine of [`analyze_game`]
+struct GameSearch<'a> {
+    id: &'a str,
+    fen: &'a str,
+    moves: &'a [String],
+    positions: &'a [Chess],
+    /// Plies to analyze, in order
+    order: &'a [usize],
+    go_mode: &'a GoMode,
+    /// Number of positions all engines analyze, for the progress
+    total: usize,
+}
+
+impl GameSearch<'_> {
+    /// Analyzes the positions with `engine`, whose session can be stopped with
+    /// `stop_engine(engine, id)`. `done` positions were analyzed by previous engines.
+    /// The session of `sessions` is used when it has the same options, and left there
+    /// unless the search fails.
+    #[allow(clippy::too_many_arguments)]
+    async fn run(
+        &self,
+        engine: &str,
+        uci_options: &[EngineOption],
+        multipv: u16,
+        analysis: &mut [MoveAnalysis],
+        done: usize,
+        sessions: &mut EngineSessions,
+        state: &AppState,
+        app: &tauri::AppHandle,
+    ) -> Result<(), Error> {
+        let key = (self.id.to_string(), engine.to_string());
+        let mut session = take_session(sessions, engine, uci_options).await?;
+        state
+            .engine_processes
+            .insert(key.clone(), session.process.clone());
+
+        let mut result = Ok(());
+        for (i, &ply) in self.order.iter().enumerate() {
+            // Plies found in the cache don't search, so a stop is looked for before each
+            if session.process.lock().await.stop_requested {
+                result = Err(Error::SearchStopped);
+                break;
+            }
+            let pos = &self.positions[ply];
+            let mut cached = false;
+            if !pos.is_game_over() {
+                let lines = multipv.min(pos.legal_moves().len() as u16);
+                let hit = cached_lines(app, engine, pos)
+                    .filter(|cached| cached.satisfies(self.go_mode, lines));
+                if let Some(hit) = hit {
+                    set_best_lines(&mut analysis[ply], hit.lines);
+                    cached = true;
+                } else {
+                    result = analyze_ply(
+                        self.fen,
+                        &self.moves[..ply],
+                        multipv,
+                        self.go_mode,
+                        uci_options,
+                        &session.process,
+                        &mut session.reader,
+                        &mut analysis[ply],
+                    )
+                    .await;
+                    if result.is_err() {
+                        break;
+                    }
+                    store_lines(app, engine, pos, &analysis[ply].best);
+                }
+            }
+            let analyzed = done + i + 1;
+            let progress = ReportProgress {
+                progress: (analyzed as f64 / self.total as f64) * 100.0,
+                id: self.id.to_string(),
+                finished: false,
+                analyzed,
+                total: self.total,
+                eval: analysis[ply].score.clone(),
+                cached,
+            }
+            .emit_all(app);
+            if let Err(e) = progress {
+                result = Err(e.into());
+                break;
+            }
+        }
+
+        // The session is cleaned up however the search ended
+        state.engine_processes.remove(&key);
+        if result.is_ok() {
+            sessions.insert(engine.to_string(), session);
+        } else {
+            session.quit().await?;
+        }
+        result
+    }
+}
+
+fn set_best_lines(analysis: &mut MoveAnalysis, best: Vec<BestMoves>) {
+    if let Some(line) = best.first() {
+        analysis.score = Some(line.score.clone());
+        analysis.best_move = line.uci_moves.first().cloned();
+        analysis.pv = line.uci_moves.clone();
+    }
+    analysis.best = best;
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn analyze_ply(
+    fen: &str,
+    moves: &[String],
+    multipv: u16,
+    go_mode: &GoMode,
+    uci_options: &[EngineOption],
+    process: &Mutex<EngineProcess>,
+    reader: &mut Lines<BufReader<ChildStdout>>,
+    analysis: &mut MoveAnalysis,
+) -> Result<(), Error> {
+    {
+        let mut proc = process.lock().await;
+        // Looked for again with the lock the search starts with, for a stop sent since
+        if proc.stop_requested {
+            return Err(Error::SearchStopped);
+        }
+        proc.set_options(EngineOptions {
+            fen: fen.to_string(),
+            moves: moves.to_vec(),
+            extra_options: with_multipv(uci_options.to_vec(), multipv),
+            tablebase: None,
+            cloud_eval: None,
+            max_pv_length: None,
+        })
+        .await?;
+        proc.go(go_mode).await?;
+    }
+    let best = search_to_end(process, reader)
+        .await?
+        .ok_or(Error::SearchStopped)?;
+    set_best_lines(analysis, best);
+    Ok(())
 }
 
 fn count_material(position: &Chess) -> i32 {
@@ -813,7 +2468,7 @@ fn naive_eval(pos: &Chess) -> i32 {
 
 #[cfg(test)]
 mod tests {
-    use shakmaty::FromSetup;
+    use shakmaty::{FromSetup, Square};
 
     use super::*;
 
@@ -822,6 +2477,19 @@ mod tests {
         Chess::from_setup(fen.into_setup(), CastlingMode::Chess960).unwrap()
     }
 
+    #[test]
+    fn passes_null_moves_to_the_engine() {
+        let moves = ["e4", "--", "d4", "0000"].map(String::from);
+        let uci = moves_to_uci(&Chess::default(), &moves, CastlingMode::Standard).unwrap();
+        assert_eq!(uci, ["e2e4", "0000", "d2d4", "0000"]);
+        let fen: Fen = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1"
+            .parse()
+            .unwrap();
+        let pos = parse_position(&fen, &uci).unwrap();
+        assert_eq!(pos.turn(), Color::White);
+        assert_eq!(pos.board().piece_at(Square::D4), Some(Color::White.pawn()));
+    }
+
     #[test]
     fn eval_start_pos() {
         assert_eq!(naive_eval(&Chess::default()), 0);
@@ -874,41 +2542,549 @@ mod tests {
         let position = pos("4kb1r/p2rqppp/5n2/1B2p1B1/4P3/1Q6/PPP2PPP/2KR4 b k - 1 14");
         assert_eq!(naive_eval(&position), 0);
     }
+
+    fn moves(moves: &str) -> Vec<String> {
+        moves.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn convert_promotions() {
+        let fen = "3r3k/4P3/8/8/8/8/6p1/4K3 w - - 0 1".to_string();
+        let converted = san_to_uci(fen.clone(), moves("exd8=Q+ Kh7 Ke2 g1=N+")).unwrap();
+        assert_eq!(converted.moves, moves("e7d8q h8h7 e1e2 g2g1n"));
+        assert_eq!(converted.fen, "3Q4/7k/8/8/8/8/4K3/6n1 w - - 0 3");
+        let back = uci_to_san(fen, converted.moves).unwrap();
+        assert_eq!(back.moves, moves("exd8=Q+ Kh7 Ke2 g1=N+"));
+        assert_eq!(back.fen, converted.fen);
+    }
+
+    #[test]
+    fn convert_castling() {
+        let fen = "r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1".to_string();
+        let converted = san_to_uci(fen.clone(), moves("O-O O-O-O")).unwrap();
+        assert_eq!(converted.moves, moves("e1g1 e8c8"));
+        assert_eq!(converted.fen, "2kr3r/8/8/8/8/8/8/R4RK1 w - - 2 2");
+        let back = uci_to_san(fen, moves("e1c1 e8g8")).unwrap();
+        assert_eq!(back.moves, moves("O-O-O O-O"));
+
+        // Chess960 castling is written as the king taking its rook
+        let fen = "bqnbrkrn/pppppppp/8/8/8/8/PPPPPPPP/BQNBRKRN w GEge - 0 1".to_string();
+        let converted = san_to_uci(fen, moves("O-O")).unwrap();
+        assert_eq!(converted.moves, moves("f1g1"));
+    }
+
+    #[test]
+    fn convert_en_passant() {
+        let fen = "4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 2".to_string();
+        let converted = san_to_uci(fen.clone(), moves("exd6")).unwrap();
+        assert_eq!(converted.moves, moves("e5d6"));
+        assert_eq!(converted.fen, "4k3/8/3P4/8/8/8/8/4K3 b - - 0 2");
+        assert_eq!(uci_to_san(fen, moves("e5d6")).unwrap().moves, moves("exd6"));
+    }
+
+    #[test]
+    fn conversion_stops_at_the_first_illegal_move() {
+        let fen = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1".to_string();
+        let error = san_to_uci(fen.clone(), moves("e4 e5 Ke3 Nc6")).unwrap_err();
+        assert!(matches!(error, Error::IllegalMove { index: 2, .. }));
+        let error = uci_to_san(fen.clone(), moves("e2e4 e7e5 x")).unwrap_err();
+        assert!(matches!(error, Error::IllegalMove { index: 2, .. }));
+        assert!(san_to_uci(fen, moves("e4 O-O")).is_err());
+    }
+
+    #[test]
+    fn validate_fens() {
+        assert!(validate_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1").is_ok());
+        assert!(validate_fen("4k3/8/8/8/8/8/8/8 w - - 0 1").is_err());
+        assert!(validate_fen("4k3/8/8/8/8/8/8/4K2r b - - 0 1").is_err());
+        assert!(validate_fen("4k3/8/8/8/8/PPPPPPPP/P7/4K3 w - - 0 1").is_err());
+        assert!(validate_fen("not a fen").is_err());
+    }
+
+    #[test]
+    fn extend_go_mode() {
+        assert!(GoMode::Depth(35).extends(&GoMode::Depth(25)));
+        assert!(!GoMode::Depth(20).extends(&GoMode::Depth(25)));
+        assert!(!GoMode::Depth(25).extends(&GoMode::Depth(25)));
+        assert!(GoMode::Infinite.extends(&GoMode::Nodes(1000)));
+        assert!(!GoMode::Time(5000).extends(&GoMode::Depth(25)));
+    }
+
+    #[test]
+    fn clock_go_command() {
+        let clock = Clock {
+            wtime: 60000,
+            btime: 58000,
+            winc: 1000,
+            binc: 1000,
+            movestogo: Some(40),
+        };
+        assert_eq!(
+            GoMode::Clock(clock.clone()).to_command(),
+            "go wtime 60000 btime 58000 winc 1000 binc 1000 movestogo 40\n"
+        );
+        assert_eq!(clock.time_left(Color::White), 60000);
+        assert_eq!(clock.time_left(Color::Black), 58000);
+
+        let clock = Clock {
+            wtime: 0,
+            btime: 3000,
+            winc: 0,
+            binc: 2000,
+            movestogo: None,
+        };
+        assert_eq!(
+            GoMode::Clock(clock.clone()).to_command(),
+            "go wtime 0 btime 3000 winc 0 binc 2000\n"
+        );
+        assert_eq!(clock.time_left(Color::White), 0);
+        assert_eq!(clock.time_left(Color::Black), 3000);
+    }
+
+    #[cfg(unix)]
+    fn script(dir: &std::path::Path, name: &str, body: &str) -> PathBuf {
+        let path = dir.join(name);
+        std::fs::write(&path, format!("#!/bin/sh\n{body}\n")).unwrap();
+        crate::fs::set_executable(&path).unwrap();
+        path
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn probes_programs() {
+        let dir = tempfile::tempdir().unwrap();
+        let timeout = std::time::Duration::from_secs(2);
+
+        let engine = script(
+            dir.path(),
+            "engine",
+            "read line\n\
+             echo 'id name Fake 1.0'\n\
+             echo 'id author Someone'\n\
+             echo 'option name MultiPV type spin default 1 min 1 max 500'\n\
+             echo uciok\n\
+             sleep 5",
+        );
+        let config = uci_handshake(engine, timeout).await.unwrap();
+        assert_eq!(config.name, "Fake 1.0");
+        assert_eq!(config.author.as_deref(), Some("Someone"));
+        assert_eq!(config.options.len(), 1);
+
+        let echo = script(dir.path(), "echo", "echo 'Hello'\necho 'World'");
+        assert!(matches!(
+            uci_handshake(echo, timeout).await,
+            Err(EngineProbeError::NotUci { output }) if output == "Hello\nWorld"
+        ));
+
+        let crash = script(dir.path(), "crash", "kill -ILL $$");
+        assert!(matches!(
+            uci_handshake(crash, timeout).await,
+            Err(EngineProbeError::UnsupportedCpu)
+        ));
+
+        let silent = script(dir.path(), "silent", "sleep 5");
+        assert!(matches!(
+            uci_handshake(silent, std::time::Duration::from_millis(200)).await,
+            Err(EngineProbeError::TimedOut)
+        ));
+
+        let text = dir.path().join("notes.txt");
+        std::fs::write(&text, "uciok").unwrap();
+        assert!(matches!(
+            uci_handshake(text, timeout).await,
+            Err(EngineProbeError::NotExecutable { .. })
+        ));
+        assert!(matches!(
+            probe_engine(dir.path().to_path_buf()).await,
+            Err(EngineProbeError::NotExecutable { .. })
+        ));
+    }
+
+    fn payload(progress: f64) -> BestMovesPayload {
+        BestMovesPayload {
+            best_lines: Arc::default(),
+            engine: String::new(),
+            tab: String::new(),
+            fen: String::new(),
+            moves: Vec::new(),
+            progress,
+            cached: false,
+            source: LinesSource::Engine,
+            tablebase: None,
+        }
+    }
+
+    #[test]
+    fn drops_oldest_sets_of_lines() {
+        let queue = PayloadQueue::default();
+        queue.push(payload(100.0), true);
+        for progress in 0..20 {
+            queue.push(payload(progress as f64), false);
+        }
+        queue.push(payload(100.0), true);
+
+        let mut progress = Vec::new();
+        while let Some(payload) = queue.pop() {
+            progress.push(payload.progress);
+        }
+        assert_eq!(progress, [100.0, 14.0, 15.0, 16.0, 17.0, 18.0, 19.0, 100.0]);
+    }
+
+    #[tokio::test]
+    async fn emits_every_final_payload() {
+        let (sender, mut emitted) = tokio::sync::mpsc::unbounded_channel();
+        let payloads = spawn_payload_task(move |payload| {
+            // slower than the searches, so that the queue fills up
+            std::thread::sleep(std::time::Duration::from_millis(1));
+            sender.send(payload.progress).unwrap();
+        });
+        for _ in 0..5 {
+            for progress in 0..200 {
+                payloads.send(payload(progress as f64 / 4.0), false);
+            }
+            payloads.send(payload(100.0), true);
+        }
+        drop(payloads);
+
+        let mut progress = Vec::new();
+        while let Some(p) = emitted.recv().await {
+            progress.push(p);
+        }
+        assert_eq!(progress.iter().filter(|&&p| p == 100.0).count(), 5);
+        assert_eq!(progress.last(), Some(&100.0));
+    }
+
+    /// Searches with batches of 500 lines every 10 ms until it's stopped, which kills the
+    /// search and answers at once with a line at depth 1000 and its best move
+    #[cfg(unix)]
+    const FAST_ENGINE: &str = r#"
+while read -r command; do
+    case "$command" in
+        uci) echo "id name Fast"; echo uciok ;;
+        isready) echo readyok ;;
+        go*)
+            (
+                depth=1
+                while :; do
+                    i=0
+                    while [ $i -lt 500 ]; do
+                        echo "info depth $depth multipv 1 score cp 15 nodes 1000 nps 50000 pv e2e4 e7e5"
+                        i=$((i + 1))
+                    done
+                    depth=$((depth + 1))
+                    sleep 0.01
+                done
+            ) &
+            search=$! ;;
+        stop)
+            kill $search
+            wait $search 2>/dev/null
+            echo "info depth 1000 multipv 1 score cp 20 nodes 2000 nps 50000 pv d2d4"
+            echo "bestmove d2d4" ;;
+        quit) exit 0 ;;
+    esac
+done"#;
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn keeps_up_with_fast_engines() {
+        let dir = tempfile::tempdir().unwrap();
+        let engine = script(dir.path(), "engine", FAST_ENGINE);
+        let (mut proc, mut reader) = EngineProcess::new(engine).await.unwrap();
+        proc.set_options(EngineOptions {
+            fen: "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1".to_string(),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        proc.go(&GoMode::Infinite).await.unwrap();
+        let process = Arc::new(Mutex::new(proc));
+
+        // Stopped while the lines still stream in
+        let stopper = process.clone();
+        let stopped = tokio::spawn(async move {
+            tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+            stopper.lock().await.stop().await.unwrap();
+            Instant::now()
+        });
+        let lines = search_to_end(&process, &mut reader).await.unwrap();
+        let latency = stopped.await.unwrap().elapsed();
+
+        // The lines sent before the stop were read as they came, not left to pile up
+        assert!(latency < std::time::Duration::from_secs(1), "{latency:?}");
+        assert!(lines.is_none());
+
+        let mut proc = process.lock().await;
+        let lines = proc.last_lines();
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0].depth, 1000);
+        assert_eq!(lines[0].san_moves, ["d4"]);
+        assert_eq!(proc.logs.len(), MAX_ENGINE_LOGS);
+        assert!(
+            matches!(proc.logs.back(), Some(EngineLog::Engine(line)) if line == "bestmove d2d4")
+        );
+        assert!(proc.line_sets.current.is_empty());
+        proc.kill().await.unwrap();
+    }
+
+    #[cfg(unix)]
+    const INSTANT_ENGINE: &str = r#"
+while read -r command; do
+    case "$command" in
+        uci) echo "id name Instant"; echo uciok ;;
+        isready) echo readyok ;;
+        go*) echo "info depth 1 multipv 1 score cp 0 nodes 1 nps 1 pv e2e4"; echo "bestmove e2e4" ;;
+        quit) exit 0 ;;
+    esac
+done"#;
+
+    /// Analyzes the start position like a game of a batch job, with the session of
+    /// `engine` in `sessions`, which is left there
+    #[cfg(unix)]
+    async fn analyze_start(
+        sessions: &mut EngineSessions,
+        engine: &str,
+        options: Vec<EngineOption>,
+    ) -> Arc<Mutex<EngineProcess>> {
+        let mut session = take_session(sessions, engine, &options).await.unwrap();
+        let mut analysis = MoveAnalysis::default();
+        analyze_ply(
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            &[],
+            1,
+            &GoMode::Depth(1),
+            &options,
+            &session.process,
+            &mut session.reader,
+            &mut analysis,
+        )
+        .await
+        .unwrap();
+        assert_eq!(analysis.best_move.as_deref(), Some("e2e4"));
+        let process = session.process.clone();
+        sessions.insert(engine.to_string(), session);
+        process
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn restarts_kept_engines_for_other_options() {
+        let dir = tempfile::tempdir().unwrap();
+        let engine = script(dir.path(), "engine", INSTANT_ENGINE);
+        let engine = engine.to_str().unwrap();
+        let sent = |process: &Mutex<EngineProcess>, command: &str| {
+            process
+                .try_lock()
+                .unwrap()
+                .logs
+                .iter()
+                .any(|log| matches!(log, EngineLog::Gui(line) if line.trim_end() == command))
+        };
+
+        // A Chess960 game, then a standard one on the same worker
+        let mut sessions = EngineSessions::new();
+        let chess960 = analyze_start(&mut sessions, engine, with_chess960(Vec::new())).await;
+        assert!(sent(&chess960, "setoption name UCI_Chess960 value true"));
+        let standard = analyze_start(&mut sessions, engine, Vec::new()).await;
+        assert!(!Arc::ptr_eq(&chess960, &standard));
+        assert!(!sent(&standard, "setoption name UCI_Chess960 value true"));
+
+        // Games with the same options keep the engine
+        let next = analyze_start(&mut sessions, engine, Vec::new()).await;
+        assert!(Arc::ptr_eq(&standard, &next));
+        assert!(sent(&next, "ucinewgame"));
+        quit_sessions(sessions).await;
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn quits_every_session() {
+        let dir = tempfile::tempdir().unwrap();
+        let engine = script(dir.path(), "engine", INSTANT_ENGINE);
+        let state = AppState::default();
+        for tab in ["idle", "running"] {
+            let (mut process, _) = EngineProcess::new(engine.clone()).await.unwrap();
+            process.running = tab == "running";
+            state.engine_processes.insert(
+                (tab.to_string(), "engine".to_string()),
+                Arc::new(Mutex::new(process)),
+            );
+        }
+
+        let summary = stop_engines(&state, true).await;
+        assert_eq!(summary.stopped, 1);
+        assert_eq!(summary.killed, 0);
+        assert!(state.engine_processes.is_empty());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn stops_games_before_their_first_search() {
+        let dir = tempfile::tempdir().unwrap();
+        let engine = script(dir.path(), "engine", INSTANT_ENGINE);
+        let engine = engine.to_str().unwrap();
+        let mut sessions = EngineSessions::new();
+        let process = analyze_start(&mut sessions, engine, Vec::new()).await;
+
+        // A stop sent once a game is over doesn't stop the next one
+        process.lock().await.stop().await.unwrap();
+        let next = analyze_start(&mut sessions, engine, Vec::new()).await;
+        assert!(Arc::ptr_eq(&process, &next));
+
+        let mut session = take_session(&mut sessions, engine, &[]).await.unwrap();
+        session.process.lock().await.stop().await.unwrap();
+        let stopped = analyze_ply(
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            &[],
+            1,
+            &GoMode::Depth(1),
+            &[],
+            &session.process,
+            &mut session.reader,
+            &mut MoveAnalysis::default(),
+        )
+        .await;
+        assert!(matches!(stopped, Err(Error::SearchStopped)));
+        session.quit().await.unwrap();
+    }
 }
 
+/// Time a program has to answer `uci` when it's probed
+pub const HANDSHAKE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Lines of output shown for programs that turn out not to be engines
+const OUTPUT_SAMPLE_LINES: usize = 10;
+
 #[derive(Type, Default, Serialize, Debug)]
 pub struct EngineConfig {
     pub name: String,
+    pub author: Option<String>,
     pub options: Vec<UciOptionConfig>,
 }
 
-#[tauri::command]
-#[specta::specta]
-pub async fn get_engine_config(path: PathBuf) -> Result<EngineConfig, Error> {
-    let mut child = start_engine(path)?;
-    let (mut stdin, mut stdout) = get_handles(&mut child)?;
+#[derive(Debug, Clone, Serialize, Type, thiserror::Error)]
+#[serde(tag = "type", content = "value", rename_all = "camelCase")]
+pub enum EngineProbeError {
+    #[error("The file can't be run: {reason}")]
+    NotExecutable { reason: String },
 
-    send_command(&mut stdin, "uci\n").await;
+    #[error("The program isn't a UCI engine, it answered: {output}")]
+    NotUci { output: String },
 
-    let mut config = EngineConfig::default();
+    #[error("The engine didn't answer in time")]
+    TimedOut,
 
-    loop {
-        if let Some(line) = stdout.next_line().await? {
-            if let UciMessage::Id {
-                name: Some(name),
-                author: _,
-            } = parse_one(&line)
-            {
-                config.name = name;
-            }
-            if let UciMessage::Option(opt) = parse_one(&line) {
-                config.options.push(opt);
+    #[error("The engine crashed with an illegal instruction, this build likely requires CPU features your processor lacks")]
+    UnsupportedCpu,
+}
+
+/// Reads the engine's id and options until `uciok`, once `uci` was sent. Every line
+/// read is added to `output`. None when the engine exits before `uciok`.
+async fn read_uci_config(
+    lines: &mut Lines<BufReader<ChildStdout>>,
+    output: &mut Vec<String>,
+) -> std::io::Result<Option<EngineConfig>> {
+    let mut config = EngineConfig::default();
+    while let Some(line) = lines.next_line().await? {
+        match parse_one(&line) {
+            UciMessage::Id { name, author } => {
+                if let Some(name) = name {
+                    config.name = name;
+                }
+                if author.is_some() {
+                    config.author = author;
+                }
             }
-            if let UciMessage::UciOk = parse_one(&line) {
-                break;
+            UciMessage::Option(opt) => config.options.push(opt),
+            UciMessage::UciOk => {
+                output.push(line);
+                return Ok(Some(config));
             }
+            _ => {}
+        }
+        output.push(line);
+    }
+    Ok(None)
+}
+
+fn not_uci(output: &[String]) -> EngineProbeError {
+    EngineProbeError::NotUci {
+        output: output[..output.len().min(OUTPUT_SAMPLE_LINES)].join("\n"),
+    }
+}
+
+/// Whether the program was killed for running an instruction the processor lacks
+fn illegal_instruction(status: std::process::ExitStatus) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        // SIGILL
+        status.signal() == Some(4)
+    }
+    #[cfg(windows)]
+    {
+        // STATUS_ILLEGAL_INSTRUCTION
+        status.code() == Some(0xC000001Du32 as i32)
+    }
+}
+
+/// Why a program exited before completing the handshake
+async fn exit_error(child: &mut Child, output: &[String]) -> EngineProbeError {
+    let status = tokio::time::timeout(std::time::Duration::from_secs(1), child.wait()).await;
+    match status {
+        Ok(Ok(status)) if illegal_instruction(status) => EngineProbeError::UnsupportedCpu,
+        _ => not_uci(output),
+    }
+}
+
+/// Sends `uci` to the program at `path` and reads its name and options until `uciok`,
+/// giving up after `timeout`. The program is killed afterwards.
+pub async fn uci_handshake(
+    path: PathBuf,
+    timeout: std::time::Duration,
+) -> Result<EngineConfig, EngineProbeError> {
+    let not_executable = |e: Error| EngineProbeError::NotExecutable {
+        reason: e.to_string(),
+    };
+    let mut child = start_engine(path).map_err(not_executable)?;
+    let (mut stdin, mut stdout) = get_handles(&mut child).map_err(not_executable)?;
+
+    let mut output = Vec::new();
+    let config = tokio::time::timeout(timeout, async {
+        // Fails for programs that already exited, which still left their output
+        let _ = stdin.write_all(b"uci\n").await;
+        read_uci_config(&mut stdout, &mut output).await
+    })
+    .await;
+    let error = match config {
+        Ok(Ok(Some(config))) => {
+            let _ = child.kill().await;
+            return Ok(config);
         }
+        Ok(Ok(None)) => exit_error(&mut child, &output).await,
+        Ok(Err(_)) => not_uci(&output),
+        Err(_) if output.is_empty() => EngineProbeError::TimedOut,
+        Err(_) => not_uci(&output),
+    };
+    let _ = child.kill().await;
+    Err(error)
+}
+
+#[tauri::command]
+#[specta::specta]
+pub async fn get_engine_config(path: PathBuf) -> Result<EngineConfig, Error> {
+    Ok(uci_handshake(path, HANDSHAKE_TIMEOUT).await?)
+}
+
+/// Checks that the file at `path` is a UCI engine before it's added, returning its
+/// id and options
+#[tauri::command]
+#[specta::specta]
+pub async fn probe_engine(path: PathBuf) -> Result<EngineConfig, EngineProbeError> {
+    if !path.is_file() {
+        return Err(EngineProbeError::NotExecutable {
+            reason: format!("{} is not a file", path.display()),
+        });
     }
-    println!("{:?}", config);
-    Ok(config)
+    uci_handshake(path, HANDSHAKE_TIMEOUT).await
 }
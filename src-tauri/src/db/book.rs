@@ -0,0 +1,460 @@
+use diesel::prelude::*;
+use log::info;
+use serde::{Deserialize, Serialize};
+use shakmaty::{Color, Position};
+use specta::Type;
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{remove_file, File},
+    io::{BufWriter, Write},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
+use tauri::{AppHandle, Manager};
+use tauri_specta::Event;
+
+use crate::{
+    book::{book_key, encode_move, write_book, BookEntry},
+    db::{
+        encoding::decode_move, filter_games, game_start, get_db_or_create, position_rating,
+        schema::*, ConnectionOptions, GameQuery,
+    },
+    error::Error,
+    AppState,
+};
+
+/// Games replayed between two progress events
+const BOOK_CHUNK_SIZE: i64 = 10_000;
+
+#[derive(Deserialize, Debug, Clone, Copy, Type, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum BookWeighting {
+    /// Games the move was played in
+    Frequency,
+    /// Points the move scored, two for a win and one for a draw like Polyglot's own books
+    Score,
+    /// Performance rating of the side playing the move, in its rated games
+    Performance,
+}
+
+#[derive(Deserialize, Debug, Clone, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct BookBuildOptions {
+    /// Moves played after this many plies are left out
+    pub max_ply: u16,
+    /// Moves played in fewer games are left out
+    pub min_games: u32,
+    pub weighting: BookWeighting,
+    /// Games whose players have a lower average rating are left out
+    pub min_rating: Option<i32>,
+}
+
+#[derive(Serialize, Debug, Clone, Default, PartialEq, Eq, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct BookSummary {
+    pub games: usize,
+    pub positions: usize,
+    pub moves: usize,
+}
+
+#[derive(Clone, Type, Serialize, Event)]
+pub struct BookProgress {
+    pub id: String,
+    pub progress: f64,
+    /// Games read so far
+    pub games: usize,
+    pub finished: bool,
+}
+
+type BookRow = (
+    i32,
+    Option<String>,
+    Vec<u8>,
+    Option<String>,
+    Option<i32>,
+    Option<i32>,
+);
+
+/// Results of a move from a position, for the side playing it
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+struct MoveStats {
+    games: u32,
+    wins: u32,
+    draws: u32,
+    losses: u32,
+    opponent_ratings: i64,
+    rated: u32,
+    rated_wins: u32,
+    rated_losses: u32,
+}
+
+impl MoveStats {
+    fn weight(&self, weighting: BookWeighting) -> u64 {
+        match weighting {
+            BookWeighting::Frequency => u64::from(self.games),
+            BookWeighting::Score => u64::from(self.wins) * 2 + u64::from(self.draws),
+            BookWeighting::Performance if self.rated == 0 => 0,
+            BookWeighting::Performance => {
+                let rated = i64::from(self.rated);
+                let margin = i64::from(self.rated_wins) - i64::from(self.rated_losses);
+                let performance = self.opponent_ratings / rated + 400 * margin / rated;
+                performance.max(1) as u64
+            }
+        }
+    }
+}
+
+type BookStats = HashMap<(u64, u16), MoveStats>;
+
+/// Adds the moves of the first `max_ply` plies of a game to `stats`, once per game for
+/// positions it reached twice like the position index
+fn add_game(
+    stats: &mut BookStats,
+    (_, fen, moves, result, white_elo, black_elo): &BookRow,
+    max_ply: u16,
+) {
+    let Some(mut position) = game_start(fen.as_deref()) else {
+        return;
+    };
+    let winner = match result.as_deref() {
+        Some("1-0") => Some(Some(Color::White)),
+        Some("0-1") => Some(Some(Color::Black)),
+        Some("1/2-1/2") => Some(None),
+        _ => None,
+    };
+    let mut seen = HashSet::new();
+    for &byte in moves.iter().take(max_ply as usize) {
+        let Some(m) = decode_move(byte, &position) else {
+            return;
+        };
+        let key = book_key(&position);
+        if seen.insert(key) {
+            let turn = position.turn();
+            let entry = stats.entry((key, encode_move(&m))).or_default();
+            entry.games += 1;
+            let won = match winner {
+                Some(Some(color)) if color == turn => Some(true),
+                Some(Some(_)) => Some(false),
+                Some(None) => {
+                    entry.draws += 1;
+                    None
+                }
+                None => None,
+            };
+            match won {
+                Some(true) => entry.wins += 1,
+                Some(false) => entry.losses += 1,
+                None => {}
+            }
+            let opponent = match turn {
+                Color::White => black_elo,
+                Color::Black => white_elo,
+            };
+            if let (Some(rating), Some(_)) = (opponent, winner) {
+                entry.opponent_ratings += i64::from(*rating);
+                entry.rated += 1;
+                match won {
+                    Some(true) => entry.rated_wins += 1,
+                    Some(false) => entry.rated_losses += 1,
+                    None => {}
+                }
+            }
+        }
+        position.play_unchecked(&m);
+    }
+}
+
+/// Reads the games of `filter` a chunk at a time and gathers the statistics of their
+/// moves. `progress` gets the games kept so far and the last id read. Fails with
+/// `BookCancelled` once `cancelled` is set.
+fn collect_moves(
+    db: &mut SqliteConnection,
+    filter: &GameQuery,
+    options: &BookBuildOptions,
+    cancelled: &AtomicBool,
+    mut progress: impl FnMut(usize, i32),
+) -> Result<(usize, BookStats), Error> {
+    let mut stats = BookStats::new();
+    let mut last = 0;
+    let mut games = 0;
+    loop {
+        if cancelled.load(Ordering::Relaxed) {
+            return Err(Error::BookCancelled);
+        }
+        let rows: Vec<BookRow> = filter_games(filter)
+            .filter(games::id.gt(last))
+            .order(games::id)
+            .limit(BOOK_CHUNK_SIZE)
+            .select((
+                games::id,
+                games::fen,
+                games::moves,
+                games::result,
+                games::white_elo,
+                games::black_elo,
+            ))
+            .load(db)?;
+        let Some(&(id, ..)) = rows.last() else {
+            break;
+        };
+        last = id;
+        for row in &rows {
+            let rating = position_rating(row.4, row.5);
+            if options
+                .min_rating
+                .is_some_and(|min| rating.map_or(true, |rating| rating < min))
+            {
+                continue;
+            }
+            add_game(&mut stats, row, options.max_ply);
+            games += 1;
+        }
+        progress(games, last);
+    }
+    Ok((games, stats))
+}
+
+/// Entries of the moves played in at least `min_games` games. Weights are scaled down in
+/// positions where the heaviest move doesn't fit in the 16 bits of an entry.
+fn book_entries(stats: BookStats, options: &BookBuildOptions) -> Vec<BookEntry> {
+    let mut positions: HashMap<u64, Vec<(u16, u64)>> = HashMap::new();
+    for ((key, mov), stats) in stats {
+        if stats.games >= options.min_games.max(1) {
+            positions
+                .entry(key)
+                .or_default()
+                .push((mov, stats.weight(options.weighting)));
+        }
+    }
+    let mut entries = Vec::new();
+    for (key, moves) in positions {
+        let heaviest = moves.iter().map(|&(_, weight)| weight).max().unwrap_or(0);
+        let scale = (u16::MAX as f64 / heaviest as f64).min(1.0);
+        for (mov, weight) in moves {
+            let scaled = (weight as f64 * scale).round() as u16;
+            entries.push(BookEntry {
+                key,
+                mov,
+                weight: if weight > 0 { scaled.max(1) } else { 0 },
+                learn: 0,
+            });
+        }
+    }
+    entries
+}
+
+/// Compiles a Polyglot book at `path` from the games of the database `file` matching
+/// `filter`, with the moves of their first `max_ply` plies weighted as `weighting` asks.
+/// Progress is emitted as `BookProgress`, and `cancel_book_build(id)` stops it before
+/// anything is written.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn build_book(
+    id: String,
+    file: PathBuf,
+    filter: GameQuery,
+    options: BookBuildOptions,
+    path: PathBuf,
+    state: tauri::State<'_, AppState>,
+    app: AppHandle,
+) -> Result<BookSummary, Error> {
+    if !app.fs_scope().is_allowed(&path) {
+        return Err(Error::ForbiddenPath);
+    }
+    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+    let last: Option<i32> = filter_games(&filter)
+        .select(diesel::dsl::max(games::id))
+        .first(db)?;
+    let last = last.unwrap_or(0).max(1);
+
+    let start = Instant::now();
+    let cancelled = Arc::new(AtomicBool::new(false));
+    state.book_builds.insert(id.clone(), cancelled.clone());
+    let result = collect_moves(db, &filter, &options, &cancelled, |games, read| {
+        let _ = BookProgress {
+            id: id.clone(),
+            progress: f64::from(read) / f64::from(last) * 100.0,
+            games,
+            finished: false,
+        }
+        .emit_all(&app);
+    });
+    state.book_builds.remove(&id);
+    let (games, stats) = result?;
+
+    let entries = book_entries(stats, &options);
+    let summary = BookSummary {
+        games,
+        positions: entries
+            .iter()
+            .map(|entry| entry.key)
+            .collect::<HashSet<_>>()
+            .len(),
+        moves: entries.len(),
+    };
+    let written = File::create(&path).and_then(|file| {
+        let mut writer = BufWriter::new(file);
+        write_book(&mut writer, entries)?;
+        writer.flush()
+    });
+    if let Err(e) = written {
+        let _ = remove_file(&path);
+        return Err(e.into());
+    }
+    info!(
+        "built a book of {} moves from {} games in {:?}",
+        summary.moves,
+        summary.games,
+        start.elapsed()
+    );
+    BookProgress {
+        id,
+        progress: 100.0,
+        games,
+        finished: true,
+    }
+    .emit_all(&app)?;
+    Ok(summary)
+}
+
+#[tauri::command]
+#[specta::specta]
+pub fn cancel_book_build(id: String, state: tauri::State<'_, AppState>) {
+    if let Some(cancelled) = state.book_builds.get(&id) {
+        cancelled.store(true, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::book::book_moves;
+    use crate::db::test_utils::database;
+    use shakmaty::Chess;
+
+    const GAMES: &str = "[White \"A\"]\n[WhiteElo \"2000\"]\n[BlackElo \"1800\"]\n\
+                         [Result \"1-0\"]\n\n1. e4 e5 2. Nf3 1-0\n\n\
+                         [White \"B\"]\n[WhiteElo \"1200\"]\n[BlackElo \"1200\"]\n\
+                         [Result \"0-1\"]\n\n1. e4 c5 0-1\n\n\
+                         [White \"C\"]\n[Result \"1/2-1/2\"]\n\n1. d4 d5 1/2-1/2";
+
+    fn build(options: &BookBuildOptions) -> (usize, Vec<u8>) {
+        let db = &mut database(GAMES, 0);
+        let (games, stats) = collect_moves(
+            db,
+            &GameQuery::default(),
+            options,
+            &AtomicBool::new(false),
+            |_, _| {},
+        )
+        .unwrap();
+        let mut book = Vec::new();
+        write_book(&mut book, book_entries(stats, options)).unwrap();
+        (games, book)
+    }
+
+    fn start_moves(book: &[u8]) -> Vec<(String, u16)> {
+        book_moves(book, &Chess::default())
+            .into_iter()
+            .map(|m| (m.uci, m.weight))
+            .collect()
+    }
+
+    #[test]
+    fn builds_books_from_games() {
+        let mut options = BookBuildOptions {
+            max_ply: 8,
+            min_games: 1,
+            weighting: BookWeighting::Frequency,
+            min_rating: None,
+        };
+        let (games, book) = build(&options);
+        assert_eq!(games, 3);
+        // e4, d4, then e5, c5, d5 and Nf3 after 1. e4 e5
+        assert_eq!(book.len(), 6 * 16);
+        assert_eq!(
+            start_moves(&book),
+            [("e2e4".to_string(), 2), ("d2d4".to_string(), 1)]
+        );
+
+        options.weighting = BookWeighting::Score;
+        assert_eq!(
+            start_moves(&build(&options).1),
+            [("e2e4".to_string(), 2), ("d2d4".to_string(), 1)]
+        );
+
+        options.weighting = BookWeighting::Performance;
+        // 1800 + 400 and 1200 - 400 over two games, the drawn game is unrated
+        assert_eq!(
+            start_moves(&build(&options).1),
+            [("e2e4".to_string(), 1500), ("d2d4".to_string(), 0)]
+        );
+
+        options.weighting = BookWeighting::Frequency;
+        options.min_games = 2;
+        assert_eq!(build(&options).1.len(), 16);
+        options.min_games = 1;
+        options.max_ply = 1;
+        assert_eq!(build(&options).1.len(), 2 * 16);
+        options.min_rating = Some(1500);
+        let (games, book) = build(&options);
+        assert_eq!(games, 1);
+        assert_eq!(start_moves(&book), [("e2e4".to_string(), 1)]);
+    }
+
+    #[test]
+    fn stops_when_cancelled() {
+        let db = &mut database(GAMES, 0);
+        let options = BookBuildOptions {
+            max_ply: 8,
+            min_games: 1,
+            weighting: BookWeighting::Frequency,
+            min_rating: None,
+        };
+        let result = collect_moves(
+            db,
+            &GameQuery::default(),
+            &options,
+            &AtomicBool::new(true),
+            |_, _| {},
+        );
+        assert!(matches!(result, Err(Error::BookCancelled)));
+    }
+
+    #[test]
+    fn scales_heavy_positions() {
+        let stats: BookStats = [
+            (
+                (1, 10),
+                MoveStats {
+                    games: 200_000,
+                    ..Default::default()
+                },
+            ),
+            (
+                (1, 20),
+                MoveStats {
+                    games: 2,
+                    ..Default::default()
+                },
+            ),
+        ]
+        .into_iter()
+        .collect();
+        let options = BookBuildOptions {
+            max_ply: 8,
+            min_games: 1,
+            weighting: BookWeighting::Frequency,
+            min_rating: None,
+        };
+        let mut entries = book_entries(stats, &options);
+        entries.sort_by_key(|entry| entry.mov);
+        assert_eq!(entries[0].weight, u16::MAX);
+        assert_eq!(entries[1].weight, 1);
+    }
+}
@@ -5,5 +5,7 @@ DROP INDEX IF EXISTS games_result_idx;
 DROP INDEX IF EXISTS games_white_elo_idx;
 DROP INDEX IF EXISTS games_black_elo_idx;
 DROP INDEX IF EXISTS games_plycount_idx;
+DROP INDEX IF EXISTS games_eco_idx;
+DROP INDEX IF EXISTS games_event_idx;
 
 VACUUM;
\ No newline at end of file
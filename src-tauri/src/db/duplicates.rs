@@ -0,0 +1,329 @@
+use diesel::{connection::DefaultLoadingMode, prelude::*};
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+    path::PathBuf,
+};
+
+use crate::{
+    db::{
+        get_db_or_create, ops::NameIds, player_stats::name_words, schema::*, ConnectionOptions,
+        TempGame,
+    },
+    error::Error,
+    AppState,
+};
+
+/// Games deleted by a single statement, below the limit of variables of SQLite
+const DELETE_CHUNK_SIZE: usize = 10_000;
+
+/// What to do when importing a game that is already in the database, or earlier in the
+/// same file
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, Type)]
+#[serde(rename_all = "camelCase")]
+pub enum DuplicatePolicy {
+    #[default]
+    KeepBoth,
+    Skip,
+    /// Deletes the game already there and imports the new one
+    Replace,
+}
+
+/// Numbers of a date up to its first unknown part, so `2024.1.5` matches `2024.01.05`
+/// and `2024.??.??` matches `2024`
+fn date_numbers(date: Option<&str>) -> Vec<u32> {
+    date.unwrap_or_default()
+        .split(['.', '-', '/'])
+        .map_while(|part| part.trim().parse().ok())
+        .collect()
+}
+
+/// Hash of what duplicates of a game share: its players, date, result, start and moves.
+/// Names are compared by their words, so `Carlsen,Magnus` matches `Carlsen, Magnus`.
+fn game_key(
+    white: Option<&str>,
+    black: Option<&str>,
+    date: Option<&str>,
+    result: Option<&str>,
+    fen: Option<&str>,
+    moves: &[u8],
+) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    // Games without players are stored with the `Unknown` player
+    name_words(white.unwrap_or("Unknown")).hash(&mut hasher);
+    name_words(black.unwrap_or("Unknown")).hash(&mut hasher);
+    date_numbers(date).hash(&mut hasher);
+    let result = result.map(str::trim).filter(|result| !result.is_empty());
+    result.unwrap_or("*").hash(&mut hasher);
+    (fen, moves).hash(&mut hasher);
+    hasher.finish()
+}
+
+type GameKeyRow = (
+    i32,
+    Option<String>,
+    Option<String>,
+    Option<String>,
+    Option<String>,
+    Option<String>,
+    Vec<u8>,
+);
+
+/// Keys of the games of a database with their ids, in the order they were imported
+fn game_keys(db: &mut SqliteConnection) -> Result<Vec<(u64, i32)>, Error> {
+    let (white_players, black_players) = diesel::alias!(players as white, players as black);
+    let keys = games::table
+        .inner_join(white_players.on(games::white_id.eq(white_players.field(players::id))))
+        .inner_join(black_players.on(games::black_id.eq(black_players.field(players::id))))
+        .select((
+            games::id,
+            white_players.field(players::name),
+            black_players.field(players::name),
+            games::date,
+            games::result,
+            games::fen,
+            games::moves,
+        ))
+        .order(games::id)
+        .load_iter::<GameKeyRow, DefaultLoadingMode>(db)?
+        .map(|row| {
+            row.map(|(id, white, black, date, result, fen, moves)| {
+                let key = game_key(
+                    white.as_deref(),
+                    black.as_deref(),
+                    date.as_deref(),
+                    result.as_deref(),
+                    fen.as_deref(),
+                    &moves,
+                );
+                (key, id)
+            })
+        })
+        .collect::<Result<_, _>>()?;
+    Ok(keys)
+}
+
+impl TempGame {
+    fn key(&self) -> u64 {
+        game_key(
+            self.white_name.as_deref(),
+            self.black_name.as_deref(),
+            self.date.as_deref(),
+            self.result.as_deref(),
+            self.fen.as_deref(),
+            &self.moves,
+        )
+    }
+}
+
+/// What became of an imported game
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Imported {
+    Added,
+    Skipped,
+    Replaced,
+}
+
+/// Games imported so far by key, to find the duplicates of the next ones
+pub(super) struct SeenGames {
+    policy: DuplicatePolicy,
+    ids: HashMap<u64, i32>,
+    /// Ids of the players, events and sites of the games imported so far
+    names: NameIds,
+}
+
+impl SeenGames {
+    /// Starts from the games of `db`, which a new database doesn't need to read
+    pub(super) fn new(
+        db: &mut SqliteConnection,
+        policy: DuplicatePolicy,
+        db_exists: bool,
+    ) -> Result<SeenGames, Error> {
+        let mut ids = HashMap::new();
+        if policy != DuplicatePolicy::KeepBoth && db_exists {
+            for (key, id) in game_keys(db)? {
+                ids.entry(key).or_insert(id);
+            }
+        }
+        Ok(SeenGames {
+            policy,
+            ids,
+            names: NameIds::default(),
+        })
+    }
+
+    pub(super) fn import(
+        &mut self,
+        db: &mut SqliteConnection,
+        game: &TempGame,
+    ) -> Result<Imported, diesel::result::Error> {
+        if self.policy == DuplicatePolicy::KeepBoth {
+            game.insert_with(db, &mut self.names)?;
+            return Ok(Imported::Added);
+        }
+        let key = game.key();
+        let imported = match (self.ids.get(&key), self.policy) {
+            (Some(_), DuplicatePolicy::Skip) => return Ok(Imported::Skipped),
+            (Some(&id), _) => {
+                diesel::delete(games::table.filter(games::id.eq(id))).execute(db)?;
+                Imported::Replaced
+            }
+            (None, _) => Imported::Added,
+        };
+        let id = game.insert_with(db, &mut self.names)?;
+        self.ids.insert(key, id);
+        Ok(imported)
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Default, PartialEq, Eq, Type)]
+pub struct DuplicateGroups {
+    /// Ids of the games of each group of duplicates, the first imported first
+    pub groups: Vec<Vec<i32>>,
+    /// Games deleted, all but the first of each group when asked for
+    pub deleted: usize,
+}
+
+fn duplicate_groups(db: &mut SqliteConnection) -> Result<Vec<Vec<i32>>, Error> {
+    let mut groups: HashMap<u64, Vec<i32>> = HashMap::new();
+    for (key, id) in game_keys(db)? {
+        groups.entry(key).or_default().push(id);
+    }
+    let mut groups: Vec<Vec<i32>> = groups
+        .into_values()
+        .filter(|group| group.len() > 1)
+        .collect();
+    groups.sort();
+    Ok(groups)
+}
+
+fn delete_duplicates(db: &mut SqliteConnection, groups: &[Vec<i32>]) -> Result<usize, Error> {
+    let extra: Vec<i32> = groups
+        .iter()
+        .flat_map(|group| group[1..].iter().copied())
+        .collect();
+    let deleted = db.transaction::<_, diesel::result::Error, _>(|db| {
+        let mut deleted = 0;
+        for ids in extra.chunks(DELETE_CHUNK_SIZE) {
+            deleted += diesel::delete(games::table.filter(games::id.eq_any(ids))).execute(db)?;
+        }
+        Ok(deleted)
+    })?;
+    Ok(deleted)
+}
+
+/// Finds the games of a database that are duplicates of each other, with the same
+/// fingerprint as the import uses, and optionally deletes all but the first of each group
+#[tauri::command]
+#[specta::specta]
+pub async fn find_duplicates(
+    file: PathBuf,
+    delete: bool,
+    state: tauri::State<'_, AppState>,
+) -> Result<DuplicateGroups, Error> {
+    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+    let groups = duplicate_groups(db)?;
+    let deleted = if delete {
+        delete_duplicates(db, &groups)?
+    } else {
+        0
+    };
+    Ok(DuplicateGroups { groups, deleted })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::db::{test_utils::empty_database, Importer};
+    use pgn_reader::BufferedReader;
+
+    fn parse(pgn: &str) -> Vec<TempGame> {
+        let mut importer = Importer::new(None, 0);
+        BufferedReader::new(pgn.as_bytes())
+            .into_iter(&mut importer)
+            .flatten()
+            .flatten()
+            .collect()
+    }
+
+    const GAMES: &str = "[White \"Carlsen, Magnus\"]\n[Date \"2024.01.05\"]\n[Result \"1-0\"]\n\n\
+                         1. e4 {First} e5 1-0\n\n\
+                         [White \"Carlsen,Magnus\"]\n[Date \"2024.1.5\"]\n[Annotator \"B\"]\n\
+                         [Result \"1-0\"]\n\n1. e4 e5 (1... c5) 1-0\n\n\
+                         [White \"Carlsen, Magnus\"]\n[Date \"2024.01.06\"]\n[Result \"1-0\"]\n\n\
+                         1. e4 e5 1-0\n\n\
+                         [White \"Carlsen, Magnus\"]\n[Date \"2024.01.05\"]\n[Result \"0-1\"]\n\n\
+                         1. e4 e5 0-1";
+
+    #[test]
+    fn duplicates_share_keys() {
+        let keys: Vec<u64> = parse(GAMES).iter().map(TempGame::key).collect();
+        assert_eq!(keys[0], keys[1]);
+        assert_ne!(keys[0], keys[2]);
+        assert_ne!(keys[0], keys[3]);
+        assert_eq!(
+            game_key(None, Some("B"), None, None, None, &[]),
+            game_key(Some("Unknown"), Some("B"), None, Some("*"), None, &[])
+        );
+        assert_eq!(date_numbers(Some("2024.??.??")), [2024]);
+        assert!(date_numbers(Some("????.??.??")).is_empty());
+    }
+
+    #[test]
+    fn follows_the_duplicate_policy() {
+        let games = parse(GAMES);
+        let import = |policy| {
+            let db = &mut empty_database();
+            let mut seen = SeenGames::new(db, policy, false).unwrap();
+            let imported: Vec<Imported> = games
+                .iter()
+                .map(|game| seen.import(db, game).unwrap())
+                .collect();
+            let ids: Vec<i32> = games::table.select(games::id).load(db).unwrap();
+            (imported, ids.len())
+        };
+        use Imported::*;
+        assert_eq!(
+            import(DuplicatePolicy::KeepBoth),
+            (vec![Added, Added, Added, Added], 4)
+        );
+        assert_eq!(
+            import(DuplicatePolicy::Skip),
+            (vec![Added, Skipped, Added, Added], 3)
+        );
+        assert_eq!(
+            import(DuplicatePolicy::Replace),
+            (vec![Added, Replaced, Added, Added], 3)
+        );
+    }
+
+    #[test]
+    fn checks_existing_games() {
+        let db = &mut empty_database();
+        let games = parse(GAMES);
+        games[0].insert_to_db(db).unwrap();
+        let mut seen = SeenGames::new(db, DuplicatePolicy::Replace, true).unwrap();
+        assert_eq!(seen.import(db, &games[1]).unwrap(), Imported::Replaced);
+        let ids: Vec<i32> = games::table.select(games::id).load(db).unwrap();
+        assert_eq!(ids, [2]);
+    }
+
+    #[test]
+    fn finds_and_deletes_duplicates() {
+        let db = &mut empty_database();
+        let games = parse(&format!("{GAMES}\n\n{GAMES}"));
+        for game in &games {
+            game.insert_to_db(db).unwrap();
+        }
+        let groups = duplicate_groups(db).unwrap();
+        assert_eq!(groups, [vec![1, 2, 5, 6], vec![3, 7], vec![4, 8]]);
+
+        assert_eq!(delete_duplicates(db, &groups).unwrap(), 5);
+        let ids: Vec<i32> = games::table.select(games::id).load(db).unwrap();
+        assert_eq!(ids, [1, 3, 4]);
+        assert!(duplicate_groups(db).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,443 @@
+use diesel::prelude::*;
+use pgn_reader::BufferedReader;
+use serde::Deserialize;
+use shakmaty::fen::Fen;
+use std::{collections::HashSet, path::PathBuf};
+
+use crate::{
+    db::{
+        encoding::decode_moves,
+        get_db_or_create, get_pawn_home, has_position_index,
+        models::Game,
+        ops::{create_event, create_player, create_site},
+        position_index::{indexed_plies, reindex_game},
+        schema::*,
+        search::{forget_games, games_by_id},
+        update_counts, ConnectionOptions, Importer, NormalizedGame, POSITION_INDEX_PLIES,
+    },
+    error::Error,
+    AppState,
+};
+
+/// Games deleted by a single statement, below the limit of variables of SQLite
+const DELETE_CHUNK_SIZE: usize = 10_000;
+
+const RESULTS: [&str; 4] = ["1-0", "0-1", "1/2-1/2", "*"];
+
+/// Changes to a stored game, which keeps whatever isn't changed
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase", default)]
+pub struct GameChanges {
+    /// New values of tags by name, like `White` or `Result`. An empty value removes
+    /// the tag, and the players, event and site become `Unknown`.
+    pub tags: Vec<(String, String)>,
+    /// Movetext replacing the moves of the game, from its starting position or the
+    /// one of a new `FEN` tag
+    pub movetext: Option<String>,
+}
+
+fn parse_elo(tag: &str, value: Option<String>) -> Result<Option<i32>, Error> {
+    value
+        .map(|value| {
+            value.parse().map_err(|_| Error::InvalidPgn {
+                reason: format!("{tag} must be a number, not {value}"),
+            })
+        })
+        .transpose()
+}
+
+/// Applies `changes` to the game `id`. Changing the moves or the starting position
+/// replays the game, and its positions are indexed again since results and ratings
+/// are part of the index too.
+fn edit_game(db: &mut SqliteConnection, id: i32, changes: &GameChanges) -> Result<(), Error> {
+    let mut game: Game = games::table.find(id).first(db)?;
+    let mut fen = game.fen.clone();
+    for (tag, value) in &changes.tags {
+        let value = Some(value.trim())
+            .filter(|value| !value.is_empty())
+            .map(str::to_string);
+        match tag.as_str() {
+            "White" => {
+                game.white_id =
+                    value.map_or(Ok(0), |name| create_player(db, &name).map(|p| p.id))?
+            }
+            "Black" => {
+                game.black_id =
+                    value.map_or(Ok(0), |name| create_player(db, &name).map(|p| p.id))?
+            }
+            "Event" => {
+                game.event_id = value.map_or(Ok(0), |name| create_event(db, &name).map(|e| e.id))?
+            }
+            "Site" => {
+                game.site_id = value.map_or(Ok(0), |name| create_site(db, &name).map(|s| s.id))?
+            }
+            "Date" => game.date = value,
+            "UTCTime" => game.time = value,
+            "Round" => game.round = value,
+            "TimeControl" => game.time_control = value,
+            "ECO" => game.eco = value,
+            "WhiteElo" => game.white_elo = parse_elo(tag, value)?,
+            "BlackElo" => game.black_elo = parse_elo(tag, value)?,
+            "Result" => match value {
+                Some(result) if !RESULTS.contains(&result.as_str()) => {
+                    return Err(Error::InvalidPgn {
+                        reason: format!("{result} isn't a result"),
+                    })
+                }
+                result => game.result = result,
+            },
+            "FEN" => fen = value,
+            tag => {
+                return Err(Error::InvalidPgn {
+                    reason: format!("the {tag} tag isn't stored in databases"),
+                })
+            }
+        }
+    }
+
+    if changes.movetext.is_some() || fen != game.fen {
+        let movetext = match &changes.movetext {
+            Some(movetext) => movetext.clone(),
+            None => {
+                let start = match &game.fen {
+                    Some(fen) => Fen::from_ascii(fen.as_bytes())?,
+                    None => Fen::default(),
+                };
+                decode_moves(std::mem::take(&mut game.moves), start)?.join(" ")
+            }
+        };
+        let pgn = match &fen {
+            Some(fen) => format!("[FEN \"{fen}\"]\n\n{movetext}"),
+            None => movetext,
+        };
+        let mut importer = Importer::new(None, 0);
+        let replayed = BufferedReader::new(pgn.as_bytes())
+            .read_game(&mut importer)?
+            .flatten()
+            .ok_or_else(|| Error::InvalidPgn {
+                reason: "illegal move or invalid starting position".to_string(),
+            })?;
+        let (white_material, black_material) = replayed.minimal_material();
+        game.fen = replayed.fen.clone();
+        game.ply_count = Some(replayed.moves.len() as i32);
+        game.white_material = white_material;
+        game.black_material = black_material;
+        game.pawn_home = get_pawn_home(replayed.position.board()) as i32;
+        game.moves = replayed.moves;
+    }
+
+    if has_position_index(db)? {
+        // While the game still has its old date, which the explorer may need to replace
+        diesel::delete(positions::table.filter(positions::game_id.eq(id))).execute(db)?;
+    }
+    diesel::update(games::table.find(id))
+        .set((
+            games::white_id.eq(game.white_id),
+            games::black_id.eq(game.black_id),
+            games::event_id.eq(game.event_id),
+            games::site_id.eq(game.site_id),
+            games::date.eq(&game.date),
+            games::time.eq(&game.time),
+            games::round.eq(&game.round),
+            games::time_control.eq(&game.time_control),
+            games::eco.eq(&game.eco),
+            games::white_elo.eq(game.white_elo),
+            games::black_elo.eq(game.black_elo),
+            games::result.eq(&game.result),
+            games::fen.eq(&game.fen),
+            games::ply_count.eq(game.ply_count),
+            games::white_material.eq(game.white_material),
+            games::black_material.eq(game.black_material),
+            games::pawn_home.eq(game.pawn_home),
+            games::moves.eq(&game.moves),
+        ))
+        .execute(db)?;
+
+    if has_position_index(db)? {
+        let plies = indexed_plies(db)?.unwrap_or(POSITION_INDEX_PLIES);
+        reindex_game(db, id, plies)?;
+    }
+    Ok(())
+}
+
+/// Edits a game, deleting the players, events and sites only it had before
+fn change_game(db: &mut SqliteConnection, id: i32, changes: &GameChanges) -> Result<(), Error> {
+    db.transaction(|db| {
+        let refs = game_refs(db, &[id])?;
+        edit_game(db, id, changes)?;
+        delete_orphans_of(db, refs)?;
+        update_counts(db)?;
+        Ok(())
+    })
+}
+
+/// Players, events and sites among the given ones that no game refers to anymore,
+/// leaving out the `Unknown` rows every database has
+fn delete_orphans(
+    db: &mut SqliteConnection,
+    players: HashSet<i32>,
+    events: HashSet<i32>,
+    sites: HashSet<i32>,
+) -> Result<(), Error> {
+    let players: Vec<i32> = players.into_iter().filter(|&id| id != 0).collect();
+    for ids in players.chunks(DELETE_CHUNK_SIZE) {
+        let mut used: HashSet<i32> = games::table
+            .filter(games::white_id.eq_any(ids))
+            .select(games::white_id)
+            .load(db)?
+            .into_iter()
+            .collect();
+        used.extend(
+            games::table
+                .filter(games::black_id.eq_any(ids))
+                .select(games::black_id)
+                .load::<i32>(db)?,
+        );
+        let orphans: Vec<i32> = ids
+            .iter()
+            .copied()
+            .filter(|id| !used.contains(id))
+            .collect();
+        diesel::delete(players::table.filter(players::id.eq_any(orphans))).execute(db)?;
+    }
+
+    let events: Vec<i32> = events.into_iter().filter(|&id| id != 0).collect();
+    for ids in events.chunks(DELETE_CHUNK_SIZE) {
+        let used: HashSet<i32> = games::table
+            .filter(games::event_id.eq_any(ids))
+            .select(games::event_id)
+            .load(db)?
+            .into_iter()
+            .collect();
+        let orphans: Vec<i32> = ids
+            .iter()
+            .copied()
+            .filter(|id| !used.contains(id))
+            .collect();
+        diesel::delete(events::table.filter(events::id.eq_any(orphans))).execute(db)?;
+    }
+
+    let sites: Vec<i32> = sites.into_iter().filter(|&id| id != 0).collect();
+    for ids in sites.chunks(DELETE_CHUNK_SIZE) {
+        let used: HashSet<i32> = games::table
+            .filter(games::site_id.eq_any(ids))
+            .select(games::site_id)
+            .load(db)?
+            .into_iter()
+            .collect();
+        let orphans: Vec<i32> = ids
+            .iter()
+            .copied()
+            .filter(|id| !used.contains(id))
+            .collect();
+        diesel::delete(sites::table.filter(sites::id.eq_any(orphans))).execute(db)?;
+    }
+    Ok(())
+}
+
+type GameRefs = (i32, i32, i32, i32);
+
+fn game_refs(db: &mut SqliteConnection, ids: &[i32]) -> Result<Vec<GameRefs>, Error> {
+    let mut refs = Vec::new();
+    for ids in ids.chunks(DELETE_CHUNK_SIZE) {
+        refs.extend(
+            games::table
+                .filter(games::id.eq_any(ids))
+                .select((
+                    games::white_id,
+                    games::black_id,
+                    games::event_id,
+                    games::site_id,
+                ))
+                .load::<GameRefs>(db)?,
+        );
+    }
+    Ok(refs)
+}
+
+fn delete_orphans_of(db: &mut SqliteConnection, refs: Vec<GameRefs>) -> Result<(), Error> {
+    let mut players = HashSet::new();
+    let mut events = HashSet::new();
+    let mut sites = HashSet::new();
+    for (white, black, event, site) in refs {
+        players.extend([white, black]);
+        events.insert(event);
+        sites.insert(site);
+    }
+    delete_orphans(db, players, events, sites)
+}
+
+/// Deletes games with their indexed positions, and the players, events and sites only
+/// they had. Returns the number of games deleted.
+pub(super) fn remove_games(db: &mut SqliteConnection, ids: &[i32]) -> Result<usize, Error> {
+    db.transaction(|db| {
+        let refs = game_refs(db, ids)?;
+        let mut deleted = 0;
+        for ids in ids.chunks(DELETE_CHUNK_SIZE) {
+            // Databases from before the explorer have no trigger deleting the positions
+            if has_position_index(db)? {
+                diesel::delete(positions::table.filter(positions::game_id.eq_any(ids)))
+                    .execute(db)?;
+            }
+            deleted += diesel::delete(games::table.filter(games::id.eq_any(ids))).execute(db)?;
+        }
+        delete_orphans_of(db, refs)?;
+        update_counts(db)?;
+        Ok(deleted)
+    })
+}
+
+/// Edits the tags or the moves of a game and returns it as the game list shows it
+#[tauri::command]
+pub async fn update_game(
+    file: PathBuf,
+    id: i32,
+    changes: GameChanges,
+    state: tauri::State<'_, AppState>,
+) -> Result<NormalizedGame, Error> {
+    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+    change_game(db, id, &changes)?;
+    forget_games(&state, &file);
+    games_by_id(db, vec![id])?
+        .remove(&id)
+        .ok_or(Error::GameNotFound { index: id as usize })
+}
+
+#[tauri::command]
+#[specta::specta]
+pub async fn delete_games(
+    file: PathBuf,
+    ids: Vec<i32>,
+    state: tauri::State<'_, AppState>,
+) -> Result<usize, Error> {
+    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+    let removed = remove_games(db, &ids)?;
+    forget_games(&state, &file);
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::db::{position_hash, test_utils::database};
+    use shakmaty::Chess;
+
+    const GAMES: &str = "[White \"Carlsen, Magnus\"]\n[Black \"Giri, Anish\"]\n\
+                         [Event \"Blitz\"]\n[Result \"1-0\"]\n\n1. e4 e5 2. Nf3 1-0\n\n\
+                         [White \"Carlsen, Magnus\"]\n[Black \"Doe, John\"]\n\
+                         [Event \"Blitz\"]\n[Result \"0-1\"]\n\n1. d4 d5 0-1";
+
+    fn player_names(db: &mut SqliteConnection) -> Vec<String> {
+        let mut names: Vec<String> = players::table
+            .select(players::name)
+            .load::<Option<String>>(db)
+            .unwrap()
+            .into_iter()
+            .flatten()
+            .collect();
+        names.sort();
+        names
+    }
+
+    fn indexed(db: &mut SqliteConnection, id: i32) -> Vec<(i64, Option<String>)> {
+        positions::table
+            .filter(positions::game_id.eq(id))
+            .order(positions::ply)
+            .select((positions::hash, positions::result))
+            .load(db)
+            .unwrap()
+    }
+
+    fn tags(tags: &[(&str, &str)]) -> GameChanges {
+        GameChanges {
+            tags: tags
+                .iter()
+                .map(|(tag, value)| (tag.to_string(), value.to_string()))
+                .collect(),
+            movetext: None,
+        }
+    }
+
+    #[test]
+    fn edits_tags() {
+        let db = &mut database(GAMES, 20);
+        edit_game(
+            db,
+            1,
+            &tags(&[
+                ("Black", "Giri, Anish N."),
+                ("Result", "1/2-1/2"),
+                ("WhiteElo", "2850"),
+            ]),
+        )
+        .unwrap();
+        let game: Game = games::table.find(1).first(db).unwrap();
+        assert_eq!(game.result.as_deref(), Some("1/2-1/2"));
+        assert_eq!(game.white_elo, Some(2850));
+        assert!(indexed(db, 1)
+            .iter()
+            .all(|(_, result)| result.as_deref() == Some("1/2-1/2")));
+
+        assert!(edit_game(db, 1, &tags(&[("Result", "2-0")])).is_err());
+        assert!(edit_game(db, 1, &tags(&[("Annotator", "Me")])).is_err());
+        assert!(edit_game(db, 1, &tags(&[("BlackElo", "high")])).is_err());
+    }
+
+    #[test]
+    fn replaces_the_moves() {
+        let db = &mut database(GAMES, 20);
+        let changes = GameChanges {
+            movetext: Some("1. c4 e5 2. Nc3 Nf6 3. g3".to_string()),
+            ..Default::default()
+        };
+        edit_game(db, 1, &changes).unwrap();
+        let game: Game = games::table.find(1).first(db).unwrap();
+        assert_eq!(game.ply_count, Some(5));
+        let hashes: Vec<i64> = indexed(db, 1).into_iter().map(|(hash, _)| hash).collect();
+        assert_eq!(hashes.len(), 6);
+        assert_eq!(hashes[0], position_hash(&Chess::default()));
+
+        let illegal = GameChanges {
+            movetext: Some("1. c4 Ke7 2. Kf3".to_string()),
+            ..Default::default()
+        };
+        assert!(edit_game(db, 1, &illegal).is_err());
+
+        // A new starting position replays the moves from it
+        let from_position = tags(&[("FEN", "4k3/8/8/8/8/8/8/R3K3 w Q - 0 1")]);
+        assert!(edit_game(db, 2, &from_position).is_err());
+        let changes = GameChanges {
+            movetext: Some("1. O-O-O Kf7".to_string()),
+            ..from_position
+        };
+        edit_game(db, 2, &changes).unwrap();
+        let game: Game = games::table.find(2).first(db).unwrap();
+        assert_eq!(game.ply_count, Some(2));
+        assert_eq!(game.white_material, 5);
+    }
+
+    #[test]
+    fn deletes_games_and_their_orphans() {
+        let db = &mut database(GAMES, 20);
+        change_game(db, 2, &tags(&[("Black", "Roe, Jane")])).unwrap();
+        assert_eq!(
+            player_names(db),
+            ["Carlsen, Magnus", "Giri, Anish", "Roe, Jane", "Unknown"]
+        );
+
+        assert_eq!(remove_games(db, &[2]).unwrap(), 1);
+        assert_eq!(
+            player_names(db),
+            ["Carlsen, Magnus", "Giri, Anish", "Unknown"]
+        );
+        assert!(indexed(db, 2).is_empty());
+        let events: i64 = events::table.count().get_result(db).unwrap();
+        assert_eq!(events, 2);
+
+        assert_eq!(remove_games(db, &[1, 3]).unwrap(), 1);
+        assert_eq!(player_names(db), ["Unknown"]);
+        let events: i64 = events::table.count().get_result(db).unwrap();
+        assert_eq!(events, 1);
+    }
+}
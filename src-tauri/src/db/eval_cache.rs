@@ -0,0 +1,273 @@
+use diesel::{connection::SimpleConnection, prelude::*};
+use serde::Serialize;
+use shakmaty::{fen::Epd, Chess, EnPassantMode};
+use specta::Type;
+use tauri::{
+    api::path::{resolve_path, BaseDirectory},
+    AppHandle, Manager,
+};
+
+use crate::{
+    chess::{BestMoves, GoMode},
+    db::{get_db_or_create, schema::evals, ConnectionOptions},
+    error::Error,
+    AppState,
+};
+
+/// Searches that didn't reach this depth aren't cached
+pub const MIN_CACHE_DEPTH: u32 = 16;
+
+const CACHE_FILE: &str = "evals.db3";
+
+const CREATE_SQL: &str = "CREATE TABLE IF NOT EXISTS Evals (
+    FEN TEXT NOT NULL,
+    Engine TEXT NOT NULL,
+    Depth INTEGER NOT NULL,
+    Nodes BIGINT NOT NULL,
+    MultiPV INTEGER NOT NULL,
+    Lines TEXT NOT NULL,
+    PRIMARY KEY (FEN, Engine)
+);";
+
+#[derive(Debug, Clone)]
+pub struct CachedEval {
+    pub depth: u32,
+    pub nodes: u64,
+    pub lines: Vec<BestMoves>,
+}
+
+impl CachedEval {
+    /// Whether the cached search went at least as far as a search with `go_mode` and
+    /// `multipv` lines would. Searches limited by time have no comparable limit.
+    pub fn satisfies(&self, go_mode: &GoMode, multipv: u16) -> bool {
+        if self.lines.len() < multipv as usize {
+            return false;
+        }
+        match go_mode {
+            GoMode::Depth(depth) => self.depth >= *depth,
+            GoMode::Nodes(nodes) => self.nodes >= *nodes as u64,
+            _ => false,
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Type)]
+pub struct EvalCacheStats {
+    pub positions: i64,
+    /// Size of the cache file in bytes
+    pub size: u64,
+}
+
+/// The position without move counters, so transpositions share an entry
+fn cache_key(position: &Chess) -> String {
+    Epd::from_position(position.clone(), EnPassantMode::Legal).to_string()
+}
+
+fn connect(
+    app: &AppHandle,
+) -> Result<
+    diesel::r2d2::PooledConnection<diesel::r2d2::ConnectionManager<diesel::SqliteConnection>>,
+    Error,
+> {
+    let path = resolve_path(
+        &app.config(),
+        app.package_info(),
+        &app.env(),
+        CACHE_FILE,
+        Some(BaseDirectory::AppData),
+    )?;
+    let state = app.state::<AppState>();
+    let mut db = get_db_or_create(&state, path.to_str().unwrap(), ConnectionOptions::default())?;
+    db.batch_execute(CREATE_SQL)?;
+    Ok(db)
+}
+
+fn get_eval(
+    db: &mut SqliteConnection,
+    position: &Chess,
+    engine: &str,
+) -> Result<Option<CachedEval>, Error> {
+    let entry = evals::table
+        .find((cache_key(position), engine))
+        .select((evals::depth, evals::nodes, evals::lines))
+        .first::<(i32, i64, String)>(db)
+        .optional()?;
+    Ok(entry.and_then(|(depth, nodes, lines)| {
+        Some(CachedEval {
+            depth: depth as u32,
+            nodes: nodes as u64,
+            lines: serde_json::from_str(&lines).ok()?,
+        })
+    }))
+}
+
+/// Stores the lines unless they are too shallow or the cache already has a deeper
+/// search of the position. Returns whether the lines were stored.
+fn put_eval(
+    db: &mut SqliteConnection,
+    position: &Chess,
+    engine: &str,
+    lines: &[BestMoves],
+) -> Result<bool, Error> {
+    let Some(first) = lines.first() else {
+        return Ok(false);
+    };
+    if first.depth < MIN_CACHE_DEPTH {
+        return Ok(false);
+    }
+    if let Some(cached) = get_eval(db, position, engine)? {
+        let deeper = first.depth > cached.depth;
+        let wider = first.depth == cached.depth && lines.len() > cached.lines.len();
+        if !deeper && !wider {
+            return Ok(false);
+        }
+    }
+    let nodes = lines
+        .iter()
+        .map(|line| line.nodes as i64)
+        .max()
+        .unwrap_or(0);
+    diesel::replace_into(evals::table)
+        .values((
+            evals::fen.eq(cache_key(position)),
+            evals::engine.eq(engine),
+            evals::depth.eq(first.depth as i32),
+            evals::nodes.eq(nodes),
+            evals::multipv.eq(lines.len() as i32),
+            evals::lines.eq(serde_json::to_string(lines).map_err(std::io::Error::from)?),
+        ))
+        .execute(db)?;
+    Ok(true)
+}
+
+pub fn get_cached_eval(
+    app: &AppHandle,
+    position: &Chess,
+    engine: &str,
+) -> Result<Option<CachedEval>, Error> {
+    get_eval(&mut connect(app)?, position, engine)
+}
+
+pub fn cache_eval(
+    app: &AppHandle,
+    position: &Chess,
+    engine: &str,
+    lines: &[BestMoves],
+) -> Result<bool, Error> {
+    put_eval(&mut connect(app)?, position, engine, lines)
+}
+
+/// Deletes the evaluations of the engine at `engine`, returning how many there were
+pub fn forget_engine_evals(app: &AppHandle, engine: &str) -> Result<usize, Error> {
+    let db = &mut connect(app)?;
+    Ok(diesel::delete(evals::table.filter(evals::engine.eq(engine))).execute(db)?)
+}
+
+#[tauri::command]
+#[specta::specta]
+pub fn get_eval_cache_stats(app: AppHandle) -> Result<EvalCacheStats, Error> {
+    let positions: i64 = evals::table.count().get_result(&mut connect(&app)?)?;
+    let path = resolve_path(
+        &app.config(),
+        app.package_info(),
+        &app.env(),
+        CACHE_FILE,
+        Some(BaseDirectory::AppData),
+    )?;
+    Ok(EvalCacheStats {
+        positions,
+        size: path.metadata()?.len(),
+    })
+}
+
+#[tauri::command]
+#[specta::specta]
+pub fn clear_eval_cache(app: AppHandle) -> Result<(), Error> {
+    let db = &mut connect(&app)?;
+    diesel::delete(evals::table).execute(db)?;
+    db.batch_execute("VACUUM;")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use vampirc_uci::uci::{Score, ScoreValue};
+
+    fn line(depth: u32, cp: i32, uci: &str) -> BestMoves {
+        BestMoves {
+            depth,
+            nodes: depth * 100_000,
+            score: Score {
+                value: ScoreValue::Cp(cp),
+                ..Default::default()
+            },
+            uci_moves: vec![uci.to_string()],
+            ..Default::default()
+        }
+    }
+
+    fn memory_db() -> SqliteConnection {
+        let mut db = SqliteConnection::establish(":memory:").unwrap();
+        db.batch_execute(CREATE_SQL).unwrap();
+        db
+    }
+
+    #[test]
+    fn deeper_results_replace_shallower() {
+        let db = &mut memory_db();
+        let pos = Chess::default();
+        assert!(!put_eval(db, &pos, "stockfish", &[line(10, 20, "e2e4")]).unwrap());
+        assert!(get_eval(db, &pos, "stockfish").unwrap().is_none());
+
+        assert!(put_eval(db, &pos, "stockfish", &[line(20, 20, "e2e4")]).unwrap());
+        assert!(!put_eval(db, &pos, "stockfish", &[line(18, 30, "d2d4")]).unwrap());
+        assert!(put_eval(db, &pos, "stockfish", &[line(24, 25, "d2d4")]).unwrap());
+        assert!(put_eval(
+            db,
+            &pos,
+            "stockfish",
+            &[line(24, 25, "d2d4"), line(24, 20, "e2e4")]
+        )
+        .unwrap());
+
+        let cached = get_eval(db, &pos, "stockfish").unwrap().unwrap();
+        assert_eq!(cached.depth, 24);
+        assert_eq!(cached.lines.len(), 2);
+        assert_eq!(cached.lines[0].uci_moves, vec!["d2d4".to_string()]);
+        assert!(get_eval(db, &pos, "lc0").unwrap().is_none());
+    }
+
+    #[test]
+    fn positions_ignore_move_counters() {
+        let db = &mut memory_db();
+        let a: Chess = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1"
+            .parse::<shakmaty::fen::Fen>()
+            .unwrap()
+            .into_position(shakmaty::CastlingMode::Standard)
+            .unwrap();
+        let b: Chess = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 4 3"
+            .parse::<shakmaty::fen::Fen>()
+            .unwrap()
+            .into_position(shakmaty::CastlingMode::Standard)
+            .unwrap();
+        put_eval(db, &a, "stockfish", &[line(20, 20, "e2e4")]).unwrap();
+        assert!(get_eval(db, &b, "stockfish").unwrap().is_some());
+    }
+
+    #[test]
+    fn cached_evals_satisfy_limits() {
+        let cached = CachedEval {
+            depth: 20,
+            nodes: 2_000_000,
+            lines: vec![line(20, 20, "e2e4")],
+        };
+        assert!(cached.satisfies(&GoMode::Depth(20), 1));
+        assert!(!cached.satisfies(&GoMode::Depth(21), 1));
+        assert!(!cached.satisfies(&GoMode::Depth(20), 2));
+        assert!(cached.satisfies(&GoMode::Nodes(1_000_000), 1));
+        assert!(!cached.satisfies(&GoMode::Time(1000), 1));
+        assert!(!cached.satisfies(&GoMode::Infinite, 1));
+    }
+}
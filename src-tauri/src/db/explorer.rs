@@ -0,0 +1,286 @@
+use diesel::{
+    prelude::*,
+    sql_query,
+    sql_types::{BigInt, Integer, Nullable, Text},
+};
+use log::info;
+use serde::{Deserialize, Serialize};
+use shakmaty::{fen::Fen, san::San, CastlingMode, Chess, Color, Position};
+use std::{path::PathBuf, time::Instant};
+
+use crate::{
+    db::{
+        encoding::decode_move, get_db_or_create, has_table, position_hash, schema::positions,
+        search::games_by_id, ConnectionOptions, NormalizedGame,
+    },
+    error::Error,
+    AppState,
+};
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum ExplorerSort {
+    #[default]
+    Games,
+    Score,
+    Rating,
+    LastPlayed,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ExplorerOptions {
+    /// Moves played in fewer games are left out
+    pub min_games: i32,
+    pub sort: ExplorerSort,
+    /// Example games returned with the moves, the last imported first
+    pub samples: i64,
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ExplorerMove {
+    pub san: String,
+    pub uci: String,
+    pub games: i32,
+    pub white: i32,
+    pub draw: i32,
+    pub black: i32,
+    /// Percentage scored by the side to move
+    pub score: f64,
+    /// Average rating of the players of the rated games
+    pub average_rating: Option<f64>,
+    /// Latest date of the games, as written in the PGN
+    pub last_played: Option<String>,
+}
+
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct OpeningExplorer {
+    /// Games in which a move was played from the position
+    pub games: i32,
+    pub moves: Vec<ExplorerMove>,
+    pub samples: Vec<NormalizedGame>,
+}
+
+/// Row of the `Explorer` table, kept up to date from the position index by triggers
+#[derive(QueryableByName)]
+struct ExplorerRow {
+    #[diesel(sql_type = Integer, column_name = "Move")]
+    move_: i32,
+    #[diesel(sql_type = Integer, column_name = "Games")]
+    games: i32,
+    #[diesel(sql_type = Integer, column_name = "White")]
+    white: i32,
+    #[diesel(sql_type = Integer, column_name = "Draw")]
+    draw: i32,
+    #[diesel(sql_type = Integer, column_name = "Black")]
+    black: i32,
+    #[diesel(sql_type = BigInt, column_name = "RatingSum")]
+    rating_sum: i64,
+    #[diesel(sql_type = Integer, column_name = "Rated")]
+    rated: i32,
+    #[diesel(sql_type = Nullable<Text>, column_name = "LastPlayed")]
+    last_played: Option<String>,
+}
+
+fn explore(
+    db: &mut SqliteConnection,
+    position: &Chess,
+    options: &ExplorerOptions,
+) -> Result<OpeningExplorer, Error> {
+    let hash = position_hash(position);
+    let rows: Vec<ExplorerRow> = sql_query("SELECT * FROM Explorer WHERE Hash = ?")
+        .bind::<BigInt, _>(hash)
+        .load(db)?;
+
+    let games = rows.iter().map(|row| row.games).sum();
+    let mut moves: Vec<ExplorerMove> = rows
+        .into_iter()
+        .filter(|row| row.games >= options.min_games.max(1))
+        .filter_map(|row| {
+            let m = decode_move(row.move_ as u8, position)?;
+            let won = match position.turn() {
+                Color::White => row.white,
+                Color::Black => row.black,
+            };
+            Some(ExplorerMove {
+                san: San::from_move(position, &m).to_string(),
+                uci: m.to_uci(position.castles().mode()).to_string(),
+                games: row.games,
+                white: row.white,
+                draw: row.draw,
+                black: row.black,
+                score: (won as f64 + row.draw as f64 / 2.0) / row.games as f64 * 100.0,
+                average_rating: (row.rated > 0).then(|| row.rating_sum as f64 / row.rated as f64),
+                last_played: row.last_played,
+            })
+        })
+        .collect();
+    moves.sort_by(|a, b| {
+        let order = match options.sort {
+            ExplorerSort::Games => a.games.cmp(&b.games),
+            ExplorerSort::Score => a.score.total_cmp(&b.score),
+            ExplorerSort::Rating => a
+                .average_rating
+                .unwrap_or(f64::MIN)
+                .total_cmp(&b.average_rating.unwrap_or(f64::MIN)),
+            ExplorerSort::LastPlayed => a.last_played.cmp(&b.last_played),
+        };
+        order.then(a.games.cmp(&b.games)).reverse()
+    });
+
+    let ids: Vec<i32> = positions::table
+        .filter(positions::hash.eq(hash))
+        .order(positions::game_id.desc())
+        .limit(options.samples)
+        .select(positions::game_id)
+        .load(db)?;
+    let mut found = games_by_id(db, ids.clone())?;
+    let samples = ids.iter().filter_map(|id| found.remove(id)).collect();
+
+    Ok(OpeningExplorer {
+        games,
+        moves,
+        samples,
+    })
+}
+
+/// Moves played from a position in a database, like an opening explorer. The position
+/// is `fen` after `moves`, given in SAN. Reads the move statistics kept at import, so
+/// it is fast enough to call on every move.
+#[tauri::command]
+pub async fn get_opening_moves(
+    file: PathBuf,
+    fen: String,
+    moves: Option<Vec<String>>,
+    options: ExplorerOptions,
+    state: tauri::State<'_, AppState>,
+) -> Result<OpeningExplorer, Error> {
+    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+    if !has_table(db, "Explorer")? {
+        return Err(Error::MissingPositionIndex);
+    }
+    let mut position: Chess =
+        Fen::from_ascii(fen.as_bytes())?.into_position(CastlingMode::Chess960)?;
+    for san in moves.unwrap_or_default() {
+        let m = san.parse::<San>()?.to_move(&position)?;
+        position.play_unchecked(&m);
+    }
+
+    let start = Instant::now();
+    let explorer = explore(db, &position, &options)?;
+    info!(
+        "explored {} moves in {:?}",
+        explorer.moves.len(),
+        start.elapsed()
+    );
+    Ok(explorer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::db::{
+        migrate,
+        test_utils::{database, import},
+        CREATE_TABLES_SQL, MIGRATIONS,
+    };
+    use diesel::connection::SimpleConnection;
+
+    const GAMES: &str = "[White \"A\"]\n[Date \"2023.05.01\"]\n[WhiteElo \"2000\"]\n\
+                         [BlackElo \"2200\"]\n[Result \"1-0\"]\n\n1. e4 e5 2. Nf3 1-0\n\n\
+                         [White \"B\"]\n[Date \"2024.??.??\"]\n[WhiteElo \"1800\"]\n\
+                         [Result \"0-1\"]\n\n1. e4 c5 0-1\n\n\
+                         [White \"C\"]\n[Result \"1/2-1/2\"]\n\n1. d4 d5 1/2-1/2";
+
+    fn sans(explorer: &OpeningExplorer) -> Vec<&str> {
+        explorer.moves.iter().map(|m| m.san.as_str()).collect()
+    }
+
+    #[test]
+    fn explores_positions() {
+        let db = &mut database(GAMES, 20);
+        let options = ExplorerOptions {
+            samples: 2,
+            ..Default::default()
+        };
+        let start = explore(db, &Chess::default(), &options).unwrap();
+        assert_eq!(start.games, 3);
+        assert_eq!(sans(&start), ["e4", "d4"]);
+        let e4 = &start.moves[0];
+        assert_eq!(e4.uci, "e2e4");
+        assert_eq!((e4.white, e4.draw, e4.black), (1, 0, 1));
+        assert_eq!(e4.score, 50.0);
+        assert_eq!(e4.average_rating, Some(1950.0));
+        assert_eq!(e4.last_played.as_deref(), Some("2024.??.??"));
+        assert_eq!(start.moves[1].last_played, None);
+        let samples: Vec<&str> = start.samples.iter().map(|g| g.white.as_str()).collect();
+        assert_eq!(samples, ["C", "B"]);
+
+        // Scores are from the side to move
+        let mut after_e4 = Chess::default();
+        let e4 = "e4".parse::<San>().unwrap().to_move(&after_e4).unwrap();
+        after_e4.play_unchecked(&e4);
+        let replies = explore(db, &after_e4, &options).unwrap();
+        assert_eq!(replies.games, 2);
+        let c5 = replies.moves.iter().find(|m| m.san == "c5").unwrap();
+        assert_eq!(c5.score, 100.0);
+    }
+
+    #[test]
+    fn sorts_and_filters_moves() {
+        let db = &mut database(GAMES, 20);
+        let position = Chess::default();
+        let explore_with = |db: &mut SqliteConnection, options| explore(db, &position, &options);
+        let by_score = ExplorerOptions {
+            sort: ExplorerSort::Score,
+            ..Default::default()
+        };
+        assert_eq!(sans(&explore_with(db, by_score).unwrap()), ["e4", "d4"]);
+        let by_date = ExplorerOptions {
+            sort: ExplorerSort::LastPlayed,
+            ..Default::default()
+        };
+        assert_eq!(sans(&explore_with(db, by_date).unwrap()), ["e4", "d4"]);
+        let popular = ExplorerOptions {
+            min_games: 2,
+            ..Default::default()
+        };
+        let popular = explore_with(db, popular).unwrap();
+        assert_eq!(sans(&popular), ["e4"]);
+        assert_eq!(popular.games, 3);
+        assert!(popular.samples.is_empty());
+    }
+
+    #[test]
+    fn follows_the_position_index() {
+        // Games imported before the explorer are counted when it is created
+        let db = &mut SqliteConnection::establish(":memory:").unwrap();
+        db.batch_execute(CREATE_TABLES_SQL).unwrap();
+        migrate(db, &MIGRATIONS[..1]).unwrap();
+        import(db, GAMES, 20);
+        migrate(db, MIGRATIONS).unwrap();
+        let start = explore(db, &Chess::default(), &ExplorerOptions::default()).unwrap();
+        assert_eq!(start.moves[0].games, 2);
+        assert_eq!(start.moves[0].last_played.as_deref(), Some("2024.??.??"));
+
+        // and deleted games aren't
+        db.batch_execute(
+            "DELETE FROM Games WHERE WhiteID IN (SELECT ID FROM Players WHERE Name = 'B')",
+        )
+        .unwrap();
+        let start = explore(db, &Chess::default(), &ExplorerOptions::default()).unwrap();
+        assert_eq!(start.games, 2);
+        let e4 = start.moves.iter().find(|m| m.san == "e4").unwrap();
+        assert_eq!((e4.games, e4.white, e4.black), (1, 1, 0));
+        assert_eq!(e4.average_rating, Some(2100.0));
+        assert_eq!(e4.last_played.as_deref(), Some("2023.05.01"));
+        db.batch_execute("DELETE FROM Games").unwrap();
+        assert!(explore(db, &Chess::default(), &ExplorerOptions::default())
+            .unwrap()
+            .moves
+            .is_empty());
+    }
+}
@@ -0,0 +1,78 @@
+CREATE TABLE Explorer (
+    Hash INTEGER NOT NULL,
+    Move INTEGER NOT NULL,
+    Games INTEGER NOT NULL,
+    White INTEGER NOT NULL,
+    Draw INTEGER NOT NULL,
+    Black INTEGER NOT NULL,
+    RatingSum INTEGER NOT NULL,
+    Rated INTEGER NOT NULL,
+    LastPlayed TEXT,
+    PRIMARY KEY (Hash, Move)
+) WITHOUT ROWID;
+
+INSERT INTO Explorer
+SELECT Hash, Move,
+    COUNT(*),
+    COUNT(CASE WHEN Positions.Result = '1-0' THEN 1 END),
+    COUNT(CASE WHEN Positions.Result = '1/2-1/2' THEN 1 END),
+    COUNT(CASE WHEN Positions.Result = '0-1' THEN 1 END),
+    COALESCE(SUM(Rating), 0),
+    COUNT(Rating),
+    -- The date of the game with the latest one, unknown parts of a date coming first
+    CASE WHEN MAX(NULLIF(replace(Games.Date, '?', '0'), '0000.00.00')) IS NOT NULL
+        THEN Games.Date END
+FROM Positions JOIN Games ON Games.ID = Positions.GameID
+WHERE Move IS NOT NULL
+GROUP BY Hash, Move;
+
+CREATE TRIGGER explorer_insert AFTER INSERT ON Positions WHEN NEW.Move IS NOT NULL
+BEGIN
+    INSERT OR IGNORE INTO Explorer VALUES (NEW.Hash, NEW.Move, 0, 0, 0, 0, 0, 0, NULL);
+    UPDATE Explorer SET
+        Games = Games + 1,
+        White = White + (NEW.Result IS '1-0'),
+        Draw = Draw + (NEW.Result IS '1/2-1/2'),
+        Black = Black + (NEW.Result IS '0-1'),
+        RatingSum = RatingSum + COALESCE(NEW.Rating, 0),
+        Rated = Rated + (NEW.Rating IS NOT NULL),
+        LastPlayed = CASE
+            WHEN NULLIF(replace((SELECT Date FROM Games WHERE ID = NEW.GameID), '?', '0'), '0000.00.00')
+                > COALESCE(replace(LastPlayed, '?', '0'), '')
+            THEN (SELECT Date FROM Games WHERE ID = NEW.GameID)
+            ELSE LastPlayed
+        END
+    WHERE Hash = NEW.Hash AND Move = NEW.Move;
+END;
+
+CREATE TRIGGER explorer_delete AFTER DELETE ON Positions WHEN OLD.Move IS NOT NULL
+BEGIN
+    UPDATE Explorer SET
+        Games = Games - 1,
+        White = White - (OLD.Result IS '1-0'),
+        Draw = Draw - (OLD.Result IS '1/2-1/2'),
+        Black = Black - (OLD.Result IS '0-1'),
+        RatingSum = RatingSum - COALESCE(OLD.Rating, 0),
+        Rated = Rated - (OLD.Rating IS NOT NULL)
+    WHERE Hash = OLD.Hash AND Move = OLD.Move;
+    DELETE FROM Explorer WHERE Hash = OLD.Hash AND Move = OLD.Move AND Games <= 0;
+    -- The latest date is looked for again when it was the one of the deleted game, or
+    -- when that game is already gone
+    UPDATE Explorer SET
+        LastPlayed = (
+            SELECT Games.Date
+            FROM Positions JOIN Games ON Games.ID = Positions.GameID
+            WHERE Positions.Hash = OLD.Hash AND Positions.Move = OLD.Move
+                AND NULLIF(replace(Games.Date, '?', '0'), '0000.00.00') IS NOT NULL
+            ORDER BY replace(Games.Date, '?', '0') DESC
+            LIMIT 1
+        )
+    WHERE Hash = OLD.Hash AND Move = OLD.Move AND LastPlayed IS NOT NULL
+        AND COALESCE((SELECT Date FROM Games WHERE ID = OLD.GameID), LastPlayed) = LastPlayed;
+END;
+
+-- Before the game is gone, so the explorer can tell whether it was the latest one
+CREATE TRIGGER positions_game_delete BEFORE DELETE ON Games
+BEGIN
+    DELETE FROM Positions WHERE GameID = OLD.ID;
+END;
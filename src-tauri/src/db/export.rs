@@ -0,0 +1,311 @@
+use diesel::prelude::*;
+use log::info;
+use pgn_reader::SanPlus;
+use serde::{Deserialize, Serialize};
+use shakmaty::{fen::Fen, EnPassantMode, Position};
+use specta::Type;
+use std::{
+    collections::HashMap,
+    fs::{remove_file, File},
+    io::{BufWriter, Write},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
+use tauri::{AppHandle, Manager};
+use tauri_specta::Event;
+
+use crate::{
+    db::{
+        encoding::decode_move,
+        filter_games, get_db_or_create,
+        models::{Event as DbEvent, Game, Player, Site},
+        schema::*,
+        ConnectionOptions, GameQuery,
+    },
+    error::Error,
+    pgn::{start_position, write_tree, GameTree, Newline, PgnHeader, PgnNode, WriteOptions},
+    AppState,
+};
+
+/// Games read from the database at once, between two progress events
+const EXPORT_CHUNK_SIZE: usize = 1000;
+
+/// Games to export, picked one by one or all the ones of a query of the game list
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub enum ExportSelection {
+    /// Written in the order given
+    Ids(Vec<i32>),
+    /// Written in the order they were imported, ignoring the sorting and paging
+    Query(GameQuery),
+}
+
+#[derive(Serialize, Debug, Clone, Default, PartialEq, Eq, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportSummary {
+    pub games: usize,
+    /// Whether the export was cancelled, the file then only has the games written so far
+    pub cancelled: bool,
+}
+
+#[derive(Clone, Type, Serialize, Event)]
+pub struct ExportProgress {
+    pub id: String,
+    pub progress: f64,
+    /// Games written so far
+    pub games: usize,
+    pub finished: bool,
+}
+
+type ExportRow = (Game, Player, Player, DbEvent, Site);
+
+/// Game tree of a stored game. Databases keep neither comments nor variations, so the
+/// tree only has the main line.
+fn game_tree((game, white, black, event, site): ExportRow) -> Result<GameTree, Error> {
+    let outcome = game.result.clone().unwrap_or_else(|| "*".to_string());
+    let tags = [
+        ("Event", event.name),
+        ("Site", site.name),
+        ("Date", game.date),
+        ("Round", game.round),
+        ("White", white.name),
+        ("Black", black.name),
+        ("Result", Some(outcome.clone())),
+        ("UTCTime", game.time),
+        ("TimeControl", game.time_control),
+        ("ECO", game.eco),
+        ("WhiteElo", game.white_elo.map(|elo| elo.to_string())),
+        ("BlackElo", game.black_elo.map(|elo| elo.to_string())),
+        ("PlyCount", game.ply_count.map(|count| count.to_string())),
+        ("FEN", game.fen),
+    ];
+    let headers: Vec<PgnHeader> = tags
+        .into_iter()
+        .filter_map(|(tag, value)| {
+            value.map(|value| PgnHeader {
+                tag: tag.to_string(),
+                value,
+            })
+        })
+        .collect();
+
+    let mut position = start_position(&headers).map_err(|reason| Error::InvalidFen { reason })?;
+    let fen = Fen::from_position(position.clone(), EnPassantMode::Legal).to_string();
+    let chess960 = position.castles().mode().is_chess960();
+    let mut moves = Vec::with_capacity(game.moves.len());
+    for byte in &game.moves {
+        let Some(m) = decode_move(*byte, &position) else {
+            break;
+        };
+        let uci = m.to_uci(position.castles().mode()).to_string();
+        let san = SanPlus::from_move_and_play_unchecked(&mut position, &m).to_string();
+        moves.push(PgnNode {
+            san,
+            uci,
+            fen: Fen::from_position(position.clone(), EnPassantMode::Legal).to_string(),
+            ..Default::default()
+        });
+    }
+
+    Ok(GameTree {
+        headers,
+        fen,
+        comments: Vec::new(),
+        shapes: Vec::new(),
+        moves,
+        outcome,
+        chess960,
+    })
+}
+
+/// Writes the games `ids` as PGN, reading them a chunk at a time so the output is
+/// never held in memory
+fn write_games(
+    db: &mut SqliteConnection,
+    ids: &[i32],
+    writer: &mut impl Write,
+    options: WriteOptions,
+    cancelled: &AtomicBool,
+    mut progress: impl FnMut(usize),
+) -> Result<ExportSummary, Error> {
+    let separator = match options.newline {
+        Newline::Lf => "\n",
+        Newline::Crlf => "\r\n",
+    };
+    let (white_players, black_players) = diesel::alias!(players as white, players as black);
+    let mut summary = ExportSummary::default();
+    for chunk in ids.chunks(EXPORT_CHUNK_SIZE) {
+        let rows: Vec<ExportRow> = games::table
+            .inner_join(white_players.on(games::white_id.eq(white_players.field(players::id))))
+            .inner_join(black_players.on(games::black_id.eq(black_players.field(players::id))))
+            .inner_join(events::table.on(games::event_id.eq(events::id)))
+            .inner_join(sites::table.on(games::site_id.eq(sites::id)))
+            .filter(games::id.eq_any(chunk))
+            .load(db)?;
+        let mut rows: HashMap<i32, ExportRow> =
+            rows.into_iter().map(|row| (row.0.id, row)).collect();
+        for id in chunk {
+            if cancelled.load(Ordering::Relaxed) {
+                summary.cancelled = true;
+                return Ok(summary);
+            }
+            let Some(row) = rows.remove(id) else {
+                continue;
+            };
+            if summary.games > 0 {
+                writer.write_all(separator.as_bytes())?;
+            }
+            writer.write_all(write_tree(&game_tree(row)?, options).as_bytes())?;
+            summary.games += 1;
+        }
+        progress(summary.games);
+    }
+    Ok(summary)
+}
+
+/// Exports games of a database to a PGN file, with the same options as a single game,
+/// though databases only keep the main line. Can be cancelled with `cancel_export(id)`,
+/// after which the file keeps the games written so far if `keep_partial`, and is
+/// deleted otherwise.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn export_games(
+    id: String,
+    file: PathBuf,
+    selection: ExportSelection,
+    path: PathBuf,
+    options: WriteOptions,
+    keep_partial: bool,
+    state: tauri::State<'_, AppState>,
+    app: AppHandle,
+) -> Result<ExportSummary, Error> {
+    if !app.fs_scope().is_allowed(&path) {
+        return Err(Error::ForbiddenPath);
+    }
+    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+    let ids: Vec<i32> = match selection {
+        ExportSelection::Ids(ids) => ids,
+        ExportSelection::Query(query) => filter_games(&query)
+            .select(games::id)
+            .order(games::id)
+            .load(db)?,
+    };
+    let total = ids.len().max(1);
+
+    let start = Instant::now();
+    let mut writer = BufWriter::new(File::create(&path)?);
+    let cancelled = Arc::new(AtomicBool::new(false));
+    state.exports.insert(id.clone(), cancelled.clone());
+    let result = write_games(db, &ids, &mut writer, options, &cancelled, |games| {
+        let _ = ExportProgress {
+            id: id.clone(),
+            progress: games as f64 / total as f64 * 100.0,
+            games,
+            finished: false,
+        }
+        .emit_all(&app);
+    })
+    .and_then(|summary| {
+        writer.flush()?;
+        Ok(summary)
+    });
+    state.exports.remove(&id);
+    drop(writer);
+
+    let keep = match &result {
+        Ok(summary) => !summary.cancelled || keep_partial,
+        Err(_) => keep_partial,
+    };
+    if !keep {
+        remove_file(&path)?;
+    }
+    let summary = result?;
+    info!("exported {} games in {:?}", summary.games, start.elapsed());
+    ExportProgress {
+        id,
+        progress: 100.0,
+        games: summary.games,
+        finished: true,
+    }
+    .emit_all(&app)?;
+    Ok(summary)
+}
+
+#[tauri::command]
+#[specta::specta]
+pub fn cancel_export(id: String, state: tauri::State<'_, AppState>) {
+    if let Some(cancelled) = state.exports.get(&id) {
+        cancelled.store(true, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::db::test_utils::database;
+    use crate::pgn::parse_game;
+
+    const GAMES: &str = "[Event \"Open\"]\n[White \"A\"]\n[Black \"B\"]\n[WhiteElo \"2100\"]\n\
+                         [Result \"1-0\"]\n\n1. e4 e5 2. Qh5 Nc6 3. Bc4 Nf6 4. Qxf7# 1-0\n\n\
+                         [White \"C\"]\n[FEN \"4k3/8/8/8/8/8/4P3/4K3 w - - 0 1\"]\n\n\
+                         1. e4 Kd7 *\n\n\
+                         [White \"D\"]\n[Result \"1/2-1/2\"]\n\n1. d4 d5 1/2-1/2";
+
+    fn export(db: &mut SqliteConnection, ids: &[i32], cancelled: bool) -> (String, usize) {
+        let mut pgn = Vec::new();
+        let summary = write_games(
+            db,
+            ids,
+            &mut pgn,
+            WriteOptions::default(),
+            &AtomicBool::new(cancelled),
+            |_| {},
+        )
+        .unwrap();
+        assert_eq!(summary.cancelled, cancelled);
+        (String::from_utf8(pgn).unwrap(), summary.games)
+    }
+
+    #[test]
+    fn exports_stored_games() {
+        let db = &mut database(GAMES, 0);
+        let (pgn, games) = export(db, &[3, 1, 2], false);
+        assert_eq!(games, 3);
+        let games: Vec<&str> = pgn.split("\n\n[Event").collect();
+        assert_eq!(games.len(), 3);
+        assert!(games[0].contains("[White \"D\"]"));
+        assert!(games[0].ends_with("1. d4 d5 1/2-1/2"));
+
+        let first = parse_game(&format!("[Event{}", games[1])).unwrap();
+        assert_eq!(first.header("Event"), Some("Open"));
+        assert_eq!(first.header("WhiteElo"), Some("2100"));
+        assert_eq!(first.moves.len(), 7);
+        assert_eq!(first.moves[6].san, "Qxf7#");
+        assert_eq!(first.outcome, "1-0");
+
+        assert!(games[2].contains("[FEN \"4k3/8/8/8/8/8/4P3/4K3 w - - 0 1\"]"));
+        assert!(games[2].contains("1. e4 Kd7 *"));
+    }
+
+    #[test]
+    fn exports_the_games_of_a_query() {
+        let db = &mut database(GAMES, 0);
+        let query = GameQuery {
+            outcome: Some("1-0".to_string()),
+            ..Default::default()
+        };
+        let ids: Vec<i32> = filter_games(&query).select(games::id).load(db).unwrap();
+        assert_eq!(ids, [1]);
+    }
+
+    #[test]
+    fn stops_when_cancelled() {
+        let db = &mut database(GAMES, 0);
+        assert_eq!(export(db, &[1, 2, 3], true), (String::new(), 0));
+    }
+}
@@ -0,0 +1,375 @@
+use diesel::{connection::SimpleConnection, prelude::*};
+use log::info;
+use serde::Serialize;
+use specta::Type;
+use std::{
+    collections::HashMap,
+    fs::remove_file,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
+use tauri_specta::Event;
+
+use crate::{
+    db::{
+        create_database,
+        duplicates::{Imported, SeenGames},
+        encoding::decode_move,
+        forget_games, game_start, get_db_or_create, has_position_index, index_position, migrate,
+        models::{Event as DbEvent, Game, Player, Site},
+        player_stats::name_words,
+        position_index::{indexed_plies, set_indexed_plies},
+        schema::*,
+        update_counts, ConnectionOptions, DuplicatePolicy, JournalMode, MaterialColor, TempGame,
+        INDEXES_SQL, MIGRATIONS, POSITION_INDEX_PLIES,
+    },
+    error::Error,
+    AppState,
+};
+
+/// Games read from a source at once, between two progress events
+const MERGE_CHUNK_SIZE: i64 = 1000;
+
+#[derive(Serialize, Debug, Clone, Default, PartialEq, Eq, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct SourceSummary {
+    pub source: PathBuf,
+    /// Games added to the merged database
+    pub merged: usize,
+    /// Games left out as duplicates of ones merged before, from this source or another
+    pub duplicates: usize,
+}
+
+#[derive(Serialize, Debug, Clone, Default, PartialEq, Eq, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct MergeSummary {
+    pub sources: Vec<SourceSummary>,
+    pub total_games: usize,
+    pub elapsed_ms: u64,
+}
+
+#[derive(Clone, Type, Serialize, Event)]
+pub struct MergeProgress {
+    /// Path of the merged database
+    pub id: String,
+    pub source: String,
+    /// Percent of the games of the source read so far
+    pub progress: f64,
+    pub games: usize,
+}
+
+type MergeRow = (Game, Player, Player, DbEvent, Site);
+
+/// Names of the players merged so far by their words, so `Carlsen,Magnus` and
+/// `Magnus Carlsen` become the first one of them
+#[derive(Default)]
+struct PlayerNames(HashMap<Vec<String>, String>);
+
+impl PlayerNames {
+    fn merge(&mut self, id: i32, name: Option<String>) -> Option<String> {
+        // Games without players refer to `Unknown`, which every database has
+        let name = name.filter(|_| id != 0)?;
+        let words = name_words(&name);
+        if words.is_empty() {
+            return Some(name);
+        }
+        Some(self.0.entry(words).or_insert(name).clone())
+    }
+}
+
+/// Stored game as the import would have read it, replayed to index its first `plies`
+/// plies
+fn stored_game(
+    (game, white, black, event, site): MergeRow,
+    names: &mut PlayerNames,
+    plies: u16,
+) -> TempGame {
+    let mut position = game_start(game.fen.as_deref()).unwrap_or_default();
+    let mut positions = Vec::new();
+    let mut replayed = 0;
+    for &byte in &game.moves {
+        index_position(&mut positions, &position, replayed, Some(byte), plies);
+        let Some(m) = decode_move(byte, &position) else {
+            break;
+        };
+        position.play_unchecked(&m);
+        replayed += 1;
+    }
+    if replayed == game.moves.len() {
+        index_position(&mut positions, &position, replayed, None, plies);
+    }
+
+    TempGame {
+        event_name: event.name.filter(|_| event.id != 0),
+        site_name: site.name.filter(|_| site.id != 0),
+        date: game.date,
+        time: game.time,
+        round: game.round,
+        white_name: names.merge(white.id, white.name),
+        white_elo: game.white_elo,
+        black_name: names.merge(black.id, black.name),
+        black_elo: game.black_elo,
+        result: game.result,
+        time_control: game.time_control,
+        eco: game.eco,
+        fen: game.fen,
+        moves: game.moves,
+        position,
+        // Stored already as the least material of the game
+        material_count: MaterialColor {
+            white: game.white_material as u8,
+            black: game.black_material as u8,
+        },
+        positions,
+    }
+}
+
+/// Adds the games of every source to `db`, leaving out the duplicates of the games
+/// merged before. Meant to run in a transaction, which `MergeCancelled` rolls back.
+fn merge_games(
+    db: &mut SqliteConnection,
+    sources: &mut [&mut SqliteConnection],
+    plies: u16,
+    cancelled: &AtomicBool,
+    mut progress: impl FnMut(usize, usize, usize),
+) -> Result<Vec<(usize, usize)>, Error> {
+    let (white_players, black_players) = diesel::alias!(players as white, players as black);
+    let mut seen = SeenGames::new(db, DuplicatePolicy::Skip, false)?;
+    let mut names = PlayerNames::default();
+    let mut counts = Vec::with_capacity(sources.len());
+    for (index, source) in sources.iter_mut().enumerate() {
+        let source = &mut **source;
+        let total: i64 = games::table.count().get_result(source)?;
+        let (mut merged, mut duplicates) = (0, 0);
+        let mut last = 0;
+        loop {
+            let rows: Vec<MergeRow> = games::table
+                .inner_join(white_players.on(games::white_id.eq(white_players.field(players::id))))
+                .inner_join(black_players.on(games::black_id.eq(black_players.field(players::id))))
+                .inner_join(events::table.on(games::event_id.eq(events::id)))
+                .inner_join(sites::table.on(games::site_id.eq(sites::id)))
+                .filter(games::id.gt(last))
+                .order(games::id)
+                .limit(MERGE_CHUNK_SIZE)
+                .load(source)?;
+            let Some(row) = rows.last() else {
+                break;
+            };
+            last = row.0.id;
+            for row in rows {
+                if cancelled.load(Ordering::Relaxed) {
+                    return Err(Error::MergeCancelled);
+                }
+                match seen.import(db, &stored_game(row, &mut names, plies))? {
+                    Imported::Skipped => duplicates += 1,
+                    _ => merged += 1,
+                }
+            }
+            progress(index, merged + duplicates, total as usize);
+        }
+        counts.push((merged, duplicates));
+    }
+    db.batch_execute(INDEXES_SQL)?;
+    Ok(counts)
+}
+
+/// Creates a database at `target` with the games of all the `sources`, one copy of the
+/// games found in several of them, and one player for the names with the same words.
+/// Progress is emitted as `MergeProgress` for each source in turn. The merge is a single
+/// transaction, so when it fails or is cancelled with `cancel_merge(target)` no database
+/// is left at `target`.
+#[tauri::command]
+#[specta::specta]
+pub async fn merge_databases(
+    sources: Vec<PathBuf>,
+    target: PathBuf,
+    title: String,
+    description: Option<String>,
+    state: tauri::State<'_, AppState>,
+    app: tauri::AppHandle,
+) -> Result<MergeSummary, Error> {
+    if target.exists() {
+        return Err(Error::DatabaseExists {
+            path: target.to_string_lossy().into_owned(),
+        });
+    }
+    let start = Instant::now();
+
+    let mut source_dbs = Vec::with_capacity(sources.len());
+    let mut plies = None;
+    for source in &sources {
+        // Opening a missing database would create an empty one
+        source.metadata()?;
+        let mut db = get_db_or_create(
+            &state,
+            source.to_str().unwrap(),
+            ConnectionOptions::default(),
+        )?;
+        // The merged index goes as deep as the deepest one of the sources
+        if has_position_index(&mut db)? {
+            plies = plies.max(indexed_plies(&mut db)?);
+        }
+        source_dbs.push(db);
+    }
+    let plies = plies.unwrap_or(POSITION_INDEX_PLIES);
+
+    let id = target.to_string_lossy().into_owned();
+    let mut db = get_db_or_create(
+        &state,
+        &id,
+        ConnectionOptions {
+            enable_foreign_keys: false,
+            busy_timeout: None,
+            journal_mode: JournalMode::Off,
+        },
+    )?;
+    let cancelled = Arc::new(AtomicBool::new(false));
+    state.merges.insert(target.clone(), cancelled.clone());
+    let result = db.transaction::<_, Error, _>(|db| {
+        create_database(db, &title, &description.unwrap_or_default())?;
+        migrate(db, MIGRATIONS)?;
+        set_indexed_plies(db, plies)?;
+        let mut source_dbs: Vec<&mut SqliteConnection> =
+            source_dbs.iter_mut().map(|source| &mut **source).collect();
+        let counts = merge_games(
+            db,
+            &mut source_dbs,
+            plies,
+            &cancelled,
+            |index, games, total| {
+                let _ = MergeProgress {
+                    id: id.clone(),
+                    source: sources[index].to_string_lossy().into_owned(),
+                    progress: games as f64 / total.max(1) as f64 * 100.0,
+                    games,
+                }
+                .emit_all(&app);
+            },
+        )?;
+        let total_games = update_counts(db)?;
+        Ok((counts, total_games))
+    });
+    state.merges.remove(&target);
+
+    if result.is_err() {
+        // Without a journal nothing was rolled back, so none of the file is kept
+        drop(db);
+        state.connection_pool.remove(&id);
+        let _ = remove_file(&target);
+    }
+    let (counts, total_games) = result?;
+    forget_games(&state, &target);
+    info!("merged {total_games} games in {:?}", start.elapsed());
+    Ok(MergeSummary {
+        sources: sources
+            .into_iter()
+            .zip(counts)
+            .map(|(source, (merged, duplicates))| SourceSummary {
+                source,
+                merged,
+                duplicates,
+            })
+            .collect(),
+        total_games,
+        elapsed_ms: start.elapsed().as_millis() as u64,
+    })
+}
+
+/// Stops the merge into `target`, which is then deleted
+#[tauri::command]
+#[specta::specta]
+pub fn cancel_merge(target: PathBuf, state: tauri::State<'_, AppState>) {
+    if let Some(cancelled) = state.merges.get(&target) {
+        cancelled.store(true, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::db::test_utils::database;
+
+    fn positions(db: &mut SqliteConnection) -> Vec<(i64, i32, Option<i32>)> {
+        positions::table
+            .select((positions::hash, positions::ply, positions::move_))
+            .order((positions::game_id, positions::ply))
+            .load(db)
+            .unwrap()
+    }
+
+    const BLITZ: &str = "[White \"Carlsen, Magnus\"]\n[Black \"Giri, Anish\"]\n\
+                         [Event \"Titled Arena\"]\n[Result \"1-0\"]\n\n1. e4 e5 2. Nf3 1-0\n\n\
+                         [White \"Giri, Anish\"]\n[Result \"*\"]\n\n1. d4 *";
+
+    const OTB: &str = "[White \"Magnus Carlsen\"]\n[Black \"Giri, Anish\"]\n[Result \"1-0\"]\n\n\
+                       1. e4 e5 2. Nf3 1-0\n\n\
+                       [White \"Magnus Carlsen\"]\n[Black \"Anand, Viswanathan\"]\n\
+                       [Result \"1/2-1/2\"]\n\n1. c4 1/2-1/2";
+
+    #[test]
+    fn merges_games_and_players() {
+        let target = &mut database("", 0);
+        let blitz = &mut database(BLITZ, 4);
+        let otb = &mut database(OTB, 4);
+        let mut events = Vec::new();
+        let counts = merge_games(
+            target,
+            &mut [blitz, otb],
+            4,
+            &AtomicBool::new(false),
+            |source, games, total| events.push((source, games, total)),
+        )
+        .unwrap();
+        assert_eq!(counts, [(2, 0), (1, 1)]);
+        assert_eq!(events, [(0, 2, 2), (1, 2, 2)]);
+
+        let mut names: Vec<Option<String>> =
+            players::table.select(players::name).load(target).unwrap();
+        names.sort();
+        let names: Vec<&str> = names.iter().flatten().map(String::as_str).collect();
+        assert_eq!(
+            names,
+            [
+                "Anand, Viswanathan",
+                "Carlsen, Magnus",
+                "Giri, Anish",
+                "Unknown"
+            ]
+        );
+        let events: Vec<Option<String>> = events::table
+            .select(events::name)
+            .order(events::id)
+            .load(target)
+            .unwrap();
+        assert_eq!(events.len(), 2);
+
+        // The index is the one the games would have had if imported into the target
+        let mut imported = database(
+            &format!(
+                "{BLITZ}\n\n[White \"Magnus Carlsen\"]\n[Black \"Anand, Viswanathan\"]\n\
+                 [Result \"1/2-1/2\"]\n\n1. c4 1/2-1/2"
+            ),
+            4,
+        );
+        assert_eq!(positions(target), positions(&mut imported));
+    }
+
+    #[test]
+    fn stops_when_cancelled() {
+        let target = &mut database("", 0);
+        let blitz = &mut database(BLITZ, 4);
+        let result = merge_games(
+            target,
+            &mut [blitz],
+            4,
+            &AtomicBool::new(true),
+            |_, _, _| {},
+        );
+        assert!(matches!(result, Err(Error::MergeCancelled)));
+    }
+}
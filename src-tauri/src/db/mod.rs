@@ -1,10 +1,21 @@
+mod book;
+mod duplicates;
+mod edit;
 mod encoding;
+mod eval_cache;
+mod explorer;
+mod export;
+mod merge;
 mod models;
 mod ops;
+mod pattern;
+mod player_stats;
+mod position_index;
 mod schema;
 mod search;
 
 use crate::{
+    batch::new_job_id,
     db::{
         encoding::{decode_move, decode_moves},
         models::*,
@@ -12,7 +23,8 @@ use crate::{
         schema::*,
     },
     error::Error,
-    opening::get_opening_from_setup,
+    opening::{find_opening, get_opening_from_setup, opening_ecos, MAX_BOOK_PLIES},
+    pgn::{is_chess960, setup_position},
     AppState,
 };
 use chrono::{NaiveDate, NaiveTime};
@@ -24,19 +36,27 @@ use diesel::{
     r2d2::{ConnectionManager, Pool},
     sql_query,
     sql_types::Text,
+    sqlite::Sqlite,
 };
+use memmap2::Mmap;
 use pgn_reader::{BufferedReader, RawHeader, SanPlus, Skip, Visitor};
 use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
 use shakmaty::{
-    fen::Fen, Board, ByColor, Chess, EnPassantMode, FromSetup, Piece, Position, PositionError,
+    fen::Fen,
+    zobrist::{Zobrist64, ZobristHash},
+    Board, ByColor, ByRole, Chess, EnPassantMode, Piece, Position, PositionError,
 };
 use specta::Type;
-use std::io::{BufWriter, Write};
+use std::io::{BufWriter, Read, Write};
 use std::{
+    collections::HashSet,
     fs::{remove_file, File, OpenOptions},
-    path::PathBuf,
-    sync::atomic::{AtomicI32, AtomicUsize, Ordering},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicI32, AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
     time::{Duration, Instant},
 };
 use tauri::State;
@@ -46,19 +66,59 @@ use tauri::{
 };
 use tauri_specta::Event as _;
 
+use self::duplicates::{Imported, SeenGames};
 use self::encoding::encode_move;
 
+pub use self::book::{build_book, cancel_book_build, BookProgress};
+pub use self::duplicates::{find_duplicates, DuplicatePolicy};
+pub use self::edit::{delete_games, update_game};
+pub use self::eval_cache::{
+    cache_eval, clear_eval_cache, forget_engine_evals, get_cached_eval, get_eval_cache_stats,
+    CachedEval, EvalCacheStats,
+};
+pub use self::explorer::get_opening_moves;
+pub use self::export::{cancel_export, export_games, ExportProgress};
+pub use self::merge::{cancel_merge, merge_databases, MergeProgress};
 pub use self::models::NormalizedGame;
-pub use self::models::Puzzle;
-pub use self::schema::puzzles;
-pub use self::search::{is_position_in_db, search_position, PositionQuery, PositionStats};
+pub use self::models::{NewPuzzle, Puzzle};
+pub use self::pattern::{cancel_pattern_search, search_pattern, PatternProgress};
+pub use self::player_stats::{get_player_stats, search_players};
+pub use self::position_index::reindex_positions;
+pub use self::schema::{puzzle_progress, puzzles};
+pub use self::search::{
+    count_positions, forget_games, search_exact_position, search_position, GamesCache,
+    PositionPopularity, PositionQuery, PositionStats,
+};
 
+/// Version of the tables created by `create.sql`
 const DATABASE_VERSION: &str = "1.0.0";
 
+/// Schema changes made since `create.sql`, oldest first, as the version each one
+/// upgrades the database to and its SQL
+const MIGRATIONS: &[(&str, &str)] = &[
+    ("1.1.0", include_str!("positions.sql")),
+    ("1.2.0", include_str!("explorer.sql")),
+];
+
+/// Plies of each game whose positions are indexed when none is given, which covers the
+/// openings
+const POSITION_INDEX_PLIES: u16 = 20;
+
 const INDEXES_SQL: &str = include_str!("indexes.sql");
 
+/// Indexes of `INDEXES_SQL` which the player stats can't do without
+const PLAYER_INDEXES_SQL: &str = "
+    CREATE INDEX IF NOT EXISTS games_white_idx ON Games(WhiteID);
+    CREATE INDEX IF NOT EXISTS games_black_idx ON Games(BlackID);
+";
+
 const DELETE_INDEXES_SQL: &str = include_str!("delete_indexes.sql");
 
+/// Index of the position index by game, which deleting games needs. Keeping it up to
+/// date while importing into a new database is slower than creating it after.
+const POSITIONS_INDEX_SQL: &str =
+    "CREATE INDEX IF NOT EXISTS positions_game_idx ON Positions(GameID);";
+
 const CREATE_TABLES_SQL: &str = include_str!("create.sql");
 
 const WHITE_PAWN: Piece = Piece {
@@ -74,13 +134,16 @@ const BLACK_PAWN: Piece = Piece {
 type MaterialCount = ByColor<u8>;
 
 fn get_material_count(board: &Board) -> MaterialCount {
-    board.material().map(|material| {
-        material.pawn
-            + material.knight * 3
-            + material.bishop * 3
-            + material.rook * 5
-            + material.queen * 9
-    })
+    board.material().map(|material| material_value(&material))
+}
+
+/// Value of the pieces of one side, in pawns
+fn material_value(material: &ByRole<u8>) -> u8 {
+    material.pawn
+        + material.knight * 3
+        + material.bishop * 3
+        + material.rook * 5
+        + material.queen * 9
 }
 
 /// Returns the bit representation of the pawns on the second and seventh rank
@@ -93,6 +156,54 @@ fn get_pawn_home(board: &Board) -> u16 {
     (second_rank_pawns as u16) | ((seventh_rank_pawns as u16) << 8)
 }
 
+/// Zobrist hash of a position, as stored in the position index
+fn position_hash(position: &Chess) -> i64 {
+    position.zobrist_hash::<Zobrist64>(EnPassantMode::Legal).0 as i64
+}
+
+/// Adds a position reached at `ply` to `positions` if it is early enough and wasn't
+/// reached before
+fn index_position(
+    positions: &mut Vec<(i64, i32, Option<u8>)>,
+    position: &Chess,
+    ply: usize,
+    next: Option<u8>,
+    plies: u16,
+) {
+    if ply > plies as usize {
+        return;
+    }
+    let hash = position_hash(position);
+    if positions.iter().all(|&(other, _, _)| other != hash) {
+        positions.push((hash, ply as i32, next));
+    }
+}
+
+/// Rating stored with the indexed positions of a game, the average of its players
+fn position_rating(white_elo: Option<i32>, black_elo: Option<i32>) -> Option<i32> {
+    match (white_elo, black_elo) {
+        (Some(white), Some(black)) => Some((white + black) / 2),
+        (elo, None) | (None, elo) => elo,
+    }
+}
+
+/// Starting position of a game from its FEN tag, read like the import does
+fn game_start(fen: Option<&str>) -> Option<Chess> {
+    variant_start(fen, false)
+}
+
+/// Starting position of a game from its FEN tag, with Chess960 castling for games whose
+/// `Variant` tag names it
+fn variant_start(fen: Option<&str>, chess960: bool) -> Option<Chess> {
+    let Some(fen) = fen else {
+        return Some(Chess::default());
+    };
+    let fen = Fen::from_ascii(fen.as_bytes()).ok()?;
+    setup_position(fen, chess960)
+        .or_else(PositionError::ignore_too_much_material)
+        .ok()
+}
+
 #[derive(Debug)]
 pub enum JournalMode {
     Delete,
@@ -202,39 +313,49 @@ pub struct TempGame {
     pub moves: Vec<u8>,
     pub position: Chess,
     pub material_count: MaterialColor,
+    /// Hash, ply and next move of the positions reached in the first plies, each once
+    pub positions: Vec<(i64, i32, Option<u8>)>,
 }
 
 impl TempGame {
-    pub fn insert_to_db(&self, db: &mut SqliteConnection) -> Result<(), diesel::result::Error> {
+    /// Inserts the game with its players, event and site, returning its id
+    pub fn insert_to_db(&self, db: &mut SqliteConnection) -> Result<i32, diesel::result::Error> {
+        self.insert_with(db, &mut NameIds::default())
+    }
+
+    /// Like `insert_to_db`, reusing the ids of the names `names` found before
+    pub fn insert_with(
+        &self,
+        db: &mut SqliteConnection,
+        names: &mut NameIds,
+    ) -> Result<i32, diesel::result::Error> {
         let pawn_home = get_pawn_home(self.position.board());
 
         let white_id = if let Some(name) = &self.white_name {
-            create_player(db, name)?.id
+            names.player(db, name)?
         } else {
             0
         };
         let black_id = if let Some(name) = &self.black_name {
-            create_player(db, name)?.id
+            names.player(db, name)?
         } else {
             0
         };
 
         let event_id = if let Some(name) = &self.event_name {
-            create_event(db, name)?.id
+            names.event(db, name)?
         } else {
             0
         };
 
         let site_id = if let Some(name) = &self.site_name {
-            create_site(db, name)?.id
+            names.site(db, name)?
         } else {
             0
         };
 
         let ply_count = (self.moves.len()) as i32;
-        let final_material = get_material_count(self.position.board());
-        let minimal_white_material = self.material_count.white.min(final_material.white) as i32;
-        let minimal_black_material = self.material_count.black.min(final_material.black) as i32;
+        let (minimal_white_material, minimal_black_material) = self.minimal_material();
 
         let new_game = NewGame {
             white_id,
@@ -258,23 +379,303 @@ impl TempGame {
             pawn_home: pawn_home as i32,
         };
 
-        create_game(db, new_game)?;
+        let game = create_game(db, new_game)?;
+
+        let rating = position_rating(self.white_elo, self.black_elo);
+        let indexed: Vec<NewPosition> = self
+            .positions
+            .iter()
+            .map(|&(hash, ply, next)| NewPosition {
+                hash,
+                game_id: game.id,
+                ply,
+                move_: next.map(i32::from),
+                result: self.result.as_deref(),
+                rating,
+            })
+            .collect();
+        insert_into(positions::table).values(&indexed).execute(db)?;
+        Ok(game.id)
+    }
+
+    /// Least material of each side during the game, which only promotions don't lower
+    fn minimal_material(&self) -> (i32, i32) {
+        let final_material = get_material_count(self.position.board());
+        (
+            self.material_count.white.min(final_material.white) as i32,
+            self.material_count.black.min(final_material.black) as i32,
+        )
+    }
+
+    /// Adds the current position to the index if it is early enough and wasn't
+    /// reached before
+    fn index_position(&mut self, next: Option<u8>, plies: u16) {
+        let ply = self.moves.len();
+        index_position(&mut self.positions, &self.position, ply, next, plies);
+    }
+}
+
+/// `1.10.0` as `[1, 10, 0]`, so versions compare by number
+fn parse_version(version: &str) -> Vec<u32> {
+    version
+        .split('.')
+        .map(|part| part.parse().unwrap_or(0))
+        .collect()
+}
+
+fn info_value(db: &mut SqliteConnection, name: &str) -> Result<Option<String>, Error> {
+    let info = info::table
+        .filter(info::name.eq(name))
+        .first::<Info>(db)
+        .optional()?;
+    Ok(info.and_then(|info| info.value))
+}
+
+fn set_info(db: &mut SqliteConnection, name: &str, value: &str) -> Result<(), Error> {
+    insert_into(info::table)
+        .values((info::name.eq(name), info::value.eq(value)))
+        .on_conflict(info::name)
+        .do_update()
+        .set(info::value.eq(value))
+        .execute(db)?;
+    Ok(())
+}
+
+/// Creates the tables of a new database, which still needs migrating to the latest
+/// version
+fn create_database(db: &mut SqliteConnection, title: &str, description: &str) -> Result<(), Error> {
+    db.batch_execute(CREATE_TABLES_SQL)?;
+    set_info(db, "Version", DATABASE_VERSION)?;
+    set_info(db, "Title", title)?;
+    set_info(db, "Description", description)?;
+    Ok(())
+}
+
+/// Adds the games of `pgn` to the database at `file`, which is created with `title` when
+/// it doesn't exist yet, indexing their positions like imports do. Returns the number of
+/// games added.
+pub fn add_games(
+    state: &State<AppState>,
+    file: &Path,
+    title: &str,
+    pgn: &str,
+) -> Result<usize, Error> {
+    let exists = file.exists();
+    let db = &mut get_db_or_create(state, file.to_str().unwrap(), ConnectionOptions::default())?;
+    if !exists {
+        create_database(db, title, "")?;
+        position_index::set_indexed_plies(db, POSITION_INDEX_PLIES)?;
+    }
+    migrate(db, MIGRATIONS)?;
+    let plies = position_index::indexed_plies(db)?.unwrap_or(POSITION_INDEX_PLIES);
+    let mut importer = Importer::new(None, plies);
+    let mut added = 0;
+    for game in BufferedReader::new(pgn.as_bytes())
+        .into_iter(&mut importer)
+        .flatten()
+        .flatten()
+    {
+        game.insert_to_db(db)?;
+        added += 1;
+    }
+    forget_games(state, file);
+    Ok(added)
+}
+
+/// Appends the games of `pgn` to the database at `file`, which is created with `title`
+/// when it doesn't exist yet, skipping the duplicates of games already there. The info
+/// `marker` is set in the same transaction, so it only ever records imports that went
+/// through. A cancelled import is rolled back with `ImportCancelled`.
+pub fn append_games(
+    state: &State<AppState>,
+    file: &Path,
+    title: &str,
+    pgn: impl Read,
+    marker: (&str, &str),
+    cancelled: &AtomicBool,
+) -> Result<ImportSummary, Error> {
+    let exists = file.exists();
+    let db = &mut get_db_or_create(state, file.to_str().unwrap(), ConnectionOptions::default())?;
+    let imported = db.transaction::<_, Error, _>(|db| {
+        if !exists {
+            create_database(db, title, "")?;
+            position_index::set_indexed_plies(db, POSITION_INDEX_PLIES)?;
+        }
+        migrate(db, MIGRATIONS)?;
+        let plies = position_index::indexed_plies(db)?.unwrap_or(POSITION_INDEX_PLIES);
+        let mut importer = Importer::new(None, plies);
+        let summary = import_games(
+            db,
+            pgn,
+            &mut importer,
+            DuplicatePolicy::Skip,
+            exists,
+            cancelled,
+            |_| {},
+        )?;
+        if summary.cancelled {
+            return Err(Error::ImportCancelled);
+        }
+        set_info(db, marker.0, marker.1)?;
+        Ok(summary)
+    });
+    forget_games(state, file);
+    imported
+}
+
+/// Names of the sites of the games of the database at `file`, like their URLs for the
+/// games of online services. Empty when the database doesn't exist.
+pub fn site_names(state: &State<AppState>, file: &Path) -> Result<HashSet<String>, Error> {
+    if !file.exists() {
+        return Ok(HashSet::new());
+    }
+    let db = &mut get_db_or_create(state, file.to_str().unwrap(), ConnectionOptions::default())?;
+    let names: Vec<Option<String>> = sites::table.select(sites::name).load(db)?;
+    Ok(names.into_iter().flatten().collect())
+}
+
+/// Value of the info `name` of the database at `file`, None when it or the database
+/// doesn't exist
+pub fn database_info_value(
+    state: &State<AppState>,
+    file: &Path,
+    name: &str,
+) -> Result<Option<String>, Error> {
+    if !file.exists() {
+        return Ok(None);
+    }
+    let db = &mut get_db_or_create(state, file.to_str().unwrap(), ConnectionOptions::default())?;
+    info_value(db, name)
+}
+
+/// Version of the schema of a database, which databases from before it was kept are at
+fn database_version(db: &mut SqliteConnection) -> Result<String, Error> {
+    Ok(info_value(db, "Version")?.unwrap_or_else(|| DATABASE_VERSION.to_string()))
+}
+
+/// Applies the migrations newer than the database in one transaction and returns the
+/// version it is at. Databases without a version have the tables of `create.sql`.
+fn migrate(db: &mut SqliteConnection, migrations: &[(&str, &str)]) -> Result<String, Error> {
+    let current = database_version(db)?;
+    let pending: Vec<&(&str, &str)> = migrations
+        .iter()
+        .filter(|(version, _)| parse_version(version) > parse_version(&current))
+        .collect();
+    let Some((latest, _)) = pending.last() else {
+        return Ok(current);
+    };
+    db.transaction::<_, diesel::result::Error, _>(|db| {
+        for (_, sql) in &pending {
+            db.batch_execute(sql)?;
+        }
+        insert_into(info::table)
+            .values((info::name.eq("Version"), info::value.eq(*latest)))
+            .on_conflict(info::name)
+            .do_update()
+            .set(info::value.eq(*latest))
+            .execute(db)?;
         Ok(())
+    })?;
+    Ok(latest.to_string())
+}
+
+/// Outcome of importing a PGN file into a database
+#[derive(Serialize, Debug, Clone, Default, PartialEq, Eq, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportSummary {
+    /// Games added to the database, the ones that replaced their duplicate included
+    pub imported: usize,
+    /// Games of the file that weren't added, whether malformed, duplicates or outdated
+    pub skipped: usize,
+    /// Games with an invalid starting position or an illegal move
+    pub malformed: usize,
+    /// Duplicates of games already in the database or earlier in the file that were
+    /// skipped
+    pub duplicates: usize,
+    /// Imported games that replaced their duplicate
+    pub replaced: usize,
+    /// Games played before the timestamp the import started from
+    pub outdated: usize,
+    /// Games in the database after the import, the ones it already had included
+    pub total_games: usize,
+    pub elapsed_ms: u64,
+    /// Job id of the import, shared with the other background tasks
+    pub id: String,
+    /// Whether the import was cancelled before the end of the file
+    pub cancelled: bool,
+    /// Whether the games imported before the import was cancelled were left out again
+    pub rolled_back: bool,
+    /// Bytes of the file read, before any decompression, which is where a cancelled
+    /// import stopped
+    pub bytes_read: u64,
+}
+
+/// What a cancelled import does with the games it added so far
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, Type)]
+#[serde(rename_all = "camelCase")]
+pub enum CancelPolicy {
+    /// Leaves the database as it was, and deletes a new one
+    #[default]
+    Rollback,
+    /// Keeps the games added so far
+    Commit,
+}
+
+#[derive(Clone, Type, Serialize, tauri_specta::Event)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportProgress {
+    pub id: String,
+    pub db_path: PathBuf,
+    /// Games read so far, the skipped ones included
+    pub games: usize,
+    pub skipped: usize,
+    pub bytes_read: u64,
+    pub total_bytes: u64,
+    /// Time left, estimated from the share of the file read so far
+    pub eta_ms: Option<u64>,
+    pub finished: bool,
+}
+
+/// Time left to read `total` bytes at the pace of the first `read` ones
+fn eta_ms(elapsed: Duration, read: u64, total: u64) -> Option<u64> {
+    if read == 0 {
+        return None;
     }
+    let left = total.saturating_sub(read) as f64 / read as f64;
+    Some((elapsed.as_millis() as f64 * left) as u64)
 }
 
 struct Importer {
     game: TempGame,
     timestamp: Option<i64>,
     skip: bool,
+    /// Whether the game has an invalid starting position or an illegal move
+    malformed: bool,
+    /// Whether the `Variant` tag names Chess960
+    chess960: bool,
+    /// Whether the result was read, after which anything up to the next game is ignored
+    finished: bool,
+    /// ECO of the deepest position found in the opening table, for games without one
+    book_eco: Option<&'static str>,
+    malformed_games: usize,
+    outdated_games: usize,
+    /// Plies of each game whose positions are indexed
+    position_plies: u16,
 }
 
 impl Importer {
-    fn new(timestamp: Option<i64>) -> Importer {
+    fn new(timestamp: Option<i64>, position_plies: u16) -> Importer {
         Importer {
             game: TempGame::default(),
             timestamp,
+            position_plies,
             skip: false,
+            malformed: false,
+            chess960: false,
+            finished: false,
+            book_eco: None,
+            malformed_games: 0,
+            outdated_games: 0,
         }
     }
 }
@@ -284,6 +685,10 @@ impl Visitor for Importer {
 
     fn begin_game(&mut self) {
         self.skip = false;
+        self.malformed = false;
+        self.chess960 = false;
+        self.finished = false;
+        self.book_eco = None;
     }
 
     fn header(&mut self, key: &[u8], value: RawHeader<'_>) {
@@ -315,25 +720,22 @@ impl Visitor for Importer {
             if value.as_bytes() == b"rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1" {
                 self.game.fen = None;
             } else {
-                let fen = Fen::from_ascii(value.as_bytes());
-                if let Ok(fen) = fen {
-                    self.game.fen = Some(value.decode_utf8_lossy().into_owned());
-                    if let Ok(setup) =
-                        Chess::from_setup(fen.into_setup(), shakmaty::CastlingMode::Standard)
-                            .or_else(PositionError::ignore_too_much_material)
-                    {
-                        self.game.position = setup;
-                    } else {
-                        self.skip = true;
-                    }
-                } else {
-                    self.skip = true;
-                }
+                self.game.fen = Some(value.decode_utf8_lossy().into_owned());
             }
+        } else if key == b"Variant" {
+            self.chess960 = is_chess960(&value.decode_utf8_lossy());
         }
     }
 
     fn end_headers(&mut self) -> Skip {
+        // Set up the position once the Variant tag, wherever it is, tells the castling
+        if let Some(fen) = &self.game.fen {
+            match variant_start(Some(fen), self.chess960) {
+                Some(position) => self.game.position = position,
+                None => self.malformed = true,
+            }
+        }
+
         // Skip games with timestamp before
         let cur_timestamp = self.game.date.as_ref().and_then(|date| {
             let date = NaiveDate::parse_from_str(date, "%Y.%m.%d").ok()?;
@@ -353,10 +755,13 @@ impl Visitor for Importer {
 
         // Skip games without ELO
         // self.skip |= self.current.white_elo.is_none() || self.current.black_elo.is_none();
-        Skip(self.skip)
+        Skip(self.skip || self.malformed)
     }
 
     fn san(&mut self, san: SanPlus) {
+        if self.finished || self.malformed {
+            return;
+        }
         let m = san.san.to_move(&self.game.position).ok();
         if let Some(m) = m {
             if m.is_promotion() {
@@ -368,12 +773,18 @@ impl Visitor for Importer {
                     self.game.material_count.black = cur_material.black;
                 }
             }
-            self.game
-                .moves
-                .push(encode_move(&m, &self.game.position).unwrap());
+            let encoded = encode_move(&m, &self.game.position).unwrap();
+            self.game.index_position(Some(encoded), self.position_plies);
+            self.game.moves.push(encoded);
             self.game.position.play_unchecked(&m);
+            if self.game.eco.is_none() && self.game.moves.len() <= MAX_BOOK_PLIES {
+                let setup = self.game.position.clone().into_setup(EnPassantMode::Legal);
+                if let Some(opening) = find_opening(setup) {
+                    self.book_eco = Some(opening.eco());
+                }
+            }
         } else {
-            self.skip = true;
+            self.malformed = true;
         }
     }
 
@@ -381,18 +792,297 @@ impl Visitor for Importer {
         Skip(true) // stay in the mainline
     }
 
+    fn outcome(&mut self, _: Option<shakmaty::Outcome>) {
+        self.finished = true;
+    }
+
     fn end_game(&mut self) -> Self::Result {
-        if self.skip {
+        if self.malformed || self.skip {
+            if self.malformed {
+                self.malformed_games += 1;
+            } else {
+                self.outdated_games += 1;
+            }
             self.game = TempGame::default();
             None
         } else {
+            if self.game.eco.is_none() {
+                self.game.eco = self.book_eco.map(str::to_string);
+            }
+            self.game.index_position(None, self.position_plies);
             Some(std::mem::take(&mut self.game))
         }
     }
 }
 
+/// Counts the bytes read from a file, before any decompression, so progress can be
+/// reported as a share of the file size
+pub(crate) struct CountingReader<R> {
+    pub(crate) inner: R,
+    pub(crate) read: Arc<AtomicU64>,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let bytes = self.inner.read(buf)?;
+        self.read.fetch_add(bytes as u64, Ordering::Relaxed);
+        Ok(bytes)
+    }
+}
+
+/// Updates the counts of the info table after games were added or deleted
+fn update_counts(db: &mut SqliteConnection) -> Result<usize, Error> {
+    let game_count: i64 = games::table.count().get_result(db)?;
+    let player_count: i64 = players::table.count().get_result(db)?;
+    let event_count: i64 = events::table.count().get_result(db)?;
+    let site_count: i64 = sites::table.count().get_result(db)?;
+
+    let counts = [
+        ("GameCount", game_count),
+        ("PlayerCount", player_count),
+        ("EventCount", event_count),
+        ("SiteCount", site_count),
+    ];
+
+    for (name, count) in counts {
+        set_info(db, name, &count.to_string())?;
+    }
+    Ok(game_count as usize)
+}
+
+/// Adds the games of a PGN stream to a database, new or not, reusing its players,
+/// events and sites. Meant to run in a transaction. A cancelled import stops at the
+/// next game and still finishes the games added so far, so rolling them back is up to
+/// the caller.
+fn import_games(
+    db: &mut SqliteConnection,
+    pgn: impl Read,
+    importer: &mut Importer,
+    duplicates: DuplicatePolicy,
+    db_exists: bool,
+    cancelled: &AtomicBool,
+    progress: impl FnMut(&ImportSummary),
+) -> Result<ImportSummary, Error> {
+    // Malformed and outdated games are read as `None`
+    let games = BufferedReader::new(pgn).into_iter(&mut *importer).flatten();
+    let summary = insert_games(db, games, duplicates, db_exists, cancelled, progress)?;
+    finish_import(
+        db,
+        summary,
+        importer.malformed_games,
+        importer.outdated_games,
+        db_exists,
+    )
+}
+
+/// Games of each chunk of a mapped file, which is read on its own
+const CHUNK_GAMES: usize = 1000;
+
+/// Whether `line` is a tag like `[White "Carlsen, Magnus"]`, unlike the clock of a
+/// comment or the start of a variation
+fn is_tag(line: &[u8]) -> bool {
+    let mut tokens = line
+        .split(u8::is_ascii_whitespace)
+        .filter(|token| !token.is_empty());
+    let Some(name) = tokens.next().and_then(|token| token.strip_prefix(b"[")) else {
+        return false;
+    };
+    let value = tokens.next().unwrap_or_default();
+    let last = tokens.last().unwrap_or(value);
+    !name.is_empty()
+        && name
+            .iter()
+            .all(|byte| byte.is_ascii_alphanumeric() || *byte == b'_')
+        && value.starts_with(b"\"")
+        && last.ends_with(b"\"]")
+}
+
+/// Offsets of the chunks of `pgn` of `games` games each, in one pass over it. The first
+/// is 0, and the others are at the first tag of a game, so the tags of a game are
+/// never split. Games without tags are part of the chunk of the game before.
+fn chunk_offsets(pgn: &[u8], games: usize) -> Vec<usize> {
+    let mut offsets = vec![0];
+    let mut found = 0;
+    let mut in_tags = false;
+    let mut offset = 0;
+    for line in pgn.split_inclusive(|&byte| byte == b'\n') {
+        if is_tag(line) {
+            if !in_tags {
+                if found > 0 && found % games == 0 {
+                    offsets.push(offset);
+                }
+                found += 1;
+                in_tags = true;
+            }
+        } else if !line.iter().all(u8::is_ascii_whitespace) {
+            in_tags = false;
+        }
+        offset += line.len();
+    }
+    offsets
+}
+
+/// Games of a chunk of a mapped file, `None` for the skipped ones
+struct Chunk {
+    games: Vec<Option<TempGame>>,
+    malformed: usize,
+    outdated: usize,
+    /// Offset of the end of the chunk in the file
+    end: usize,
+}
+
+fn read_chunk(pgn: &[u8], end: usize, timestamp: Option<i64>, position_plies: u16) -> Chunk {
+    let mut importer = Importer::new(timestamp, position_plies);
+    let games = BufferedReader::new(pgn)
+        .into_iter(&mut importer)
+        .flatten()
+        .collect();
+    Chunk {
+        games,
+        malformed: importer.malformed_games,
+        outdated: importer.outdated_games,
+        end,
+    }
+}
+
+/// Like `import_games` for a file mapped in memory, whose chunks are read in parallel
+/// while the games of the ones before are written. They're still written in the order
+/// of the file, and a malformed game, like one with a comment that's never closed, can
+/// only take the rest of its chunk with it. `bytes_read` is where the chunk being
+/// written ends.
+#[allow(clippy::too_many_arguments)]
+fn import_mapped_games(
+    db: &mut SqliteConnection,
+    pgn: &[u8],
+    importer: &Importer,
+    duplicates: DuplicatePolicy,
+    db_exists: bool,
+    cancelled: &AtomicBool,
+    bytes_read: &AtomicU64,
+    progress: impl FnMut(&ImportSummary),
+) -> Result<ImportSummary, Error> {
+    let mut offsets = chunk_offsets(pgn, CHUNK_GAMES);
+    offsets.push(pgn.len());
+    let ranges: Vec<(usize, usize)> = offsets.windows(2).map(|w| (w[0], w[1])).collect();
+    let (timestamp, position_plies) = (importer.timestamp, importer.position_plies);
+    // Chunks are read a batch at a time, so only the next batch waits to be written
+    let batch = rayon::current_num_threads() * 2;
+    let (sender, receiver) = std::sync::mpsc::sync_channel::<Chunk>(batch);
+    std::thread::scope(|scope| {
+        scope.spawn(move || {
+            for ranges in ranges.chunks(batch) {
+                let chunks: Vec<Chunk> = ranges
+                    .par_iter()
+                    .map(|&(start, end)| {
+                        read_chunk(&pgn[start..end], end, timestamp, position_plies)
+                    })
+                    .collect();
+                for chunk in chunks {
+                    // The import stopped
+                    if sender.send(chunk).is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+        let (mut malformed, mut outdated) = (0, 0);
+        let games = receiver.into_iter().flat_map(|chunk| {
+            malformed += chunk.malformed;
+            outdated += chunk.outdated;
+            bytes_read.store(chunk.end as u64, Ordering::Relaxed);
+            chunk.games
+        });
+        let summary = insert_games(db, games, duplicates, db_exists, cancelled, progress)?;
+        finish_import(db, summary, malformed, outdated, db_exists)
+    })
+}
+
+/// Writes `games`, the first step of the imports
+fn insert_games(
+    db: &mut SqliteConnection,
+    games: impl Iterator<Item = Option<TempGame>>,
+    duplicates: DuplicatePolicy,
+    db_exists: bool,
+    cancelled: &AtomicBool,
+    mut progress: impl FnMut(&ImportSummary),
+) -> Result<ImportSummary, Error> {
+    let mut seen = SeenGames::new(db, duplicates, db_exists)?;
+    let mut summary = ImportSummary::default();
+    // Replacing games deletes their positions, which takes the index
+    if !db_exists && duplicates != DuplicatePolicy::Replace {
+        db.batch_execute("DROP INDEX IF EXISTS positions_game_idx;")?;
+    }
+    for (read, game) in games.enumerate() {
+        if cancelled.load(Ordering::Relaxed) {
+            summary.cancelled = true;
+            break;
+        }
+        if read % 1000 == 0 {
+            progress(&summary);
+        }
+        let Some(game) = game else {
+            summary.skipped += 1;
+            continue;
+        };
+        match seen.import(db, &game)? {
+            Imported::Added => summary.imported += 1,
+            Imported::Skipped => {
+                summary.skipped += 1;
+                summary.duplicates += 1;
+            }
+            Imported::Replaced => {
+                summary.imported += 1;
+                summary.replaced += 1;
+            }
+        }
+    }
+    progress(&summary);
+    Ok(summary)
+}
+
+/// Counts the games skipped while reading them and creates the indexes, the last step
+/// of the imports
+fn finish_import(
+    db: &mut SqliteConnection,
+    mut summary: ImportSummary,
+    malformed: usize,
+    outdated: usize,
+    db_exists: bool,
+) -> Result<ImportSummary, Error> {
+    summary.malformed = malformed;
+    summary.outdated = outdated;
+    summary.skipped = summary.malformed + summary.duplicates + summary.outdated;
+    // Kept across imports, since the games themselves never make it to the database
+    let malformed = malformed_games(db)? + summary.malformed;
+    set_info(db, "MalformedGames", &malformed.to_string())?;
+
+    if !db_exists {
+        // Create all the necessary indexes
+        db.batch_execute(INDEXES_SQL)?;
+        db.batch_execute(POSITIONS_INDEX_SQL)?;
+    }
+    db.batch_execute(PLAYER_INDEXES_SQL)?;
+
+    summary.total_games = update_counts(db)?;
+    Ok(summary)
+}
+
+/// Imports a PGN file game by game, so only the games being parsed are held in memory.
+/// Uncompressed files are mapped and read in chunks in parallel, in the order of the
+/// file, and the others, or the ones that can't be mapped, as a stream. Progress is
+/// emitted as `ImportProgress`, and as `(games, elapsed ms, percent of the file read)`
+/// for the frontend from before it. Malformed games are skipped and counted. `duplicates`
+/// picks what to do with the games already in the database or earlier in the file, which
+/// are kept by default. The positions of the first `position_plies` plies of each game
+/// are indexed for `search_exact_position`.
+///
+/// Importing into an existing database appends to it. The import is a single
+/// transaction, so when it fails the database is left as it was, and a new one is
+/// deleted. It can be cancelled with `cancel_import(id)`, the id of its progress
+/// events, after which `on_cancel` picks whether the games added so far are kept.
 #[tauri::command]
 #[specta::specta]
+#[allow(clippy::too_many_arguments)]
 pub async fn convert_pgn(
     file: PathBuf,
     db_path: PathBuf,
@@ -400,15 +1090,19 @@ pub async fn convert_pgn(
     app: tauri::AppHandle,
     title: String,
     description: Option<String>,
+    duplicates: Option<DuplicatePolicy>,
+    position_plies: Option<u16>,
+    on_cancel: Option<CancelPolicy>,
     state: tauri::State<'_, AppState>,
-) -> Result<(), Error> {
+) -> Result<ImportSummary, Error> {
+    let id = new_job_id(&state, "import");
     let description = description.unwrap_or_default();
     let extension = file.extension();
 
     let db_exists = db_path.exists();
 
     // create the database file
-    let db = &mut get_db_or_create(
+    let mut db = get_db_or_create(
         &state,
         db_path.to_str().unwrap(),
         ConnectionOptions {
@@ -417,20 +1111,28 @@ pub async fn convert_pgn(
             journal_mode: JournalMode::Off,
         },
     )?;
-
-    if !db_exists {
-        db.batch_execute(CREATE_TABLES_SQL)?;
-        db.batch_execute(
-            format!(
-                "INSERT INTO Info (Name, Value) VALUES (\"Version\", \"{DATABASE_VERSION}\");
-                INSERT INTO Info (Name, Value) VALUES (\"Title\", \"{title}\");
-                INSERT INTO Info (Name, Value) VALUES (\"Description\", \"{description}\");"
-            )
-            .as_str(),
-        )?;
+    if db_exists {
+        // Rolling back needs the journal, which only a new database can go without. With
+        // a write-ahead log the games already there can be browsed during the import.
+        db.batch_execute("PRAGMA journal_mode = WAL;")?;
     }
 
     let file = File::open(&file)?;
+    let file_size = file.metadata()?.len().max(1);
+    let compressed = extension == Some("bz2".as_ref()) || extension == Some("zst".as_ref());
+    // Files that don't need decompressing are read in parallel from memory, unless they
+    // can't be mapped
+    // SAFETY: the file is only read, and isn't expected to change during the import
+    let mapped = if compressed {
+        None
+    } else {
+        unsafe { Mmap::map(&file) }.ok()
+    };
+    let bytes_read = Arc::new(AtomicU64::new(0));
+    let file = CountingReader {
+        inner: file,
+        read: bytes_read.clone(),
+    };
 
     let uncompressed: Box<dyn std::io::Read + Send> = if extension == Some("bz2".as_ref()) {
         Box::new(bzip2::read::MultiBzDecoder::new(file))
@@ -443,54 +1145,153 @@ pub async fn convert_pgn(
     // start counting time
     let start = Instant::now();
 
-    let mut importer = Importer::new(timestamp.map(|t| t as i64));
-    db.transaction::<_, diesel::result::Error, _>(|db| {
-        for (i, game) in BufferedReader::new(uncompressed)
-            .into_iter(&mut importer)
-            .flatten()
-            .flatten()
-            .enumerate()
-        {
-            if i % 1000 == 0 {
-                let elapsed = start.elapsed().as_millis() as u32;
-                app.emit_all("convert_progress", (i, elapsed)).unwrap();
-            }
-            game.insert_to_db(db)?;
+    let progress = |summary: &ImportSummary, finished: bool| {
+        let elapsed = start.elapsed();
+        let read = bytes_read.load(Ordering::Relaxed).min(file_size);
+        let percent = read as f64 / file_size as f64 * 100.0;
+        let _ = app.emit_all(
+            "convert_progress",
+            (summary.imported, elapsed.as_millis() as u32, percent),
+        );
+        let _ = ImportProgress {
+            id: id.clone(),
+            db_path: db_path.clone(),
+            games: summary.imported + summary.skipped,
+            skipped: summary.skipped,
+            bytes_read: read,
+            total_bytes: file_size,
+            eta_ms: if finished {
+                Some(0)
+            } else {
+                eta_ms(elapsed, read, file_size)
+            },
+            finished,
         }
-        Ok(())
-    })?;
+        .emit_all(&app);
+    };
 
-    if !db_exists {
-        // Create all the necessary indexes
-        db.batch_execute(INDEXES_SQL)?;
+    // Appended games are indexed as deep as the ones already there
+    let stored_plies = if db_exists {
+        position_index::indexed_plies(&mut db)?
+    } else {
+        None
+    };
+    let position_plies = position_plies
+        .or(stored_plies)
+        .unwrap_or(POSITION_INDEX_PLIES);
+    let mut importer = Importer::new(timestamp.map(|t| t as i64), position_plies);
+    let cancelled = Arc::new(AtomicBool::new(false));
+    state.imports.insert(id.clone(), cancelled.clone());
+    let mut stopped = None;
+    let result = db.transaction::<_, Error, _>(|db| {
+        if !db_exists {
+            create_database(db, &title, &description)?;
+        }
+        migrate(db, MIGRATIONS)?;
+        // The index is only as deep as the shallowest import, and unknown when the games
+        // already there were indexed to an unknown depth
+        match stored_plies {
+            Some(stored) => position_index::set_indexed_plies(db, stored.min(position_plies))?,
+            None if !db_exists => position_index::set_indexed_plies(db, position_plies)?,
+            None => {}
+        }
+        let summary = match &mapped {
+            Some(pgn) => import_mapped_games(
+                db,
+                pgn,
+                &importer,
+                duplicates.unwrap_or_default(),
+                db_exists,
+                &cancelled,
+                &bytes_read,
+                |summary| progress(summary, false),
+            )?,
+            None => import_games(
+                db,
+                uncompressed,
+                &mut importer,
+                duplicates.unwrap_or_default(),
+                db_exists,
+                &cancelled,
+                |summary| progress(summary, false),
+            )?,
+        };
+        if summary.cancelled && on_cancel.unwrap_or_default() == CancelPolicy::Rollback {
+            stopped = Some(summary);
+            return Err(Error::ImportCancelled);
+        }
+        Ok(summary)
+    });
+    state.imports.remove(&id);
+    if db_exists {
+        // Other connections use a rollback journal, this moves the log into the database
+        db.batch_execute("PRAGMA journal_mode = DELETE;")?;
     }
+    let result = match (result, stopped) {
+        (Err(Error::ImportCancelled), Some(summary)) if db_exists => Ok(ImportSummary {
+            rolled_back: true,
+            total_games: games::table.count().get_result::<i64>(&mut db)? as usize,
+            ..summary
+        }),
+        (Err(Error::ImportCancelled), Some(summary)) => Ok(ImportSummary {
+            rolled_back: true,
+            total_games: 0,
+            ..summary
+        }),
+        (result, _) => result,
+    };
 
-    // get game, player, event and site counts and to the info table
-    let game_count: i64 = games::table.count().get_result(db)?;
-    let player_count: i64 = players::table.count().get_result(db)?;
-    let event_count: i64 = events::table.count().get_result(db)?;
-    let site_count: i64 = sites::table.count().get_result(db)?;
+    let kept = matches!(&result, Ok(summary) if !summary.rolled_back);
+    if !kept && !db_exists {
+        // Without a journal nothing was rolled back, so none of the file is kept
+        drop(db);
+        state.connection_pool.remove(db_path.to_str().unwrap());
+        let _ = remove_file(&db_path);
+    }
+    forget_games(&state, &db_path);
+    let mut summary = result?;
+    summary.id = id.clone();
+    summary.bytes_read = bytes_read.load(Ordering::Relaxed);
+    summary.elapsed_ms = start.elapsed().as_millis() as u64;
+    progress(&summary, true);
+    Ok(summary)
+}
 
-    let counts = [
-        ("GameCount", game_count),
-        ("PlayerCount", player_count),
-        ("EventCount", event_count),
-        ("SiteCount", site_count),
-    ];
+/// Imports `pgn` into a new database in memory the way `convert_pgn` imports a file,
+/// mapped in memory or else as a stream, with the positions of the first plies indexed
+/// and every duplicate kept
+pub fn import_into_memory(
+    pgn: &[u8],
+    mapped: bool,
+) -> Result<(SqliteConnection, ImportSummary), Error> {
+    let mut db = SqliteConnection::establish(":memory:")?;
+    let summary = db.transaction::<_, Error, _>(|db| {
+        create_database(db, "", "")?;
+        migrate(db, MIGRATIONS)?;
+        position_index::set_indexed_plies(db, POSITION_INDEX_PLIES)?;
+        let mut importer = Importer::new(None, POSITION_INDEX_PLIES);
+        let cancelled = AtomicBool::new(false);
+        let policy = DuplicatePolicy::KeepBoth;
+        if mapped {
+            let read = AtomicU64::new(0);
+            import_mapped_games(db, pgn, &importer, policy, false, &cancelled, &read, |_| {})
+        } else {
+            import_games(db, pgn, &mut importer, policy, false, &cancelled, |_| {})
+        }
+    })?;
+    Ok((db, summary))
+}
 
-    for c in counts.iter() {
-        insert_into(info::table)
-            .values((info::name.eq(c.0), info::value.eq(c.1.to_string())))
-            .on_conflict(info::name)
-            .do_update()
-            .set(info::value.eq(c.1.to_string()))
-            .execute(db)?;
+/// Stops the import with the job id `id` at the next game
+#[tauri::command]
+#[specta::specta]
+pub fn cancel_import(id: String, state: tauri::State<'_, AppState>) {
+    if let Some(cancelled) = state.imports.get(&id) {
+        cancelled.store(true, Ordering::Relaxed);
     }
-
-    Ok(())
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, Debug, Default, PartialEq)]
 pub struct DatabaseInfo {
     title: String,
     description: String,
@@ -500,6 +1301,59 @@ pub struct DatabaseInfo {
     storage_size: usize,
     filename: String,
     indexed: bool,
+    /// Dates of the first and last games, leaving out the ones without a known year
+    first_date: Option<String>,
+    last_date: Option<String>,
+    version: String,
+    position_index: bool,
+    /// Plies of each game in the position index, unknown for databases indexed before
+    /// it was kept
+    position_plies: Option<u16>,
+    /// Games of all the imports into the database that were skipped as malformed
+    malformed_games: usize,
+}
+
+fn malformed_games(db: &mut SqliteConnection) -> Result<usize, Error> {
+    let malformed = info_value(db, "MalformedGames")?;
+    Ok(malformed.and_then(|count| count.parse().ok()).unwrap_or(0))
+}
+
+/// Everything about a database but its file
+fn database_info(db: &mut SqliteConnection) -> Result<DatabaseInfo, Error> {
+    let player_count = players::table.count().get_result::<i64>(db)? as usize;
+    let game_count = games::table.count().get_result::<i64>(db)? as usize;
+    let event_count = events::table.count().get_result::<i64>(db)? as usize;
+
+    let known_dates = games::table.filter(games::date.not_like("?%"));
+    let first_date = known_dates
+        .select(diesel::dsl::min(games::date))
+        .first::<Option<String>>(db)?;
+    let last_date = known_dates
+        .select(diesel::dsl::max(games::date))
+        .first::<Option<String>>(db)?;
+
+    let position_index = has_position_index(db)?;
+    let position_plies = if position_index {
+        position_index::indexed_plies(db)?
+    } else {
+        None
+    };
+
+    Ok(DatabaseInfo {
+        title: info_value(db, "Title")?.unwrap_or_else(|| "Untitled".to_string()),
+        description: info_value(db, "Description")?.unwrap_or_default(),
+        player_count,
+        game_count,
+        event_count,
+        indexed: check_index_exists(db)?,
+        first_date,
+        last_date,
+        version: database_version(db)?,
+        position_index,
+        position_plies,
+        malformed_games: malformed_games(db)?,
+        ..Default::default()
+    })
 }
 
 #[derive(QueryableByName, Debug, Serialize)]
@@ -514,6 +1368,19 @@ fn check_index_exists(conn: &mut SqliteConnection) -> Result<bool, Error> {
     Ok(!indexes.is_empty())
 }
 
+/// Whether the database has the position index, which databases created before it
+/// don't have until games are imported into them again
+fn has_position_index(conn: &mut SqliteConnection) -> Result<bool, Error> {
+    has_table(conn, "Positions")
+}
+
+fn has_table(conn: &mut SqliteConnection, table: &str) -> Result<bool, Error> {
+    let query = sql_query("SELECT name FROM sqlite_master WHERE type = 'table' AND name = ?;")
+        .bind::<Text, _>(table);
+    let tables: Vec<IndexInfo> = query.load(conn)?;
+    Ok(!tables.is_empty())
+}
+
 #[tauri::command]
 pub async fn get_db_info(
     file: PathBuf,
@@ -532,41 +1399,58 @@ pub async fn get_db_info(
 
     let db = &mut get_db_or_create(&state, path.to_str().unwrap(), ConnectionOptions::default())?;
 
-    let player_count = players::table.count().get_result::<i64>(db)? as usize;
-    let game_count = games::table.count().get_result::<i64>(db)? as usize;
-    let event_count = events::table.count().get_result::<i64>(db)? as usize;
-
-    let title = match info::table
-        .filter(info::name.eq("Title"))
-        .first(db)
-        .map(|title_info: Info| title_info.value)
-    {
-        Ok(Some(title)) => title,
-        _ => "Untitled".to_string(),
-    };
-
-    let description = match info::table
-        .filter(info::name.eq("Description"))
-        .first(db)
-        .map(|description_info: Info| description_info.value)
-    {
-        Ok(Some(description)) => description,
-        _ => "".to_string(),
-    };
-
     let storage_size = path.metadata()?.len() as usize;
     let filename = path.file_name().expect("get filename").to_string_lossy();
 
-    let is_indexed = check_index_exists(db)?;
     Ok(DatabaseInfo {
-        title,
-        description,
-        player_count,
-        game_count,
-        event_count,
         storage_size,
         filename: filename.to_string(),
-        indexed: is_indexed,
+        ..database_info(db)?
+    })
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct OptimizeSummary {
+    pub size_before: u64,
+    pub size_after: u64,
+    /// Indexes of the game list and the position index that were missing and created
+    pub indexes_created: usize,
+}
+
+fn index_count(db: &mut SqliteConnection) -> Result<usize, Error> {
+    let query = sql_query(
+        "SELECT name FROM sqlite_master WHERE type = 'index' AND tbl_name IN ('Games', 'Positions');",
+    );
+    let indexes: Vec<IndexInfo> = query.load(db)?;
+    Ok(indexes.len())
+}
+
+/// Creates the missing indexes, updates the statistics the query planner uses and
+/// rebuilds the file without the space deleted games left
+fn optimize(db: &mut SqliteConnection) -> Result<usize, Error> {
+    let before = index_count(db)?;
+    db.batch_execute(INDEXES_SQL)?;
+    if has_position_index(db)? {
+        db.batch_execute(POSITIONS_INDEX_SQL)?;
+    }
+    db.batch_execute("ANALYZE; VACUUM;")?;
+    Ok(index_count(db)? - before)
+}
+
+#[tauri::command]
+#[specta::specta]
+pub async fn optimize_db(
+    file: PathBuf,
+    state: tauri::State<'_, AppState>,
+) -> Result<OptimizeSummary, Error> {
+    let size_before = file.metadata()?.len();
+    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+    let indexes_created = optimize(db)?;
+    Ok(OptimizeSummary {
+        size_before,
+        size_after: file.metadata()?.len(),
+        indexes_created,
     })
 }
 
@@ -673,6 +1557,15 @@ pub struct GameQuery {
     pub range2: Option<(i32, i32)>,
     pub sides: Option<Sides>,
     pub outcome: Option<String>,
+    /// ECO codes from the first to the second, both included, like `("B20", "B99")`
+    pub eco_range: Option<(String, String)>,
+    /// Part of the name of an opening, matched through the ECO codes of the openings
+    /// with that name since games only store their code
+    pub opening: Option<String>,
+    /// Games with fewer full moves are left out
+    pub min_moves: Option<i32>,
+    /// Part of the name of a player, the event or the site
+    pub text: Option<String>,
     pub position: Option<PositionQuery>,
 }
 
@@ -692,124 +1585,118 @@ pub struct QueryResponse<T> {
     pub count: Option<i64>,
 }
 
-#[tauri::command]
-pub async fn get_games(
-    file: PathBuf,
-    query: GameQuery,
-    state: tauri::State<'_, AppState>,
-) -> Result<QueryResponse<Vec<NormalizedGame>>, Error> {
-    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+/// `text` with the wildcards of `LIKE` escaped by a backslash
+fn escape_like(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
 
-    let mut count: Option<i64> = None;
-    let query_options = query.options.unwrap_or_default();
+/// Games of a database matching the filters of `query`, without its sorting and paging
+fn filter_games(query: &GameQuery) -> games::BoxedQuery<'static, Sqlite> {
+    let mut filtered = games::table.into_boxed();
 
-    let (white_players, black_players) = diesel::alias!(players as white, players as black);
-    let mut sql_query = games::table
-        .inner_join(white_players.on(games::white_id.eq(white_players.field(players::id))))
-        .inner_join(black_players.on(games::black_id.eq(black_players.field(players::id))))
-        .inner_join(events::table.on(games::event_id.eq(events::id)))
-        .inner_join(sites::table.on(games::site_id.eq(sites::id)))
-        .into_boxed();
-    let mut count_query = games::table.into_boxed();
+    if let Some(outcome) = query.outcome.clone() {
+        filtered = filtered.filter(games::result.eq(outcome));
+    }
 
-    // if let Some(speed) = query.speed {
-    //     sql_query = sql_query.filter(games::speed.eq(speed as i32));
-    //     count_query = count_query.filter(games::speed.eq(speed as i32));
-    // }
+    if let Some(start_date) = query.start_date.clone() {
+        filtered = filtered.filter(games::date.ge(start_date));
+    }
 
-    if let Some(outcome) = query.outcome {
-        sql_query = sql_query.filter(games::result.eq(outcome.clone()));
-        count_query = count_query.filter(games::result.eq(outcome));
+    if let Some(end_date) = query.end_date.clone() {
+        filtered = filtered.filter(games::date.le(end_date));
     }
 
-    if let Some(start_date) = query.start_date {
-        sql_query = sql_query.filter(games::date.ge(start_date.clone()));
-        count_query = count_query.filter(games::date.ge(start_date));
+    if let Some(tournament_id) = query.tournament_id {
+        filtered = filtered.filter(games::event_id.eq(tournament_id));
     }
 
-    if let Some(end_date) = query.end_date {
-        sql_query = sql_query.filter(games::date.le(end_date.clone()));
-        count_query = count_query.filter(games::date.le(end_date));
+    if let Some((first, last)) = query.eco_range.clone() {
+        filtered = filtered.filter(games::eco.between(first, last));
     }
 
-    if let Some(tournament_id) = query.tournament_id {
-        sql_query = sql_query.filter(games::event_id.eq(tournament_id));
-        count_query = count_query.filter(games::event_id.eq(tournament_id));
+    if let Some(opening) = query.opening.as_deref() {
+        filtered = filtered.filter(games::eco.eq_any(opening_ecos(opening)));
     }
 
-    if let Some(limit) = query_options.page_size {
-        sql_query = sql_query.limit(limit);
+    if let Some(min_moves) = query.min_moves {
+        // The last move of a game may only have white's half
+        filtered = filtered.filter(games::ply_count.ge(min_moves * 2 - 1));
     }
 
-    if let Some(page) = query_options.page {
-        sql_query = sql_query.offset((page - 1) * query_options.page_size.unwrap_or(10));
+    if let Some(text) = query
+        .text
+        .as_deref()
+        .map(str::trim)
+        .filter(|t| !t.is_empty())
+    {
+        let pattern = format!("%{}%", escape_like(text));
+        let named_players = || {
+            players::table
+                .filter(players::name.like(pattern.clone()).escape('\\'))
+                .select(players::id)
+        };
+        let named_events = events::table
+            .filter(events::name.like(pattern.clone()).escape('\\'))
+            .select(events::id);
+        let named_sites = sites::table
+            .filter(sites::name.like(pattern.clone()).escape('\\'))
+            .select(sites::id);
+        filtered = filtered.filter(
+            games::white_id
+                .eq_any(named_players())
+                .or(games::black_id.eq_any(named_players()))
+                .or(games::event_id.eq_any(named_events))
+                .or(games::site_id.eq_any(named_sites)),
+        );
     }
 
     match query.sides {
         Some(Sides::BlackWhite) => {
             if let Some(player1) = query.player1 {
-                sql_query = sql_query.filter(games::black_id.eq(player1));
-                count_query = count_query.filter(games::black_id.eq(player1));
+                filtered = filtered.filter(games::black_id.eq(player1));
             }
             if let Some(player2) = query.player2 {
-                sql_query = sql_query.filter(games::white_id.eq(player2));
-                count_query = count_query.filter(games::white_id.eq(player2));
+                filtered = filtered.filter(games::white_id.eq(player2));
             }
 
             if let Some(range1) = query.range1 {
-                sql_query = sql_query.filter(games::black_elo.between(range1.0, range1.1));
-                count_query = count_query.filter(games::black_elo.between(range1.0, range1.1));
+                filtered = filtered.filter(games::black_elo.between(range1.0, range1.1));
             }
 
             if let Some(range2) = query.range2 {
-                sql_query = sql_query.filter(games::white_elo.between(range2.0, range2.1));
-                count_query = count_query.filter(games::white_elo.between(range2.0, range2.1));
+                filtered = filtered.filter(games::white_elo.between(range2.0, range2.1));
             }
         }
         Some(Sides::WhiteBlack) => {
             if let Some(player1) = query.player1 {
-                sql_query = sql_query.filter(games::white_id.eq(player1));
-                count_query = count_query.filter(games::white_id.eq(player1));
+                filtered = filtered.filter(games::white_id.eq(player1));
             }
             if let Some(player2) = query.player2 {
-                sql_query = sql_query.filter(games::black_id.eq(player2));
-                count_query = count_query.filter(games::black_id.eq(player2));
+                filtered = filtered.filter(games::black_id.eq(player2));
             }
 
             if let Some(range1) = query.range1 {
-                sql_query = sql_query.filter(games::white_elo.between(range1.0, range1.1));
-                count_query = count_query.filter(games::white_elo.between(range1.0, range1.1));
+                filtered = filtered.filter(games::white_elo.between(range1.0, range1.1));
             }
 
             if let Some(range2) = query.range2 {
-                sql_query = sql_query.filter(games::black_elo.between(range2.0, range2.1));
-                count_query = count_query.filter(games::black_elo.between(range2.0, range2.1));
+                filtered = filtered.filter(games::black_elo.between(range2.0, range2.1));
             }
         }
         Some(Sides::Any) => {
             if let Some(player1) = query.player1 {
-                sql_query =
-                    sql_query.filter(games::white_id.eq(player1).or(games::black_id.eq(player1)));
-                count_query =
-                    count_query.filter(games::white_id.eq(player1).or(games::black_id.eq(player1)));
+                filtered =
+                    filtered.filter(games::white_id.eq(player1).or(games::black_id.eq(player1)));
             }
             if let Some(player2) = query.player2 {
-                sql_query =
-                    sql_query.filter(games::white_id.eq(player2).or(games::black_id.eq(player2)));
-                count_query =
-                    count_query.filter(games::white_id.eq(player2).or(games::black_id.eq(player2)));
+                filtered =
+                    filtered.filter(games::white_id.eq(player2).or(games::black_id.eq(player2)));
             }
 
             if let (Some(range1), Some(range2)) = (query.range1, query.range2) {
-                sql_query = sql_query.filter(
-                    games::white_elo
-                        .between(range1.0, range1.1)
-                        .or(games::black_elo.between(range1.0, range1.1))
-                        .or(games::white_elo
-                            .between(range2.0, range2.1)
-                            .or(games::black_elo.between(range2.0, range2.1))),
-                );
-                count_query = count_query.filter(
+                filtered = filtered.filter(
                     games::white_elo
                         .between(range1.0, range1.1)
                         .or(games::black_elo.between(range1.0, range1.1))
@@ -819,12 +1706,7 @@ pub async fn get_games(
                 );
             } else {
                 if let Some(range1) = query.range1 {
-                    sql_query = sql_query.filter(
-                        games::white_elo
-                            .between(range1.0, range1.1)
-                            .or(games::black_elo.between(range1.0, range1.1)),
-                    );
-                    count_query = count_query.filter(
+                    filtered = filtered.filter(
                         games::white_elo
                             .between(range1.0, range1.1)
                             .or(games::black_elo.between(range1.0, range1.1)),
@@ -832,12 +1714,7 @@ pub async fn get_games(
                 }
 
                 if let Some(range2) = query.range2 {
-                    sql_query = sql_query.filter(
-                        games::white_elo
-                            .between(range2.0, range2.1)
-                            .or(games::black_elo.between(range2.0, range2.1)),
-                    );
-                    count_query = count_query.filter(
+                    filtered = filtered.filter(
                         games::white_elo
                             .between(range2.0, range2.1)
                             .or(games::black_elo.between(range2.0, range2.1)),
@@ -848,49 +1725,75 @@ pub async fn get_games(
         None => {}
     }
 
-    sql_query = match query_options.sort {
+    filtered
+}
+
+/// Page of the games matching `query`, with their total unless `skip_count`. The page
+/// is picked from the games table alone so the filters and the sorting can use its
+/// indexes, and only its games are joined with their players, event and site.
+fn query_games(
+    db: &mut SqliteConnection,
+    query: &GameQuery,
+) -> Result<QueryResponse<Vec<NormalizedGame>>, Error> {
+    let query_options = query.options.clone().unwrap_or_default();
+
+    let mut page = filter_games(query);
+    page = match query_options.sort {
         GameSort::Id => match query_options.direction {
-            SortDirection::Asc => sql_query.order(games::id.asc()),
-            SortDirection::Desc => sql_query.order(games::id.desc()),
+            SortDirection::Asc => page.order(games::id.asc()),
+            SortDirection::Desc => page.order(games::id.desc()),
         },
         GameSort::Date => match query_options.direction {
-            SortDirection::Asc => sql_query.order((games::date.asc(), games::time.asc())),
-            SortDirection::Desc => sql_query.order((games::date.desc(), games::time.desc())),
+            SortDirection::Asc => page.order((games::date.asc(), games::time.asc())),
+            SortDirection::Desc => page.order((games::date.desc(), games::time.desc())),
         },
         GameSort::WhiteElo => match query_options.direction {
-            SortDirection::Asc => sql_query.order(games::white_elo.asc()),
-            SortDirection::Desc => sql_query.order(games::white_elo.desc()),
+            SortDirection::Asc => page.order(games::white_elo.asc()),
+            SortDirection::Desc => page.order(games::white_elo.desc()),
         },
         GameSort::BlackElo => match query_options.direction {
-            SortDirection::Asc => sql_query.order(games::black_elo.asc()),
-            SortDirection::Desc => sql_query.order(games::black_elo.desc()),
+            SortDirection::Asc => page.order(games::black_elo.asc()),
+            SortDirection::Desc => page.order(games::black_elo.desc()),
         },
         GameSort::PlyCount => match query_options.direction {
-            SortDirection::Asc => sql_query.order(games::ply_count.asc()),
-            SortDirection::Desc => sql_query.order(games::ply_count.desc()),
+            SortDirection::Asc => page.order(games::ply_count.asc()),
+            SortDirection::Desc => page.order(games::ply_count.desc()),
         },
     };
 
-    if !query_options.skip_count {
-        count = Some(
-            count_query
+    if let Some(limit) = query_options.page_size {
+        page = page.limit(limit);
+    }
+
+    if let Some(page_number) = query_options.page {
+        page = page.offset((page_number - 1) * query_options.page_size.unwrap_or(10));
+    }
+
+    let count = if query_options.skip_count {
+        None
+    } else {
+        Some(
+            filter_games(query)
                 .select(diesel::dsl::count(games::id))
                 .first(db)?,
-        );
-    }
+        )
+    };
 
-    // println!(
-    //     "{:?}\n",
-    //     diesel::debug_query::<diesel::sqlite::Sqlite, _>(&sql_query)
-    // );
+    let ids: Vec<i32> = page.select(games::id).load(db)?;
+    let mut found = search::games_by_id(db, ids.clone())?;
+    let data = ids.iter().filter_map(|id| found.remove(id)).collect();
 
-    let games: Vec<(Game, Player, Player, Event, Site)> = sql_query.load(db)?;
-    let normalized_games = normalize_games(games);
+    Ok(QueryResponse { data, count })
+}
 
-    Ok(QueryResponse {
-        data: normalized_games,
-        count,
-    })
+#[tauri::command]
+pub async fn get_games(
+    file: PathBuf,
+    query: GameQuery,
+    state: tauri::State<'_, AppState>,
+) -> Result<QueryResponse<Vec<NormalizedGame>>, Error> {
+    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+    query_games(db, &query)
 }
 
 fn normalize_games(games: Vec<(Game, Player, Player, Event, Site)>) -> Vec<NormalizedGame> {
@@ -1314,6 +2217,7 @@ pub async fn delete_database(
     let pool = &state.connection_pool;
     let path_str = file.to_str().unwrap();
     pool.remove(path_str);
+    forget_games(&state, &file);
 
     // delete file
     remove_file(path_str)?;
@@ -1341,6 +2245,7 @@ pub async fn delete_duplicated_games(
         );
         ",
     )?;
+    forget_games(&state, &file);
 
     Ok(())
 }
@@ -1353,6 +2258,7 @@ pub async fn delete_empty_games(
     let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
 
     diesel::delete(games::table.filter(games::ply_count.eq(0))).execute(db)?;
+    forget_games(&state, &file);
 
     Ok(())
 }
@@ -1498,6 +2404,19 @@ pub async fn export_to_pgn(
     Ok(())
 }
 
+/// Starting position and SAN moves of a game in a database
+pub fn get_game_moves(
+    file: &Path,
+    game_id: i32,
+    state: &State<AppState>,
+) -> Result<(String, Vec<String>), Error> {
+    let db = &mut get_db_or_create(state, file.to_str().unwrap(), ConnectionOptions::default())?;
+    let game: Game = games::table.filter(games::id.eq(game_id)).first(db)?;
+    let fen = game.fen.unwrap_or_else(|| Fen::default().to_string());
+    let moves = decode_moves(game.moves, Fen::from_ascii(fen.as_bytes())?)?;
+    Ok((fen, moves))
+}
+
 #[tauri::command]
 pub async fn delete_db_game(
     file: PathBuf,
@@ -1505,9 +2424,8 @@ pub async fn delete_db_game(
     state: tauri::State<'_, AppState>,
 ) -> Result<(), Error> {
     let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
-
-    diesel::delete(games::table.filter(games::id.eq(game_id))).execute(db)?;
-
+    edit::remove_games(db, &[game_id])?;
+    forget_games(&state, &file);
     Ok(())
 }
 
@@ -1552,6 +2470,7 @@ pub async fn merge_players(
         .do_update()
         .set(info::value.eq(player_count.to_string()))
         .execute(db)?;
+    forget_games(&state, &file);
 
     Ok(())
 }
@@ -1559,13 +2478,518 @@ pub async fn merge_players(
 #[tauri::command]
 pub fn clear_games(state: tauri::State<'_, AppState>) {
     let mut state = state.db_cache.lock().unwrap();
-    state.clear();
+    *state = GamesCache::default();
+}
+
+/// Databases in memory for the tests of the database modules
+#[cfg(test)]
+pub(crate) mod test_utils {
+    use diesel::{connection::SimpleConnection, Connection, SqliteConnection};
+    use pgn_reader::BufferedReader;
+
+    use super::{migrate, Importer, TempGame, CREATE_TABLES_SQL, MIGRATIONS};
+
+    /// A new database with the tables of the latest version
+    pub(crate) fn empty_database() -> SqliteConnection {
+        let mut db = SqliteConnection::establish(":memory:").unwrap();
+        db.batch_execute(CREATE_TABLES_SQL).unwrap();
+        migrate(&mut db, MIGRATIONS).unwrap();
+        db
+    }
+
+    /// Imports the games of `pgn`, indexing the positions of their first `plies`, and
+    /// returns them
+    pub(crate) fn import(db: &mut SqliteConnection, pgn: &str, plies: u16) -> Vec<TempGame> {
+        let mut importer = Importer::new(None, plies);
+        let games: Vec<TempGame> = BufferedReader::new(pgn.as_bytes())
+            .into_iter(&mut importer)
+            .flatten()
+            .flatten()
+            .collect();
+        for game in &games {
+            game.insert_to_db(db).unwrap();
+        }
+        games
+    }
+
+    /// A new database with the games of `pgn`, imported with `import`
+    pub(crate) fn database(pgn: &str, plies: u16) -> SqliteConnection {
+        let mut db = empty_database();
+        import(&mut db, pgn, plies);
+        db
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn imports_games_one_by_one() {
+        let pgn = b"\n\n[White \"A\"]\n\n1. e4 e5 1-0\n\nDownloaded from a3 archive\n\n\n\
+                    [White \"B\"]\n[FEN \"8/8/8/8/8/8/8/8 w - - 0 1\"]\n\n1. e4 *\n\
+                    [White \"C\"]\n\n1. d4 d5 0-1";
+        let mut importer = Importer::new(None, 0);
+        let games: Vec<TempGame> = BufferedReader::new(&pgn[..])
+            .into_iter(&mut importer)
+            .flatten()
+            .flatten()
+            .collect();
+        assert_eq!(games.len(), 2);
+        assert_eq!(games[0].white_name.as_deref(), Some("A"));
+        assert_eq!(games[0].moves.len(), 2);
+        assert_eq!(games[1].white_name.as_deref(), Some("C"));
+        assert_eq!(games[1].moves.len(), 2);
+        assert_eq!(importer.malformed_games, 1);
+    }
+
+    #[test]
+    fn imports_chess960_castling() {
+        let fen = "bqnbrkrn/pppppppp/8/8/8/8/PPPPPPPP/BQNBRKRN w KQkq - 0 1";
+        let pgn =
+            format!("[FEN \"{fen}\"]\n[SetUp \"1\"]\n[Variant \"Chess960\"]\n\n1. O-O O-O 2. e4 *");
+        let mut importer = Importer::new(None, 0);
+        let games: Vec<TempGame> = BufferedReader::new(pgn.as_bytes())
+            .into_iter(&mut importer)
+            .flatten()
+            .flatten()
+            .collect();
+        assert_eq!(importer.malformed_games, 0);
+        assert_eq!(games.len(), 1);
+        assert_eq!(games[0].fen.as_deref(), Some(fen));
+        let moves = decode_moves(games[0].moves.clone(), fen.parse().unwrap()).unwrap();
+        assert_eq!(moves, ["O-O", "O-O", "e4"]);
+    }
+
+    #[test]
+    fn counts_skipped_games() {
+        let pgn = b"[Date \"2020.01.01\"]\n[UTCTime \"10:00:00\"]\n\n1. e4 e5 1-0\n\n\
+                    [White \"Illegal\"]\n\n1. e4 e5 2. Ke3 Nc6 3. Nf3 *\n\n\
+                    [Date \"2024.01.01\"]\n[UTCTime \"10:00:00\"]\n\n1. d4 d5 0-1";
+        let timestamp = NaiveDate::from_ymd_opt(2022, 1, 1)
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap()
+            .and_utc()
+            .timestamp();
+        let mut importer = Importer::new(Some(timestamp), 0);
+        let games: Vec<TempGame> = BufferedReader::new(&pgn[..])
+            .into_iter(&mut importer)
+            .flatten()
+            .flatten()
+            .collect();
+        assert_eq!(games.len(), 1);
+        assert_eq!(games[0].date.as_deref(), Some("2024.01.01"));
+        assert_eq!(importer.malformed_games, 1);
+        assert_eq!(importer.outdated_games, 1);
+    }
+
+    #[test]
+    fn migrations_apply_once() {
+        let db = &mut SqliteConnection::establish(":memory:").unwrap();
+        db.batch_execute(CREATE_TABLES_SQL).unwrap();
+        let migrations = [
+            ("1.1.0", "CREATE TABLE A (ID INTEGER);"),
+            ("1.9.0", "CREATE TABLE B (ID INTEGER);"),
+            ("1.10.0", "CREATE TABLE C (ID INTEGER);"),
+        ];
+        // Databases from before versioning start at the first version
+        assert_eq!(migrate(db, &migrations[..1]).unwrap(), "1.1.0");
+        assert_eq!(migrate(db, &migrations).unwrap(), "1.10.0");
+        assert_eq!(migrate(db, &migrations).unwrap(), "1.10.0");
+        db.batch_execute("SELECT * FROM A; SELECT * FROM B; SELECT * FROM C;")
+            .unwrap();
+        assert_eq!(migrate(db, MIGRATIONS).unwrap(), "1.10.0");
+    }
+
+    #[test]
+    fn counts_bytes_read() {
+        let read = Arc::new(AtomicU64::new(0));
+        let mut reader = CountingReader {
+            inner: &b"1. e4 e5 *\n"[..],
+            read: read.clone(),
+        };
+        let mut buf = [0; 4];
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(read.load(Ordering::Relaxed), 4);
+        std::io::copy(&mut reader, &mut std::io::sink()).unwrap();
+        assert_eq!(read.load(Ordering::Relaxed), 11);
+    }
+
+    #[test]
+    fn appends_to_databases() {
+        let db = &mut test_utils::empty_database();
+        let import = |db: &mut SqliteConnection, pgn: &str, db_exists, cancelled| {
+            db.transaction(|db| {
+                import_games(
+                    db,
+                    pgn.as_bytes(),
+                    &mut Importer::new(None, 20),
+                    DuplicatePolicy::Skip,
+                    db_exists,
+                    &AtomicBool::new(cancelled),
+                    |_| {},
+                )
+            })
+        };
+        let first = "[White \"A\"]\n[Black \"B\"]\n\n1. e4 e5 1-0\n\n\
+                     [White \"A\"]\n[Black \"C\"]\n\n1. d4 d5 0-1";
+        let summary = import(db, first, false, false).unwrap();
+        assert_eq!((summary.imported, summary.total_games), (2, 2));
+
+        // Existing players are reused and duplicates of existing games skipped
+        let second = "[White \"A\"]\n[Black \"B\"]\n\n1. e4 e5 1-0\n\n\
+                      [White \"B\"]\n[Black \"C\"]\n\n1. c4 e5 *\n\n\
+                      [White \"B\"]\n\n1. e4 e5 2. Ke3 *";
+        let summary = import(db, second, true, false).unwrap();
+        assert_eq!((summary.imported, summary.skipped), (1, 2));
+        assert_eq!((summary.duplicates, summary.malformed), (1, 1));
+        assert_eq!(summary.total_games, 3);
+        let players: i64 = players::table.count().get_result(db).unwrap();
+        assert_eq!(players, 4);
+        assert_eq!(malformed_games(db).unwrap(), 1);
+        let indexed: i64 = positions::table
+            .filter(positions::hash.eq(position_hash(&Chess::default())))
+            .count()
+            .get_result(db)
+            .unwrap();
+        assert_eq!(indexed, 3);
+
+        // and a cancelled import stops before the next game
+        let third = "[White \"D\"]\n\n1. f4 *\n\n[White \"E\"]\n\n1. g4 *";
+        let summary = import(db, third, true, true).unwrap();
+        assert!(summary.cancelled);
+        assert_eq!(summary.imported, 0);
+        let games: i64 = games::table.count().get_result(db).unwrap();
+        assert_eq!(games, 3);
+        let count: Option<String> = info::table
+            .filter(info::name.eq("GameCount"))
+            .select(info::value)
+            .first(db)
+            .unwrap();
+        assert_eq!(count.as_deref(), Some("3"));
+    }
+
+    const FIXTURE: &str =
+        "[Event \"Tata Steel\"]\n[Site \"Wijk aan Zee\"]\n[Date \"2023.01.14\"]\n\
+                           [White \"Carlsen, Magnus\"]\n[Black \"Giri, Anish\"]\n\
+                           [WhiteElo \"2850\"]\n[BlackElo \"2760\"]\n[ECO \"B90\"]\n\
+                           [Result \"1-0\"]\n\n\
+                           1. e4 c5 2. Nf3 d6 3. d4 cxd4 4. Nxd4 Nf6 5. Nc3 a6 1-0\n\n\
+                           [Event \"Norway Chess\"]\n[Site \"Stavanger\"]\n[Date \"2023.06.01\"]\n\
+                           [White \"Giri, Anish\"]\n[Black \"Carlsen, Magnus\"]\n\
+                           [WhiteElo \"2770\"]\n[BlackElo \"2855\"]\n[ECO \"D37\"]\n\
+                           [Result \"1/2-1/2\"]\n\n\
+                           1. d4 d5 2. c4 e6 3. Nc3 Nf6 4. Nf3 Be7 1/2-1/2\n\n\
+                           [Event \"Club 50%_night\"]\n[Site \"Home\"]\n[Date \"2024.02.01\"]\n\
+                           [White \"Doe, John\"]\n[Black \"Roe, Jane\"]\n[WhiteElo \"1500\"]\n\
+                           [Result \"0-1\"]\n\n1. f3 e5 2. g4 Qh4# 0-1";
+
+    fn fixture() -> SqliteConnection {
+        let mut db = test_utils::database(FIXTURE, 0);
+        db.batch_execute(INDEXES_SQL).unwrap();
+        db
+    }
+
+    /// White players of the page of games matching `query`, with the total
+    fn whites(db: &mut SqliteConnection, query: GameQuery) -> (Vec<String>, Option<i64>) {
+        let response = query_games(db, &query).unwrap();
+        let whites = response.data.into_iter().map(|game| game.white).collect();
+        (whites, response.count)
+    }
+
+    #[test]
+    fn filters_games() {
+        let db = &mut fixture();
+        let carlsen: i32 = players::table
+            .filter(players::name.eq("Carlsen, Magnus"))
+            .select(players::id)
+            .first(db)
+            .unwrap();
+        let norway: i32 = events::table
+            .filter(events::name.eq("Norway Chess"))
+            .select(events::id)
+            .first(db)
+            .unwrap();
+        let both = ["Giri, Anish", "Carlsen, Magnus"];
+
+        let by_player = GameQuery {
+            player1: Some(carlsen),
+            sides: Some(Sides::Any),
+            ..Default::default()
+        };
+        assert_eq!(
+            whites(db, by_player.clone()),
+            (both.map(String::from).to_vec(), Some(2))
+        );
+        let as_white = GameQuery {
+            sides: Some(Sides::WhiteBlack),
+            ..by_player.clone()
+        };
+        assert_eq!(whites(db, as_white).1, Some(1));
+        let won = GameQuery {
+            outcome: Some("1-0".to_string()),
+            ..by_player
+        };
+        assert_eq!(whites(db, won).0, ["Carlsen, Magnus"]);
+
+        let rated = GameQuery {
+            range1: Some((2800, 2900)),
+            sides: Some(Sides::WhiteBlack),
+            ..Default::default()
+        };
+        assert_eq!(whites(db, rated).0, ["Carlsen, Magnus"]);
+        let sicilians = GameQuery {
+            eco_range: Some(("B20".to_string(), "B99".to_string())),
+            ..Default::default()
+        };
+        assert_eq!(whites(db, sicilians).0, ["Carlsen, Magnus"]);
+        let najdorf = GameQuery {
+            opening: Some("najdorf".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(whites(db, najdorf).0, ["Carlsen, Magnus"]);
+        let in_2023 = GameQuery {
+            start_date: Some("2023.06.01".to_string()),
+            end_date: Some("2023.12.31".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(whites(db, in_2023).0, ["Giri, Anish"]);
+        let event = GameQuery {
+            tournament_id: Some(norway),
+            ..Default::default()
+        };
+        assert_eq!(whites(db, event).0, ["Giri, Anish"]);
+        let long = GameQuery {
+            min_moves: Some(5),
+            ..Default::default()
+        };
+        assert_eq!(whites(db, long).0, ["Carlsen, Magnus"]);
+    }
+
+    #[test]
+    fn searches_tags_and_pages() {
+        let db = &mut fixture();
+        let text = |text: &str| GameQuery {
+            text: Some(text.to_string()),
+            ..Default::default()
+        };
+        assert_eq!(whites(db, text("stavanger")).0, ["Giri, Anish"]);
+        assert_eq!(whites(db, text("roe")).0, ["Doe, John"]);
+        // Wildcards are matched literally
+        assert_eq!(whites(db, text("%_")).0, ["Doe, John"]);
+        assert_eq!(whites(db, text("a_n")).1, Some(0));
+
+        let page = GameQuery {
+            options: Some(QueryOptions {
+                page: Some(2),
+                page_size: Some(1),
+                sort: GameSort::Date,
+                direction: SortDirection::Asc,
+                skip_count: false,
+            }),
+            ..Default::default()
+        };
+        assert_eq!(whites(db, page), (vec!["Giri, Anish".to_string()], Some(3)));
+    }
+
+    /// `count` games of a few hundred players with the tags of online games, each one
+    /// with the first moves of a common opening
+    fn generated_pgn(count: usize) -> String {
+        const OPENINGS: [&str; 4] = [
+            "1. e4 e5 2. Nf3 Nc6 3. Bb5 a6 4. Ba4 Nf6 5. O-O Be7",
+            "1. d4 Nf6 2. c4 e6 3. Nc3 Bb4 4. e3 O-O 5. Bd3 d5",
+            "1. e4 c5 2. Nf3 d6 3. d4 cxd4 4. Nxd4 Nf6 5. Nc3 a6",
+            "1. c4 e5 2. Nc3 Nf6 3. g3 d5 4. cxd5 Nxd5 5. Bg2 Nb6",
+        ];
+        const RESULTS: [&str; 3] = ["1-0", "0-1", "1/2-1/2"];
+        let mut pgn = String::new();
+        for index in 0..count {
+            let result = RESULTS[index % 3];
+            pgn += &format!(
+                "[Event \"Rated blitz game {}\"]\n[Site \"https://example.org/{index}\"]\n\
+                 [Date \"2024.{:02}.{:02}\"]\n[Round \"-\"]\n[White \"player{}\"]\n\
+                 [Black \"player{}\"]\n[Result \"{result}\"]\n[WhiteElo \"{}\"]\n\
+                 [BlackElo \"{}\"]\n[TimeControl \"180+2\"]\n\n{} {result}\n\n",
+                index % 50,
+                1 + index % 12,
+                1 + index % 28,
+                index % 300,
+                (index * 7 + 1) % 300,
+                1500 + index % 700,
+                1500 + index * 3 % 700,
+                OPENINGS[index % OPENINGS.len()],
+            );
+        }
+        pgn
+    }
+
+    #[test]
+    fn imports_games_in_bulk() {
+        let pgn = generated_pgn(10_000);
+        // Reading the games is what any import takes, the rest is writing them
+        let start = Instant::now();
+        let mut importer = Importer::new(None, POSITION_INDEX_PLIES);
+        let read = BufferedReader::new(pgn.as_bytes())
+            .into_iter(&mut importer)
+            .flatten()
+            .flatten()
+            .count();
+        let reading = start.elapsed();
+        assert_eq!(read, 10_000);
+
+        let db = &mut test_utils::empty_database();
+        let start = Instant::now();
+        let summary = db
+            .transaction::<_, Error, _>(|db| {
+                import_games(
+                    db,
+                    pgn.as_bytes(),
+                    &mut Importer::new(None, POSITION_INDEX_PLIES),
+                    DuplicatePolicy::KeepBoth,
+                    false,
+                    &AtomicBool::new(false),
+                    |_| {},
+                )
+            })
+            .unwrap();
+        let importing = start.elapsed();
+        assert_eq!((summary.imported, summary.total_games), (10_000, 10_000));
+        let players: i64 = players::table.count().get_result(db).unwrap();
+        assert_eq!(players, 300);
+        // The position index dropped for the import is back
+        assert_eq!(index_count(db).unwrap(), 10);
+
+        // Generous, so that only writing much slower than reading fails it
+        assert!(
+            importing < reading * 20,
+            "{importing:?} to import the games, {reading:?} to read them"
+        );
+    }
+
+    #[test]
+    fn splits_files_between_games() {
+        let games = [
+            "[Event \"A\"]\n[White \"A\"]\n\n1. e4 {\n[%clk 0:03:00] } e5 1-0\n\n",
+            "[Event \"B\"]\n\n1. d4 *\n",
+            "[Event \"C\"]\n\n1. c4 *\n\n1. f4 *\n\n",
+            "[Event \"D\"]\n\n1. g3 *\n",
+        ];
+        let pgn = games.concat();
+        let starts: Vec<usize> = games
+            .iter()
+            .scan(0, |offset, game| {
+                let start = *offset;
+                *offset += game.len();
+                Some(start)
+            })
+            .collect();
+        assert_eq!(chunk_offsets(pgn.as_bytes(), 1), starts);
+        assert_eq!(chunk_offsets(pgn.as_bytes(), 2), [starts[0], starts[2]]);
+        assert_eq!(chunk_offsets(b"", CHUNK_GAMES), [0]);
+    }
+
+    /// Imports `pgn` into a new database, mapped in memory or as a stream, giving the
+    /// summary and the sites of the games in the order of their ids
+    fn import_into_new(pgn: &str, mapped: bool) -> (ImportSummary, Vec<String>) {
+        let (mut db, summary) = import_into_memory(pgn.as_bytes(), mapped).unwrap();
+        let db = &mut db;
+        let sites: Vec<Option<String>> = games::table
+            .inner_join(sites::table)
+            .order(games::id)
+            .select(sites::name)
+            .load(db)
+            .unwrap();
+        (summary, sites.into_iter().flatten().collect())
+    }
+
+    #[test]
+    fn imports_mapped_files_in_parallel() {
+        let pgn = generated_pgn(10_000);
+        let (streamed, streamed_sites) = import_into_new(&pgn, false);
+        let (mapped, sites) = import_into_new(&pgn, true);
+        assert_eq!(mapped.imported, 10_000);
+        assert_eq!(mapped, streamed);
+        // The games are in the order of the file
+        assert_eq!(sites, streamed_sites);
+        assert_eq!(sites[1234], "https://example.org/1234");
+    }
+
+    #[test]
+    fn keeps_malformed_games_to_their_chunk() {
+        let games = generated_pgn(3000);
+        // An illegal move and a comment never closed, in the second chunk
+        let broken = "[White \"Broken\"]\n\n1. e4 e5 2. Ke3 { never closed\n\n";
+        let (split, _) = games.match_indices("[Event").nth(1500).unwrap();
+        let pgn = format!("{}{broken}{}", &games[..split], &games[split..]);
+
+        // Read as a stream, the comment takes the rest of the file
+        let (streamed, _) = import_into_new(&pgn, false);
+        assert_eq!((streamed.imported, streamed.malformed), (1500, 1));
+        // and only the rest of its chunk when mapped
+        let (mapped, sites) = import_into_new(&pgn, true);
+        assert_eq!((mapped.imported, mapped.malformed), (3000 - 499, 1));
+        assert_eq!(sites[1499], "https://example.org/1499");
+        assert_eq!(sites[1500], "https://example.org/1999");
+    }
+
+    #[test]
+    fn reports_skipped_games_as_they_are_read() {
+        let db = &mut test_utils::empty_database();
+        let pgn = "1. e4 e5 2. Ke3 *\n\n1. d4 *\n\n1. d4 *";
+        let mut reported = Vec::new();
+        let summary = import_games(
+            db,
+            pgn.as_bytes(),
+            &mut Importer::new(None, 0),
+            DuplicatePolicy::Skip,
+            false,
+            &AtomicBool::new(false),
+            |summary| reported.push((summary.imported, summary.skipped)),
+        )
+        .unwrap();
+        assert_eq!(reported, [(0, 0), (1, 2)]);
+        assert_eq!((summary.malformed, summary.duplicates), (1, 1));
+        assert!(!summary.cancelled);
+    }
+
+    #[test]
+    fn estimates_the_time_left() {
+        let elapsed = Duration::from_secs(10);
+        assert_eq!(eta_ms(elapsed, 0, 100), None);
+        assert_eq!(eta_ms(elapsed, 25, 100), Some(30_000));
+        assert_eq!(eta_ms(elapsed, 100, 100), Some(0));
+    }
+
+    #[test]
+    fn describes_databases() {
+        let db = &mut fixture();
+        let info = database_info(db).unwrap();
+        assert_eq!(
+            (info.game_count, info.player_count, info.event_count),
+            (3, 5, 4)
+        );
+        assert_eq!(info.first_date.as_deref(), Some("2023.01.14"));
+        assert_eq!(info.last_date.as_deref(), Some("2024.02.01"));
+        assert_eq!(info.version, "1.2.0");
+        assert!(info.indexed && info.position_index);
+        assert_eq!(info.position_plies, None);
+        assert_eq!(info.title, "Untitled");
+
+        position_index::set_indexed_plies(db, 12).unwrap();
+        assert_eq!(database_info(db).unwrap().position_plies, Some(12));
+    }
+
+    #[test]
+    fn creates_missing_indexes() {
+        let db = &mut fixture();
+        db.batch_execute(DELETE_INDEXES_SQL).unwrap();
+        db.batch_execute("DROP INDEX positions_game_idx;").unwrap();
+        assert!(!check_index_exists(db).unwrap());
+        assert_eq!(optimize(db).unwrap(), 10);
+        assert_eq!(optimize(db).unwrap(), 0);
+    }
+
     #[test]
     fn home_row() {
         use shakmaty::Board;
@@ -14,6 +14,21 @@ pub struct Puzzle {
     pub rating_deviation: i32,
     pub popularity: i32,
     pub nb_plays: i32,
+    /// Lichess themes of the puzzle separated by spaces, empty for puzzle databases
+    /// downloaded before they were kept
+    pub themes: String,
+}
+
+#[derive(Insertable, Debug)]
+#[diesel(table_name = puzzles)]
+pub struct NewPuzzle<'a> {
+    pub fen: &'a str,
+    pub moves: &'a str,
+    pub rating: i32,
+    pub rating_deviation: i32,
+    pub popularity: i32,
+    pub nb_plays: i32,
+    pub themes: &'a str,
 }
 
 #[derive(Default, Debug, Queryable, Serialize, Deserialize, Identifiable, Clone, Type)]
@@ -83,6 +98,21 @@ pub struct NewGame<'a> {
     pub pawn_home: i32,
 }
 
+/// A position reached by a game, for finding the games of a position without
+/// replaying them
+#[derive(Insertable, Debug)]
+#[diesel(table_name = positions)]
+pub struct NewPosition<'a> {
+    pub hash: i64,
+    pub game_id: i32,
+    pub ply: i32,
+    /// Move played from the position, encoded like the moves of the game
+    pub move_: Option<i32>,
+    pub result: Option<&'a str>,
+    /// Average rating of the players
+    pub rating: Option<i32>,
+}
+
 #[derive(Default, Debug, Queryable, Serialize, Deserialize, Identifiable, Clone)]
 pub struct Site {
     pub id: i32,
@@ -1,6 +1,12 @@
+use std::collections::HashMap;
+
 use crate::db::models::{Event, Game, NewEvent, NewGame, NewPlayer, NewSite, Player, Site};
 use diesel::prelude::*;
 
+/// Names kept by `NameIds` of each kind at most, so importing huge files doesn't keep
+/// all of them in memory
+const MAX_CACHED_NAMES: usize = 100_000;
+
 /// Creates a new player in the database, and returns the player's ID.
 /// If the player already exists, returns the ID of the existing player.
 pub fn create_player(
@@ -60,6 +66,61 @@ pub fn create_site(conn: &mut SqliteConnection, name: &str) -> Result<Site, dies
     }
 }
 
+/// Ids of the players, events and sites created or found while adding games, so the
+/// names repeated across games are only looked up once
+#[derive(Debug, Default)]
+pub struct NameIds {
+    players: HashMap<String, i32>,
+    events: HashMap<String, i32>,
+    sites: HashMap<String, i32>,
+}
+
+fn cached_id(
+    ids: &mut HashMap<String, i32>,
+    name: &str,
+    create: impl FnOnce() -> Result<i32, diesel::result::Error>,
+) -> Result<i32, diesel::result::Error> {
+    if let Some(&id) = ids.get(name) {
+        return Ok(id);
+    }
+    let id = create()?;
+    if ids.len() >= MAX_CACHED_NAMES {
+        ids.clear();
+    }
+    ids.insert(name.to_string(), id);
+    Ok(id)
+}
+
+impl NameIds {
+    pub fn player(
+        &mut self,
+        conn: &mut SqliteConnection,
+        name: &str,
+    ) -> Result<i32, diesel::result::Error> {
+        cached_id(
+            &mut self.players,
+            name,
+            || Ok(create_player(conn, name)?.id),
+        )
+    }
+
+    pub fn event(
+        &mut self,
+        conn: &mut SqliteConnection,
+        name: &str,
+    ) -> Result<i32, diesel::result::Error> {
+        cached_id(&mut self.events, name, || Ok(create_event(conn, name)?.id))
+    }
+
+    pub fn site(
+        &mut self,
+        conn: &mut SqliteConnection,
+        name: &str,
+    ) -> Result<i32, diesel::result::Error> {
+        cached_id(&mut self.sites, name, || Ok(create_site(conn, name)?.id))
+    }
+}
+
 /// Creates a new game in the database, and returns the game's ID.
 pub fn create_game(
     conn: &mut SqliteConnection,
@@ -0,0 +1,468 @@
+use diesel::{connection::DefaultLoadingMode, prelude::*};
+use log::info;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use shakmaty::{
+    fen::Fen, san::SanPlus, Board, ByColor, ByRole, Chess, Color, Piece, Position, Role, Square,
+};
+use specta::Type;
+use std::{
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
+use tauri::AppHandle;
+use tauri_specta::Event;
+
+use crate::{
+    db::{
+        encoding::decode_move,
+        get_db_or_create, get_material_count, material_value,
+        schema::games,
+        search::{games_by_id, is_material_reachable, PositionMatch},
+        ConnectionOptions, MaterialCount,
+    },
+    error::Error,
+    AppState,
+};
+
+/// Games replayed between two progress events and cancellation checks
+const CHUNK_SIZE: usize = 10_000;
+
+/// A piece, or an empty square, that a square must or must not hold
+#[derive(Deserialize, Debug, Clone, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct SquareConstraint {
+    /// Name of the square, like `d4`
+    pub square: String,
+    /// FEN letter of the piece, none for an empty square
+    pub piece: Option<String>,
+    /// Whether the square must hold anything but `piece`
+    #[serde(default)]
+    pub absent: bool,
+}
+
+#[derive(Deserialize, Debug, Clone, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct PatternQuery {
+    /// Pieces of each side, like `KRBvKRN`, which a position must have exactly. Kings
+    /// can be left out.
+    pub material: Option<String>,
+    /// Whether `material` matches with any number of pawns
+    #[serde(default)]
+    pub any_pawns: bool,
+    /// Constraints on some squares, every other square can hold anything
+    #[serde(default)]
+    pub squares: Vec<SquareConstraint>,
+}
+
+fn invalid(reason: impl Into<String>) -> Error {
+    Error::InvalidPattern {
+        reason: reason.into(),
+    }
+}
+
+fn parse_side(pieces: &str) -> Result<ByRole<u8>, Error> {
+    let mut material = Board::empty().material().white;
+    for c in pieces.chars() {
+        match Role::from_char(c.to_ascii_lowercase()) {
+            Some(Role::King) => (),
+            Some(role) => *material.get_mut(role) += 1,
+            None => return Err(invalid(format!("unknown piece {c}"))),
+        }
+    }
+    Ok(material)
+}
+
+/// Pieces of a material signature like `KRBvKRN`, without the kings
+fn parse_material(signature: &str) -> Result<ByColor<ByRole<u8>>, Error> {
+    let (white, black) = signature
+        .split_once('v')
+        .ok_or_else(|| invalid(format!("{signature} is not like KRBvKRN")))?;
+    Ok(ByColor {
+        white: parse_side(white)?,
+        black: parse_side(black)?,
+    })
+}
+
+/// A query checked against every position of a game
+#[derive(Debug)]
+struct Pattern {
+    material: Option<ByColor<ByRole<u8>>>,
+    any_pawns: bool,
+    /// Squares with the piece they must hold, or must not hold when the flag is unset
+    squares: Vec<(Square, Option<Piece>, bool)>,
+    /// Least material of a position matching the pattern, replaying a game stops below it
+    least_material: MaterialCount,
+    /// Most material a game can end with after going through the pattern
+    most_material: Option<MaterialCount>,
+}
+
+impl Pattern {
+    fn new(query: &PatternQuery) -> Result<Pattern, Error> {
+        let material = query.material.as_deref().map(parse_material).transpose()?;
+        let squares = query
+            .squares
+            .iter()
+            .map(|constraint| {
+                let square: Square = constraint
+                    .square
+                    .parse()
+                    .map_err(|_| invalid(format!("unknown square {}", constraint.square)))?;
+                let piece = match constraint.piece.as_deref() {
+                    None => None,
+                    Some(letter) => Some(
+                        letter
+                            .chars()
+                            .next()
+                            .filter(|_| letter.len() == 1)
+                            .and_then(Piece::from_char)
+                            .ok_or_else(|| invalid(format!("unknown piece {letter}")))?,
+                    ),
+                };
+                Ok((square, piece, !constraint.absent))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        let signature_value = material.map(|material| {
+            material.map(|mut side| {
+                if query.any_pawns {
+                    side.pawn = 0;
+                }
+                material_value(&side)
+            })
+        });
+        let mut required = Board::empty().material();
+        for (_, piece, present) in &squares {
+            if let (Some(piece), true) = (piece, present) {
+                if piece.role != Role::King {
+                    *required.get_mut(piece.color).get_mut(piece.role) += 1;
+                }
+            }
+        }
+        let required = required.map(|side| material_value(&side));
+        let least_material = match signature_value {
+            Some(value) => ByColor {
+                white: value.white.max(required.white),
+                black: value.black.max(required.black),
+            },
+            None => required,
+        };
+
+        Ok(Pattern {
+            material,
+            any_pawns: query.any_pawns,
+            squares,
+            least_material,
+            most_material: signature_value.filter(|_| !query.any_pawns),
+        })
+    }
+
+    fn matches(&self, board: &Board) -> bool {
+        if let Some(material) = &self.material {
+            let found = board.material();
+            let same = |color: Color| {
+                let (mut found, mut wanted) = (*found.get(color), *material.get(color));
+                found.king = 0;
+                if self.any_pawns {
+                    found.pawn = 0;
+                    wanted.pawn = 0;
+                }
+                found == wanted
+            };
+            if !same(Color::White) || !same(Color::Black) {
+                return false;
+            }
+        }
+        self.squares
+            .iter()
+            .all(|(square, piece, present)| (board.piece_at(*square) == *piece) == *present)
+    }
+
+    /// Ply of the first position of a game matching the pattern, with the move played
+    /// from it
+    fn first_match(&self, moves: &[u8], fen: &Option<String>) -> Option<(i32, String)> {
+        let mut chess: Chess = match fen {
+            Some(fen) => Fen::from_ascii(fen.as_bytes())
+                .ok()?
+                .into_position(shakmaty::CastlingMode::Chess960)
+                .ok()?,
+            None => Chess::default(),
+        };
+        for ply in 0..=moves.len() {
+            let board = chess.board();
+            if !is_material_reachable(&self.least_material, &get_material_count(board)) {
+                return None;
+            }
+            let next = moves.get(ply).and_then(|byte| decode_move(*byte, &chess));
+            if self.matches(board) {
+                let san = next.map_or("*".to_string(), |m| {
+                    SanPlus::from_move(chess.clone(), &m).to_string()
+                });
+                return Some((ply as i32, san));
+            }
+            chess.play_unchecked(&next?);
+        }
+        None
+    }
+}
+
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PatternSearch {
+    /// Games in which the pattern occurs
+    pub games: usize,
+    /// Page of those games, the last imported first, at the first position matching
+    pub matches: Vec<PositionMatch>,
+    /// Whether the search was cancelled, the counts then only cover the games scanned
+    pub cancelled: bool,
+}
+
+#[derive(Clone, Type, Serialize, Event)]
+pub struct PatternProgress {
+    pub id: String,
+    pub progress: f64,
+    /// Games matching so far
+    pub games: usize,
+    pub finished: bool,
+}
+
+type PatternRow = (i32, Vec<u8>, Option<String>, i32, i32);
+
+/// Replays the games of the database, newest first, to find the ones going through
+/// `pattern`, keeping the first `limit`
+fn scan_games(
+    db: &mut SqliteConnection,
+    pattern: &Pattern,
+    limit: usize,
+    cancelled: &AtomicBool,
+    mut progress: impl FnMut(usize, usize),
+) -> Result<PatternSearch, Error> {
+    let mut found = 0;
+    let mut page = Vec::new();
+    let mut scanned = 0;
+    let mut rows = games::table
+        .select((
+            games::id,
+            games::moves,
+            games::fen,
+            games::white_material,
+            games::black_material,
+        ))
+        .order(games::id.desc())
+        .load_iter::<PatternRow, DefaultLoadingMode>(db)?;
+    loop {
+        if cancelled.load(Ordering::Relaxed) {
+            break;
+        }
+        let chunk = rows
+            .by_ref()
+            .take(CHUNK_SIZE)
+            .collect::<Result<Vec<PatternRow>, _>>()?;
+        if chunk.is_empty() {
+            break;
+        }
+        let matches: Vec<(i32, i32, String)> = chunk
+            .par_iter()
+            .filter_map(|(id, moves, fen, white_material, black_material)| {
+                let end_material = ByColor {
+                    white: *white_material as u8,
+                    black: *black_material as u8,
+                };
+                if let Some(most) = &pattern.most_material {
+                    if !is_material_reachable(&end_material, most) {
+                        return None;
+                    }
+                }
+                let (ply, san) = pattern.first_match(moves, fen)?;
+                Some((*id, ply, san))
+            })
+            .collect();
+        scanned += chunk.len();
+        found += matches.len();
+        page.extend(matches.into_iter().take(limit - page.len()));
+        progress(scanned, found);
+    }
+    drop(rows);
+
+    let ids = page.iter().map(|(id, _, _)| *id).collect();
+    let mut games = games_by_id(db, ids)?;
+    Ok(PatternSearch {
+        games: found,
+        matches: page
+            .into_iter()
+            .filter_map(|(id, ply, move_)| {
+                Some(PositionMatch {
+                    game: games.remove(&id)?,
+                    ply,
+                    move_,
+                })
+            })
+            .collect(),
+        cancelled: cancelled.load(Ordering::Relaxed),
+    })
+}
+
+/// Finds the games going through a material signature and piece placement, with the
+/// first `limit` of them. Slower than `search_exact_position` since the games are
+/// replayed, though a material signature skips the ones ending with more material. Can
+/// be cancelled with `cancel_pattern_search(id)`.
+#[tauri::command]
+pub async fn search_pattern(
+    id: String,
+    file: PathBuf,
+    query: PatternQuery,
+    limit: usize,
+    state: tauri::State<'_, AppState>,
+    app: AppHandle,
+) -> Result<PatternSearch, Error> {
+    let pattern = Pattern::new(&query)?;
+    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+    let total = games::table.count().get_result::<i64>(db)?.max(1);
+
+    let start = Instant::now();
+    let cancelled = Arc::new(AtomicBool::new(false));
+    state.pattern_searches.insert(id.clone(), cancelled.clone());
+    let result = scan_games(db, &pattern, limit, &cancelled, |scanned, games| {
+        let _ = PatternProgress {
+            id: id.clone(),
+            progress: scanned as f64 / total as f64 * 100.0,
+            games,
+            finished: false,
+        }
+        .emit_all(&app);
+    });
+    state.pattern_searches.remove(&id);
+
+    let search = result?;
+    info!("pattern search finished in {:?}", start.elapsed());
+    PatternProgress {
+        id,
+        progress: 100.0,
+        games: search.games,
+        finished: true,
+    }
+    .emit_all(&app)?;
+    Ok(search)
+}
+
+#[tauri::command]
+#[specta::specta]
+pub fn cancel_pattern_search(id: String, state: tauri::State<'_, AppState>) {
+    if let Some(cancelled) = state.pattern_searches.get(&id) {
+        cancelled.store(true, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::db::test_utils::database;
+
+    const GAMES: &str = "[White \"Endgame\"]\n[FEN \"r3k3/8/2n5/8/8/2B5/8/R3K3 w - - 0 1\"]\n\n\
+                         1. Ke2 Kd8 *\n\n\
+                         [White \"Trade\"]\n[FEN \"r2qk3/8/2n5/8/8/2B5/8/R2QK3 w - - 0 1\"]\n\n\
+                         1. Qxd8+ Kxd8 *\n\n\
+                         [White \"Panov\"]\n\n1. e4 c6 2. d4 d5 3. exd5 cxd5 4. c4 Nf6 5. Nc3 e6 \
+                         6. Nf3 Be7 7. cxd5 Nxd5 *\n\n\
+                         [White \"Open\"]\n\n1. e4 e5 *";
+
+    fn search(db: &mut SqliteConnection, query: PatternQuery, limit: usize) -> PatternSearch {
+        let pattern = Pattern::new(&query).unwrap();
+        scan_games(db, &pattern, limit, &AtomicBool::new(false), |_, _| ()).unwrap()
+    }
+
+    fn material(signature: &str) -> PatternQuery {
+        PatternQuery {
+            material: Some(signature.to_string()),
+            any_pawns: false,
+            squares: Vec::new(),
+        }
+    }
+
+    fn square(square: &str, piece: Option<&str>, absent: bool) -> SquareConstraint {
+        SquareConstraint {
+            square: square.to_string(),
+            piece: piece.map(str::to_string),
+            absent,
+        }
+    }
+
+    #[test]
+    fn finds_material_signatures() {
+        let db = &mut database(GAMES, 0);
+        let found = search(db, material("KRBvKRN"), 10);
+        assert_eq!(found.games, 2);
+        assert!(!found.cancelled);
+        let plies: Vec<(&str, i32, &str)> = found
+            .matches
+            .iter()
+            .map(|m| (m.game.white.as_str(), m.ply, m.move_.as_str()))
+            .collect();
+        assert_eq!(plies, [("Trade", 2, "*"), ("Endgame", 0, "Ke2")]);
+
+        assert_eq!(search(db, material("RBvRN"), 1).matches.len(), 1);
+        assert_eq!(search(db, material("KRBvKR"), 10).games, 0);
+
+        let mut with_pawns = material("KvK");
+        with_pawns.any_pawns = true;
+        assert_eq!(search(db, with_pawns, 10).games, 0);
+    }
+
+    #[test]
+    fn finds_piece_placements() {
+        let db = &mut database(GAMES, 0);
+        // An isolated queen pawn for White
+        let mut squares = vec![square("d4", Some("P"), false)];
+        for file in ["c", "e"] {
+            for rank in 2..=7 {
+                squares.push(square(&format!("{file}{rank}"), Some("P"), true));
+            }
+        }
+        let query = PatternQuery {
+            material: None,
+            any_pawns: false,
+            squares,
+        };
+        let found = search(db, query, 10);
+        assert_eq!(found.games, 1);
+        assert_eq!(found.matches[0].game.white, "Panov");
+        assert_eq!(found.matches[0].ply, 13);
+        assert_eq!(found.matches[0].move_, "Nxd5");
+
+        let empty = PatternQuery {
+            material: None,
+            any_pawns: false,
+            squares: vec![square("e2", None, false), square("e7", None, false)],
+        };
+        assert_eq!(search(db, empty, 10).games, 4);
+    }
+
+    #[test]
+    fn rejects_invalid_patterns() {
+        assert!(Pattern::new(&material("KRBxKRN")).is_err());
+        assert!(Pattern::new(&material("KRBvKRZ")).is_err());
+        let query = |constraint| PatternQuery {
+            material: None,
+            any_pawns: false,
+            squares: vec![constraint],
+        };
+        assert!(Pattern::new(&query(square("z9", None, false))).is_err());
+        assert!(Pattern::new(&query(square("d4", Some("X"), false))).is_err());
+        assert!(Pattern::new(&query(square("d4", Some("PP"), false))).is_err());
+    }
+
+    #[test]
+    fn stops_when_cancelled() {
+        let db = &mut database(GAMES, 0);
+        let pattern = Pattern::new(&material("KRBvKRN")).unwrap();
+        let found = scan_games(db, &pattern, 10, &AtomicBool::new(true), |_, _| ()).unwrap();
+        assert!(found.cancelled);
+        assert_eq!(found.games, 0);
+        assert!(found.matches.is_empty());
+    }
+}
@@ -0,0 +1,358 @@
+use diesel::{
+    prelude::*,
+    sql_query,
+    sql_types::{BigInt, Integer, Nullable, Text},
+};
+use serde::Serialize;
+use specta::Type;
+use std::path::PathBuf;
+use strsim::{jaro_winkler, sorensen_dice};
+
+use crate::{
+    db::{get_db_or_create, models::Player, schema::players, ConnectionOptions, Results},
+    error::Error,
+    AppState,
+};
+
+/// Names scoring less than this against the query are left out of a player search
+const MIN_NAME_SCORE: f64 = 0.75;
+
+/// Most played openings returned with the stats of a player
+const TOP_OPENINGS: i64 = 10;
+
+/// `c` without its diacritic, for the letters of the Latin alphabets
+fn fold_diacritic(c: char) -> char {
+    match c {
+        'à' | 'á' | 'â' | 'ã' | 'ä' | 'å' | 'ā' | 'ă' | 'ą' => 'a',
+        'ç' | 'ć' | 'ĉ' | 'č' => 'c',
+        'ď' | 'đ' => 'd',
+        'è' | 'é' | 'ê' | 'ë' | 'ē' | 'ė' | 'ę' | 'ě' => 'e',
+        'ĝ' | 'ğ' | 'ģ' => 'g',
+        'ì' | 'í' | 'î' | 'ï' | 'ī' | 'į' | 'ı' => 'i',
+        'ĺ' | 'ļ' | 'ľ' | 'ł' => 'l',
+        'ñ' | 'ń' | 'ņ' | 'ň' => 'n',
+        'ò' | 'ó' | 'ô' | 'õ' | 'ö' | 'ø' | 'ō' | 'ő' => 'o',
+        'ŕ' | 'ř' => 'r',
+        'ś' | 'ş' | 'š' | 'ș' | 'ß' => 's',
+        'ţ' | 'ť' | 'ț' => 't',
+        'ù' | 'ú' | 'û' | 'ü' | 'ū' | 'ů' | 'ű' | 'ų' => 'u',
+        'ý' | 'ÿ' => 'y',
+        'ź' | 'ż' | 'ž' => 'z',
+        c => c,
+    }
+}
+
+/// Words of a name in lowercase and without diacritics, sorted so that "Carlsen, Magnus"
+/// and "Magnus Carlsen" have the same words
+pub(super) fn name_words(name: &str) -> Vec<String> {
+    let folded: String = name.to_lowercase().chars().map(fold_diacritic).collect();
+    let mut words: Vec<String> = folded
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_string)
+        .collect();
+    words.sort();
+    words
+}
+
+/// How close a name is to the words of a query, 1 being the same name. Names with a word
+/// starting with each word of the query, like "Carlsen, M." for "magnus carl", score
+/// above the names that are only similar.
+fn name_score(query: &[String], name: &str) -> f64 {
+    let words = name_words(name);
+    if words.is_empty() {
+        return 0.0;
+    }
+    let mut unused: Vec<&str> = words.iter().map(String::as_str).collect();
+    let prefixes = query.iter().all(|word| {
+        let found = unused.iter().position(|name_word| {
+            name_word.starts_with(word.as_str()) || word.starts_with(*name_word)
+        });
+        found.map(|i| unused.remove(i)).is_some()
+    });
+    if prefixes {
+        let query_len: usize = query.iter().map(String::len).sum();
+        let name_len: usize = words.iter().map(String::len).sum();
+        return 0.9 + 0.1 * query_len.min(name_len) as f64 / query_len.max(name_len) as f64;
+    }
+    let (query, name) = (query.join(" "), words.join(" "));
+    0.9 * jaro_winkler(&query, &name).max(sorensen_dice(&query, &name))
+}
+
+#[derive(Debug, Clone, Serialize, Type)]
+pub struct PlayerMatch {
+    pub player: Player,
+    /// Similarity of the name to the query, from 0 to 1
+    pub score: f64,
+}
+
+fn find_players(
+    db: &mut SqliteConnection,
+    query: &str,
+    limit: usize,
+) -> Result<Vec<PlayerMatch>, Error> {
+    let query = name_words(query);
+    if query.is_empty() {
+        return Ok(Vec::new());
+    }
+    let mut matches: Vec<PlayerMatch> = players::table
+        .filter(players::name.is_not("Unknown"))
+        .load::<Player>(db)?
+        .into_iter()
+        .filter_map(|player| {
+            let score = name_score(&query, player.name.as_deref()?);
+            (score >= MIN_NAME_SCORE).then_some(PlayerMatch { player, score })
+        })
+        .collect();
+    matches.sort_by(|a, b| b.score.total_cmp(&a.score));
+    matches.truncate(limit);
+    Ok(matches)
+}
+
+/// Finds the players of a database whose name is close to `query`, best match first.
+/// The order of the first and last names, commas and diacritics don't matter.
+#[tauri::command]
+#[specta::specta]
+pub async fn search_players(
+    file: PathBuf,
+    query: String,
+    limit: usize,
+    state: tauri::State<'_, AppState>,
+) -> Result<Vec<PlayerMatch>, Error> {
+    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+    find_players(db, &query, limit)
+}
+
+#[derive(Debug, Clone, Serialize, Type, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct PlayerStats {
+    pub games: i32,
+    pub white: Results,
+    pub black: Results,
+    /// Average Elo of the opponents in the games where it is known
+    pub average_opponent_rating: Option<f64>,
+    /// Rating performance over the games against rated opponents, by the rule of 400
+    pub performance_rating: Option<i32>,
+    /// ECO codes of the most played openings, with their number of games
+    pub openings: Vec<(String, i32)>,
+    /// Average Elo of the player per month, in `YYYY-MM` order
+    pub ratings: Vec<(String, i32)>,
+}
+
+/// Games of a player with one color and result
+#[derive(QueryableByName)]
+struct ResultCount {
+    #[diesel(sql_type = Integer)]
+    white: i32,
+    #[diesel(sql_type = Nullable<Text>, column_name = "Result")]
+    result: Option<String>,
+    #[diesel(sql_type = BigInt)]
+    games: i64,
+    #[diesel(sql_type = BigInt)]
+    opponent_sum: i64,
+    #[diesel(sql_type = BigInt)]
+    rated: i64,
+}
+
+const RESULTS_SQL: &str = "
+    SELECT 1 AS white, Result, COUNT(*) AS games,
+        COALESCE(SUM(BlackElo), 0) AS opponent_sum, COUNT(BlackElo) AS rated
+    FROM Games WHERE WhiteID = ? GROUP BY Result
+    UNION ALL
+    SELECT 0 AS white, Result, COUNT(*) AS games,
+        COALESCE(SUM(WhiteElo), 0) AS opponent_sum, COUNT(WhiteElo) AS rated
+    FROM Games WHERE BlackID = ? GROUP BY Result
+";
+
+#[derive(QueryableByName)]
+struct Grouped {
+    #[diesel(sql_type = Text)]
+    key: String,
+    #[diesel(sql_type = BigInt)]
+    value: i64,
+}
+
+const OPENINGS_SQL: &str = "
+    SELECT ECO AS key, COUNT(*) AS value
+    FROM (
+        SELECT ECO FROM Games WHERE WhiteID = ?
+        UNION ALL
+        SELECT ECO FROM Games WHERE BlackID = ?
+    )
+    WHERE ECO IS NOT NULL
+    GROUP BY ECO
+    ORDER BY value DESC, key
+    LIMIT ?
+";
+
+const RATINGS_SQL: &str = "
+    SELECT replace(substr(Date, 1, 7), '.', '-') AS key, CAST(AVG(Elo) AS INTEGER) AS value
+    FROM (
+        SELECT Date, WhiteElo AS Elo FROM Games WHERE WhiteID = ?
+        UNION ALL
+        SELECT Date, BlackElo AS Elo FROM Games WHERE BlackID = ?
+    )
+    WHERE Elo IS NOT NULL AND Date GLOB '[0-9][0-9][0-9][0-9].[0-9][0-9]*'
+    GROUP BY key
+    ORDER BY key
+";
+
+fn player_stats(db: &mut SqliteConnection, id: i32) -> Result<PlayerStats, Error> {
+    let counts: Vec<ResultCount> = sql_query(RESULTS_SQL)
+        .bind::<Integer, _>(id)
+        .bind::<Integer, _>(id)
+        .load(db)?;
+
+    let mut stats = PlayerStats::default();
+    let (mut opponent_sum, mut rated, mut rated_score) = (0, 0, 0);
+    for count in &counts {
+        let results = if count.white == 1 {
+            &mut stats.white
+        } else {
+            &mut stats.black
+        };
+        let games = count.games as i32;
+        // Wins minus losses, from the side of the player
+        let score = match (count.result.as_deref(), count.white == 1) {
+            (Some("1-0"), true) | (Some("0-1"), false) => {
+                results.won += games;
+                1
+            }
+            (Some("1-0"), false) | (Some("0-1"), true) => {
+                results.lost += games;
+                -1
+            }
+            (Some("1/2-1/2"), _) => {
+                results.draw += games;
+                0
+            }
+            _ => 0,
+        };
+        stats.games += games;
+        opponent_sum += count.opponent_sum;
+        rated += count.rated;
+        rated_score += score * count.rated;
+    }
+    if rated > 0 {
+        let average = opponent_sum as f64 / rated as f64;
+        stats.average_opponent_rating = Some(average);
+        stats.performance_rating =
+            Some((average + 400.0 * rated_score as f64 / rated as f64).round() as i32);
+    }
+
+    let grouped = |db: &mut SqliteConnection, sql, limit: Option<i64>| {
+        let query = sql_query(sql).bind::<Integer, _>(id).bind::<Integer, _>(id);
+        let rows: Vec<Grouped> = match limit {
+            Some(limit) => query.bind::<BigInt, _>(limit).load(db)?,
+            None => query.load(db)?,
+        };
+        Ok::<_, Error>(
+            rows.into_iter()
+                .map(|row| (row.key, row.value as i32))
+                .collect(),
+        )
+    };
+    stats.openings = grouped(db, OPENINGS_SQL, Some(TOP_OPENINGS))?;
+    stats.ratings = grouped(db, RATINGS_SQL, None)?;
+    Ok(stats)
+}
+
+/// Results, opponents, openings and rating history of a player, computed by the
+/// database from the player indexes created at import
+#[tauri::command]
+#[specta::specta]
+pub async fn get_player_stats(
+    file: PathBuf,
+    id: i32,
+    state: tauri::State<'_, AppState>,
+) -> Result<PlayerStats, Error> {
+    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+    player_stats(db, id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::db::test_utils::database;
+
+    fn words(query: &str) -> Vec<String> {
+        name_words(query)
+    }
+
+    #[test]
+    fn normalizes_names() {
+        assert_eq!(name_words("Carlsen, Magnus"), name_words("Magnus Carlsen"));
+        assert_eq!(
+            name_words("Dominguez Pérez, Leinier"),
+            ["dominguez", "leinier", "perez"]
+        );
+        assert_eq!(name_words("Łukasz  Jarmuła"), ["jarmula", "lukasz"]);
+        assert!(name_words(" , ").is_empty());
+    }
+
+    #[test]
+    fn scores_names() {
+        let query = words("magnus carlsen");
+        assert_eq!(name_score(&query, "Carlsen, Magnus"), 1.0);
+        assert!(name_score(&query, "Carlsen, M.") > name_score(&query, "Carlsson, Magnus"));
+        assert!(name_score(&words("carl"), "Carlsen, Magnus") >= 0.9);
+        assert!(name_score(&words("carlsne"), "Carlsen, Magnus") > MIN_NAME_SCORE);
+        assert!(name_score(&query, "Giri, Anish") < MIN_NAME_SCORE);
+    }
+
+    #[test]
+    fn finds_players() {
+        let db = &mut database(
+            "[White \"Carlsen, Magnus\"]\n[Black \"Giri, Anish\"]\n\n1. e4 *\n\n\
+             [White \"Carlsen, Magnus\"]\n[Black \"Carlsson, Magnús\"]\n\n1. d4 *",
+        );
+        let found = find_players(db, "magnus carlsen", 10).unwrap();
+        let names: Vec<&str> = found
+            .iter()
+            .map(|m| m.player.name.as_deref().unwrap())
+            .collect();
+        assert_eq!(names, ["Carlsen, Magnus", "Carlsson, Magnús"]);
+        assert_eq!(find_players(db, "anish", 10).unwrap().len(), 1);
+        assert_eq!(find_players(db, "magnus", 1).unwrap().len(), 1);
+        assert!(find_players(db, "", 10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn computes_player_stats() {
+        let db = &mut database(
+            "[White \"A\"]\n[Black \"B\"]\n[WhiteElo \"2500\"]\n[BlackElo \"2400\"]\n\
+             [Date \"2024.01.05\"]\n[Result \"1-0\"]\n\n1. e4 e5 1-0\n\n\
+             [White \"B\"]\n[Black \"A\"]\n[WhiteElo \"2600\"]\n[BlackElo \"2520\"]\n\
+             [Date \"2024.01.20\"]\n[Result \"1/2-1/2\"]\n\n1. e4 c5 1/2-1/2\n\n\
+             [White \"C\"]\n[Black \"A\"]\n[BlackElo \"2540\"]\n\
+             [Date \"2024.02.??\"]\n[Result \"1-0\"]\n\n1. e4 e5 1-0\n\n\
+             [White \"A\"]\n[Black \"C\"]\n[Date \"????.??.??\"]\n[Result \"*\"]\n\n1. d4 *",
+        );
+        let id = find_players(db, "A", 1).unwrap()[0].player.id;
+        let stats = player_stats(db, id).unwrap();
+        assert_eq!(stats.games, 4);
+        assert_eq!(
+            (stats.white.won, stats.white.draw, stats.white.lost),
+            (1, 0, 0)
+        );
+        assert_eq!(
+            (stats.black.won, stats.black.draw, stats.black.lost),
+            (0, 1, 1)
+        );
+        // Only the opponents of the first two games are rated
+        assert_eq!(stats.average_opponent_rating, Some(2500.0));
+        assert_eq!(stats.performance_rating, Some(2700));
+        assert_eq!(stats.openings[0], ("C20".to_string(), 2));
+        assert_eq!(stats.openings.len(), 3);
+        assert_eq!(
+            stats.ratings,
+            [("2024-01".to_string(), 2510), ("2024-02".to_string(), 2540)]
+        );
+
+        let unknown = player_stats(db, 1000).unwrap();
+        assert_eq!(unknown.games, 0);
+        assert_eq!(unknown.performance_rating, None);
+        assert!(unknown.ratings.is_empty());
+    }
+}
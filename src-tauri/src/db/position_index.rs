@@ -0,0 +1,256 @@
+use diesel::{connection::SimpleConnection, insert_into, prelude::*};
+use log::info;
+use rayon::prelude::*;
+use shakmaty::Position;
+use std::{path::PathBuf, time::Instant};
+use tauri_specta::Event;
+
+use crate::{
+    db::{
+        encoding::decode_move, game_start, get_db_or_create, index_position, info_value, migrate,
+        models::NewPosition, position_rating, schema::*, set_info, ConnectionOptions,
+        DatabaseProgress, MIGRATIONS,
+    },
+    error::Error,
+    AppState,
+};
+
+/// Games replayed between two progress events
+const REINDEX_CHUNK_SIZE: i64 = 10_000;
+
+/// Positions inserted by a single statement, below the limit of variables of SQLite
+const INSERT_CHUNK_SIZE: usize = 5_000;
+
+type IndexRow = (
+    i32,
+    Option<String>,
+    Vec<u8>,
+    Option<String>,
+    Option<i32>,
+    Option<i32>,
+);
+
+fn index_row() -> (
+    games::id,
+    games::fen,
+    games::moves,
+    games::result,
+    games::white_elo,
+    games::black_elo,
+) {
+    (
+        games::id,
+        games::fen,
+        games::moves,
+        games::result,
+        games::white_elo,
+        games::black_elo,
+    )
+}
+
+/// Hash, ply and next move of the positions of a stored game in its first `plies`
+/// plies, the same ones the import indexes
+fn game_positions(fen: Option<&str>, moves: &[u8], plies: u16) -> Vec<(i64, i32, Option<u8>)> {
+    let mut positions = Vec::new();
+    let Some(mut position) = game_start(fen) else {
+        return positions;
+    };
+    for (ply, &byte) in moves.iter().enumerate() {
+        index_position(&mut positions, &position, ply, Some(byte), plies);
+        if ply >= plies as usize {
+            return positions;
+        }
+        let Some(m) = decode_move(byte, &position) else {
+            return positions;
+        };
+        position.play_unchecked(&m);
+    }
+    index_position(&mut positions, &position, moves.len(), None, plies);
+    positions
+}
+
+fn new_positions(
+    (id, fen, moves, result, white_elo, black_elo): &IndexRow,
+    plies: u16,
+) -> Vec<NewPosition<'_>> {
+    let rating = position_rating(*white_elo, *black_elo);
+    game_positions(fen.as_deref(), moves, plies)
+        .into_iter()
+        .map(|(hash, ply, next)| NewPosition {
+            hash,
+            game_id: *id,
+            ply,
+            move_: next.map(i32::from),
+            result: result.as_deref(),
+            rating,
+        })
+        .collect()
+}
+
+/// Plies of each game the position index was built with, unknown for databases
+/// imported before it was kept
+pub(super) fn indexed_plies(db: &mut SqliteConnection) -> Result<Option<u16>, Error> {
+    let plies = info_value(db, "PositionPlies")?;
+    Ok(plies.and_then(|plies| plies.parse().ok()))
+}
+
+pub(super) fn set_indexed_plies(db: &mut SqliteConnection, plies: u16) -> Result<(), Error> {
+    set_info(db, "PositionPlies", &plies.to_string())
+}
+
+/// Indexes the positions of a game again after it was edited
+pub(super) fn reindex_game(db: &mut SqliteConnection, id: i32, plies: u16) -> Result<(), Error> {
+    let row: IndexRow = games::table.find(id).select(index_row()).first(db)?;
+    diesel::delete(positions::table.filter(positions::game_id.eq(id))).execute(db)?;
+    insert_into(positions::table)
+        .values(new_positions(&row, plies))
+        .execute(db)?;
+    Ok(())
+}
+
+/// Replaces the position index, and the explorer counts built from it, with the
+/// positions of the first `plies` plies of every game
+fn reindex(
+    db: &mut SqliteConnection,
+    plies: u16,
+    mut progress: impl FnMut(usize, usize),
+) -> Result<usize, Error> {
+    migrate(db, MIGRATIONS)?;
+    let total: i64 = games::table.count().get_result(db)?;
+    db.transaction(|db| {
+        // Emptied first so deleting the positions has no counts to update
+        db.batch_execute("DELETE FROM Explorer; DELETE FROM Positions;")?;
+        let mut last = 0;
+        let mut indexed = 0;
+        loop {
+            let rows: Vec<IndexRow> = games::table
+                .filter(games::id.gt(last))
+                .order(games::id)
+                .limit(REINDEX_CHUNK_SIZE)
+                .select(index_row())
+                .load(db)?;
+            let Some(&(id, ..)) = rows.last() else {
+                break;
+            };
+            last = id;
+
+            let positions: Vec<NewPosition> = rows
+                .par_iter()
+                .flat_map_iter(|row| new_positions(row, plies))
+                .collect();
+            for chunk in positions.chunks(INSERT_CHUNK_SIZE) {
+                insert_into(positions::table).values(chunk).execute(db)?;
+            }
+            indexed += rows.len();
+            progress(indexed, total as usize);
+        }
+        set_indexed_plies(db, plies)?;
+        Ok(indexed)
+    })
+}
+
+/// Builds the position index of a database again with the first `max_ply` plies of
+/// each game, for databases imported before it existed or with another limit. Progress
+/// is emitted as `DatabaseProgress` with the path of the database as id. Returns the
+/// number of games indexed.
+#[tauri::command]
+#[specta::specta]
+pub async fn reindex_positions(
+    file: PathBuf,
+    max_ply: u16,
+    state: tauri::State<'_, AppState>,
+    app: tauri::AppHandle,
+) -> Result<usize, Error> {
+    let id = file.to_string_lossy().into_owned();
+    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+
+    let start = Instant::now();
+    let indexed = reindex(db, max_ply, |indexed, total| {
+        let _ = DatabaseProgress {
+            id: id.clone(),
+            progress: indexed as f64 / total.max(1) as f64 * 100.0,
+        }
+        .emit_all(&app);
+    })?;
+    info!(
+        "indexed the positions of {indexed} games in {:?}",
+        start.elapsed()
+    );
+    Ok(indexed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::db::test_utils::database;
+    use diesel::sql_types::{BigInt, Integer};
+
+    const GAMES: &str = "[WhiteElo \"2000\"]\n[BlackElo \"2200\"]\n[Result \"1-0\"]\n\n\
+                         1. e4 e5 2. Nf3 Nc6 3. Bb5 a6 1-0\n\n\
+                         [FEN \"4k3/8/8/8/8/8/4P3/4K3 w - - 0 1\"]\n\n1. Kd2 Kd8 2. Ke1 Ke8 *\n\n\
+                         [Result \"0-1\"]\n\n1. f3 e5 2. g4 Qh4# 0-1";
+
+    type IndexedRow = (i64, i32, i32, Option<i32>, Option<String>, Option<i32>);
+
+    #[derive(QueryableByName, Debug, PartialEq)]
+    struct ExplorerCount {
+        #[diesel(sql_type = BigInt, column_name = "Hash")]
+        hash: i64,
+        #[diesel(sql_type = Integer, column_name = "Move")]
+        move_: i32,
+        #[diesel(sql_type = Integer, column_name = "Games")]
+        games: i32,
+    }
+
+    fn index(db: &mut SqliteConnection) -> Vec<IndexedRow> {
+        positions::table
+            .select((
+                positions::hash,
+                positions::game_id,
+                positions::ply,
+                positions::move_,
+                positions::result,
+                positions::rating,
+            ))
+            .order((positions::game_id, positions::ply))
+            .load(db)
+            .unwrap()
+    }
+
+    fn explorer(db: &mut SqliteConnection) -> Vec<ExplorerCount> {
+        diesel::sql_query("SELECT Hash, Move, Games FROM Explorer ORDER BY Hash, Move")
+            .load(db)
+            .unwrap()
+    }
+
+    #[test]
+    fn indexes_like_the_import() {
+        let imported = &mut database(GAMES, 4);
+        let reindexed = &mut database(GAMES, 1);
+        let mut events = Vec::new();
+        let games = reindex(reindexed, 4, |indexed, total| events.push((indexed, total))).unwrap();
+        assert_eq!(games, 3);
+        assert_eq!(events, [(3, 3)]);
+        assert_eq!(index(reindexed), index(imported));
+        assert_eq!(explorer(reindexed), explorer(imported));
+
+        // The kings walking back repeat the start, which is indexed once
+        let repeated: Vec<i32> = index(reindexed)
+            .into_iter()
+            .filter(|row| row.1 == 2)
+            .map(|row| row.2)
+            .collect();
+        assert_eq!(repeated, [0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn changes_the_ply_limit() {
+        let db = &mut database(GAMES, 20);
+        reindex(db, 0, |_, _| {}).unwrap();
+        let indexed = index(db);
+        assert_eq!(indexed.len(), 3);
+        assert!(indexed.iter().all(|row| row.2 == 0));
+        assert_eq!(game_positions(None, &[], 0).len(), 1);
+    }
+}
@@ -0,0 +1,12 @@
+CREATE TABLE Positions (
+    Hash INTEGER NOT NULL,
+    GameID INTEGER NOT NULL,
+    Ply INTEGER NOT NULL,
+    Move INTEGER,
+    Result TEXT,
+    Rating INTEGER,
+    PRIMARY KEY (Hash, GameID),
+    FOREIGN KEY(GameID) REFERENCES Games ON DELETE CASCADE
+) WITHOUT ROWID;
+
+CREATE INDEX positions_game_idx ON Positions(GameID);
@@ -7,6 +7,33 @@ diesel::table! {
         rating_deviation -> Integer,
         popularity -> Integer,
         nb_plays -> Integer,
+        themes -> Text,
+    }
+}
+
+diesel::table! {
+    puzzle_progress (puzzle_id) {
+        puzzle_id -> Integer,
+        attempts -> Integer,
+        solved -> Bool,
+    }
+}
+
+diesel::table! {
+    #[sql_name = "Evals"]
+    evals (fen, engine) {
+        #[sql_name = "FEN"]
+        fen -> Text,
+        #[sql_name = "Engine"]
+        engine -> Text,
+        #[sql_name = "Depth"]
+        depth -> Integer,
+        #[sql_name = "Nodes"]
+        nodes -> BigInt,
+        #[sql_name = "MultiPV"]
+        multipv -> Integer,
+        #[sql_name = "Lines"]
+        lines -> Text,
     }
 }
 
@@ -110,7 +137,29 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    #[sql_name = "Positions"]
+    positions (hash, game_id) {
+        #[sql_name = "Hash"]
+        hash -> BigInt,
+        #[sql_name = "GameID"]
+        game_id -> Integer,
+        #[sql_name = "Ply"]
+        ply -> Integer,
+        #[sql_name = "Move"]
+        move_ -> Nullable<Integer>,
+        #[sql_name = "Result"]
+        result -> Nullable<Text>,
+        #[sql_name = "Rating"]
+        rating -> Nullable<Integer>,
+    }
+}
+
 diesel::joinable!(games -> events (event_id));
 diesel::joinable!(games -> sites (site_id));
 
-diesel::allow_tables_to_appear_in_same_query!(comments, events, games, info, players, sites,);
+diesel::allow_tables_to_appear_in_same_query!(
+    comments, events, games, info, players, positions, sites,
+);
+
+diesel::allow_tables_to_appear_in_same_query!(puzzles, puzzle_progress);
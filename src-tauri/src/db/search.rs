@@ -1,26 +1,36 @@
 use dashmap::{mapref::entry::Entry, DashMap};
-use diesel::prelude::*;
+use diesel::{
+    prelude::*,
+    sql_query,
+    sql_types::{BigInt, Integer, Nullable},
+};
 use log::info;
 use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
-use shakmaty::{fen::Fen, san::SanPlus, Bitboard, ByColor, Chess, FromSetup, Position, Setup};
+use shakmaty::{
+    fen::Fen, san::SanPlus, Bitboard, Board, ByColor, Chess, Color, EnPassantMode, FromSetup,
+    Position, Setup,
+};
+use specta::Type;
 use std::{
-    path::PathBuf,
+    collections::HashMap,
+    path::{Path, PathBuf},
     sync::{
-        atomic::{AtomicUsize, Ordering},
+        atomic::{AtomicBool, AtomicUsize, Ordering},
         Mutex,
     },
-    time::Instant,
+    time::{Instant, SystemTime},
 };
 use tauri::Manager;
 
 use crate::{
     db::{
-        encoding::decode_move, get_db_or_create, get_material_count, get_pawn_home, models::*,
-        normalize_games, schema::*, ConnectionOptions, MaterialCount,
+        encoding::decode_move, game_start, get_db_or_create, get_material_count, get_pawn_home,
+        has_position_index, models::*, normalize_games, position_hash,
+        position_index::indexed_plies, schema::*, ConnectionOptions, MaterialCount,
     },
     error::Error,
-    AppState,
+    AppState, GameData,
 };
 
 use super::GameQuery;
@@ -149,7 +159,7 @@ fn is_end_reachable(end: u16, pos: u16) -> bool {
 }
 
 /// Returns true if the end material is reachable
-fn is_material_reachable(end: &MaterialCount, pos: &MaterialCount) -> bool {
+pub(super) fn is_material_reachable(end: &MaterialCount, pos: &MaterialCount) -> bool {
     end.white <= pos.white && end.black <= pos.black
 }
 
@@ -158,7 +168,7 @@ fn is_contained(container: Bitboard, subset: Bitboard) -> bool {
     container & subset == subset
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, Type)]
 pub struct PositionStats {
     #[serde(rename = "move")]
     pub move_: String,
@@ -233,26 +243,8 @@ pub async fn search_position(
     info!("start loading games");
 
     let permit = state.new_request.acquire().await.unwrap();
-    let mut games = state.db_cache.lock().unwrap();
-
-    if games.is_empty() {
-        *games = games::table
-            .select((
-                games::id,
-                games::white_id,
-                games::black_id,
-                games::date,
-                games::result,
-                games::moves,
-                games::fen,
-                games::pawn_home,
-                games::white_material,
-                games::black_material,
-            ))
-            .load(db)?;
-
-        info!("got {} games: {:?}", games.len(), start.elapsed());
-    }
+    let mut cache = state.db_cache.lock().unwrap();
+    let games = cache.games(db, &file)?;
 
     let openings: DashMap<String, PositionStats> = DashMap::new();
     let sample_games: Mutex<Vec<i32>> = Mutex::new(Vec::new());
@@ -390,91 +382,566 @@ pub async fn search_position(
     Ok((openings, normalized_games))
 }
 
-pub async fn is_position_in_db(
+/// How many games of the database reached a position, and what was played from it
+#[derive(Debug, Clone, Default)]
+pub struct PositionPopularity {
+    pub games: usize,
+    pub moves: Vec<PositionStats>,
+}
+
+/// Games of the database last searched by replaying them, with what they need for it
+#[derive(Debug, Default)]
+pub struct GamesCache {
     file: PathBuf,
-    query: GameQuery,
+    /// Modification time of the database when its games were loaded
+    modified: Option<SystemTime>,
+    games: Vec<GameData>,
+}
+
+impl GamesCache {
+    /// The games of the database `file`, loaded again when it was written since
+    fn games(&mut self, db: &mut SqliteConnection, file: &Path) -> Result<&[GameData], Error> {
+        let modified = std::fs::metadata(file)
+            .and_then(|meta| meta.modified())
+            .ok();
+        if self.file != file || self.modified != modified || modified.is_none() {
+            let start = Instant::now();
+            self.games = games::table
+                .select((
+                    games::id,
+                    games::white_id,
+                    games::black_id,
+                    games::date,
+                    games::result,
+                    games::moves,
+                    games::fen,
+                    games::pawn_home,
+                    games::white_material,
+                    games::black_material,
+                ))
+                .load(db)?;
+            self.file = file.to_path_buf();
+            self.modified = modified;
+            info!("got {} games: {:?}", self.games.len(), start.elapsed());
+        }
+        Ok(&self.games)
+    }
+}
+
+/// Drops what the searches keep of the database `file`, after it was written
+pub fn forget_games(state: &AppState, file: &Path) {
+    let mut cache = state.db_cache.lock().unwrap();
+    if cache.file == file {
+        *cache = GamesCache::default();
+    }
+    state.line_cache.retain(|(_, cached), _| cached != file);
+}
+
+/// Counts the games of a database that reached each of `positions`, the positions of a
+/// game from its start. The ones of the first plies are looked up in the position
+/// index, which has the first plies of every game, and the others are counted by
+/// replaying the games.
+pub async fn count_positions(
+    file: PathBuf,
+    positions: &[Chess],
     state: tauri::State<'_, AppState>,
-) -> Result<bool, Error> {
+) -> Result<Vec<PositionPopularity>, Error> {
     let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
-
-    if let Some(pos) = state.line_cache.get(&(query.clone(), file.clone())) {
-        return Ok(!pos.0.is_empty());
+    // The index only has the first plies of the games from the starting position
+    let from_start = positions.first().is_some_and(|position| {
+        position.board() == &Board::default() && position.turn().is_white()
+    });
+    let indexed = if from_start && has_position_index(db)? {
+        indexed_plies(db)?.map_or(0, |plies| plies as usize + 1)
+    } else {
+        0
+    };
+    let indexed = indexed.min(positions.len());
+    let mut popularity = positions[..indexed]
+        .iter()
+        .map(|position| count_indexed(db, position))
+        .collect::<Result<Vec<_>, _>>()?;
+    if indexed < positions.len() {
+        popularity.extend(count_by_replaying(db, &file, &positions[indexed..], &state).await?);
     }
+    Ok(popularity)
+}
 
-    // start counting the time
-    let start = Instant::now();
-    info!("start loading games");
+/// The games that reached `position` in the position index, and the moves they played
+fn count_indexed(db: &mut SqliteConnection, position: &Chess) -> Result<PositionPopularity, Error> {
+    let counts: Vec<MoveCounts> = sql_query(MOVE_COUNTS_SQL)
+        .bind::<BigInt, _>(position_hash(position))
+        .load(db)?;
+    let mut moves: Vec<PositionStats> = counts
+        .iter()
+        .map(|count| PositionStats {
+            move_: indexed_san(position, count.move_),
+            white: count.white as i32,
+            draw: count.draw as i32,
+            black: count.black as i32,
+        })
+        .collect();
+    moves.sort_by_key(|m| -(m.white + m.draw + m.black));
+    Ok(PositionPopularity {
+        games: counts.iter().map(|count| count.games as usize).sum(),
+        moves,
+    })
+}
 
+/// Counts the games that reached each of `positions` in a single pass over the games.
+/// Replaying a game stops once none of the positions can be reached.
+async fn count_by_replaying(
+    db: &mut SqliteConnection,
+    file: &Path,
+    positions: &[Chess],
+    state: &AppState,
+) -> Result<Vec<PositionPopularity>, Error> {
+    let start = Instant::now();
     let permit = state.new_request.acquire().await.unwrap();
-    let mut games = state.db_cache.lock().unwrap();
-
-    if games.is_empty() {
-        *games = games::table
-            .select((
-                games::id,
-                games::white_id,
-                games::black_id,
-                games::date,
-                games::result,
-                games::moves,
-                games::fen,
-                games::pawn_home,
-                games::white_material,
-                games::black_material,
-            ))
-            .load(db)?;
-
-        info!("got {} games: {:?}", games.len(), start.elapsed());
-    }
-
-    let exists = games.par_iter().any(
-        |(
-            _id,
-            _white_id,
-            _black_id,
-            _date,
-            _result,
-            game,
-            fen,
-            end_pawn_home,
-            white_material,
-            black_material,
-        )| {
-            if state.new_request.available_permits() == 0 {
-                return false;
-            }
-            let end_material: MaterialCount = ByColor {
-                white: *white_material as u8,
-                black: *black_material as u8,
-            };
-            if let Some(position_query) = &query.position {
-                position_query.can_reach(&end_material, *end_pawn_home as u16)
-                    && get_move_after_match(game, fen, position_query)
-                        .unwrap_or(None)
-                        .is_some()
-            } else {
-                false
+    let mut cache = state.db_cache.lock().unwrap();
+    let games = cache.games(db, file)?;
+    // Set when a new request stopped the count before every game was replayed
+    let stopped = AtomicBool::new(false);
+
+    let mut indices: HashMap<(Board, Color), Vec<usize>> = HashMap::new();
+    for (i, pos) in positions.iter().enumerate() {
+        indices
+            .entry((pos.board().clone(), pos.turn()))
+            .or_default()
+            .push(i);
+    }
+    let targets: Vec<(u16, MaterialCount)> = positions
+        .iter()
+        .map(|pos| (get_pawn_home(pos.board()), get_material_count(pos.board())))
+        .collect();
+
+    // Number of games and results of each continuation, per position
+    type Counts = Vec<(usize, HashMap<String, PositionStats>)>;
+    let counts = games
+        .par_iter()
+        .fold(
+            || vec![(0, HashMap::new()); positions.len()],
+            |mut counts: Counts, (_, _, _, _, result, game, fen, _, _, _)| {
+                if state.new_request.available_permits() == 0 {
+                    stopped.store(true, Ordering::Relaxed);
+                    return counts;
+                }
+                let mut chess = match fen {
+                    Some(fen) => match Fen::from_ascii(fen.as_bytes())
+                        .map(|fen| fen.into_position(shakmaty::CastlingMode::Chess960))
+                    {
+                        Ok(Ok(chess)) => chess,
+                        _ => return counts,
+                    },
+                    None => Chess::default(),
+                };
+                let mut seen = vec![false; positions.len()];
+                for i in 0..=game.len() {
+                    let board = chess.board();
+                    let (pawn_home, material) = (get_pawn_home(board), get_material_count(board));
+                    if !targets.iter().any(|(end_pawn_home, end_material)| {
+                        is_end_reachable(*end_pawn_home, pawn_home)
+                            && is_material_reachable(end_material, &material)
+                    }) {
+                        break;
+                    }
+                    let next_move = game.get(i).and_then(|byte| decode_move(*byte, &chess));
+                    if let Some(matches) = indices.get(&(board.clone(), chess.turn())) {
+                        let san = next_move.as_ref().map_or("*".to_string(), |m| {
+                            SanPlus::from_move(chess.clone(), m).to_string()
+                        });
+                        for &index in matches {
+                            if seen[index] {
+                                continue;
+                            }
+                            seen[index] = true;
+                            let (count, moves) = &mut counts[index];
+                            *count += 1;
+                            let stats = moves.entry(san.clone()).or_insert_with(|| PositionStats {
+                                move_: san.clone(),
+                                white: 0,
+                                draw: 0,
+                                black: 0,
+                            });
+                            match result.as_deref() {
+                                Some("1-0") => stats.white += 1,
+                                Some("0-1") => stats.black += 1,
+                                Some("1/2-1/2") => stats.draw += 1,
+                                _ => (),
+                            }
+                        }
+                    }
+                    match next_move {
+                        Some(m) => chess.play_unchecked(&m),
+                        None => break,
+                    }
+                }
+                counts
+            },
+        )
+        .reduce_with(|mut a, b| {
+            for ((count, moves), (other_count, other_moves)) in a.iter_mut().zip(b) {
+                *count += other_count;
+                for (san, stats) in other_moves {
+                    let entry = moves.entry(san.clone()).or_insert_with(|| PositionStats {
+                        move_: san,
+                        white: 0,
+                        draw: 0,
+                        black: 0,
+                    });
+                    entry.white += stats.white;
+                    entry.draw += stats.draw;
+                    entry.black += stats.black;
+                }
             }
-        },
-    );
-    info!("finished search in {:?}", start.elapsed());
-    if state.new_request.available_permits() == 0 {
+            a
+        })
+        .unwrap_or_else(|| vec![(0, HashMap::new()); positions.len()]);
+    info!("counted positions in {:?}", start.elapsed());
+
+    if stopped.load(Ordering::Relaxed) || state.new_request.available_permits() == 0 {
         drop(permit);
         return Err(Error::SearchStopped);
     }
 
-    if !exists {
-        state.line_cache.insert((query, file), (vec![], vec![]));
+    drop(permit);
+    Ok(counts
+        .into_iter()
+        .map(|(games, moves)| {
+            let mut moves: Vec<PositionStats> = moves.into_values().collect();
+            moves.sort_by_key(|m| -(m.white + m.draw + m.black));
+            PositionPopularity { games, moves }
+        })
+        .collect())
+}
+
+/// Results and ratings of the indexed games of a position, per move played from it
+#[derive(QueryableByName)]
+struct MoveCounts {
+    #[diesel(sql_type = Nullable<Integer>, column_name = "Move")]
+    move_: Option<i32>,
+    #[diesel(sql_type = BigInt)]
+    games: i64,
+    #[diesel(sql_type = BigInt)]
+    white: i64,
+    #[diesel(sql_type = BigInt)]
+    draw: i64,
+    #[diesel(sql_type = BigInt)]
+    black: i64,
+    #[diesel(sql_type = BigInt)]
+    rating_sum: i64,
+    #[diesel(sql_type = BigInt)]
+    rated: i64,
+}
+
+const MOVE_COUNTS_SQL: &str = "
+    SELECT Move,
+        COUNT(*) AS games,
+        COUNT(CASE WHEN Result = '1-0' THEN 1 END) AS white,
+        COUNT(CASE WHEN Result = '1/2-1/2' THEN 1 END) AS draw,
+        COUNT(CASE WHEN Result = '0-1' THEN 1 END) AS black,
+        COALESCE(SUM(Rating), 0) AS rating_sum,
+        COUNT(Rating) AS rated
+    FROM Positions
+    WHERE Hash = ?
+    GROUP BY Move
+";
+
+/// A game that reached the searched position
+#[derive(Serialize, Clone)]
+pub struct PositionMatch {
+    pub game: NormalizedGame,
+    /// Plies played before the position was reached
+    pub ply: i32,
+    /// Move played from the position, `*` if the game ended there
+    #[serde(rename = "move")]
+    pub move_: String,
+}
+
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PositionSearch {
+    /// Games that reached the position
+    pub games: usize,
+    /// Percentages of the games won by White, drawn and won by Black
+    pub white: f64,
+    pub draw: f64,
+    pub black: f64,
+    /// Average rating of the players of the rated games
+    pub average_rating: Option<f64>,
+    /// Moves played from the position, most played first
+    pub moves: Vec<PositionStats>,
+    /// Page of the games, the last imported first
+    pub matches: Vec<PositionMatch>,
+}
+
+/// Loads the games of `ids`, by id
+pub(super) fn games_by_id(
+    db: &mut SqliteConnection,
+    ids: Vec<i32>,
+) -> Result<HashMap<i32, NormalizedGame>, Error> {
+    let (white_players, black_players) = diesel::alias!(players as white, players as black);
+    let found: Vec<(Game, Player, Player, Event, Site)> = games::table
+        .inner_join(white_players.on(games::white_id.eq(white_players.field(players::id))))
+        .inner_join(black_players.on(games::black_id.eq(black_players.field(players::id))))
+        .inner_join(events::table.on(games::event_id.eq(events::id)))
+        .inner_join(sites::table.on(games::site_id.eq(sites::id)))
+        .filter(games::id.eq_any(ids))
+        .load(db)?;
+    Ok(normalize_games(found)
+        .into_iter()
+        .map(|game| (game.id, game))
+        .collect())
+}
+
+/// SAN of a move encoded in the position index
+fn indexed_san(position: &Chess, encoded: Option<i32>) -> String {
+    encoded
+        .and_then(|byte| decode_move(byte as u8, position))
+        .map_or_else(
+            || "*".to_string(),
+            |m| SanPlus::from_move(position.clone(), &m).to_string(),
+        )
+}
+
+/// Whether `game` reaches `position` at `ply`, to tell its match in the position index
+/// from a hash collision
+fn reaches(game: &NormalizedGame, ply: i32, position: &Chess) -> bool {
+    let Some(mut current) = game_start(Some(&game.fen)) else {
+        return false;
+    };
+    for san in game.moves.split_whitespace().take(ply as usize) {
+        let Some(m) = san
+            .parse::<SanPlus>()
+            .ok()
+            .and_then(|san| san.san.to_move(&current).ok())
+        else {
+            return false;
+        };
+        current.play_unchecked(&m);
     }
+    current.board() == position.board()
+        && current.turn() == position.turn()
+        && current.castles().castling_rights() == position.castles().castling_rights()
+        && current.ep_square(EnPassantMode::Legal) == position.ep_square(EnPassantMode::Legal)
+}
 
-    drop(permit);
-    Ok(exists)
+/// Looks `position` up in the position index, with statistics of all the games that
+/// reached it and the `limit` games from `offset`. The games of the page are replayed
+/// to leave out hash collisions, which are too rare to replay every game for the
+/// statistics.
+fn find_position(
+    db: &mut SqliteConnection,
+    position: &Chess,
+    offset: i64,
+    limit: i64,
+) -> Result<PositionSearch, Error> {
+    let hash = position_hash(position);
+    let mut counts: Vec<MoveCounts> = sql_query(MOVE_COUNTS_SQL)
+        .bind::<BigInt, _>(hash)
+        .load(db)?;
+    counts.sort_by_key(|count| -count.games);
+    let total = |count: fn(&MoveCounts) -> i64| counts.iter().map(count).sum::<i64>();
+    let total_games = total(|count| count.games);
+    let percent = |games: i64| {
+        if total_games == 0 {
+            0.0
+        } else {
+            games as f64 / total_games as f64 * 100.0
+        }
+    };
+    let rated = total(|count| count.rated);
+
+    let page: Vec<(i32, i32, Option<i32>)> = positions::table
+        .filter(positions::hash.eq(hash))
+        .order(positions::game_id.desc())
+        .offset(offset)
+        .limit(limit)
+        .select((positions::game_id, positions::ply, positions::move_))
+        .load(db)?;
+    let ids: Vec<i32> = page.iter().map(|(id, _, _)| *id).collect();
+    let mut found = games_by_id(db, ids)?;
+    let matches = page
+        .into_iter()
+        .filter_map(|(id, ply, next)| {
+            let game = found
+                .remove(&id)
+                .filter(|game| reaches(game, ply, position))?;
+            Some(PositionMatch {
+                game,
+                ply,
+                move_: indexed_san(position, next),
+            })
+        })
+        .collect();
+
+    Ok(PositionSearch {
+        games: total_games as usize,
+        white: percent(total(|count| count.white)),
+        draw: percent(total(|count| count.draw)),
+        black: percent(total(|count| count.black)),
+        average_rating: (rated > 0).then(|| total(|count| count.rating_sum) as f64 / rated as f64),
+        moves: counts
+            .iter()
+            .map(|count| PositionStats {
+                move_: indexed_san(position, count.move_),
+                white: count.white as i32,
+                draw: count.draw as i32,
+                black: count.black as i32,
+            })
+            .collect(),
+        matches,
+    })
+}
+
+/// Finds the games that reached a position without replaying them, using the position
+/// index built when the games were imported. Only the first plies of each game are
+/// indexed.
+#[tauri::command]
+pub async fn search_exact_position(
+    file: PathBuf,
+    fen: String,
+    offset: i64,
+    limit: i64,
+    state: tauri::State<'_, AppState>,
+) -> Result<PositionSearch, Error> {
+    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+    if !has_position_index(db)? {
+        return Err(Error::MissingPositionIndex);
+    }
+    let position: Chess =
+        Fen::from_ascii(fen.as_bytes())?.into_position(shakmaty::CastlingMode::Chess960)?;
+    find_position(db, &position, offset, limit)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    use crate::db::test_utils::{empty_database, import};
+
+    fn position(moves: &[&str]) -> Chess {
+        let mut position = Chess::default();
+        for san in moves {
+            let m = san
+                .parse::<SanPlus>()
+                .unwrap()
+                .san
+                .to_move(&position)
+                .unwrap();
+            position.play_unchecked(&m);
+        }
+        position
+    }
+
+    #[test]
+    fn finds_indexed_positions() {
+        let db = &mut empty_database();
+        import(
+            db,
+            "[White \"A\"]\n[WhiteElo \"2000\"]\n[BlackElo \"2200\"]\n[Result \"1-0\"]\n\n\
+             1. e4 e5 2. Nf3 Nc6 1-0\n\n\
+             [White \"B\"]\n[WhiteElo \"1800\"]\n[Result \"0-1\"]\n\n1. Nf3 e5 2. e4 Nf6 0-1\n\n\
+             [White \"C\"]\n[Result \"1/2-1/2\"]\n\n1. d4 d5 1/2-1/2",
+            20,
+        );
+
+        let start = find_position(db, &Chess::default(), 0, 10).unwrap();
+        assert_eq!(start.games, 3);
+        assert_eq!(start.moves.len(), 3);
+        assert_eq!(start.matches.len(), 3);
+        assert!((start.draw - 100.0 / 3.0).abs() < 1e-9);
+
+        // Reached by both move orders
+        let search = find_position(db, &position(&["e4", "e5", "Nf3"]), 0, 1).unwrap();
+        assert_eq!(search.games, 2);
+        assert_eq!(search.white, 50.0);
+        assert_eq!(search.black, 50.0);
+        assert_eq!(search.average_rating, Some(1950.0));
+        let mut moves: Vec<&str> = search.moves.iter().map(|m| m.move_.as_str()).collect();
+        moves.sort();
+        assert_eq!(moves, ["Nc6", "Nf6"]);
+        assert_eq!(search.matches.len(), 1);
+        assert_eq!(search.matches[0].game.white, "B");
+        assert_eq!(search.matches[0].ply, 3);
+        assert_eq!(search.matches[0].move_, "Nf6");
+
+        let end = find_position(db, &position(&["d4", "d5"]), 0, 10).unwrap();
+        assert_eq!(end.moves[0].move_, "*");
+        assert_eq!(end.moves[0].draw, 1);
+
+        let missing = find_position(db, &position(&["c4"]), 0, 10).unwrap();
+        assert_eq!(missing.games, 0);
+        assert_eq!(missing.average_rating, None);
+    }
+
+    #[test]
+    fn counts_indexed_positions() {
+        let db = &mut empty_database();
+        import(
+            db,
+            "[Result \"1-0\"]\n\n1. e4 e5 2. Nf3 Nc6 1-0\n\n\
+             [Result \"0-1\"]\n\n1. e4 c5 0-1\n\n\
+             [Result \"1/2-1/2\"]\n\n1. e4 e5 1/2-1/2",
+            20,
+        );
+
+        let start = count_indexed(db, &Chess::default()).unwrap();
+        assert_eq!(start.games, 3);
+        assert_eq!(start.moves.len(), 1);
+        assert_eq!(start.moves[0].move_, "e4");
+
+        let open = count_indexed(db, &position(&["e4", "e5"])).unwrap();
+        assert_eq!(open.games, 2);
+        let continued = open.moves.iter().find(|m| m.move_ == "Nf3").unwrap();
+        assert_eq!(continued.white, 1);
+        let ended = open.moves.iter().find(|m| m.move_ == "*").unwrap();
+        assert_eq!(ended.draw, 1);
+    }
+
+    #[test]
+    fn leaves_out_hash_collisions() {
+        let db = &mut empty_database();
+        import(
+            db,
+            "[White \"A\"]\n\n1. e4 e5 2. Nf3 *\n\n[White \"B\"]\n\n1. d4 d5 *",
+            20,
+        );
+        let target = position(&["e4", "e5", "Nf3"]);
+        diesel::update(
+            positions::table
+                .filter(positions::game_id.eq(2))
+                .filter(positions::ply.eq(2)),
+        )
+        .set(positions::hash.eq(position_hash(&target)))
+        .execute(db)
+        .unwrap();
+
+        let search = find_position(db, &target, 0, 10).unwrap();
+        assert_eq!(search.games, 2);
+        let found: Vec<&str> = search
+            .matches
+            .iter()
+            .map(|m| m.game.white.as_str())
+            .collect();
+        assert_eq!(found, ["A"]);
+    }
+
+    #[test]
+    fn indexes_only_the_first_plies() {
+        let db = &mut empty_database();
+        let games = import(db, "1. Nf3 Nf6 2. Ng1 Ng8 3. e4 *", 4);
+        // The start position is reached again at ply 4 but only indexed once
+        let plies: Vec<i32> = games[0].positions.iter().map(|(_, ply, _)| *ply).collect();
+        assert_eq!(plies, [0, 1, 2, 3]);
+        assert_eq!(
+            find_position(db, &position(&["e4"]), 0, 10).unwrap().games,
+            0
+        );
+        let start = find_position(db, &Chess::default(), 0, 10).unwrap();
+        assert_eq!(start.moves[0].move_, "Nf3");
+    }
+
     fn assert_partial_match(fen1: &str, fen2: &str) {
         let query = PositionQuery::partial_from_fen(fen1).unwrap();
         let fen = Fen::from_ascii(fen2.as_bytes()).unwrap();
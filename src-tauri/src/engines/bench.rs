@@ -0,0 +1,493 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+use specta::Type;
+use tauri::AppHandle;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncWriteExt, BufReader},
+    process::{Child, ChildStdin},
+    sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+};
+
+use super::{engines_dir, save_engines, stored_engines};
+use crate::{
+    chess::{start_engine, EngineProbeError, HANDSHAKE_TIMEOUT},
+    error::Error,
+    AppState,
+};
+
+/// Nodes searched in each position of the suite, for engines without `bench`
+const SUITE_NODES: u64 = 1_000_000;
+
+/// Positions searched by engines without a `bench` command, from the one of Stockfish
+const SUITE: [&str; 10] = [
+    "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+    "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 10",
+    "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 11",
+    "4rrk1/pp1n3p/3q2pQ/2p1pb2/2PP4/2P3N1/P2B2PP/4RRK1 b - - 7 19",
+    "rq3rk1/ppp2ppp/1bnpb3/3N2B1/3NP3/7P/PPPQ1PP1/2KR3R w - - 7 14",
+    "r1bq1r1k/1pp1n1pp/1p1p4/4p2Q/4Pp2/1BNP4/PPP2PPP/3R1RK1 w - - 2 14",
+    "r3r1k1/2p2ppp/p1p1bn2/8/1q2P3/2NPQN2/PPP3PP/R4RK1 b - - 2 15",
+    "r1bbk1nr/pp3p1p/2n5/1N4p1/2Np1B2/8/PPP2PPP/2KR1B1R w kq - 0 13",
+    "r1bq1rk1/ppp1nppp/4n3/3p3Q/3P4/1BP1B3/PP1N2PP/R4RK1 w - - 1 16",
+    "4r1k1/r1q2ppp/ppp2n2/4P3/5Rb1/1N1BQ3/PPP3PP/R5K1 w - - 1 17",
+];
+
+/// How often a benchmark checks whether it was cancelled while the engine thinks
+const CANCEL_POLL: Duration = Duration::from_millis(100);
+
+/// Nodes per second machines get, roughly, with the `bench` of Stockfish 16 and its
+/// default parameters, a single thread at depth 13
+const REFERENCE_MACHINES: [(&str, u64); 5] = [
+    ("Raspberry Pi 4", 180_000),
+    ("Typical laptop", 900_000),
+    ("Apple M1", 1_300_000),
+    ("Desktop with a Ryzen 7 5800X", 1_700_000),
+    ("Desktop with a Core i9-13900K", 2_400_000),
+];
+
+/// The reference machine results are described relative to
+const TYPICAL_MACHINE: &str = "Typical laptop";
+
+#[derive(Debug, Clone, Copy, Serialize, Type, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum BenchmarkMethod {
+    /// The `bench` command of the engine
+    Bench,
+    /// The positions of `SUITE` at `SUITE_NODES` nodes
+    Suite,
+}
+
+#[derive(Debug, Clone, Serialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct EngineBenchmark {
+    pub nps: u64,
+    pub nodes: u64,
+    pub time_ms: u64,
+    pub method: BenchmarkMethod,
+    pub threads: u32,
+    pub hash: u32,
+    /// When it ran, in RFC 3339
+    pub date: String,
+}
+
+#[derive(Debug, Clone, Serialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct ReferenceComparison {
+    pub machine: String,
+    pub nps: u64,
+    /// Nodes per second of this machine divided by the ones of the reference
+    pub ratio: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct HardwareCheck {
+    pub nodes: u64,
+    pub nps: u64,
+    pub time_ms: u64,
+    /// Ratio to the typical laptop of the reference machines
+    pub relative: f64,
+    pub references: Vec<ReferenceComparison>,
+    /// When it ran, in RFC 3339
+    pub date: String,
+}
+
+struct Engine {
+    stdin: ChildStdin,
+    /// Lines of stdout and stderr, where Stockfish prints the results of `bench`
+    lines: UnboundedReceiver<String>,
+}
+
+fn forward_lines(output: impl AsyncRead + Unpin + Send + 'static, sender: UnboundedSender<String>) {
+    tokio::spawn(async move {
+        let mut lines = BufReader::new(output).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            if sender.send(line).is_err() {
+                break;
+            }
+        }
+    });
+}
+
+impl Engine {
+    /// Starts the engine at `path`, with a process of its own rather than the one of an
+    /// analysis session so that the output of `bench` doesn't reach the best moves
+    fn spawn(path: &str) -> Result<(Child, Engine), Error> {
+        let mut child = start_engine(PathBuf::from(path))?;
+        let (sender, lines) = unbounded_channel();
+        forward_lines(child.stdout.take().ok_or(Error::NoStdout)?, sender.clone());
+        if let Some(stderr) = child.stderr.take() {
+            forward_lines(stderr, sender);
+        }
+        let engine = Engine {
+            stdin: child.stdin.take().ok_or(Error::NoStdin)?,
+            lines,
+        };
+        Ok((child, engine))
+    }
+
+    async fn send(&mut self, command: &str) -> Result<(), Error> {
+        self.stdin.write_all(command.as_bytes()).await?;
+        Ok(())
+    }
+
+    /// Lines of the engine until the first one `last` accepts, included
+    async fn read_until(
+        &mut self,
+        cancelled: &AtomicBool,
+        last: impl Fn(&str) -> bool,
+    ) -> Result<Vec<String>, Error> {
+        let mut lines = Vec::new();
+        loop {
+            if cancelled.load(Ordering::Relaxed) {
+                return Err(Error::BenchmarkCancelled);
+            }
+            match tokio::time::timeout(CANCEL_POLL, self.lines.recv()).await {
+                Ok(Some(line)) => {
+                    let done = last(&line);
+                    lines.push(line);
+                    if done {
+                        return Ok(lines);
+                    }
+                }
+                Ok(None) => return Err(Error::BenchmarkFailed),
+                Err(_) => {}
+            }
+        }
+    }
+}
+
+/// Value of a `Nodes/second    : 1234` line of the output of `bench`
+fn bench_value(lines: &[String], key: &str) -> Option<u64> {
+    lines.iter().find_map(|line| {
+        let (name, value) = line.split_once(':')?;
+        if name.trim() == key {
+            value.trim().parse().ok()
+        } else {
+            None
+        }
+    })
+}
+
+/// Nodes an `info` line reports
+fn info_nodes(line: &str) -> Option<u64> {
+    let mut words = line.split_whitespace();
+    if words.next() != Some("info") {
+        return None;
+    }
+    words
+        .skip_while(|&word| word != "nodes")
+        .nth(1)?
+        .parse()
+        .ok()
+}
+
+fn is_bestmove(line: &str) -> bool {
+    line.starts_with("bestmove")
+}
+
+async fn handshake(engine: &mut Engine, cancelled: &AtomicBool) -> Result<(), Error> {
+    engine.send("uci\n").await?;
+    tokio::time::timeout(
+        HANDSHAKE_TIMEOUT,
+        engine.read_until(cancelled, |line| line.trim() == "uciok"),
+    )
+    .await
+    .map_err(|_| EngineProbeError::TimedOut)??;
+    Ok(())
+}
+
+/// Output of the `bench` command with `args`, until it's done. Engines without `bench`
+/// answer the `isready` that follows right away, Stockfish once it's done.
+async fn bench(
+    engine: &mut Engine,
+    args: &str,
+    cancelled: &AtomicBool,
+) -> Result<Vec<String>, Error> {
+    engine.send(&format!("bench {args}\nisready\n")).await?;
+    engine
+        .read_until(cancelled, |line| line.trim() == "readyok")
+        .await
+}
+
+async fn run_benchmark(
+    engine: &mut Engine,
+    threads: u32,
+    hash: u32,
+    cancelled: &AtomicBool,
+) -> Result<(BenchmarkMethod, u64, Duration), Error> {
+    handshake(engine, cancelled).await?;
+    engine
+        .send(&format!(
+            "setoption name Threads value {threads}\nsetoption name Hash value {hash}\nisready\n"
+        ))
+        .await?;
+    engine
+        .read_until(cancelled, |line| line.trim() == "readyok")
+        .await?;
+
+    let output = bench(
+        engine,
+        &format!("{hash} {threads} 13 default depth"),
+        cancelled,
+    )
+    .await?;
+    if let (Some(nodes), Some(ms)) = (
+        bench_value(&output, "Nodes searched"),
+        bench_value(&output, "Total time (ms)"),
+    ) {
+        return Ok((BenchmarkMethod::Bench, nodes, Duration::from_millis(ms)));
+    }
+
+    let start = Instant::now();
+    let mut nodes = 0;
+    for fen in SUITE {
+        engine
+            .send(&format!(
+                "ucinewgame\nposition fen {fen}\ngo nodes {SUITE_NODES}\n"
+            ))
+            .await?;
+        let output = engine.read_until(cancelled, is_bestmove).await?;
+        nodes += output
+            .iter()
+            .rev()
+            .find_map(|line| info_nodes(line))
+            .unwrap_or(SUITE_NODES);
+    }
+    Ok((BenchmarkMethod::Suite, nodes, start.elapsed()))
+}
+
+/// Sets `key` of the local engines at `path` in the engines store to `result`
+fn store_result(store: &Path, path: &str, key: &str, result: &impl Serialize) -> Result<(), Error> {
+    let mut engines = stored_engines(store)?;
+    let value = serde_json::to_value(result).map_err(std::io::Error::from)?;
+    for engine in &mut engines {
+        if engine["type"] == "local" && engine["path"] == path {
+            engine[key] = value.clone();
+        }
+    }
+    save_engines(store, &engines)
+}
+
+/// Runs `run` on the engine at `path` as the only benchmark, so that `cancel_benchmark`
+/// stops it, and quits the engine after
+async fn exclusive<T>(
+    state: &AppState,
+    path: &str,
+    run: impl for<'a> FnOnce(
+        &'a mut Engine,
+        &'a AtomicBool,
+    ) -> futures_util::future::BoxFuture<'a, Result<T, Error>>,
+) -> Result<T, Error> {
+    let cancelled = Arc::new(AtomicBool::new(false));
+    {
+        let mut running = state.benchmark.lock().unwrap();
+        if running.is_some() {
+            return Err(Error::BenchmarkRunning);
+        }
+        *running = Some(cancelled.clone());
+    }
+
+    let result = async {
+        let (mut child, mut engine) = Engine::spawn(path)?;
+        let result = run(&mut engine, &cancelled).await;
+        let _ = engine.send("quit\n").await;
+        let _ = child.kill().await;
+        result
+    }
+    .await;
+    *state.benchmark.lock().unwrap() = None;
+    result
+}
+
+fn compare(nps: u64) -> (f64, Vec<ReferenceComparison>) {
+    let references: Vec<_> = REFERENCE_MACHINES
+        .iter()
+        .map(|&(machine, reference)| ReferenceComparison {
+            machine: machine.to_string(),
+            nps: reference,
+            ratio: nps as f64 / reference as f64,
+        })
+        .collect();
+    let relative = references
+        .iter()
+        .find(|reference| reference.machine == TYPICAL_MACHINE)
+        .map_or(1.0, |reference| reference.ratio);
+    (relative, references)
+}
+
+/// Measures the nodes per second of the engine at `id` with `threads` threads and
+/// `hash` MB of hash, with its `bench` command when it has one and by searching ten
+/// positions otherwise. The result is stored with the engine in the engines store.
+/// Only one benchmark runs at a time, and `cancel_benchmark` stops it.
+#[tauri::command]
+#[specta::specta]
+pub async fn benchmark_engine(
+    id: String,
+    threads: u32,
+    hash: u32,
+    state: tauri::State<'_, AppState>,
+    app: AppHandle,
+) -> Result<EngineBenchmark, Error> {
+    let (method, nodes, time) = exclusive(&state, &id, |engine, cancelled| {
+        Box::pin(run_benchmark(engine, threads, hash, cancelled))
+    })
+    .await?;
+    let time_ms = time.as_millis().max(1) as u64;
+    let benchmark = EngineBenchmark {
+        nps: nodes * 1000 / time_ms,
+        nodes,
+        time_ms,
+        method,
+        threads,
+        hash,
+        date: chrono::Utc::now().to_rfc3339(),
+    };
+    store_result(
+        &engines_dir(&app)?.join("engines.json"),
+        &id,
+        "benchmark",
+        &benchmark,
+    )?;
+    Ok(benchmark)
+}
+
+/// Runs the `bench` command of the engine at `engine_id` with its default parameters
+/// and compares its speed with the one of `REFERENCE_MACHINES`, for a measure of the
+/// hardware that is the same from one run to the next. The result is stored with the
+/// engine as `hardwareCheck`. It shares the slot of `benchmark_engine`, so it's
+/// cancelled with `cancel_benchmark` and can't run at the same time.
+#[tauri::command]
+#[specta::specta]
+pub async fn hardware_check(
+    engine_id: String,
+    state: tauri::State<'_, AppState>,
+    app: AppHandle,
+) -> Result<HardwareCheck, Error> {
+    let output = exclusive(&state, &engine_id, |engine, cancelled| {
+        Box::pin(async move {
+            handshake(engine, cancelled).await?;
+            bench(engine, "", cancelled).await
+        })
+    })
+    .await?;
+    let (Some(nodes), Some(time_ms)) = (
+        bench_value(&output, "Nodes searched"),
+        bench_value(&output, "Total time (ms)"),
+    ) else {
+        return Err(Error::NoBenchCommand);
+    };
+    let time_ms = time_ms.max(1);
+    let nps = bench_value(&output, "Nodes/second").unwrap_or(nodes * 1000 / time_ms);
+    let (relative, references) = compare(nps);
+    let check = HardwareCheck {
+        nodes,
+        nps,
+        time_ms,
+        relative,
+        references,
+        date: chrono::Utc::now().to_rfc3339(),
+    };
+    store_result(
+        &engines_dir(&app)?.join("engines.json"),
+        &engine_id,
+        "hardwareCheck",
+        &check,
+    )?;
+    Ok(check)
+}
+
+/// Stops the running benchmark
+#[tauri::command]
+#[specta::specta]
+pub fn cancel_benchmark(state: tauri::State<'_, AppState>) {
+    if let Some(cancelled) = state.benchmark.lock().unwrap().as_ref() {
+        cancelled.store(true, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::json;
+    use shakmaty::{fen::Fen, CastlingMode, Chess};
+
+    #[test]
+    fn reads_engine_output() {
+        let output: Vec<String> = [
+            "info string NNUE evaluation using nn-b1a57edbea57.nnue enabled",
+            "===========================",
+            "Total time (ms) : 2140",
+            "Nodes searched  : 3440562",
+            "Nodes/second    : 1607739",
+            "readyok",
+        ]
+        .map(String::from)
+        .to_vec();
+        assert_eq!(bench_value(&output, "Nodes searched"), Some(3440562));
+        assert_eq!(bench_value(&output, "Total time (ms)"), Some(2140));
+        assert_eq!(bench_value(&output, "Nodes/second"), Some(1607739));
+        assert_eq!(bench_value(&output[..1], "Nodes searched"), None);
+
+        assert_eq!(
+            info_nodes("info depth 12 seldepth 18 score cp 31 nodes 104221 nps 912000 pv e2e4"),
+            Some(104221)
+        );
+        assert_eq!(info_nodes("bestmove e2e4 ponder e7e5"), None);
+        assert!(is_bestmove("bestmove e2e4 ponder e7e5"));
+    }
+
+    #[test]
+    fn compares_with_reference_machines() {
+        let (relative, references) = compare(1_530_000);
+        assert!((relative - 1.7).abs() < 1e-9);
+        assert_eq!(references.len(), REFERENCE_MACHINES.len());
+        assert!(references.iter().any(|reference| reference.ratio > 1.0));
+        assert!(references.iter().any(|reference| reference.ratio < 1.0));
+    }
+
+    #[test]
+    fn suite_positions_are_legal() {
+        for fen in SUITE {
+            let fen: Fen = fen.parse().unwrap();
+            fen.into_position::<Chess>(CastlingMode::Standard).unwrap();
+        }
+    }
+
+    #[test]
+    fn stores_the_result_with_the_engine() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = dir.path().join("engines.json");
+        save_engines(
+            &store,
+            &[
+                json!({ "type": "local", "name": "Stockfish", "path": "/sf" }),
+                json!({ "type": "local", "name": "Berserk", "path": "/berserk" }),
+            ],
+        )
+        .unwrap();
+        let benchmark = EngineBenchmark {
+            nps: 1_000_000,
+            nodes: 2_000_000,
+            time_ms: 2000,
+            method: BenchmarkMethod::Suite,
+            threads: 1,
+            hash: 16,
+            date: "2024-03-01T12:00:00+00:00".to_string(),
+        };
+        store_result(&store, "/sf", "benchmark", &benchmark).unwrap();
+        let engines = stored_engines(&store).unwrap();
+        assert_eq!(engines[0]["benchmark"]["nps"], 1_000_000);
+        assert_eq!(engines[0]["benchmark"]["method"], "suite");
+        assert!(engines[1].get("benchmark").is_none());
+    }
+}
@@ -0,0 +1,745 @@
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
+
+use log::info;
+use serde::{Deserialize, Serialize};
+use shakmaty::{
+    fen::Fen, san::SanPlus, uci::Uci, zobrist::Zobrist64, Chess, Color, EnPassantMode, Position,
+};
+use specta::Type;
+use tauri::{AppHandle, Manager};
+use tauri_specta::Event;
+use vampirc_uci::uci::{Score, ScoreValue};
+
+use crate::{
+    chess::{
+        kill_session, parse_position, search_move, validate_fen, Clock, EngineOption,
+        EngineOptions, GoMode,
+    },
+    db::add_games,
+    error::Error,
+    tablebase::{probe_with, TablebaseCategory, TablebaseOptions},
+    AppState,
+};
+
+/// Title of the databases created for match games
+const MATCH_DATABASE: &str = "Engine matches";
+
+/// Centipawns mates count as for the evaluation adjudication
+const MATE_CP: i32 = 100_000;
+
+#[derive(Deserialize, Debug, Clone, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct MatchEngine {
+    /// Path of the engine, its id in the engines store
+    pub path: String,
+    /// Name of the player in the games
+    pub name: String,
+    pub options: Vec<EngineOption>,
+}
+
+/// Position a pair of games starts from, `fen` then the UCI `moves`
+#[derive(Deserialize, Debug, Clone, Default, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct MatchOpening {
+    pub fen: Option<String>,
+    pub moves: Vec<String>,
+}
+
+#[derive(Deserialize, Debug, Clone, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct TablebaseAdjudication {
+    pub options: TablebaseOptions,
+    /// Games are adjudicated once this many pieces are left
+    pub max_pieces: usize,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct EvalAdjudication {
+    /// Centipawns both engines have to see for the same side
+    pub threshold: i32,
+    /// Moves of each engine in a row the evaluations have to stay over the threshold
+    pub moves: u32,
+}
+
+#[derive(Deserialize, Debug, Clone, Default, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct MatchAdjudication {
+    pub tablebase: Option<TablebaseAdjudication>,
+    pub eval: Option<EvalAdjudication>,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, Type, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum Termination {
+    Checkmate,
+    Stalemate,
+    InsufficientMaterial,
+    FiftyMoves,
+    Repetition,
+    Tablebase,
+    Evaluation,
+    TimeForfeit,
+    /// The engine sent an illegal move, or none in a position with legal moves
+    IllegalMove,
+}
+
+impl Termination {
+    /// Value of the PGN `Termination` header
+    fn header(self) -> &'static str {
+        match self {
+            Termination::Checkmate
+            | Termination::Stalemate
+            | Termination::InsufficientMaterial
+            | Termination::FiftyMoves
+            | Termination::Repetition => "normal",
+            Termination::Tablebase | Termination::Evaluation => "adjudication",
+            Termination::TimeForfeit => "time forfeit",
+            Termination::IllegalMove => "rules infraction",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct GameEnd {
+    winner: Option<Color>,
+    termination: Termination,
+}
+
+fn result_tag(winner: Option<Color>) -> &'static str {
+    match winner {
+        Some(Color::White) => "1-0",
+        Some(Color::Black) => "0-1",
+        None => "1/2-1/2",
+    }
+}
+
+/// Times each position of the game was reached, for threefold repetitions
+#[derive(Debug, Default)]
+struct Repetitions(HashMap<u64, u32>);
+
+impl Repetitions {
+    fn add(&mut self, pos: &Chess) -> u32 {
+        let key: u64 = pos.zobrist_hash::<Zobrist64>(EnPassantMode::Legal).into();
+        let count = self.0.entry(key).or_default();
+        *count += 1;
+        *count
+    }
+}
+
+/// End of the game by the rules in `pos`, reached for the `repetitions`th time
+fn rule_end(pos: &Chess, repetitions: u32) -> Option<GameEnd> {
+    let termination = if pos.is_checkmate() {
+        return Some(GameEnd {
+            winner: Some(!pos.turn()),
+            termination: Termination::Checkmate,
+        });
+    } else if pos.is_stalemate() {
+        Termination::Stalemate
+    } else if pos.is_insufficient_material() {
+        Termination::InsufficientMaterial
+    } else if pos.halfmoves() >= 100 {
+        Termination::FiftyMoves
+    } else if repetitions >= 3 {
+        Termination::Repetition
+    } else {
+        return None;
+    };
+    Some(GameEnd {
+        winner: None,
+        termination,
+    })
+}
+
+/// Winner of a tablebase result for the side to move, `Some(None)` for draws and `None`
+/// when the result depends on the moves since the last capture or pawn move
+fn tablebase_winner(category: TablebaseCategory, turn: Color) -> Option<Option<Color>> {
+    match category {
+        TablebaseCategory::Win => Some(Some(turn)),
+        TablebaseCategory::Loss => Some(Some(!turn)),
+        TablebaseCategory::MaybeWin | TablebaseCategory::MaybeLoss => None,
+        _ => Some(None),
+    }
+}
+
+fn white_cp(score: &Score) -> i32 {
+    match score.value {
+        ScoreValue::Cp(cp) => cp,
+        ScoreValue::Mate(moves) if moves >= 0 => MATE_CP,
+        ScoreValue::Mate(_) => -MATE_CP,
+    }
+}
+
+/// Evaluations in a row over the threshold of `EvalAdjudication` for the same side
+#[derive(Debug, Default)]
+struct EvalStreak {
+    side: Option<Color>,
+    plies: u32,
+}
+
+impl EvalStreak {
+    /// Adds the evaluation of the last move, from White's point of view, returning the
+    /// side the game is adjudicated to
+    fn add(&mut self, score: Option<&Score>, rule: &EvalAdjudication) -> Option<Color> {
+        let side = score
+            .map(white_cp)
+            .filter(|cp| cp.abs() > rule.threshold)
+            .map(|cp| Color::from_white(cp > 0));
+        if side.is_some() && side == self.side {
+            self.plies += 1;
+        } else {
+            self.side = side;
+            self.plies = u32::from(side.is_some());
+        }
+        self.side.filter(|_| self.plies >= rule.moves.max(1) * 2)
+    }
+}
+
+/// Elo difference of a score, with the margin of its 95% confidence interval
+fn elo_difference(wins: u32, draws: u32, losses: u32) -> Option<(f64, f64)> {
+    let games = f64::from(wins + draws + losses);
+    let score = (f64::from(wins) + f64::from(draws) / 2.0) / games.max(1.0);
+    if score <= 0.0 || score >= 1.0 {
+        return None;
+    }
+    let elo = |score: f64| -400.0 * (1.0 / score - 1.0).log10();
+    let variance = (f64::from(wins) * (1.0 - score).powi(2)
+        + f64::from(draws) * (0.5 - score).powi(2)
+        + f64::from(losses) * score.powi(2))
+        / games;
+    let margin = 1.96 * (variance / games).sqrt();
+    let high = elo((score + margin).min(0.999));
+    let low = elo((score - margin).max(0.001));
+    Some((elo(score), (high - low) / 2.0))
+}
+
+/// Movetext of `moves` from `start`, with the move numbers of the position
+fn movetext(start: &Chess, moves: &[Uci]) -> Result<String, Error> {
+    let mut pos = start.clone();
+    let mut text = Vec::new();
+    for (i, uci) in moves.iter().enumerate() {
+        let m = uci.to_move(&pos)?;
+        let number = pos.fullmoves();
+        if pos.turn().is_white() {
+            text.push(format!("{number}."));
+        } else if i == 0 {
+            text.push(format!("{number}..."));
+        }
+        text.push(SanPlus::from_move_and_play_unchecked(&mut pos, &m).to_string());
+    }
+    Ok(text.join(" "))
+}
+
+fn game_pgn(
+    headers: &[(&str, String)],
+    start: &Chess,
+    moves: &[Uci],
+    result: &str,
+) -> Result<String, Error> {
+    let mut pgn: String = headers
+        .iter()
+        .map(|(name, value)| format!("[{name} \"{}\"]\n", value.replace('"', "'")))
+        .collect();
+    let movetext = movetext(start, moves)?;
+    pgn.push('\n');
+    if !movetext.is_empty() {
+        pgn.push_str(&movetext);
+        pgn.push(' ');
+    }
+    pgn.push_str(result);
+    pgn.push_str("\n\n");
+    Ok(pgn)
+}
+
+#[derive(Clone, Type, Serialize, Event)]
+pub struct MatchProgress {
+    pub id: String,
+    /// Games finished so far
+    pub game: u32,
+    pub games: u32,
+    pub white: String,
+    pub black: String,
+    /// Result of the last game
+    pub result: String,
+    /// Score of the first engine so far
+    pub wins: u32,
+    pub draws: u32,
+    pub losses: u32,
+    pub finished: bool,
+}
+
+#[derive(Serialize, Debug, Clone, Default, PartialEq, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct MatchSummary {
+    pub games: u32,
+    /// Games the first engine won
+    pub wins: u32,
+    pub draws: u32,
+    pub losses: u32,
+    /// Points of the first engine over the games played
+    pub score: f64,
+    /// Elo difference of the first engine, unknown when it won or lost every game
+    pub elo: Option<f64>,
+    /// Margin of the elo difference at 95% confidence
+    pub elo_margin: Option<f64>,
+    /// The match was cancelled, the games finished were kept
+    pub cancelled: bool,
+}
+
+impl MatchSummary {
+    fn add(&mut self, winner: Option<Color>, first_color: Color) {
+        self.games += 1;
+        match winner {
+            Some(color) if color == first_color => self.wins += 1,
+            Some(_) => self.losses += 1,
+            None => self.draws += 1,
+        }
+        self.score = (f64::from(self.wins) + f64::from(self.draws) / 2.0) / f64::from(self.games);
+        let elo = elo_difference(self.wins, self.draws, self.losses);
+        self.elo = elo.map(|(elo, _)| elo);
+        self.elo_margin = elo.map(|(_, margin)| margin);
+    }
+}
+
+/// Searches with the clock when the time control is one, updating it with the time the
+/// engine took. Returns `None` when the engine ran out of time.
+fn spend_time(clock: &mut Clock, color: Color, elapsed: u32) -> Option<()> {
+    let (time, increment) = match color {
+        Color::White => (&mut clock.wtime, clock.winc),
+        Color::Black => (&mut clock.btime, clock.binc),
+    };
+    *time = time.checked_sub(elapsed)? + increment;
+    Some(())
+}
+
+struct MatchGame<'a> {
+    id: &'a str,
+    engines: [(&'a MatchEngine, String); 2],
+    /// Which of `engines` plays white
+    white: usize,
+    time_control: &'a GoMode,
+    adjudication: &'a MatchAdjudication,
+    cancelled: &'a AtomicBool,
+}
+
+impl MatchGame<'_> {
+    /// Plays the game from the opening, returning how it ended and its moves, or `None`
+    /// when the match was cancelled
+    async fn play(
+        &self,
+        opening: &MatchOpening,
+        app: &AppHandle,
+        state: &AppState,
+    ) -> Result<Option<(GameEnd, Vec<Uci>)>, Error> {
+        let fen = opening.fen.clone().unwrap_or_else(|| {
+            Fen::from_position(Chess::default(), EnPassantMode::Legal).to_string()
+        });
+        let mut pos = parse_position(&validate_fen(&fen)?, &opening.moves)?;
+        let mut moves: Vec<Uci> = opening
+            .moves
+            .iter()
+            .map(|m| Uci::from_ascii(m.as_bytes()))
+            .collect::<Result<_, _>>()?;
+        let mut clock = match self.time_control {
+            GoMode::Clock(clock) => Some(clock.clone()),
+            _ => None,
+        };
+        let mut repetitions = Repetitions::default();
+        let mut streak = EvalStreak::default();
+        let mut count = repetitions.add(&pos);
+        loop {
+            if let Some(end) = rule_end(&pos, count) {
+                return Ok(Some((end, moves)));
+            }
+            if let Some(rule) = &self.adjudication.tablebase {
+                if pos.board().occupied().count() <= rule.max_pieces {
+                    let winner = probe_with(state, &pos, &rule.options)
+                        .await
+                        .ok()
+                        .and_then(|result| tablebase_winner(result.category, pos.turn()));
+                    if let Some(winner) = winner {
+                        let termination = Termination::Tablebase;
+                        return Ok(Some((
+                            GameEnd {
+                                winner,
+                                termination,
+                            },
+                            moves,
+                        )));
+                    }
+                }
+            }
+            if self.cancelled.load(Ordering::Relaxed) {
+                return Ok(None);
+            }
+
+            let turn = pos.turn();
+            let side = if turn.is_white() {
+                self.white
+            } else {
+                1 - self.white
+            };
+            let (engine, tab) = &self.engines[side];
+            let go_mode = match &clock {
+                Some(clock) => GoMode::Clock(clock.clone()),
+                None => self.time_control.clone(),
+            };
+            let options = EngineOptions {
+                fen: fen.clone(),
+                moves: moves.iter().map(|m| m.to_string()).collect(),
+                extra_options: engine.options.clone(),
+                tablebase: None,
+                cloud_eval: None,
+                max_pv_length: None,
+            };
+            let start = Instant::now();
+            let engine_move = search_move(
+                self.id.to_string(),
+                engine.path.clone(),
+                tab.clone(),
+                &go_mode,
+                options,
+                app.clone(),
+                state,
+            )
+            .await?;
+            let lost = |termination| -> Result<Option<(GameEnd, Vec<Uci>)>, Error> {
+                Ok(Some((
+                    GameEnd {
+                        winner: Some(!turn),
+                        termination,
+                    },
+                    moves.clone(),
+                )))
+            };
+            if let Some(clock) = &mut clock {
+                let elapsed = start.elapsed().as_millis() as u32;
+                if spend_time(clock, turn, elapsed).is_none() {
+                    return lost(Termination::TimeForfeit);
+                }
+            }
+            let played = engine_move
+                .uci
+                .as_deref()
+                .and_then(|uci| Uci::from_ascii(uci.as_bytes()).ok())
+                .and_then(|uci| Some((uci.to_move(&pos).ok()?, uci)));
+            let Some((m, uci)) = played else {
+                return lost(Termination::IllegalMove);
+            };
+            pos.play_unchecked(&m);
+            moves.push(uci);
+            count = repetitions.add(&pos);
+            if let Some(rule) = &self.adjudication.eval {
+                if let Some(winner) = streak.add(engine_move.score.as_ref(), rule) {
+                    let end = GameEnd {
+                        winner: Some(winner),
+                        termination: Termination::Evaluation,
+                    };
+                    return Ok(Some((end, moves)));
+                }
+            }
+        }
+    }
+}
+
+/// Plays `games` games between two engines, or two settings of one, from `openings`
+/// in turn, each opening twice with the colors switched. Games end by the rules, on
+/// time with a clock time control, and optionally by tablebase or evaluation
+/// adjudication. Every game is added to the database `database` as soon as it ends,
+/// and emitted as `MatchProgress`. `cancel_match(id)` stops it after the moves being
+/// searched, keeping the games finished. Returns the score of `engine_a`.
+#[tauri::command]
+#[specta::specta]
+#[allow(clippy::too_many_arguments)]
+pub async fn run_match(
+    id: String,
+    engine_a: MatchEngine,
+    engine_b: MatchEngine,
+    time_control: GoMode,
+    openings: Vec<MatchOpening>,
+    games: u32,
+    adjudication: MatchAdjudication,
+    database: PathBuf,
+    state: tauri::State<'_, AppState>,
+    app: AppHandle,
+) -> Result<MatchSummary, Error> {
+    if !app.fs_scope().is_allowed(&database) {
+        return Err(Error::ForbiddenPath);
+    }
+    let openings = if openings.is_empty() {
+        vec![MatchOpening::default()]
+    } else {
+        openings
+    };
+    let tabs = [format!("match-{id}-a"), format!("match-{id}-b")];
+    let cancelled = Arc::new(AtomicBool::new(false));
+    state.matches.insert(id.clone(), cancelled.clone());
+    let date = chrono::Local::now().format("%Y.%m.%d").to_string();
+    info!(
+        "Starting a match of {games} games between {} and {}",
+        engine_a.name, engine_b.name
+    );
+
+    let mut summary = MatchSummary::default();
+    let mut result = Ok(());
+    for game in 0..games {
+        let opening = &openings[(game / 2) as usize % openings.len()];
+        let white = (game % 2) as usize;
+        let engines = [(&engine_a, tabs[0].clone()), (&engine_b, tabs[1].clone())];
+        let names = [&engine_a.name, &engine_b.name];
+        let runner = MatchGame {
+            id: &id,
+            engines,
+            white,
+            time_control: &time_control,
+            adjudication: &adjudication,
+            cancelled: &cancelled,
+        };
+        let played = match runner.play(opening, &app, &state).await {
+            Ok(Some(played)) => played,
+            Ok(None) => {
+                summary.cancelled = true;
+                break;
+            }
+            Err(e) => {
+                result = Err(e);
+                break;
+            }
+        };
+        let (end, moves) = played;
+        let tag = result_tag(end.winner);
+        let mut headers = vec![
+            ("Event", "Engine match".to_string()),
+            ("Site", "En Croissant".to_string()),
+            ("Date", date.clone()),
+            ("Round", (game + 1).to_string()),
+            ("White", names[white].clone()),
+            ("Black", names[1 - white].clone()),
+            ("Result", tag.to_string()),
+        ];
+        let start = match &opening.fen {
+            Some(fen) => {
+                headers.push(("FEN", fen.clone()));
+                headers.push(("SetUp", "1".to_string()));
+                parse_position(&validate_fen(fen)?, &[])?
+            }
+            None => Chess::default(),
+        };
+        headers.push(("Termination", end.termination.header().to_string()));
+        let pgn = game_pgn(&headers, &start, &moves, tag)?;
+        if let Err(e) = add_games(&state, &database, MATCH_DATABASE, &pgn) {
+            result = Err(e);
+            break;
+        }
+        let first_color = if white == 0 {
+            Color::White
+        } else {
+            Color::Black
+        };
+        summary.add(end.winner, first_color);
+        let _ = MatchProgress {
+            id: id.clone(),
+            game: summary.games,
+            games,
+            white: names[white].clone(),
+            black: names[1 - white].clone(),
+            result: tag.to_string(),
+            wins: summary.wins,
+            draws: summary.draws,
+            losses: summary.losses,
+            finished: false,
+        }
+        .emit_all(&app);
+    }
+    state.matches.remove(&id);
+    for (engine, tab) in [(&engine_a, &tabs[0]), (&engine_b, &tabs[1])] {
+        let _ = kill_session(&state, tab.clone(), engine.path.clone()).await;
+    }
+    result?;
+
+    info!(
+        "Match finished: +{} ={} -{}",
+        summary.wins, summary.draws, summary.losses
+    );
+    MatchProgress {
+        id,
+        game: summary.games,
+        games,
+        white: String::new(),
+        black: String::new(),
+        result: String::new(),
+        wins: summary.wins,
+        draws: summary.draws,
+        losses: summary.losses,
+        finished: true,
+    }
+    .emit_all(&app)?;
+    Ok(summary)
+}
+
+#[tauri::command]
+#[specta::specta]
+pub fn cancel_match(id: String, state: tauri::State<'_, AppState>) {
+    if let Some(cancelled) = state.matches.get(&id) {
+        cancelled.store(true, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use shakmaty::CastlingMode;
+
+    fn position(fen: &str) -> Chess {
+        Fen::from_ascii(fen.as_bytes())
+            .unwrap()
+            .into_position(CastlingMode::Standard)
+            .unwrap()
+    }
+
+    fn cp(cp: i32) -> Score {
+        Score {
+            value: ScoreValue::Cp(cp),
+            wdl: None,
+        }
+    }
+
+    #[test]
+    fn ends_games_by_the_rules() {
+        assert_eq!(rule_end(&Chess::default(), 2), None);
+        let mate = position("2Q1k3/8/4K3/8/8/8/8/8 b - - 0 1");
+        assert_eq!(
+            rule_end(&mate, 1),
+            Some(GameEnd {
+                winner: Some(Color::White),
+                termination: Termination::Checkmate
+            })
+        );
+        let stalemate = position("k7/2Q5/1K6/8/8/8/8/8 b - - 0 1");
+        assert_eq!(
+            rule_end(&stalemate, 1).map(|end| end.termination),
+            Some(Termination::Stalemate)
+        );
+        let bare = position("k7/8/1K6/8/8/8/8/8 w - - 0 1");
+        assert_eq!(
+            rule_end(&bare, 1).map(|end| end.termination),
+            Some(Termination::InsufficientMaterial)
+        );
+        let fifty = position("k7/8/1K6/8/8/8/8/R7 w - - 100 80");
+        assert_eq!(
+            rule_end(&fifty, 1).map(|end| end.termination),
+            Some(Termination::FiftyMoves)
+        );
+        let pos = position("k7/8/1K6/8/8/8/8/R7 w - - 0 80");
+        assert_eq!(rule_end(&pos, 2), None);
+        assert_eq!(
+            rule_end(&pos, 3).map(|end| end.termination),
+            Some(Termination::Repetition)
+        );
+    }
+
+    #[test]
+    fn counts_repetitions() {
+        let mut repetitions = Repetitions::default();
+        let mut pos = Chess::default();
+        assert_eq!(repetitions.add(&pos), 1);
+        for _ in 0..2 {
+            for uci in ["g1f3", "g8f6", "f3g1", "f6g8"] {
+                let m = Uci::from_ascii(uci.as_bytes())
+                    .unwrap()
+                    .to_move(&pos)
+                    .unwrap();
+                pos.play_unchecked(&m);
+                repetitions.add(&pos);
+            }
+        }
+        assert_eq!(repetitions.add(&pos), 4);
+    }
+
+    #[test]
+    fn adjudicates_by_evaluation() {
+        let rule = EvalAdjudication {
+            threshold: 500,
+            moves: 2,
+        };
+        let mut streak = EvalStreak::default();
+        assert_eq!(streak.add(Some(&cp(600)), &rule), None);
+        assert_eq!(streak.add(Some(&cp(700)), &rule), None);
+        assert_eq!(streak.add(Some(&cp(-900)), &rule), None);
+        assert_eq!(streak.add(Some(&cp(-900)), &rule), None);
+        assert_eq!(streak.add(None, &rule), None);
+        for _ in 0..3 {
+            assert_eq!(streak.add(Some(&cp(-800)), &rule), None);
+        }
+        assert_eq!(streak.add(Some(&cp(-800)), &rule), Some(Color::Black));
+
+        let mate = Score {
+            value: ScoreValue::Mate(3),
+            wdl: None,
+        };
+        let mut streak = EvalStreak::default();
+        for _ in 0..3 {
+            streak.add(Some(&mate), &rule);
+        }
+        assert_eq!(streak.add(Some(&mate), &rule), Some(Color::White));
+    }
+
+    #[test]
+    fn estimates_elo_differences() {
+        let (elo, margin) = elo_difference(60, 20, 20).unwrap();
+        assert!((elo - 147.2).abs() < 0.1, "{elo}");
+        assert!(margin > 50.0 && margin < 100.0, "{margin}");
+        let (even, _) = elo_difference(10, 80, 10).unwrap();
+        assert!(even.abs() < 1e-9);
+        assert_eq!(elo_difference(10, 0, 0), None);
+        assert_eq!(elo_difference(0, 0, 0), None);
+
+        let mut summary = MatchSummary::default();
+        summary.add(Some(Color::White), Color::White);
+        summary.add(Some(Color::White), Color::Black);
+        summary.add(None, Color::Black);
+        assert_eq!((summary.wins, summary.draws, summary.losses), (1, 1, 1));
+        assert_eq!(summary.score, 0.5);
+    }
+
+    #[test]
+    fn runs_out_of_time() {
+        let mut clock = Clock {
+            wtime: 1000,
+            btime: 1000,
+            winc: 100,
+            binc: 0,
+            movestogo: None,
+        };
+        assert_eq!(spend_time(&mut clock, Color::White, 400), Some(()));
+        assert_eq!(clock.wtime, 700);
+        assert_eq!(spend_time(&mut clock, Color::Black, 1001), None);
+    }
+
+    #[test]
+    fn writes_match_games() {
+        let start = position("k7/8/1K6/8/8/8/8/2Q5 b - - 3 40");
+        let moves: Vec<Uci> = ["a8b8", "c1c7", "b8a8", "c7c8"]
+            .iter()
+            .map(|m| Uci::from_ascii(m.as_bytes()).unwrap())
+            .collect();
+        let headers = [("White", "A \"B\"".to_string())];
+        assert_eq!(
+            game_pgn(&headers, &start, &moves, "1-0").unwrap(),
+            "[White \"A 'B'\"]\n\n40... Kb8 41. Qc7+ Ka8 42. Qc8# 1-0\n\n"
+        );
+        assert_eq!(
+            game_pgn(&[], &Chess::default(), &[], "1/2-1/2").unwrap(),
+            "\n1/2-1/2\n\n"
+        );
+    }
+}
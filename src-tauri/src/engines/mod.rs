@@ -0,0 +1,1907 @@
+mod bench;
+mod matches;
+
+use std::{
+    fs::{create_dir_all, remove_dir_all, remove_file, File},
+    io::{BufReader, BufWriter, Read, Write},
+    path::{Component, Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use flate2::read::GzDecoder;
+use futures_util::{future::join_all, StreamExt};
+use log::info;
+use reqwest::Client;
+use serde::Serialize;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use specta::Type;
+use tauri::{
+    api::path::{resolve_path, BaseDirectory},
+    AppHandle, Manager,
+};
+use tauri_specta::Event;
+
+use crate::{
+    batch::new_job_id,
+    chess::{probe_engine, quit_idle_sessions, uci_handshake, EngineConfig, HANDSHAKE_TIMEOUT},
+    db::forget_engine_evals,
+    error::Error,
+    fs::set_executable,
+    online::ensure_online,
+    AppState,
+};
+
+pub use self::bench::{benchmark_engine, cancel_benchmark, hardware_check};
+pub use self::matches::{cancel_match, run_match, MatchProgress};
+
+/// Minimum time between two progress events of a download
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Options stored with a new engine, the same ones the engines page adds
+const REQUIRED_SETTINGS: [&str; 3] = ["MultiPV", "Threads", "Hash"];
+
+/// Instructions a CPU may have, that builds of the catalog for x86-64 use
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CpuLevel {
+    Any,
+    /// SSE 4.1 and POPCNT
+    Popcnt,
+    Avx2,
+    /// AVX2 and BMI2
+    Bmi2,
+    /// AVX-512 F and BW
+    Avx512,
+}
+
+#[derive(Debug, Clone, Serialize, Type, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct CpuFeatures {
+    /// Architecture of the processor, `aarch64` on Apple Silicon even when the app
+    /// runs translated by Rosetta
+    pub arch: String,
+    pub popcnt: bool,
+    pub avx2: bool,
+    pub bmi2: bool,
+    pub avx512: bool,
+    pub apple_silicon: bool,
+}
+
+impl CpuFeatures {
+    fn supports(&self, level: CpuLevel) -> bool {
+        match level {
+            CpuLevel::Any => true,
+            CpuLevel::Popcnt => self.popcnt,
+            CpuLevel::Avx2 => self.avx2,
+            CpuLevel::Bmi2 => self.avx2 && self.bmi2,
+            CpuLevel::Avx512 => self.avx512,
+        }
+    }
+}
+
+/// Whether this x86-64 app runs on Apple Silicon through Rosetta
+#[cfg(all(target_os = "macos", target_arch = "x86_64"))]
+fn runs_translated() -> bool {
+    std::process::Command::new("sysctl")
+        .args(["-n", "sysctl.proc_translated"])
+        .output()
+        .is_ok_and(|output| output.stdout.starts_with(b"1"))
+}
+
+#[cfg(not(all(target_os = "macos", target_arch = "x86_64")))]
+fn runs_translated() -> bool {
+    false
+}
+
+pub fn cpu_features() -> CpuFeatures {
+    let mut features = CpuFeatures {
+        arch: std::env::consts::ARCH.to_string(),
+        ..Default::default()
+    };
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    {
+        features.popcnt = is_x86_feature_detected!("sse4.1") && is_x86_feature_detected!("popcnt");
+        features.avx2 = is_x86_feature_detected!("avx2");
+        features.bmi2 = is_x86_feature_detected!("bmi2");
+        features.avx512 =
+            is_x86_feature_detected!("avx512f") && is_x86_feature_detected!("avx512bw");
+    }
+    if cfg!(target_os = "macos") && (features.arch == "aarch64" || runs_translated()) {
+        features.arch = "aarch64".to_string();
+        features.apple_silicon = true;
+    }
+    features
+}
+
+/// Processor architecture and the instructions engine builds may need
+#[tauri::command]
+#[specta::specta]
+pub fn get_cpu_features() -> CpuFeatures {
+    cpu_features()
+}
+
+struct CatalogAsset {
+    /// Operating system and architecture, as in `std::env::consts`
+    os: &'static str,
+    arch: &'static str,
+    /// Instructions the build needs besides the ones of every CPU of `arch`
+    cpu: CpuLevel,
+    url: &'static str,
+    /// Path of the executable inside the archive
+    binary: &'static str,
+    /// Published SHA-256 of the archive, checked before anything is extracted. Fill
+    /// it from the release page when adding or updating an asset.
+    sha256: Option<&'static str>,
+}
+
+struct CatalogEngine {
+    id: &'static str,
+    name: &'static str,
+    version: &'static str,
+    elo: Option<u32>,
+    /// Builds for each system, the ones needing the most recent CPUs first
+    assets: &'static [CatalogAsset],
+}
+
+const CATALOG: &[CatalogEngine] = &[
+    CatalogEngine {
+        id: "stockfish",
+        name: "Stockfish",
+        version: "16.1",
+        elo: Some(3635),
+        assets: &[
+            CatalogAsset {
+                os: "windows",
+                arch: "x86_64",
+                cpu: CpuLevel::Avx512,
+                url: "https://github.com/official-stockfish/Stockfish/releases/download/sf_16.1/stockfish-windows-x86-64-avx512.zip",
+                binary: "stockfish/stockfish-windows-x86-64-avx512.exe",
+                sha256: None,
+            },
+            CatalogAsset {
+                os: "windows",
+                arch: "x86_64",
+                cpu: CpuLevel::Bmi2,
+                url: "https://github.com/official-stockfish/Stockfish/releases/download/sf_16.1/stockfish-windows-x86-64-bmi2.zip",
+                binary: "stockfish/stockfish-windows-x86-64-bmi2.exe",
+                sha256: None,
+            },
+            CatalogAsset {
+                os: "windows",
+                arch: "x86_64",
+                cpu: CpuLevel::Avx2,
+                url: "https://github.com/official-stockfish/Stockfish/releases/download/sf_16.1/stockfish-windows-x86-64-avx2.zip",
+                binary: "stockfish/stockfish-windows-x86-64-avx2.exe",
+                sha256: None,
+            },
+            CatalogAsset {
+                os: "windows",
+                arch: "x86_64",
+                cpu: CpuLevel::Popcnt,
+                url: "https://github.com/official-stockfish/Stockfish/releases/download/sf_16.1/stockfish-windows-x86-64-sse41-popcnt.zip",
+                binary: "stockfish/stockfish-windows-x86-64-sse41-popcnt.exe",
+                sha256: None,
+            },
+            CatalogAsset {
+                os: "windows",
+                arch: "x86_64",
+                cpu: CpuLevel::Any,
+                url: "https://github.com/official-stockfish/Stockfish/releases/download/sf_16.1/stockfish-windows-x86-64.zip",
+                binary: "stockfish/stockfish-windows-x86-64.exe",
+                sha256: None,
+            },
+            CatalogAsset {
+                os: "linux",
+                arch: "x86_64",
+                cpu: CpuLevel::Avx512,
+                url: "https://github.com/official-stockfish/Stockfish/releases/download/sf_16.1/stockfish-ubuntu-x86-64-avx512.tar",
+                binary: "stockfish/stockfish-ubuntu-x86-64-avx512",
+                sha256: None,
+            },
+            CatalogAsset {
+                os: "linux",
+                arch: "x86_64",
+                cpu: CpuLevel::Bmi2,
+                url: "https://github.com/official-stockfish/Stockfish/releases/download/sf_16.1/stockfish-ubuntu-x86-64-bmi2.tar",
+                binary: "stockfish/stockfish-ubuntu-x86-64-bmi2",
+                sha256: None,
+            },
+            CatalogAsset {
+                os: "linux",
+                arch: "x86_64",
+                cpu: CpuLevel::Avx2,
+                url: "https://github.com/official-stockfish/Stockfish/releases/download/sf_16.1/stockfish-ubuntu-x86-64-avx2.tar",
+                binary: "stockfish/stockfish-ubuntu-x86-64-avx2",
+                sha256: None,
+            },
+            CatalogAsset {
+                os: "linux",
+                arch: "x86_64",
+                cpu: CpuLevel::Popcnt,
+                url: "https://github.com/official-stockfish/Stockfish/releases/download/sf_16.1/stockfish-ubuntu-x86-64-sse41-popcnt.tar",
+                binary: "stockfish/stockfish-ubuntu-x86-64-sse41-popcnt",
+                sha256: None,
+            },
+            CatalogAsset {
+                os: "linux",
+                arch: "x86_64",
+                cpu: CpuLevel::Any,
+                url: "https://github.com/official-stockfish/Stockfish/releases/download/sf_16.1/stockfish-ubuntu-x86-64.tar",
+                binary: "stockfish/stockfish-ubuntu-x86-64",
+                sha256: None,
+            },
+            CatalogAsset {
+                os: "macos",
+                arch: "aarch64",
+                cpu: CpuLevel::Any,
+                url: "https://github.com/official-stockfish/Stockfish/releases/download/sf_16.1/stockfish-macos-m1-apple-silicon.tar",
+                binary: "stockfish/stockfish-macos-m1-apple-silicon",
+                sha256: None,
+            },
+            CatalogAsset {
+                os: "macos",
+                arch: "x86_64",
+                cpu: CpuLevel::Bmi2,
+                url: "https://github.com/official-stockfish/Stockfish/releases/download/sf_16.1/stockfish-macos-x86-64-bmi2.tar",
+                binary: "stockfish/stockfish-macos-x86-64-bmi2",
+                sha256: None,
+            },
+            CatalogAsset {
+                os: "macos",
+                arch: "x86_64",
+                cpu: CpuLevel::Avx2,
+                url: "https://github.com/official-stockfish/Stockfish/releases/download/sf_16.1/stockfish-macos-x86-64-avx2.tar",
+                binary: "stockfish/stockfish-macos-x86-64-avx2",
+                sha256: None,
+            },
+            CatalogAsset {
+                os: "macos",
+                arch: "x86_64",
+                cpu: CpuLevel::Popcnt,
+                url: "https://github.com/official-stockfish/Stockfish/releases/download/sf_16.1/stockfish-macos-x86-64-sse41-popcnt.tar",
+                binary: "stockfish/stockfish-macos-x86-64-sse41-popcnt",
+                sha256: None,
+            },
+            CatalogAsset {
+                os: "macos",
+                arch: "x86_64",
+                cpu: CpuLevel::Any,
+                url: "https://github.com/official-stockfish/Stockfish/releases/download/sf_16.1/stockfish-macos-x86-64.tar",
+                binary: "stockfish/stockfish-macos-x86-64",
+                sha256: None,
+            },
+        ],
+    },
+    CatalogEngine {
+        id: "lc0",
+        name: "Lc0",
+        version: "0.30.0",
+        elo: None,
+        assets: &[CatalogAsset {
+            os: "windows",
+            arch: "x86_64",
+            cpu: CpuLevel::Any,
+            url: "https://github.com/LeelaChessZero/lc0/releases/download/v0.30.0/lc0-v0.30.0-windows-cpu-dnnl.zip",
+            binary: "lc0.exe",
+            sha256: None,
+        }],
+    },
+];
+
+impl CatalogEngine {
+    /// The fastest build that runs on `cpu`
+    fn asset(&self, os: &str, cpu: &CpuFeatures) -> Option<&CatalogAsset> {
+        self.assets
+            .iter()
+            .find(|asset| asset.os == os && asset.arch == cpu.arch && cpu.supports(asset.cpu))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct DownloadableEngine {
+    /// Id to pass to `download_engine`
+    pub id: String,
+    pub name: String,
+    pub version: String,
+    pub elo: Option<u32>,
+    pub url: String,
+}
+
+fn downloadable_engines(os: &str, cpu: &CpuFeatures) -> Vec<DownloadableEngine> {
+    CATALOG
+        .iter()
+        .filter_map(|engine| {
+            let asset = engine.asset(os, cpu)?;
+            Some(DownloadableEngine {
+                id: engine.id.to_string(),
+                name: engine.name.to_string(),
+                version: engine.version.to_string(),
+                elo: engine.elo,
+                url: asset.url.to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Engines of the built-in catalog with a build for this system, the one its CPU runs
+/// fastest
+#[tauri::command]
+#[specta::specta]
+pub fn list_downloadable_engines() -> Vec<DownloadableEngine> {
+    downloadable_engines(std::env::consts::OS, &cpu_features())
+}
+
+#[derive(Clone, Type, Serialize, Event)]
+#[serde(rename_all = "camelCase")]
+pub struct EngineDownloadProgress {
+    pub id: String,
+    /// Url or catalog id the download was started with
+    pub engine: String,
+    pub downloaded: u64,
+    pub total: Option<u64>,
+    /// Average speed since the start, in bytes per second
+    pub speed: f64,
+    pub finished: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct DownloadedEngine {
+    pub id: String,
+    pub name: String,
+    pub version: String,
+    /// Executable of the engine, as registered in the engines store
+    pub path: PathBuf,
+    pub download_size: u64,
+    /// SHA-256 of the downloaded file, to compare with the one its publisher gives
+    pub sha256: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ArchiveKind {
+    Zip,
+    Tar,
+    TarGz,
+    SevenZ,
+    /// The executable itself
+    Binary,
+}
+
+/// Last segment of the path of `url`, without the query
+fn file_name(url: &str) -> &str {
+    let path = url.split(|c| c == '?' || c == '#').next().unwrap_or(url);
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+fn archive_kind(url: &str) -> ArchiveKind {
+    let name = file_name(url).to_lowercase();
+    if name.ends_with(".zip") {
+        ArchiveKind::Zip
+    } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+        ArchiveKind::TarGz
+    } else if name.ends_with(".tar") {
+        ArchiveKind::Tar
+    } else if name.ends_with(".7z") {
+        ArchiveKind::SevenZ
+    } else {
+        ArchiveKind::Binary
+    }
+}
+
+/// Folder an engine downloaded from `url` is installed to when none is given
+fn default_folder(url: &str) -> String {
+    let name = file_name(url);
+    let lowercase = name.to_ascii_lowercase();
+    let stem = [".tar.gz", ".tgz", ".tar", ".zip", ".7z", ".exe"]
+        .iter()
+        .find(|extension| lowercase.ends_with(*extension))
+        .map_or(name, |extension| &name[..name.len() - extension.len()]);
+    if stem.is_empty() {
+        "engine".to_string()
+    } else {
+        stem.to_string()
+    }
+}
+
+/// Url to download `source` from, a url or the id of a catalog engine
+fn resolve_source<'a>(
+    source: &'a str,
+    os: &str,
+    cpu: &CpuFeatures,
+) -> Result<
+    (
+        &'a str,
+        Option<(&'static CatalogEngine, &'static CatalogAsset)>,
+    ),
+    Error,
+> {
+    if source.contains("://") {
+        return Ok((source, None));
+    }
+    let engine = CATALOG
+        .iter()
+        .find(|engine| engine.id == source)
+        .ok_or_else(|| Error::UnknownEngine {
+            id: source.to_string(),
+        })?;
+    let asset = engine.asset(os, cpu).ok_or_else(|| Error::NoEngineBuild {
+        id: source.to_string(),
+    })?;
+    Ok((asset.url, Some((engine, asset))))
+}
+
+fn extract(kind: ArchiveKind, archive: &Path, dir: &Path, name: &str) -> Result<(), Error> {
+    create_dir_all(dir)?;
+    match kind {
+        ArchiveKind::Zip => {
+            zip::ZipArchive::new(BufReader::new(File::open(archive)?))?.extract(dir)?
+        }
+        ArchiveKind::Tar => tar::Archive::new(BufReader::new(File::open(archive)?)).unpack(dir)?,
+        ArchiveKind::TarGz => {
+            tar::Archive::new(GzDecoder::new(BufReader::new(File::open(archive)?))).unpack(dir)?
+        }
+        ArchiveKind::SevenZ => sevenz_rust::decompress_file(archive, dir)?,
+        ArchiveKind::Binary => std::fs::rename(archive, dir.join(name))?,
+    }
+    Ok(())
+}
+
+/// Whether a file is named like an executable: `.exe` files on Windows, and elsewhere
+/// files without an extension or whose extension is part of a version, like
+/// `lc0-v0.30.0`, which skips the networks and readmes archives ship with
+fn is_executable_name(path: &Path, windows: bool) -> bool {
+    match path.extension() {
+        Some(extension) if windows => extension.eq_ignore_ascii_case("exe"),
+        Some(extension) => !extension
+            .to_string_lossy()
+            .chars()
+            .all(|c| c.is_ascii_alphabetic()),
+        None => !windows,
+    }
+}
+
+/// Whether the permissions of a file let it run
+#[cfg(unix)]
+fn has_executable_bit(metadata: &std::fs::Metadata) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode() & 0o111 != 0
+}
+
+#[cfg(not(unix))]
+fn has_executable_bit(_metadata: &std::fs::Metadata) -> bool {
+    false
+}
+
+/// Keeps the executables among the files of an engine, given with whether their
+/// permissions let them run: the ones that can when some can, and otherwise, like for
+/// zip archives which don't keep permissions, the ones named like executables
+fn keep_executables(files: &mut Vec<(u64, PathBuf, bool)>, windows: bool) {
+    if !windows && files.iter().any(|(_, _, runs)| *runs) {
+        files.retain(|(_, _, runs)| *runs);
+    } else {
+        files.retain(|(_, path, _)| is_executable_name(path, windows));
+    }
+}
+
+/// The largest executable under `dir`, as `keep_executables` tells them
+fn find_binary(dir: &Path, windows: bool) -> Result<Option<PathBuf>, Error> {
+    let mut files = Vec::new();
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in std::fs::read_dir(dir)? {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            let path = entry.path();
+            if metadata.is_dir() {
+                dirs.push(path);
+            } else {
+                files.push((metadata.len(), path, has_executable_bit(&metadata)));
+            }
+        }
+    }
+    keep_executables(&mut files, windows);
+    Ok(files
+        .into_iter()
+        .max_by_key(|(size, _, _)| *size)
+        .map(|(_, path, _)| path))
+}
+
+/// Engines of the engines store the engines page reads, empty when there is none yet
+fn stored_engines(store: &Path) -> Result<Vec<serde_json::Value>, Error> {
+    match File::open(store) {
+        Ok(file) => {
+            Ok(serde_json::from_reader(BufReader::new(file)).map_err(std::io::Error::from)?)
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn save_engines(store: &Path, engines: &[serde_json::Value]) -> Result<(), Error> {
+    let file = File::create(store)?;
+    serde_json::to_writer(BufWriter::new(file), engines).map_err(std::io::Error::from)?;
+    Ok(())
+}
+
+/// Adds an engine to the engines store
+fn register_engine(store: &Path, engine: serde_json::Value) -> Result<(), Error> {
+    let mut engines = stored_engines(store)?;
+    engines.push(engine);
+    save_engines(store, &engines)
+}
+
+/// Removes the local engines at `path` from the engines store, returning how many
+/// profiles there were
+fn deregister_engine(store: &Path, path: &str) -> Result<usize, Error> {
+    let mut engines = stored_engines(store)?;
+    let count = engines.len();
+    engines.retain(|engine| engine["type"] != "local" || engine["path"] != path);
+    if engines.len() < count {
+        save_engines(store, &engines)?;
+    }
+    Ok(count - engines.len())
+}
+
+/// File or folder of the engines folder the executable at `path` was installed to, or
+/// None when it's somewhere else. Both paths must be canonical.
+fn installed_entry(engines: &Path, path: &Path) -> Option<PathBuf> {
+    match path.strip_prefix(engines).ok()?.components().next()? {
+        Component::Normal(entry) => Some(engines.join(entry)),
+        _ => None,
+    }
+}
+
+fn disk_size(path: &Path) -> u64 {
+    let Ok(metadata) = std::fs::metadata(path) else {
+        return 0;
+    };
+    if !metadata.is_dir() {
+        return metadata.len();
+    }
+    std::fs::read_dir(path).map_or(0, |entries| {
+        entries
+            .flatten()
+            .map(|entry| disk_size(&entry.path()))
+            .sum()
+    })
+}
+
+/// Default values of the `REQUIRED_SETTINGS` the engine has, in the format of the store
+fn default_settings(config: &EngineConfig) -> Vec<serde_json::Value> {
+    config
+        .options
+        .iter()
+        .filter_map(|option| {
+            let option = serde_json::to_value(option).ok()?;
+            let name = option["value"]["name"].as_str()?;
+            REQUIRED_SETTINGS
+                .contains(&name)
+                .then(|| json!({ "name": name, "value": option["value"]["default"] }))
+        })
+        .collect()
+}
+
+fn has_option(config: &EngineConfig, name: &str) -> bool {
+    config.options.iter().any(|option| {
+        serde_json::to_value(option)
+            .is_ok_and(|option| option["value"]["name"].as_str() == Some(name))
+    })
+}
+
+async fn download(
+    url: &str,
+    part: &Path,
+    cancelled: &AtomicBool,
+    mut progress: impl FnMut(u64, Option<u64>),
+) -> Result<(u64, String), Error> {
+    let res = Client::new().get(url).send().await?.error_for_status()?;
+    let total = res.content_length();
+    let mut file = BufWriter::new(File::create(part)?);
+    let mut hasher = Sha256::new();
+    let mut downloaded = 0;
+    let mut stream = res.bytes_stream();
+    while let Some(chunk) = stream.next().await {
+        if cancelled.load(Ordering::Relaxed) {
+            return Err(Error::DownloadCancelled);
+        }
+        let chunk = chunk?;
+        file.write_all(&chunk)?;
+        hasher.update(&chunk);
+        downloaded += chunk.len() as u64;
+        progress(downloaded, total);
+    }
+    file.flush()?;
+    Ok((downloaded, to_hex(&hasher.finalize())))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+pub fn file_sha256(path: &Path) -> Result<String, Error> {
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut BufReader::new(File::open(path)?), &mut hasher)?;
+    Ok(to_hex(&hasher.finalize()))
+}
+
+/// Fails with `ChecksumMismatch` when `sha256` isn't the published SHA-256 of `asset`
+fn check_sha256(asset: Option<&CatalogAsset>, sha256: &str) -> Result<(), Error> {
+    match asset.and_then(|asset| asset.sha256) {
+        Some(expected) if !expected.eq_ignore_ascii_case(sha256) => Err(Error::ChecksumMismatch {
+            expected: expected.to_string(),
+            actual: sha256.to_string(),
+        }),
+        _ => Ok(()),
+    }
+}
+
+/// Downloads `url` to `part` and extracts it to `dir`, checking the published SHA-256 of
+/// catalog assets before anything is extracted. Returns the size and SHA-256 of the
+/// download with the executable of the engine, which is made executable.
+async fn fetch(
+    url: &str,
+    asset: Option<&CatalogAsset>,
+    part: &Path,
+    dir: &Path,
+    cancelled: &AtomicBool,
+    mut progress: impl FnMut(u64, Option<u64>),
+) -> Result<(u64, String, PathBuf), Error> {
+    info!("Downloading engine from {url}");
+    let mut last_event: Option<Instant> = None;
+    let (size, sha256) = download(url, part, cancelled, |downloaded, total| {
+        if last_event.map_or(true, |last| last.elapsed() >= PROGRESS_INTERVAL) {
+            last_event = Some(Instant::now());
+            progress(downloaded, total);
+        }
+    })
+    .await?;
+    progress(size, Some(size));
+    check_sha256(asset, &sha256)?;
+    info!("Downloaded {url} with SHA-256 {sha256}");
+
+    let kind = archive_kind(url);
+    extract(kind, part, dir, file_name(url))?;
+    if kind != ArchiveKind::Binary {
+        remove_file(part)?;
+    }
+
+    let binary = match asset {
+        Some(asset) if dir.join(asset.binary).is_file() => Some(dir.join(asset.binary)),
+        _ => find_binary(dir, cfg!(windows))?,
+    };
+    let binary = binary.ok_or(Error::NoEngineBinary)?;
+    set_executable(&binary)?;
+    Ok((size, sha256, binary))
+}
+
+/// Emits the `EngineDownloadProgress` of the job `id`, started at `start`
+fn progress_emitter<'a>(
+    app: &'a AppHandle,
+    id: &'a str,
+    engine: &'a str,
+    start: Instant,
+) -> impl Fn(u64, Option<u64>, bool) + 'a {
+    move |downloaded, total, finished| {
+        let _ = EngineDownloadProgress {
+            id: id.to_string(),
+            engine: engine.to_string(),
+            downloaded,
+            total,
+            speed: downloaded as f64 / start.elapsed().as_secs_f64().max(0.001),
+            finished,
+        }
+        .emit_all(app);
+    }
+}
+
+fn engines_dir(app: &AppHandle) -> Result<PathBuf, Error> {
+    Ok(resolve_path(
+        &app.config(),
+        app.package_info(),
+        &app.env(),
+        "engines",
+        Some(BaseDirectory::AppData),
+    )?)
+}
+
+/// Downloads an engine, from a url or the catalog of `list_downloadable_engines`, and
+/// installs it to `destination`, a folder of the engines directory that defaults to
+/// the id of the engine or the name of the archive. Zip, tar, tar.gz and 7z archives
+/// are extracted, and the engine is added to the engines store with its default
+/// settings. Progress is emitted as `EngineDownloadProgress`, and `cancel_engine_download`
+/// stops the download. Catalog archives are checked against their published SHA-256.
+/// The partial files are deleted when it fails, is cancelled or the sum doesn't match.
+/// The engine is stored with the SHA-256 of its executable for `verify_engines`.
+#[tauri::command]
+#[specta::specta]
+pub async fn download_engine(
+    source: String,
+    destination: Option<String>,
+    state: tauri::State<'_, AppState>,
+    app: AppHandle,
+) -> Result<DownloadedEngine, Error> {
+    ensure_online(&state)?;
+    let (url, entry) = resolve_source(&source, std::env::consts::OS, &cpu_features())?;
+    let folder = destination.unwrap_or_else(|| match entry {
+        Some((engine, _)) => engine.id.to_string(),
+        None => default_folder(url),
+    });
+    let mut components = Path::new(&folder).components();
+    if !matches!(
+        (components.next(), components.next()),
+        (Some(Component::Normal(_)), None)
+    ) {
+        return Err(Error::ForbiddenPath);
+    }
+
+    let engines = engines_dir(&app)?;
+    let dir = engines.join(&folder);
+    if dir.exists() {
+        return Err(Error::EngineExists {
+            path: dir.display().to_string(),
+        });
+    }
+    let part = engines.join(format!("{folder}.part"));
+
+    let id = new_job_id(&state, "engine");
+    let cancelled = Arc::new(AtomicBool::new(false));
+    state.engine_downloads.insert(id.clone(), cancelled.clone());
+
+    let progress = progress_emitter(&app, &id, &source, Instant::now());
+
+    let result = async {
+        let (size, sha256, binary) = fetch(
+            url,
+            entry.map(|(_, asset)| asset),
+            &part,
+            &dir,
+            &cancelled,
+            |downloaded, total| progress(downloaded, total, false),
+        )
+        .await?;
+
+        let config = uci_handshake(binary.clone(), HANDSHAKE_TIMEOUT).await.ok();
+        let (name, version) = match (entry, &config) {
+            (Some((engine, _)), _) => (engine.name.to_string(), engine.version.to_string()),
+            (None, Some(config)) if !config.name.is_empty() => (config.name.clone(), String::new()),
+            (None, _) => (
+                binary
+                    .file_stem()
+                    .map(|stem| stem.to_string_lossy().into_owned())
+                    .unwrap_or_else(|| folder.clone()),
+                String::new(),
+            ),
+        };
+
+        register_engine(
+            &engines.join("engines.json"),
+            json!({
+                "type": "local",
+                "name": name,
+                "version": version,
+                "path": binary,
+                "elo": entry.and_then(|(engine, _)| engine.elo),
+                "catalogId": entry.map(|(engine, _)| engine.id),
+                "downloadLink": url,
+                "downloadSize": size,
+                "sha256": file_sha256(&binary)?,
+                "loaded": true,
+                "settings": config.as_ref().map(default_settings).unwrap_or_default(),
+            }),
+        )?;
+        info!("Installed {name} to {}", binary.display());
+
+        Ok::<_, Error>(DownloadedEngine {
+            id: id.clone(),
+            name,
+            version,
+            path: binary,
+            download_size: size,
+            sha256,
+        })
+    }
+    .await;
+
+    state.engine_downloads.remove(&id);
+    match result {
+        Ok(engine) => {
+            progress(engine.download_size, Some(engine.download_size), true);
+            Ok(engine)
+        }
+        Err(e) => {
+            let _ = remove_file(&part);
+            let _ = remove_dir_all(&dir);
+            progress(0, None, true);
+            Err(e)
+        }
+    }
+}
+
+/// Stops the engine download with the job id `id`, deleting what was downloaded
+#[tauri::command]
+#[specta::specta]
+pub fn cancel_engine_download(id: String, state: tauri::State<'_, AppState>) {
+    if let Some(cancelled) = state.engine_downloads.get(&id) {
+        cancelled.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Folders of archives that hold sources, documentation or tests rather than engines
+const SKIPPED_FOLDERS: [&str; 8] = [
+    "src",
+    "source",
+    "sources",
+    "doc",
+    "docs",
+    "documentation",
+    "tests",
+    "__MACOSX",
+];
+
+/// Executables under `dir` outside of `SKIPPED_FOLDERS`, as `keep_executables` tells
+/// them, relative to it and the largest first
+fn binary_candidates(dir: &Path, windows: bool) -> Result<Vec<PathBuf>, Error> {
+    let mut candidates = Vec::new();
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(current) = dirs.pop() {
+        for entry in std::fs::read_dir(current)? {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            let path = entry.path();
+            if metadata.is_dir() {
+                let skipped = SKIPPED_FOLDERS
+                    .iter()
+                    .any(|folder| entry.file_name().eq_ignore_ascii_case(folder));
+                if !skipped {
+                    dirs.push(path);
+                }
+            } else {
+                let relative = path.strip_prefix(dir).unwrap_or(&path).to_path_buf();
+                candidates.push((metadata.len(), relative, has_executable_bit(&metadata)));
+            }
+        }
+    }
+    keep_executables(&mut candidates, windows);
+    candidates.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+    Ok(candidates.into_iter().map(|(_, path, _)| path).collect())
+}
+
+#[derive(Debug, Clone, Serialize, Type)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum ArchiveInstall {
+    #[serde(rename_all = "camelCase")]
+    Installed {
+        name: String,
+        version: Option<String>,
+        path: PathBuf,
+    },
+    /// The archive has several executables, to pick one of as `binary`
+    Candidates { binaries: Vec<PathBuf> },
+}
+
+/// Installs the engine of a zip, tar, tar.gz or 7z archive at `path` to a new folder of
+/// the engines directory named after the archive. The executable is the one the archive
+/// has outside of source and documentation folders, or `binary`, a path inside the
+/// archive, when it has several, which are returned otherwise for the user to pick one.
+/// The engine must complete a UCI handshake, and is added to the engines store with the
+/// name it gives and its default settings. The folder is deleted when it fails or the
+/// executable is still to be picked.
+#[tauri::command]
+#[specta::specta]
+pub async fn install_engine_from_archive(
+    path: PathBuf,
+    binary: Option<PathBuf>,
+    app: AppHandle,
+) -> Result<ArchiveInstall, Error> {
+    let archive_name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let kind = archive_kind(&archive_name);
+    if kind == ArchiveKind::Binary {
+        return Err(Error::NotAnArchive {
+            path: path.display().to_string(),
+        });
+    }
+    let folder = default_folder(&archive_name);
+    let engines = engines_dir(&app)?;
+    let dir = engines.join(&folder);
+    if dir.exists() {
+        return Err(Error::EngineExists {
+            path: dir.display().to_string(),
+        });
+    }
+
+    let result = async {
+        extract(kind, &path, &dir, &archive_name)?;
+        let binaries = binary_candidates(&dir, cfg!(windows))?;
+        let binary = match (binary, binaries.as_slice()) {
+            (Some(binary), _) if binaries.contains(&binary) => binary,
+            (Some(_), _) | (None, []) => return Err(Error::NoEngineBinary),
+            (None, [binary]) => binary.clone(),
+            (None, _) => return Ok(ArchiveInstall::Candidates { binaries }),
+        };
+        let binary = dir.join(binary);
+        set_executable(&binary)?;
+        let config = probe_engine(binary.clone()).await?;
+
+        let name = match config.name.trim() {
+            "" => folder.clone(),
+            name => name.to_string(),
+        };
+        let (_, version) = split_version(&name);
+        register_engine(
+            &engines.join("engines.json"),
+            json!({
+                "type": "local",
+                "name": name,
+                "version": version.clone().unwrap_or_default(),
+                "path": binary,
+                "sha256": file_sha256(&binary)?,
+                "loaded": true,
+                "settings": default_settings(&config),
+            }),
+        )?;
+        info!("Installed {name} from {}", path.display());
+        Ok::<_, Error>(ArchiveInstall::Installed {
+            name,
+            version,
+            path: binary,
+        })
+    }
+    .await;
+
+    if !matches!(result, Ok(ArchiveInstall::Installed { .. })) {
+        let _ = remove_dir_all(&dir);
+    }
+    result
+}
+
+#[derive(Debug, Clone, Serialize, Type, PartialEq)]
+#[serde(tag = "type", content = "value", rename_all = "camelCase")]
+pub enum EngineIntegrity {
+    Unchanged,
+    /// The executable isn't the one that was installed, with its current SHA-256
+    Changed(String),
+    Missing,
+    /// Added without a SHA-256 to compare with
+    Unknown,
+}
+
+#[derive(Debug, Clone, Serialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct EngineVerification {
+    pub name: String,
+    pub path: PathBuf,
+    pub integrity: EngineIntegrity,
+}
+
+fn verify(engine: &serde_json::Value) -> Option<EngineVerification> {
+    if engine["type"] != "local" {
+        return None;
+    }
+    let path = PathBuf::from(engine["path"].as_str()?);
+    let integrity = match engine["sha256"].as_str() {
+        _ if !path.is_file() => EngineIntegrity::Missing,
+        None => EngineIntegrity::Unknown,
+        Some(expected) => match file_sha256(&path) {
+            Ok(actual) if expected.eq_ignore_ascii_case(&actual) => EngineIntegrity::Unchanged,
+            Ok(actual) => EngineIntegrity::Changed(actual),
+            Err(_) => EngineIntegrity::Missing,
+        },
+    };
+    Some(EngineVerification {
+        name: engine["name"].as_str().unwrap_or_default().to_string(),
+        path,
+        integrity,
+    })
+}
+
+/// Compares the executables of the engines store with the SHA-256 they were installed
+/// with, to find engines that were replaced or tampered with since
+#[tauri::command]
+#[specta::specta]
+pub async fn verify_engines(app: AppHandle) -> Result<Vec<EngineVerification>, Error> {
+    let engines = stored_engines(&engines_dir(&app)?.join("engines.json"))?;
+    Ok(engines.iter().filter_map(verify).collect())
+}
+
+#[derive(Debug, Clone, Serialize, Type, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct EngineRemoval {
+    /// Profiles removed from the engines store
+    pub profiles: usize,
+    /// File or folder deleted from the engines folder
+    pub deleted: Option<PathBuf>,
+    pub freed_bytes: u64,
+    /// Idle sessions of the engine that were closed
+    pub sessions: usize,
+    pub cached_evals: usize,
+}
+
+/// Removes the engine with the path `id` from the engines store, along with its
+/// cached handshake and evaluations. With `delete_files`, the folder it was installed
+/// to is deleted too, which is refused for engines outside of the engines folder.
+/// Fails while the engine is analyzing, so it can be stopped first.
+#[tauri::command]
+#[specta::specta]
+pub async fn remove_engine(
+    id: String,
+    delete_files: bool,
+    state: tauri::State<'_, AppState>,
+    app: AppHandle,
+) -> Result<EngineRemoval, Error> {
+    let engines = engines_dir(&app)?.canonicalize()?;
+    let path = PathBuf::from(&id).canonicalize().ok();
+    let entry = match &path {
+        Some(path) if delete_files => {
+            Some(installed_entry(&engines, path).ok_or(Error::ForbiddenPath)?)
+        }
+        _ => None,
+    };
+
+    let mut removal = EngineRemoval {
+        sessions: quit_idle_sessions(&state, &id).await?,
+        ..Default::default()
+    };
+    removal.profiles = deregister_engine(&engines.join("engines.json"), &id)?;
+    if let Some(path) = &path {
+        state
+            .engine_handshakes
+            .retain(|(cached, _), _| cached != path);
+    }
+    removal.cached_evals = forget_engine_evals(&app, &id)?;
+    if let Some(entry) = entry {
+        removal.freed_bytes = disk_size(&entry);
+        if entry.is_dir() {
+            remove_dir_all(&entry)?;
+        } else {
+            remove_file(&entry)?;
+        }
+        info!("Deleted {}", entry.display());
+        removal.deleted = Some(entry);
+    }
+    Ok(removal)
+}
+
+/// Part of the url of a release asset naming the repository, which stays the same
+/// from one version to the next
+fn release_repo(url: &str) -> Option<&str> {
+    url.split_once("/releases/download/").map(|(repo, _)| repo)
+}
+
+/// The catalog engine a local engine of the store was installed from, by the id it
+/// was stored with or, for engines downloaded before ids were stored, the repository
+/// it was downloaded from
+fn catalog_engine(engine: &serde_json::Value) -> Option<&'static CatalogEngine> {
+    if engine["type"] != "local" {
+        return None;
+    }
+    if let Some(id) = engine["catalogId"].as_str() {
+        return CATALOG.iter().find(|catalog| catalog.id == id);
+    }
+    let repo = release_repo(engine["downloadLink"].as_str()?)?;
+    CATALOG.iter().find(|catalog| {
+        catalog
+            .assets
+            .iter()
+            .any(|asset| release_repo(asset.url) == Some(repo))
+    })
+}
+
+/// Numbers of a version like `16.1` or `v0.30.0`, to compare versions with
+fn version_numbers(version: &str) -> Vec<u32> {
+    version
+        .trim()
+        .trim_start_matches(|c: char| !c.is_ascii_digit())
+        .split('.')
+        .map_while(|part| {
+            let digits: String = part.chars().take_while(char::is_ascii_digit).collect();
+            digits.parse().ok()
+        })
+        .collect()
+}
+
+fn is_newer(latest: &str, installed: &str) -> bool {
+    version_numbers(latest) > version_numbers(installed)
+}
+
+#[derive(Debug, Clone, Serialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct EngineUpdate {
+    /// Path of the engine, to pass to `update_engine`
+    pub id: String,
+    pub name: String,
+    pub installed: String,
+    pub latest: String,
+    pub url: String,
+}
+
+/// The update of a local engine of the store, given the version it reported in its
+/// last handshake
+fn engine_update(
+    engine: &serde_json::Value,
+    reported: Option<&str>,
+    os: &str,
+    cpu: &CpuFeatures,
+) -> Option<EngineUpdate> {
+    let catalog = catalog_engine(engine)?;
+    let installed = reported
+        .or_else(|| engine["version"].as_str())
+        .filter(|version| !version.is_empty())?;
+    if !is_newer(catalog.version, installed) {
+        return None;
+    }
+    Some(EngineUpdate {
+        id: engine["path"].as_str()?.to_string(),
+        name: engine["name"].as_str().unwrap_or(catalog.name).to_string(),
+        installed: installed.to_string(),
+        latest: catalog.version.to_string(),
+        url: catalog.asset(os, cpu)?.url.to_string(),
+    })
+}
+
+/// Version the engine at `path` gave in its cached handshake, while the executable
+/// wasn't modified since
+fn reported_version(state: &AppState, path: &Path) -> Option<String> {
+    let path = path.canonicalize().ok()?;
+    let modified = std::fs::metadata(&path).ok()?.modified().ok()?;
+    state
+        .engine_handshakes
+        .get(&(path, modified))?
+        .as_ref()?
+        .version
+        .clone()
+}
+
+/// Engines installed from the built-in catalog with a newer version in it, comparing
+/// with the version they reported in `id name` when it's known and the one they were
+/// installed with otherwise
+#[tauri::command]
+#[specta::specta]
+pub async fn check_engine_updates(
+    state: tauri::State<'_, AppState>,
+    app: AppHandle,
+) -> Result<Vec<EngineUpdate>, Error> {
+    let engines = stored_engines(&engines_dir(&app)?.join("engines.json"))?;
+    let cpu = cpu_features();
+    Ok(engines
+        .iter()
+        .filter_map(|engine| {
+            let reported = engine["path"]
+                .as_str()
+                .and_then(|path| reported_version(&state, Path::new(path)));
+            engine_update(engine, reported.as_deref(), std::env::consts::OS, &cpu)
+        })
+        .collect())
+}
+
+/// Puts the folder `staging` in place of `installed`, which is only deleted once the
+/// new one is there and restored if it can't be
+fn swap_folders(installed: &Path, staging: &Path) -> Result<(), Error> {
+    let mut old = installed.as_os_str().to_owned();
+    old.push(".old");
+    let old = PathBuf::from(old);
+    std::fs::rename(installed, &old)?;
+    if let Err(e) = std::fs::rename(staging, installed) {
+        let _ = std::fs::rename(&old, installed);
+        return Err(e.into());
+    }
+    let _ = remove_dir_all(&old);
+    Ok(())
+}
+
+/// Sets the fields of the new version of the local engines at `path`, keeping their
+/// name and settings. Benchmarks of the old version are dropped.
+fn store_update(store: &Path, path: &str, update: serde_json::Value) -> Result<(), Error> {
+    let mut engines = stored_engines(store)?;
+    for engine in &mut engines {
+        if engine["type"] == "local" && engine["path"] == path {
+            if let (Some(engine), Some(update)) = (engine.as_object_mut(), update.as_object()) {
+                engine.remove("benchmark");
+                engine.extend(update.clone());
+            }
+        }
+    }
+    save_engines(store, &engines)
+}
+
+/// Updates the catalog engine with the path `id` to the version of the catalog. The
+/// new build is downloaded and checked next to the installed one, which is only
+/// replaced once the new one completes a UCI handshake, and the profiles of the engine
+/// keep their settings. Progress is emitted as `EngineDownloadProgress` and
+/// `cancel_engine_download` stops it. Idle sessions of the engine are closed, and it
+/// fails while the engine is analyzing.
+#[tauri::command]
+#[specta::specta]
+pub async fn update_engine(
+    id: String,
+    state: tauri::State<'_, AppState>,
+    app: AppHandle,
+) -> Result<DownloadedEngine, Error> {
+    ensure_online(&state)?;
+    let engines = engines_dir(&app)?.canonicalize()?;
+    let store = engines.join("engines.json");
+    let catalog = stored_engines(&store)?
+        .iter()
+        .filter(|engine| engine["path"] == id.as_str())
+        .find_map(catalog_engine)
+        .ok_or_else(|| Error::UnknownEngine { id: id.clone() })?;
+    let asset = catalog
+        .asset(std::env::consts::OS, &cpu_features())
+        .ok_or_else(|| Error::NoEngineBuild {
+            id: catalog.id.to_string(),
+        })?;
+    let installed = PathBuf::from(&id)
+        .canonicalize()
+        .ok()
+        .and_then(|path| installed_entry(&engines, &path))
+        .filter(|entry| entry.is_dir())
+        .ok_or(Error::ForbiddenPath)?;
+    let mut staging = installed.as_os_str().to_owned();
+    staging.push(".update");
+    let staging = PathBuf::from(staging);
+    let part = staging.with_extension("update.part");
+
+    let job = new_job_id(&state, "engine");
+    let cancelled = Arc::new(AtomicBool::new(false));
+    state
+        .engine_downloads
+        .insert(job.clone(), cancelled.clone());
+    let progress = progress_emitter(&app, &job, catalog.id, Instant::now());
+
+    let result = async {
+        let _ = remove_dir_all(&staging);
+        let (size, sha256, binary) = fetch(
+            asset.url,
+            Some(asset),
+            &part,
+            &staging,
+            &cancelled,
+            |downloaded, total| progress(downloaded, total, false),
+        )
+        .await?;
+        probe_engine(binary.clone()).await?;
+
+        quit_idle_sessions(&state, &id).await?;
+        let binary = installed.join(binary.strip_prefix(&staging).unwrap_or(&binary));
+        swap_folders(&installed, &staging)?;
+        store_update(
+            &store,
+            &id,
+            json!({
+                "version": catalog.version,
+                "path": binary,
+                "elo": catalog.elo,
+                "catalogId": catalog.id,
+                "downloadLink": asset.url,
+                "downloadSize": size,
+                "sha256": file_sha256(&binary)?,
+            }),
+        )?;
+        state
+            .engine_handshakes
+            .retain(|(cached, _), _| !cached.starts_with(&installed));
+        forget_engine_evals(&app, &id)?;
+        info!(
+            "Updated {} to {} at {}",
+            catalog.name,
+            catalog.version,
+            binary.display()
+        );
+
+        Ok::<_, Error>(DownloadedEngine {
+            id: job.clone(),
+            name: catalog.name.to_string(),
+            version: catalog.version.to_string(),
+            path: binary,
+            download_size: size,
+            sha256,
+        })
+    }
+    .await;
+
+    state.engine_downloads.remove(&job);
+    let _ = remove_file(&part);
+    let _ = remove_dir_all(&staging);
+    match result {
+        Ok(engine) => {
+            progress(engine.download_size, Some(engine.download_size), true);
+            Ok(engine)
+        }
+        Err(e) => {
+            progress(0, None, true);
+            Err(e)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Type, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct EngineIdentity {
+    /// Name the engine gave in `id name`, without the version
+    pub name: String,
+    pub version: Option<String>,
+    pub multipv: bool,
+    pub chess960: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct InstalledEngine {
+    /// Name in the engines store, or of the file for engines only in the engines folder
+    pub name: String,
+    pub path: PathBuf,
+    pub size: u64,
+    pub os: Option<String>,
+    pub arch: Option<String>,
+    /// Added to the engines store, not just found in the engines folder
+    pub registered: bool,
+    /// None when the engine didn't complete the UCI handshake
+    pub uci: Option<EngineIdentity>,
+}
+
+/// Splits `Stockfish 16.1` or `Lc0 v0.30.0` into its name and version
+fn split_version(name: &str) -> (String, Option<String>) {
+    if let Some((base, last)) = name.trim().rsplit_once(' ') {
+        let version = last.strip_prefix('v').unwrap_or(last);
+        if version.starts_with(|c: char| c.is_ascii_digit()) {
+            return (base.trim_end().to_string(), Some(version.to_string()));
+        }
+    }
+    (name.trim().to_string(), None)
+}
+
+fn identity(config: &EngineConfig) -> EngineIdentity {
+    let (name, version) = split_version(&config.name);
+    EngineIdentity {
+        name,
+        version,
+        multipv: has_option(config, "MultiPV"),
+        chess960: has_option(config, "UCI_Chess960"),
+    }
+}
+
+/// Operating system and architecture an executable was built for, read from its
+/// ELF, PE or Mach-O header
+fn binary_platform(header: &[u8]) -> Option<(&'static str, Option<&'static str>)> {
+    let u16_at = |offset: usize| {
+        Some(u16::from_le_bytes(
+            header.get(offset..offset + 2)?.try_into().ok()?,
+        ))
+    };
+    let u32_at = |offset: usize| {
+        Some(u32::from_le_bytes(
+            header.get(offset..offset + 4)?.try_into().ok()?,
+        ))
+    };
+    match header.get(..4)? {
+        [0x7f, b'E', b'L', b'F'] => {
+            let arch = match u16_at(18)? {
+                0x03 => Some("x86"),
+                0x3e => Some("x86_64"),
+                0x28 => Some("arm"),
+                0xb7 => Some("aarch64"),
+                _ => None,
+            };
+            Some(("linux", arch))
+        }
+        [b'M', b'Z', ..] => {
+            let pe = u32_at(0x3c)? as usize;
+            if header.get(pe..pe + 4)? != b"PE\0\0" {
+                return None;
+            }
+            let arch = match u16_at(pe + 4)? {
+                0x014c => Some("x86"),
+                0x8664 => Some("x86_64"),
+                0xaa64 => Some("aarch64"),
+                _ => None,
+            };
+            Some(("windows", arch))
+        }
+        [0xcf, 0xfa, 0xed, 0xfe] => {
+            let arch = match u32_at(4)? {
+                0x0100_0007 => Some("x86_64"),
+                0x0100_000c => Some("aarch64"),
+                _ => None,
+            };
+            Some(("macos", arch))
+        }
+        // Universal binaries hold several architectures
+        [0xca, 0xfe, 0xba, 0xbe] => Some(("macos", None)),
+        _ => None,
+    }
+}
+
+fn read_platform(path: &Path) -> Option<(&'static str, Option<&'static str>)> {
+    let mut header = Vec::with_capacity(4096);
+    File::open(path)
+        .ok()?
+        .take(4096)
+        .read_to_end(&mut header)
+        .ok()?;
+    binary_platform(&header)
+}
+
+/// Executables of the engines folder, one per folder, and the engines of the store
+/// with the names they were stored with
+fn engine_paths(dir: &Path, windows: bool) -> Result<Vec<(PathBuf, Option<String>)>, Error> {
+    let mut paths = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            if let Some(binary) = find_binary(&path, windows)? {
+                paths.push((binary, None));
+            }
+        } else if is_executable_name(&path, windows) {
+            paths.push((path, None));
+        }
+    }
+    for engine in stored_engines(&dir.join("engines.json"))? {
+        if engine["type"] != "local" {
+            continue;
+        }
+        if let Some(path) = engine["path"].as_str() {
+            let name = engine["name"].as_str().map(str::to_string);
+            paths.push((PathBuf::from(path), name));
+        }
+    }
+
+    let mut engines: Vec<(PathBuf, Option<String>)> = Vec::new();
+    for (path, name) in paths {
+        let Ok(path) = path.canonicalize() else {
+            info!("Skipping missing engine {}", path.display());
+            continue;
+        };
+        match engines.iter_mut().find(|(known, _)| *known == path) {
+            Some((_, known)) => *known = name.or(known.take()),
+            None => engines.push((path, name)),
+        }
+    }
+    Ok(engines)
+}
+
+/// Engines of the engines folder and the ones added to the engines store from
+/// elsewhere, with what they say about themselves in a UCI handshake. Handshakes are
+/// cached until the executable is modified.
+#[tauri::command]
+#[specta::specta]
+pub async fn list_installed_engines(
+    state: tauri::State<'_, AppState>,
+    app: AppHandle,
+) -> Result<Vec<InstalledEngine>, Error> {
+    let paths = engine_paths(&engines_dir(&app)?, cfg!(windows))?;
+    let engines = paths.into_iter().map(|(path, name)| {
+        let state = &state;
+        async move {
+            let metadata = std::fs::metadata(&path)?;
+            let key = (path.clone(), metadata.modified()?);
+            let cached = state.engine_handshakes.get(&key).map(|uci| uci.clone());
+            let uci = match cached {
+                Some(uci) => uci,
+                None => {
+                    let uci = uci_handshake(path.clone(), HANDSHAKE_TIMEOUT)
+                        .await
+                        .ok()
+                        .map(|config| identity(&config));
+                    state.engine_handshakes.insert(key, uci.clone());
+                    uci
+                }
+            };
+            let platform = read_platform(&path);
+            Ok::<_, Error>(InstalledEngine {
+                registered: name.is_some(),
+                name: name.unwrap_or_else(|| {
+                    path.file_stem()
+                        .map(|stem| stem.to_string_lossy().into_owned())
+                        .unwrap_or_default()
+                }),
+                size: metadata.len(),
+                os: platform.map(|(os, _)| os.to_string()),
+                arch: platform.and_then(|(_, arch)| arch).map(str::to_string),
+                uci,
+                path,
+            })
+        }
+    });
+    join_all(engines).await.into_iter().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_downloads_not_matching_their_checksum() {
+        let sha256 = to_hex(&Sha256::digest(b"engine"));
+        let asset = CatalogAsset {
+            os: "linux",
+            arch: "x86_64",
+            cpu: CpuLevel::Any,
+            url: "https://example.org/engine.tar",
+            binary: "engine",
+            sha256: Some("ED9F6F25068608EFD412958DA4DFC19328CA3511251FA6D5F9C42BAF230E32F8"),
+        };
+        assert!(check_sha256(Some(&asset), &sha256).is_ok());
+        assert!(check_sha256(None, &sha256).is_ok());
+
+        let tampered = to_hex(&Sha256::digest(b"engine with a backdoor"));
+        assert!(matches!(
+            check_sha256(Some(&asset), &tampered),
+            Err(Error::ChecksumMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn picks_the_build_of_the_system() {
+        let old = CpuFeatures {
+            arch: "x86_64".to_string(),
+            ..Default::default()
+        };
+        let engines = downloadable_engines("linux", &old);
+        assert_eq!(engines.len(), 1);
+        assert_eq!(engines[0].id, "stockfish");
+        assert!(engines[0].url.ends_with("stockfish-ubuntu-x86-64.tar"));
+
+        let haswell = CpuFeatures {
+            popcnt: true,
+            avx2: true,
+            bmi2: true,
+            ..old.clone()
+        };
+        let engines = downloadable_engines("windows", &haswell);
+        assert_eq!(engines.len(), 2);
+        assert!(engines[0].url.ends_with("-x86-64-bmi2.zip"));
+        let zen = CpuFeatures {
+            bmi2: false,
+            ..haswell.clone()
+        };
+        assert!(downloadable_engines("linux", &zen)[0]
+            .url
+            .ends_with("-x86-64-avx2.tar"));
+        // Pentiums have BMI2 without AVX2, which the BMI2 builds need too
+        let pentium = CpuFeatures {
+            avx2: false,
+            ..haswell.clone()
+        };
+        assert!(downloadable_engines("linux", &pentium)[0]
+            .url
+            .ends_with("-x86-64-sse41-popcnt.tar"));
+
+        let apple = CpuFeatures {
+            arch: "aarch64".to_string(),
+            apple_silicon: true,
+            ..Default::default()
+        };
+        assert!(downloadable_engines("macos", &apple)[0]
+            .url
+            .ends_with("-m1-apple-silicon.tar"));
+
+        let riscv = CpuFeatures {
+            arch: "riscv64".to_string(),
+            ..Default::default()
+        };
+        assert!(downloadable_engines("linux", &riscv).is_empty());
+        assert!(matches!(
+            resolve_source("lc0", "linux", &haswell),
+            Err(Error::NoEngineBuild { .. })
+        ));
+        assert!(matches!(
+            resolve_source("komodo", "linux", &haswell),
+            Err(Error::UnknownEngine { .. })
+        ));
+        let url = "https://example.com/engine.zip";
+        assert!(matches!(resolve_source(url, "linux", &old), Ok((u, None)) if u == url));
+    }
+
+    #[test]
+    fn reads_archive_names() {
+        assert_eq!(archive_kind("https://a.org/sf.zip?raw=1"), ArchiveKind::Zip);
+        assert_eq!(archive_kind("https://a.org/sf.TAR.GZ"), ArchiveKind::TarGz);
+        assert_eq!(archive_kind("https://a.org/sf.tar"), ArchiveKind::Tar);
+        assert_eq!(archive_kind("https://a.org/sf.7z"), ArchiveKind::SevenZ);
+        assert_eq!(archive_kind("https://a.org/sf.exe"), ArchiveKind::Binary);
+        assert_eq!(
+            default_folder("https://a.org/x/berserk-13.tar.gz"),
+            "berserk-13"
+        );
+        assert_eq!(default_folder("https://a.org/x/"), "engine");
+    }
+
+    #[test]
+    fn finds_the_engine_and_registers_it() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("src")).unwrap();
+        std::fs::write(dir.path().join("README"), "engine").unwrap();
+        std::fs::write(dir.path().join("nn.nnue"), vec![0; 64]).unwrap();
+        std::fs::write(dir.path().join("src").join("engine"), vec![0; 32]).unwrap();
+        std::fs::write(dir.path().join("engine.exe"), vec![0; 16]).unwrap();
+        assert_eq!(
+            find_binary(dir.path(), false).unwrap(),
+            Some(dir.path().join("src").join("engine"))
+        );
+        assert_eq!(
+            find_binary(dir.path(), true).unwrap(),
+            Some(dir.path().join("engine.exe"))
+        );
+
+        let store = dir.path().join("engines.json");
+        register_engine(&store, json!({ "name": "First" })).unwrap();
+        register_engine(&store, json!({ "name": "Second" })).unwrap();
+        let engines: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&store).unwrap()).unwrap();
+        assert_eq!(engines, json!([{ "name": "First" }, { "name": "Second" }]));
+    }
+
+    #[test]
+    fn lists_the_executables_of_archives() {
+        let dir = tempfile::tempdir().unwrap();
+        for folder in ["src", "Docs", "bin"] {
+            std::fs::create_dir(dir.path().join(folder)).unwrap();
+        }
+        std::fs::write(dir.path().join("src").join("Makefile"), vec![0; 64]).unwrap();
+        std::fs::write(dir.path().join("Docs").join("LICENSE"), vec![0; 64]).unwrap();
+        std::fs::write(dir.path().join("bin").join("sf-avx2"), vec![0; 32]).unwrap();
+        std::fs::write(dir.path().join("sf"), vec![0; 16]).unwrap();
+        std::fs::write(dir.path().join("sf.exe"), vec![0; 16]).unwrap();
+        std::fs::write(dir.path().join("nn.nnue"), vec![0; 128]).unwrap();
+        assert_eq!(
+            binary_candidates(dir.path(), false).unwrap(),
+            [Path::new("bin").join("sf-avx2"), PathBuf::from("sf")]
+        );
+        assert_eq!(
+            binary_candidates(dir.path(), true).unwrap(),
+            [PathBuf::from("sf.exe")]
+        );
+        assert_eq!(archive_kind("Berserk-13.tar.gz"), ArchiveKind::TarGz);
+        assert_eq!(archive_kind("berserk"), ArchiveKind::Binary);
+    }
+
+    #[test]
+    fn tells_executables_by_their_permissions() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("README"), vec![0; 64]).unwrap();
+        std::fs::write(dir.path().join("lc0-v0.30.0"), vec![0; 32]).unwrap();
+        std::fs::write(dir.path().join("network.pb.gz"), vec![0; 128]).unwrap();
+        // Without permissions to go by, like after extracting a zip archive, by name
+        assert_eq!(
+            binary_candidates(dir.path(), false).unwrap(),
+            [PathBuf::from("README"), PathBuf::from("lc0-v0.30.0")]
+        );
+        if cfg!(unix) {
+            set_executable(&dir.path().join("lc0-v0.30.0")).unwrap();
+            assert_eq!(
+                binary_candidates(dir.path(), false).unwrap(),
+                [PathBuf::from("lc0-v0.30.0")]
+            );
+            assert_eq!(
+                find_binary(dir.path(), false).unwrap(),
+                Some(dir.path().join("lc0-v0.30.0"))
+            );
+        }
+    }
+
+    #[test]
+    fn finds_what_to_remove() {
+        let dir = tempfile::tempdir().unwrap();
+        let engines = dir.path().canonicalize().unwrap();
+        assert_eq!(
+            installed_entry(&engines, &engines.join("stockfish").join("sf")),
+            Some(engines.join("stockfish"))
+        );
+        assert_eq!(
+            installed_entry(&engines, &engines.join("berserk")),
+            Some(engines.join("berserk"))
+        );
+        assert_eq!(
+            installed_entry(&engines, Path::new("/usr/bin/stockfish")),
+            None
+        );
+        assert_eq!(installed_entry(&engines, &engines), None);
+
+        std::fs::create_dir(engines.join("stockfish")).unwrap();
+        std::fs::write(engines.join("stockfish").join("sf"), vec![0; 10]).unwrap();
+        std::fs::write(engines.join("stockfish").join("nn.nnue"), vec![0; 5]).unwrap();
+        assert_eq!(disk_size(&engines.join("stockfish")), 15);
+
+        let store = engines.join("engines.json");
+        for (kind, path) in [("local", "/a/sf"), ("local", "/b/sf"), ("local", "/a/sf")] {
+            register_engine(&store, json!({ "type": kind, "path": path })).unwrap();
+        }
+        register_engine(&store, json!({ "type": "lichess", "url": "/a/sf" })).unwrap();
+        assert_eq!(deregister_engine(&store, "/a/sf").unwrap(), 2);
+        assert_eq!(deregister_engine(&store, "/a/sf").unwrap(), 0);
+        assert_eq!(stored_engines(&store).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn verifies_stored_hashes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("engine");
+        std::fs::write(&path, "abc").unwrap();
+        let sha256 = file_sha256(&path).unwrap();
+        assert_eq!(
+            sha256,
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+
+        let engine = |sha256: Option<&str>| {
+            let integrity = verify(&json!({
+                "type": "local",
+                "name": "Engine",
+                "path": path,
+                "sha256": sha256,
+            }));
+            integrity.unwrap().integrity
+        };
+        assert_eq!(engine(Some(sha256.as_str())), EngineIntegrity::Unchanged);
+        assert_eq!(engine(None), EngineIntegrity::Unknown);
+        std::fs::write(&path, "abcd").unwrap();
+        assert_eq!(
+            engine(Some(sha256.to_uppercase().as_str())),
+            EngineIntegrity::Changed(file_sha256(&path).unwrap())
+        );
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(engine(Some(sha256.as_str())), EngineIntegrity::Missing);
+        assert!(verify(&json!({ "type": "chessdb", "name": "Cloud" })).is_none());
+    }
+
+    #[test]
+    fn finds_updates() {
+        assert!(is_newer("16.1", "16"));
+        assert!(is_newer("0.30.0", "v0.29.1"));
+        assert!(is_newer("17", "16.1"));
+        assert!(!is_newer("16.1", "16.1"));
+        assert!(!is_newer("16.1", "dev-20240301"));
+
+        let cpu = CpuFeatures {
+            arch: "x86_64".to_string(),
+            ..Default::default()
+        };
+        let old = json!({
+            "type": "local",
+            "name": "My Stockfish",
+            "version": "16",
+            "path": "/engines/stockfish/sf",
+            "downloadLink": "https://github.com/official-stockfish/Stockfish/releases/download/sf_16/stockfish-ubuntu-x86-64.tar",
+        });
+        let update = engine_update(&old, None, "linux", &cpu).unwrap();
+        assert_eq!(update.id, "/engines/stockfish/sf");
+        assert_eq!(update.name, "My Stockfish");
+        assert_eq!(update.installed, "16");
+        assert_eq!(update.latest, "16.1");
+        assert!(update.url.ends_with("stockfish-ubuntu-x86-64.tar"));
+        assert!(engine_update(&old, Some("16.1"), "linux", &cpu).is_none());
+
+        let by_id =
+            json!({ "type": "local", "version": "0.29.0", "catalogId": "lc0", "path": "/lc0" });
+        assert_eq!(catalog_engine(&by_id).map(|engine| engine.id), Some("lc0"));
+        assert!(engine_update(&by_id, None, "linux", &cpu).is_none());
+        assert!(engine_update(&by_id, None, "windows", &cpu).is_some());
+        let manual = json!({ "type": "local", "version": "1", "path": "/e", "downloadLink": "https://example.com/e.zip" });
+        assert!(catalog_engine(&manual).is_none());
+    }
+
+    #[test]
+    fn swaps_in_updates() {
+        let dir = tempfile::tempdir().unwrap();
+        let installed = dir.path().join("stockfish");
+        let staging = dir.path().join("stockfish.update");
+        std::fs::create_dir(&installed).unwrap();
+        std::fs::write(installed.join("sf"), "16").unwrap();
+        std::fs::create_dir(&staging).unwrap();
+        std::fs::write(staging.join("sf"), "16.1").unwrap();
+        swap_folders(&installed, &staging).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(installed.join("sf")).unwrap(),
+            "16.1"
+        );
+        assert!(!staging.exists());
+        assert!(!dir.path().join("stockfish.old").exists());
+
+        // Nothing to swap in, the installed engine stays
+        assert!(swap_folders(&installed, &staging).is_err());
+        assert_eq!(
+            std::fs::read_to_string(installed.join("sf")).unwrap(),
+            "16.1"
+        );
+
+        let store = dir.path().join("engines.json");
+        let settings = json!([{ "name": "Threads", "value": 8 }]);
+        save_engines(
+            &store,
+            &[json!({
+                "type": "local",
+                "name": "Stockfish",
+                "version": "16",
+                "path": "/sf",
+                "settings": settings,
+                "benchmark": { "nps": 1 },
+            })],
+        )
+        .unwrap();
+        store_update(&store, "/sf", json!({ "version": "16.1", "path": "/sf2" })).unwrap();
+        let engine = &stored_engines(&store).unwrap()[0];
+        assert_eq!(engine["version"], "16.1");
+        assert_eq!(engine["path"], "/sf2");
+        assert_eq!(engine["settings"], settings);
+        assert!(engine.get("benchmark").is_none());
+    }
+
+    #[test]
+    fn reads_versions_and_platforms() {
+        assert_eq!(
+            split_version("Stockfish 16.1"),
+            ("Stockfish".to_string(), Some("16.1".to_string()))
+        );
+        assert_eq!(
+            split_version("Lc0 v0.30.0"),
+            ("Lc0".to_string(), Some("0.30.0".to_string()))
+        );
+        assert_eq!(
+            split_version("Stockfish dev"),
+            ("Stockfish dev".to_string(), None)
+        );
+
+        let mut elf = vec![0x7f, b'E', b'L', b'F'];
+        elf.resize(20, 0);
+        elf[18] = 0x3e;
+        assert_eq!(binary_platform(&elf), Some(("linux", Some("x86_64"))));
+
+        let mut pe = vec![0; 0x48];
+        pe[..2].copy_from_slice(b"MZ");
+        pe[0x3c] = 0x40;
+        pe[0x40..0x44].copy_from_slice(b"PE\0\0");
+        pe[0x44..0x46].copy_from_slice(&0xaa64u16.to_le_bytes());
+        assert_eq!(binary_platform(&pe), Some(("windows", Some("aarch64"))));
+
+        let macho = [0xcf, 0xfa, 0xed, 0xfe, 0x0c, 0x00, 0x00, 0x01];
+        assert_eq!(binary_platform(&macho), Some(("macos", Some("aarch64"))));
+        assert_eq!(binary_platform(b"#!/bin/sh\n"), None);
+    }
+
+    #[test]
+    fn lists_folder_and_store_engines() {
+        let dir = tempfile::tempdir().unwrap();
+        let engines = dir.path().canonicalize().unwrap();
+        std::fs::create_dir(engines.join("stockfish")).unwrap();
+        std::fs::write(engines.join("stockfish").join("stockfish"), "sf").unwrap();
+        std::fs::write(engines.join("berserk"), "berserk").unwrap();
+        std::fs::write(engines.join("download.part"), "").unwrap();
+        let elsewhere = tempfile::tempdir().unwrap();
+        let komodo = elsewhere.path().canonicalize().unwrap().join("komodo");
+        std::fs::write(&komodo, "komodo").unwrap();
+
+        let store = engines.join("engines.json");
+        for (name, path) in [
+            ("Stockfish 16", engines.join("stockfish").join("stockfish")),
+            ("Komodo", komodo.clone()),
+            ("Deleted", engines.join("deleted")),
+        ] {
+            register_engine(
+                &store,
+                json!({ "type": "local", "name": name, "path": path }),
+            )
+            .unwrap();
+        }
+        register_engine(&store, json!({ "type": "lichess", "name": "Cloud" })).unwrap();
+
+        let mut paths = engine_paths(&engines, false).unwrap();
+        let mut expected = vec![
+            (engines.join("berserk"), None),
+            (
+                engines.join("stockfish").join("stockfish"),
+                Some("Stockfish 16".to_string()),
+            ),
+            (komodo, Some("Komodo".to_string())),
+        ];
+        paths.sort();
+        expected.sort();
+        assert_eq!(paths, expected);
+    }
+}
@@ -54,9 +54,27 @@ pub enum Error {
     #[error(transparent)]
     R2d2(#[from] diesel::r2d2::PoolError),
 
+    #[error(transparent)]
+    Connection(#[from] diesel::ConnectionError),
+
+    #[error(transparent)]
+    Csv(#[from] csv::Error),
+
     #[error(transparent)]
     SystemTime(#[from] std::time::SystemTimeError),
 
+    #[error(transparent)]
+    SevenZ(#[from] sevenz_rust::Error),
+
+    #[error(transparent)]
+    EngineProbe(#[from] crate::chess::EngineProbeError),
+
+    #[error(transparent)]
+    Tablebase(#[from] crate::tablebase::TablebaseError),
+
+    #[error(transparent)]
+    Keyring(#[from] keyring::Error),
+
     #[error("No stdin")]
     NoStdin,
 
@@ -72,9 +90,106 @@ pub enum Error {
     #[error("Search stopped")]
     SearchStopped,
 
+    #[error("The side to move has no time left")]
+    NoTimeLeft,
+
+    #[error("Invalid FEN: {reason}")]
+    InvalidFen { reason: String },
+
+    #[error("Invalid PGN: {reason}")]
+    InvalidPgn { reason: String },
+
+    #[error("Invalid pattern: {reason}")]
+    InvalidPattern { reason: String },
+
+    #[error("No batch analysis job found")]
+    NoBatchJob,
+
+    #[error("No engine session found")]
+    NoEngineSession,
+
+    #[error("The engine is analyzing in {tabs} tabs, stop it before removing it")]
+    EngineInUse { tabs: usize },
+
+    #[error("Analysis can only be extended with a larger limit of the same kind")]
+    InvalidExtension,
+
+    #[error("The path is outside of the allowed scope")]
+    ForbiddenPath,
+
     #[error("Missing reference database")]
     MissingReferenceDatabase,
 
+    #[error("The database has no position index, import its games again to create one")]
+    MissingPositionIndex,
+
+    #[error("Game {index} not found")]
+    GameNotFound { index: usize },
+
+    #[error("Import cancelled, the database was left as it was")]
+    ImportCancelled,
+
+    #[error("Merge cancelled, the merged database was deleted")]
+    MergeCancelled,
+
+    #[error("Book build cancelled, no book was written")]
+    BookCancelled,
+
+    #[error("There is already a database at {path}")]
+    DatabaseExists { path: String },
+
+    #[error("Unknown engine {id}")]
+    UnknownEngine { id: String },
+
+    #[error("There is no build of {id} for this system")]
+    NoEngineBuild { id: String },
+
+    #[error("There is already an engine at {path}")]
+    EngineExists { path: String },
+
+    #[error("No engine executable found in the download")]
+    NoEngineBinary,
+
+    #[error("{path} is not a zip, tar or 7z archive")]
+    NotAnArchive { path: String },
+
+    #[error("The download is corrupted or was tampered with, its SHA-256 is {actual} instead of {expected}")]
+    ChecksumMismatch { expected: String, actual: String },
+
+    #[error("Download cancelled, the downloaded files were deleted")]
+    DownloadCancelled,
+
+    #[error("Download cancelled, it continues where it stopped when started again")]
+    TablebaseDownloadCancelled,
+
+    #[error("There are no {pieces} piece tablebases to download, only 3 to 6 pieces")]
+    UnsupportedPieceCount { pieces: u8 },
+
+    #[error("Not enough disk space, {needed} bytes are needed and {available} are free")]
+    NotEnoughDiskSpace { needed: u64, available: u64 },
+
+    #[error("Another engine is being benchmarked")]
+    BenchmarkRunning,
+
+    #[error("Benchmark cancelled")]
+    BenchmarkCancelled,
+
+    #[error("The engine exited during the benchmark")]
+    BenchmarkFailed,
+
+    #[error("The engine has no bench command")]
+    NoBenchCommand,
+
+    #[error("Illegal move {mov} at index {index}: {reason}")]
+    IllegalMove {
+        index: usize,
+        mov: String,
+        reason: String,
+    },
+
+    #[error("Cannot merge games that start from different positions")]
+    DifferentStartPositions,
+
     #[error("No opening found")]
     NoOpeningFound,
 
@@ -84,6 +199,51 @@ pub enum Error {
     #[error("No puzzles")]
     NoPuzzles,
 
+    #[error("Invalid date {date}, expected YYYY-MM-DD")]
+    InvalidDate { date: String },
+
+    #[error("{service} kept limiting the rate of the requests")]
+    RateLimited { service: String },
+
+    #[error("There is no player named {username}")]
+    UnknownOnlineUser { username: String },
+
+    #[error("The study {id} is private, log in to lichess with an account that can see it")]
+    PrivateStudy { id: String },
+
+    #[error("There is no lichess study {id}")]
+    UnknownStudy { id: String },
+
+    #[error("No issue was found on the page of The Week in Chess")]
+    NoTwicIssue,
+
+    #[error("No lichess BOT token was set")]
+    NoBotToken,
+
+    #[error("{username} is not a lichess BOT account")]
+    NotBotAccount { username: String },
+
+    #[error("{service} stopped sending data")]
+    StreamStalled { service: String },
+
+    #[error("Lichess login failed: {reason}")]
+    LoginFailed { reason: String },
+
+    #[error("Network calls are disabled")]
+    OfflineMode,
+
+    #[error("Online data unavailable: {reason}")]
+    OnlineExplorerUnavailable { reason: String },
+
+    #[error("The explorer request was replaced by a newer one")]
+    ExplorerRequestSuperseded,
+
+    #[error("Unknown practice session {id}")]
+    UnknownPracticeSession { id: String },
+
+    #[error("It's not the turn of the player in the practice session")]
+    NotPracticeTurn,
+
     #[error("Players aren't the same. They have played against each other")]
     NotDistinctPlayers,
 }
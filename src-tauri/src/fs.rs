@@ -7,6 +7,7 @@ use std::{
 use log::info;
 use reqwest::{header::HeaderMap, Client};
 use specta::Type;
+use tauri::Manager;
 use tauri_specta::Event;
 
 #[cfg(unix)]
@@ -14,7 +15,7 @@ use std::os::unix::fs::PermissionsExt;
 
 use futures_util::StreamExt;
 
-use crate::error::Error;
+use crate::{error::Error, online::ensure_online, AppState};
 
 #[derive(Clone, Type, serde::Serialize, Event)]
 pub struct DownloadProgress {
@@ -33,6 +34,7 @@ pub async fn download_file(
     finalize: Option<bool>,
     total_size: Option<u32>,
 ) -> Result<(), Error> {
+    ensure_online(&app.state::<AppState>())?;
     let finalize = finalize.unwrap_or(true);
     info!("Downloading file from {}", url);
     let client = Client::new();
@@ -128,14 +130,19 @@ pub async fn unzip_file(path: &Path, file: Vec<u8>) -> Result<(), Error> {
 }
 
 #[tauri::command]
-pub async fn set_file_as_executable(_path: String) -> Result<(), Error> {
+pub async fn set_file_as_executable(path: String) -> Result<(), Error> {
+    set_executable(Path::new(&path))?;
+    Ok(())
+}
+
+/// Lets everyone run the file on unix, files are always executable on Windows
+pub fn set_executable(_path: &Path) -> std::io::Result<()> {
     #[cfg(unix)]
     {
-        let path = Path::new(&_path);
-        let metadata = std::fs::metadata(path)?;
+        let metadata = std::fs::metadata(_path)?;
         let mut permissions = metadata.permissions();
         permissions.set_mode(0o755);
-        std::fs::set_permissions(path, permissions)?;
+        std::fs::set_permissions(_path, permissions)?;
     }
     Ok(())
 }
@@ -0,0 +1,539 @@
+mod analysis;
+mod analysis_cache;
+mod batch;
+mod book;
+mod chess;
+mod db;
+mod engines;
+mod error;
+mod fide;
+mod fs;
+mod lexer;
+mod oauth;
+mod online;
+mod opening;
+mod pgn;
+mod practice;
+mod puzzle;
+mod report;
+mod tablebase;
+mod uci;
+
+use std::path::PathBuf;
+use std::sync::{
+    atomic::{AtomicBool, AtomicU64},
+    Arc, Mutex,
+};
+use std::time::SystemTime;
+use std::{fs::create_dir_all, path::Path};
+
+use batch::{BatchJobFinished, BatchQueue, BatchQueueChanged};
+use chess::{BestMoves, BestMovesPayload, CandidatesPayload, EngineProcess, ReportProgress};
+use dashmap::DashMap;
+use db::{DatabaseProgress, GameQuery, GamesCache, NormalizedGame, PositionStats};
+use derivative::Derivative;
+use fide::FidePlayer;
+use log::LevelFilter;
+use oauth::AuthState;
+use specta::ts::{BigIntExportBehavior, ExportConfig};
+use sysinfo::SystemExt;
+use tauri::{
+    api::path::{resolve_path, BaseDirectory},
+    Manager, Window,
+};
+use tauri::{CustomMenuItem, Menu, MenuItem, Submenu};
+use tauri_plugin_log::LogTarget;
+
+use crate::analysis::{annotate_game, export_mistakes};
+use crate::analysis_cache::{clear_analysis_cache, get_analysis_cache_stats, AnalysisCache};
+use crate::batch::{
+    cancel_batch, cancel_batch_job, get_batch_queue, move_batch_job, queue_analysis,
+    set_batch_concurrency, set_batch_paused, set_batch_threads, start_batch_worker,
+};
+use crate::book::{get_book_moves, list_books, pick_book_move};
+use crate::chess::{
+    analyze_candidates, analyze_game, analyze_threat, extend_analysis, get_engine_config,
+    get_engine_logs, get_hint, kill_engine, kill_engines, play_move, probe_engine, san_to_uci,
+    set_analysis_debounce, stop_all_engines, stop_engine, stop_engines, uci_to_san,
+};
+use crate::db::{
+    build_book, cancel_book_build, cancel_export, cancel_import, cancel_merge,
+    cancel_pattern_search, clear_eval_cache, clear_games, convert_pgn, create_indexes,
+    delete_database, delete_db_game, delete_empty_games, delete_games, delete_indexes,
+    export_games, export_to_pgn, find_duplicates, get_eval_cache_stats, get_opening_moves,
+    get_player, get_player_stats, get_players_game_info, get_tournaments, merge_databases,
+    optimize_db, reindex_positions, search_exact_position, search_pattern, search_players,
+    search_position, update_game, BookProgress, ExportProgress, ImportProgress, MergeProgress,
+    PatternProgress,
+};
+use crate::engines::{
+    benchmark_engine, cancel_benchmark, cancel_engine_download, cancel_match, check_engine_updates,
+    download_engine, get_cpu_features, hardware_check, install_engine_from_archive,
+    list_downloadable_engines, list_installed_engines, remove_engine, run_match, update_engine,
+    verify_engines, EngineDownloadProgress, EngineIdentity, MatchProgress,
+};
+use crate::fide::{download_fide_db, find_fide_player};
+use crate::fs::{append_to_file, set_file_as_executable, DownloadProgress};
+use crate::lexer::lex_pgn;
+use crate::oauth::{
+    authenticate, lichess_account, login_lichess, logout_lichess, set_lichess_bot_token,
+};
+use crate::online::{
+    cancel_online_import, get_chesscom_daily_games, get_lichess_explorer, import_chesscom_games,
+    import_lichess_games, import_lichess_study, set_offline_mode, start_bot, stop_bot,
+    stop_broadcast, update_reference_db, watch_broadcast, BotStatus, BroadcastUpdate,
+    OnlineImportProgress, TwicProgress,
+};
+use crate::pgn::{
+    cancel_pgn_split, clean_pgn, count_pgn_games, delete_game, export_game, import_pgn_text,
+    merge_pgns, parse_pgn, read_game, read_game_summaries, read_games, split_pgn, validate_pgn,
+    write_game, write_pgn, GameIndex, SplitProgress,
+};
+use crate::practice::{end_practice, practice_move, start_practice, PracticeSession};
+use crate::puzzle::{get_puzzle, get_puzzle_db_info, import_puzzles, mark_puzzle};
+use crate::report::export_report;
+use crate::tablebase::{
+    cancel_tablebase_download, download_tablebases, list_tablebase_files, probe_tablebase,
+    TablebaseCache, TablebaseDownloadProgress, TablebaseResult,
+};
+use crate::{
+    chess::get_best_moves,
+    db::{
+        delete_duplicated_games, edit_db_info, get_db_info, get_games, get_players, merge_players,
+    },
+    fs::{download_file, file_exists, get_file_metadata},
+    opening::{classify_opening, get_opening_from_fen, get_opening_from_name, search_opening_name},
+};
+use tokio::sync::{RwLock, Semaphore};
+
+#[cfg(any(windows, target_os = "macos"))]
+use window_shadows::set_shadow;
+
+// What the benchmarks measure
+pub use crate::db::{import_into_memory, ImportSummary};
+
+pub type GameData = (
+    i32,
+    i32,
+    i32,
+    Option<String>,
+    Option<String>,
+    Vec<u8>,
+    Option<String>,
+    i32,
+    i32,
+    i32,
+);
+
+#[derive(Derivative)]
+#[derivative(Default)]
+pub struct AppState {
+    connection_pool: DashMap<
+        String,
+        diesel::r2d2::Pool<diesel::r2d2::ConnectionManager<diesel::SqliteConnection>>,
+    >,
+    line_cache: DashMap<(GameQuery, PathBuf), (Vec<PositionStats>, Vec<NormalizedGame>)>,
+    db_cache: Mutex<GamesCache>,
+    #[derivative(Default(value = "Arc::new(Semaphore::new(2))"))]
+    new_request: Arc<Semaphore>,
+    pgn_offsets: DashMap<String, Vec<u64>>,
+    pgn_indexes: DashMap<PathBuf, GameIndex>,
+    pgn_splits: DashMap<String, Arc<AtomicBool>>,
+    pattern_searches: DashMap<String, Arc<AtomicBool>>,
+    imports: DashMap<String, Arc<AtomicBool>>,
+    exports: DashMap<String, Arc<AtomicBool>>,
+    book_builds: DashMap<String, Arc<AtomicBool>>,
+    merges: DashMap<PathBuf, Arc<AtomicBool>>,
+    engine_downloads: DashMap<String, Arc<AtomicBool>>,
+    matches: DashMap<String, Arc<AtomicBool>>,
+    online_imports: DashMap<String, Arc<AtomicBool>>,
+    broadcasts: DashMap<String, Arc<AtomicBool>>,
+    /// Stop flag of the lichess bot running, there is at most one
+    bot: Mutex<Option<Arc<AtomicBool>>>,
+    /// Network calls to online services are disabled
+    offline: AtomicBool,
+    /// Token of the lichess account logged in, None until it's read from the keychain
+    lichess_token: Mutex<Option<Option<String>>>,
+    /// Answers of the lichess cloud evaluation by FEN and MultiPV
+    cloud_evals: DashMap<(String, u16), Option<Vec<BestMoves>>>,
+    /// Latest lichess explorer request of each tab, the others being dropped
+    explorer_requests: DashMap<String, u64>,
+    practice_sessions: DashMap<String, PracticeSession>,
+    engine_handshakes: DashMap<(PathBuf, SystemTime), Option<EngineIdentity>>,
+    /// Cancellation flag of the engine benchmark running, there is at most one
+    benchmark: Mutex<Option<Arc<AtomicBool>>>,
+    tablebase: TablebaseCache,
+    /// Answers of the lichess tablebase by FEN
+    tablebase_responses: DashMap<String, TablebaseResult>,
+    tablebase_downloads: DashMap<String, Arc<AtomicBool>>,
+    fide_players: RwLock<Vec<FidePlayer>>,
+    engine_processes: DashMap<(String, String), Arc<tokio::sync::Mutex<EngineProcess>>>,
+    /// Deepest lines of the positions analyzed recently
+    analysis_cache: Mutex<AnalysisCache>,
+    #[derivative(Default(value = "AtomicU64::new(150)"))]
+    analysis_debounce: AtomicU64,
+    batch: BatchQueue,
+    auth: AuthState,
+}
+
+const REQUIRED_DIRS: &[(BaseDirectory, &str)] = &[
+    (BaseDirectory::AppData, "engines"),
+    (BaseDirectory::AppData, "db"),
+    (BaseDirectory::AppData, "presets"),
+    (BaseDirectory::AppData, "puzzles"),
+    (BaseDirectory::AppData, "books"),
+    (BaseDirectory::AppData, "tablebases"),
+    (BaseDirectory::AppData, "explorer"),
+    (BaseDirectory::AppData, "documents"),
+    (BaseDirectory::AppData, "batch/reports"),
+    (BaseDirectory::Document, "EnCroissant"),
+];
+
+const REQUIRED_FILES: &[(BaseDirectory, &str, &str)] =
+    &[(BaseDirectory::AppData, "engines/engines.json", "[]")];
+
+#[tauri::command]
+#[specta::specta]
+async fn close_splashscreen(window: Window) -> Result<(), String> {
+    window
+        .get_window("main")
+        .expect("no window labeled 'main' found")
+        .maximize()
+        .unwrap();
+    window
+        .get_window("main")
+        .expect("no window labeled 'main' found")
+        .show()
+        .unwrap();
+    Ok(())
+}
+
+#[cfg(debug_assertions)]
+const LOG_TARGETS: [LogTarget; 2] = [LogTarget::Stdout, LogTarget::Webview];
+
+#[cfg(not(debug_assertions))]
+const LOG_TARGETS: [LogTarget; 2] = [LogTarget::Stdout, LogTarget::LogDir];
+
+/// Starts the app. The app is a library with a small binary so the benchmarks can use
+/// the code they measure.
+pub fn run() {
+    let specta_builder = {
+        let specta_builder = tauri_specta::ts::builder()
+            .config(ExportConfig::new().bigint(BigIntExportBehavior::BigInt))
+            .commands(tauri_specta::collect_commands!(
+                close_splashscreen,
+                find_fide_player,
+                get_best_moves,
+                play_move,
+                analyze_threat,
+                get_hint,
+                san_to_uci,
+                uci_to_san,
+                analyze_candidates,
+                analyze_game,
+                annotate_game,
+                export_mistakes,
+                export_report,
+                parse_pgn,
+                write_pgn,
+                export_game,
+                read_game_summaries,
+                read_game,
+                import_pgn_text,
+                merge_pgns,
+                validate_pgn,
+                clean_pgn,
+                split_pgn,
+                cancel_pgn_split,
+                queue_analysis,
+                get_batch_queue,
+                set_batch_paused,
+                set_batch_concurrency,
+                set_batch_threads,
+                move_batch_job,
+                cancel_batch_job,
+                cancel_batch,
+                get_eval_cache_stats,
+                clear_eval_cache,
+                get_analysis_cache_stats,
+                clear_analysis_cache,
+                stop_engine,
+                extend_analysis,
+                set_analysis_debounce,
+                stop_all_engines,
+                kill_engine,
+                kill_engines,
+                get_engine_logs,
+                memory_size,
+                get_puzzle,
+                mark_puzzle,
+                import_puzzles,
+                set_menu_visisble,
+                is_menu_visisble,
+                get_opening_from_fen,
+                get_opening_from_name,
+                classify_opening,
+                get_players_game_info,
+                get_engine_config,
+                probe_engine,
+                file_exists,
+                get_file_metadata,
+                merge_players,
+                convert_pgn,
+                get_player,
+                cancel_import,
+                cancel_export,
+                reindex_positions,
+                cancel_pattern_search,
+                search_players,
+                get_player_stats,
+                find_duplicates,
+                delete_games,
+                optimize_db,
+                merge_databases,
+                cancel_merge,
+                list_downloadable_engines,
+                download_engine,
+                cancel_engine_download,
+                list_installed_engines,
+                verify_engines,
+                remove_engine,
+                get_cpu_features,
+                benchmark_engine,
+                cancel_benchmark,
+                check_engine_updates,
+                update_engine,
+                install_engine_from_archive,
+                hardware_check,
+                run_match,
+                cancel_match,
+                start_practice,
+                import_lichess_games,
+                import_lichess_study,
+                update_reference_db,
+                watch_broadcast,
+                stop_broadcast,
+                start_bot,
+                stop_bot,
+                set_lichess_bot_token,
+                import_chesscom_games,
+                get_chesscom_daily_games,
+                cancel_online_import,
+                set_offline_mode,
+                login_lichess,
+                lichess_account,
+                logout_lichess,
+                practice_move,
+                end_practice,
+                probe_tablebase,
+                list_tablebase_files,
+                download_tablebases,
+                cancel_tablebase_download,
+                get_book_moves,
+                list_books,
+                pick_book_move,
+                cancel_book_build,
+            ))
+            .events(tauri_specta::collect_events!(
+                BestMovesPayload,
+                CandidatesPayload,
+                DatabaseProgress,
+                DownloadProgress,
+                ReportProgress,
+                BatchQueueChanged,
+                BatchJobFinished,
+                SplitProgress,
+                PatternProgress,
+                ExportProgress,
+                MergeProgress,
+                ImportProgress,
+                EngineDownloadProgress,
+                BookProgress,
+                TablebaseDownloadProgress,
+                MatchProgress,
+                OnlineImportProgress,
+                TwicProgress,
+                BroadcastUpdate,
+                BotStatus
+            ));
+
+        #[cfg(debug_assertions)]
+        let specta_builder = specta_builder.path("../src/bindings/generated.ts");
+        specta_builder.into_plugin()
+    };
+
+    let menu = Menu::new()
+        .add_submenu(Submenu::new(
+            "File",
+            Menu::new()
+                .add_item(CustomMenuItem::new("new_tab".to_string(), "New tab"))
+                .add_item(CustomMenuItem::new("open_file".to_string(), "Open file"))
+                .add_native_item(MenuItem::Quit),
+        ))
+        .add_submenu(Submenu::new(
+            "Edit",
+            Menu::new()
+                .add_native_item(MenuItem::Undo)
+                .add_native_item(MenuItem::Redo)
+                .add_native_item(MenuItem::Separator)
+                .add_native_item(MenuItem::Cut)
+                .add_native_item(MenuItem::Copy)
+                .add_native_item(MenuItem::Paste)
+                .add_native_item(MenuItem::SelectAll),
+        ))
+        .add_submenu(Submenu::new(
+            "View",
+            Menu::new()
+                .add_item(CustomMenuItem::new("reload".to_string(), "Reload"))
+                .add_native_item(MenuItem::EnterFullScreen)
+                .add_native_item(MenuItem::Minimize),
+        ))
+        .add_submenu(Submenu::new(
+            "Help",
+            Menu::new()
+                .add_item(CustomMenuItem::new(
+                    "documentation".to_string(),
+                    "Documentation",
+                ))
+                .add_item(CustomMenuItem::new(
+                    "clear_saved_data".to_string(),
+                    "Clear saved data",
+                ))
+                .add_item(CustomMenuItem::new("open_logs".to_string(), "Open logs"))
+                .add_item(CustomMenuItem::new(
+                    "check_for_updates".to_string(),
+                    "Check for updates",
+                ))
+                .add_item(CustomMenuItem::new("about".to_string(), "About")),
+        ));
+
+    tauri::Builder::default()
+        .menu(menu)
+        .plugin(
+            tauri_plugin_log::Builder::default()
+                .targets(LOG_TARGETS)
+                .level(LevelFilter::Info)
+                .build(),
+        )
+        .plugin(specta_builder)
+        .setup(|app| {
+            log::info!("Setting up application");
+
+            log::info!("Checking for required directories");
+            for (dir, path) in REQUIRED_DIRS.iter() {
+                let path = resolve_path(
+                    &app.config(),
+                    app.package_info(),
+                    &app.env(),
+                    path,
+                    Some(*dir),
+                );
+                if let Ok(path) = path {
+                    if !Path::new(&path).exists() {
+                        log::info!("Creating directory {}", path.to_string_lossy());
+                        create_dir_all(&path).unwrap();
+                    }
+                };
+            }
+
+            log::info!("Checking for required files");
+            for (dir, path, contents) in REQUIRED_FILES.iter() {
+                let path = resolve_path(
+                    &app.config(),
+                    app.package_info(),
+                    &app.env(),
+                    path,
+                    Some(*dir),
+                )
+                .unwrap();
+                if !Path::new(&path).exists() {
+                    log::info!("Creating file {}", path.to_string_lossy());
+                    std::fs::write(&path, contents).unwrap();
+                }
+            }
+
+            start_batch_worker(app.handle());
+
+            #[cfg(any(windows, target_os = "macos"))]
+            set_shadow(&app.get_window("main").unwrap(), true).unwrap();
+
+            Ok(())
+        })
+        .manage(AppState::default())
+        .invoke_handler(tauri::generate_handler![
+            download_file,
+            get_games,
+            get_players,
+            get_tournaments,
+            get_db_info,
+            get_puzzle_db_info,
+            edit_db_info,
+            delete_duplicated_games,
+            authenticate,
+            delete_database,
+            search_position,
+            get_lichess_explorer,
+            search_exact_position,
+            search_pattern,
+            export_games,
+            build_book,
+            update_game,
+            get_opening_moves,
+            is_bmi2_compatible,
+            clear_games,
+            set_file_as_executable,
+            count_pgn_games,
+            read_games,
+            append_to_file,
+            delete_game,
+            write_game,
+            delete_indexes,
+            create_indexes,
+            lex_pgn,
+            download_fide_db,
+            search_opening_name,
+            delete_db_game,
+            delete_empty_games,
+            export_to_pgn
+        ])
+        .build(tauri::generate_context!())
+        .expect("error while running tauri application")
+        .run(|app, event| {
+            if let tauri::RunEvent::Exit = event {
+                log::info!("Stopping engines before exit");
+                let state = app.state::<AppState>();
+                tauri::async_runtime::block_on(stop_engines(&state, true));
+            }
+        });
+}
+
+#[tauri::command]
+fn is_bmi2_compatible() -> bool {
+    engines::cpu_features().bmi2
+}
+
+#[tauri::command]
+#[specta::specta]
+async fn is_menu_visisble(window: tauri::Window) -> bool {
+    window.menu_handle().is_visible().unwrap()
+}
+
+#[tauri::command]
+#[specta::specta]
+async fn set_menu_visisble(state: bool, window: tauri::Window) {
+    let menu = window.menu_handle();
+    if state {
+        menu.show().unwrap();
+        window.set_decorations(true).unwrap();
+    } else {
+        menu.hide().unwrap();
+        window.set_decorations(false).unwrap();
+    }
+}
+
+#[tauri::command]
+#[specta::specta]
+fn memory_size() -> u32 {
+    let total_bytes = sysinfo::System::new_all().total_memory();
+    (total_bytes / 1024 / 1024) as u32
+}
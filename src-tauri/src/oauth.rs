@@ -1,17 +1,40 @@
 use axum::{extract::Query, response::IntoResponse, routing::get, Extension, Router};
-use log::info;
+use log::{error, info};
 use oauth2::{
     basic::BasicClient, reqwest::async_http_client, AuthUrl, AuthorizationCode, ClientId,
     CsrfToken, PkceCodeChallenge, PkceCodeVerifier, RedirectUrl, Scope, TokenResponse, TokenUrl,
 };
-use serde::Deserialize;
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use specta::Type;
 use std::{
     net::{SocketAddr, TcpListener},
-    sync::Arc,
+    sync::{Arc, Mutex},
+    time::Duration,
 };
 use tauri::Manager;
+use tokio::sync::oneshot;
 
-use crate::{error::Error, AppState};
+use crate::{
+    error::Error,
+    online::{ensure_online, is_offline},
+    AppState,
+};
+
+const LICHESS_ACCOUNT: &str = "https://lichess.org/api/account";
+const LICHESS_TOKEN: &str = "https://lichess.org/api/token";
+
+/// Permissions asked for at login: the preferences, and the private studies
+const LICHESS_SCOPES: [&str; 2] = ["preference:read", "study:read"];
+
+/// Entry of the OS keychain the lichess token is kept in
+const KEYCHAIN_SERVICE: &str = "org.encroissant.app";
+const KEYCHAIN_USER: &str = "lichess";
+/// Entry of the token of the BOT account the engines play with, apart from the login
+const KEYCHAIN_BOT_USER: &str = "lichess-bot";
+
+/// Time the user has to authorize the app in the browser
+const LOGIN_TIMEOUT: Duration = Duration::from_secs(5 * 60);
 
 fn create_client(redirect_url: RedirectUrl) -> BasicClient {
     let client_id = ClientId::new("org.encroissant.app".to_string());
@@ -117,3 +140,206 @@ async fn run_server(handle: tauri::AppHandle) -> Result<(), axum::Error> {
 
     Ok(())
 }
+
+#[derive(Serialize, Deserialize, Debug, Clone, Type)]
+pub struct LichessAccount {
+    pub id: String,
+    pub username: String,
+}
+
+fn keychain_entry(user: &str) -> Result<keyring::Entry, Error> {
+    Ok(keyring::Entry::new(KEYCHAIN_SERVICE, user)?)
+}
+
+fn stored_token(user: &str) -> Result<Option<String>, Error> {
+    match keychain_entry(user)?.get_password() {
+        Ok(token) => Ok(Some(token)),
+        Err(keyring::Error::NoEntry) => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Token of the lichess account logged in, read from the keychain once
+pub fn lichess_token(state: &AppState) -> Option<String> {
+    let mut token = state.lichess_token.lock().unwrap();
+    token
+        .get_or_insert_with(|| {
+            stored_token(KEYCHAIN_USER).unwrap_or_else(|e| {
+                error!("Failed to read the lichess token: {}", e);
+                None
+            })
+        })
+        .clone()
+}
+
+fn forget_token(state: &AppState) -> Result<(), Error> {
+    *state.lichess_token.lock().unwrap() = Some(None);
+    match keychain_entry(KEYCHAIN_USER)?.delete_password() {
+        Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Token of the lichess BOT account, None when none was set
+pub fn lichess_bot_token() -> Result<Option<String>, Error> {
+    stored_token(KEYCHAIN_BOT_USER)
+}
+
+/// The account of `token`, None when lichess doesn't accept it anymore
+async fn fetch_account(token: &str) -> Result<Option<LichessAccount>, Error> {
+    let response = reqwest::Client::new()
+        .get(LICHESS_ACCOUNT)
+        .bearer_auth(token)
+        .send()
+        .await?;
+    if response.status() == StatusCode::UNAUTHORIZED {
+        return Ok(None);
+    }
+    Ok(Some(response.error_for_status()?.json().await?))
+}
+
+#[derive(Deserialize)]
+struct LoginCallback {
+    code: Option<String>,
+    state: Option<String>,
+    error: Option<String>,
+}
+
+type CallbackSender = Arc<Mutex<Option<oneshot::Sender<LoginCallback>>>>;
+
+async fn login_callback(
+    sender: Extension<CallbackSender>,
+    query: Query<LoginCallback>,
+) -> &'static str {
+    if let Some(sender) = sender.lock().unwrap().take() {
+        let _ = sender.send(query.0);
+    }
+    "En Croissant received the answer of lichess, this window can be closed"
+}
+
+/// Logs in to lichess with the PKCE flow: the authorization page is opened in the
+/// browser, which redirects to a listener on the loopback interface with the code
+/// exchanged for the token. The token is kept in the keychain of the system and sent
+/// with every lichess request from then on.
+#[tauri::command]
+#[specta::specta]
+pub async fn login_lichess(
+    state: tauri::State<'_, AppState>,
+    app: tauri::AppHandle,
+) -> Result<LichessAccount, Error> {
+    ensure_online(&state)?;
+    let failed = |reason: String| Error::LoginFailed { reason };
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let redirect_url = format!("http://{}/callback", listener.local_addr()?);
+    let client = create_client(RedirectUrl::new(redirect_url).unwrap());
+    let (challenge, verifier) = PkceCodeChallenge::new_random_sha256();
+    let mut request = client.authorize_url(CsrfToken::new_random);
+    for scope in LICHESS_SCOPES {
+        request = request.add_scope(Scope::new(scope.to_string()));
+    }
+    let (auth_url, csrf_token) = request.set_pkce_challenge(challenge).url();
+
+    let (sender, receiver) = oneshot::channel();
+    let (stop, stopped) = oneshot::channel::<()>();
+    let sender: CallbackSender = Arc::new(Mutex::new(Some(sender)));
+    let router = Router::new()
+        .route("/callback", get(login_callback))
+        .layer(Extension(sender));
+    let server = axum::Server::from_tcp(listener)
+        .map_err(|e| failed(e.to_string()))?
+        .serve(router.into_make_service())
+        .with_graceful_shutdown(async move {
+            let _ = stopped.await;
+        });
+    tauri::async_runtime::spawn(async move {
+        if let Err(e) = server.await {
+            error!("Login listener failed: {}", e);
+        }
+    });
+
+    info!("Logging in to lichess");
+    tauri::api::shell::open(&app.shell_scope(), auth_url.to_string(), None)?;
+    let callback = tokio::time::timeout(LOGIN_TIMEOUT, receiver).await;
+    let _ = stop.send(());
+    let callback = match callback {
+        Ok(Ok(callback)) => callback,
+        _ => return Err(failed("no answer from the browser".to_string())),
+    };
+    if let Some(error) = callback.error {
+        return Err(failed(error));
+    }
+    if callback.state.as_deref() != Some(csrf_token.secret().as_str()) {
+        return Err(failed("the answer doesn't match the request".to_string()));
+    }
+    let code = callback
+        .code
+        .ok_or_else(|| failed("no authorization code".to_string()))?;
+    let token = client
+        .exchange_code(AuthorizationCode::new(code))
+        .set_pkce_verifier(verifier)
+        .request_async(async_http_client)
+        .await
+        .map_err(|e| failed(e.to_string()))?;
+    let token = token.access_token().secret().clone();
+
+    keychain_entry(KEYCHAIN_USER)?.set_password(&token)?;
+    *state.lichess_token.lock().unwrap() = Some(Some(token.clone()));
+    fetch_account(&token)
+        .await?
+        .ok_or_else(|| failed("lichess refused the token".to_string()))
+}
+
+/// The lichess account logged in, None when there is none or its token was revoked
+#[tauri::command]
+#[specta::specta]
+pub async fn lichess_account(
+    state: tauri::State<'_, AppState>,
+) -> Result<Option<LichessAccount>, Error> {
+    let Some(token) = lichess_token(&state) else {
+        return Ok(None);
+    };
+    ensure_online(&state)?;
+    let account = fetch_account(&token).await?;
+    if account.is_none() {
+        forget_token(&state)?;
+    }
+    Ok(account)
+}
+
+/// Revokes the lichess token and removes it from the keychain. The token is removed
+/// even when lichess can't be reached to revoke it.
+#[tauri::command]
+#[specta::specta]
+pub async fn logout_lichess(state: tauri::State<'_, AppState>) -> Result<(), Error> {
+    let Some(token) = lichess_token(&state) else {
+        return Ok(());
+    };
+    if is_offline(&state) {
+        return forget_token(&state);
+    }
+    let revoked = reqwest::Client::new()
+        .delete(LICHESS_TOKEN)
+        .bearer_auth(&token)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status());
+    if let Err(e) = revoked {
+        error!("Failed to revoke the lichess token: {}", e);
+    }
+    forget_token(&state)
+}
+
+/// Keeps the API token of a lichess BOT account in the keychain for `start_bot`, or
+/// removes it when `token` is None
+#[tauri::command]
+#[specta::specta]
+pub fn set_lichess_bot_token(token: Option<String>) -> Result<(), Error> {
+    let entry = keychain_entry(KEYCHAIN_BOT_USER)?;
+    match token {
+        Some(token) => Ok(entry.set_password(&token)?),
+        None => match entry.delete_password() {
+            Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+            Err(e) => Err(e.into()),
+        },
+    }
+}
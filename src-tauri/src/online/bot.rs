@@ -0,0 +1,735 @@
+use std::{
+    collections::HashSet,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use futures_util::{Stream, StreamExt};
+use log::{error, info};
+use reqwest::{Client, Response};
+use serde::{Deserialize, Serialize};
+use shakmaty::{Color, Position};
+use specta::Type;
+use tauri::{AppHandle, Manager};
+use tauri_specta::Event;
+use vampirc_uci::uci::{Score, ScoreValue};
+
+use super::{client, ensure_online, wait, MAX_RETRIES};
+use crate::{
+    chess::{
+        kill_session, parse_position, search_move, validate_fen, with_chess960, Clock,
+        EngineOption, EngineOptions, GoMode,
+    },
+    error::Error,
+    oauth::lichess_bot_token,
+    AppState,
+};
+
+const LICHESS_API: &str = "https://lichess.org/api";
+
+const STARTING_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+/// Lichess sends a blank line every few seconds, so a stream silent for longer was cut
+const STREAM_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Wait before reconnecting to the event stream, doubling up to `RECONNECT_MAX` while it
+/// keeps failing
+const RECONNECT_WAIT: Duration = Duration::from_secs(5);
+const RECONNECT_MAX: Duration = Duration::from_secs(120);
+
+/// Centipawns a mate counts as for the resign rule
+const MATE_CP: i32 = 100_000;
+
+/// Challenges the bot accepts
+#[derive(Deserialize, Debug, Clone, Default, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct BotChallengeRules {
+    /// Speeds like `blitz` or `rapid`, all of them when empty
+    #[serde(default)]
+    pub speeds: Vec<String>,
+    /// Bounds of the initial time in seconds
+    pub min_initial: Option<u32>,
+    pub max_initial: Option<u32>,
+    /// Longest increment in seconds
+    pub max_increment: Option<u32>,
+    #[serde(default)]
+    pub rated: bool,
+    #[serde(default)]
+    pub casual: bool,
+    /// Whether Chess960 challenges are accepted along with standard ones
+    #[serde(default)]
+    pub chess960: bool,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct BotResignRule {
+    /// Centipawns the engine has to see itself losing by
+    pub threshold: i32,
+    /// Moves in a row the evaluation has to stay under the threshold
+    pub moves: u32,
+}
+
+#[derive(Deserialize, Debug, Clone, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct BotConfig {
+    /// Path of the engine, its id in the engines store
+    pub engine: String,
+    pub options: Vec<EngineOption>,
+    pub challenges: BotChallengeRules,
+    /// Games played at once, the challenges past it being declined
+    pub max_games: usize,
+    /// Seconds the opponent has to play their first move before the game is aborted
+    pub abort_after: Option<u32>,
+    pub resign: Option<BotResignRule>,
+}
+
+#[derive(Clone, Type, Serialize, Event)]
+#[serde(rename_all = "camelCase")]
+pub struct BotStatus {
+    /// Whether the event stream of lichess is connected
+    pub connected: bool,
+    /// Ids of the games being played
+    pub games: Vec<String>,
+    /// What just happened
+    pub message: String,
+    /// The bot was stopped and its last game is over
+    pub finished: bool,
+}
+
+#[derive(Deserialize, Debug)]
+struct BotAccount {
+    id: String,
+    username: String,
+    title: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Variant {
+    key: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct TimeControl {
+    /// Seconds, only clock time controls have it
+    limit: Option<u32>,
+    increment: Option<u32>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Challenger {
+    #[serde(default)]
+    name: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Challenge {
+    id: String,
+    rated: bool,
+    variant: Variant,
+    speed: String,
+    time_control: TimeControl,
+    challenger: Option<Challenger>,
+    /// `out` for the challenges the bot sent
+    direction: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct GameId {
+    game_id: String,
+}
+
+/// Events of the account, from `/api/stream/event`
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "camelCase")]
+enum AccountEvent {
+    Challenge {
+        challenge: Challenge,
+    },
+    GameStart {
+        game: GameId,
+    },
+    GameFinish {
+        game: GameId,
+    },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Deserialize, Debug)]
+struct GamePlayer {
+    /// None for the AI of lichess
+    id: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct GameState {
+    /// UCI moves from the start
+    moves: String,
+    wtime: u32,
+    btime: u32,
+    winc: u32,
+    binc: u32,
+    status: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct GameFull {
+    white: GamePlayer,
+    /// `startpos` or a FEN
+    initial_fen: String,
+    variant: Variant,
+    state: GameState,
+}
+
+/// Events of a game, from `/api/bot/game/stream/{id}`
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "camelCase")]
+enum GameEvent {
+    GameFull(GameFull),
+    GameState(GameState),
+    #[serde(other)]
+    Other,
+}
+
+/// Why the bot declines `challenge` with the reasons lichess knows, None to accept it.
+/// `full` is whether it already plays as many games as it may.
+fn decline_reason(
+    challenge: &Challenge,
+    rules: &BotChallengeRules,
+    full: bool,
+) -> Option<&'static str> {
+    let (Some(initial), Some(increment)) = (
+        challenge.time_control.limit,
+        challenge.time_control.increment,
+    ) else {
+        return Some("tooSlow");
+    };
+    if full {
+        return Some("later");
+    }
+    match challenge.variant.key.as_str() {
+        "standard" => {}
+        "chess960" if rules.chess960 => {}
+        _ if rules.chess960 => return Some("variant"),
+        _ => return Some("standard"),
+    }
+    if challenge.rated && !rules.rated {
+        return Some("casual");
+    }
+    if !challenge.rated && !rules.casual {
+        return Some("rated");
+    }
+    if !rules.speeds.is_empty() && !rules.speeds.contains(&challenge.speed) {
+        return Some("timeControl");
+    }
+    if rules.min_initial.is_some_and(|min| initial < min) {
+        return Some("tooFast");
+    }
+    if rules.max_initial.is_some_and(|max| initial > max)
+        || rules.max_increment.is_some_and(|max| increment > max)
+    {
+        return Some("tooSlow");
+    }
+    None
+}
+
+/// Centipawns of a score from White's point of view for `color`
+fn own_cp(score: &Score, color: Color) -> i32 {
+    let cp = match score.value {
+        ScoreValue::Cp(cp) => cp,
+        ScoreValue::Mate(mate) if mate > 0 => MATE_CP,
+        ScoreValue::Mate(_) => -MATE_CP,
+    };
+    match color {
+        Color::White => cp,
+        Color::Black => -cp,
+    }
+}
+
+/// Next line of an NDJSON stream, skipping the blank ones lichess keeps it alive with.
+/// None when the stream ends, and an error when it fails or stays silent for
+/// `STREAM_TIMEOUT`.
+async fn next_line<S, B>(stream: &mut S, buffer: &mut Vec<u8>) -> Result<Option<String>, Error>
+where
+    S: Stream<Item = reqwest::Result<B>> + Unpin,
+    B: AsRef<[u8]>,
+{
+    loop {
+        if let Some(end) = buffer.iter().position(|&byte| byte == b'\n') {
+            let line: Vec<u8> = buffer.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line).trim().to_string();
+            if !line.is_empty() {
+                return Ok(Some(line));
+            }
+            continue;
+        }
+        match tokio::time::timeout(STREAM_TIMEOUT, stream.next()).await {
+            Ok(Some(chunk)) => buffer.extend_from_slice(chunk?.as_ref()),
+            Ok(None) => return Ok(None),
+            Err(_) => {
+                return Err(Error::StreamStalled {
+                    service: "lichess".to_string(),
+                })
+            }
+        }
+    }
+}
+
+/// What the tasks of a running bot share
+struct Bot {
+    config: BotConfig,
+    token: String,
+    /// Id of the BOT account
+    account: String,
+    client: Client,
+    app: AppHandle,
+    stopped: Arc<AtomicBool>,
+    games: Mutex<HashSet<String>>,
+    connected: AtomicBool,
+}
+
+/// How a game of the bot went, in the words of the lichess game status
+struct GameOver(String);
+
+impl Bot {
+    fn status(&self, message: String) {
+        info!("Lichess bot: {message}");
+        let games: Vec<String> = self.games.lock().unwrap().iter().cloned().collect();
+        let finished = self.stopped.load(Ordering::Relaxed) && games.is_empty();
+        let _ = BotStatus {
+            connected: self.connected.load(Ordering::Relaxed),
+            games,
+            message,
+            finished,
+        }
+        .emit_all(&self.app);
+    }
+
+    async fn get(&self, path: &str) -> Result<Response, Error> {
+        Ok(self
+            .client
+            .get(format!("{LICHESS_API}/{path}"))
+            .bearer_auth(&self.token)
+            .send()
+            .await?
+            .error_for_status()?)
+    }
+
+    async fn post(&self, path: &str, form: &[(&str, &str)]) -> Result<(), Error> {
+        self.client
+            .post(format!("{LICHESS_API}/{path}"))
+            .bearer_auth(&self.token)
+            .form(form)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    async fn answer(&self, challenge: Challenge) -> Result<(), Error> {
+        if challenge.direction.as_deref() == Some("out") {
+            return Ok(());
+        }
+        let full = self.games.lock().unwrap().len() >= self.config.max_games;
+        let challenger = challenge.challenger.as_ref().map_or("?", |c| &c.name);
+        match decline_reason(&challenge, &self.config.challenges, full) {
+            Some(reason) => {
+                let path = format!("challenge/{}/decline", challenge.id);
+                self.post(&path, &[("reason", reason)]).await?;
+                self.status(format!("Declined the challenge of {challenger} ({reason})"));
+            }
+            None => {
+                self.post(&format!("challenge/{}/accept", challenge.id), &[])
+                    .await?;
+                self.status(format!("Accepted the challenge of {challenger}"));
+            }
+        }
+        Ok(())
+    }
+
+    /// Follows the event stream until it ends, answering challenges and starting the
+    /// games
+    async fn run_events(self: &Arc<Self>, delay: &mut Duration) -> Result<(), Error> {
+        let response = self.get("stream/event").await?;
+        self.connected.store(true, Ordering::Relaxed);
+        *delay = RECONNECT_WAIT;
+        self.status("Connected to lichess".to_string());
+        let mut stream = Box::pin(response.bytes_stream());
+        let mut buffer = Vec::new();
+        while !self.stopped.load(Ordering::Relaxed) {
+            let Some(line) = next_line(&mut stream, &mut buffer).await? else {
+                return Ok(());
+            };
+            match serde_json::from_str(&line) {
+                Ok(AccountEvent::Challenge { challenge }) => {
+                    if let Err(e) = self.answer(challenge).await {
+                        self.status(format!("Failed to answer a challenge: {e}"));
+                    }
+                }
+                Ok(AccountEvent::GameStart { game }) => self.start_game(game.game_id),
+                Ok(AccountEvent::GameFinish { game }) => info!("Game {} finished", game.game_id),
+                Ok(AccountEvent::Other) => {}
+                Err(e) => error!("Unexpected lichess event {line}: {e}"),
+            }
+        }
+        Ok(())
+    }
+
+    /// Plays the game `id` in its own task, unless it's already being played. Lichess
+    /// starts the games in progress again after a reconnection.
+    fn start_game(self: &Arc<Self>, id: String) {
+        if !self.games.lock().unwrap().insert(id.clone()) {
+            return;
+        }
+        self.status(format!("Game {id} started"));
+        let bot = self.clone();
+        tauri::async_runtime::spawn(async move {
+            let result = bot.play(&id).await;
+            bot.games.lock().unwrap().remove(&id);
+            let state = bot.app.state::<AppState>();
+            let _ = kill_session(&state, format!("bot-{id}"), bot.config.engine.clone()).await;
+            match result {
+                Ok(GameOver(status)) => bot.status(format!("Game {id} is over: {status}")),
+                Err(e) => bot.status(format!("Game {id} failed: {e}")),
+            }
+        });
+    }
+
+    /// Plays the game `id` until it's over, following its stream and reconnecting to it
+    /// when it's cut
+    async fn play(&self, id: &str) -> Result<GameOver, Error> {
+        let state = self.app.state::<AppState>();
+        let tab = format!("bot-{id}");
+        // Color, start position and whether castling follows Chess960 rules
+        let mut game: Option<(Color, String, bool)> = None;
+        // Number of moves of the position last searched
+        let mut searched = None;
+        let mut losing = 0;
+        let mut abort_deadline = None;
+        let mut retries = 0;
+        loop {
+            let mut stream = Box::pin(
+                self.get(&format!("bot/game/stream/{id}"))
+                    .await?
+                    .bytes_stream(),
+            );
+            let mut buffer = Vec::new();
+            let cut = loop {
+                let line = match abort_deadline {
+                    Some(deadline) => {
+                        match tokio::time::timeout_at(deadline, next_line(&mut stream, &mut buffer))
+                            .await
+                        {
+                            Ok(line) => line,
+                            Err(_) => {
+                                self.post(&format!("bot/game/{id}/abort"), &[]).await?;
+                                return Ok(GameOver("aborted".to_string()));
+                            }
+                        }
+                    }
+                    None => next_line(&mut stream, &mut buffer).await,
+                };
+                let line = match line {
+                    Ok(Some(line)) => line,
+                    Ok(None) => break None,
+                    Err(e) => break Some(e),
+                };
+                retries = 0;
+                let update = match serde_json::from_str(&line) {
+                    Ok(GameEvent::GameFull(full)) => {
+                        let color = if full.white.id.as_deref() == Some(self.account.as_str()) {
+                            Color::White
+                        } else {
+                            Color::Black
+                        };
+                        let fen = match full.initial_fen.as_str() {
+                            "startpos" => STARTING_FEN.to_string(),
+                            fen => fen.to_string(),
+                        };
+                        game = Some((color, fen, full.variant.key == "chess960"));
+                        full.state
+                    }
+                    Ok(GameEvent::GameState(update)) => update,
+                    Ok(GameEvent::Other) => continue,
+                    Err(e) => {
+                        error!("Unexpected lichess game event {line}: {e}");
+                        continue;
+                    }
+                };
+                let Some((color, fen, chess960)) = &game else {
+                    continue;
+                };
+                if !matches!(update.status.as_str(), "created" | "started") {
+                    return Ok(GameOver(update.status));
+                }
+                let moves: Vec<String> = update
+                    .moves
+                    .split_whitespace()
+                    .map(str::to_string)
+                    .collect();
+                let pos = parse_position(&validate_fen(fen)?, &moves)?;
+                let opponent_moved = moves.len() > usize::from(color.is_white());
+                if pos.turn() != *color {
+                    abort_deadline = match (self.config.abort_after, opponent_moved) {
+                        (Some(seconds), false) => abort_deadline.or_else(|| {
+                            Some(tokio::time::Instant::now() + Duration::from_secs(seconds.into()))
+                        }),
+                        _ => None,
+                    };
+                    continue;
+                }
+                abort_deadline = None;
+                if searched == Some(moves.len()) {
+                    continue;
+                }
+                searched = Some(moves.len());
+
+                let go_mode = GoMode::Clock(Clock {
+                    wtime: update.wtime,
+                    btime: update.btime,
+                    winc: update.winc,
+                    binc: update.binc,
+                    movestogo: None,
+                });
+                let extra_options = if *chess960 {
+                    with_chess960(self.config.options.clone())
+                } else {
+                    self.config.options.clone()
+                };
+                let options = EngineOptions {
+                    fen: fen.clone(),
+                    moves,
+                    extra_options,
+                    tablebase: None,
+                    cloud_eval: None,
+                    max_pv_length: None,
+                };
+                let engine_move = search_move(
+                    id.to_string(),
+                    self.config.engine.clone(),
+                    tab.clone(),
+                    &go_mode,
+                    options,
+                    self.app.clone(),
+                    &state,
+                )
+                .await?;
+                if let Some(rule) = &self.config.resign {
+                    let lost = engine_move
+                        .score
+                        .as_ref()
+                        .is_some_and(|score| own_cp(score, *color) <= -rule.threshold);
+                    losing = if lost { losing + 1 } else { 0 };
+                    if losing >= rule.moves.max(1) {
+                        self.post(&format!("bot/game/{id}/resign"), &[]).await?;
+                        return Ok(GameOver("resigned".to_string()));
+                    }
+                }
+                let Some(uci) = engine_move.uci else {
+                    self.post(&format!("bot/game/{id}/resign"), &[]).await?;
+                    return Ok(GameOver("resigned without a move".to_string()));
+                };
+                self.post(&format!("bot/game/{id}/move/{uci}"), &[]).await?;
+            };
+            retries += 1;
+            if retries > MAX_RETRIES {
+                return Err(cut.unwrap_or(Error::StreamStalled {
+                    service: "lichess".to_string(),
+                }));
+            }
+            info!("The stream of the game {id} was cut, reconnecting");
+            wait(RECONNECT_WAIT, &AtomicBool::new(false)).await;
+        }
+    }
+}
+
+/// Follows the events of the account until the bot is stopped, reconnecting when the
+/// stream is cut
+async fn run_bot(bot: Arc<Bot>) {
+    let mut delay = RECONNECT_WAIT;
+    while !bot.stopped.load(Ordering::Relaxed) {
+        let result = bot.run_events(&mut delay).await;
+        bot.connected.store(false, Ordering::Relaxed);
+        if bot.stopped.load(Ordering::Relaxed) {
+            break;
+        }
+        let reason = match result {
+            Ok(()) => "the stream ended".to_string(),
+            Err(e) => e.to_string(),
+        };
+        bot.status(format!(
+            "Disconnected ({reason}), reconnecting in {}s",
+            delay.as_secs()
+        ));
+        wait(delay, &bot.stopped).await;
+        delay = (delay * 2).min(RECONNECT_MAX);
+    }
+    bot.status("Stopped accepting challenges".to_string());
+}
+
+/// Plays on lichess with an installed engine as the BOT account whose token was set
+/// with `set_lichess_bot_token`. Challenges matching `config` are accepted up to its
+/// number of games at once, and each game is played with its clock by an engine
+/// session of its own, aborted when the opponent doesn't start and resigned by the
+/// resign rule. The event stream is reconnected to when it drops, and `BotStatus`
+/// events tell what the bot does. A bot started again replaces the running one.
+#[tauri::command]
+#[specta::specta]
+pub async fn start_bot(
+    config: BotConfig,
+    app: AppHandle,
+    state: tauri::State<'_, AppState>,
+) -> Result<(), Error> {
+    ensure_online(&state)?;
+    let token = lichess_bot_token()?.ok_or(Error::NoBotToken)?;
+    let client = client()?;
+    let account: BotAccount = client
+        .get(format!("{LICHESS_API}/account"))
+        .bearer_auth(&token)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    if account.title.as_deref() != Some("BOT") {
+        return Err(Error::NotBotAccount {
+            username: account.username,
+        });
+    }
+
+    let stopped = Arc::new(AtomicBool::new(false));
+    if let Some(previous) = state.bot.lock().unwrap().replace(stopped.clone()) {
+        previous.store(true, Ordering::Relaxed);
+    }
+    info!("Starting the lichess bot {}", account.username);
+    let bot = Arc::new(Bot {
+        config,
+        token,
+        account: account.id,
+        client,
+        app,
+        stopped,
+        games: Mutex::new(HashSet::new()),
+        connected: AtomicBool::new(false),
+    });
+    tauri::async_runtime::spawn(run_bot(bot));
+    Ok(())
+}
+
+/// Stops accepting challenges and disconnects, the games being played going on until
+/// they're over
+#[tauri::command]
+#[specta::specta]
+pub fn stop_bot(state: tauri::State<'_, AppState>) {
+    if let Some(stopped) = state.bot.lock().unwrap().take() {
+        stopped.store(true, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn challenge(json: &str) -> Challenge {
+        match serde_json::from_str(json).unwrap() {
+            AccountEvent::Challenge { challenge } => challenge,
+            event => panic!("not a challenge: {event:?}"),
+        }
+    }
+
+    #[test]
+    fn answers_challenges() {
+        let blitz = challenge(
+            r#"{"type": "challenge", "challenge": {"id": "a", "rated": true,
+                "variant": {"key": "standard"}, "speed": "blitz",
+                "timeControl": {"type": "clock", "limit": 180, "increment": 2},
+                "challenger": {"id": "b", "name": "B"}, "direction": "in"}}"#,
+        );
+        let rules = BotChallengeRules {
+            speeds: vec!["blitz".to_string()],
+            max_initial: Some(600),
+            rated: true,
+            casual: true,
+            ..Default::default()
+        };
+        assert_eq!(decline_reason(&blitz, &rules, false), None);
+        assert_eq!(decline_reason(&blitz, &rules, true), Some("later"));
+
+        let casual_only = BotChallengeRules {
+            rated: false,
+            ..rules.clone()
+        };
+        assert_eq!(decline_reason(&blitz, &casual_only, false), Some("casual"));
+        let fast = BotChallengeRules {
+            min_initial: Some(300),
+            ..rules.clone()
+        };
+        assert_eq!(decline_reason(&blitz, &fast, false), Some("tooFast"));
+
+        let correspondence = challenge(
+            r#"{"type": "challenge", "challenge": {"id": "c", "rated": false,
+                "variant": {"key": "chess960"}, "speed": "correspondence",
+                "timeControl": {"type": "correspondence", "daysPerTurn": 2}}}"#,
+        );
+        assert_eq!(
+            decline_reason(&correspondence, &rules, false),
+            Some("tooSlow")
+        );
+        let chess960 = Challenge {
+            time_control: TimeControl {
+                limit: Some(180),
+                increment: Some(0),
+            },
+            ..correspondence
+        };
+        assert_eq!(decline_reason(&chess960, &rules, false), Some("standard"));
+    }
+
+    #[test]
+    fn reads_game_events() {
+        let event: GameEvent = serde_json::from_str(
+            r#"{"type": "gameFull", "id": "g", "variant": {"key": "standard"},
+                "white": {"id": "bot", "name": "Bot"}, "black": {"aiLevel": 3},
+                "initialFen": "startpos",
+                "state": {"type": "gameState", "moves": "e2e4 e7e5", "wtime": 180000,
+                    "btime": 179000, "winc": 2000, "binc": 2000, "status": "started"}}"#,
+        )
+        .unwrap();
+        let GameEvent::GameFull(full) = event else {
+            panic!("not a full game");
+        };
+        assert_eq!(full.white.id.as_deref(), Some("bot"));
+        assert_eq!(full.state.moves, "e2e4 e7e5");
+        assert!(matches!(
+            serde_json::from_str::<GameEvent>(r#"{"type": "chatLine", "text": "hi"}"#).unwrap(),
+            GameEvent::Other
+        ));
+
+        let score = |value| Score { value, wdl: None };
+        assert_eq!(own_cp(&score(ScoreValue::Cp(-300)), Color::Black), 300);
+        assert_eq!(own_cp(&score(ScoreValue::Mate(2)), Color::Black), -MATE_CP);
+    }
+
+    #[tokio::test]
+    async fn reads_ndjson_lines() {
+        let chunks: Vec<reqwest::Result<&[u8]>> = vec![
+            Ok(&b"{\"a\": 1}\n\n{\"b\""[..]),
+            Ok(&b": 2}\n"[..]),
+            Ok(&b"\n"[..]),
+        ];
+        let mut stream = futures_util::stream::iter(chunks);
+        let mut buffer = Vec::new();
+        let mut lines = Vec::new();
+        while let Some(line) = next_line(&mut stream, &mut buffer).await.unwrap() {
+            lines.push(line);
+        }
+        assert_eq!(lines, ["{\"a\": 1}", "{\"b\": 2}"]);
+    }
+}
@@ -0,0 +1,276 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use reqwest::{
+    header::{HeaderName, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED},
+    Client, StatusCode,
+};
+use serde::Serialize;
+use specta::Type;
+use tauri::Manager;
+use tauri_specta::Event;
+
+use super::{client, ensure_online, is_offline, wait};
+use crate::{
+    error::Error,
+    pgn::{parse_readable_games, GameTree},
+    AppState,
+};
+
+/// Shortest time between two polls, so a broadcast isn't fetched more than servers
+/// like lichess allow
+const MIN_INTERVAL: Duration = Duration::from_secs(2);
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, Type)]
+#[serde(rename_all = "camelCase")]
+pub enum BroadcastChange {
+    /// A game that wasn't there at the last fetch
+    New,
+    /// Moves were played, or taken back by the organizers
+    Moves,
+    /// Same moves, but other changes like a clock, comment or result
+    Updated,
+}
+
+#[derive(Serialize, Debug, Clone, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct BroadcastGame {
+    /// Identifies the game across fetches
+    pub key: String,
+    /// Position of the game in the PGN
+    pub index: usize,
+    pub change: BroadcastChange,
+    pub tree: GameTree,
+}
+
+/// Games of a broadcast that changed since the last poll
+#[derive(Clone, Type, Serialize, Event)]
+#[serde(rename_all = "camelCase")]
+pub struct BroadcastUpdate {
+    pub id: String,
+    pub games: Vec<BroadcastGame>,
+    /// Why the last poll failed, the watch going on regardless
+    pub error: Option<String>,
+}
+
+/// What the previous fetch of a broadcast returned
+#[derive(Default)]
+struct Broadcast {
+    games: HashMap<String, GameTree>,
+    etag: Option<String>,
+    last_modified: Option<String>,
+}
+
+/// Identifies a game by the URL lichess gives each of them, or by its players and round
+fn game_key(tree: &GameTree) -> String {
+    ["GameURL", "ChapterURL"]
+        .iter()
+        .find_map(|tag| tree.header(tag))
+        .map_or_else(
+            || {
+                let tags = ["Event", "Round", "White", "Black"];
+                let values: Vec<_> = tags
+                    .iter()
+                    .map(|tag| tree.header(tag).unwrap_or("?"))
+                    .collect();
+                values.join("|")
+            },
+            str::to_string,
+        )
+}
+
+fn main_line(tree: &GameTree) -> Vec<&str> {
+    tree.moves.iter().map(|node| node.uci.as_str()).collect()
+}
+
+impl Broadcast {
+    /// Games that are new or changed since the last fetch, which become the state the
+    /// next one is compared with
+    fn diff(&mut self, trees: Vec<GameTree>) -> Vec<BroadcastGame> {
+        trees
+            .into_iter()
+            .enumerate()
+            .filter_map(|(index, tree)| {
+                let key = game_key(&tree);
+                let change = match self.games.get(&key) {
+                    None => BroadcastChange::New,
+                    Some(previous) if *previous == tree => return None,
+                    Some(previous) if main_line(previous) != main_line(&tree) => {
+                        BroadcastChange::Moves
+                    }
+                    Some(_) => BroadcastChange::Updated,
+                };
+                self.games.insert(key.clone(), tree.clone());
+                Some(BroadcastGame {
+                    key,
+                    index,
+                    change,
+                    tree,
+                })
+            })
+            .collect()
+    }
+
+    /// Fetches the PGN at `url`, None when the server answers the conditional request
+    /// with the PGN being unchanged since the last fetch
+    async fn fetch(&mut self, client: &Client, url: &str) -> Result<Option<String>, Error> {
+        let mut request = client.get(url);
+        if let Some(etag) = &self.etag {
+            request = request.header(IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = &self.last_modified {
+            request = request.header(IF_MODIFIED_SINCE, last_modified);
+        }
+        let response = request.send().await?;
+        if response.status() == StatusCode::NOT_MODIFIED {
+            return Ok(None);
+        }
+        let response = response.error_for_status()?;
+        let headers = response.headers();
+        let header = |name: HeaderName| {
+            headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string)
+        };
+        self.etag = header(ETAG);
+        self.last_modified = header(LAST_MODIFIED);
+        Ok(Some(response.text().await?))
+    }
+}
+
+/// Follows the broadcast whose PGN is at `url`, like a lichess broadcast round or the
+/// `games.pgn` of an organizer, polling it every `interval` seconds. Returns the games
+/// it has now, then sends a `BroadcastUpdate` with the games that got new moves or
+/// appeared after each poll that found some. The watch `id` replaces the one with the
+/// same id and goes on until `stop_broadcast(id)`, polls being skipped while network
+/// calls are disabled.
+#[tauri::command]
+#[specta::specta]
+pub async fn watch_broadcast(
+    id: String,
+    url: String,
+    interval: u32,
+    app: tauri::AppHandle,
+    state: tauri::State<'_, AppState>,
+) -> Result<Vec<BroadcastGame>, Error> {
+    ensure_online(&state)?;
+    let client = client()?;
+    let mut broadcast = Broadcast::default();
+    let pgn = broadcast.fetch(&client, &url).await?.unwrap_or_default();
+    let games = broadcast.diff(parse_readable_games(&pgn));
+
+    let stopped = Arc::new(AtomicBool::new(false));
+    if let Some(previous) = state.broadcasts.insert(id.clone(), stopped.clone()) {
+        previous.store(true, Ordering::Relaxed);
+    }
+    let interval = Duration::from_secs(interval.into()).max(MIN_INTERVAL);
+    tauri::async_runtime::spawn(async move {
+        loop {
+            wait(interval, &stopped).await;
+            if stopped.load(Ordering::Relaxed) {
+                break;
+            }
+            if is_offline(&app.state::<AppState>()) {
+                continue;
+            }
+            let (games, error) = match broadcast.fetch(&client, &url).await {
+                Ok(Some(pgn)) => (broadcast.diff(parse_readable_games(&pgn)), None),
+                Ok(None) => continue,
+                Err(e) => (Vec::new(), Some(e.to_string())),
+            };
+            if games.is_empty() && error.is_none() {
+                continue;
+            }
+            let _ = BroadcastUpdate {
+                id: id.clone(),
+                games,
+                error,
+            }
+            .emit_all(&app);
+        }
+        // A newer watch with the same id keeps its entry
+        app.state::<AppState>()
+            .broadcasts
+            .remove_if(&id, |_, flag| Arc::ptr_eq(flag, &stopped));
+    });
+    Ok(games)
+}
+
+/// Stops the watch `id`, its polling ending within a second
+#[tauri::command]
+#[specta::specta]
+pub fn stop_broadcast(id: String, state: tauri::State<'_, AppState>) {
+    if let Some((_, stopped)) = state.broadcasts.remove(&id) {
+        stopped.store(true, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::pgn::parse_games;
+
+    fn round(movetext: &[&str]) -> Vec<GameTree> {
+        let pgn: Vec<_> = movetext
+            .iter()
+            .enumerate()
+            .map(|(board, moves)| {
+                format!(
+                    "[Round \"1.{}\"]\n[White \"W{board}\"]\n[Black \"B{board}\"]\n\n{moves}\n",
+                    board + 1
+                )
+            })
+            .collect();
+        parse_games(&pgn.join("\n")).unwrap()
+    }
+
+    #[test]
+    fn reports_changed_games() {
+        let mut broadcast = Broadcast::default();
+        let games = broadcast.diff(round(&["1. e4 *", "1. d4 *"]));
+        assert_eq!(games.len(), 2);
+        assert!(games.iter().all(|game| game.change == BroadcastChange::New));
+        assert_eq!(games[1].key, "?|1.2|W1|B1");
+
+        let games = broadcast.diff(round(&["1. e4 *", "1. d4 d5 *"]));
+        assert_eq!(games.len(), 1);
+        assert_eq!(
+            (games[0].index, games[0].change),
+            (1, BroadcastChange::Moves)
+        );
+        assert_eq!(games[0].tree.moves.len(), 2);
+
+        let games = broadcast.diff(round(&[
+            "1. e4 { [%clk 1:30:00] } *",
+            "1. d4 d5 *",
+            "1. c4 *",
+        ]));
+        let changes: Vec<_> = games.iter().map(|game| game.change).collect();
+        assert_eq!(changes, [BroadcastChange::Updated, BroadcastChange::New]);
+
+        assert!(broadcast
+            .diff(round(&[
+                "1. e4 { [%clk 1:30:00] } *",
+                "1. d4 d5 *",
+                "1. c4 *"
+            ]))
+            .is_empty());
+    }
+
+    #[test]
+    fn keys_games_by_their_url() {
+        let tree = parse_games(
+            "[White \"A\"]\n[GameURL \"https://lichess.org/broadcast/r/game\"]\n\n1. e4 *",
+        )
+        .unwrap();
+        assert_eq!(game_key(&tree[0]), "https://lichess.org/broadcast/r/game");
+    }
+}
@@ -0,0 +1,447 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use chrono::{NaiveDate, Utc};
+use log::info;
+use reqwest::{Client, StatusCode};
+use serde::{Deserialize, Serialize};
+use specta::Type;
+
+use super::{
+    client, ensure_online, send_with_backoff, ArchiveProgress, DateRange, GameSink, ImportTarget,
+    OnlineImportSummary,
+};
+use crate::{
+    error::Error,
+    pgn::{parse_readable_games, GameTree},
+    AppState,
+};
+
+const CHESSCOM_PLAYER: &str = "https://api.chess.com/pub/player";
+
+/// Rules of the games that can be read, the others being variants like bughouse
+const READABLE_RULES: [&str; 2] = ["chess", "chess960"];
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Type)]
+#[serde(rename_all = "camelCase")]
+pub enum ChesscomTimeClass {
+    Bullet,
+    Blitz,
+    Rapid,
+    Daily,
+}
+
+impl ChesscomTimeClass {
+    /// Name of the time class in the API
+    fn name(self) -> &'static str {
+        match self {
+            ChesscomTimeClass::Bullet => "bullet",
+            ChesscomTimeClass::Blitz => "blitz",
+            ChesscomTimeClass::Rapid => "rapid",
+            ChesscomTimeClass::Daily => "daily",
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Default, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct ChesscomImportOptions {
+    /// Time classes of the games to import, all of them when empty
+    #[serde(default)]
+    pub time_classes: Vec<ChesscomTimeClass>,
+    /// First day of the games, as `YYYY-MM-DD`
+    pub since: Option<String>,
+    /// Last day of the games, as `YYYY-MM-DD`
+    pub until: Option<String>,
+    pub max_games: Option<u32>,
+    #[serde(default)]
+    pub rated_only: bool,
+}
+
+#[derive(Deserialize, Debug)]
+struct Archives {
+    archives: Vec<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct MonthlyArchive {
+    games: Vec<ChesscomGame>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ChesscomGame {
+    #[serde(default)]
+    url: String,
+    #[serde(default)]
+    pgn: Option<String>,
+    #[serde(default)]
+    rated: bool,
+    #[serde(default)]
+    rules: String,
+    #[serde(default)]
+    time_class: String,
+    /// Seconds since the epoch
+    end_time: Option<i64>,
+}
+
+/// The PGN of a game with its URL as its site, chess.com only giving its own name, so
+/// the games imported before can be told apart
+fn with_site(pgn: &str, url: &str) -> String {
+    let site = format!("[Site \"{url}\"]");
+    let mut replaced = false;
+    let mut lines: Vec<&str> = pgn
+        .lines()
+        .map(|line| {
+            if !replaced && line.starts_with("[Site ") {
+                replaced = true;
+                site.as_str()
+            } else {
+                line
+            }
+        })
+        .collect();
+    if !replaced {
+        lines.insert(0, &site);
+    }
+    lines.join("\n")
+}
+
+/// Year and month of the archive at `url`, which ends in `/YYYY/MM`
+fn archive_month(url: &str) -> Option<(i32, u32)> {
+    let mut segments = url.trim_end_matches('/').rsplit('/');
+    let month = segments.next()?.parse().ok()?;
+    let year = segments.next()?.parse().ok()?;
+    Some((year, month))
+}
+
+/// Whether the month has days in the range
+fn month_in_range(range: &DateRange, (year, month): (i32, u32)) -> bool {
+    let Some(first) = NaiveDate::from_ymd_opt(year, month, 1) else {
+        return false;
+    };
+    let next = if month == 12 {
+        NaiveDate::from_ymd_opt(year + 1, 1, 1)
+    } else {
+        NaiveDate::from_ymd_opt(year, month + 1, 1)
+    };
+    range.until.map_or(true, |until| first <= until)
+        && range
+            .since
+            .zip(next)
+            .map_or(true, |(since, next)| since < next)
+}
+
+/// Whether the game is kept by the filters. Variants are sorted out separately, since
+/// they're counted.
+fn keep_game(game: &ChesscomGame, range: &DateRange, options: &ChesscomImportOptions) -> bool {
+    let ended = game.end_time.map(|time| time * 1000);
+    let in_range = match ended {
+        Some(ended) => {
+            range.since_ms().map_or(true, |since| ended >= since)
+                && range.until_ms().map_or(true, |until| ended < until)
+        }
+        None => true,
+    };
+    in_range
+        && (!options.rated_only || game.rated)
+        && (options.time_classes.is_empty()
+            || options
+                .time_classes
+                .iter()
+                .any(|class| class.name() == game.time_class))
+}
+
+async fn get_json<T: serde::de::DeserializeOwned>(
+    client: &Client,
+    url: &str,
+    username: &str,
+    cancelled: &AtomicBool,
+) -> Result<T, Error> {
+    match send_with_backoff("chess.com", || client.get(url), cancelled).await {
+        Err(Error::Reqwest(e)) if e.status() == Some(StatusCode::NOT_FOUND) => {
+            Err(Error::UnknownOnlineUser {
+                username: username.to_string(),
+            })
+        }
+        response => Ok(response?.json().await?),
+    }
+}
+
+/// Imports the games of the chess.com player `username` into `target` from their
+/// monthly archives, newest first. Only the archives of the months in the date range
+/// are fetched, and progress is reported after each of them. Games of variants that
+/// can't be read, like bughouse or crazyhouse, are skipped and counted, and so are the
+/// games already in `target`, found by their URL.
+#[tauri::command]
+#[specta::specta]
+pub async fn import_chesscom_games(
+    id: String,
+    username: String,
+    options: ChesscomImportOptions,
+    target: ImportTarget,
+    app: tauri::AppHandle,
+    state: tauri::State<'_, AppState>,
+) -> Result<OnlineImportSummary, Error> {
+    ensure_online(&state)?;
+    let range = DateRange::parse(options.since.as_deref(), options.until.as_deref())?;
+    let mut sink = GameSink::new(id.clone(), target.clone(), &state, app)?;
+    sink.skip_known_sites(&target)?;
+    let cancelled = Arc::new(AtomicBool::new(false));
+    state.online_imports.insert(id.clone(), cancelled.clone());
+    info!("Importing the chess.com games of {username}");
+
+    let client = client()?;
+    let username = username.to_lowercase();
+    let result: Result<(), Error> = async {
+        let url = format!("{CHESSCOM_PLAYER}/{username}/games/archives");
+        let archives: Archives = get_json(&client, &url, &username, &cancelled).await?;
+        let archives: Vec<_> = archives
+            .archives
+            .into_iter()
+            .filter_map(|url| Some((archive_month(&url)?, url)))
+            .filter(|(month, _)| month_in_range(&range, *month))
+            .rev()
+            .collect();
+
+        let total = archives.len();
+        for (index, ((year, month), url)) in archives.into_iter().enumerate() {
+            if cancelled.load(Ordering::Relaxed) {
+                return Ok(());
+            }
+            sink.archive = Some(ArchiveProgress {
+                month: format!("{year:04}-{month:02}"),
+                index,
+                total,
+            });
+            sink.progress(false);
+            let archive: MonthlyArchive = get_json(&client, &url, &username, &cancelled).await?;
+            for game in archive.games.iter().rev() {
+                if !keep_game(game, &range, &options) {
+                    continue;
+                }
+                match &game.pgn {
+                    Some(pgn) if READABLE_RULES.contains(&game.rules.as_str()) => {
+                        sink.push_new(&game.url, &with_site(pgn, &game.url))?
+                    }
+                    _ => sink.skipped += 1,
+                }
+                if options
+                    .max_games
+                    .is_some_and(|max| sink.games >= max as usize)
+                {
+                    return Ok(());
+                }
+            }
+        }
+        Ok(())
+    }
+    .await;
+    state.online_imports.remove(&id);
+    let flushed = sink.finish(cancelled.load(Ordering::Relaxed));
+    result?;
+    flushed
+}
+
+#[derive(Deserialize, Debug)]
+struct CurrentGames {
+    games: Vec<CurrentGame>,
+}
+
+/// A daily game being played, as chess.com gives it
+#[derive(Deserialize, Debug)]
+struct CurrentGame {
+    url: String,
+    #[serde(default)]
+    pgn: Option<String>,
+    fen: String,
+    rules: String,
+    /// `white` or `black`
+    turn: String,
+    /// Profile URLs of the players
+    white: String,
+    black: String,
+    /// Seconds since the epoch the move is due by
+    move_by: Option<i64>,
+    /// Color that offered a draw
+    draw_offer: Option<String>,
+}
+
+/// A daily game being played by the player asked for
+#[derive(Serialize, Debug, Clone, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct ChesscomDailyGame {
+    pub url: String,
+    pub tree: GameTree,
+    /// `white` or `black`
+    pub turn: String,
+    /// Whether it's the move of the player asked for
+    pub player_to_move: bool,
+    /// Milliseconds since the epoch the move is due by, None for a game with no deadline
+    pub move_by_ms: Option<i64>,
+    /// Milliseconds until the move is due, zero once it's late
+    pub time_left_ms: Option<i64>,
+    /// Whether the player to move was offered a draw
+    pub draw_offered: bool,
+}
+
+/// The daily game `game` of `username` when its moves or its position can be read
+fn daily_game(game: CurrentGame, username: &str, now_ms: i64) -> Option<ChesscomDailyGame> {
+    if !READABLE_RULES.contains(&game.rules.as_str()) {
+        return None;
+    }
+    let mut tree = game
+        .pgn
+        .as_deref()
+        .and_then(|pgn| parse_readable_games(pgn).into_iter().next());
+    if tree.is_none() {
+        let pgn = format!("[SetUp \"1\"]\n[FEN \"{}\"]\n\n*\n", game.fen);
+        tree = parse_readable_games(&pgn).into_iter().next();
+    }
+    let profile = if game.turn == "white" {
+        &game.white
+    } else {
+        &game.black
+    };
+    let player = profile.rsplit('/').next().unwrap_or_default();
+    let move_by_ms = game
+        .move_by
+        .filter(|&time| time > 0)
+        .map(|time| time * 1000);
+    Some(ChesscomDailyGame {
+        tree: tree?,
+        player_to_move: player.eq_ignore_ascii_case(username),
+        move_by_ms,
+        time_left_ms: move_by_ms.map(|due| (due - now_ms).max(0)),
+        draw_offered: game.draw_offer.is_some_and(|color| color != game.turn),
+        url: game.url,
+        turn: game.turn,
+    })
+}
+
+/// The daily games being played by the chess.com player `username`, with the time left
+/// for the player to move. The games of variants that can't be read are left out.
+#[tauri::command]
+#[specta::specta]
+pub async fn get_chesscom_daily_games(
+    username: String,
+    state: tauri::State<'_, AppState>,
+) -> Result<Vec<ChesscomDailyGame>, Error> {
+    ensure_online(&state)?;
+    let client = client()?;
+    let username = username.to_lowercase();
+    let url = format!("{CHESSCOM_PLAYER}/{username}/games");
+    let current: CurrentGames = get_json(&client, &url, &username, &AtomicBool::new(false)).await?;
+    let now_ms = Utc::now().timestamp_millis();
+    Ok(current
+        .games
+        .into_iter()
+        .filter_map(|game| daily_game(game, &username, now_ms))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn picks_archives_in_range() {
+        let url = "https://api.chess.com/pub/player/hikaru/games/2023/12";
+        assert_eq!(archive_month(url), Some((2023, 12)));
+        assert_eq!(archive_month("https://api.chess.com/pub/player/x"), None);
+
+        let range = DateRange::parse(Some("2023-12-31"), Some("2024-02-01")).unwrap();
+        assert!(!month_in_range(&range, (2023, 11)));
+        assert!(month_in_range(&range, (2023, 12)));
+        assert!(month_in_range(&range, (2024, 2)));
+        assert!(!month_in_range(&range, (2024, 3)));
+        assert!(month_in_range(
+            &DateRange::parse(None, None).unwrap(),
+            (2010, 1)
+        ));
+    }
+
+    #[test]
+    fn filters_archive_games() {
+        let archive: MonthlyArchive = serde_json::from_str(
+            r#"{"games": [
+                {"pgn": "1. e4 *", "rated": true, "rules": "chess", "time_class": "blitz", "end_time": 1704067200},
+                {"pgn": "1. e4 *", "rated": false, "rules": "chess", "time_class": "rapid", "end_time": 1704067200},
+                {"pgn": "1. P@e4 *", "rated": true, "rules": "crazyhouse", "time_class": "blitz", "end_time": 1704067200},
+                {"rated": true, "rules": "chess", "time_class": "daily"}
+            ]}"#,
+        )
+        .unwrap();
+        let range = DateRange::parse(Some("2024-01-01"), None).unwrap();
+        let options = ChesscomImportOptions {
+            rated_only: true,
+            ..Default::default()
+        };
+        let kept: Vec<_> = archive
+            .games
+            .iter()
+            .map(|game| keep_game(game, &range, &options))
+            .collect();
+        assert_eq!(kept, [true, false, true, true]);
+
+        let blitz = ChesscomImportOptions {
+            time_classes: vec![ChesscomTimeClass::Blitz],
+            ..Default::default()
+        };
+        let late = DateRange::parse(Some("2024-01-02"), None).unwrap();
+        assert!(keep_game(&archive.games[0], &range, &blitz));
+        assert!(!keep_game(&archive.games[1], &range, &blitz));
+        assert!(!keep_game(&archive.games[0], &late, &blitz));
+    }
+
+    #[test]
+    fn sets_game_urls_as_sites() {
+        let url = "https://www.chess.com/game/live/1";
+        assert_eq!(
+            with_site(
+                "[Event \"Live Chess\"]\n[Site \"Chess.com\"]\n\n1. e4 *",
+                url
+            ),
+            "[Event \"Live Chess\"]\n[Site \"https://www.chess.com/game/live/1\"]\n\n1. e4 *"
+        );
+        assert_eq!(
+            with_site("1. e4 *", url),
+            "[Site \"https://www.chess.com/game/live/1\"]\n1. e4 *"
+        );
+    }
+
+    #[test]
+    fn reads_daily_games() {
+        let current: CurrentGames = serde_json::from_str(
+            r#"{"games": [
+                {"url": "https://www.chess.com/game/daily/1", "pgn": "[Event \"Let's Play!\"]\n\n1. e4 e5 *",
+                 "fen": "rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR w KQkq - 0 2",
+                 "rules": "chess", "turn": "white", "move_by": 1704070800, "draw_offer": "black",
+                 "white": "https://api.chess.com/pub/player/Erik", "black": "https://api.chess.com/pub/player/hikaru"},
+                {"url": "https://www.chess.com/game/daily/2",
+                 "fen": "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1",
+                 "rules": "chess", "turn": "black", "move_by": 0,
+                 "white": "https://api.chess.com/pub/player/erik", "black": "https://api.chess.com/pub/player/hikaru"},
+                {"url": "https://www.chess.com/game/daily/3", "fen": "", "rules": "bughouse", "turn": "white",
+                 "white": "https://api.chess.com/pub/player/erik", "black": "https://api.chess.com/pub/player/hikaru"}
+            ]}"#,
+        )
+        .unwrap();
+        let now_ms = 1_704_067_200_000;
+        let games: Vec<_> = current
+            .games
+            .into_iter()
+            .filter_map(|game| daily_game(game, "erik", now_ms))
+            .collect();
+
+        assert_eq!(games.len(), 2);
+        assert!(games[0].player_to_move);
+        assert_eq!(games[0].tree.moves.len(), 2);
+        assert_eq!(games[0].time_left_ms, Some(3_600_000));
+        assert!(games[0].draw_offered);
+        assert!(!games[1].player_to_move);
+        assert_eq!(games[1].move_by_ms, None);
+        assert!(games[1].tree.fen.starts_with("rnbqkbnr/pppppppp/8/8/4P3"));
+    }
+}
@@ -0,0 +1,148 @@
+use std::time::Duration;
+
+use reqwest::StatusCode;
+use serde::Deserialize;
+use shakmaty::{fen::Fen, san::SanPlus, uci::Uci, Chess, EnPassantMode};
+use specta::Type;
+use vampirc_uci::uci::{Score, ScoreValue};
+
+use super::{client, is_offline, with_lichess_token};
+use crate::{chess::BestMoves, AppState};
+
+const LICHESS_CLOUD_EVAL: &str = "https://lichess.org/api/cloud-eval";
+
+/// Time lichess has to answer, kept short since the engine is starting meanwhile
+const CLOUD_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Answers kept in memory before the cache is cleared
+const CLOUD_CACHE_SIZE: usize = 10_000;
+
+/// Shows the evaluation of the lichess cloud while the engine starts
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct CloudEvalOptions {
+    /// Depth under which cloud evaluations aren't shown
+    pub min_depth: u32,
+}
+
+#[derive(Deserialize, Debug)]
+struct CloudPv {
+    moves: String,
+    cp: Option<i32>,
+    mate: Option<i32>,
+}
+
+#[derive(Deserialize, Debug)]
+struct CloudResponse {
+    depth: u32,
+    knodes: u32,
+    pvs: Vec<CloudPv>,
+}
+
+/// The lines of a cloud evaluation of `pos`, whose scores are from White's point of
+/// view like the engine's. A line stops at its first move that isn't legal.
+fn cloud_lines(pos: &Chess, response: CloudResponse) -> Vec<BestMoves> {
+    response
+        .pvs
+        .into_iter()
+        .enumerate()
+        .filter_map(|(index, pv)| {
+            let mut line = pos.clone();
+            let mut uci_moves = Vec::new();
+            let mut san_moves = Vec::new();
+            for uci in pv.moves.split_whitespace() {
+                let Some(m) = Uci::from_ascii(uci.as_bytes())
+                    .ok()
+                    .and_then(|uci| uci.to_move(&line).ok())
+                else {
+                    break;
+                };
+                san_moves.push(SanPlus::from_move_and_play_unchecked(&mut line, &m).to_string());
+                uci_moves.push(uci.to_string());
+            }
+            if uci_moves.is_empty() {
+                return None;
+            }
+            let value = match pv.mate {
+                Some(mate) => ScoreValue::Mate(mate),
+                None => ScoreValue::Cp(pv.cp.unwrap_or(0)),
+            };
+            Some(BestMoves {
+                nodes: response.knodes.saturating_mul(1000),
+                depth: response.depth,
+                score: Score { value, wdl: None },
+                uci_moves,
+                san_moves,
+                multipv: index as u16 + 1,
+                nps: 0,
+                truncated_from: None,
+            })
+        })
+        .collect()
+}
+
+/// Lines of the lichess cloud evaluation of `pos` with up to `multipv` of them, None
+/// when lichess has none, can't be reached, or network calls are disabled. Answers,
+/// including the lack of one, are cached.
+pub async fn cloud_eval(state: &AppState, pos: &Chess, multipv: u16) -> Option<Vec<BestMoves>> {
+    if is_offline(state) {
+        return None;
+    }
+    let fen = Fen::from_position(pos.clone(), EnPassantMode::Legal).to_string();
+    let key = (fen, multipv);
+    if let Some(lines) = state.cloud_evals.get(&key) {
+        return lines.clone();
+    }
+    let request = client()
+        .ok()?
+        .get(LICHESS_CLOUD_EVAL)
+        .query(&[("fen", key.0.clone()), ("multiPv", multipv.to_string())])
+        .timeout(CLOUD_TIMEOUT);
+    let response = with_lichess_token(state, request).send().await.ok()?;
+    let lines = if response.status() == StatusCode::NOT_FOUND {
+        None
+    } else {
+        let response: CloudResponse = response.error_for_status().ok()?.json().await.ok()?;
+        Some(cloud_lines(pos, response))
+    };
+    if state.cloud_evals.len() >= CLOUD_CACHE_SIZE {
+        state.cloud_evals.clear();
+    }
+    state.cloud_evals.insert(key, lines.clone());
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use shakmaty::CastlingMode;
+
+    #[test]
+    fn reads_cloud_evaluations() {
+        let response: CloudResponse = serde_json::from_str(
+            r#"{"fen": "", "knodes": 1500, "depth": 40, "pvs": [
+                {"moves": "e7e5 g1f3 b8c6", "cp": 25},
+                {"moves": "c7c5 e1e8", "cp": 30},
+                {"moves": "g7g5", "mate": -2},
+                {"moves": "", "cp": 0}
+            ]}"#,
+        )
+        .unwrap();
+        let pos: Chess =
+            Fen::from_ascii(b"rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1")
+                .unwrap()
+                .into_position(CastlingMode::Standard)
+                .unwrap();
+        let lines = cloud_lines(&pos, response);
+
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0].san_moves, ["e5", "Nf3", "Nc6"]);
+        assert_eq!(lines[0].multipv, 1);
+        assert_eq!((lines[0].depth, lines[0].nodes), (40, 1_500_000));
+        assert!(matches!(lines[0].score.value, ScoreValue::Cp(25)));
+        assert_eq!(lines[1].uci_moves, ["c7c5"]);
+        assert!(matches!(lines[2].score.value, ScoreValue::Mate(-2)));
+        assert_eq!(lines[2].multipv, 3);
+    }
+}
@@ -0,0 +1,452 @@
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use log::info;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use shakmaty::{fen::Fen, san::SanPlus, uci::Uci, CastlingMode, Chess, EnPassantMode};
+use specta::Type;
+use tauri::{
+    api::path::{resolve_path, BaseDirectory},
+    AppHandle, Manager,
+};
+
+use super::{client, is_offline, with_lichess_token};
+use crate::{
+    db::{NormalizedGame, PositionStats},
+    error::Error,
+    AppState,
+};
+
+const LICHESS_EXPLORER: &str = "https://explorer.lichess.ovh";
+
+/// Age after which a cached answer is asked for again, and only served when lichess
+/// can't be reached
+const CACHE_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Time a request waits for the board to settle before going out, so positions only
+/// passed through aren't asked for
+const DEBOUNCE: Duration = Duration::from_millis(300);
+
+const EXPLORER_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Type)]
+#[serde(rename_all = "camelCase")]
+pub enum ExplorerSource {
+    /// Over the board games of titled players
+    Masters,
+    /// Games played on lichess
+    Lichess,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Type)]
+#[serde(rename_all = "camelCase")]
+pub enum ExplorerSpeed {
+    UltraBullet,
+    Bullet,
+    Blitz,
+    Rapid,
+    Classical,
+    Correspondence,
+}
+
+impl ExplorerSpeed {
+    /// Name of the speed in the API
+    fn name(self) -> &'static str {
+        match self {
+            ExplorerSpeed::UltraBullet => "ultraBullet",
+            ExplorerSpeed::Bullet => "bullet",
+            ExplorerSpeed::Blitz => "blitz",
+            ExplorerSpeed::Rapid => "rapid",
+            ExplorerSpeed::Classical => "classical",
+            ExplorerSpeed::Correspondence => "correspondence",
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default, PartialEq, Eq, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct ExplorerFilters {
+    /// Rating groups of the lichess games, like 1600 for the players from 1600 to 1799,
+    /// all of them when empty
+    #[serde(default)]
+    pub ratings: Vec<u16>,
+    /// Speeds of the lichess games, all of them when empty
+    #[serde(default)]
+    pub speeds: Vec<ExplorerSpeed>,
+    /// First month of the games, as `YYYY-MM`, of which only the year counts for masters
+    pub since: Option<String>,
+    /// Last month of the games, as `YYYY-MM`, of which only the year counts for masters
+    pub until: Option<String>,
+    /// Games to list, up to 15
+    pub top_games: Option<u8>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+struct ExplorerMove {
+    san: String,
+    white: i64,
+    draws: i64,
+    black: i64,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+struct ExplorerPlayer {
+    name: String,
+    rating: Option<i32>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+struct ExplorerGame {
+    /// Move played in the position
+    uci: String,
+    id: String,
+    winner: Option<String>,
+    white: ExplorerPlayer,
+    black: ExplorerPlayer,
+    year: Option<i32>,
+    /// `YYYY-MM`
+    month: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+struct ExplorerResponse {
+    #[serde(default)]
+    moves: Vec<ExplorerMove>,
+    #[serde(default)]
+    top_games: Vec<ExplorerGame>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+struct CacheEntry {
+    /// Seconds since the epoch
+    fetched_at: u64,
+    response: ExplorerResponse,
+}
+
+fn query(
+    source: ExplorerSource,
+    fen: &str,
+    filters: &ExplorerFilters,
+) -> Vec<(&'static str, String)> {
+    let mut query = vec![("fen", fen.to_string())];
+    if let Some(top_games) = filters.top_games {
+        query.push(("topGames", top_games.to_string()));
+    }
+    match source {
+        ExplorerSource::Masters => {
+            let year = |month: &str| month.chars().take(4).collect::<String>();
+            if let Some(since) = &filters.since {
+                query.push(("since", year(since)));
+            }
+            if let Some(until) = &filters.until {
+                query.push(("until", year(until)));
+            }
+        }
+        ExplorerSource::Lichess => {
+            query.push(("recentGames", "0".to_string()));
+            if !filters.speeds.is_empty() {
+                let speeds: Vec<_> = filters.speeds.iter().map(|s| s.name()).collect();
+                query.push(("speeds", speeds.join(",")));
+            }
+            if !filters.ratings.is_empty() {
+                let ratings: Vec<_> = filters.ratings.iter().map(|r| r.to_string()).collect();
+                query.push(("ratings", ratings.join(",")));
+            }
+            if let Some(since) = &filters.since {
+                query.push(("since", since.clone()));
+            }
+            if let Some(until) = &filters.until {
+                query.push(("until", until.clone()));
+            }
+        }
+    }
+    query
+}
+
+/// File the answer for the position and filters is cached in
+fn cache_file(dir: &Path, source: ExplorerSource, fen: &str, filters: &ExplorerFilters) -> PathBuf {
+    let key = serde_json::to_string(&(source, fen, filters)).unwrap_or_default();
+    let hash: String = Sha256::digest(key.as_bytes())
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+    dir.join(format!("{hash}.json"))
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs())
+}
+
+fn read_cache(file: &Path) -> Option<CacheEntry> {
+    let file = File::open(file).ok()?;
+    serde_json::from_reader(BufReader::new(file)).ok()
+}
+
+fn write_cache(file: &Path, response: &ExplorerResponse) -> Result<(), Error> {
+    let entry = CacheEntry {
+        fetched_at: now(),
+        response: response.clone(),
+    };
+    serde_json::to_writer(BufWriter::new(File::create(file)?), &entry)
+        .map_err(std::io::Error::from)?;
+    Ok(())
+}
+
+fn count(games: i64) -> i32 {
+    i32::try_from(games).unwrap_or(i32::MAX)
+}
+
+/// The answer of lichess in the shape of the local explorer's
+fn to_local(
+    fen: &str,
+    pos: &Chess,
+    response: ExplorerResponse,
+) -> (Vec<PositionStats>, Vec<NormalizedGame>) {
+    let stats = response
+        .moves
+        .into_iter()
+        .map(|m| PositionStats {
+            move_: m.san,
+            white: count(m.white),
+            draw: count(m.draws),
+            black: count(m.black),
+        })
+        .collect();
+    let games = response
+        .top_games
+        .into_iter()
+        .map(|game| {
+            let san = Uci::from_ascii(game.uci.as_bytes())
+                .ok()
+                .and_then(|uci| uci.to_move(pos).ok())
+                .map(|m| SanPlus::from_move(pos.clone(), &m).to_string())
+                .unwrap_or_default();
+            let result = match game.winner.as_deref() {
+                Some("white") => "1-0",
+                Some("black") => "0-1",
+                _ => "1/2-1/2",
+            };
+            NormalizedGame {
+                id: 0,
+                fen: fen.to_string(),
+                event: String::new(),
+                event_id: 0,
+                site: format!("https://lichess.org/{}", game.id),
+                site_id: 0,
+                date: game
+                    .month
+                    .map(|month| month.replace('-', "."))
+                    .or(game.year.map(|year| year.to_string())),
+                time: None,
+                round: None,
+                white: game.white.name,
+                white_id: 0,
+                white_elo: game.white.rating,
+                black: game.black.name,
+                black_id: 0,
+                black_elo: game.black.rating,
+                result: Some(result.to_string()),
+                time_control: None,
+                eco: None,
+                ply_count: None,
+                white_material: 0,
+                black_material: 0,
+                moves: san,
+            }
+        })
+        .collect();
+    (stats, games)
+}
+
+async fn fetch(
+    state: &AppState,
+    source: ExplorerSource,
+    fen: &str,
+    filters: &ExplorerFilters,
+) -> Result<ExplorerResponse, Error> {
+    let path = match source {
+        ExplorerSource::Masters => "masters",
+        ExplorerSource::Lichess => "lichess",
+    };
+    let request = client()?
+        .get(format!("{LICHESS_EXPLORER}/{path}"))
+        .query(&query(source, fen, filters))
+        .timeout(EXPLORER_TIMEOUT);
+    Ok(with_lichess_token(state, request)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?)
+}
+
+/// Moves and top games of the lichess explorer for `fen`, from the masters database or
+/// the games of lichess, in the shape of `search_position`'s so the sources can be
+/// switched. Answers are cached on disk for a day. Requests wait for the board of `tab`
+/// to settle, and the ones a newer request of the tab replaced end with
+/// `ExplorerRequestSuperseded`. When lichess can't be reached an expired answer is
+/// served if there is one, `OnlineExplorerUnavailable` otherwise.
+#[tauri::command]
+pub async fn get_lichess_explorer(
+    tab: String,
+    fen: String,
+    source: ExplorerSource,
+    filters: ExplorerFilters,
+    app: AppHandle,
+    state: tauri::State<'_, AppState>,
+) -> Result<(Vec<PositionStats>, Vec<NormalizedGame>), Error> {
+    let pos: Chess = Fen::from_ascii(fen.as_bytes())?.into_position(CastlingMode::Chess960)?;
+    let fen = Fen::from_position(pos.clone(), EnPassantMode::Legal).to_string();
+    let dir = resolve_path(
+        &app.config(),
+        app.package_info(),
+        &app.env(),
+        "explorer",
+        Some(BaseDirectory::AppData),
+    )?;
+    let file = cache_file(&dir, source, &fen, &filters);
+    let cached = read_cache(&file);
+    if let Some(entry) = &cached {
+        if now().saturating_sub(entry.fetched_at) < CACHE_TTL.as_secs() {
+            return Ok(to_local(&fen, &pos, entry.response.clone()));
+        }
+    }
+
+    if is_offline(&state) {
+        return cached
+            .map(|entry| to_local(&fen, &pos, entry.response))
+            .ok_or(Error::OfflineMode);
+    }
+
+    let generation = {
+        let mut latest = state.explorer_requests.entry(tab.clone()).or_insert(0);
+        *latest += 1;
+        *latest
+    };
+    let superseded = || state.explorer_requests.get(&tab).map(|latest| *latest) != Some(generation);
+    tokio::time::sleep(DEBOUNCE).await;
+    if superseded() {
+        return Err(Error::ExplorerRequestSuperseded);
+    }
+
+    match fetch(&state, source, &fen, &filters).await {
+        Ok(response) => {
+            if let Err(e) = write_cache(&file, &response) {
+                info!("Could not cache the explorer answer: {e}");
+            }
+            if superseded() {
+                return Err(Error::ExplorerRequestSuperseded);
+            }
+            Ok(to_local(&fen, &pos, response))
+        }
+        Err(e) => match cached {
+            Some(entry) => Ok(to_local(&fen, &pos, entry.response)),
+            None => Err(Error::OnlineExplorerUnavailable {
+                reason: e.to_string(),
+            }),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ANSWER: &str = r#"{
+        "white": 10, "draws": 5, "black": 3,
+        "moves": [
+            {"uci": "e2e4", "san": "e4", "averageRating": 2400, "white": 6, "draws": 3, "black": 2, "game": null},
+            {"uci": "d2d4", "san": "d4", "averageRating": 2410, "white": 4000000000, "draws": 2, "black": 1, "game": null}
+        ],
+        "topGames": [
+            {"uci": "e2e4", "id": "abcd1234", "winner": "black",
+             "white": {"name": "Carlsen, M.", "rating": 2850},
+             "black": {"name": "Caruana, F.", "rating": 2820},
+             "year": 2019, "month": "2019-05"},
+            {"uci": "d2d4", "id": "efgh5678", "winner": null,
+             "white": {"name": "A", "rating": 2500}, "black": {"name": "B", "rating": null},
+             "year": 2001}
+        ],
+        "opening": null
+    }"#;
+
+    #[test]
+    fn maps_answers_like_the_local_explorer() {
+        let response: ExplorerResponse = serde_json::from_str(ANSWER).unwrap();
+        let fen = Fen::default().to_string();
+        let (stats, games) = to_local(&fen, &Chess::default(), response);
+
+        assert_eq!(stats.len(), 2);
+        assert_eq!(stats[0].move_, "e4");
+        assert_eq!((stats[0].white, stats[0].draw, stats[0].black), (6, 3, 2));
+        assert_eq!(stats[1].white, i32::MAX);
+
+        assert_eq!(games.len(), 2);
+        assert_eq!(games[0].site, "https://lichess.org/abcd1234");
+        assert_eq!(games[0].result.as_deref(), Some("0-1"));
+        assert_eq!(games[0].date.as_deref(), Some("2019.05"));
+        assert_eq!(games[0].moves, "e4");
+        assert_eq!(games[1].result.as_deref(), Some("1/2-1/2"));
+        assert_eq!(games[1].date.as_deref(), Some("2001"));
+        assert_eq!(games[1].black_elo, None);
+    }
+
+    #[test]
+    fn caches_by_position_and_filters() {
+        let dir = tempfile::tempdir().unwrap();
+        let fen = Fen::default().to_string();
+        let filters = ExplorerFilters::default();
+        let blitz = ExplorerFilters {
+            speeds: vec![ExplorerSpeed::Blitz],
+            ..Default::default()
+        };
+        let file = cache_file(dir.path(), ExplorerSource::Lichess, &fen, &filters);
+        assert_eq!(
+            file,
+            cache_file(dir.path(), ExplorerSource::Lichess, &fen, &filters)
+        );
+        assert_ne!(
+            file,
+            cache_file(dir.path(), ExplorerSource::Masters, &fen, &filters)
+        );
+        assert_ne!(
+            file,
+            cache_file(dir.path(), ExplorerSource::Lichess, &fen, &blitz)
+        );
+
+        assert!(read_cache(&file).is_none());
+        let response: ExplorerResponse = serde_json::from_str(ANSWER).unwrap();
+        write_cache(&file, &response).unwrap();
+        let entry = read_cache(&file).unwrap();
+        assert_eq!(entry.response.moves.len(), 2);
+        assert!(now() - entry.fetched_at < 5);
+    }
+
+    #[test]
+    fn builds_queries_for_each_source() {
+        let filters = ExplorerFilters {
+            ratings: vec![1600, 1800],
+            speeds: vec![ExplorerSpeed::Blitz, ExplorerSpeed::Rapid],
+            since: Some("2015-03".to_string()),
+            until: None,
+            top_games: Some(4),
+        };
+        let masters = query(ExplorerSource::Masters, "fen", &filters);
+        assert!(masters.contains(&("since", "2015".to_string())));
+        assert!(!masters.iter().any(|(key, _)| *key == "speeds"));
+
+        let lichess = query(ExplorerSource::Lichess, "fen", &filters);
+        assert!(lichess.contains(&("since", "2015-03".to_string())));
+        assert!(lichess.contains(&("speeds", "blitz,rapid".to_string())));
+        assert!(lichess.contains(&("ratings", "1600,1800".to_string())));
+        assert!(lichess.contains(&("topGames", "4".to_string())));
+    }
+}
@@ -0,0 +1,335 @@
+use std::{
+    collections::HashSet,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use chrono::{NaiveDate, NaiveTime};
+use futures_util::StreamExt;
+use log::info;
+use reqwest::StatusCode;
+use serde::Deserialize;
+use specta::Type;
+
+use super::{
+    client, ensure_online, send_with_backoff, DateRange, GameSink, ImportTarget,
+    OnlineImportSummary, MAX_RETRIES,
+};
+use crate::{error::Error, oauth::lichess_token, AppState};
+
+const LICHESS_GAMES: &str = "https://lichess.org/api/games/user";
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Type)]
+#[serde(rename_all = "camelCase")]
+pub enum LichessPerf {
+    UltraBullet,
+    Bullet,
+    Blitz,
+    Rapid,
+    Classical,
+    Correspondence,
+    Chess960,
+    Crazyhouse,
+    Antichess,
+    Atomic,
+    Horde,
+    KingOfTheHill,
+    RacingKings,
+    ThreeCheck,
+}
+
+impl LichessPerf {
+    /// Name of the perf type in the API
+    fn name(self) -> &'static str {
+        match self {
+            LichessPerf::UltraBullet => "ultraBullet",
+            LichessPerf::Bullet => "bullet",
+            LichessPerf::Blitz => "blitz",
+            LichessPerf::Rapid => "rapid",
+            LichessPerf::Classical => "classical",
+            LichessPerf::Correspondence => "correspondence",
+            LichessPerf::Chess960 => "chess960",
+            LichessPerf::Crazyhouse => "crazyhouse",
+            LichessPerf::Antichess => "antichess",
+            LichessPerf::Atomic => "atomic",
+            LichessPerf::Horde => "horde",
+            LichessPerf::KingOfTheHill => "kingOfTheHill",
+            LichessPerf::RacingKings => "racingKings",
+            LichessPerf::ThreeCheck => "threeCheck",
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Default, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct LichessImportOptions {
+    /// Kinds of games to import, all of them when empty
+    #[serde(default)]
+    pub perf_types: Vec<LichessPerf>,
+    /// First day of the games, as `YYYY-MM-DD`
+    pub since: Option<String>,
+    /// Last day of the games, as `YYYY-MM-DD`
+    pub until: Option<String>,
+    pub max_games: Option<u32>,
+    #[serde(default)]
+    pub rated_only: bool,
+    /// Also imports the games being played
+    #[serde(default)]
+    pub ongoing: bool,
+    /// Personal API token, which raises the rate limit and gives the unlisted games of
+    /// its owner. The one of the account logged in is used by default.
+    pub token: Option<String>,
+}
+
+/// Splits the PGN stream of lichess into games as its chunks arrive. A game ends at the
+/// blank line after its movetext.
+#[derive(Default)]
+struct PgnSplitter {
+    /// Start of a line that hasn't been received whole yet
+    line: Vec<u8>,
+    game: String,
+    in_movetext: bool,
+}
+
+impl PgnSplitter {
+    fn push(&mut self, bytes: &[u8]) -> Vec<String> {
+        let mut games = Vec::new();
+        for &byte in bytes {
+            if byte != b'\n' {
+                self.line.push(byte);
+                continue;
+            }
+            let line = String::from_utf8_lossy(&self.line).into_owned();
+            self.line.clear();
+            if let Some(game) = self.push_line(&line) {
+                games.push(game);
+            }
+        }
+        games
+    }
+
+    fn push_line(&mut self, line: &str) -> Option<String> {
+        let line = line.trim_end_matches('\r');
+        let mut finished = None;
+        if line.trim().is_empty() || (line.starts_with('[') && self.in_movetext) {
+            if self.in_movetext {
+                finished = Some(std::mem::take(&mut self.game));
+                self.in_movetext = false;
+            }
+            if line.trim().is_empty() {
+                if !self.game.is_empty() {
+                    self.game.push('\n');
+                }
+                return finished;
+            }
+        } else if !line.starts_with('[') {
+            self.in_movetext = true;
+        }
+        self.game.push_str(line);
+        self.game.push('\n');
+        finished
+    }
+
+    /// The game the stream ended in, if it has any moves
+    fn finish(mut self) -> Option<String> {
+        if !self.line.is_empty() {
+            let line = String::from_utf8_lossy(&self.line).into_owned();
+            if let Some(game) = self.push_line(&line) {
+                return Some(game);
+            }
+        }
+        self.in_movetext.then_some(self.game)
+    }
+}
+
+fn header<'a>(game: &'a str, tag: &str) -> Option<&'a str> {
+    game.lines().find_map(|line| {
+        line.strip_prefix('[')?
+            .strip_prefix(tag)?
+            .strip_prefix(" \"")?
+            .strip_suffix("\"]")
+    })
+}
+
+/// Milliseconds since the epoch the game started at, to the second, from its UTC headers
+fn game_start_ms(game: &str) -> Option<i64> {
+    let date = NaiveDate::parse_from_str(header(game, "UTCDate")?, "%Y.%m.%d").ok()?;
+    let time = NaiveTime::parse_from_str(header(game, "UTCTime")?, "%H:%M:%S").ok()?;
+    Some(date.and_time(time).and_utc().timestamp_millis())
+}
+
+/// Imports the games of the lichess player `username` into `target`, newest first.
+/// Games arrive as a stream the size of which lichess doesn't give, so the progress
+/// events count them and their rate. Rate limits are waited out, and a stream cut
+/// midway resumes from the last game received.
+#[tauri::command]
+#[specta::specta]
+pub async fn import_lichess_games(
+    id: String,
+    username: String,
+    options: LichessImportOptions,
+    target: ImportTarget,
+    app: tauri::AppHandle,
+    state: tauri::State<'_, AppState>,
+) -> Result<OnlineImportSummary, Error> {
+    ensure_online(&state)?;
+    let range = DateRange::parse(options.since.as_deref(), options.until.as_deref())?;
+    let mut sink = GameSink::new(id.clone(), target, &state, app)?;
+    let cancelled = Arc::new(AtomicBool::new(false));
+    state.online_imports.insert(id.clone(), cancelled.clone());
+    info!("Importing the lichess games of {username}");
+
+    let client = client()?;
+    let token = options.token.clone().or_else(|| lichess_token(&state));
+    let url = format!("{LICHESS_GAMES}/{username}");
+    let since = range.since_ms();
+    let mut until = range.until_ms();
+    // Games of the second the stream was cut at are asked for again after a resume
+    let mut received = HashSet::new();
+    let mut resumes = 0;
+    let result: Result<(), Error> = async {
+        loop {
+            let remaining = options
+                .max_games
+                .map(|max| max.saturating_sub(sink.games as u32));
+            if remaining == Some(0) {
+                return Ok(());
+            }
+            let request = || {
+                let mut query: Vec<(&str, String)> = vec![
+                    ("clocks", "true".to_string()),
+                    ("opening", "true".to_string()),
+                ];
+                if !options.perf_types.is_empty() {
+                    let perfs: Vec<_> = options.perf_types.iter().map(|p| p.name()).collect();
+                    query.push(("perfType", perfs.join(",")));
+                }
+                if let Some(since) = since {
+                    query.push(("since", since.to_string()));
+                }
+                if let Some(until) = until {
+                    query.push(("until", until.to_string()));
+                }
+                if let Some(remaining) = remaining {
+                    // Games received before a cut may come again, counting towards the limit
+                    query.push(("max", (remaining as usize + received.len()).to_string()));
+                }
+                if options.rated_only {
+                    query.push(("rated", "true".to_string()));
+                }
+                if options.ongoing {
+                    query.push(("ongoing", "true".to_string()));
+                }
+                let request = client
+                    .get(&url)
+                    .header("Accept", "application/x-chess-pgn")
+                    .query(&query);
+                match &token {
+                    Some(token) => request.bearer_auth(token),
+                    None => request,
+                }
+            };
+            let response = match send_with_backoff("lichess", request, &cancelled).await {
+                Err(Error::Reqwest(e)) if e.status() == Some(StatusCode::NOT_FOUND) => {
+                    return Err(Error::UnknownOnlineUser {
+                        username: username.clone(),
+                    })
+                }
+                response => response?,
+            };
+
+            let mut stream = response.bytes_stream();
+            let mut splitter = PgnSplitter::default();
+            let mut batch = HashSet::new();
+            let mut add = |game: String, sink: &mut GameSink| -> Result<bool, Error> {
+                let site = header(&game, "Site").unwrap_or_default().to_string();
+                if !received.contains(&site) && batch.insert(site) {
+                    if let Some(start) = game_start_ms(&game) {
+                        until = Some(start + 1000);
+                    }
+                    sink.push(&game)?;
+                }
+                Ok(options
+                    .max_games
+                    .is_some_and(|max| sink.games >= max as usize))
+            };
+            let mut cut = None;
+            while let Some(chunk) = stream.next().await {
+                if cancelled.load(Ordering::Relaxed) {
+                    return Ok(());
+                }
+                match chunk {
+                    Ok(bytes) => {
+                        for game in splitter.push(&bytes) {
+                            if add(game, &mut sink)? {
+                                return Ok(());
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        cut = Some(e);
+                        break;
+                    }
+                }
+            }
+            let Some(e) = cut else {
+                if let Some(game) = splitter.finish() {
+                    add(game, &mut sink)?;
+                }
+                return Ok(());
+            };
+            resumes += 1;
+            if resumes > MAX_RETRIES {
+                return Err(e.into());
+            }
+            info!("The lichess stream was cut ({e}), resuming");
+            received.extend(batch);
+        }
+    }
+    .await;
+    state.online_imports.remove(&id);
+    let flushed = sink.finish(cancelled.load(Ordering::Relaxed));
+    result?;
+    flushed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STREAM: &str = "[Event \"Rated blitz game\"]\n[Site \"https://lichess.org/a\"]\n\
+                          [UTCDate \"2024.01.31\"]\n[UTCTime \"10:00:05\"]\n\n\
+                          1. e4 e5 { [%clk 0:03:00] } 2. Nf3 1-0\n\n\n\
+                          [Event \"Casual rapid game\"]\n[Site \"https://lichess.org/b\"]\n\n\
+                          1. d4 d5 *\n\n\n";
+
+    #[test]
+    fn splits_streamed_games() {
+        let mut splitter = PgnSplitter::default();
+        let mut games = Vec::new();
+        for chunk in STREAM.as_bytes().chunks(7) {
+            games.extend(splitter.push(chunk));
+        }
+        assert_eq!(splitter.finish(), None);
+        assert_eq!(games.len(), 2);
+        assert!(games[0].starts_with("[Event \"Rated blitz game\"]"));
+        assert!(games[0].trim_end().ends_with("2. Nf3 1-0"));
+        assert_eq!(
+            games[1],
+            "[Event \"Casual rapid game\"]\n[Site \"https://lichess.org/b\"]\n\n1. d4 d5 *\n"
+        );
+
+        let mut cut = PgnSplitter::default();
+        assert!(cut.push(b"[Site \"x\"]\n\n1. e4 e5").is_empty());
+        assert_eq!(cut.finish().as_deref(), Some("[Site \"x\"]\n\n1. e4 e5\n"));
+    }
+
+    #[test]
+    fn reads_game_starts() {
+        assert_eq!(header(STREAM, "Site"), Some("https://lichess.org/a"));
+        assert_eq!(game_start_ms(STREAM), Some(1_706_695_205_000));
+        assert_eq!(game_start_ms("[Site \"x\"]\n\n1. e4 *"), None);
+    }
+}
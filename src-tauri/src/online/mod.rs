@@ -0,0 +1,423 @@
+mod bot;
+mod broadcast;
+mod chesscom;
+mod cloud;
+mod explorer;
+mod lichess;
+mod study;
+mod twic;
+
+use std::{
+    collections::HashSet,
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::PathBuf,
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, Instant},
+};
+
+use chrono::NaiveDate;
+use log::info;
+use reqwest::{RequestBuilder, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use tauri::{AppHandle, Manager, State};
+use tauri_specta::Event;
+
+use crate::{
+    db::{add_games, site_names},
+    error::Error,
+    oauth::lichess_token,
+    AppState,
+};
+
+pub use self::bot::{start_bot, stop_bot, BotStatus};
+pub use self::broadcast::{stop_broadcast, watch_broadcast, BroadcastUpdate};
+pub use self::chesscom::{get_chesscom_daily_games, import_chesscom_games};
+pub use self::cloud::{cloud_eval, CloudEvalOptions};
+pub use self::explorer::get_lichess_explorer;
+pub use self::lichess::import_lichess_games;
+pub use self::study::import_lichess_study;
+pub use self::twic::{update_reference_db, TwicProgress};
+
+/// Sent with every request, services like chess.com refusing the ones without it
+const USER_AGENT: &str = concat!("en-croissant/", env!("CARGO_PKG_VERSION"));
+
+/// Times a request is retried after being rate limited or failing midway
+const MAX_RETRIES: u32 = 5;
+
+/// Wait after a first rate limit when the service doesn't say how long, the minute
+/// lichess asks for
+const RATE_LIMIT_WAIT: Duration = Duration::from_secs(60);
+
+/// Games added to a database at once
+const DATABASE_CHUNK: usize = 100;
+
+/// Games between progress events
+const PROGRESS_GAMES: usize = 50;
+
+/// Where the games of an online import go
+#[derive(Deserialize, Debug, Clone, Type)]
+#[serde(tag = "t", content = "c")]
+pub enum ImportTarget {
+    /// A PGN file, which the games are appended to
+    Pgn(PathBuf),
+    /// A database, created with `title` when it doesn't exist yet
+    Database { path: PathBuf, title: String },
+}
+
+impl ImportTarget {
+    fn path(&self) -> &PathBuf {
+        match self {
+            ImportTarget::Pgn(path) => path,
+            ImportTarget::Database { path, .. } => path,
+        }
+    }
+
+    /// Sites of the games already there, which are the URLs of the games of online
+    /// services
+    fn site_names(&self, state: &State<AppState>) -> Result<HashSet<String>, Error> {
+        match self {
+            ImportTarget::Pgn(path) if path.exists() => Ok(BufReader::new(File::open(path)?)
+                .lines()
+                .map_while(Result::ok)
+                .filter_map(|line| {
+                    let site = line.strip_prefix("[Site \"")?.strip_suffix("\"]")?;
+                    Some(site.to_string())
+                })
+                .collect()),
+            ImportTarget::Pgn(_) => Ok(HashSet::new()),
+            ImportTarget::Database { path, .. } => site_names(state, path),
+        }
+    }
+}
+
+/// Monthly archive of chess.com being imported
+#[derive(Clone, Debug, Type, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ArchiveProgress {
+    /// `YYYY-MM`
+    pub month: String,
+    /// Archives imported before this one
+    pub index: usize,
+    pub total: usize,
+}
+
+#[derive(Clone, Type, Serialize, Event)]
+#[serde(rename_all = "camelCase")]
+pub struct OnlineImportProgress {
+    pub id: String,
+    /// Games received so far, the services not saying how many there are in total
+    pub games: usize,
+    /// Games left out as variants that can't be read
+    pub skipped: usize,
+    /// Games left out as they were imported before
+    pub duplicates: usize,
+    pub archive: Option<ArchiveProgress>,
+    pub elapsed_ms: u64,
+    pub games_per_second: f64,
+    pub finished: bool,
+}
+
+#[derive(Serialize, Debug, Clone, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct OnlineImportSummary {
+    pub id: String,
+    pub games: usize,
+    pub skipped: usize,
+    pub duplicates: usize,
+    pub elapsed_ms: u64,
+    /// Stopped with `cancel_online_import`, the games received until then being kept
+    pub cancelled: bool,
+}
+
+/// First and last days of the games to import, both included
+struct DateRange {
+    since: Option<NaiveDate>,
+    until: Option<NaiveDate>,
+}
+
+impl DateRange {
+    /// Reads the `YYYY-MM-DD` dates of the options
+    fn parse(since: Option<&str>, until: Option<&str>) -> Result<Self, Error> {
+        let parse = |date: Option<&str>| {
+            date.map(|date| {
+                NaiveDate::parse_from_str(date, "%Y-%m-%d").map_err(|_| Error::InvalidDate {
+                    date: date.to_string(),
+                })
+            })
+            .transpose()
+        };
+        Ok(DateRange {
+            since: parse(since)?,
+            until: parse(until)?,
+        })
+    }
+
+    /// Milliseconds since the epoch of the start of the first day
+    fn since_ms(&self) -> Option<i64> {
+        self.since
+            .and_then(|date| date.and_hms_opt(0, 0, 0))
+            .map(|time| time.and_utc().timestamp_millis())
+    }
+
+    /// Milliseconds since the epoch of the end of the last day, excluded
+    fn until_ms(&self) -> Option<i64> {
+        self.until
+            .and_then(|date| date.succ_opt())
+            .and_then(|date| date.and_hms_opt(0, 0, 0))
+            .map(|time| time.and_utc().timestamp_millis())
+    }
+}
+
+/// Whether network calls to online services are disabled
+pub fn is_offline(state: &AppState) -> bool {
+    state.offline.load(Ordering::Relaxed)
+}
+
+pub fn ensure_online(state: &AppState) -> Result<(), Error> {
+    if is_offline(state) {
+        return Err(Error::OfflineMode);
+    }
+    Ok(())
+}
+
+/// Sends the token of the lichess account logged in with `request`, if there is one
+fn with_lichess_token(state: &AppState, request: RequestBuilder) -> RequestBuilder {
+    match lichess_token(state) {
+        Some(token) => request.bearer_auth(token),
+        None => request,
+    }
+}
+
+fn client() -> Result<reqwest::Client, Error> {
+    Ok(reqwest::Client::builder().user_agent(USER_AGENT).build()?)
+}
+
+/// Sleeps for `duration`, waking up early when the import is cancelled
+async fn wait(duration: Duration, cancelled: &AtomicBool) {
+    let start = Instant::now();
+    while start.elapsed() < duration && !cancelled.load(Ordering::Relaxed) {
+        tokio::time::sleep(Duration::from_secs(1).min(duration - start.elapsed())).await;
+    }
+}
+
+/// Sends the request `request` builds, waiting out rate limits and server errors: for
+/// the `Retry-After` the service gives, a minute otherwise, doubling at each retry
+async fn send_with_backoff(
+    service: &str,
+    request: impl Fn() -> RequestBuilder,
+    cancelled: &AtomicBool,
+) -> Result<Response, Error> {
+    let mut delay = RATE_LIMIT_WAIT;
+    for _ in 0..MAX_RETRIES {
+        let response = request().send().await?;
+        let status = response.status();
+        if status != StatusCode::TOO_MANY_REQUESTS && !status.is_server_error() {
+            return Ok(response.error_for_status()?);
+        }
+        let retry_after = response
+            .headers()
+            .get("Retry-After")
+            .and_then(|value| value.to_str().ok()?.parse().ok())
+            .map(Duration::from_secs);
+        let duration = retry_after.unwrap_or(delay);
+        info!(
+            "{service} answered {status}, retrying in {}s",
+            duration.as_secs()
+        );
+        wait(duration, cancelled).await;
+        if cancelled.load(Ordering::Relaxed) {
+            break;
+        }
+        delay *= 2;
+    }
+    Err(Error::RateLimited {
+        service: service.to_string(),
+    })
+}
+
+enum Writer {
+    Pgn(BufWriter<File>),
+    /// Games waiting to be added to the database
+    Database {
+        path: PathBuf,
+        title: String,
+        pgn: String,
+        games: usize,
+    },
+}
+
+/// Writes the games of an import as they arrive and reports the progress
+struct GameSink<'a> {
+    id: String,
+    writer: Writer,
+    state: &'a State<'a, AppState>,
+    app: AppHandle,
+    start: Instant,
+    games: usize,
+    skipped: usize,
+    /// Sites of the games imported, to leave out the ones imported before
+    sites: HashSet<String>,
+    duplicates: usize,
+    archive: Option<ArchiveProgress>,
+}
+
+impl<'a> GameSink<'a> {
+    fn new(
+        id: String,
+        target: ImportTarget,
+        state: &'a State<'a, AppState>,
+        app: AppHandle,
+    ) -> Result<Self, Error> {
+        if !app.fs_scope().is_allowed(target.path()) {
+            return Err(Error::ForbiddenPath);
+        }
+        let writer = match target {
+            ImportTarget::Pgn(path) => Writer::Pgn(BufWriter::new(
+                OpenOptions::new().create(true).append(true).open(path)?,
+            )),
+            ImportTarget::Database { path, title } => Writer::Database {
+                path,
+                title,
+                pgn: String::new(),
+                games: 0,
+            },
+        };
+        Ok(GameSink {
+            id,
+            writer,
+            state,
+            app,
+            start: Instant::now(),
+            games: 0,
+            skipped: 0,
+            sites: HashSet::new(),
+            duplicates: 0,
+            archive: None,
+        })
+    }
+
+    fn push(&mut self, game: &str) -> Result<(), Error> {
+        let game = game.trim();
+        if game.is_empty() {
+            return Ok(());
+        }
+        match &mut self.writer {
+            Writer::Pgn(file) => writeln!(file, "{game}\n")?,
+            Writer::Database { pgn, games, .. } => {
+                pgn.push_str(game);
+                pgn.push_str("\n\n");
+                *games += 1;
+                if *games >= DATABASE_CHUNK {
+                    self.flush()?;
+                }
+            }
+        }
+        self.games += 1;
+        if self.games % PROGRESS_GAMES == 0 {
+            self.progress(false);
+        }
+        Ok(())
+    }
+
+    /// Leaves out of `push_new` the games of the sites that are already in `target`,
+    /// which must be the target of the sink
+    fn skip_known_sites(&mut self, target: &ImportTarget) -> Result<(), Error> {
+        self.sites = target.site_names(self.state)?;
+        Ok(())
+    }
+
+    /// Pushes a game unless one from the same site, its URL, was imported before
+    fn push_new(&mut self, site: &str, game: &str) -> Result<(), Error> {
+        if !self.sites.insert(site.to_string()) {
+            self.duplicates += 1;
+            return Ok(());
+        }
+        self.push(game)
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        match &mut self.writer {
+            Writer::Pgn(file) => file.flush()?,
+            Writer::Database {
+                path,
+                title,
+                pgn,
+                games,
+            } => {
+                if *games > 0 {
+                    add_games(self.state, path, title, pgn)?;
+                    pgn.clear();
+                    *games = 0;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn progress(&self, finished: bool) {
+        let elapsed = self.start.elapsed();
+        let _ = OnlineImportProgress {
+            id: self.id.clone(),
+            games: self.games,
+            skipped: self.skipped,
+            duplicates: self.duplicates,
+            archive: self.archive.clone(),
+            elapsed_ms: elapsed.as_millis() as u64,
+            games_per_second: self.games as f64 / elapsed.as_secs_f64().max(0.001),
+            finished,
+        }
+        .emit_all(&self.app);
+    }
+
+    fn finish(mut self, cancelled: bool) -> Result<OnlineImportSummary, Error> {
+        self.flush()?;
+        self.progress(true);
+        Ok(OnlineImportSummary {
+            id: self.id,
+            games: self.games,
+            skipped: self.skipped,
+            duplicates: self.duplicates,
+            elapsed_ms: self.start.elapsed().as_millis() as u64,
+            cancelled,
+        })
+    }
+}
+
+/// Disables the calls to online services like the lichess cloud evaluation, explorer
+/// and tablebase, the game imports, or the downloads of engines, tablebases and the
+/// FIDE players, for privacy
+#[tauri::command]
+#[specta::specta]
+pub fn set_offline_mode(offline: bool, state: tauri::State<'_, AppState>) {
+    state.offline.store(offline, Ordering::Relaxed);
+}
+
+/// Stops the online import `id`, keeping the games received so far
+#[tauri::command]
+#[specta::specta]
+pub fn cancel_online_import(id: String, state: tauri::State<'_, AppState>) {
+    if let Some(cancelled) = state.online_imports.get(&id) {
+        cancelled.store(true, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_date_ranges() {
+        let range = DateRange::parse(Some("2024-01-31"), Some("2024-02-01")).unwrap();
+        assert_eq!(range.since_ms(), Some(1_706_659_200_000));
+        assert_eq!(range.until_ms(), Some(1_706_832_000_000));
+
+        let open = DateRange::parse(None, None).unwrap();
+        assert_eq!((open.since_ms(), open.until_ms()), (None, None));
+        assert!(matches!(
+            DateRange::parse(Some("2024.01.31"), None),
+            Err(Error::InvalidDate { date }) if date == "2024.01.31"
+        ));
+    }
+}
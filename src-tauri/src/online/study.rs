@@ -0,0 +1,173 @@
+use std::{fs::OpenOptions, io::Write, path::PathBuf, sync::atomic::AtomicBool};
+
+use log::info;
+use reqwest::StatusCode;
+use serde::Serialize;
+use specta::Type;
+use tauri::Manager;
+
+use super::{client, ensure_online, send_with_backoff, with_lichess_token};
+use crate::{
+    error::Error,
+    oauth::lichess_token,
+    pgn::{parse_games, GameTree},
+    AppState,
+};
+
+const LICHESS_STUDY: &str = "https://lichess.org/api/study";
+
+/// A chapter of a lichess study, with the comments, arrows and circles of its author
+#[derive(Serialize, Debug, Clone, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct StudyChapter {
+    pub name: String,
+    pub tree: GameTree,
+}
+
+/// Id of the study `study` names, either the id itself or the URL of the study or of
+/// one of its chapters, like `https://lichess.org/study/4hZhYhYh/5nYhXhXh`
+fn study_id(study: &str) -> Option<&str> {
+    let study = study.trim().trim_end_matches('/');
+    let id = match study.split_once("/study/") {
+        Some((_, path)) => path.split(['/', '#', '?']).next()?,
+        None => study,
+    };
+    let valid = id.len() == 8 && id.chars().all(|c| c.is_ascii_alphanumeric());
+    valid.then_some(id)
+}
+
+/// Name of the chapter from its `ChapterName` tag, or the `Event` tag older exports
+/// have as `Study: Chapter`
+fn chapter_name(tree: &GameTree, index: usize) -> String {
+    tree.header("ChapterName")
+        .or_else(|| {
+            let event = tree.header("Event")?;
+            Some(event.split_once(": ").map_or(event, |(_, chapter)| chapter))
+        })
+        .map_or_else(|| format!("Chapter {}", index + 1), str::to_string)
+}
+
+fn chapters(pgn: &str) -> Result<Vec<StudyChapter>, Error> {
+    Ok(parse_games(pgn)?
+        .into_iter()
+        .enumerate()
+        .map(|(index, tree)| StudyChapter {
+            name: chapter_name(&tree, index),
+            tree,
+        })
+        .collect())
+}
+
+/// Downloads every chapter of the lichess study `study`, an id or a URL, in the order
+/// of the study. Private studies need the account logged in to be able to see them.
+/// The chapters are also appended to the PGN file `save_to` when there is one, like a
+/// repertoire.
+#[tauri::command]
+#[specta::specta]
+pub async fn import_lichess_study(
+    study: String,
+    save_to: Option<PathBuf>,
+    app: tauri::AppHandle,
+    state: tauri::State<'_, AppState>,
+) -> Result<Vec<StudyChapter>, Error> {
+    ensure_online(&state)?;
+    let id = study_id(&study)
+        .ok_or_else(|| Error::UnknownStudy { id: study.clone() })?
+        .to_string();
+    if let Some(path) = &save_to {
+        if !app.fs_scope().is_allowed(path) {
+            return Err(Error::ForbiddenPath);
+        }
+    }
+    info!("Importing the lichess study {id}");
+
+    let client = client()?;
+    let url = format!("{LICHESS_STUDY}/{id}.pgn");
+    let request = || {
+        let request = client.get(&url).query(&[
+            ("comments", "true"),
+            ("variations", "true"),
+            ("clocks", "true"),
+        ]);
+        with_lichess_token(&state, request)
+    };
+    let response = match send_with_backoff("lichess", request, &AtomicBool::new(false)).await {
+        Err(Error::Reqwest(e)) => {
+            return Err(match e.status() {
+                Some(StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN) => {
+                    Error::PrivateStudy { id }
+                }
+                // Lichess hides private studies from those who aren't logged in
+                Some(StatusCode::NOT_FOUND) if lichess_token(&state).is_none() => {
+                    Error::PrivateStudy { id }
+                }
+                Some(StatusCode::NOT_FOUND) => Error::UnknownStudy { id },
+                _ => e.into(),
+            });
+        }
+        response => response?,
+    };
+    let pgn = response.text().await?;
+    let chapters = chapters(&pgn)?;
+
+    if let Some(path) = save_to {
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        writeln!(file, "{}\n", pgn.trim())?;
+    }
+    Ok(chapters)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_study_ids() {
+        assert_eq!(study_id("4hZhYhYh"), Some("4hZhYhYh"));
+        assert_eq!(
+            study_id("https://lichess.org/study/4hZhYhYh/5nYhXhXh#12"),
+            Some("4hZhYhYh")
+        );
+        assert_eq!(
+            study_id(" https://lichess.org/study/4hZhYhYh/ "),
+            Some("4hZhYhYh")
+        );
+        assert_eq!(study_id("https://lichess.org/abcdefgh1234"), None);
+        assert_eq!(study_id("not an id"), None);
+    }
+
+    #[test]
+    fn keeps_chapter_names_and_shapes() {
+        let chapters = chapters(
+            r#"[Event "Openings: Italian"]
+[ChapterName "Italian"]
+
+{ [%csl Gd5] The center } 1. e4 { [%cal Ge2e4,Rd7d5] } 1... e5 2. Nf3 *
+
+[Event "Openings: Scotch"]
+
+1. e4 e5 2. Nf3 Nc6 3. d4 { [%csl Rd4][%cal Bd1d4] Central } *
+"#,
+        )
+        .unwrap();
+        assert_eq!(chapters.len(), 2);
+        assert_eq!(chapters[0].name, "Italian");
+        assert_eq!(chapters[1].name, "Scotch");
+
+        let italian = &chapters[0].tree;
+        assert_eq!(italian.comments, ["The center"]);
+        assert_eq!(italian.shapes[0].from, "d5");
+        assert_eq!(italian.shapes[0].to, None);
+        let arrows = &italian.moves[0].shapes;
+        assert_eq!(arrows.len(), 2);
+        assert_eq!(
+            (arrows[1].from.as_str(), arrows[1].to.as_deref()),
+            ("d7", Some("d5"))
+        );
+        assert!(italian.moves[0].comments.is_empty());
+
+        let d4 = &chapters[1].tree.moves[4];
+        assert_eq!(d4.comments, ["Central"]);
+        assert_eq!(d4.shapes.len(), 2);
+    }
+}
@@ -0,0 +1,218 @@
+use std::{
+    io::Cursor,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use log::info;
+use serde::Serialize;
+use specta::Type;
+use tauri::Manager;
+use tauri_specta::Event;
+
+use super::{client, ensure_online, send_with_backoff, wait};
+use crate::{
+    db::{append_games, database_info_value},
+    error::Error,
+    AppState,
+};
+
+const TWIC_PAGE: &str = "https://theweekinchess.com/twic";
+const TWIC_ZIPS: &str = "https://theweekinchess.com/zips";
+
+/// Info of the database recording the last issue imported
+const TWIC_ISSUE_INFO: &str = "TwicIssue";
+
+/// Wait between two downloads, TWIC being a small site run by one person
+const DOWNLOAD_PACING: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Type, Serialize, Event)]
+#[serde(rename_all = "camelCase")]
+pub struct TwicProgress {
+    pub id: String,
+    /// Issue being downloaded, or the last one imported once finished
+    pub issue: u32,
+    /// Issues imported before this one
+    pub index: usize,
+    pub total: usize,
+    /// Games added so far
+    pub games: usize,
+    pub finished: bool,
+}
+
+#[derive(Serialize, Debug, Clone, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct TwicSummary {
+    pub id: String,
+    /// Issues imported, in order
+    pub issues: Vec<u32>,
+    pub games: usize,
+    /// Games of the issues already in the database
+    pub duplicates: usize,
+    /// Last issue in the database after the update, which the next one starts after
+    pub last_issue: Option<u32>,
+    pub elapsed_ms: u64,
+    pub cancelled: bool,
+}
+
+/// Number of the latest issue linked from the TWIC page, whose zips are named like
+/// `twic1520g.zip`
+fn latest_issue(page: &str) -> Option<u32> {
+    page.match_indices("twic")
+        .filter_map(|(start, _)| {
+            let rest = &page[start + 4..];
+            let digits = rest.find(|c: char| !c.is_ascii_digit())?;
+            if !rest[digits..].starts_with("g.zip") {
+                return None;
+            }
+            rest[..digits].parse().ok()
+        })
+        .max()
+}
+
+/// Reads the PGN of an issue out of its zip
+fn issue_pgn(zip: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut archive = zip::ZipArchive::new(Cursor::new(zip))?;
+    let name = archive
+        .file_names()
+        .find(|name| name.to_lowercase().ends_with(".pgn"))
+        .map(str::to_string)
+        .ok_or(zip::result::ZipError::FileNotFound)?;
+    let mut pgn = Vec::new();
+    std::io::copy(&mut archive.by_name(&name)?, &mut pgn)?;
+    Ok(pgn)
+}
+
+/// Brings the reference database `db` up to date with The Week in Chess, importing every
+/// issue after the last one it has, or from `from_issue` when it has none, up to the
+/// latest. Each issue is imported in its own transaction with the number it's at, so
+/// an update stopped midway, even by closing the app, resumes with the issue it was
+/// on. Games already in the database are skipped, and downloads are a few seconds
+/// apart. The database is created when it doesn't exist yet, and the update stops at
+/// the next issue with `cancel_online_import(id)`.
+#[tauri::command]
+#[specta::specta]
+pub async fn update_reference_db(
+    id: String,
+    db: PathBuf,
+    from_issue: u32,
+    app: tauri::AppHandle,
+    state: tauri::State<'_, AppState>,
+) -> Result<TwicSummary, Error> {
+    ensure_online(&state)?;
+    if !app.fs_scope().is_allowed(&db) {
+        return Err(Error::ForbiddenPath);
+    }
+    let stored: Option<u32> =
+        database_info_value(&state, &db, TWIC_ISSUE_INFO)?.and_then(|issue| issue.parse().ok());
+    let first = stored.map_or(from_issue, |issue| (issue + 1).max(from_issue));
+
+    let client = client()?;
+    let cancelled = Arc::new(AtomicBool::new(false));
+    state.online_imports.insert(id.clone(), cancelled.clone());
+    let start = Instant::now();
+    let mut summary = TwicSummary {
+        id: id.clone(),
+        issues: Vec::new(),
+        games: 0,
+        duplicates: 0,
+        last_issue: stored,
+        elapsed_ms: 0,
+        cancelled: false,
+    };
+    let result: Result<(), Error> = async {
+        let page = send_with_backoff("TWIC", || client.get(TWIC_PAGE), &cancelled)
+            .await?
+            .text()
+            .await?;
+        let latest = latest_issue(&page).ok_or(Error::NoTwicIssue)?;
+        let total = (latest + 1).saturating_sub(first) as usize;
+        info!("Importing the TWIC issues {first} to {latest}");
+        for (index, issue) in (first..=latest).enumerate() {
+            if cancelled.load(Ordering::Relaxed) {
+                return Ok(());
+            }
+            let _ = TwicProgress {
+                id: id.clone(),
+                issue,
+                index,
+                total,
+                games: summary.games,
+                finished: false,
+            }
+            .emit_all(&app);
+            if index > 0 {
+                wait(DOWNLOAD_PACING, &cancelled).await;
+            }
+            let url = format!("{TWIC_ZIPS}/twic{issue}g.zip");
+            let zip = send_with_backoff("TWIC", || client.get(&url), &cancelled)
+                .await?
+                .bytes()
+                .await?;
+            let pgn = issue_pgn(&zip)?;
+            let imported = match append_games(
+                &state,
+                &db,
+                "The Week in Chess",
+                pgn.as_slice(),
+                (TWIC_ISSUE_INFO, &issue.to_string()),
+                &cancelled,
+            ) {
+                Err(Error::ImportCancelled) => return Ok(()),
+                imported => imported?,
+            };
+            summary.issues.push(issue);
+            summary.games += imported.imported;
+            summary.duplicates += imported.duplicates;
+            summary.last_issue = Some(issue);
+        }
+        Ok(())
+    }
+    .await;
+    state.online_imports.remove(&id);
+    summary.cancelled = cancelled.load(Ordering::Relaxed);
+    summary.elapsed_ms = start.elapsed().as_millis() as u64;
+    let _ = TwicProgress {
+        id,
+        issue: summary.last_issue.unwrap_or_default(),
+        index: summary.issues.len(),
+        total: summary.issues.len(),
+        games: summary.games,
+        finished: true,
+    }
+    .emit_all(&app);
+    result?;
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Write;
+
+    #[test]
+    fn finds_the_latest_issue() {
+        let page = r#"<a href="https://theweekinchess.com/zips/twic1519g.zip">PGN</a>
+            <a href="https://theweekinchess.com/html/twic1520.html">1520</a>
+            <a href="https://theweekinchess.com/zips/twic1520g.zip">PGN</a>
+            <a href="/zips/twic999g.zip">PGN</a>"#;
+        assert_eq!(latest_issue(page), Some(1520));
+        assert_eq!(latest_issue("<p>twic</p>"), None);
+    }
+
+    #[test]
+    fn reads_issue_zips() {
+        let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        zip.start_file("twic1520.pgn", zip::write::FileOptions::default())
+            .unwrap();
+        zip.write_all(b"[Event \"Tata Steel\"]\n\n1. e4 1-0\n")
+            .unwrap();
+        let zip = zip.finish().unwrap().into_inner();
+        assert!(issue_pgn(&zip).unwrap().starts_with(b"[Event"));
+    }
+}
@@ -1,6 +1,9 @@
+use std::{collections::HashMap, num::NonZeroU32};
+
 use log::info;
 use serde::{ser::SerializeStruct, Deserialize, Serialize};
 use shakmaty::{fen::Fen, san::San, Chess, EnPassantMode, Position, Setup};
+use specta::Type;
 
 use lazy_static::lazy_static;
 use strsim::{jaro_winkler, sorensen_dice};
@@ -74,6 +77,81 @@ pub fn get_opening_from_setup(setup: Setup) -> Result<String, Error> {
         .ok_or_else(|| Error::NoOpeningFound)
 }
 
+/// ECO codes of the openings whose name contains `name`, ignoring case
+pub fn opening_ecos(name: &str) -> Vec<String> {
+    let name = name.to_lowercase();
+    let mut ecos: Vec<String> = OPENINGS
+        .iter()
+        .filter(|o| o.pgn.is_some() && o.name.to_lowercase().contains(&name))
+        .map(|o| o.eco.clone())
+        .collect();
+    ecos.sort();
+    ecos.dedup();
+    ecos
+}
+
+/// Longer than any line of the opening table, so later positions aren't looked up
+pub const MAX_BOOK_PLIES: usize = 40;
+
+#[derive(Serialize, Debug, Clone, PartialEq, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct OpeningClassification {
+    pub eco: String,
+    pub name: String,
+    /// Ply of the deepest position of the game in the opening table
+    pub last_book_ply: usize,
+}
+
+impl Opening {
+    pub fn eco(&self) -> &str {
+        &self.eco
+    }
+}
+
+/// Move counters differ between transpositions, so they aren't part of the key
+fn position_key(mut setup: Setup) -> Setup {
+    setup.halfmoves = 0;
+    setup.fullmoves = NonZeroU32::MIN;
+    setup
+}
+
+/// Opening of the table with the same position, regardless of the move order
+pub fn find_opening(setup: Setup) -> Option<&'static Opening> {
+    POSITIONS
+        .get(&position_key(setup))
+        .map(|&index| &OPENINGS[index])
+}
+
+/// Classifies a game by the deepest of its positions found in the opening table, so a
+/// game that leaves the book keeps the name of the last known position.
+pub fn classify_setups(setups: impl IntoIterator<Item = Setup>) -> Option<OpeningClassification> {
+    let mut deepest = None;
+    for (ply, setup) in setups.into_iter().enumerate().take(MAX_BOOK_PLIES + 1) {
+        if let Some(opening) = find_opening(setup) {
+            deepest = Some((ply, opening));
+        }
+    }
+    deepest.map(|(ply, opening)| OpeningClassification {
+        eco: opening.eco.clone(),
+        name: opening.name.clone(),
+        last_book_ply: ply,
+    })
+}
+
+/// Classifies the game reached by `moves` in SAN from the starting position
+#[tauri::command]
+#[specta::specta]
+pub fn classify_opening(moves: Vec<String>) -> Result<Option<OpeningClassification>, Error> {
+    let mut pos = Chess::default();
+    let mut setups = vec![pos.clone().into_setup(EnPassantMode::Legal)];
+    for san in moves.iter().take(MAX_BOOK_PLIES) {
+        let m = san.parse::<San>()?.to_move(&pos)?;
+        pos.play_unchecked(&m);
+        setups.push(pos.clone().into_setup(EnPassantMode::Legal));
+    }
+    Ok(classify_setups(setups))
+}
+
 #[tauri::command]
 pub async fn search_opening_name(query: String) -> Result<Vec<Opening>, Error> {
     let lower_query = query.to_lowercase();
@@ -154,6 +232,19 @@ lazy_static! {
         }
         positions
     };
+    /// Index in `OPENINGS` of each position of the ECO table, keyed by
+    /// [`position_key`]. The first opening wins when several share a position.
+    static ref POSITIONS: HashMap<Setup, usize> = {
+        let mut positions = HashMap::new();
+        for (index, opening) in OPENINGS.iter().enumerate() {
+            if opening.pgn.is_some() {
+                positions
+                    .entry(position_key(opening.setup.clone()))
+                    .or_insert(index);
+            }
+        }
+        positions
+    };
 }
 
 #[cfg(test)]
@@ -167,4 +258,35 @@ mod tests {
                 .unwrap();
         assert_eq!(opening, "Bongcloud Attack");
     }
+
+    fn classify(moves: &str) -> Option<OpeningClassification> {
+        classify_opening(moves.split_whitespace().map(str::to_string).collect()).unwrap()
+    }
+
+    #[test]
+    fn test_classify_opening() {
+        let ruy = classify("e4 e5 Nf3 Nc6 Bb5").unwrap();
+        assert_eq!(ruy.eco, "C60");
+        assert_eq!(ruy.last_book_ply, 5);
+
+        // Same position as 1. d4 d5 2. Nf3
+        let transposed = classify("Nf3 d5 d4").unwrap();
+        let direct = classify("d4 d5 Nf3").unwrap();
+        assert_eq!(transposed.name, direct.name);
+
+        let deviation = classify("e4 e5 Nf3 Nc6 Bb5 a6 h4 h5 a4").unwrap();
+        assert_eq!(deviation.last_book_ply, 6);
+        assert_eq!(deviation.name, "Ruy Lopez: Morphy Defense");
+
+        assert_eq!(classify(""), None);
+    }
+
+    #[test]
+    fn book_fits_in_lookup_depth() {
+        for opening in OPENINGS.iter() {
+            let ply =
+                (opening.setup.fullmoves.get() as usize - 1) * 2 + opening.setup.turn.fold_wb(0, 1);
+            assert!(ply <= MAX_BOOK_PLIES);
+        }
+    }
 }
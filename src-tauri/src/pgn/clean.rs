@@ -0,0 +1,170 @@
+use serde::Deserialize;
+use specta::Type;
+
+use super::{
+    tree::{parse_games, GameTree, PgnNode},
+    writer::{strip_command, write_tree, WriteOptions},
+};
+use crate::error::Error;
+
+/// Annotations to remove from a game. Moves are never changed.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, Type)]
+#[serde(rename_all = "camelCase", default)]
+pub struct CleanOptions {
+    pub comments: bool,
+    pub nags: bool,
+    /// Whether to drop every variation and keep only the main line
+    pub variations: bool,
+    /// Whether to remove `[%eval]` commands but keep the rest of their comments
+    pub evals: bool,
+    /// Whether to remove the clock and move time of each move
+    pub clocks: bool,
+}
+
+impl CleanOptions {
+    fn comments(&self, comments: &mut Vec<String>) {
+        if self.comments {
+            comments.clear();
+            return;
+        }
+        for comment in comments.iter_mut() {
+            if self.evals {
+                *comment = strip_command(comment, "eval");
+            }
+            if self.clocks {
+                *comment = strip_command(&strip_command(comment, "clk"), "emt");
+            }
+        }
+        comments.retain(|comment| !comment.is_empty());
+    }
+
+    fn line(&self, line: &mut [PgnNode]) {
+        for node in line {
+            self.comments(&mut node.starting_comments);
+            self.comments(&mut node.comments);
+            if self.comments {
+                node.shapes.clear();
+            }
+            if self.nags {
+                node.nags.clear();
+            }
+            if self.clocks {
+                node.clock = None;
+                node.elapsed = None;
+            }
+            if self.variations {
+                node.variations.clear();
+            }
+            for variation in &mut node.variations {
+                self.line(variation);
+            }
+        }
+    }
+}
+
+/// Removes the annotations picked in `options`, keeping the tags and every move
+pub fn clean_game(mut tree: GameTree, options: CleanOptions) -> GameTree {
+    options.comments(&mut tree.comments);
+    if options.comments {
+        tree.shapes.clear();
+    }
+    options.line(&mut tree.moves);
+    tree
+}
+
+/// Cleans every game of a PGN and writes them back as PGN
+#[tauri::command]
+#[specta::specta]
+pub fn clean_pgn(pgn: String, options: CleanOptions) -> Result<String, Error> {
+    let games: Vec<String> = parse_games(&pgn)?
+        .into_iter()
+        .map(|tree| write_tree(&clean_game(tree, options), WriteOptions::default()))
+        .collect();
+    Ok(games.join("\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::pgn::parse_game;
+
+    const ANNOTATED: &str = r#"[Event "Annotated"]
+[Result "1-0"]
+
+{ Start } 1. e4 { [%eval 0.3] [%clk 0:03:00] Best by test } 1... e5 $1 (1... c5
+{ Sicilian } 2. Nf3 $14 { [%eval 0.4] }) 2. Nf3 { [%clk 0:02:58] } Nc6 $2 { [%eval 1.2] }
+3. Bb5 $16 { [%emt 0:00:05] } 1-0
+"#;
+
+    fn all() -> CleanOptions {
+        CleanOptions {
+            comments: true,
+            nags: true,
+            variations: true,
+            evals: true,
+            clocks: true,
+        }
+    }
+
+    #[test]
+    fn strips_everything() {
+        let tree = parse_game(ANNOTATED).unwrap();
+        let cleaned = clean_game(tree.clone(), all());
+        let written = write_tree(&cleaned, WriteOptions::default());
+        assert!(written.ends_with("[Result \"1-0\"]\n\n1. e4 e5 2. Nf3 Nc6 3. Bb5 1-0\n"));
+        assert!(written.starts_with("[Event \"Annotated\"]\n"));
+
+        // The moves still replay to the same positions
+        let replayed = parse_game(&written).unwrap();
+        assert_eq!(replayed.moves.len(), tree.moves.len());
+        for (replayed, original) in replayed.moves.iter().zip(&tree.moves) {
+            assert_eq!(replayed.uci, original.uci);
+            assert_eq!(replayed.fen, original.fen);
+        }
+    }
+
+    #[test]
+    fn strips_only_evals() {
+        let options = CleanOptions {
+            evals: true,
+            ..Default::default()
+        };
+        let tree = clean_game(parse_game(ANNOTATED).unwrap(), options);
+        assert_eq!(tree.comments, ["Start"]);
+        assert_eq!(tree.moves[0].comments, ["Best by test"]);
+        assert_eq!(tree.moves[0].clock, Some(180_000));
+        assert_eq!(tree.moves[1].nags, [1]);
+        assert!(tree.moves[3].comments.is_empty());
+        let sicilian = &tree.moves[1].variations[0];
+        assert_eq!(sicilian[0].comments, ["Sicilian"]);
+        assert!(sicilian[1].comments.is_empty());
+    }
+
+    #[test]
+    fn strips_only_clocks() {
+        let options = CleanOptions {
+            clocks: true,
+            ..Default::default()
+        };
+        let tree = clean_game(parse_game(ANNOTATED).unwrap(), options);
+        assert_eq!(tree.moves[0].comments, ["[%eval 0.3] Best by test"]);
+        assert!(tree.moves.iter().all(|node| node.clock.is_none()));
+        assert_eq!(tree.moves[4].elapsed, None);
+        assert_eq!(tree.moves[3].comments, ["[%eval 1.2]"]);
+    }
+
+    #[test]
+    fn keeps_every_game() {
+        let pgn = format!("{ANNOTATED}\n[Event \"Second\"]\n\n1. d4 {{ Solid }} d5 *\n");
+        let options = CleanOptions {
+            comments: true,
+            clocks: true,
+            ..Default::default()
+        };
+        let cleaned = clean_pgn(pgn, options).unwrap();
+        assert!(cleaned.contains("1. e4 e5 $1 (1... c5 2. Nf3 $14) 2. Nf3 Nc6 $2 3. Bb5 $16 1-0"));
+        assert!(cleaned.contains("[Event \"Second\"]"));
+        assert!(cleaned.ends_with("1. d4 d5 *\n"));
+    }
+}
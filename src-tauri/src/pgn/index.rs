@@ -0,0 +1,381 @@
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::SystemTime,
+};
+
+use pgn_reader::{BufferedReader, RawHeader, SanPlus, Skip, Visitor};
+use serde::Serialize;
+use shakmaty::{fen::Fen, Chess, EnPassantMode};
+use specta::Type;
+
+use super::tree::{parse_game, GameTree, PgnHeader};
+use crate::{error::Error, AppState};
+
+const BOM: [u8; 3] = [0xEF, 0xBB, 0xBF];
+
+/// Byte offsets of the games of a PGN file, valid as long as the file isn't modified.
+/// Cloning it shares the offsets.
+#[derive(Debug, Clone)]
+pub struct GameIndex {
+    modified: Option<SystemTime>,
+    len: u64,
+    /// Start of every game followed by the end of the file
+    offsets: Arc<[u64]>,
+}
+
+impl GameIndex {
+    fn games(&self) -> usize {
+        self.offsets.len().saturating_sub(1)
+    }
+}
+
+fn trim(mut line: &[u8]) -> &[u8] {
+    while let [first, rest @ ..] = line {
+        if !first.is_ascii_whitespace() {
+            break;
+        }
+        line = rest;
+    }
+    while let [rest @ .., last] = line {
+        if !last.is_ascii_whitespace() {
+            break;
+        }
+        line = rest;
+    }
+    line
+}
+
+/// Whether a line of movetext ends inside a `{}` comment
+pub(super) fn ends_in_comment(line: &[u8], mut in_comment: bool) -> bool {
+    for &byte in line {
+        match byte {
+            b'}' if in_comment => in_comment = false,
+            b'{' if !in_comment => in_comment = true,
+            b';' if !in_comment => break,
+            _ => {}
+        }
+    }
+    in_comment
+}
+
+/// Reads the games of a PGN one at a time, without parsing them. A game starts at a tag
+/// line following movetext, with tags inside comments not counting.
+pub(super) struct GameScanner<R> {
+    reader: R,
+    /// Bytes read so far
+    offset: u64,
+    /// Start and bytes of the game being read
+    start: Option<u64>,
+    game: Vec<u8>,
+    in_comment: bool,
+    in_movetext: bool,
+    line: Vec<u8>,
+}
+
+impl<R: BufRead> GameScanner<R> {
+    pub(super) fn new(mut reader: R) -> io::Result<Self> {
+        let mut offset = 0;
+        if reader.fill_buf()?.starts_with(&BOM) {
+            reader.consume(BOM.len());
+            offset = BOM.len() as u64;
+        }
+        Ok(Self {
+            reader,
+            offset,
+            start: None,
+            game: Vec::new(),
+            in_comment: false,
+            in_movetext: false,
+            line: Vec::new(),
+        })
+    }
+
+    pub(super) fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// The start and the bytes of the next game, exactly as written
+    pub(super) fn next_game(&mut self) -> io::Result<Option<(u64, Vec<u8>)>> {
+        loop {
+            self.line.clear();
+            let bytes = self.reader.read_until(b'\n', &mut self.line)?;
+            if bytes == 0 {
+                let game = std::mem::take(&mut self.game);
+                return Ok(self.start.take().map(|start| (start, game)));
+            }
+            let text = trim(&self.line);
+            let mut finished = None;
+            if !self.in_comment && text.starts_with(b"[") {
+                if self.start.is_none() || self.in_movetext {
+                    let game = std::mem::take(&mut self.game);
+                    finished = self.start.replace(self.offset).map(|start| (start, game));
+                    self.in_movetext = false;
+                }
+            } else if self.in_comment || !(text.is_empty() || text.starts_with(b"%")) {
+                self.start.get_or_insert(self.offset);
+                self.in_movetext = true;
+                self.in_comment = ends_in_comment(text, self.in_comment);
+            }
+            if self.start.is_some() {
+                self.game.extend_from_slice(&self.line);
+            }
+            self.offset += bytes as u64;
+            if finished.is_some() {
+                return Ok(finished);
+            }
+        }
+    }
+}
+
+/// Finds where games start. Returns the start of every game and the end of the input.
+pub(super) fn index_games<R: BufRead>(reader: &mut R) -> io::Result<Vec<u64>> {
+    let mut scanner = GameScanner::new(reader)?;
+    let mut offsets = Vec::new();
+    while let Some((start, _)) = scanner.next_game()? {
+        offsets.push(start);
+    }
+    offsets.push(scanner.offset());
+    Ok(offsets)
+}
+
+/// The index of `file`, built on the first read and again whenever the file changes
+fn game_index(file: &Path, state: &AppState) -> Result<GameIndex, Error> {
+    let metadata = file.metadata()?;
+    let modified = metadata.modified().ok();
+    if let Some(index) = state.pgn_indexes.get(file) {
+        if index.modified == modified && index.len == metadata.len() {
+            return Ok(index.clone());
+        }
+    }
+    let index = GameIndex {
+        modified,
+        len: metadata.len(),
+        offsets: index_games(&mut BufReader::new(File::open(file)?))?.into(),
+    };
+    state.pgn_indexes.insert(file.to_path_buf(), index.clone());
+    Ok(index)
+}
+
+/// Bytes of the games `start..end`
+fn read_range(file: &Path, index: &GameIndex, start: usize, end: usize) -> io::Result<Vec<u8>> {
+    let from = index.offsets[start];
+    let mut bytes = vec![0; (index.offsets[end] - from) as usize];
+    let mut file = File::open(file)?;
+    file.seek(SeekFrom::Start(from))?;
+    file.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct GameSummary {
+    pub index: usize,
+    pub headers: Vec<PgnHeader>,
+    /// Number of half-moves of the main line
+    pub moves: usize,
+    /// Starting position
+    pub fen: String,
+}
+
+#[derive(Serialize, Debug, Clone, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct GameSummaries {
+    /// Number of games in the file
+    pub total: usize,
+    pub games: Vec<GameSummary>,
+}
+
+#[derive(Default)]
+struct SummaryReader {
+    headers: Vec<PgnHeader>,
+    moves: usize,
+    finished: bool,
+}
+
+impl Visitor for SummaryReader {
+    type Result = (Vec<PgnHeader>, usize);
+
+    fn begin_game(&mut self) {
+        *self = SummaryReader::default();
+    }
+
+    fn header(&mut self, key: &[u8], value: RawHeader<'_>) {
+        self.headers.push(PgnHeader {
+            tag: String::from_utf8_lossy(key).into_owned(),
+            value: value.decode_utf8_lossy().into_owned(),
+        });
+    }
+
+    fn san(&mut self, _: SanPlus) {
+        if !self.finished {
+            self.moves += 1;
+        }
+    }
+
+    fn begin_variation(&mut self) -> Skip {
+        Skip(true)
+    }
+
+    fn outcome(&mut self, _: Option<shakmaty::Outcome>) {
+        self.finished = true;
+    }
+
+    fn end_game(&mut self) -> Self::Result {
+        (std::mem::take(&mut self.headers), self.moves)
+    }
+}
+
+fn summarize(index: usize, game: &[u8]) -> io::Result<GameSummary> {
+    let (headers, moves) = BufferedReader::new(game)
+        .read_game(&mut SummaryReader::default())?
+        .unwrap_or_default();
+    let fen = headers
+        .iter()
+        .find(|header| header.tag == "FEN")
+        .map(|header| header.value.clone())
+        .unwrap_or_else(|| Fen::from_position(Chess::default(), EnPassantMode::Legal).to_string());
+    Ok(GameSummary {
+        index,
+        headers,
+        moves,
+        fen,
+    })
+}
+
+/// Summaries of up to `count` games of a PGN file, starting with game `offset`
+#[tauri::command]
+#[specta::specta]
+pub async fn read_game_summaries(
+    file: PathBuf,
+    offset: usize,
+    count: usize,
+    state: tauri::State<'_, AppState>,
+) -> Result<GameSummaries, Error> {
+    let index = game_index(&file, &state)?;
+    let start = offset.min(index.games());
+    let end = offset.saturating_add(count).min(index.games());
+    let bytes = read_range(&file, &index, start, end)?;
+    let base = index.offsets[start];
+    let games = (start..end)
+        .map(|i| {
+            let from = (index.offsets[i] - base) as usize;
+            let to = (index.offsets[i + 1] - base) as usize;
+            summarize(i, &bytes[from..to])
+        })
+        .collect::<io::Result<_>>()?;
+    Ok(GameSummaries {
+        total: index.games(),
+        games,
+    })
+}
+
+/// Parses game `index` of a PGN file into its full tree
+#[tauri::command]
+#[specta::specta]
+pub async fn read_game(
+    file: PathBuf,
+    index: usize,
+    state: tauri::State<'_, AppState>,
+) -> Result<GameTree, Error> {
+    let games = game_index(&file, &state)?;
+    if index >= games.games() {
+        return Err(Error::GameNotFound { index });
+    }
+    let bytes = read_range(&file, &games, index, index + 1)?;
+    parse_game(&String::from_utf8_lossy(&bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GAMES: &str = concat!(
+        "\u{feff}[Event \"One\"]\r\n[White \"A\"]\r\n\r\n",
+        "1. e4 e5 {A comment\r\n[Event \"Not a game\"]} 2. Nf3 (2. Bc4) 1-0\r\n\r\n",
+        "[Event \"Two\"]\n[FEN \"4k3/8/8/8/8/8/4P3/4K3 w - - 0 1\"]\n",
+        "1. e3 ; [Event inside a line comment\n*\n",
+        "[Event \"Three\"]\n\n1. d4 *\n",
+    );
+
+    fn games(offsets: &[u64]) -> Vec<&[u8]> {
+        offsets
+            .windows(2)
+            .map(|w| &GAMES.as_bytes()[w[0] as usize..w[1] as usize])
+            .collect()
+    }
+
+    #[test]
+    fn finds_game_boundaries() {
+        let offsets = index_games(&mut GAMES.as_bytes()).unwrap();
+        assert_eq!(offsets.len(), 4);
+        assert_eq!(offsets[0], 3);
+        assert_eq!(*offsets.last().unwrap(), GAMES.len() as u64);
+        let games = games(&offsets);
+        assert!(games[1].starts_with(b"[Event \"Two\"]"));
+        assert!(games[2].starts_with(b"[Event \"Three\"]"));
+    }
+
+    #[test]
+    fn summarizes_games() {
+        let offsets = index_games(&mut GAMES.as_bytes()).unwrap();
+        let summaries: Vec<GameSummary> = games(&offsets)
+            .into_iter()
+            .enumerate()
+            .map(|(i, game)| summarize(i, game).unwrap())
+            .collect();
+        assert_eq!(summaries[0].headers[1].value, "A");
+        assert_eq!(summaries[0].moves, 3);
+        assert_eq!(
+            summaries[0].fen,
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1"
+        );
+        assert_eq!(summaries[1].moves, 1);
+        assert_eq!(summaries[1].fen, "4k3/8/8/8/8/8/4P3/4K3 w - - 0 1");
+        assert_eq!(summaries[2].index, 2);
+    }
+
+    #[test]
+    fn games_without_tags() {
+        assert_eq!(index_games(&mut &b"1. e4 e5 *\n"[..]).unwrap(), vec![0, 11]);
+        assert_eq!(index_games(&mut &b""[..]).unwrap(), vec![0]);
+    }
+
+    #[test]
+    fn text_between_games() {
+        let pgn = "\n\n[Event \"One\"]\n\n1. e4 e5 1-0\n\n{ Downloaded from somewhere }\n\
+                   a3 h6\n\n\n[Event \"Two\"]\n\n1. d4 d5 0-1";
+        let offsets = index_games(&mut pgn.as_bytes()).unwrap();
+        assert_eq!(offsets, vec![2, 69, pgn.len() as u64]);
+
+        let games: Vec<&[u8]> = offsets
+            .windows(2)
+            .map(|w| &pgn.as_bytes()[w[0] as usize..w[1] as usize])
+            .collect();
+        assert_eq!(summarize(0, games[0]).unwrap().moves, 2);
+        let tree = parse_game(&String::from_utf8_lossy(games[0])).unwrap();
+        assert_eq!(tree.moves.len(), 2);
+        assert!(tree.moves[1].comments.is_empty());
+        assert_eq!(tree.outcome, "1-0");
+
+        // The last game has no trailing newline
+        let tree = parse_game(&String::from_utf8_lossy(games[1])).unwrap();
+        assert_eq!(tree.moves[1].san, "d5");
+        assert_eq!(tree.outcome, "0-1");
+    }
+
+    #[test]
+    fn parses_games_with_windows_line_endings() {
+        let offsets = index_games(&mut GAMES.as_bytes()).unwrap();
+        let game = String::from_utf8_lossy(games(&offsets)[0]);
+        let tree = parse_game(&game).unwrap();
+        assert_eq!(tree.moves.len(), 3);
+        assert_eq!(
+            tree.moves[1].comments,
+            vec!["A comment\r\n[Event \"Not a game\"]"]
+        );
+        assert_eq!(tree.moves[2].variations[0][0].san, "Bc4");
+    }
+}
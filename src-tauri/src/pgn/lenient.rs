@@ -0,0 +1,313 @@
+use serde::Serialize;
+use specta::Type;
+
+use super::{
+    index::ends_in_comment,
+    tree::{parse_game, GameTree},
+};
+use crate::error::Error;
+
+pub(super) const RESULTS: [&str; 4] = ["1-0", "0-1", "1/2-1/2", "*"];
+
+/// Quirk of a pasted PGN that was fixed before parsing
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, Type)]
+#[serde(rename_all = "camelCase")]
+pub enum RepairKind {
+    /// `0-0` instead of `O-O`
+    Castling,
+    /// `Z0` instead of `--` for a null move
+    NullMove,
+    /// `e.p.` after an en passant capture
+    EnPassantSuffix,
+    /// Ellipsis glued to a move or standing in for White's move
+    Ellipsis,
+    /// Typographic quotes around a tag value
+    SmartQuotes,
+    /// `½-½` instead of `1/2-1/2`
+    Result,
+    /// No result at the end of the movetext
+    MissingResult,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq, Type)]
+pub struct Repair {
+    pub kind: RepairKind,
+    /// Text as it was pasted
+    pub original: String,
+}
+
+#[derive(Serialize, Debug, Clone, Type)]
+pub struct LenientGame {
+    pub tree: GameTree,
+    pub repairs: Vec<Repair>,
+}
+
+#[derive(Default)]
+struct Normalizer {
+    repairs: Vec<Repair>,
+    tokens: Vec<String>,
+}
+
+impl Normalizer {
+    fn repair(&mut self, kind: RepairKind, original: &str) {
+        self.repairs.push(Repair {
+            kind,
+            original: original.to_string(),
+        });
+    }
+
+    fn tag(&mut self, line: &str) -> String {
+        if !line.contains(['“', '”', '„', '″']) {
+            return line.to_string();
+        }
+        self.repair(RepairKind::SmartQuotes, line);
+        line.replace(['“', '”', '„', '″'], "\"")
+    }
+
+    fn token(&mut self, token: &str) {
+        if token == "e.p." {
+            self.repair(RepairKind::EnPassantSuffix, token);
+            return;
+        }
+        if let Some(capture) = token.strip_suffix("e.p.") {
+            self.repair(RepairKind::EnPassantSuffix, token);
+            return self.token(capture);
+        }
+        if token == "Z0" {
+            self.repair(RepairKind::NullMove, token);
+            self.tokens.push("--".to_string());
+            return;
+        }
+        if token == "½-½" {
+            self.repair(RepairKind::Result, token);
+            self.tokens.push("1/2-1/2".to_string());
+            return;
+        }
+        let suffix = token.trim_start_matches(['0', '-']);
+        let castling = &token[..token.len() - suffix.len()];
+        if castling == "0-0" || castling == "0-0-0" {
+            self.repair(RepairKind::Castling, token);
+            self.tokens.push(castling.replace('0', "O") + suffix);
+            return;
+        }
+        if let Some(dots) = token.find("...") {
+            let (number, rest) = (&token[..dots], &token[dots + 3..]);
+            if number.chars().all(|c| c.is_ascii_digit()) {
+                let white_missing = number.is_empty()
+                    && self
+                        .tokens
+                        .last()
+                        .is_some_and(|last| last.ends_with('.') && !last.ends_with("..."));
+                if !rest.is_empty() || white_missing {
+                    self.repair(RepairKind::Ellipsis, token);
+                    if !number.is_empty() {
+                        self.tokens.push(format!("{number}..."));
+                    }
+                    if !rest.is_empty() {
+                        self.token(rest);
+                    }
+                    return;
+                }
+            }
+        }
+        self.tokens.push(token.to_string());
+    }
+
+    fn movetext(&mut self, text: &str) {
+        let mut chars = text.char_indices();
+        let mut token_start = None;
+        while let Some((i, c)) = chars.next() {
+            let end = match c {
+                '{' | ';' | '(' | ')' => true,
+                c => c.is_whitespace(),
+            };
+            if !end {
+                token_start.get_or_insert(i);
+                continue;
+            }
+            if let Some(start) = token_start.take() {
+                self.token(&text[start..i]);
+            }
+            match c {
+                '{' | ';' => {
+                    let close = if c == '{' { '}' } else { '\n' };
+                    let mut comment = String::from(c);
+                    for (_, next) in chars.by_ref() {
+                        comment.push(next);
+                        if next == close {
+                            break;
+                        }
+                    }
+                    if c == ';' && !comment.ends_with('\n') {
+                        comment.push('\n');
+                    }
+                    self.tokens.push(comment);
+                }
+                '(' | ')' => self.tokens.push(c.to_string()),
+                _ => {}
+            }
+        }
+        if let Some(start) = token_start {
+            self.token(&text[start..]);
+        }
+    }
+}
+
+/// Rewrites the quirks of PGNs copied from websites and apps into standard PGN. Only
+/// the first game of `text` is kept.
+fn normalize(text: &str) -> (String, Vec<Repair>) {
+    let mut normalizer = Normalizer::default();
+    let mut tags = Vec::new();
+    let mut movetext = String::new();
+    let mut in_comment = false;
+    for line in text.lines() {
+        let trimmed = line.trim();
+        if trimmed.starts_with('[') && !in_comment {
+            if !movetext.trim().is_empty() {
+                // The next game
+                break;
+            }
+            tags.push(normalizer.tag(trimmed));
+        } else {
+            in_comment = ends_in_comment(line.as_bytes(), in_comment);
+            movetext.push_str(line);
+            movetext.push('\n');
+        }
+    }
+    normalizer.movetext(&movetext);
+
+    let ends_with_result = normalizer
+        .tokens
+        .last()
+        .is_some_and(|last| RESULTS.contains(&last.as_str()));
+    if !ends_with_result {
+        let result = tags
+            .iter()
+            .find_map(|tag| tag.strip_prefix("[Result \"")?.strip_suffix("\"]"))
+            .filter(|result| RESULTS.contains(result))
+            .unwrap_or("*")
+            .to_string();
+        normalizer.repair(RepairKind::MissingResult, "");
+        normalizer.tokens.push(result);
+    }
+
+    let mut pgn = tags.join("\n");
+    pgn.push_str("\n\n");
+    pgn.push_str(&normalizer.tokens.join(" "));
+    pgn.push('\n');
+    (pgn, normalizer.repairs)
+}
+
+/// Parses a game after repairing common quirks, for PGNs pasted by the user. Imports
+/// into databases stay strict.
+pub fn parse_game_lenient(text: &str) -> Result<LenientGame, Error> {
+    let (pgn, repairs) = normalize(text);
+    Ok(LenientGame {
+        tree: parse_game(&pgn)?,
+        repairs,
+    })
+}
+
+#[tauri::command]
+#[specta::specta]
+pub fn import_pgn_text(text: String) -> Result<LenientGame, Error> {
+    parse_game_lenient(&text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(repairs: &[Repair]) -> Vec<RepairKind> {
+        repairs.iter().map(|repair| repair.kind).collect()
+    }
+
+    #[test]
+    fn zero_castling() {
+        let game = parse_game_lenient(
+            "1. e4 e5 2. Nf3 Nc6 3. Bc4 Bc5 4. 0-0 Nf6 5. d3 d6 6. Bg5 h6 7. Bh4 Qe7 \
+             8. Nc3 Bd7 9. a3 0-0-0+ 1-0",
+        )
+        .unwrap();
+        assert_eq!(game.tree.moves[6].san, "O-O");
+        assert_eq!(game.tree.moves[17].san, "O-O-O");
+        assert_eq!(game.tree.outcome, "1-0");
+        assert_eq!(
+            kinds(&game.repairs),
+            vec![RepairKind::Castling, RepairKind::Castling]
+        );
+        assert_eq!(game.repairs[1].original, "0-0-0+");
+    }
+
+    #[test]
+    fn z0_null_moves() {
+        let game = parse_game_lenient("1. e4 e5 2. Nf3 (2. Z0 Qh4) Nc6 *").unwrap();
+        assert_eq!(game.tree.moves[2].variations[0][0].san, "--");
+        assert_eq!(game.tree.moves[2].variations[0][1].san, "Qh4");
+        assert_eq!(kinds(&game.repairs), vec![RepairKind::NullMove]);
+    }
+
+    #[test]
+    fn missing_result() {
+        let game = parse_game_lenient("[Result \"0-1\"]\n\n1. f3 e5 2. g4 Qh4#").unwrap();
+        assert_eq!(game.tree.outcome, "0-1");
+        assert_eq!(kinds(&game.repairs), vec![RepairKind::MissingResult]);
+
+        let game = parse_game_lenient("1. d4 d5").unwrap();
+        assert_eq!(game.tree.outcome, "*");
+    }
+
+    #[test]
+    fn ellipses() {
+        let game = parse_game_lenient(
+            "[FEN \"rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1\"]\n\n\
+             1. ... e5 2. Nf3 2...Nc6 3. Bb5 ...a6 *",
+        )
+        .unwrap();
+        assert_eq!(game.tree.moves.len(), 5);
+        assert_eq!(game.tree.moves[0].san, "e5");
+        assert_eq!(
+            kinds(&game.repairs),
+            vec![
+                RepairKind::Ellipsis,
+                RepairKind::Ellipsis,
+                RepairKind::Ellipsis
+            ]
+        );
+    }
+
+    #[test]
+    fn smart_quotes_and_en_passant() {
+        let game = parse_game_lenient(
+            "[White “Magnus”]\n[Black „Hikaru”]\n\n\
+             1. e4 Nf6 2. e5 d5 3. exd6 e.p. {Textbook} exd6 4. d4 d5 ½-½",
+        )
+        .unwrap();
+        assert_eq!(game.tree.header("White"), Some("Magnus"));
+        assert_eq!(game.tree.header("Black"), Some("Hikaru"));
+        assert_eq!(game.tree.moves[4].uci, "e5d6");
+        assert_eq!(game.tree.moves[4].comments, vec!["Textbook"]);
+        assert_eq!(game.tree.outcome, "1/2-1/2");
+        assert_eq!(
+            kinds(&game.repairs),
+            vec![
+                RepairKind::SmartQuotes,
+                RepairKind::SmartQuotes,
+                RepairKind::EnPassantSuffix,
+                RepairKind::Result
+            ]
+        );
+    }
+
+    #[test]
+    fn standard_pgn_needs_no_repairs() {
+        let game = parse_game_lenient(
+            "[Event \"?\"]\n[Result \"*\"]\n\n1. e4 {Best by test; 1-0} 1... e5 \
+             (1... c5 2. Nf3) 2. Nf3 ; a line comment\n*\n\n[Event \"Next\"]\n\n1. d4 *",
+        )
+        .unwrap();
+        assert!(game.repairs.is_empty());
+        assert_eq!(game.tree.moves.len(), 3);
+        assert_eq!(game.tree.moves[0].comments, vec!["Best by test; 1-0"]);
+    }
+}
@@ -0,0 +1,317 @@
+use std::{collections::HashMap, path::PathBuf};
+
+use serde::Deserialize;
+use specta::Type;
+
+use super::tree::{parse_games, GameTree, PgnNode};
+use crate::error::Error;
+
+/// A PGN to merge, pasted or read from a file
+#[derive(Deserialize, Debug, Clone, Type)]
+#[serde(rename_all = "camelCase", tag = "type", content = "value")]
+pub enum PgnSource {
+    Text(String),
+    File(PathBuf),
+}
+
+struct MergeNode {
+    parent: usize,
+    /// The move without its variations, which are kept as `children` instead
+    node: PgnNode,
+    /// Main continuation first
+    children: Vec<usize>,
+    /// Node with the same position reached by other moves, where this line continues
+    transposes_to: Option<usize>,
+}
+
+/// Position of a FEN without the move counters, which differ between move orders
+fn position_key(fen: &str) -> String {
+    fen.split_whitespace().take(4).collect::<Vec<_>>().join(" ")
+}
+
+fn push_attributed(comments: &mut Vec<String>, source: &str, new: &[String]) {
+    for comment in new.iter().filter(|comment| !comment.is_empty()) {
+        let comment = format!("{source}: {comment}");
+        if !comments.contains(&comment) {
+            comments.push(comment);
+        }
+    }
+}
+
+struct Repertoire {
+    nodes: Vec<MergeNode>,
+    positions: HashMap<String, usize>,
+}
+
+impl Repertoire {
+    fn new(fen: &str) -> Self {
+        Repertoire {
+            nodes: vec![MergeNode {
+                parent: 0,
+                node: PgnNode {
+                    fen: fen.to_string(),
+                    ..Default::default()
+                },
+                children: Vec::new(),
+                transposes_to: None,
+            }],
+            positions: HashMap::from([(position_key(fen), 0)]),
+        }
+    }
+
+    /// Moves leading to `index` with their move numbers, `1. d4 d5 2. c4`
+    fn path(&self, mut index: usize) -> String {
+        let mut moves = Vec::new();
+        while index != 0 {
+            let parent = self.nodes[index].parent;
+            let mut fields = self.nodes[parent].node.fen.split_whitespace().skip(1);
+            let white = fields.next() != Some("b");
+            let number = fields.nth(3).unwrap_or("1");
+            let san = &self.nodes[index].node.san;
+            moves.push(match (white, parent == 0) {
+                (true, _) => format!("{number}. {san}"),
+                (false, true) => format!("{number}... {san}"),
+                (false, false) => san.clone(),
+            });
+            index = parent;
+        }
+        moves.reverse();
+        moves.join(" ")
+    }
+
+    /// Adds `node` after `parent` and returns the node its line continues from
+    fn insert_move(&mut self, parent: usize, node: &PgnNode, source: &str) -> usize {
+        let existing = self.nodes[parent]
+            .children
+            .iter()
+            .copied()
+            .find(|&child| self.nodes[child].node.uci == node.uci);
+        if let Some(child) = existing {
+            let merged = &mut self.nodes[child].node;
+            push_attributed(
+                &mut merged.starting_comments,
+                source,
+                &node.starting_comments,
+            );
+            push_attributed(&mut merged.comments, source, &node.comments);
+            for nag in &node.nags {
+                if !merged.nags.contains(nag) {
+                    merged.nags.push(*nag);
+                }
+            }
+            return self.nodes[child].transposes_to.unwrap_or(child);
+        }
+
+        let mut new = PgnNode {
+            san: node.san.clone(),
+            uci: node.uci.clone(),
+            fen: node.fen.clone(),
+            nags: node.nags.clone(),
+            shapes: node.shapes.clone(),
+            ..Default::default()
+        };
+        push_attributed(&mut new.starting_comments, source, &node.starting_comments);
+        push_attributed(&mut new.comments, source, &node.comments);
+        let transposes_to = self.positions.get(&position_key(&node.fen)).copied();
+        if let Some(target) = transposes_to {
+            new.comments
+                .push(format!("Transposes to {}", self.path(target)));
+        }
+        self.nodes.push(MergeNode {
+            parent,
+            node: new,
+            children: Vec::new(),
+            transposes_to,
+        });
+        let index = self.nodes.len() - 1;
+        self.nodes[parent].children.push(index);
+        if transposes_to.is_none() {
+            self.positions.insert(position_key(&node.fen), index);
+        }
+        transposes_to.unwrap_or(index)
+    }
+
+    fn insert_line(&mut self, mut parent: usize, line: &[PgnNode], source: &str) {
+        for node in line {
+            let next = self.insert_move(parent, node, source);
+            for variation in &node.variations {
+                self.insert_line(parent, variation, source);
+            }
+            parent = next;
+        }
+    }
+
+    fn line(&self, start: usize) -> Vec<PgnNode> {
+        let mut line = Vec::new();
+        let mut next = Some(start);
+        while let Some(index) = next {
+            let mut node = self.nodes[index].node.clone();
+            let siblings = &self.nodes[self.nodes[index].parent].children;
+            if siblings.first() == Some(&index) {
+                node.variations = siblings[1..].iter().map(|&i| self.line(i)).collect();
+            }
+            line.push(node);
+            next = self.nodes[index].children.first().copied();
+        }
+        line
+    }
+}
+
+/// Merges games into one tree of all their lines. Moves reaching a position already in
+/// the tree by another move order end with a transposition comment, and the rest of
+/// their line is merged where that position was first reached. Comments are prefixed
+/// with the name of their source.
+pub fn merge_trees(trees: &[(String, GameTree)]) -> Result<GameTree, Error> {
+    let Some((_, first)) = trees.first() else {
+        return Ok(GameTree {
+            outcome: "*".to_string(),
+            ..Default::default()
+        });
+    };
+    let mut repertoire = Repertoire::new(&first.fen);
+    for (source, tree) in trees {
+        if position_key(&tree.fen) != position_key(&first.fen) {
+            return Err(Error::DifferentStartPositions);
+        }
+        push_attributed(
+            &mut repertoire.nodes[0].node.comments,
+            source,
+            &tree.comments,
+        );
+        repertoire.insert_line(0, &tree.moves, source);
+    }
+    Ok(GameTree {
+        headers: Vec::new(),
+        fen: first.fen.clone(),
+        comments: std::mem::take(&mut repertoire.nodes[0].node.comments),
+        shapes: Vec::new(),
+        moves: match repertoire.nodes[0].children.first() {
+            Some(&main) => repertoire.line(main),
+            None => Vec::new(),
+        },
+        outcome: "*".to_string(),
+        chess960: first.chess960,
+    })
+}
+
+/// Merges every game of the PGNs into one repertoire tree
+#[tauri::command]
+#[specta::specta]
+pub async fn merge_pgns(sources: Vec<PgnSource>) -> Result<GameTree, Error> {
+    let mut trees = Vec::new();
+    for (i, source) in sources.into_iter().enumerate() {
+        let (name, pgn) = match source {
+            PgnSource::Text(text) => (format!("PGN {}", i + 1), text),
+            PgnSource::File(path) => (
+                path.file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .unwrap_or_else(|| path.display().to_string()),
+                std::fs::read_to_string(&path)?,
+            ),
+        };
+        for tree in parse_games(&pgn)? {
+            trees.push((name.clone(), tree));
+        }
+    }
+    merge_trees(&trees)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::pgn::parse_game;
+
+    fn merge(pgns: &[(&str, &str)]) -> GameTree {
+        let trees: Vec<(String, GameTree)> = pgns
+            .iter()
+            .map(|(name, pgn)| (name.to_string(), parse_game(pgn).unwrap()))
+            .collect();
+        merge_trees(&trees).unwrap()
+    }
+
+    fn sans(line: &[PgnNode]) -> Vec<&str> {
+        line.iter().map(|node| node.san.as_str()).collect()
+    }
+
+    #[test]
+    fn transpositions_collapse() {
+        let tree = merge(&[
+            (
+                "qgd.pgn",
+                "1. d4 d5 2. c4 e6 3. Nc3 Nf6 4. Bg5 { Main line } (4. cxd5 exd5) *",
+            ),
+            (
+                "english.pgn",
+                "1. c4 e6 2. Nc3 d5 3. d4 Nf6 4. Nf3 { Quieter } *",
+            ),
+        ]);
+        assert_eq!(
+            sans(&tree.moves),
+            ["d4", "d5", "c4", "e6", "Nc3", "Nf6", "Bg5"]
+        );
+        assert_eq!(tree.moves[6].comments, ["qgd.pgn: Main line"]);
+        let alternatives: Vec<_> = tree.moves[6].variations.iter().map(|v| sans(v)).collect();
+        assert_eq!(alternatives, [vec!["cxd5", "exd5"], vec!["Nf3"]]);
+        assert_eq!(
+            tree.moves[6].variations[1][0].comments,
+            ["english.pgn: Quieter"]
+        );
+
+        let english = &tree.moves[0].variations[0];
+        assert_eq!(sans(english), ["c4", "e6", "Nc3", "d5", "d4"]);
+        assert_eq!(
+            english[4].comments,
+            ["Transposes to 1. d4 d5 2. c4 e6 3. Nc3"]
+        );
+        assert!(english[4].variations.is_empty());
+    }
+
+    #[test]
+    fn shared_moves_keep_comments_from_every_source() {
+        let tree = merge(&[
+            ("a.pgn", "{ Sicilian } 1. e4 c5 { Sharp } 2. Nf3 $1 *"),
+            ("b.pgn", "1. e4 c5 { Sharp } { Best } 2. Nf3 $1 $14 d6 *"),
+            ("a.pgn", "1. e4 c5 2. Nc3 *"),
+        ]);
+        assert_eq!(tree.comments, ["a.pgn: Sicilian"]);
+        assert_eq!(sans(&tree.moves), ["e4", "c5", "Nf3", "d6"]);
+        assert_eq!(
+            tree.moves[1].comments,
+            ["a.pgn: Sharp", "b.pgn: Sharp", "b.pgn: Best"]
+        );
+        assert_eq!(tree.moves[2].nags, [1, 14]);
+        assert_eq!(sans(&tree.moves[2].variations[0]), ["Nc3"]);
+    }
+
+    #[test]
+    fn later_moves_continue_after_a_transposition() {
+        let tree = merge(&[
+            ("a", "1. Nf3 Nf6 2. d4 d5 3. c4 *"),
+            ("b", "1. d4 d5 2. Nf3 Nf6 3. c4 e6 *"),
+            ("b", "1. d4 d5 2. Nf3 Nf6 3. Bf4 *"),
+        ]);
+        assert_eq!(sans(&tree.moves), ["Nf3", "Nf6", "d4", "d5", "c4", "e6"]);
+        let alternatives: Vec<_> = tree.moves[4].variations.iter().map(|v| sans(v)).collect();
+        assert_eq!(alternatives, [vec!["Bf4"]]);
+        assert_eq!(
+            tree.moves[0].variations[0][3].comments,
+            ["Transposes to 1. Nf3 Nf6 2. d4 d5"]
+        );
+    }
+
+    #[test]
+    fn start_positions_must_match() {
+        let trees = [
+            ("a".to_string(), parse_game("1. e4 *").unwrap()),
+            (
+                "b".to_string(),
+                parse_game("[FEN \"4k3/8/8/8/8/8/4P3/4K3 w - - 0 1\"]\n\n1. e4 *").unwrap(),
+            ),
+        ];
+        assert!(matches!(
+            merge_trees(&trees),
+            Err(Error::DifferentStartPositions)
+        ));
+    }
+}
@@ -6,6 +6,26 @@ use std::{
 
 use crate::{error::Error, AppState};
 
+mod clean;
+mod index;
+mod lenient;
+mod merge;
+mod split;
+mod tree;
+mod validate;
+mod writer;
+
+pub use clean::clean_pgn;
+pub use index::{read_game, read_game_summaries, GameIndex};
+pub use lenient::import_pgn_text;
+pub use merge::merge_pgns;
+pub use split::{cancel_pgn_split, split_pgn, SplitProgress};
+pub(crate) use tree::{is_chess960, setup_position, start_position, PgnHeader};
+pub use tree::{parse_game, parse_games, parse_pgn, parse_readable_games, GameTree, PgnNode};
+pub use validate::validate_pgn;
+pub use writer::{export_game, write_pgn};
+pub(crate) use writer::{write_tree, Newline, WriteOptions};
+
 const GAME_OFFSET_FREQ: usize = 100;
 
 struct PgnParser {
@@ -76,7 +96,7 @@ impl PgnParser {
                     }
                     new_game = false;
                 }
-            } else {
+            } else if !line.trim().is_empty() {
                 new_game = true;
             }
             line.clear();
@@ -100,7 +120,7 @@ impl PgnParser {
                 if new_game {
                     break;
                 }
-            } else {
+            } else if !self.line.trim().is_empty() {
                 new_game = true;
             }
             self.game.push_str(&self.line);
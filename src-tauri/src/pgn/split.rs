@@ -0,0 +1,458 @@
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use tauri::{AppHandle, Manager};
+use tauri_specta::Event;
+
+use super::{index::GameScanner, tree::PgnHeader, validate::parse_tag};
+use crate::{error::Error, AppState};
+
+/// Output files open at the same time, well under the usual file descriptor limits
+const MAX_OPEN_FILES: usize = 64;
+
+/// Games that pass every filter that is set
+#[derive(Deserialize, Debug, Clone, Default, Type)]
+#[serde(rename_all = "camelCase", default)]
+pub struct GameFilter {
+    /// Part of the name of either player, ignoring case
+    pub player: Option<String>,
+    /// Lowest and highest ECO code, like `B20` and `B99` for the Sicilian
+    pub eco: Option<(String, String)>,
+    /// `1-0`, `0-1`, `1/2-1/2` or `*`
+    pub result: Option<String>,
+}
+
+impl GameFilter {
+    fn matches(&self, headers: &[PgnHeader]) -> bool {
+        let tag = |name: &str| {
+            headers
+                .iter()
+                .find(|header| header.tag == name)
+                .map(|header| header.value.as_str())
+        };
+        if let Some(player) = &self.player {
+            let player = player.to_lowercase();
+            let plays =
+                |color: &str| tag(color).is_some_and(|name| name.to_lowercase().contains(&player));
+            if !plays("White") && !plays("Black") {
+                return false;
+            }
+        }
+        if let Some((from, to)) = &self.eco {
+            let Some(eco) = tag("ECO").map(str::to_uppercase) else {
+                return false;
+            };
+            if eco < from.to_uppercase() || eco > to.to_uppercase() {
+                return false;
+            }
+        }
+        if let Some(result) = &self.result {
+            if tag("Result") != Some(result.as_str()) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Type)]
+#[serde(rename_all = "camelCase", tag = "type")]
+pub enum SplitCriteria {
+    /// One file per game
+    Game,
+    /// One file per value of a tag, like `Event` or `Date`
+    Tag { tag: String },
+    /// One file with the games matching the filter
+    Filter(GameFilter),
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq, Type)]
+pub struct SplitFile {
+    pub path: PathBuf,
+    pub games: usize,
+}
+
+#[derive(Serialize, Debug, Clone, Default, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct SplitManifest {
+    pub files: Vec<SplitFile>,
+    /// Games read from the input, including the ones no file took
+    pub games: usize,
+    /// Whether the split was cancelled before the end of the input
+    pub cancelled: bool,
+}
+
+#[derive(Clone, Type, Serialize, Event)]
+pub struct SplitProgress {
+    pub id: String,
+    pub progress: f64,
+    /// Games read so far
+    pub games: usize,
+    pub finished: bool,
+}
+
+/// Tags of a game, read without parsing its movetext
+fn headers(game: &[u8]) -> Vec<PgnHeader> {
+    String::from_utf8_lossy(game)
+        .lines()
+        .map(str::trim)
+        .take_while(|line| line.is_empty() || line.starts_with('['))
+        .filter_map(parse_tag)
+        .collect()
+}
+
+/// `value` with the characters file systems reject replaced
+fn file_name(value: &str) -> String {
+    let name: String = value
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || " -_.,".contains(c) {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    let name = name.trim_matches([' ', '.']);
+    if name.is_empty() || value.chars().all(|c| c == '?') {
+        "Unknown".to_string()
+    } else {
+        name.to_string()
+    }
+}
+
+/// Files the games are written to. Only the most recent ones are kept open, and a file
+/// closed to make room is appended to when it gets another game.
+struct Outputs {
+    dir: PathBuf,
+    files: Vec<SplitFile>,
+    indexes: HashMap<PathBuf, usize>,
+    writers: HashMap<usize, BufWriter<File>>,
+}
+
+impl Outputs {
+    fn write(&mut self, name: &str, game: &[u8]) -> io::Result<()> {
+        let path = self.dir.join(format!("{name}.pgn"));
+        let index = match self.indexes.get(&path) {
+            Some(&index) => index,
+            None => {
+                self.files.push(SplitFile {
+                    path: path.clone(),
+                    games: 0,
+                });
+                self.indexes.insert(path, self.files.len() - 1);
+                self.files.len() - 1
+            }
+        };
+        if self.writers.len() >= MAX_OPEN_FILES && !self.writers.contains_key(&index) {
+            self.close()?;
+        }
+        let file = &self.files[index];
+        let writer = match self.writers.entry(index) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let output = if file.games == 0 {
+                    File::create(&file.path)?
+                } else {
+                    OpenOptions::new().append(true).open(&file.path)?
+                };
+                entry.insert(BufWriter::new(output))
+            }
+        };
+        writer.write_all(game)?;
+        // The last game of the input may have no line ending
+        if !game.ends_with(b"\n") {
+            writer.write_all(b"\n\n")?;
+        }
+        self.files[index].games += 1;
+        Ok(())
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        for (_, mut writer) in self.writers.drain() {
+            writer.flush()?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes the games of `reader` to files in `dir`, exactly as they were written. Files
+/// are named after `stem` or the tag the games are grouped by. `progress` gets the
+/// number of games and bytes read every 1000 games.
+fn split_games<R: BufRead>(
+    reader: R,
+    criteria: &SplitCriteria,
+    dir: &Path,
+    stem: &str,
+    cancelled: &AtomicBool,
+    mut progress: impl FnMut(usize, u64),
+) -> Result<SplitManifest, Error> {
+    let mut scanner = GameScanner::new(reader)?;
+    let mut outputs = Outputs {
+        dir: dir.to_path_buf(),
+        files: Vec::new(),
+        indexes: HashMap::new(),
+        writers: HashMap::new(),
+    };
+    let mut manifest = SplitManifest::default();
+    while let Some((_, game)) = scanner.next_game()? {
+        if cancelled.load(Ordering::Relaxed) {
+            manifest.cancelled = true;
+            break;
+        }
+        let name = match criteria {
+            SplitCriteria::Game => Some(format!("{stem}-{:06}", manifest.games + 1)),
+            SplitCriteria::Tag { tag } => {
+                let headers = headers(&game);
+                let value = headers
+                    .iter()
+                    .find(|header| header.tag == *tag)
+                    .map(|header| header.value.as_str());
+                Some(file_name(value.unwrap_or_default()))
+            }
+            SplitCriteria::Filter(filter) => filter
+                .matches(&headers(&game))
+                .then(|| format!("{stem}-filtered")),
+        };
+        if let Some(name) = name {
+            outputs.write(&name, &game)?;
+        }
+        manifest.games += 1;
+        if manifest.games % 1000 == 0 {
+            progress(manifest.games, scanner.offset());
+        }
+    }
+    outputs.close()?;
+    manifest.files = outputs.files;
+    Ok(manifest)
+}
+
+/// Splits a PGN file into several without importing it, one game at a time so it works
+/// on files of any size. Can be cancelled with `cancel_pgn_split(id)`, which keeps the
+/// files written so far.
+#[tauri::command]
+#[specta::specta]
+pub async fn split_pgn(
+    id: String,
+    path: PathBuf,
+    criteria: SplitCriteria,
+    output_dir: PathBuf,
+    state: tauri::State<'_, AppState>,
+    app: AppHandle,
+) -> Result<SplitManifest, Error> {
+    if !app.fs_scope().is_allowed(&output_dir) {
+        return Err(Error::ForbiddenPath);
+    }
+    fs::create_dir_all(&output_dir)?;
+    let file = File::open(&path)?;
+    let len = file.metadata()?.len().max(1);
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "games".to_string());
+
+    let cancelled = Arc::new(AtomicBool::new(false));
+    state.pgn_splits.insert(id.clone(), cancelled.clone());
+    let result = split_games(
+        BufReader::new(file),
+        &criteria,
+        &output_dir,
+        &stem,
+        &cancelled,
+        |games, read| {
+            let _ = SplitProgress {
+                id: id.clone(),
+                progress: read as f64 / len as f64 * 100.0,
+                games,
+                finished: false,
+            }
+            .emit_all(&app);
+        },
+    );
+    state.pgn_splits.remove(&id);
+
+    let manifest = result?;
+    SplitProgress {
+        id,
+        progress: 100.0,
+        games: manifest.games,
+        finished: true,
+    }
+    .emit_all(&app)?;
+    Ok(manifest)
+}
+
+#[tauri::command]
+#[specta::specta]
+pub fn cancel_pgn_split(id: String, state: tauri::State<'_, AppState>) {
+    if let Some(cancelled) = state.pgn_splits.get(&id) {
+        cancelled.store(true, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::pgn::index::index_games;
+
+    const GAMES: &str = concat!(
+        "[Event \"Wijk aan Zee\"]\r\n[White \"Carlsen, Magnus\"]\r\n[Black \"Giri, Anish\"]\r\n",
+        "[Result \"1-0\"]\r\n[ECO \"B90\"]\r\n\r\n1. e4 c5 {Sicilian\r\n[Event \"Not a game\"]}\r\n",
+        "2. Nf3 1-0\r\n\r\n",
+        "[Event \"Tata Steel: Masters\"]\n[White \"Ding, Liren\"]\n[Black \"Carlsen, Magnus\"]\n",
+        "[Result \"1/2-1/2\"]\n[ECO \"D37\"]\n\n1. d4 d5    2. c4 1/2-1/2\n\n",
+        "[Event \"Wijk aan Zee\"]\n[White \"Giri, Anish\"]\n[Black \"So, Wesley\"]\n",
+        "[Result \"0-1\"]\n[ECO \"B33\"]\n\n1. e4 c5 0-1",
+    );
+
+    fn split(criteria: SplitCriteria) -> (tempfile::TempDir, SplitManifest) {
+        let dir = tempfile::tempdir().unwrap();
+        let manifest = split_games(
+            GAMES.as_bytes(),
+            &criteria,
+            dir.path(),
+            "twic",
+            &AtomicBool::new(false),
+            |_, _| {},
+        )
+        .unwrap();
+        (dir, manifest)
+    }
+
+    fn names(manifest: &SplitManifest) -> Vec<(String, usize)> {
+        manifest
+            .files
+            .iter()
+            .map(|file| {
+                let name = file.path.file_name().unwrap().to_string_lossy();
+                (name.into_owned(), file.games)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn one_file_per_game() {
+        let (dir, manifest) = split(SplitCriteria::Game);
+        assert_eq!(manifest.games, 3);
+        assert_eq!(
+            names(&manifest),
+            [
+                ("twic-000001.pgn".to_string(), 1),
+                ("twic-000002.pgn".to_string(), 1),
+                ("twic-000003.pgn".to_string(), 1)
+            ]
+        );
+        // Written exactly as in the input
+        let first = fs::read_to_string(dir.path().join("twic-000001.pgn")).unwrap();
+        assert!(GAMES.starts_with(&first));
+        assert!(first.ends_with("2. Nf3 1-0\r\n\r\n"));
+        let second = fs::read_to_string(dir.path().join("twic-000002.pgn")).unwrap();
+        assert!(second.contains("1. d4 d5    2. c4"));
+        let last = fs::read_to_string(dir.path().join("twic-000003.pgn")).unwrap();
+        assert!(last.ends_with("1. e4 c5 0-1\n\n"));
+    }
+
+    #[test]
+    fn groups_by_tag() {
+        let (dir, manifest) = split(SplitCriteria::Tag {
+            tag: "Event".to_string(),
+        });
+        assert_eq!(
+            names(&manifest),
+            [
+                ("Wijk aan Zee.pgn".to_string(), 2),
+                ("Tata Steel_ Masters.pgn".to_string(), 1)
+            ]
+        );
+        let wijk = fs::read_to_string(dir.path().join("Wijk aan Zee.pgn")).unwrap();
+        let games = index_games(&mut wijk.as_bytes()).unwrap();
+        assert_eq!(games.len(), 3);
+        assert!(wijk.contains("[White \"Giri, Anish\"]"));
+
+        let (_dir, manifest) = split(SplitCriteria::Tag {
+            tag: "Round".to_string(),
+        });
+        assert_eq!(names(&manifest), [("Unknown.pgn".to_string(), 3)]);
+    }
+
+    #[test]
+    fn filters_games() {
+        let matching = |filter: GameFilter| names(&split(SplitCriteria::Filter(filter)).1);
+        assert_eq!(
+            matching(GameFilter {
+                player: Some("carlsen".to_string()),
+                ..Default::default()
+            }),
+            [("twic-filtered.pgn".to_string(), 2)]
+        );
+        assert_eq!(
+            matching(GameFilter {
+                player: Some("Giri".to_string()),
+                eco: Some(("B20".to_string(), "b99".to_string())),
+                result: Some("0-1".to_string()),
+            }),
+            [("twic-filtered.pgn".to_string(), 1)]
+        );
+        assert!(matching(GameFilter {
+            eco: Some(("C00".to_string(), "C99".to_string())),
+            ..Default::default()
+        })
+        .is_empty());
+    }
+
+    #[test]
+    fn reopens_closed_files() {
+        let mut pgn = String::new();
+        for i in 0..MAX_OPEN_FILES + 5 {
+            pgn.push_str(&format!("[Event \"{i}\"]\n\n1. e4 *\n\n"));
+        }
+        pgn.push_str("[Event \"0\"]\n\n1. d4 *\n");
+        let dir = tempfile::tempdir().unwrap();
+        let criteria = SplitCriteria::Tag {
+            tag: "Event".to_string(),
+        };
+        let manifest = split_games(
+            pgn.as_bytes(),
+            &criteria,
+            dir.path(),
+            "events",
+            &AtomicBool::new(false),
+            |_, _| {},
+        )
+        .unwrap();
+        assert_eq!(manifest.files.len(), MAX_OPEN_FILES + 5);
+        assert_eq!(manifest.files[0].games, 2);
+        let first = fs::read_to_string(&manifest.files[0].path).unwrap();
+        assert_eq!(
+            first,
+            "[Event \"0\"]\n\n1. e4 *\n\n[Event \"0\"]\n\n1. d4 *\n"
+        );
+    }
+
+    #[test]
+    fn stops_when_cancelled() {
+        let dir = tempfile::tempdir().unwrap();
+        let manifest = split_games(
+            GAMES.as_bytes(),
+            &SplitCriteria::Game,
+            dir.path(),
+            "twic",
+            &AtomicBool::new(true),
+            |_, _| {},
+        )
+        .unwrap();
+        assert!(manifest.cancelled);
+        assert!(manifest.files.is_empty());
+    }
+}
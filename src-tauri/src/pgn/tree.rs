@@ -0,0 +1,669 @@
+use pgn_reader::{BufferedReader, Nag, RawComment, RawHeader, SanPlus, Skip, Visitor};
+use serde::{Deserialize, Serialize};
+use shakmaty::{
+    fen::Fen, san::San, CastlingMode, Chess, Color, EnPassantMode, FromSetup, Position,
+    PositionError, Setup, Square,
+};
+use specta::Type;
+
+use crate::{analysis::parse_duration, error::Error};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Type)]
+#[serde(rename_all = "camelCase")]
+pub enum ShapeColor {
+    Green,
+    Red,
+    Yellow,
+    Blue,
+}
+
+impl ShapeColor {
+    /// Letter of the color in `[%cal]` and `[%csl]` commands
+    pub(super) fn letter(self) -> char {
+        match self {
+            ShapeColor::Green => 'G',
+            ShapeColor::Red => 'R',
+            ShapeColor::Yellow => 'Y',
+            ShapeColor::Blue => 'B',
+        }
+    }
+
+    fn from_letter(letter: char) -> Option<Self> {
+        match letter {
+            'G' => Some(ShapeColor::Green),
+            'R' => Some(ShapeColor::Red),
+            'Y' => Some(ShapeColor::Yellow),
+            'B' => Some(ShapeColor::Blue),
+            _ => None,
+        }
+    }
+}
+
+/// An arrow drawn on the board from a `[%cal]` command, or a circle from `[%csl]`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct Shape {
+    pub color: ShapeColor,
+    pub from: String,
+    /// Square the arrow points to, `None` for a circle
+    pub to: Option<String>,
+}
+
+/// Reads the shapes of a `[%cal]` or `[%csl]` value like `Ge2e4,Rd7d5`, `None` if one
+/// of them isn't a color followed by `squares` squares
+fn parse_shapes(value: &str, squares: usize) -> Option<Vec<Shape>> {
+    value
+        .split(',')
+        .map(|shape| {
+            let shape = shape.trim();
+            let color = ShapeColor::from_letter(shape.chars().next()?)?;
+            let parsed: Vec<_> = shape[1..]
+                .as_bytes()
+                .chunks(2)
+                .map(|square| Square::from_ascii(square).ok().map(|s| s.to_string()))
+                .collect::<Option<_>>()?;
+            if parsed.len() != squares {
+                return None;
+            }
+            let mut parsed = parsed.into_iter();
+            Some(Shape {
+                color,
+                from: parsed.next()?,
+                to: parsed.next(),
+            })
+        })
+        .collect()
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Type)]
+pub struct PgnHeader {
+    pub tag: String,
+    pub value: String,
+}
+
+/// A move of a game with its annotations
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct PgnNode {
+    pub san: String,
+    pub uci: String,
+    /// Position after the move
+    pub fen: String,
+    /// Comments before the move, which only the first move of a variation can have
+    pub starting_comments: Vec<String>,
+    pub comments: Vec<String>,
+    pub nags: Vec<u8>,
+    /// Clock of the player after the move in milliseconds, from a `[%clk]` command
+    pub clock: Option<u32>,
+    /// Time spent on the move in milliseconds, from a `[%emt]` command
+    pub elapsed: Option<u32>,
+    /// Arrows and circles drawn on the position after the move
+    #[serde(default)]
+    pub shapes: Vec<Shape>,
+    /// Lines played instead of this move
+    pub variations: Vec<Vec<PgnNode>>,
+}
+
+/// A game with all its variations. Each line is a list of moves rather than a chain of
+/// nested nodes, so only variations add depth to the tree.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct GameTree {
+    /// Tags in the order they appear in the game
+    pub headers: Vec<PgnHeader>,
+    /// Starting position
+    pub fen: String,
+    /// Comments before the first move
+    pub comments: Vec<String>,
+    /// Arrows and circles drawn on the starting position
+    #[serde(default)]
+    pub shapes: Vec<Shape>,
+    pub moves: Vec<PgnNode>,
+    /// `1-0`, `0-1`, `1/2-1/2` or `*`
+    pub outcome: String,
+    /// Whether castling follows Chess960 rules, so engines need `UCI_Chess960`
+    #[serde(default)]
+    pub chess960: bool,
+}
+
+impl GameTree {
+    pub fn header(&self, tag: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|header| header.tag == tag)
+            .map(|header| header.value.as_str())
+    }
+}
+
+struct BuilderNode {
+    parent: Option<usize>,
+    position: Chess,
+    node: PgnNode,
+    children: Vec<usize>,
+}
+
+/// Builds the tree in an arena first, since a variation can only be attached once the
+/// move it replaces has been read
+#[derive(Default)]
+struct TreeBuilder {
+    headers: Vec<PgnHeader>,
+    nodes: Vec<BuilderNode>,
+    current: usize,
+    /// Nodes to continue from once the open variations end
+    variations: Vec<usize>,
+    /// Whether no move of the current variation was read yet
+    variation_start: bool,
+    starting_comments: Vec<String>,
+    outcome: Option<String>,
+    error: Option<String>,
+}
+
+/// Splits a `[%command value]` off a comment, returning the rest of the comment and the
+/// value
+fn take_command(comment: &str, command: &str) -> Option<(String, String)> {
+    let tag = format!("[%{command} ");
+    let start = comment.find(&tag)?;
+    let end = start + comment[start..].find(']')?;
+    let value = comment[start + tag.len()..end].trim().to_string();
+    let rest = format!(
+        "{} {}",
+        comment[..start].trim_end(),
+        comment[end + 1..].trim_start()
+    );
+    Some((rest.trim().to_string(), value))
+}
+
+/// Move number as written before a move from `position`, `4.` or `4...`
+pub(super) fn move_number(position: &Chess) -> String {
+    match position.turn() {
+        Color::White => format!("{}.", position.fullmoves()),
+        Color::Black => format!("{}...", position.fullmoves()),
+    }
+}
+
+/// Position after the side to move passes, `None` when it is in check. Annotators use
+/// null moves to show what a side threatens.
+pub(super) fn play_null(position: &Chess) -> Option<Chess> {
+    if position.is_check() {
+        return None;
+    }
+    let mut setup = position.clone().into_setup(EnPassantMode::Legal);
+    if setup.turn == Color::Black {
+        setup.fullmoves = setup.fullmoves.saturating_add(1);
+    }
+    setup.turn = !setup.turn;
+    setup.ep_square = None;
+    setup.halfmoves = setup.halfmoves.saturating_add(1);
+    Chess::from_setup(setup, position.castles().mode()).ok()
+}
+
+/// Starting position set by the `FEN` and `Variant` tags
+///
+/// Games from a custom position, like lichess' `From Position`, fall back to Chess960
+/// castling when their castling rights name rooks standard chess can't castle with.
+pub(crate) fn start_position(headers: &[PgnHeader]) -> Result<Chess, String> {
+    let chess960 = headers
+        .iter()
+        .any(|header| header.tag == "Variant" && is_chess960(&header.value));
+    let mode = if chess960 {
+        CastlingMode::Chess960
+    } else {
+        CastlingMode::Standard
+    };
+    let Some(fen) = headers
+        .iter()
+        .find(|header| header.tag == "FEN")
+        .map(|header| &header.value)
+    else {
+        return Chess::from_setup(Setup::default(), mode)
+            .map_err(|e| format!("invalid start position: {e}"));
+    };
+    let fen: Fen = fen
+        .parse()
+        .map_err(|e| format!("invalid FEN header: {e}"))?;
+    setup_position(fen, chess960).map_err(|e| format!("invalid FEN header: {e}"))
+}
+
+/// Position of a FEN, with Chess960 castling for Chess960 games and as the fallback of
+/// the others
+pub(crate) fn setup_position(fen: Fen, chess960: bool) -> Result<Chess, PositionError<Chess>> {
+    if chess960 {
+        return fen.into_position(CastlingMode::Chess960);
+    }
+    fen.clone()
+        .into_position(CastlingMode::Standard)
+        .or_else(|e| fen.into_position(CastlingMode::Chess960).map_err(|_| e))
+}
+
+/// Whether a `Variant` tag names Chess960, like `Chess960`, `chess 960` or `Fischerandom`
+pub(crate) fn is_chess960(variant: &str) -> bool {
+    let variant = variant.to_lowercase();
+    variant.contains("960") || variant.starts_with("fischer")
+}
+
+impl TreeBuilder {
+    fn line(&mut self, start: usize) -> Vec<PgnNode> {
+        let mut line = Vec::new();
+        let mut next = Some(start);
+        while let Some(index) = next {
+            let mut node = std::mem::take(&mut self.nodes[index].node);
+            let parent = self.nodes[index].parent.unwrap_or_default();
+            if self.nodes[parent].children.first() == Some(&index) {
+                let siblings = self.nodes[parent].children[1..].to_vec();
+                node.variations = siblings.into_iter().map(|i| self.line(i)).collect();
+            }
+            line.push(node);
+            next = self.nodes[index].children.first().copied();
+        }
+        line
+    }
+}
+
+impl Visitor for TreeBuilder {
+    type Result = Result<GameTree, Error>;
+
+    fn begin_game(&mut self) {
+        *self = TreeBuilder::default();
+    }
+
+    fn header(&mut self, key: &[u8], value: RawHeader<'_>) {
+        self.headers.push(PgnHeader {
+            tag: String::from_utf8_lossy(key).into_owned(),
+            value: value.decode_utf8_lossy().into_owned(),
+        });
+    }
+
+    fn end_headers(&mut self) -> Skip {
+        match start_position(&self.headers) {
+            Ok(position) => {
+                self.nodes.push(BuilderNode {
+                    parent: None,
+                    position,
+                    node: PgnNode::default(),
+                    children: Vec::new(),
+                });
+                Skip(false)
+            }
+            Err(reason) => {
+                self.error = Some(reason);
+                Skip(true)
+            }
+        }
+    }
+
+    fn san(&mut self, san_plus: SanPlus) {
+        // Anything after the result is text between games
+        if self.error.is_some() || self.outcome.is_some() {
+            return;
+        }
+        let parent = self.current;
+        let mut position = self.nodes[parent].position.clone();
+        let (san, uci) = if san_plus.san == San::Null {
+            let Some(after) = play_null(&position) else {
+                self.error = Some(format!("illegal null move {} --", move_number(&position)));
+                return;
+            };
+            position = after;
+            ("--".to_string(), "0000".to_string())
+        } else {
+            let Ok(m) = san_plus.san.to_move(&position) else {
+                self.error = Some(format!(
+                    "illegal move {} {}",
+                    move_number(&position),
+                    san_plus
+                ));
+                return;
+            };
+            let uci = m.to_uci(position.castles().mode()).to_string();
+            let san = SanPlus::from_move_and_play_unchecked(&mut position, &m).to_string();
+            (san, uci)
+        };
+        let fen = Fen::from_position(position.clone(), EnPassantMode::Legal).to_string();
+        self.nodes.push(BuilderNode {
+            parent: Some(parent),
+            position,
+            node: PgnNode {
+                san,
+                uci,
+                fen,
+                starting_comments: std::mem::take(&mut self.starting_comments),
+                ..Default::default()
+            },
+            children: Vec::new(),
+        });
+        self.current = self.nodes.len() - 1;
+        self.nodes[parent].children.push(self.current);
+        self.variation_start = false;
+    }
+
+    fn nag(&mut self, nag: Nag) {
+        if self.error.is_none()
+            && self.outcome.is_none()
+            && !self.variation_start
+            && self.current != 0
+        {
+            self.nodes[self.current].node.nags.push(nag.0);
+        }
+    }
+
+    fn comment(&mut self, comment: RawComment<'_>) {
+        if self.error.is_some() || self.outcome.is_some() {
+            return;
+        }
+        let mut comment = String::from_utf8_lossy(comment.as_bytes())
+            .trim()
+            .to_string();
+        if self.variation_start {
+            self.starting_comments.push(comment);
+            return;
+        }
+        let node = &mut self.nodes[self.current].node;
+        let mut commands = false;
+        for (command, squares) in [("cal", 2), ("csl", 1)] {
+            while let Some((rest, value)) = take_command(&comment, command) {
+                let Some(shapes) = parse_shapes(&value, squares) else {
+                    break;
+                };
+                node.shapes.extend(shapes);
+                comment = rest;
+                commands = true;
+            }
+        }
+        if self.current != 0 {
+            for (command, field) in [("clk", &mut node.clock), ("emt", &mut node.elapsed)] {
+                let Some((rest, value)) = take_command(&comment, command) else {
+                    continue;
+                };
+                if let Some(seconds) = parse_duration(&value) {
+                    *field = Some((seconds * 1000.0).round() as u32);
+                    comment = rest;
+                    commands = true;
+                }
+            }
+        }
+        if !(commands && comment.is_empty()) {
+            node.comments.push(comment);
+        }
+    }
+
+    fn begin_variation(&mut self) -> Skip {
+        if self.error.is_some() || self.outcome.is_some() {
+            return Skip(true);
+        }
+        let Some(parent) = self.nodes[self.current]
+            .parent
+            .filter(|_| !self.variation_start)
+        else {
+            let position = &self.nodes[self.current].position;
+            self.error = Some(format!(
+                "variation without a move to replace at {}",
+                move_number(position)
+            ));
+            return Skip(true);
+        };
+        self.variations.push(self.current);
+        self.current = parent;
+        self.variation_start = true;
+        Skip(false)
+    }
+
+    fn end_variation(&mut self) {
+        if let Some(node) = self.variations.pop() {
+            self.current = node;
+        }
+        self.variation_start = false;
+        self.starting_comments.clear();
+    }
+
+    fn outcome(&mut self, outcome: Option<shakmaty::Outcome>) {
+        self.outcome = Some(outcome.map_or("*".to_string(), |o| o.to_string()));
+    }
+
+    fn end_game(&mut self) -> Self::Result {
+        if let Some(reason) = self.error.take() {
+            return Err(Error::InvalidPgn { reason });
+        }
+        let moves = match self.nodes[0].children.first() {
+            Some(&first) => self.line(first),
+            None => Vec::new(),
+        };
+        Ok(GameTree {
+            headers: std::mem::take(&mut self.headers),
+            fen: Fen::from_position(self.nodes[0].position.clone(), EnPassantMode::Legal)
+                .to_string(),
+            comments: std::mem::take(&mut self.nodes[0].node.comments),
+            shapes: std::mem::take(&mut self.nodes[0].node.shapes),
+            moves,
+            outcome: self.outcome.take().unwrap_or_else(|| "*".to_string()),
+            chess960: self.nodes[0].position.castles().mode() == CastlingMode::Chess960,
+        })
+    }
+}
+
+/// Parses the first game of `pgn`
+pub fn parse_game(pgn: &str) -> Result<GameTree, Error> {
+    let mut reader = BufferedReader::new(pgn.as_bytes());
+    reader
+        .read_game(&mut TreeBuilder::default())?
+        .ok_or_else(|| Error::InvalidPgn {
+            reason: "no game found".to_string(),
+        })?
+}
+
+/// Parses every game of `pgn`
+pub fn parse_games(pgn: &str) -> Result<Vec<GameTree>, Error> {
+    BufferedReader::new(pgn.as_bytes())
+        .into_iter(&mut TreeBuilder::default())
+        .map(|game| game?)
+        .collect()
+}
+
+/// Parses the games of `pgn` that can be read, leaving out the others
+pub fn parse_readable_games(pgn: &str) -> Vec<GameTree> {
+    BufferedReader::new(pgn.as_bytes())
+        .into_iter(&mut TreeBuilder::default())
+        .filter_map(|game| game.ok()?.ok())
+        .collect()
+}
+
+#[tauri::command]
+#[specta::specta]
+pub fn parse_pgn(pgn: String) -> Result<GameTree, Error> {
+    parse_game(&pgn)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn variations_comments_and_nags() {
+        let tree = parse_game(
+            r#"[Event "Casual"]
+[White "A"]
+[Black "B"]
+
+{Start} 1. e4 {Good} {Very good} (1. d4 d5 (1... Nf6 $14)) 1... e5 2. Nf3 $1 $14
+(2. Bc4 {Bishop's opening}) 2... Nc6 ({Or} 2... d6 3. d4 (3. Bc4)) 1-0"#,
+        )
+        .unwrap();
+        assert_eq!(tree.header("White"), Some("A"));
+        assert_eq!(tree.headers[0].tag, "Event");
+        assert_eq!(tree.comments, vec!["Start"]);
+        assert_eq!(tree.outcome, "1-0");
+        assert_eq!(tree.moves.len(), 4);
+
+        let e4 = &tree.moves[0];
+        assert_eq!(e4.uci, "e2e4");
+        assert_eq!(e4.comments, vec!["Good", "Very good"]);
+        assert_eq!(e4.variations.len(), 1);
+        let d4 = &e4.variations[0];
+        assert_eq!(d4.len(), 2);
+        assert_eq!(d4[1].variations[0][0].san, "Nf6");
+        assert_eq!(d4[1].variations[0][0].nags, vec![14]);
+
+        let nf3 = &tree.moves[2];
+        assert_eq!(nf3.nags, vec![1, 14]);
+        assert_eq!(nf3.variations[0][0].comments, vec!["Bishop's opening"]);
+
+        let d6 = &tree.moves[3].variations[0];
+        assert_eq!(d6[0].starting_comments, vec!["Or"]);
+        assert_eq!(d6[1].variations[0][0].san, "Bc4");
+        assert_eq!(
+            d6[1].variations[0][0].fen,
+            "rnbqkbnr/ppp2ppp/3p4/4p3/2B1P3/5N2/PPPP1PPP/RNBQK2R b KQkq - 1 3"
+        );
+    }
+
+    #[test]
+    fn custom_start_position() {
+        let tree = parse_game(
+            r#"[FEN "4k3/8/8/8/8/8/4P3/4K3 b - - 0 30"]
+
+30... Kd7 31. e4 *"#,
+        )
+        .unwrap();
+        assert_eq!(tree.fen, "4k3/8/8/8/8/8/4P3/4K3 b - - 0 30");
+        assert_eq!(tree.moves[1].san, "e4");
+        assert_eq!(tree.outcome, "*");
+    }
+
+    #[test]
+    fn chess960_games() {
+        let tree = parse_game(
+            r#"[Variant "Chess960"]
+[FEN "bqnbrkrn/pppppppp/8/8/8/8/PPPPPPPP/BQNBRKRN w KQkq - 0 1"]
+
+1. O-O O-O 2. d4 *"#,
+        )
+        .unwrap();
+        assert!(tree.chess960);
+        assert_eq!(tree.moves[0].uci, "f1g1");
+        assert_eq!(tree.moves[1].uci, "f8g8");
+        assert_eq!(tree.moves[1].san, "O-O");
+        assert!(tree.moves[1]
+            .fen
+            .starts_with("bqnbrrkn/pppppppp/8/8/8/8/PPPPPPPP/BQNBRRKN w"));
+
+        // Castling rights with arbitrary rook files only work with Chess960 rules
+        let tree = parse_game(
+            r#"[Variant "From Position"]
+[FEN "bqnbrkrn/pppppppp/8/8/8/8/PPPPPPPP/BQNBRKRN w GEge - 0 1"]
+
+1. O-O *"#,
+        )
+        .unwrap();
+        assert!(tree.chess960);
+        assert_eq!(tree.moves[0].uci, "f1g1");
+
+        let tree = parse_game(
+            r#"[Variant "From Position"]
+[FEN "r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1"]
+
+1. O-O *"#,
+        )
+        .unwrap();
+        assert!(!tree.chess960);
+        assert_eq!(tree.moves[0].uci, "e1g1");
+        assert!(!parse_game("1. e4 *").unwrap().chess960);
+    }
+
+    #[test]
+    fn clock_commands() {
+        let tree = parse_game(
+            "1. e4 { [%clk 0:03:00] } 1... e5 { Solid [%emt 0:00:12] [%clk 0:02:49.5] } \
+             2. Nf3 { [%eval 0.2] [%clk 1:02] } {} *",
+        )
+        .unwrap();
+        assert_eq!(tree.moves[0].clock, Some(180_000));
+        assert!(tree.moves[0].comments.is_empty());
+        assert_eq!(tree.moves[1].clock, Some(169_500));
+        assert_eq!(tree.moves[1].elapsed, Some(12_000));
+        assert_eq!(tree.moves[1].comments, vec!["Solid"]);
+        assert_eq!(tree.moves[2].clock, Some(62_000));
+        assert_eq!(tree.moves[2].comments, vec!["[%eval 0.2]", ""]);
+    }
+
+    #[test]
+    fn shape_commands() {
+        let tree = parse_game(
+            "{ [%csl Gd5] } 1. e4 { Center [%cal Ge2e4,Rd7d5] [%csl Yd4] } \
+             1... e5 { [%cal Xe7e5] } 2. Nf3 { [%cal Ge2] } *",
+        )
+        .unwrap();
+        assert_eq!(tree.shapes[0].color, ShapeColor::Green);
+        assert!(tree.comments.is_empty());
+
+        let e4 = &tree.moves[0];
+        assert_eq!(e4.comments, vec!["Center"]);
+        assert_eq!(
+            e4.shapes,
+            vec![
+                Shape {
+                    color: ShapeColor::Green,
+                    from: "e2".to_string(),
+                    to: Some("e4".to_string()),
+                },
+                Shape {
+                    color: ShapeColor::Red,
+                    from: "d7".to_string(),
+                    to: Some("d5".to_string()),
+                },
+                Shape {
+                    color: ShapeColor::Yellow,
+                    from: "d4".to_string(),
+                    to: None,
+                },
+            ]
+        );
+        // Shapes that can't be read stay in the comment
+        assert_eq!(tree.moves[1].comments, vec!["[%cal Xe7e5]"]);
+        assert_eq!(tree.moves[2].comments, vec!["[%cal Ge2]"]);
+        assert!(tree.moves[2].shapes.is_empty());
+    }
+
+    #[test]
+    fn null_moves() {
+        let tree = parse_game(
+            "1. e4 e5 2. Nf3 Nc6 3. Bc4 Nf6 4. d3 (4. -- { Threatening } 4... Ng4 5. O-O) *",
+        )
+        .unwrap();
+        let line = &tree.moves[6].variations[0];
+        assert_eq!(line[0].san, "--");
+        assert_eq!(line[0].uci, "0000");
+        assert_eq!(
+            line[0].fen,
+            "r1bqkb1r/pppp1ppp/2n2n2/4p3/2B1P3/5N2/PPPP1PPP/RNBQK2R b KQkq - 5 4"
+        );
+        assert_eq!(line[0].comments, vec!["Threatening"]);
+        assert_eq!(line[2].san, "O-O");
+
+        let error = parse_game("1. e4 f5 2. Qh5+ -- *").unwrap_err().to_string();
+        assert_eq!(error, "Invalid PGN: illegal null move 2... --");
+    }
+
+    #[test]
+    fn check_suffixes_are_recomputed() {
+        let tree = parse_game("1. e4+ e5 2. Qh5 Nc6 3. Bc4 Nf6 4. Qxf7 1-0").unwrap();
+        assert_eq!(tree.moves[0].san, "e4");
+        assert_eq!(tree.moves[6].san, "Qxf7#");
+    }
+
+    #[test]
+    fn illegal_moves_name_the_move_number() {
+        let error = parse_game("1. e4 e5 2. Nf3 (2. Ke3) Nc6 3. Bb5 a6 4. Bxc6 Kxe7 *")
+            .unwrap_err()
+            .to_string();
+        assert_eq!(error, "Invalid PGN: illegal move 2. Ke3");
+
+        let error = parse_game("1. e4 e5 2. Nf3 Nc6 3. Bb5 a6 4. Bxc6 Kxe7 *")
+            .unwrap_err()
+            .to_string();
+        assert_eq!(error, "Invalid PGN: illegal move 4... Kxe7");
+
+        let error = parse_game("[FEN \"8/8/8\"]\n\n1. e4 *").unwrap_err();
+        assert!(error
+            .to_string()
+            .starts_with("Invalid PGN: invalid FEN header"));
+    }
+}
@@ -0,0 +1,489 @@
+use std::{
+    fs::File,
+    io::{self, BufReader, Read},
+};
+
+use serde::Serialize;
+use shakmaty::{
+    san::{San, SanPlus},
+    Chess, Position,
+};
+use specta::Type;
+
+use super::{
+    index::index_games,
+    lenient::RESULTS,
+    merge::PgnSource,
+    tree::{move_number, play_null, start_position, PgnHeader},
+};
+use crate::error::Error;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, Type)]
+#[serde(rename_all = "camelCase")]
+pub enum IssueKind {
+    /// A move that can't be played in its position
+    IllegalMove,
+    /// Movetext that is neither a move, a move number, a NAG nor a result
+    UnknownToken,
+    /// A `{` comment that runs to the end of the game
+    UnterminatedComment,
+    /// A `)` without its `(`, or the other way around
+    UnbalancedVariation,
+    /// A malformed tag pair or an invalid `FEN` tag
+    BadTag,
+    /// The result contradicts the `Result` tag or the final position
+    ResultMismatch,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct PgnIssue {
+    /// Index of the game in the file
+    pub game: usize,
+    /// Line of the file, starting at 1
+    pub line: usize,
+    /// Move number where the issue is, `12.` or `12...`, for issues in the movetext
+    pub move_number: Option<String>,
+    pub kind: IssueKind,
+    pub message: String,
+}
+
+#[derive(Serialize, Debug, Clone, Default, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct ValidationReport {
+    /// Number of games
+    pub games: usize,
+    /// Games without any issue
+    pub valid_games: Vec<usize>,
+    pub issues: Vec<PgnIssue>,
+}
+
+/// Parses `[Name "value"]`, unescaping the value
+pub(super) fn parse_tag(line: &str) -> Option<PgnHeader> {
+    let inner = line.strip_prefix('[')?.strip_suffix(']')?.trim();
+    let (name, value) = inner.split_once(char::is_whitespace)?;
+    if !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return None;
+    }
+    let value = value.trim().strip_prefix('"')?.strip_suffix('"')?;
+    let mut unescaped = String::new();
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => unescaped.push(chars.next()?),
+            '"' => return None,
+            c => unescaped.push(c),
+        }
+    }
+    Some(PgnHeader {
+        tag: name.to_string(),
+        value: unescaped,
+    })
+}
+
+/// The main line or a variation being read
+struct Line {
+    position: Chess,
+    /// Position before the last move, where a variation starts
+    before_last: Option<Chess>,
+    /// Whether an illegal move was found, after which positions are unknown
+    broken: bool,
+}
+
+struct GameValidator<'a> {
+    game: usize,
+    text: &'a str,
+    first_line: usize,
+    issues: Vec<PgnIssue>,
+}
+
+impl GameValidator<'_> {
+    fn issue(&mut self, offset: usize, position: Option<&Chess>, kind: IssueKind, message: String) {
+        self.issues.push(PgnIssue {
+            game: self.game,
+            line: self.first_line + self.text[..offset].matches('\n').count(),
+            move_number: position.map(move_number),
+            kind,
+            message,
+        });
+    }
+
+    /// Reads the tag pairs and returns them with the offset where the movetext starts
+    fn tags(&mut self) -> (Vec<(usize, PgnHeader)>, usize) {
+        let mut tags = Vec::new();
+        let mut offset = 0;
+        for line in self.text.split_inclusive('\n') {
+            let trimmed = line.trim();
+            if !trimmed.is_empty() && !trimmed.starts_with('[') {
+                break;
+            }
+            if !trimmed.is_empty() {
+                match parse_tag(trimmed) {
+                    Some(header) => tags.push((offset, header)),
+                    None => self.issue(
+                        offset,
+                        None,
+                        IssueKind::BadTag,
+                        format!("malformed tag pair {trimmed}"),
+                    ),
+                }
+            }
+            offset += line.len();
+        }
+        (tags, offset)
+    }
+
+    fn token(&mut self, offset: usize, token: &str, line: &mut Line) {
+        if token.starts_with('$') && token[1..].chars().all(|c| c.is_ascii_digit()) {
+            return;
+        }
+        let mut san = token;
+        let after_number = token.trim_start_matches(|c: char| c.is_ascii_digit());
+        if after_number.starts_with('.') {
+            san = after_number.trim_start_matches('.');
+        }
+        let castling;
+        if san.starts_with("0-0") {
+            castling = san.replace('0', "O");
+            san = &castling;
+        }
+        let san = san.trim_end_matches(['!', '?']);
+        if san.is_empty() {
+            return;
+        }
+        let Ok(san_plus) = san.parse::<SanPlus>() else {
+            let position = (!line.broken).then_some(&line.position);
+            self.issue(
+                offset,
+                position,
+                IssueKind::UnknownToken,
+                format!("unknown token {token}"),
+            );
+            return;
+        };
+        if line.broken {
+            return;
+        }
+        let after = if san_plus.san == San::Null {
+            play_null(&line.position)
+        } else {
+            san_plus.san.to_move(&line.position).ok().map(|m| {
+                let mut position = line.position.clone();
+                position.play_unchecked(&m);
+                position
+            })
+        };
+        match after {
+            Some(after) => {
+                line.before_last = Some(std::mem::replace(&mut line.position, after));
+            }
+            None => {
+                let position = line.position.clone();
+                self.issue(
+                    offset,
+                    Some(&position),
+                    IssueKind::IllegalMove,
+                    format!("illegal move {} {san}", move_number(&position)),
+                );
+                line.broken = true;
+            }
+        }
+    }
+
+    fn validate(&mut self) {
+        let (tags, movetext_start) = self.tags();
+        let headers: Vec<PgnHeader> = tags.iter().map(|(_, header)| header.clone()).collect();
+        let mut line = match start_position(&headers) {
+            Ok(position) => Line {
+                position,
+                before_last: None,
+                broken: false,
+            },
+            Err(reason) => {
+                let offset = tags
+                    .iter()
+                    .find(|(_, header)| header.tag == "FEN")
+                    .map_or(0, |(offset, _)| *offset);
+                self.issue(offset, None, IssueKind::BadTag, reason);
+                Line {
+                    position: Chess::default(),
+                    before_last: None,
+                    broken: true,
+                }
+            }
+        };
+
+        let text = self.text;
+        let mut variations: Vec<(usize, Line)> = Vec::new();
+        let mut result = None;
+        let mut chars = text[movetext_start..]
+            .char_indices()
+            .map(|(i, c)| (movetext_start + i, c))
+            .peekable();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '{' => match text[i..].find('}') {
+                    Some(end) => while chars.next_if(|&(j, _)| j <= i + end).is_some() {},
+                    None => {
+                        self.issue(
+                            i,
+                            None,
+                            IssueKind::UnterminatedComment,
+                            "comment is never closed".to_string(),
+                        );
+                        break;
+                    }
+                },
+                ';' => while chars.next_if(|&(_, c)| c != '\n').is_some() {},
+                '%' if text[..i].ends_with('\n') => {
+                    while chars.next_if(|&(_, c)| c != '\n').is_some() {}
+                }
+                '(' => {
+                    let variation = Line {
+                        position: line.before_last.clone().unwrap_or_default(),
+                        before_last: None,
+                        broken: line.broken || line.before_last.is_none(),
+                    };
+                    if line.before_last.is_none() && !line.broken {
+                        let position = line.position.clone();
+                        self.issue(
+                            i,
+                            Some(&position),
+                            IssueKind::IllegalMove,
+                            "variation without a move to replace".to_string(),
+                        );
+                    }
+                    variations.push((i, std::mem::replace(&mut line, variation)));
+                }
+                ')' => match variations.pop() {
+                    Some((_, parent)) => line = parent,
+                    None => self.issue(
+                        i,
+                        None,
+                        IssueKind::UnbalancedVariation,
+                        "`)` without an open variation".to_string(),
+                    ),
+                },
+                c if c.is_whitespace() => {}
+                _ => {
+                    let mut end = i + c.len_utf8();
+                    while let Some((j, c)) =
+                        chars.next_if(|&(_, c)| !c.is_whitespace() && !"{};()".contains(c))
+                    {
+                        end = j + c.len_utf8();
+                    }
+                    let token = &text[i..end];
+                    if RESULTS.contains(&token) && variations.is_empty() {
+                        // Anything after the result is text between games
+                        result = Some((i, token));
+                        break;
+                    }
+                    self.token(i, token, &mut line);
+                }
+            }
+        }
+
+        for &(offset, _) in &variations {
+            self.issue(
+                offset,
+                None,
+                IssueKind::UnbalancedVariation,
+                "variation is never closed".to_string(),
+            );
+        }
+        let main = variations.into_iter().next().map_or(line, |(_, main)| main);
+        self.check_result(&headers, result, &main);
+    }
+
+    fn check_result(&mut self, headers: &[PgnHeader], result: Option<(usize, &str)>, main: &Line) {
+        let tag = headers
+            .iter()
+            .find(|header| header.tag == "Result")
+            .map(|header| header.value.as_str());
+        let offset = result.map_or(self.text.trim_end().len(), |(offset, _)| offset);
+        if let (Some(tag), Some((_, result))) = (tag, result) {
+            if tag != result {
+                self.issue(
+                    offset,
+                    None,
+                    IssueKind::ResultMismatch,
+                    format!("the Result tag is {tag} but the game ends with {result}"),
+                );
+            }
+        }
+        let Some(result) = result.map(|(_, result)| result).or(tag) else {
+            return;
+        };
+        if main.broken || result == "*" {
+            return;
+        }
+        let Some(outcome) = main.position.outcome() else {
+            return;
+        };
+        let expected = outcome.to_string();
+        if result != expected {
+            let end = if main.position.is_checkmate() {
+                "checkmate"
+            } else if main.position.is_stalemate() {
+                "stalemate"
+            } else {
+                "a draw by insufficient material"
+            };
+            self.issue(
+                offset,
+                None,
+                IssueKind::ResultMismatch,
+                format!("the game ends in {end}, so the result should be {expected}, not {result}"),
+            );
+        }
+    }
+}
+
+fn validate_game(game: usize, text: &str, first_line: usize) -> Vec<PgnIssue> {
+    let mut validator = GameValidator {
+        game,
+        text,
+        first_line,
+        issues: Vec::new(),
+    };
+    validator.validate();
+    validator.issues
+}
+
+/// Validates the games at `offsets`, as found by [`index_games`], one at a time
+fn validate_games<R: Read>(mut reader: R, offsets: &[u64]) -> io::Result<ValidationReport> {
+    let mut report = ValidationReport::default();
+    let Some(&start) = offsets.first() else {
+        return Ok(report);
+    };
+    io::copy(&mut reader.by_ref().take(start), &mut io::sink())?;
+    let mut first_line = 1;
+    let mut bytes = Vec::new();
+    for (game, window) in offsets.windows(2).enumerate() {
+        bytes.resize((window[1] - window[0]) as usize, 0);
+        reader.read_exact(&mut bytes)?;
+        let issues = validate_game(game, &String::from_utf8_lossy(&bytes), first_line);
+        if issues.is_empty() {
+            report.valid_games.push(game);
+        }
+        report.issues.extend(issues);
+        first_line += bytes.iter().filter(|&&byte| byte == b'\n').count();
+    }
+    report.games = offsets.len() - 1;
+    Ok(report)
+}
+
+/// Lists every problem of a PGN instead of stopping at the first one, along with the
+/// games that can be imported as they are
+#[tauri::command]
+#[specta::specta]
+pub async fn validate_pgn(source: PgnSource) -> Result<ValidationReport, Error> {
+    let report = match source {
+        PgnSource::Text(text) => {
+            let offsets = index_games(&mut text.as_bytes())?;
+            validate_games(text.as_bytes(), &offsets)?
+        }
+        PgnSource::File(path) => {
+            let offsets = index_games(&mut BufReader::new(File::open(&path)?))?;
+            validate_games(BufReader::new(File::open(&path)?), &offsets)?
+        }
+    };
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GAMES: &str = r#"[Event "Fine"]
+
+1. e4 e5 2. Nf3 *
+
+[Event "Bad]
+[Site "?"]
+
+1. e4 e5 2. Ke3 Nc6
+3. Nf3 xyz *
+
+[Event "Mate"]
+[Result "0-1"]
+
+1. f3 e5 2. g4 Qh4# 1-0
+
+[Event "Variations"]
+
+1. e4 e5 (1... c5 2. Nf3 Qxh7) 2. Nf3 ) *
+
+[Event "Comment"]
+
+1. d4 { never closed
+2. c4 *
+"#;
+
+    fn validate(pgn: &str) -> ValidationReport {
+        validate_games(pgn.as_bytes(), &index_games(&mut pgn.as_bytes()).unwrap()).unwrap()
+    }
+
+    fn located(report: &ValidationReport) -> Vec<(usize, usize, IssueKind)> {
+        report
+            .issues
+            .iter()
+            .map(|issue| (issue.game, issue.line, issue.kind))
+            .collect()
+    }
+
+    #[test]
+    fn finds_every_issue() {
+        let report = validate(GAMES);
+        assert_eq!(report.games, 5);
+        assert_eq!(report.valid_games, vec![0]);
+        assert_eq!(
+            located(&report),
+            vec![
+                (1, 5, IssueKind::BadTag),
+                (1, 8, IssueKind::IllegalMove),
+                (1, 9, IssueKind::UnknownToken),
+                (2, 14, IssueKind::ResultMismatch),
+                (2, 14, IssueKind::ResultMismatch),
+                (3, 18, IssueKind::IllegalMove),
+                (3, 18, IssueKind::UnbalancedVariation),
+                (4, 22, IssueKind::UnterminatedComment),
+            ]
+        );
+
+        let illegal = &report.issues[1];
+        assert_eq!(illegal.move_number.as_deref(), Some("2."));
+        assert_eq!(illegal.message, "illegal move 2. Ke3");
+        assert_eq!(
+            report.issues[4].message,
+            "the game ends in checkmate, so the result should be 0-1, not 1-0"
+        );
+        assert_eq!(report.issues[5].move_number.as_deref(), Some("2..."));
+    }
+
+    #[test]
+    fn accepts_standard_pgn() {
+        let report = validate(
+            "\u{feff}[Event \"A \\\"quoted\\\" name\"]\n[Result \"1-0\"]\n\n\
+             1.e4 e5!? 2. Bc4 $1 Nc6 3. Qh5 Nf6?? {Oops} (3... g6 4. Qf3) 4. Qxf7# 1-0\n\n\
+             [Event \"B\"]\n\n1. d4 (1. --) 1... d5 2. 0-0 ; not castling yet\n*\n\
+             Text between games",
+        );
+        assert_eq!(report.games, 2);
+        assert_eq!(report.valid_games, vec![0]);
+        assert_eq!(report.issues[0].kind, IssueKind::IllegalMove);
+        assert_eq!(report.issues[0].line, 8);
+        assert_eq!(report.issues[0].message, "illegal move 2. O-O");
+    }
+
+    #[test]
+    fn tag_pairs() {
+        assert_eq!(
+            parse_tag(r#"[White "Carlsen, \"Magnus\""]"#).map(|header| header.value),
+            Some(r#"Carlsen, "Magnus""#.to_string())
+        );
+        assert_eq!(parse_tag(r#"[White "Carlsen"#), None);
+        assert_eq!(parse_tag(r#"[White Carlsen]"#), None);
+        assert_eq!(parse_tag(r#"[White "A" "B"]"#), None);
+    }
+}
@@ -0,0 +1,609 @@
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use tauri::{AppHandle, Manager};
+
+use super::tree::{GameTree, PgnNode, Shape};
+use crate::error::Error;
+
+/// Lines of movetext are wrapped at this width. Comments are never broken up, so a
+/// long comment can make its line longer.
+const LINE_WIDTH: usize = 80;
+
+/// Tags every game has, in the order they are written
+const SEVEN_TAG_ROSTER: [(&str, &str); 7] = [
+    ("Event", "?"),
+    ("Site", "?"),
+    ("Date", "????.??.??"),
+    ("Round", "?"),
+    ("White", "?"),
+    ("Black", "?"),
+    ("Result", "*"),
+];
+
+const STARTING_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, Type)]
+#[serde(rename_all = "camelCase")]
+pub enum Newline {
+    #[default]
+    Lf,
+    Crlf,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Type)]
+#[serde(rename_all = "camelCase", default)]
+pub struct WriteOptions {
+    pub comments: bool,
+    /// Whether to keep `[%eval]` commands in comments
+    pub evals: bool,
+    /// Whether to write the clock and move time of each move as `[%clk]` and `[%emt]`
+    pub clocks: bool,
+    /// How deeply variations may be nested, `0` for only the main line. Unlimited if
+    /// `None`.
+    pub max_variation_depth: Option<u32>,
+    /// Whether to write the FEN and SetUp tags of games from a custom position
+    pub setup_tags: bool,
+    pub newline: Newline,
+}
+
+impl Default for WriteOptions {
+    fn default() -> Self {
+        Self {
+            comments: true,
+            evals: true,
+            clocks: true,
+            max_variation_depth: None,
+            setup_tags: true,
+            newline: Newline::Lf,
+        }
+    }
+}
+
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Comment `comment` without its `[%command]`s
+pub(super) fn strip_command(comment: &str, command: &str) -> String {
+    let command = format!("[%{command}");
+    let mut rest = comment;
+    let mut stripped = String::new();
+    while let Some(start) = rest.find(&command) {
+        stripped.push_str(&rest[..start]);
+        rest = match rest[start..].find(']') {
+            Some(end) => &rest[start + end + 1..],
+            None => "",
+        };
+    }
+    stripped.push_str(rest);
+    stripped.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// `h:mm:ss` with the fraction of a second if there is one
+fn format_duration(ms: u32) -> String {
+    let seconds = ms / 1000;
+    let mut duration = format!(
+        "{}:{:02}:{:02}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    );
+    if ms % 1000 != 0 {
+        let fraction = format!(".{:03}", ms % 1000);
+        duration.push_str(fraction.trim_end_matches('0'));
+    }
+    duration
+}
+
+/// Side to move and move number of a FEN
+fn move_number(fen: &str) -> (bool, u32) {
+    let mut fields = fen.split_whitespace().skip(1);
+    let white = fields.next() != Some("b");
+    let number = fields.nth(3).and_then(|n| n.parse().ok()).unwrap_or(1);
+    (white, number)
+}
+
+struct MovetextWriter {
+    options: WriteOptions,
+    tokens: Vec<String>,
+    /// Whether the next token starts a variation
+    variation_start: bool,
+}
+
+impl MovetextWriter {
+    /// Pushes a token, with the parentheses of variations attached to their first and
+    /// last tokens so they never end up alone on a line
+    fn push(&mut self, token: String) {
+        if self.variation_start {
+            self.tokens.push(format!("({token}"));
+            self.variation_start = false;
+        } else {
+            self.tokens.push(token);
+        }
+    }
+
+    /// Pushes the comments and returns whether there were any
+    fn comments(&mut self, comments: &[String]) -> bool {
+        if !self.options.comments {
+            return false;
+        }
+        let mut written = false;
+        for comment in comments {
+            let mut comment = comment.clone();
+            if !self.options.evals {
+                comment = strip_command(&comment, "eval");
+            }
+            if !self.options.clocks {
+                comment = strip_command(&comment, "clk");
+            }
+            if !comment.is_empty() {
+                self.push(format!("{{{comment}}}"));
+                written = true;
+            }
+        }
+        written
+    }
+
+    /// The `[%csl]` and `[%cal]` commands of shapes, which go with the comments
+    fn shape_commands(&self, shapes: &[Shape]) -> Vec<String> {
+        if !self.options.comments {
+            return Vec::new();
+        }
+        let (arrows, circles): (Vec<_>, Vec<_>) =
+            shapes.iter().partition(|shape| shape.to.is_some());
+        [("csl", circles), ("cal", arrows)]
+            .into_iter()
+            .filter(|(_, shapes)| !shapes.is_empty())
+            .map(|(command, shapes)| {
+                let shapes: Vec<_> = shapes
+                    .iter()
+                    .map(|shape| {
+                        let to = shape.to.as_deref().unwrap_or_default();
+                        format!("{}{}{to}", shape.color.letter(), shape.from)
+                    })
+                    .collect();
+                format!("[%{command} {}]", shapes.join(","))
+            })
+            .collect()
+    }
+
+    /// The shape, `[%clk]` and `[%emt]` commands of a move
+    fn commands(&self, node: &PgnNode) -> String {
+        let mut commands = self.shape_commands(&node.shapes);
+        if self.options.clocks {
+            let clock = node
+                .clock
+                .map(|ms| format!("[%clk {}]", format_duration(ms)));
+            let elapsed = node
+                .elapsed
+                .map(|ms| format!("[%emt {}]", format_duration(ms)));
+            commands.extend(clock.into_iter().chain(elapsed));
+        }
+        commands.join(" ")
+    }
+
+    /// Writes a line of moves played from the position `fen`, nested in `depth`
+    /// variations
+    fn line(&mut self, moves: &[PgnNode], fen: &str, depth: u32) {
+        let mut fen = fen;
+        let mut number_needed = true;
+        for node in moves {
+            if self.comments(&node.starting_comments) {
+                number_needed = true;
+            }
+            let (white, number) = move_number(fen);
+            if white {
+                self.push(format!("{number}."));
+            } else if number_needed {
+                self.push(format!("{number}..."));
+            }
+            self.push(node.san.clone());
+            for nag in &node.nags {
+                self.push(format!("${nag}"));
+            }
+            let mut comments = node.comments.clone();
+            let commands = self.commands(node);
+            if !commands.is_empty() {
+                match comments.first_mut() {
+                    Some(first) if !first.is_empty() => *first = format!("{first} {commands}"),
+                    Some(first) => *first = commands,
+                    None => comments.push(commands),
+                }
+            }
+            number_needed = self.comments(&comments);
+            if !matches!(self.options.max_variation_depth, Some(max) if depth >= max) {
+                for variation in &node.variations {
+                    self.variation_start = true;
+                    self.line(variation, fen, depth + 1);
+                    if let Some(last) = self.tokens.last_mut() {
+                        last.push(')');
+                    }
+                    number_needed = true;
+                }
+            }
+            fen = &node.fen;
+        }
+    }
+}
+
+fn wrap(tokens: &[String]) -> String {
+    let mut text = String::new();
+    let mut width = 0;
+    for token in tokens {
+        if width > 0 {
+            if width + 1 + token.len() > LINE_WIDTH {
+                text.push('\n');
+                width = 0;
+            } else {
+                text.push(' ');
+                width += 1;
+            }
+        }
+        text.push_str(token);
+        width += token.len();
+    }
+    text
+}
+
+fn tag(pgn: &mut String, tag: &str, value: &str) {
+    pgn.push_str(&format!("[{tag} \"{}\"]\n", escape(value)));
+}
+
+/// Writes a game as PGN, with the Seven Tag Roster first
+pub fn write_tree(tree: &GameTree, options: WriteOptions) -> String {
+    let mut pgn = String::new();
+    for (name, default) in SEVEN_TAG_ROSTER {
+        let value = match name {
+            "Result" => tree.header(name).unwrap_or(&tree.outcome),
+            _ => tree.header(name).unwrap_or(default),
+        };
+        tag(&mut pgn, name, value);
+    }
+    if tree.chess960 && tree.header("Variant").is_none() {
+        tag(&mut pgn, "Variant", "Chess960");
+    }
+    let custom_start = tree.fen != STARTING_FEN;
+    if options.setup_tags && custom_start && tree.header("FEN").is_none() {
+        tag(&mut pgn, "FEN", &tree.fen);
+        tag(&mut pgn, "SetUp", "1");
+    }
+    for header in &tree.headers {
+        match header.tag.as_str() {
+            name if SEVEN_TAG_ROSTER.iter().any(|(tag, _)| *tag == name) => {}
+            "FEN" | "SetUp" if !options.setup_tags => {}
+            "FEN" => {
+                tag(&mut pgn, "FEN", &tree.fen);
+                if tree.header("SetUp").is_none() {
+                    tag(&mut pgn, "SetUp", "1");
+                }
+            }
+            name => tag(&mut pgn, name, &header.value),
+        }
+    }
+    pgn.push('\n');
+
+    let mut writer = MovetextWriter {
+        options,
+        tokens: Vec::new(),
+        variation_start: false,
+    };
+    let mut comments = tree.comments.clone();
+    comments.extend(writer.shape_commands(&tree.shapes));
+    writer.comments(&comments);
+    writer.line(&tree.moves, &tree.fen, 0);
+    writer.push(tree.outcome.clone());
+    pgn.push_str(&wrap(&writer.tokens));
+    pgn.push('\n');
+    match options.newline {
+        Newline::Lf => pgn,
+        Newline::Crlf => pgn.replace("\r\n", "\n").replace('\n', "\r\n"),
+    }
+}
+
+#[tauri::command]
+#[specta::specta]
+pub fn write_pgn(tree: GameTree, options: WriteOptions) -> String {
+    write_tree(&tree, options)
+}
+
+/// Writes a game to a path the user picked, as UTF-8 without a byte order mark
+#[tauri::command]
+#[specta::specta]
+pub fn export_game(
+    tree: GameTree,
+    options: WriteOptions,
+    path: PathBuf,
+    app: AppHandle,
+) -> Result<(), Error> {
+    if !app.fs_scope().is_allowed(&path) {
+        return Err(Error::ForbiddenPath);
+    }
+    std::fs::write(path, write_tree(&tree, options))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::pgn::parse_game;
+
+    const CORPUS: [&str; 5] = [
+        r#"[Event "Rated Blitz game"]
+[Site "https://lichess.org/abcdefgh"]
+[Date "2023.04.01"]
+[Round "-"]
+[White "Alice \"The Rook\""]
+[Black "Bob"]
+[Result "0-1"]
+[TimeControl "180+0"]
+
+1. e4 { [%clk 0:03:00] } 1... e5 { [%clk 0:02:59] } 2. Nf3 { [%eval 0.3] [%clk 0:02:58] }
+2... Nc6 3. Bb5 a6 4. Ba4 Nf6 5. O-O Be7 0-1"#,
+        r#"[Event "Training"]
+[Site "Home"]
+[Date "2024.01.15"]
+[Round "1"]
+[White "A"]
+[Black "B"]
+[Result "*"]
+
+{ The Scotch } 1. e4 e5 2. Nf3 Nc6 3. d4 $1 { [%csl Gd4,Re5] [%cal Gd4e5] } (3. Bc4 Bc5
+(3... Nf6 $5 { Two knights } 4. Ng5) 4. c3) 3... exd4 $14 4. Nxd4 { Main line }
+{ Second comment } 4... Nf6 (4... Bc5 5. Be3 (5. Nxc6 Qf6 (5... bxc6)) 5... Qf6) 5. Nxc6 *"#,
+        r#"[Event "Endgame"]
+[Site "?"]
+[Date "????.??.??"]
+[Round "?"]
+[White "?"]
+[Black "?"]
+[Result "1/2-1/2"]
+[FEN "4k3/8/8/8/8/8/4P3/4K3 b - - 0 30"]
+[SetUp "1"]
+
+30... Kd7 ({ Or } 30... Ke7 31. e4 Ke6) 31. e4 Ke6 1/2-1/2"#,
+        r#"[Event "Long"]
+[Site "?"]
+[Date "????.??.??"]
+[Round "?"]
+[White "?"]
+[Black "?"]
+[Result "1-0"]
+
+1. d4 d5 2. c4 c6 3. Nf3 Nf6 4. Nc3 dxc4 5. a4 Bf5 6. e3 e6 7. Bxc4 Bb4 8. O-O O-O
+9. Qe2 Nbd7 10. e4 Bg6 11. Bd3 Bh5 12. e5 Nd5 13. Nxd5 cxd5 14. Qe3 Bg6 15. Ng5 Re8
+16. f4 Bxd3 17. Qxd3 f5 18. Be3 Nf8 19. Kh1 Rc8 20. g4 Qd7 21. Rg1 Be7 22. Nf3 Qc6 1-0"#,
+        r#"[Event "Threats"]
+[Site "?"]
+[Date "????.??.??"]
+[Round "?"]
+[White "?"]
+[Black "?"]
+[Result "*"]
+
+1. e4 e5 2. Nf3 Nc6 3. Bc4 Nf6 4. d3 (4. -- { Threatening } 4... Ng4 5. O-O Nxf2) 4... Be7 *"#,
+    ];
+
+    #[test]
+    fn round_trips() {
+        for pgn in CORPUS {
+            let tree = parse_game(pgn).unwrap();
+            let written = write_tree(&tree, WriteOptions::default());
+            assert_eq!(parse_game(&written).unwrap(), tree, "{written}");
+            assert!(written.lines().all(|line| line.len() <= LINE_WIDTH));
+        }
+    }
+
+    #[test]
+    fn writes_standard_movetext() {
+        let tree = parse_game(CORPUS[1]).unwrap();
+        assert_eq!(
+            write_tree(&tree, WriteOptions::default()),
+            r#"[Event "Training"]
+[Site "Home"]
+[Date "2024.01.15"]
+[Round "1"]
+[White "A"]
+[Black "B"]
+[Result "*"]
+
+{The Scotch} 1. e4 e5 2. Nf3 Nc6 3. d4 $1 {[%csl Gd4,Re5] [%cal Gd4e5]} (3. Bc4
+Bc5 (3... Nf6 $5 {Two knights} 4. Ng5) 4. c3) 3... exd4 $14 4. Nxd4 {Main line}
+{Second comment} 4... Nf6 (4... Bc5 5. Be3 (5. Nxc6 Qf6 (5... bxc6)) 5... Qf6)
+5. Nxc6 *
+"#
+        );
+        assert_eq!(
+            write_tree(
+                &tree,
+                WriteOptions {
+                    comments: false,
+                    max_variation_depth: Some(0),
+                    ..Default::default()
+                }
+            )
+            .lines()
+            .last(),
+            Some("1. e4 e5 2. Nf3 Nc6 3. d4 $1 exd4 $14 4. Nxd4 Nf6 5. Nxc6 *")
+        );
+    }
+
+    #[test]
+    fn fills_in_the_seven_tag_roster() {
+        let tree = parse_game("[White \"A\"]\n[Event \"E\"]\n[ECO \"C20\"]\n\n1. e4 1-0").unwrap();
+        assert_eq!(
+            write_tree(&tree, WriteOptions::default()),
+            "[Event \"E\"]\n[Site \"?\"]\n[Date \"????.??.??\"]\n[Round \"?\"]\n\
+             [White \"A\"]\n[Black \"?\"]\n[Result \"1-0\"]\n[ECO \"C20\"]\n\n1. e4 1-0\n"
+        );
+    }
+
+    #[test]
+    fn strips_evals() {
+        let tree = parse_game(CORPUS[0]).unwrap();
+        let written = write_tree(
+            &tree,
+            WriteOptions {
+                evals: false,
+                ..Default::default()
+            },
+        );
+        assert!(!written.contains("%eval"));
+        assert!(written.contains("2. Nf3 {[%clk 0:02:58]}"));
+        assert_eq!(strip_command("[%eval #-3]", "eval"), "");
+
+        let written = write_tree(
+            &tree,
+            WriteOptions {
+                clocks: false,
+                ..Default::default()
+            },
+        );
+        assert!(!written.contains("%clk"));
+        assert!(written.contains("1. e4 e5 2. Nf3 {[%eval 0.3]} 2... Nc6"));
+    }
+
+    #[test]
+    fn limits_variation_depth() {
+        let tree = parse_game(CORPUS[1]).unwrap();
+        let movetext = |depth| {
+            let written = write_tree(
+                &tree,
+                WriteOptions {
+                    comments: false,
+                    max_variation_depth: Some(depth),
+                    ..Default::default()
+                },
+            );
+            written.split("\n\n").nth(1).unwrap().replace('\n', " ")
+        };
+        assert_eq!(
+            movetext(1),
+            "1. e4 e5 2. Nf3 Nc6 3. d4 $1 (3. Bc4 Bc5 4. c3) 3... exd4 $14 4. Nxd4 Nf6 \
+             (4... Bc5 5. Be3 Qf6) 5. Nxc6 * "
+        );
+        assert!(movetext(2).contains("(5. Nxc6 Qf6) 5... Qf6"));
+    }
+
+    #[test]
+    fn setup_tags() {
+        let mut tree = parse_game("[Event \"E\"]\n\n1. e4 *").unwrap();
+        tree.fen = "4k3/8/8/8/8/8/4P3/4K3 w - - 0 1".to_string();
+        let written = write_tree(&tree, WriteOptions::default());
+        assert!(written.contains(
+            "[Result \"*\"]\n[FEN \"4k3/8/8/8/8/8/4P3/4K3 w - - 0 1\"]\n[SetUp \"1\"]\n\n"
+        ));
+
+        let tree = parse_game(CORPUS[2]).unwrap();
+        let written = write_tree(
+            &tree,
+            WriteOptions {
+                setup_tags: false,
+                ..Default::default()
+            },
+        );
+        assert!(!written.contains("FEN") && !written.contains("SetUp"));
+
+        let tree =
+            parse_game("[FEN \"4k3/8/8/8/8/8/4P3/4K3 w - - 0 20\"]\n\n20. e4 Kd7 *").unwrap();
+        let written = write_tree(&tree, WriteOptions::default());
+        assert!(written.contains("[FEN \"4k3/8/8/8/8/8/4P3/4K3 w - - 0 20\"]\n[SetUp \"1\"]\n"));
+        assert!(written.ends_with("\n\n20. e4 Kd7 *\n"));
+        assert_eq!(parse_game(&written).unwrap().moves, tree.moves);
+    }
+
+    #[test]
+    fn chess960_variant_tag() {
+        let pgn = r#"[Event "Casual Chess960"]
+[Site "?"]
+[Date "????.??.??"]
+[Round "?"]
+[White "?"]
+[Black "?"]
+[Result "*"]
+[Variant "Chess960"]
+[FEN "bqnbrkrn/pppppppp/8/8/8/8/PPPPPPPP/BQNBRKRN w KQkq - 0 1"]
+[SetUp "1"]
+
+1. O-O O-O 2. d4 *
+"#;
+        let tree = parse_game(pgn).unwrap();
+        assert_eq!(write_tree(&tree, WriteOptions::default()), pgn);
+
+        // Tagged when only the castling rights showed it was Chess960
+        let tree = parse_game(
+            "[FEN \"bqnbrkrn/pppppppp/8/8/8/8/PPPPPPPP/BQNBRKRN w GEge - 0 1\"]\n\n1. O-O *",
+        )
+        .unwrap();
+        let written = write_tree(&tree, WriteOptions::default());
+        assert!(written.contains("[Variant \"Chess960\"]\n[FEN "));
+        let reparsed = parse_game(&written).unwrap();
+        assert!(reparsed.chess960);
+        assert_eq!(reparsed.moves, tree.moves);
+    }
+
+    #[test]
+    fn writes_clock_commands() {
+        let tree = parse_game(
+            "1. e4 { [%clk 0:03:00] } 1... e5 { Solid [%emt 0:00:12] [%clk 0:02:49.5] } \
+             2. Nf3 { [%eval 0.2] [%clk 1:02] } *",
+        )
+        .unwrap();
+        let written = write_tree(&tree, WriteOptions::default());
+        assert!(written.ends_with(
+            "1. e4 {[%clk 0:03:00]} 1... e5 {Solid [%clk 0:02:49.5] [%emt 0:00:12]} 2. Nf3\n\
+             {[%eval 0.2] [%clk 0:01:02]} *\n"
+        ));
+        assert_eq!(parse_game(&written).unwrap(), tree);
+        assert_eq!(format_duration(3_723_050), "1:02:03.05");
+    }
+
+    #[test]
+    fn writes_shape_commands() {
+        let tree = parse_game(
+            "{ [%csl Gd5] } 1. e4 { Center [%cal Ge2e4,Rd7d5] [%csl Yd4] [%clk 0:03:00] } *",
+        )
+        .unwrap();
+        let written = write_tree(&tree, WriteOptions::default());
+        assert!(written.ends_with(
+            "{[%csl Gd5]} 1. e4 {Center [%csl Yd4] [%cal Ge2e4,Rd7d5] [%clk 0:03:00]} *\n"
+        ));
+        assert_eq!(parse_game(&written).unwrap(), tree);
+
+        let plain = WriteOptions {
+            comments: false,
+            ..Default::default()
+        };
+        assert!(write_tree(&tree, plain).ends_with("\n\n1. e4 *\n"));
+    }
+
+    #[test]
+    fn writes_null_moves() {
+        let tree = parse_game(CORPUS[4]).unwrap();
+        let written = write_tree(&tree, WriteOptions::default());
+        assert!(written.ends_with(
+            "1. e4 e5 2. Nf3 Nc6 3. Bc4 Nf6 4. d3 (4. -- {Threatening} 4... Ng4 5. O-O Nxf2)\n\
+             4... Be7 *\n"
+        ));
+    }
+
+    #[test]
+    fn windows_newlines() {
+        let tree = parse_game(CORPUS[3]).unwrap();
+        let written = write_tree(
+            &tree,
+            WriteOptions {
+                newline: Newline::Crlf,
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            written.matches('\n').count(),
+            written.matches("\r\n").count()
+        );
+        assert!(written.ends_with("1-0\r\n"));
+        assert_eq!(parse_game(&written).unwrap(), tree);
+    }
+}
@@ -0,0 +1,521 @@
+use std::{collections::HashMap, path::PathBuf, sync::Arc};
+
+use log::info;
+use memmap2::Mmap;
+use serde::{Deserialize, Serialize};
+use shakmaty::{
+    fen::Fen, san::SanPlus, uci::Uci, CastlingMode, Chess, Color, EnPassantMode, Move, Position,
+};
+use specta::Type;
+use vampirc_uci::uci::Score;
+
+use crate::{
+    batch::new_job_id,
+    book::{book_key, book_moves, choose_book_move, open_book, BookMove},
+    chess::{
+        kill_session, parse_position, search_move, EngineMove, EngineOption, EngineOptions, GoMode,
+    },
+    error::Error,
+    pgn::{parse_games, PgnNode},
+    AppState,
+};
+
+/// Where the moves of the opening come from
+#[derive(Deserialize, Debug, Clone, Type)]
+#[serde(tag = "t", content = "c")]
+pub enum PracticeSource {
+    /// A Polyglot book
+    Book { path: PathBuf, randomness: f64 },
+    /// A PGN file whose games and variations are the repertoire, every move weighing the
+    /// number of times it appears
+    Repertoire { path: PathBuf, randomness: f64 },
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Type)]
+pub enum PracticeColor {
+    White,
+    Black,
+}
+
+impl From<PracticeColor> for Color {
+    fn from(color: PracticeColor) -> Self {
+        match color {
+            PracticeColor::White => Color::White,
+            PracticeColor::Black => Color::Black,
+        }
+    }
+}
+
+/// The engine playing once the opening is left, its strength set through its options
+#[derive(Deserialize, Debug, Clone, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct PracticeEngine {
+    pub path: String,
+    pub go_mode: GoMode,
+    pub options: Vec<EngineOption>,
+}
+
+/// Moves of the repertoire by Polyglot key of the position they are played in
+type Repertoire = HashMap<u64, Vec<BookMove>>;
+
+enum Theory {
+    Book(Mmap),
+    Repertoire(Repertoire),
+}
+
+impl Theory {
+    fn moves(&self, pos: &Chess) -> Vec<BookMove> {
+        match self {
+            Theory::Book(book) => book_moves(book, pos),
+            Theory::Repertoire(repertoire) => {
+                repertoire.get(&book_key(pos)).cloned().unwrap_or_default()
+            }
+        }
+    }
+}
+
+fn add_repertoire_move(repertoire: &mut Repertoire, pos: &Chess, m: &Move) {
+    let uci = m.to_uci(CastlingMode::Standard).to_string();
+    let moves = repertoire.entry(book_key(pos)).or_default();
+    match moves.iter_mut().find(|known| known.uci == uci) {
+        Some(known) => known.weight = known.weight.saturating_add(1),
+        None => moves.push(BookMove {
+            uci,
+            san: SanPlus::from_move(pos.clone(), m).to_string(),
+            weight: 1,
+            learn: 0,
+            percentage: 0.0,
+        }),
+    }
+}
+
+/// Adds the moves of `line`, played from `pos`, and of its variations
+fn add_repertoire_line(
+    repertoire: &mut Repertoire,
+    mut pos: Chess,
+    line: &[PgnNode],
+) -> Result<(), Error> {
+    for node in line {
+        for variation in &node.variations {
+            add_repertoire_line(repertoire, pos.clone(), variation)?;
+        }
+        let m = Uci::from_ascii(node.uci.as_bytes())?.to_move(&pos)?;
+        add_repertoire_move(repertoire, &pos, &m);
+        pos.play_unchecked(&m);
+    }
+    Ok(())
+}
+
+/// The repertoire of the games of `pgn`, moves sorted like the ones of a book
+fn parse_repertoire(pgn: &str) -> Result<Repertoire, Error> {
+    let mut repertoire = Repertoire::new();
+    for game in parse_games(pgn)? {
+        let mode = if game.chess960 {
+            CastlingMode::Chess960
+        } else {
+            CastlingMode::Standard
+        };
+        let pos: Chess = Fen::from_ascii(game.fen.as_bytes())?.into_position(mode)?;
+        add_repertoire_line(&mut repertoire, pos, &game.moves)?;
+    }
+    for moves in repertoire.values_mut() {
+        let total: u32 = moves.iter().map(|m| u32::from(m.weight)).sum();
+        for m in moves.iter_mut() {
+            m.percentage = f64::from(m.weight) * 100.0 / f64::from(total);
+        }
+        moves.sort_by(|a, b| b.weight.cmp(&a.weight));
+    }
+    Ok(repertoire)
+}
+
+fn load_theory(source: &PracticeSource) -> Result<(Theory, f64), Error> {
+    Ok(match source {
+        PracticeSource::Book { path, randomness } => (Theory::Book(open_book(path)?), *randomness),
+        PracticeSource::Repertoire { path, randomness } => (
+            Theory::Repertoire(parse_repertoire(&std::fs::read_to_string(path)?)?),
+            *randomness,
+        ),
+    })
+}
+
+/// First move of the user that the opening doesn't have
+#[derive(Serialize, Debug, Clone, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct Deviation {
+    /// Plies played from the start of the session before the move
+    pub ply: usize,
+    /// Position the move was played in
+    pub fen: String,
+    pub uci: String,
+    pub san: String,
+    /// Moves the opening has for the position, the heaviest first
+    pub expected: Vec<String>,
+    /// Evaluation of the engine after the move, from White's point of view
+    pub eval: Option<Score>,
+}
+
+/// State of a practice session between the moves of the user
+#[derive(Clone)]
+pub struct PracticeSession {
+    theory: Arc<Theory>,
+    randomness: f64,
+    color: Color,
+    engine: PracticeEngine,
+    start_fen: String,
+    position: Chess,
+    /// UCI moves played since the start
+    moves: Vec<String>,
+    in_theory: bool,
+    /// Plies played before the opening ran out of moves without the user leaving it
+    theory_end: Option<usize>,
+    deviation: Option<Deviation>,
+}
+
+impl PracticeSession {
+    fn play(&mut self, m: &Move) {
+        self.moves
+            .push(m.to_uci(CastlingMode::Standard).to_string());
+        self.position.play_unchecked(m);
+    }
+
+    /// Checks the move of the user against the opening, then plays it
+    fn user_move(&mut self, uci: &str) -> Result<(), Error> {
+        let illegal = |reason: String| Error::IllegalMove {
+            index: self.moves.len(),
+            mov: uci.to_string(),
+            reason,
+        };
+        let m = Uci::from_ascii(uci.as_bytes())
+            .map_err(|e| illegal(e.to_string()))?
+            .to_move(&self.position)
+            .map_err(|e| illegal(e.to_string()))?;
+
+        if self.in_theory {
+            let expected = self.theory.moves(&self.position);
+            let played = m.to_uci(CastlingMode::Standard).to_string();
+            if expected.is_empty() {
+                self.in_theory = false;
+                self.theory_end = Some(self.moves.len());
+            } else if !expected.iter().any(|known| known.uci == played) {
+                self.in_theory = false;
+                self.deviation = Some(Deviation {
+                    ply: self.moves.len(),
+                    fen: Fen::from_position(self.position.clone(), EnPassantMode::Legal)
+                        .to_string(),
+                    uci: played,
+                    san: SanPlus::from_move(self.position.clone(), &m).to_string(),
+                    expected: expected.into_iter().map(|known| known.san).collect(),
+                    eval: None,
+                });
+            }
+        }
+        self.play(&m);
+        Ok(())
+    }
+
+    /// A move of the opening for the opponent, None once it's left
+    fn theory_reply(&mut self) -> Option<EngineMove> {
+        if !self.in_theory {
+            return None;
+        }
+        let moves = self.theory.moves(&self.position);
+        let Some(reply) =
+            choose_book_move(&moves, self.randomness, &mut rand::thread_rng()).cloned()
+        else {
+            self.in_theory = false;
+            self.theory_end = Some(self.moves.len());
+            return None;
+        };
+        Some(EngineMove {
+            uci: Some(reply.uci),
+            san: Some(reply.san),
+            score: None,
+            depth: 0,
+            time_ms: 0,
+            from_book: true,
+            book_exit: None,
+        })
+    }
+
+    fn fen(&self) -> String {
+        Fen::from_position(self.position.clone(), EnPassantMode::Legal).to_string()
+    }
+
+    fn theory_plies(&self) -> usize {
+        self.deviation
+            .as_ref()
+            .map(|deviation| deviation.ply)
+            .or(self.theory_end)
+            .unwrap_or(self.moves.len())
+    }
+}
+
+/// Engine session of a practice session
+fn practice_tab(id: &str) -> String {
+    format!("practice-{id}")
+}
+
+/// Plays the move of the opponent if it's their turn: from the opening while the user
+/// follows it, from the engine otherwise. The first evaluation of the engine after a
+/// deviation is the one it's recorded with.
+async fn opponent_reply(
+    id: &str,
+    session: &mut PracticeSession,
+    app: &tauri::AppHandle,
+    state: &AppState,
+) -> Result<Option<EngineMove>, Error> {
+    if session.position.turn() == session.color || session.position.is_game_over() {
+        return Ok(None);
+    }
+    let reply = match session.theory_reply() {
+        Some(reply) => reply,
+        None => {
+            let options = EngineOptions {
+                fen: session.start_fen.clone(),
+                moves: session.moves.clone(),
+                extra_options: session.engine.options.clone(),
+                tablebase: None,
+                cloud_eval: None,
+                max_pv_length: None,
+            };
+            let mut reply = search_move(
+                id.to_string(),
+                session.engine.path.clone(),
+                practice_tab(id),
+                &session.engine.go_mode,
+                options,
+                app.clone(),
+                state,
+            )
+            .await?;
+            reply.book_exit = session.theory_end.map(|ply| ply as u32);
+            if let Some(deviation) = &mut session.deviation {
+                if deviation.eval.is_none() {
+                    deviation.eval = reply.score.clone();
+                }
+            }
+            reply
+        }
+    };
+    if let Some(uci) = &reply.uci {
+        let m = Uci::from_ascii(uci.as_bytes())?.to_move(&session.position)?;
+        session.play(&m);
+    }
+    Ok(Some(reply))
+}
+
+#[derive(Serialize, Debug, Clone, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct PracticeState {
+    pub id: String,
+    pub fen: String,
+    /// UCI moves played since the start
+    pub moves: Vec<String>,
+    /// Move the opponent answered with, None when it's still the user's turn
+    pub reply: Option<EngineMove>,
+    pub in_theory: bool,
+    pub deviation: Option<Deviation>,
+    pub game_over: bool,
+}
+
+impl PracticeState {
+    fn new(id: String, session: &PracticeSession, reply: Option<EngineMove>) -> Self {
+        PracticeState {
+            id,
+            fen: session.fen(),
+            moves: session.moves.clone(),
+            reply,
+            in_theory: session.in_theory,
+            deviation: session.deviation.clone(),
+            game_over: session.position.is_game_over(),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct PracticeResult {
+    pub deviation: Option<Deviation>,
+    /// Final position
+    pub fen: String,
+    pub moves: Vec<String>,
+    /// Plies played in the opening before the user left it or it ran out of moves
+    pub theory_plies: usize,
+}
+
+/// Starts drilling the opening of `source` as `color` from `fen`, the starting position
+/// by default. The opponent replies with the moves of the opening until the user leaves
+/// it, and with the engine from then on.
+#[tauri::command]
+#[specta::specta]
+pub async fn start_practice(
+    source: PracticeSource,
+    color: PracticeColor,
+    engine: PracticeEngine,
+    fen: Option<String>,
+    app: tauri::AppHandle,
+    state: tauri::State<'_, AppState>,
+) -> Result<PracticeState, Error> {
+    let (theory, randomness) = load_theory(&source)?;
+    let start_fen = fen.unwrap_or_else(|| Fen::default().to_string());
+    let start = parse_position(&Fen::from_ascii(start_fen.as_bytes())?, &[])?;
+    let mut session = PracticeSession {
+        theory: Arc::new(theory),
+        randomness,
+        color: color.into(),
+        engine,
+        start_fen,
+        position: start,
+        moves: Vec::new(),
+        in_theory: true,
+        theory_end: None,
+        deviation: None,
+    };
+
+    let id = new_job_id(&state, "practice");
+    info!("Starting practice session {id}");
+    let reply = opponent_reply(&id, &mut session, &app, &state).await?;
+    let practice = PracticeState::new(id.clone(), &session, reply);
+    state.practice_sessions.insert(id, session);
+    Ok(practice)
+}
+
+/// Plays the UCI move of the user in a practice session, then the reply of the opponent
+#[tauri::command]
+#[specta::specta]
+pub async fn practice_move(
+    id: String,
+    uci: String,
+    app: tauri::AppHandle,
+    state: tauri::State<'_, AppState>,
+) -> Result<PracticeState, Error> {
+    let mut session = state
+        .practice_sessions
+        .get(&id)
+        .map(|session| session.clone())
+        .ok_or_else(|| Error::UnknownPracticeSession { id: id.clone() })?;
+    if session.position.turn() != session.color || session.position.is_game_over() {
+        return Err(Error::NotPracticeTurn);
+    }
+
+    session.user_move(&uci)?;
+    let reply = opponent_reply(&id, &mut session, &app, &state).await?;
+    let practice = PracticeState::new(id.clone(), &session, reply);
+    state.practice_sessions.insert(id, session);
+    Ok(practice)
+}
+
+/// Ends a practice session and stops its engine
+#[tauri::command]
+#[specta::specta]
+pub async fn end_practice(
+    id: String,
+    state: tauri::State<'_, AppState>,
+) -> Result<PracticeResult, Error> {
+    let (_, session) = state
+        .practice_sessions
+        .remove(&id)
+        .ok_or_else(|| Error::UnknownPracticeSession { id: id.clone() })?;
+    kill_session(&state, practice_tab(&id), session.engine.path.clone()).await?;
+    Ok(PracticeResult {
+        fen: session.fen(),
+        theory_plies: session.theory_plies(),
+        deviation: session.deviation,
+        moves: session.moves,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session(pgn: &str, color: Color) -> PracticeSession {
+        PracticeSession {
+            theory: Arc::new(Theory::Repertoire(parse_repertoire(pgn).unwrap())),
+            randomness: 0.0,
+            color,
+            engine: PracticeEngine {
+                path: String::new(),
+                go_mode: GoMode::Infinite,
+                options: Vec::new(),
+            },
+            start_fen: Fen::default().to_string(),
+            position: Chess::default(),
+            moves: Vec::new(),
+            in_theory: true,
+            theory_end: None,
+            deviation: None,
+        }
+    }
+
+    const REPERTOIRE: &str = "1. e4 e5 (1... c5 2. Nf3) 2. Nf3 Nc6 *\n\n1. e4 e5 2. Bc4 *\n";
+
+    #[test]
+    fn builds_repertoires_with_variations() {
+        let repertoire = parse_repertoire(REPERTOIRE).unwrap();
+        let moves = |pos: &Chess| -> Vec<(String, u16)> {
+            repertoire
+                .get(&book_key(pos))
+                .map(|moves| moves.iter().map(|m| (m.san.clone(), m.weight)).collect())
+                .unwrap_or_default()
+        };
+
+        let mut pos = Chess::default();
+        assert_eq!(moves(&pos), [("e4".to_string(), 2)]);
+        pos.play_unchecked(&Uci::from_ascii(b"e2e4").unwrap().to_move(&pos).unwrap());
+        assert_eq!(moves(&pos), [("e5".to_string(), 2), ("c5".to_string(), 1)]);
+        pos.play_unchecked(&Uci::from_ascii(b"e7e5").unwrap().to_move(&pos).unwrap());
+        let replies = moves(&pos);
+        assert_eq!(replies.len(), 2);
+        assert!(replies.contains(&("Nf3".to_string(), 1)));
+        assert!(replies.contains(&("Bc4".to_string(), 1)));
+    }
+
+    #[test]
+    fn records_the_first_deviation() {
+        let mut session = session(REPERTOIRE, Color::White);
+        session.user_move("e2e4").unwrap();
+        let reply = session.theory_reply().unwrap();
+        assert!(reply.from_book);
+        assert_eq!(reply.uci.as_deref(), Some("e7e5"));
+        session.play(
+            &Uci::from_ascii(b"e7e5")
+                .unwrap()
+                .to_move(&session.position)
+                .unwrap(),
+        );
+        assert!(session.in_theory);
+
+        session.user_move("d2d4").unwrap();
+        assert!(!session.in_theory);
+        let deviation = session.deviation.clone().unwrap();
+        assert_eq!(deviation.ply, 2);
+        assert_eq!(deviation.san, "d4");
+        assert_eq!(deviation.expected.len(), 2);
+        assert!(session.theory_reply().is_none());
+        assert_eq!(session.theory_plies(), 2);
+
+        session.play(
+            &Uci::from_ascii(b"e5d4")
+                .unwrap()
+                .to_move(&session.position)
+                .unwrap(),
+        );
+        session.user_move("g1f3").unwrap();
+        assert_eq!(session.deviation.unwrap().ply, 2);
+    }
+
+    #[test]
+    fn ends_theory_without_deviation() {
+        let mut session = session("1. e4 *", Color::White);
+        session.user_move("e2e4").unwrap();
+        assert!(session.theory_reply().is_none());
+        assert!(!session.in_theory);
+        assert!(session.deviation.is_none());
+        assert_eq!(session.theory_end, Some(1));
+        assert!(matches!(
+            session.user_move("e2e5"),
+            Err(Error::IllegalMove { index: 1, .. })
+        ));
+    }
+}
@@ -1,24 +1,154 @@
-use std::{collections::VecDeque, path::PathBuf, sync::Mutex};
+use std::{
+    collections::VecDeque,
+    fs::{remove_file, File},
+    io::Read,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
 
-use diesel::{dsl::sql, sql_types::Bool, Connection, ExpressionMethods, QueryDsl, RunQueryDsl};
+use diesel::{
+    connection::SimpleConnection,
+    dsl::sql,
+    insert_into,
+    sql_types::{BigInt, Bool, Text},
+    BoolExpressionMethods, Connection, ExpressionMethods, QueryDsl, RunQueryDsl, SqliteConnection,
+};
 use once_cell::sync::Lazy;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+use shakmaty::{fen::Fen, san::SanPlus, uci::Uci, CastlingMode, Chess};
+use specta::Type;
 use tauri::{
     api::path::{resolve_path, BaseDirectory},
     Manager,
 };
+use tauri_specta::Event;
 
 use crate::{
-    db::{puzzles, Puzzle},
+    db::{puzzle_progress, puzzles, CountingReader, DatabaseProgress, NewPuzzle, Puzzle},
     error::Error,
 };
 
+/// Puzzles read from the CSV file by a single transaction, between two progress events
+const IMPORT_BATCH_SIZE: usize = 50_000;
+
+/// Puzzles inserted by a single statement, below the limit of variables of SQLite
+const INSERT_CHUNK_SIZE: usize = 1_000;
+
+const CREATE_PUZZLES_SQL: &str = "
+    CREATE TABLE puzzles (
+        id INTEGER PRIMARY KEY,
+        fen TEXT NOT NULL,
+        moves TEXT NOT NULL,
+        rating INTEGER NOT NULL,
+        rating_deviation INTEGER NOT NULL,
+        popularity INTEGER NOT NULL,
+        nb_plays INTEGER NOT NULL,
+        themes TEXT NOT NULL DEFAULT ''
+    );
+";
+
+const PROGRESS_SQL: &str = "
+    CREATE TABLE IF NOT EXISTS puzzle_progress (
+        puzzle_id INTEGER PRIMARY KEY,
+        attempts INTEGER NOT NULL,
+        solved BOOLEAN NOT NULL
+    );
+";
+
+/// Which puzzles `get_puzzle` picks from, any rating by default
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq, Type)]
+#[serde(rename_all = "camelCase", default)]
+pub struct PuzzleQuery {
+    pub min_rating: u16,
+    pub max_rating: u16,
+    /// Lichess themes the puzzle must all have, like `fork` or `mateIn2`
+    pub themes: Vec<String>,
+    /// Leaves out the puzzles already attempted with `mark_puzzle`
+    pub exclude_seen: bool,
+}
+
+impl Default for PuzzleQuery {
+    fn default() -> Self {
+        Self {
+            min_rating: 0,
+            max_rating: u16::MAX,
+            themes: Vec::new(),
+            exclude_seen: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Type)]
+pub struct PuzzleWithSolution {
+    #[serde(flatten)]
+    pub puzzle: Puzzle,
+    /// The moves of the puzzle in SAN, the first one being the move of the opponent
+    /// that sets it up
+    pub san: Vec<String>,
+}
+
+/// Adds what older puzzle databases don't have, the themes and the progress table
+fn prepare(db: &mut SqliteConnection) -> Result<(), Error> {
+    let themes: i64 = diesel::select(sql::<BigInt>(
+        "(SELECT COUNT(*) FROM pragma_table_info('puzzles') WHERE name = 'themes')",
+    ))
+    .get_result(db)?;
+    if themes == 0 {
+        db.batch_execute("ALTER TABLE puzzles ADD COLUMN themes TEXT NOT NULL DEFAULT '';")?;
+    }
+    db.batch_execute(PROGRESS_SQL)?;
+    Ok(())
+}
+
+fn find_puzzles(
+    db: &mut SqliteConnection,
+    query: &PuzzleQuery,
+    limit: i64,
+) -> Result<Vec<Puzzle>, Error> {
+    let mut matching = puzzles::table
+        .filter(puzzles::rating.le(query.max_rating as i32))
+        .filter(puzzles::rating.ge(query.min_rating as i32))
+        .into_boxed();
+    for theme in &query.themes {
+        // Themes are single words, so anything else can't match and would be a wildcard
+        let theme: String = theme.chars().filter(|c| c.is_alphanumeric()).collect();
+        let pattern = format!("% {theme} %");
+        matching =
+            matching.filter(sql::<Bool>("(' ' || themes || ' ') LIKE ").bind::<Text, _>(pattern));
+    }
+    if query.exclude_seen {
+        matching = matching.filter(diesel::dsl::not(
+            puzzles::id.eq_any(puzzle_progress::table.select(puzzle_progress::puzzle_id)),
+        ));
+    }
+    Ok(matching
+        .order(sql::<Bool>("RANDOM()"))
+        .limit(limit)
+        .load::<Puzzle>(db)?)
+}
+
+/// The UCI moves of a puzzle in SAN, played from its FEN
+fn solution_san(fen: &str, moves: &str) -> Result<Vec<String>, Error> {
+    let mut position: Chess =
+        Fen::from_ascii(fen.as_bytes())?.into_position(CastlingMode::Standard)?;
+    moves
+        .split_whitespace()
+        .map(|uci| -> Result<String, Error> {
+            let m = Uci::from_ascii(uci.as_bytes())?.to_move(&position)?;
+            Ok(SanPlus::from_move_and_play_unchecked(&mut position, &m).to_string())
+        })
+        .collect()
+}
+
 #[derive(Debug)]
 struct PuzzleCache {
     cache: VecDeque<Puzzle>,
     counter: usize,
-    min_rating: u16,
-    max_rating: u16,
+    file: String,
+    query: PuzzleQuery,
 }
 
 impl PuzzleCache {
@@ -26,36 +156,24 @@ impl PuzzleCache {
         Self {
             cache: VecDeque::new(),
             counter: 0,
-            min_rating: 0,
-            max_rating: 0,
+            file: String::new(),
+            query: PuzzleQuery::default(),
         }
     }
 
-    fn get_puzzles(
-        &mut self,
-        file: &str,
-        min_rating: u16,
-        max_rating: u16,
-    ) -> Result<(), Error> {
-        if self.cache.is_empty()
-            || self.min_rating != min_rating
-            || self.max_rating != max_rating
-            || self.counter >= 20
+    fn get_puzzles(&mut self, file: &str, query: &PuzzleQuery) -> Result<(), Error> {
+        if self.cache.is_empty() || self.file != file || self.query != *query || self.counter >= 20
         {
             self.cache.clear();
             self.counter = 0;
 
-            let mut db = diesel::SqliteConnection::establish(file).expect("open database");
-            let new_puzzles = puzzles::table
-                .filter(puzzles::rating.le(max_rating as i32))
-                .filter(puzzles::rating.ge(min_rating as i32))
-                .order(sql::<Bool>("RANDOM()"))
-                .limit(20)
-                .load::<Puzzle>(&mut db)?;
+            let mut db = diesel::SqliteConnection::establish(file)?;
+            prepare(&mut db)?;
+            let new_puzzles = find_puzzles(&mut db, query, 20)?;
 
             self.cache = new_puzzles.into_iter().collect();
-            self.min_rating = min_rating;
-            self.max_rating = max_rating;
+            self.file = file.to_string();
+            self.query = query.clone();
         }
 
         Ok(())
@@ -71,14 +189,162 @@ impl PuzzleCache {
     }
 }
 
+/// A random puzzle matching `query`, with its moves in SAN
 #[tauri::command]
 #[specta::specta]
-pub fn get_puzzle(file: String, min_rating: u16, max_rating: u16) -> Result<Puzzle, Error> {
+pub fn get_puzzle(file: String, query: PuzzleQuery) -> Result<PuzzleWithSolution, Error> {
     static PUZZLE_CACHE: Lazy<Mutex<PuzzleCache>> = Lazy::new(|| Mutex::new(PuzzleCache::new()));
 
     let mut cache = PUZZLE_CACHE.lock().unwrap();
-    cache.get_puzzles(&file, min_rating, max_rating)?;
-    cache.get_next_puzzle().ok_or(Error::NoPuzzles)
+    cache.get_puzzles(&file, &query)?;
+    let puzzle = cache.get_next_puzzle().ok_or(Error::NoPuzzles)?;
+    Ok(PuzzleWithSolution {
+        san: solution_san(&puzzle.fen, &puzzle.moves)?,
+        puzzle,
+    })
+}
+
+/// Records an attempt at a puzzle, which stays solved once it was
+#[tauri::command]
+#[specta::specta]
+pub fn mark_puzzle(file: String, id: i32, solved: bool) -> Result<(), Error> {
+    let mut db = diesel::SqliteConnection::establish(&file)?;
+    prepare(&mut db)?;
+    record_attempt(&mut db, id, solved)
+}
+
+fn record_attempt(db: &mut SqliteConnection, id: i32, solved: bool) -> Result<(), Error> {
+    insert_into(puzzle_progress::table)
+        .values((
+            puzzle_progress::puzzle_id.eq(id),
+            puzzle_progress::attempts.eq(1),
+            puzzle_progress::solved.eq(solved),
+        ))
+        .on_conflict(puzzle_progress::puzzle_id)
+        .do_update()
+        .set((
+            puzzle_progress::attempts.eq(puzzle_progress::attempts + 1),
+            puzzle_progress::solved.eq(puzzle_progress::solved.or(solved)),
+        ))
+        .execute(db)?;
+    Ok(())
+}
+
+/// A row of the lichess puzzle dump, whose other columns aren't kept
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct PuzzleRecord {
+    #[serde(rename = "FEN")]
+    fen: String,
+    moves: String,
+    rating: i32,
+    rating_deviation: i32,
+    popularity: i32,
+    nb_plays: i32,
+    #[serde(default)]
+    themes: String,
+}
+
+fn insert_puzzles(db: &mut SqliteConnection, records: &[PuzzleRecord]) -> Result<(), Error> {
+    db.transaction(|db| {
+        for chunk in records.chunks(INSERT_CHUNK_SIZE) {
+            let puzzles: Vec<NewPuzzle> = chunk
+                .iter()
+                .map(|record| NewPuzzle {
+                    fen: &record.fen,
+                    moves: &record.moves,
+                    rating: record.rating,
+                    rating_deviation: record.rating_deviation,
+                    popularity: record.popularity,
+                    nb_plays: record.nb_plays,
+                    themes: &record.themes,
+                })
+                .collect();
+            insert_into(puzzles::table).values(&puzzles).execute(db)?;
+        }
+        Ok(())
+    })
+}
+
+/// Streams the puzzles of a CSV file into a new puzzle database, a transaction per
+/// batch so the file is never held in memory. Returns the number of puzzles.
+fn import_csv(
+    db: &mut SqliteConnection,
+    csv: impl Read,
+    mut progress: impl FnMut(usize),
+) -> Result<usize, Error> {
+    db.batch_execute(CREATE_PUZZLES_SQL)?;
+    db.batch_execute(PROGRESS_SQL)?;
+    let mut reader = csv::Reader::from_reader(csv);
+    let mut batch = Vec::with_capacity(IMPORT_BATCH_SIZE);
+    let mut imported = 0;
+    for record in reader.deserialize::<PuzzleRecord>() {
+        batch.push(record?);
+        if batch.len() == IMPORT_BATCH_SIZE {
+            insert_puzzles(db, &batch)?;
+            imported += batch.len();
+            batch.clear();
+            progress(imported);
+        }
+    }
+    insert_puzzles(db, &batch)?;
+    imported += batch.len();
+    db.batch_execute("CREATE INDEX puzzles_rating_idx ON puzzles(rating);")?;
+    progress(imported);
+    Ok(imported)
+}
+
+/// Imports the lichess puzzle dump, compressed with zstd or not, into the puzzle
+/// database `db_name`. Progress is emitted as `DatabaseProgress` with the path of the
+/// database as id, and a failed import deletes the database.
+#[tauri::command]
+#[specta::specta]
+pub async fn import_puzzles(
+    csv_path: PathBuf,
+    db_name: String,
+    app: tauri::AppHandle,
+) -> Result<usize, Error> {
+    let path = resolve_path(
+        &app.config(),
+        app.package_info(),
+        &app.env(),
+        PathBuf::from("puzzles").join(format!("{db_name}.db3")),
+        Some(BaseDirectory::AppData),
+    )?;
+    if path.exists() {
+        return Err(Error::DatabaseExists {
+            path: path.to_string_lossy().into_owned(),
+        });
+    }
+
+    let file = File::open(&csv_path)?;
+    let file_size = file.metadata()?.len().max(1);
+    let bytes_read = Arc::new(AtomicU64::new(0));
+    let file = CountingReader {
+        inner: file,
+        read: bytes_read.clone(),
+    };
+    let csv: Box<dyn Read + Send> = if csv_path.extension() == Some("zst".as_ref()) {
+        Box::new(zstd::Decoder::new(file)?)
+    } else {
+        Box::new(file)
+    };
+
+    let id = path.to_string_lossy().into_owned();
+    let mut db = SqliteConnection::establish(&id)?;
+    let result = import_csv(&mut db, csv, |_| {
+        let read = bytes_read.load(Ordering::Relaxed).min(file_size);
+        let _ = DatabaseProgress {
+            id: id.clone(),
+            progress: read as f64 / file_size as f64 * 100.0,
+        }
+        .emit_all(&app);
+    });
+    if result.is_err() {
+        drop(db);
+        let _ = remove_file(&path);
+    }
+    result
 }
 
 #[derive(Serialize)]
@@ -105,8 +371,7 @@ pub async fn get_puzzle_db_info(
         Some(BaseDirectory::AppData),
     )?;
 
-    let mut db =
-        diesel::SqliteConnection::establish(&path.to_string_lossy()).expect("open database");
+    let mut db = diesel::SqliteConnection::establish(&path.to_string_lossy())?;
 
     let puzzle_count = puzzles::table.count().get_result::<i64>(&mut db)? as usize;
 
@@ -121,3 +386,99 @@ pub async fn get_puzzle_db_info(
         path: path.to_string_lossy().to_string(),
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CSV: &str = "PuzzleId,FEN,Moves,Rating,RatingDeviation,Popularity,NbPlays,Themes,GameUrl,OpeningTags\n\
+                       00008,r6k/pp2r2p/4Rp1Q/3p4/8/1N1P2R1/PqP2bPP/7K b - - 0 24,f2g3 e6e7 b2b1 b3c1 b1c1 h6c1,1913,75,94,6612,crushing hangingPiece long middlegame,https://lichess.org/787zsVup/black#48,\n\
+                       0000D,5rk1/1p3ppp/pq3b2/8/8/1P1Q1N2/P4PPP/3R2K1 w - - 2 27,d3d6 f8d8 d6d8 f6d8,1580,73,97,24993,advantage endgame short,https://lichess.org/F8M8OS71#53,\n\
+                       0009B,r2qr1k1/b1p2ppp/pp4n1/P1P1p3/4P1n1/B2P2Pb/3NBP1P/RN1QR1K1 b - - 1 16,b6c5 e2g4 h3g4 d1g4,1118,76,95,9083,advantage middlegame short,https://lichess.org/4MWQCxQ6/black#32,Kings_Pawn_Game";
+
+    fn database() -> SqliteConnection {
+        let mut db = SqliteConnection::establish(":memory:").unwrap();
+        let mut batches = Vec::new();
+        let imported =
+            import_csv(&mut db, CSV.as_bytes(), |puzzles| batches.push(puzzles)).unwrap();
+        assert_eq!(imported, 3);
+        assert_eq!(batches, [3]);
+        db
+    }
+
+    fn ratings(db: &mut SqliteConnection, query: PuzzleQuery) -> Vec<i32> {
+        let mut ratings: Vec<i32> = find_puzzles(db, &query, 20)
+            .unwrap()
+            .into_iter()
+            .map(|puzzle| puzzle.rating)
+            .collect();
+        ratings.sort();
+        ratings
+    }
+
+    #[test]
+    fn queries_puzzles_by_rating_and_theme() {
+        let db = &mut database();
+        let all = PuzzleQuery {
+            min_rating: 1000,
+            max_rating: 2000,
+            ..Default::default()
+        };
+        assert_eq!(ratings(db, all.clone()), [1118, 1580, 1913]);
+        // Leaving out a bound of the rating leaves it open
+        let above: PuzzleQuery = serde_json::from_str(r#"{"minRating": 1500}"#).unwrap();
+        assert_eq!(ratings(db, above), [1580, 1913]);
+
+        let middlegame = PuzzleQuery {
+            themes: vec!["middlegame".to_string(), "short".to_string()],
+            ..all.clone()
+        };
+        assert_eq!(ratings(db, middlegame), [1118]);
+        // A theme only matches whole
+        let partial = PuzzleQuery {
+            themes: vec!["middle".to_string()],
+            ..all.clone()
+        };
+        assert!(ratings(db, partial).is_empty());
+
+        let unseen = PuzzleQuery {
+            exclude_seen: true,
+            ..all
+        };
+        record_attempt(db, 1, false).unwrap();
+        record_attempt(db, 1, true).unwrap();
+        record_attempt(db, 1, false).unwrap();
+        assert_eq!(ratings(db, unseen), [1118, 1580]);
+        let progress: (i32, bool) = puzzle_progress::table
+            .select((puzzle_progress::attempts, puzzle_progress::solved))
+            .first(db)
+            .unwrap();
+        assert_eq!(progress, (3, true));
+    }
+
+    #[test]
+    fn converts_solutions_to_san() {
+        let db = &mut database();
+        let puzzle: Puzzle = puzzles::table.find(2).first(db).unwrap();
+        assert_eq!(
+            solution_san(&puzzle.fen, &puzzle.moves).unwrap(),
+            ["Qd6", "Rd8", "Qxd8+", "Bxd8"]
+        );
+        assert!(solution_san(&puzzle.fen, "d3d6 d3d6").is_err());
+    }
+
+    #[test]
+    fn adds_themes_to_older_databases() {
+        let db = &mut SqliteConnection::establish(":memory:").unwrap();
+        db.batch_execute(
+            "CREATE TABLE puzzles (id INTEGER PRIMARY KEY, fen TEXT, moves TEXT, rating INTEGER,
+             rating_deviation INTEGER, popularity INTEGER, nb_plays INTEGER);
+             INSERT INTO puzzles VALUES (1, '8/8/8/8/8/8/8/K1k5 w - - 0 1', 'a1a2', 1500, 0, 0, 0);",
+        )
+        .unwrap();
+        prepare(db).unwrap();
+        prepare(db).unwrap();
+        let puzzle: Puzzle = puzzles::table.first(db).unwrap();
+        assert_eq!(puzzle.themes, "");
+    }
+}
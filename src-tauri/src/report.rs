@@ -0,0 +1,186 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::PathBuf,
+};
+
+use serde::{Deserialize, Serialize};
+use shakmaty::{fen::Fen, Color};
+use specta::Type;
+use tauri::{AppHandle, Manager};
+
+use crate::{
+    analysis::{win_percent, Classification, Eval, GameAnalysis},
+    error::Error,
+};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Type)]
+#[serde(rename_all = "camelCase")]
+pub enum ReportFormat {
+    Json,
+    Csv,
+}
+
+const CSV_HEADER: [&str; 6] = [
+    "move",
+    "san",
+    "eval",
+    "win_percent",
+    "classification",
+    "time_spent",
+];
+
+/// Evaluation in pawns from White's point of view, `#n` for mates or the result once
+/// the game is over
+fn format_eval(eval: Eval) -> String {
+    match eval {
+        Eval::Cp(cp) => format!("{:.2}", cp as f64 / 100.0),
+        Eval::Mate(moves) => format!("#{moves}"),
+        Eval::Over(Some(Color::White)) => "1-0".to_string(),
+        Eval::Over(Some(Color::Black)) => "0-1".to_string(),
+        Eval::Over(None) => "1/2-1/2".to_string(),
+    }
+}
+
+fn format_classification(classification: Classification) -> &'static str {
+    match classification {
+        Classification::Inaccuracy => "inaccuracy",
+        Classification::Mistake => "mistake",
+        Classification::Blunder => "blunder",
+    }
+}
+
+/// One row per move in the order of [`CSV_HEADER`]. Move numbers are `1.` for White
+/// and `1...` for Black.
+fn csv_rows(report: &GameAnalysis) -> Result<Vec<[String; 6]>, Error> {
+    let mut rows = Vec::new();
+    for (ply, window) in report.moves.windows(2).enumerate() {
+        let (before, after) = (&window[0], &window[1]);
+        let setup = before.fen.parse::<Fen>()?.into_setup();
+        let number = match setup.turn {
+            Color::White => format!("{}.", setup.fullmoves),
+            Color::Black => format!("{}...", setup.fullmoves),
+        };
+        let eval = after.eval();
+        rows.push([
+            number,
+            after.san.clone().unwrap_or_default(),
+            eval.map(format_eval).unwrap_or_default(),
+            eval.map(|eval| format!("{:.1}", win_percent(eval)))
+                .unwrap_or_default(),
+            after
+                .classification
+                .map(format_classification)
+                .unwrap_or_default()
+                .to_string(),
+            report
+                .time_usage
+                .get(ply + 1)
+                .copied()
+                .flatten()
+                .map(|seconds| format!("{seconds:.1}"))
+                .unwrap_or_default(),
+        ]);
+    }
+    Ok(rows)
+}
+
+fn write_csv<W: Write>(report: &GameAnalysis, writer: W) -> Result<(), Error> {
+    let mut writer = csv::Writer::from_writer(writer);
+    writer.write_record(CSV_HEADER)?;
+    for row in csv_rows(report)? {
+        writer.write_record(&row)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Writes a game report to a path the user picked, as the full JSON structure or as a
+/// CSV table of the moves
+#[tauri::command]
+#[specta::specta]
+pub fn export_report(
+    report: GameAnalysis,
+    format: ReportFormat,
+    path: PathBuf,
+    app: AppHandle,
+) -> Result<(), Error> {
+    if !app.fs_scope().is_allowed(&path) {
+        return Err(Error::ForbiddenPath);
+    }
+    let file = BufWriter::new(File::create(&path)?);
+    match format {
+        ReportFormat::Json => {
+            serde_json::to_writer_pretty(file, &report).map_err(std::io::Error::from)?
+        }
+        ReportFormat::Csv => write_csv(&report, file)?,
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use vampirc_uci::uci::{Score, ScoreValue};
+
+    use crate::analysis::MoveAnalysis;
+
+    fn analyzed_move(fen: &str, san: Option<&str>, value: Option<ScoreValue>) -> MoveAnalysis {
+        MoveAnalysis {
+            fen: fen.to_string(),
+            san: san.map(String::from),
+            score: value.map(|value| Score {
+                value,
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    fn report() -> GameAnalysis {
+        let mut moves = vec![
+            analyzed_move(
+                "r1bqkb1r/pppp1ppp/2n2n2/4p2Q/2B1P3/8/PPPP1PPP/RNB1K1NR w KQkq - 4 4",
+                None,
+                Some(ScoreValue::Mate(1)),
+            ),
+            analyzed_move(
+                "r1bqkb1r/pppp1Qpp/2n2n2/4p3/2B1P3/8/PPPP1PPP/RNB1K1NR b KQkq - 0 4",
+                Some("Qxf7#"),
+                None,
+            ),
+        ];
+        moves[1].classification = Some(Classification::Blunder);
+        GameAnalysis {
+            moves,
+            time_usage: vec![None, Some(3.5)],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn csv_has_a_row_per_move() {
+        let mut out = Vec::new();
+        write_csv(&report(), &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "move,san,eval,win_percent,classification,time_spent\n\
+             4.,Qxf7#,1-0,100.0,blunder,3.5\n"
+        );
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let json = serde_json::to_string(&report()).unwrap();
+        let parsed: GameAnalysis = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.moves.len(), 2);
+        assert_eq!(parsed.moves[1].san.as_deref(), Some("Qxf7#"));
+        assert_eq!(
+            parsed.moves[1].classification,
+            Some(Classification::Blunder)
+        );
+        assert_eq!(parsed.time_usage, vec![None, Some(3.5)]);
+        assert_eq!(serde_json::to_string(&parsed).unwrap(), json);
+    }
+}
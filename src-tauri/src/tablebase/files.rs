@@ -0,0 +1,588 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    io::{BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use futures_util::StreamExt;
+use log::info;
+use reqwest::{header::RANGE, Client, StatusCode};
+use serde::Serialize;
+use specta::Type;
+use sysinfo::{DiskExt, System, SystemExt};
+use tauri::{
+    api::path::{resolve_path, BaseDirectory},
+    AppHandle, Manager,
+};
+use tauri_specta::Event;
+
+use crate::{
+    chess::resend_option, engines::file_sha256, error::Error, online::ensure_online,
+    tablebase::TablebaseError, AppState,
+};
+
+const MIRROR: &str = "https://tablebase.lichess.ovh/tables/standard";
+
+/// Fewest and most pieces of the tables that can be downloaded
+const PIECE_COUNTS: std::ops::RangeInclusive<u8> = 3..=6;
+
+/// Rough size of all the WDL and DTZ tables with a number of pieces, for the disk space
+/// check before downloading
+const SET_SIZES: [(u8, u64); 4] = [
+    (3, 100_000),
+    (4, 30_000_000),
+    (5, 910_000_000),
+    (6, 149_200_000_000),
+];
+
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Pieces other than kings, from the strongest
+const PIECES: [char; 5] = ['Q', 'R', 'B', 'N', 'P'];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TableKind {
+    Wdl,
+    Dtz,
+}
+
+impl TableKind {
+    fn extension(self) -> &'static str {
+        match self {
+            TableKind::Wdl => "rtbw",
+            TableKind::Dtz => "rtbz",
+        }
+    }
+
+    /// First bytes of every table of this kind
+    fn magic(self) -> [u8; 4] {
+        match self {
+            TableKind::Wdl => [0x71, 0xe8, 0x23, 0x5d],
+            TableKind::Dtz => [0xd7, 0x66, 0x0c, 0xa5],
+        }
+    }
+
+    fn of(file: &str) -> Option<Self> {
+        match file.rsplit_once('.')?.1 {
+            "rtbw" => Some(TableKind::Wdl),
+            "rtbz" => Some(TableKind::Dtz),
+            _ => None,
+        }
+    }
+}
+
+/// Folder of the mirror with the tables of a number of pieces
+fn mirror_folder(pieces: u8, kind: TableKind) -> &'static str {
+    match (pieces, kind) {
+        (..=5, _) => "3-4-5",
+        (_, TableKind::Wdl) => "6-wdl",
+        (_, TableKind::Dtz) => "6-dtz",
+    }
+}
+
+/// The ways to pick `count` pieces, strongest first
+fn sides(count: usize) -> Vec<Vec<usize>> {
+    if count == 0 {
+        return vec![vec![]];
+    }
+    sides(count - 1)
+        .into_iter()
+        .flat_map(|side| {
+            let from = side.last().copied().unwrap_or(0);
+            (from..PIECES.len()).map(move |piece| {
+                let mut side = side.clone();
+                side.push(piece);
+                side
+            })
+        })
+        .collect()
+}
+
+fn side_name(side: &[usize]) -> String {
+    std::iter::once('K')
+        .chain(side.iter().map(|&piece| PIECES[piece]))
+        .collect()
+}
+
+/// Materials of the tables with `pieces` pieces in the naming of Syzygy, with the side
+/// of more pieces first, then the side of stronger pieces
+fn materials(pieces: u8) -> Vec<String> {
+    let others = usize::from(pieces).saturating_sub(2);
+    let mut materials = Vec::new();
+    for black in 0..=others / 2 {
+        let white = others - black;
+        for strong in sides(white) {
+            for weak in sides(black) {
+                if white == black && strong > weak {
+                    continue;
+                }
+                materials.push(format!("{}v{}", side_name(&strong), side_name(&weak)));
+            }
+        }
+    }
+    materials
+}
+
+/// Files of the WDL and DTZ tables with `pieces` pieces
+fn table_files(pieces: u8) -> Vec<String> {
+    materials(pieces)
+        .into_iter()
+        .flat_map(|material| {
+            [TableKind::Wdl, TableKind::Dtz].map(|kind| format!("{material}.{}", kind.extension()))
+        })
+        .collect()
+}
+
+/// Size of the table at `path`, if it's there in full
+fn table_size(path: &Path) -> Option<u64> {
+    fs::metadata(path)
+        .ok()
+        .filter(|metadata| metadata.is_file() && metadata.len() > 0)
+        .map(|metadata| metadata.len())
+}
+
+fn part_path(path: &Path) -> PathBuf {
+    let mut part = path.as_os_str().to_owned();
+    part.push(".part");
+    PathBuf::from(part)
+}
+
+#[derive(Debug, Clone, Serialize, Type, PartialEq, Eq)]
+pub struct TablebaseSet {
+    pub pieces: u8,
+    /// Tables with this many pieces, each made of a WDL and a DTZ file
+    pub tables: usize,
+    /// WDL files present
+    pub wdl: usize,
+    /// DTZ files present
+    pub dtz: usize,
+    /// Bytes used by the files present
+    pub size: u64,
+    pub missing: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Type, PartialEq, Eq)]
+pub struct TablebaseFiles {
+    pub dir: PathBuf,
+    pub sets: Vec<TablebaseSet>,
+}
+
+fn list_sets(dir: &Path) -> Vec<TablebaseSet> {
+    PIECE_COUNTS
+        .map(|pieces| {
+            let mut set = TablebaseSet {
+                pieces,
+                tables: materials(pieces).len(),
+                wdl: 0,
+                dtz: 0,
+                size: 0,
+                missing: Vec::new(),
+            };
+            for file in table_files(pieces) {
+                match table_size(&dir.join(&file)) {
+                    Some(size) => {
+                        set.size += size;
+                        match TableKind::of(&file) {
+                            Some(TableKind::Wdl) => set.wdl += 1,
+                            Some(TableKind::Dtz) => set.dtz += 1,
+                            None => {}
+                        }
+                    }
+                    None => set.missing.push(file),
+                }
+            }
+            set
+        })
+        .collect()
+}
+
+/// Bytes the missing files of `sets` should take once downloaded, less what was already
+/// downloaded of them
+fn needed_space(dir: &Path, sets: &[TablebaseSet]) -> u64 {
+    sets.iter()
+        .map(|set| {
+            let set_size = SET_SIZES
+                .iter()
+                .find(|(pieces, _)| *pieces == set.pieces)
+                .map_or(0, |(_, size)| *size);
+            let file_size = set_size / (set.tables as u64 * 2).max(1);
+            set.missing
+                .iter()
+                .map(|file| {
+                    let partial = fs::metadata(part_path(&dir.join(file))).map_or(0, |m| m.len());
+                    file_size.saturating_sub(partial)
+                })
+                .sum::<u64>()
+        })
+        .sum()
+}
+
+/// Free space of the disk `dir` is on, when it can be found
+fn available_space(dir: &Path) -> Option<u64> {
+    let dir = dir.canonicalize().ok()?;
+    let mut system = System::new();
+    system.refresh_disks_list();
+    system
+        .disks()
+        .iter()
+        .filter(|disk| dir.starts_with(disk.mount_point()))
+        .max_by_key(|disk| disk.mount_point().as_os_str().len())
+        .map(|disk| disk.available_space())
+}
+
+/// Tablebase directory of the app data, used when no directory is given
+fn tablebase_dir(dir: Option<PathBuf>, app: &AppHandle) -> Result<PathBuf, Error> {
+    match dir {
+        Some(dir) if !app.fs_scope().is_allowed(&dir) => Err(Error::ForbiddenPath),
+        Some(dir) => Ok(dir),
+        None => Ok(resolve_path(
+            &app.config(),
+            app.package_info(),
+            &app.env(),
+            "tablebases",
+            Some(BaseDirectory::AppData),
+        )?),
+    }
+}
+
+/// Which of the 3 to 6 piece Syzygy files are in `dir`, the tablebase directory of the
+/// app data by default
+#[tauri::command]
+#[specta::specta]
+pub fn list_tablebase_files(dir: Option<PathBuf>, app: AppHandle) -> Result<TablebaseFiles, Error> {
+    let dir = tablebase_dir(dir, &app)?;
+    Ok(TablebaseFiles {
+        sets: list_sets(&dir),
+        dir,
+    })
+}
+
+/// SHA-256 sums of a folder of the mirror, when it publishes a `SHA256SUMS` list
+async fn published_checksums(client: &Client, folder: &str) -> HashMap<String, String> {
+    let list = async {
+        client
+            .get(format!("{MIRROR}/{folder}/SHA256SUMS"))
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await
+    };
+    let Ok(list) = list.await else {
+        return HashMap::new();
+    };
+    list.lines()
+        .filter_map(|line| {
+            let (sum, file) = line.split_once(char::is_whitespace)?;
+            let file = file.trim().trim_start_matches('*').trim_start_matches("./");
+            Some((file.to_string(), sum.to_lowercase()))
+        })
+        .collect()
+}
+
+/// Checks that the downloaded `part` is a table of `file`, with the published SHA-256
+/// when there is one, and deletes it when it isn't
+fn verify_table(part: &Path, file: &str, expected: Option<&str>) -> Result<(), Error> {
+    let corrupted = |reason: String| {
+        let _ = fs::remove_file(part);
+        Error::from(TablebaseError::CorruptedTable {
+            material: file.to_string(),
+            reason,
+        })
+    };
+    let mut magic = [0; 4];
+    File::open(part)?
+        .read_exact(&mut magic)
+        .map_err(|e| corrupted(e.to_string()))?;
+    if TableKind::of(file).is_some_and(|kind| kind.magic() != magic) {
+        return Err(corrupted("the download isn't a Syzygy table".to_string()));
+    }
+    if let Some(expected) = expected {
+        let actual = file_sha256(part)?;
+        if !expected.eq_ignore_ascii_case(&actual) {
+            let _ = fs::remove_file(part);
+            return Err(Error::ChecksumMismatch {
+                expected: expected.to_string(),
+                actual,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Downloads `url` to the `.part` file next to `path`, continuing a previous download
+/// when the mirror supports ranges, and moves it to `path` once it is verified. Returns
+/// the bytes downloaded.
+async fn download_table(
+    client: &Client,
+    url: &str,
+    path: &Path,
+    expected: Option<&str>,
+    cancelled: &AtomicBool,
+    mut progress: impl FnMut(u64, Option<u64>),
+) -> Result<u64, Error> {
+    let part = part_path(path);
+    let resumed = fs::metadata(&part).map_or(0, |metadata| metadata.len());
+    let mut request = client.get(url);
+    if resumed > 0 {
+        request = request.header(RANGE, format!("bytes={resumed}-"));
+    }
+    let res = request.send().await?;
+    let mut downloaded = 0;
+    // the part is already complete when the range starts at its end
+    if res.status() != StatusCode::RANGE_NOT_SATISFIABLE {
+        let res = res.error_for_status()?;
+        let file = if res.status() == StatusCode::PARTIAL_CONTENT {
+            downloaded = resumed;
+            OpenOptions::new().append(true).open(&part)?
+        } else {
+            File::create(&part)?
+        };
+        let total = res.content_length().map(|length| length + downloaded);
+        let mut file = BufWriter::new(file);
+        let mut stream = res.bytes_stream();
+        while let Some(chunk) = stream.next().await {
+            if cancelled.load(Ordering::Relaxed) {
+                file.flush()?;
+                return Err(Error::TablebaseDownloadCancelled);
+            }
+            let chunk = chunk?;
+            file.write_all(&chunk)?;
+            downloaded += chunk.len() as u64;
+            progress(downloaded, total);
+        }
+        file.flush()?;
+    }
+    let file = path.file_name().unwrap_or_default().to_string_lossy();
+    verify_table(&part, &file, expected)?;
+    fs::rename(&part, path)?;
+    Ok(downloaded)
+}
+
+#[derive(Clone, Type, Serialize, Event)]
+pub struct TablebaseDownloadProgress {
+    pub id: String,
+    /// File being downloaded
+    pub file: String,
+    /// Files downloaded so far
+    pub done: usize,
+    /// Files to download
+    pub files: usize,
+    pub downloaded: u64,
+    pub total: Option<u64>,
+    pub finished: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Type, PartialEq, Eq)]
+pub struct TablebaseDownload {
+    pub dir: PathBuf,
+    /// Files downloaded
+    pub files: usize,
+    /// Files that were already there
+    pub present: usize,
+    pub bytes: u64,
+}
+
+/// Downloads the Syzygy files of up to `piece_count` pieces that are missing from `dir`,
+/// the tablebase directory of the app data by default, from the lichess mirror. It fails
+/// before downloading anything when the disk doesn't have room for them. Progress is
+/// emitted per file as `TablebaseDownloadProgress`, and `cancel_tablebase_download(id)`
+/// stops it. Interrupted files are left as `.part` files and continued by the next
+/// download. The files downloaded are used right away by `probe_tablebase` and by the
+/// engines whose `SyzygyPath` is set, which reload it with their next search.
+#[tauri::command]
+#[specta::specta]
+pub async fn download_tablebases(
+    id: String,
+    piece_count: u8,
+    dir: Option<PathBuf>,
+    state: tauri::State<'_, AppState>,
+    app: AppHandle,
+) -> Result<TablebaseDownload, Error> {
+    ensure_online(&state)?;
+    if !PIECE_COUNTS.contains(&piece_count) {
+        return Err(Error::UnsupportedPieceCount {
+            pieces: piece_count,
+        });
+    }
+    let dir = tablebase_dir(dir, &app)?;
+    fs::create_dir_all(&dir)?;
+    let sets: Vec<_> = list_sets(&dir)
+        .into_iter()
+        .filter(|set| set.pieces <= piece_count)
+        .collect();
+    let needed = needed_space(&dir, &sets);
+    if let Some(available) = available_space(&dir) {
+        if available < needed {
+            return Err(Error::NotEnoughDiskSpace { needed, available });
+        }
+    }
+
+    let present = sets.iter().map(|set| set.wdl + set.dtz).sum();
+    let missing: Vec<_> = sets
+        .iter()
+        .flat_map(|set| {
+            set.missing
+                .iter()
+                .map(move |file| (set.pieces, file.clone()))
+        })
+        .collect();
+    info!(
+        "Downloading {} tablebase files of up to {piece_count} pieces to {}",
+        missing.len(),
+        dir.display()
+    );
+
+    let client = Client::new();
+    let mut checksums = HashMap::new();
+    let cancelled = Arc::new(AtomicBool::new(false));
+    state
+        .tablebase_downloads
+        .insert(id.clone(), cancelled.clone());
+    let mut summary = TablebaseDownload {
+        dir: dir.clone(),
+        files: 0,
+        present,
+        bytes: 0,
+    };
+    let mut result = Ok(());
+    for (done, (pieces, file)) in missing.iter().enumerate() {
+        let Some(kind) = TableKind::of(file) else {
+            continue;
+        };
+        let folder = mirror_folder(*pieces, kind);
+        if !checksums.contains_key(folder) {
+            checksums.insert(folder, published_checksums(&client, folder).await);
+        }
+        let mut last_event: Option<Instant> = None;
+        let downloaded = download_table(
+            &client,
+            &format!("{MIRROR}/{folder}/{file}"),
+            &dir.join(file),
+            checksums[folder].get(file).map(String::as_str),
+            &cancelled,
+            |downloaded, total| {
+                if last_event.map_or(true, |last| last.elapsed() >= PROGRESS_INTERVAL) {
+                    last_event = Some(Instant::now());
+                    let _ = TablebaseDownloadProgress {
+                        id: id.clone(),
+                        file: file.clone(),
+                        done,
+                        files: missing.len(),
+                        downloaded,
+                        total,
+                        finished: false,
+                    }
+                    .emit_all(&app);
+                }
+            },
+        )
+        .await;
+        match downloaded {
+            Ok(bytes) => {
+                summary.files += 1;
+                summary.bytes += bytes;
+            }
+            Err(e) => {
+                result = Err(e);
+                break;
+            }
+        }
+    }
+    state.tablebase_downloads.remove(&id);
+
+    if summary.files > 0 {
+        *state.tablebase.lock().unwrap() = None;
+        resend_option(&state, "SyzygyPath").await;
+    }
+    result?;
+    info!(
+        "Downloaded {} tablebase files, {} bytes",
+        summary.files, summary.bytes
+    );
+    TablebaseDownloadProgress {
+        id,
+        file: String::new(),
+        done: missing.len(),
+        files: missing.len(),
+        downloaded: summary.bytes,
+        total: Some(summary.bytes),
+        finished: true,
+    }
+    .emit_all(&app)?;
+    Ok(summary)
+}
+
+#[tauri::command]
+#[specta::specta]
+pub fn cancel_tablebase_download(id: String, state: tauri::State<'_, AppState>) {
+    if let Some(cancelled) = state.tablebase_downloads.get(&id) {
+        cancelled.store(true, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_tables_like_syzygy() {
+        let counts: Vec<_> = PIECE_COUNTS.map(|pieces| materials(pieces).len()).collect();
+        assert_eq!(counts, [5, 30, 110, 365]);
+        let five = materials(5);
+        for material in ["KQRvK", "KBNvKQ", "KRPvKR", "KPPvKP"] {
+            assert!(five.contains(&material.to_string()), "{material}");
+        }
+        let four = materials(4);
+        assert!(four.contains(&"KBvKN".to_string()));
+        assert!(!four.contains(&"KNvKB".to_string()));
+        assert!(materials(6).contains(&"KQNvKRB".to_string()));
+        assert!(!materials(6).contains(&"KRBvKQN".to_string()));
+        assert_eq!(table_files(3)[..2], ["KQvK.rtbw", "KQvK.rtbz"]);
+    }
+
+    #[test]
+    fn lists_the_files_present() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("KQvK.rtbw"), TableKind::Wdl.magic()).unwrap();
+        fs::write(dir.path().join("KRvK.rtbz"), TableKind::Dtz.magic()).unwrap();
+        fs::write(dir.path().join("KBvK.rtbw"), []).unwrap();
+        fs::write(dir.path().join("KNvK.rtbw.part"), [0; 8]).unwrap();
+
+        let sets = list_sets(dir.path());
+        assert_eq!(sets.len(), 4);
+        assert_eq!((sets[0].wdl, sets[0].dtz, sets[0].size), (1, 1, 8));
+        assert_eq!(sets[0].missing.len(), 8);
+        assert!(sets[0].missing.contains(&"KBvK.rtbw".to_string()));
+        assert_eq!(sets[3].missing.len(), 730);
+
+        let file_size = SET_SIZES[0].1 / 10;
+        assert_eq!(needed_space(dir.path(), &sets[..1]), file_size * 8 - 8);
+    }
+
+    #[test]
+    fn checks_downloaded_tables() {
+        let dir = tempfile::tempdir().unwrap();
+        let part = dir.path().join("KQvK.rtbw.part");
+        fs::write(&part, TableKind::Wdl.magic()).unwrap();
+        assert!(verify_table(&part, "KQvK.rtbw", None).is_ok());
+        assert!(matches!(
+            verify_table(&part, "KQvK.rtbw", Some("00")),
+            Err(Error::ChecksumMismatch { .. })
+        ));
+        assert!(!part.exists());
+
+        fs::write(&part, TableKind::Dtz.magic()).unwrap();
+        assert!(matches!(
+            verify_table(&part, "KQvK.rtbw", None),
+            Err(Error::Tablebase(TablebaseError::CorruptedTable { .. }))
+        ));
+        assert!(!part.exists());
+    }
+}
@@ -0,0 +1,87 @@
+use std::path::{Path, PathBuf};
+
+use shakmaty::{Chess, Position};
+
+use super::{material_key, TablebaseError};
+
+/// Extensions of Gaviota tables, the compressed ones last
+const EXTENSIONS: [&str; 2] = ["gtb", "gtb.cp4"];
+
+/// Names a Gaviota table of the material of `pos` can have, like `krpkr`. A table has
+/// the positions of both colors, under the name of either side first.
+fn table_names(pos: &Chess) -> [String; 2] {
+    let key = material_key(pos.board()).to_lowercase();
+    let (white, black) = key.split_once('v').unwrap_or((key.as_str(), ""));
+    [format!("{white}{black}"), format!("{black}{white}")]
+}
+
+/// The Gaviota table of the material of `pos` in `dirs`, uncompressed ones first
+fn find_table(dirs: &[PathBuf], pos: &Chess) -> Option<PathBuf> {
+    let names = table_names(pos);
+    EXTENSIONS.iter().find_map(|extension| {
+        dirs.iter().find_map(|dir| {
+            names
+                .iter()
+                .map(|name| dir.join(format!("{name}.{extension}")))
+                .find(|path| path.is_file())
+        })
+    })
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Moves to mate in `pos` from the Gaviota tables of `dirs`, positive when the side to
+/// move mates. Only says why it can't be read for now: compressed tables would need a
+/// decompressor and the uncompressed ones a port of the Gaviota indexing, so the DTM
+/// comes from lichess until then.
+pub fn probe_dtm(dirs: &[PathBuf], pos: &Chess) -> Result<i32, TablebaseError> {
+    if pos.is_checkmate() {
+        return Ok(0);
+    }
+    let table = find_table(dirs, pos).ok_or_else(|| TablebaseError::MissingGaviotaTable {
+        material: material_key(pos.board()),
+    })?;
+    let file = file_name(&table);
+    if file.ends_with(".cp4") {
+        return Err(TablebaseError::CompressedGaviotaTable { file });
+    }
+    Err(TablebaseError::UnreadableGaviotaTable { file })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use shakmaty::{fen::Fen, CastlingMode};
+
+    #[test]
+    fn finds_tables_of_either_side() {
+        let pos: Chess = Fen::from_ascii(b"8/8/8/8/8/2k5/2p5/K2R4 b - - 0 1")
+            .unwrap()
+            .into_position(CastlingMode::Standard)
+            .unwrap();
+        assert_eq!(table_names(&pos), ["krkp", "kpkr"]);
+
+        let dir = tempfile::tempdir().unwrap();
+        let dirs = [dir.path().to_path_buf()];
+        assert!(matches!(
+            probe_dtm(&dirs, &pos),
+            Err(TablebaseError::MissingGaviotaTable { material }) if material == "KRvKP"
+        ));
+        std::fs::write(dir.path().join("krkp.gtb.cp4"), []).unwrap();
+        assert!(matches!(
+            probe_dtm(&dirs, &pos),
+            Err(TablebaseError::CompressedGaviotaTable { file }) if file == "krkp.gtb.cp4"
+        ));
+        std::fs::write(dir.path().join("krkp.gtb"), []).unwrap();
+        assert!(matches!(
+            probe_dtm(&dirs, &pos),
+            Err(TablebaseError::UnreadableGaviotaTable { file }) if file == "krkp.gtb"
+        ));
+    }
+}
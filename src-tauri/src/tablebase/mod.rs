@@ -0,0 +1,781 @@
+mod files;
+mod gaviota;
+
+use std::{
+    cmp::Ordering,
+    collections::HashMap,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+use shakmaty::{
+    fen::Fen, san::SanPlus, uci::Uci, zobrist::Zobrist64, Board, CastlingMode, Chess, Color,
+    EnPassantMode, Position, Role,
+};
+use shakmaty_syzygy::{AmbiguousWdl, Dtz, MaybeRounded, SyzygyError, Tablebase};
+use specta::Type;
+
+use crate::{chess::BestMoves, error::Error, online::is_offline, AppState};
+
+pub use self::files::{
+    cancel_tablebase_download, download_tablebases, list_tablebase_files, TablebaseDownloadProgress,
+};
+
+/// Most pieces there are Syzygy tables for
+const MAX_PIECES: usize = 7;
+
+const LICHESS_TABLEBASE: &str = "https://tablebase.lichess.ovh/standard";
+
+/// Time lichess has to answer when none is given
+const ONLINE_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Lichess answers kept before they are all forgotten
+const ONLINE_CACHE_SIZE: usize = 2000;
+
+/// Plies of an engine line looked at for a position the tables have
+const LINE_PLIES: usize = 8;
+
+/// Result of a position for the side to move, from the worst to the best
+#[derive(Debug, Clone, Copy, Serialize, Type, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "camelCase")]
+pub enum TablebaseCategory {
+    Loss,
+    /// A loss or a draw depending on the moves played since the last capture or pawn move
+    MaybeLoss,
+    /// A loss the 50-move rule turns into a draw
+    BlessedLoss,
+    Draw,
+    /// A win the 50-move rule turns into a draw
+    CursedWin,
+    /// A win or a draw depending on the moves played since the last capture or pawn move
+    MaybeWin,
+    Win,
+}
+
+impl TablebaseCategory {
+    fn from_wdl(wdl: AmbiguousWdl) -> Self {
+        match wdl {
+            AmbiguousWdl::Loss => Self::Loss,
+            AmbiguousWdl::MaybeLoss => Self::MaybeLoss,
+            AmbiguousWdl::BlessedLoss => Self::BlessedLoss,
+            AmbiguousWdl::Draw => Self::Draw,
+            AmbiguousWdl::CursedWin => Self::CursedWin,
+            AmbiguousWdl::MaybeWin => Self::MaybeWin,
+            AmbiguousWdl::Win => Self::Win,
+        }
+    }
+
+    /// The category of the same position for the other side
+    pub fn flip(self) -> Self {
+        match self {
+            Self::Loss => Self::Win,
+            Self::MaybeLoss => Self::MaybeWin,
+            Self::BlessedLoss => Self::CursedWin,
+            Self::Draw => Self::Draw,
+            Self::CursedWin => Self::BlessedLoss,
+            Self::MaybeWin => Self::MaybeLoss,
+            Self::Win => Self::Loss,
+        }
+    }
+
+    fn is_win(self) -> bool {
+        self > Self::Draw
+    }
+
+    fn is_loss(self) -> bool {
+        self < Self::Draw
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct TablebaseMove {
+    pub uci: String,
+    pub san: String,
+    /// Result for the side playing the move
+    pub category: TablebaseCategory,
+    /// Distance to zeroing of the position after the move, for the side playing it
+    pub dtz: Option<i32>,
+    /// Moves to mate after the move, for the side playing it
+    pub dtm: Option<i32>,
+    /// A capture or a pawn move, which resets the 50-move rule
+    pub zeroing: bool,
+    pub checkmate: bool,
+    pub stalemate: bool,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Type, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum TablebaseSource {
+    /// The Syzygy tables of the tablebase directories
+    Local,
+    /// The tablebase API of lichess
+    Lichess,
+}
+
+/// Distance a result is given in
+#[derive(Debug, Clone, Copy, Serialize, Type, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum TablebaseMetric {
+    /// Moves to mate, from the Gaviota tables or lichess
+    Dtm,
+    /// Moves to the next capture or pawn move, from the Syzygy tables
+    Dtz,
+}
+
+#[derive(Debug, Clone, Serialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct TablebaseResult {
+    pub source: TablebaseSource,
+    /// Result for the side to move
+    pub category: TablebaseCategory,
+    /// Moves to the next capture or pawn move with the best play, positive when the side
+    /// to move wins. Tables may round it by one when the 50-move rule can't matter.
+    pub dtz: Option<i32>,
+    /// Moves to mate with the best play, positive when the side to move mates
+    pub dtm: Option<i32>,
+    /// Distance to show, DTM when there is one
+    pub metric: TablebaseMetric,
+    /// Why the Gaviota directories gave no DTM, when there are some
+    pub gaviota_error: Option<TablebaseError>,
+    pub checkmate: bool,
+    pub stalemate: bool,
+    /// Legal moves, the best first
+    pub moves: Vec<TablebaseMove>,
+}
+
+#[derive(Debug, Clone, Serialize, Type, thiserror::Error)]
+#[serde(tag = "type", content = "value", rename_all = "camelCase")]
+pub enum TablebaseError {
+    #[error("The position has {pieces} pieces, tablebases have at most {MAX_PIECES}")]
+    TooManyPieces { pieces: usize },
+
+    #[error("The {material} table is missing from the tablebase directories")]
+    MissingTable { material: String },
+
+    #[error("Tablebases don't have positions with castling rights")]
+    Castling,
+
+    #[error("The {material} table can't be read: {reason}")]
+    CorruptedTable { material: String, reason: String },
+
+    #[error("Tablebase unavailable: {reason}")]
+    Unavailable { reason: String },
+
+    #[error("The {material} table is missing from the Gaviota directories")]
+    MissingGaviotaTable { material: String },
+
+    #[error("{file} is compressed, only uncompressed Gaviota tables can be read")]
+    CompressedGaviotaTable { file: String },
+
+    #[error("Gaviota tables like {file} can't be read yet, the DTM comes from lichess")]
+    UnreadableGaviotaTable { file: String },
+}
+
+#[derive(Debug, Clone, Deserialize, Type, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct TablebaseOptions {
+    /// Directories of Syzygy tables
+    pub dirs: Vec<PathBuf>,
+    /// Asks lichess for the positions the local tables don't have
+    pub allow_online: bool,
+    /// Time lichess has to answer, in milliseconds
+    pub timeout: Option<u64>,
+    /// Directories of Gaviota tables, for the distance to mate
+    #[serde(default)]
+    pub gaviota_dirs: Vec<PathBuf>,
+}
+
+/// Syzygy tables opened from some directories, reopened when the directories change
+pub type TablebaseCache = Mutex<Option<(Vec<PathBuf>, Arc<Tablebase<Chess>>)>>;
+
+/// Material of the board in the naming of Syzygy tables, like `KRPvKR`
+fn material_key(board: &Board) -> String {
+    let side = |color: Color| -> String {
+        [
+            Role::King,
+            Role::Queen,
+            Role::Rook,
+            Role::Bishop,
+            Role::Knight,
+            Role::Pawn,
+        ]
+        .iter()
+        .flat_map(|&role| {
+            let count = (board.by_role(role) & board.by_color(color)).count();
+            std::iter::repeat(role.upper_char()).take(count)
+        })
+        .collect()
+    };
+    format!("{}v{}", side(Color::White), side(Color::Black))
+}
+
+fn tablebase_error(error: SyzygyError, board: &Board) -> TablebaseError {
+    match error {
+        SyzygyError::Castling => TablebaseError::Castling,
+        // The tables of the directories stop before the pieces of the position
+        SyzygyError::TooManyPieces | SyzygyError::MissingTable { .. } => {
+            TablebaseError::MissingTable {
+                material: material_key(board),
+            }
+        }
+        SyzygyError::ProbeFailed { error, .. } => TablebaseError::CorruptedTable {
+            material: material_key(board),
+            reason: error.to_string(),
+        },
+    }
+}
+
+fn dtz_value(dtz: MaybeRounded<Dtz>) -> i32 {
+    match dtz {
+        MaybeRounded::Precise(Dtz(dtz)) | MaybeRounded::Rounded(Dtz(dtz)) => dtz,
+    }
+}
+
+/// Best moves first: wins that reset the 50-move counter sooner, then draws, then
+/// losses that take the longest
+fn compare_moves(a: &TablebaseMove, b: &TablebaseMove) -> Ordering {
+    let distance = |m: &TablebaseMove| m.dtz.map_or(i32::MAX, i32::abs);
+    b.checkmate
+        .cmp(&a.checkmate)
+        .then(b.category.cmp(&a.category))
+        .then_with(|| {
+            if a.category.is_win() {
+                distance(a).cmp(&distance(b))
+            } else if a.category.is_loss() {
+                distance(b).cmp(&distance(a))
+            } else {
+                Ordering::Equal
+            }
+        })
+        .then_with(|| a.uci.cmp(&b.uci))
+}
+
+/// Result and DTZ of `pos` for the side to move, which checkmates and stalemates need
+/// no table for
+fn probe_position(
+    tables: &Tablebase<Chess>,
+    pos: &Chess,
+) -> Result<(TablebaseCategory, Option<i32>), TablebaseError> {
+    if pos.is_checkmate() {
+        return Ok((TablebaseCategory::Loss, Some(0)));
+    }
+    if pos.is_stalemate() {
+        return Ok((TablebaseCategory::Draw, Some(0)));
+    }
+    let error = |e| tablebase_error(e, pos.board());
+    let wdl = tables.probe_wdl(pos).map_err(error)?;
+    let dtz = tables.probe_dtz(pos).map_err(error)?;
+    Ok((TablebaseCategory::from_wdl(wdl), Some(dtz_value(dtz))))
+}
+
+pub fn probe(tables: &Tablebase<Chess>, pos: &Chess) -> Result<TablebaseResult, TablebaseError> {
+    let pieces = pos.board().occupied().count();
+    if pieces > MAX_PIECES {
+        return Err(TablebaseError::TooManyPieces { pieces });
+    }
+    let (category, dtz) = probe_position(tables, pos)?;
+
+    let mut moves = Vec::new();
+    for m in pos.legal_moves() {
+        let uci = m.to_uci(CastlingMode::Standard).to_string();
+        let san = SanPlus::from_move(pos.clone(), &m).to_string();
+        let zeroing = m.is_zeroing();
+        let mut after = pos.clone();
+        after.play_unchecked(&m);
+        let (category, dtz) = probe_position(tables, &after)?;
+        moves.push(TablebaseMove {
+            uci,
+            san,
+            category: category.flip(),
+            dtz: dtz.map(|dtz| -dtz),
+            dtm: None,
+            zeroing,
+            checkmate: after.is_checkmate(),
+            stalemate: after.is_stalemate(),
+        });
+    }
+    moves.sort_by(compare_moves);
+
+    Ok(TablebaseResult {
+        source: TablebaseSource::Local,
+        category,
+        dtz,
+        dtm: None,
+        metric: TablebaseMetric::Dtz,
+        gaviota_error: None,
+        checkmate: pos.is_checkmate(),
+        stalemate: pos.is_stalemate(),
+        moves,
+    })
+}
+
+/// The tables of `dirs`, opened once and kept until other directories are asked for
+fn open_tables(cache: &TablebaseCache, dirs: &[PathBuf]) -> Result<Arc<Tablebase<Chess>>, Error> {
+    let mut cache = cache.lock().unwrap();
+    if let Some((cached, tables)) = cache.as_ref() {
+        if cached == dirs {
+            return Ok(tables.clone());
+        }
+    }
+    let mut tables = Tablebase::new();
+    for dir in dirs {
+        tables.add_directory(dir)?;
+    }
+    let tables = Arc::new(tables);
+    *cache = Some((dirs.to_vec(), tables.clone()));
+    Ok(tables)
+}
+
+#[derive(Deserialize)]
+struct LichessMove {
+    uci: String,
+    san: String,
+    zeroing: bool,
+    checkmate: bool,
+    stalemate: bool,
+    dtz: Option<i32>,
+    dtm: Option<i32>,
+    category: String,
+}
+
+#[derive(Deserialize)]
+struct LichessTablebase {
+    checkmate: bool,
+    stalemate: bool,
+    dtz: Option<i32>,
+    dtm: Option<i32>,
+    category: String,
+    moves: Vec<LichessMove>,
+}
+
+fn lichess_category(category: &str) -> Option<TablebaseCategory> {
+    match category {
+        "win" | "syzygy-win" => Some(TablebaseCategory::Win),
+        "maybe-win" => Some(TablebaseCategory::MaybeWin),
+        "cursed-win" => Some(TablebaseCategory::CursedWin),
+        "draw" => Some(TablebaseCategory::Draw),
+        "blessed-loss" => Some(TablebaseCategory::BlessedLoss),
+        "maybe-loss" => Some(TablebaseCategory::MaybeLoss),
+        "loss" | "syzygy-loss" => Some(TablebaseCategory::Loss),
+        _ => None,
+    }
+}
+
+/// The answer of lichess as the result of a local probe. The categories and DTZ of its
+/// moves are the ones of the positions after them, for the other side.
+fn from_lichess(response: LichessTablebase) -> Result<TablebaseResult, TablebaseError> {
+    let category =
+        lichess_category(&response.category).ok_or_else(|| TablebaseError::Unavailable {
+            reason: format!("lichess doesn't know the result ({})", response.category),
+        })?;
+    let mut moves: Vec<_> = response
+        .moves
+        .into_iter()
+        .filter_map(|m| {
+            Some(TablebaseMove {
+                category: lichess_category(&m.category)?.flip(),
+                dtz: m.dtz.map(|dtz| -dtz),
+                dtm: m.dtm.map(|dtm| -dtm),
+                uci: m.uci,
+                san: m.san,
+                zeroing: m.zeroing,
+                checkmate: m.checkmate,
+                stalemate: m.stalemate,
+            })
+        })
+        .collect();
+    moves.sort_by(compare_moves);
+    Ok(TablebaseResult {
+        source: TablebaseSource::Lichess,
+        category,
+        dtz: response.dtz,
+        dtm: response.dtm,
+        metric: if response.dtm.is_some() {
+            TablebaseMetric::Dtm
+        } else {
+            TablebaseMetric::Dtz
+        },
+        gaviota_error: None,
+        checkmate: response.checkmate,
+        stalemate: response.stalemate,
+        moves,
+    })
+}
+
+async fn probe_online(
+    state: &AppState,
+    pos: &Chess,
+    timeout: Duration,
+) -> Result<TablebaseResult, TablebaseError> {
+    let fen = Fen::from_position(pos.clone(), EnPassantMode::Legal).to_string();
+    if let Some(result) = state.tablebase_responses.get(&fen) {
+        return Ok(result.clone());
+    }
+    if is_offline(state) {
+        return Err(TablebaseError::Unavailable {
+            reason: "network calls are disabled".to_string(),
+        });
+    }
+    let unavailable = |e: reqwest::Error| TablebaseError::Unavailable {
+        reason: e.to_string(),
+    };
+    let response: LichessTablebase = reqwest::Client::new()
+        .get(LICHESS_TABLEBASE)
+        .query(&[("fen", &fen)])
+        .timeout(timeout)
+        .send()
+        .await
+        .and_then(|res| res.error_for_status())
+        .map_err(unavailable)?
+        .json()
+        .await
+        .map_err(unavailable)?;
+    let result = from_lichess(response)?;
+    if state.tablebase_responses.len() >= ONLINE_CACHE_SIZE {
+        state.tablebase_responses.clear();
+    }
+    state.tablebase_responses.insert(fen, result.clone());
+    Ok(result)
+}
+
+/// Result of the position `fen` in the Syzygy tables of the tablebase directories, with
+/// the one of each legal move, for positions of up to seven pieces without castling
+/// rights. With `allow_online`, the positions the tables are missing are asked to
+/// lichess, whose answers are cached, and it is unavailable when lichess doesn't answer
+/// before the timeout. Lichess also gives the distance to mate, which is otherwise asked
+/// to the Gaviota directories when there are some; `metric` tells which one it has.
+#[tauri::command]
+#[specta::specta]
+pub async fn probe_tablebase(
+    fen: String,
+    options: TablebaseOptions,
+    state: tauri::State<'_, AppState>,
+) -> Result<TablebaseResult, Error> {
+    let pos: Chess = Fen::from_ascii(fen.as_bytes())?.into_position(CastlingMode::Standard)?;
+    probe_with(&state, &pos, &options).await
+}
+
+/// Probes `pos` like `probe_tablebase`
+pub async fn probe_with(
+    state: &AppState,
+    pos: &Chess,
+    options: &TablebaseOptions,
+) -> Result<TablebaseResult, Error> {
+    let tables = open_tables(&state.tablebase, &options.dirs)?;
+    let mut result = match probe(&tables, pos) {
+        Err(TablebaseError::MissingTable { .. } | TablebaseError::CorruptedTable { .. })
+            if options.allow_online =>
+        {
+            let timeout = options
+                .timeout
+                .map_or(ONLINE_TIMEOUT, Duration::from_millis);
+            probe_online(state, pos, timeout).await?
+        }
+        result => result?,
+    };
+    if result.dtm.is_none() && !options.gaviota_dirs.is_empty() {
+        match gaviota::probe_dtm(&options.gaviota_dirs, pos) {
+            Ok(dtm) => {
+                result.dtm = Some(dtm);
+                result.metric = TablebaseMetric::Dtm;
+            }
+            Err(e) => result.gaviota_error = Some(e),
+        }
+    }
+    Ok(result)
+}
+
+#[derive(Debug, Clone, Serialize, Type, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct LineTablebase {
+    pub multipv: u16,
+    /// Plies into the line of the position the result is from, 1 for the first move
+    pub ply: usize,
+    /// Result of the line for the side to move in the analyzed position
+    pub category: TablebaseCategory,
+    /// Distance to zeroing of that position, positive when the side to move in the
+    /// analyzed position wins
+    pub dtz: Option<i32>,
+    /// The first move of the line gives away the result of the analyzed position, like
+    /// a draw in a won position
+    pub throws_result: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct TablebaseAnnotation {
+    /// Result of the analyzed position, when it is in the tablebases
+    pub result: Option<TablebaseResult>,
+    pub lines: Vec<LineTablebase>,
+}
+
+/// Results of the positions of engine lines already probed, by their Zobrist hash, so
+/// the same lines sent again as the search goes on aren't probed every time
+pub type LineProbes = HashMap<u64, Option<(TablebaseCategory, Option<i32>)>>;
+
+/// Tablebase result of the engine line, from the moves of `root`, the result of `pos`,
+/// or else from the first of its positions in the local `tables`
+fn annotate_line(
+    tables: &Tablebase<Chess>,
+    pos: &Chess,
+    root: Option<&TablebaseResult>,
+    line: &BestMoves,
+    probes: &mut LineProbes,
+) -> Option<LineTablebase> {
+    let first = line.uci_moves.first()?;
+    if let Some(root) = root {
+        let m = root.moves.iter().find(|m| &m.uci == first)?;
+        return Some(LineTablebase {
+            multipv: line.multipv,
+            ply: 1,
+            category: m.category,
+            dtz: m.dtz,
+            throws_result: m.category < root.category,
+        });
+    }
+    let mut pos = pos.clone();
+    for (i, uci) in line.uci_moves.iter().take(LINE_PLIES).enumerate() {
+        let m = uci.parse::<Uci>().ok()?.to_move(&pos).ok()?;
+        pos.play_unchecked(&m);
+        let hash = pos.zobrist_hash::<Zobrist64>(EnPassantMode::Legal).0;
+        let probed = *probes
+            .entry(hash)
+            .or_insert_with(|| probe_position(tables, &pos).ok());
+        if let Some((category, dtz)) = probed {
+            let ours = i % 2 == 1;
+            return Some(LineTablebase {
+                multipv: line.multipv,
+                ply: i + 1,
+                category: if ours { category } else { category.flip() },
+                dtz: dtz.map(|dtz| if ours { dtz } else { -dtz }),
+                throws_result: false,
+            });
+        }
+    }
+    None
+}
+
+/// Tablebase results of the engine `lines` of `pos`, whose own result is `root`, with
+/// the positions of the lines probed in the local tables of `dirs` unless they are in
+/// `probes` already
+pub fn annotate_lines(
+    state: &AppState,
+    dirs: &[PathBuf],
+    pos: &Chess,
+    root: Option<&TablebaseResult>,
+    lines: &[BestMoves],
+    probes: &mut LineProbes,
+) -> TablebaseAnnotation {
+    let tables = open_tables(&state.tablebase, dirs).unwrap_or_else(|_| Arc::new(Tablebase::new()));
+    TablebaseAnnotation {
+        result: root.cloned(),
+        lines: lines
+            .iter()
+            .filter_map(|line| annotate_line(&tables, pos, root, line, probes))
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn position(fen: &str) -> Chess {
+        Fen::from_ascii(fen.as_bytes())
+            .unwrap()
+            .into_position(CastlingMode::Standard)
+            .unwrap()
+    }
+
+    #[test]
+    fn reports_what_it_cant_probe() {
+        let tables = Tablebase::new();
+        assert!(matches!(
+            probe(&tables, &Chess::default()),
+            Err(TablebaseError::TooManyPieces { pieces: 32 })
+        ));
+        assert!(matches!(
+            probe(&tables, &position("8/8/8/8/8/2k5/2p5/K2R4 w - - 0 1")),
+            Err(TablebaseError::MissingTable { material }) if material == "KRvKP"
+        ));
+    }
+
+    #[test]
+    fn probes_terminal_positions_without_tables() {
+        let tables = Tablebase::new();
+        let mate = probe(&tables, &position("k7/1Q6/1K6/8/8/8/8/8 b - - 0 1")).unwrap();
+        assert_eq!(mate.category, TablebaseCategory::Loss);
+        assert!(mate.checkmate);
+        assert!(mate.moves.is_empty());
+        let stalemate = probe(&tables, &position("k7/2Q5/1K6/8/8/8/8/8 b - - 0 1")).unwrap();
+        assert_eq!(stalemate.category, TablebaseCategory::Draw);
+        assert!(stalemate.stalemate);
+    }
+
+    #[test]
+    fn reads_lichess_answers() {
+        let response: LichessTablebase = serde_json::from_str(
+            r#"{
+                "checkmate": false,
+                "stalemate": false,
+                "dtz": 1,
+                "precise_dtz": 1,
+                "dtm": 17,
+                "category": "win",
+                "moves": [
+                    { "uci": "h7h8q", "san": "h8=Q+", "zeroing": true, "checkmate": false,
+                      "stalemate": false, "dtz": -2, "precise_dtz": -2, "category": "loss" },
+                    { "uci": "a1b1", "san": "Kb1", "zeroing": false, "checkmate": false,
+                      "stalemate": false, "dtz": null, "category": "unknown" },
+                    { "uci": "a1a2", "san": "Ka2", "zeroing": false, "checkmate": false,
+                      "stalemate": false, "dtz": 0, "category": "draw" }
+                ]
+            }"#,
+        )
+        .unwrap();
+        let result = from_lichess(response).unwrap();
+        assert_eq!(result.source, TablebaseSource::Lichess);
+        assert_eq!(result.category, TablebaseCategory::Win);
+        assert_eq!(result.dtz, Some(1));
+        assert_eq!(result.dtm, Some(17));
+        assert_eq!(result.metric, TablebaseMetric::Dtm);
+        let moves: Vec<_> = result
+            .moves
+            .iter()
+            .map(|m| (m.uci.as_str(), m.category, m.dtz))
+            .collect();
+        assert_eq!(
+            moves,
+            [
+                ("h7h8q", TablebaseCategory::Win, Some(2)),
+                ("a1a2", TablebaseCategory::Draw, Some(0))
+            ]
+        );
+
+        let unknown: LichessTablebase = serde_json::from_str(
+            r#"{ "checkmate": false, "stalemate": false, "dtz": null, "category": "unknown", "moves": [] }"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            from_lichess(unknown),
+            Err(TablebaseError::Unavailable { .. })
+        ));
+    }
+
+    #[test]
+    fn annotates_engine_lines() {
+        let tables = Tablebase::new();
+        let pos = position("k7/8/1K6/8/8/8/8/2Q5 w - - 0 1");
+        let line = |multipv, moves: &[&str]| BestMoves {
+            multipv,
+            uci_moves: moves.iter().map(|m| m.to_string()).collect(),
+            ..Default::default()
+        };
+        let mut probes = LineProbes::new();
+        let lines = [
+            line(1, &["c1c8"]),
+            line(2, &["c1c7"]),
+            line(3, &["c1c2", "a8a7"]),
+        ];
+        let annotated: Vec<_> = lines
+            .iter()
+            .filter_map(|line| annotate_line(&tables, &pos, None, line, &mut probes))
+            .map(|line| (line.multipv, line.ply, line.category, line.throws_result))
+            .collect();
+        assert_eq!(
+            annotated,
+            [
+                (1, 1, TablebaseCategory::Win, false),
+                (2, 1, TablebaseCategory::Draw, false)
+            ]
+        );
+
+        let m = |uci: &str, category| TablebaseMove {
+            uci: uci.to_string(),
+            san: String::new(),
+            category,
+            dtz: Some(0),
+            dtm: None,
+            zeroing: false,
+            checkmate: false,
+            stalemate: false,
+        };
+        let root = TablebaseResult {
+            source: TablebaseSource::Lichess,
+            category: TablebaseCategory::Win,
+            dtz: Some(1),
+            dtm: None,
+            metric: TablebaseMetric::Dtz,
+            gaviota_error: None,
+            checkmate: false,
+            stalemate: false,
+            moves: vec![
+                m("c1c8", TablebaseCategory::Win),
+                m("c1c2", TablebaseCategory::CursedWin),
+                m("c1c7", TablebaseCategory::Draw),
+            ],
+        };
+        let throws: Vec<_> = lines
+            .iter()
+            .filter_map(|line| annotate_line(&tables, &pos, Some(&root), line, &mut probes))
+            .map(|line| (line.multipv, line.throws_result))
+            .collect();
+        assert_eq!(throws, [(1, false), (2, true), (3, true)]);
+    }
+
+    #[test]
+    fn probes_positions_of_lines_once() {
+        let tables = Tablebase::new();
+        let pos = position("k7/8/1K6/8/8/8/8/2Q5 w - - 0 1");
+        let line = BestMoves {
+            multipv: 1,
+            uci_moves: vec!["c1c7".to_string()],
+            ..Default::default()
+        };
+        let mut probes = LineProbes::new();
+        let annotated = annotate_line(&tables, &pos, None, &line, &mut probes).unwrap();
+        assert_eq!(annotated.category, TablebaseCategory::Draw);
+        assert_eq!(probes.len(), 1);
+
+        // The result kept is the one given again
+        for probed in probes.values_mut() {
+            *probed = Some((TablebaseCategory::Loss, Some(3)));
+        }
+        let annotated = annotate_line(&tables, &pos, None, &line, &mut probes).unwrap();
+        assert_eq!(annotated.category, TablebaseCategory::Win);
+        assert_eq!(annotated.dtz, Some(-3));
+    }
+
+    #[test]
+    fn sorts_moves_by_result() {
+        let m = |uci: &str, category, dtz, checkmate| TablebaseMove {
+            uci: uci.to_string(),
+            san: String::new(),
+            category,
+            dtz: Some(dtz),
+            dtm: None,
+            zeroing: false,
+            checkmate,
+            stalemate: false,
+        };
+        let mut moves = vec![
+            m("a", TablebaseCategory::Loss, -3, false),
+            m("b", TablebaseCategory::Draw, 0, false),
+            m("c", TablebaseCategory::Win, 9, false),
+            m("d", TablebaseCategory::Loss, -20, false),
+            m("e", TablebaseCategory::Win, 1, true),
+            m("f", TablebaseCategory::Win, 3, false),
+            m("g", TablebaseCategory::CursedWin, 101, false),
+        ];
+        moves.sort_by(compare_moves);
+        let order: Vec<_> = moves.iter().map(|m| m.uci.as_str()).collect();
+        assert_eq!(order, ["e", "f", "c", "g", "b", "d", "a"]);
+        assert_eq!(
+            TablebaseCategory::CursedWin.flip(),
+            TablebaseCategory::BlessedLoss
+        );
+    }
+}
@@ -0,0 +1,274 @@
+//! Reading of the `info` lines engines send while searching, which arrive by thousands
+//! per second. A line is split once and its tokens are borrowed until its moves are
+//! played, so only the moves kept allocate. The lines are then gathered into the
+//! MultiPV sets they're sent in.
+//!
+//! This module only depends on shakmaty and vampirc-uci so the benchmarks can include it.
+
+use shakmaty::{san::SanPlus, uci::Uci, Chess};
+use vampirc_uci::uci::{Score, ScoreValue};
+
+/// Words starting the attributes of an `info` line, which end a `pv`
+const KEYWORDS: [&str; 17] = [
+    "depth",
+    "seldepth",
+    "time",
+    "nodes",
+    "pv",
+    "multipv",
+    "score",
+    "wdl",
+    "currmove",
+    "currmovenumber",
+    "hashfull",
+    "nps",
+    "tbhits",
+    "sbhits",
+    "cpuload",
+    "refutation",
+    "currline",
+];
+
+/// An `info` line, borrowed from the text it was read from
+#[derive(Debug, Clone)]
+pub struct InfoLine<'a> {
+    pub depth: u32,
+    pub multipv: u16,
+    pub nodes: u64,
+    pub nps: u64,
+    pub score: Option<Score>,
+    /// Moves of the line in UCI, separated by spaces
+    pub pv: &'a str,
+}
+
+impl Default for InfoLine<'_> {
+    fn default() -> Self {
+        InfoLine {
+            depth: 0,
+            multipv: 1,
+            nodes: 0,
+            nps: 0,
+            score: None,
+            pv: "",
+        }
+    }
+}
+
+fn number<T: std::str::FromStr + Default>(token: Option<&str>) -> T {
+    token
+        .and_then(|token| token.parse().ok())
+        .unwrap_or_default()
+}
+
+/// Offset of `token` in `line`, which it was split from
+fn offset(line: &str, token: &str) -> usize {
+    token.as_ptr() as usize - line.as_ptr() as usize
+}
+
+/// The `info` line `line`, None for other commands. Unknown attributes are skipped,
+/// and so is everything after `string`.
+pub fn parse_info(line: &str) -> Option<InfoLine<'_>> {
+    let mut tokens = line.split_ascii_whitespace().peekable();
+    if tokens.next()? != "info" {
+        return None;
+    }
+    let mut info = InfoLine::default();
+    let mut value = None;
+    let mut wdl = None;
+    while let Some(token) = tokens.next() {
+        match token {
+            "depth" => info.depth = number(tokens.next()),
+            "multipv" => info.multipv = number(tokens.next()),
+            "nodes" => info.nodes = number(tokens.next()),
+            "nps" => info.nps = number(tokens.next()),
+            "score" => {
+                value = match tokens.next() {
+                    Some("cp") => Some(ScoreValue::Cp(number(tokens.next()))),
+                    Some("mate") => Some(ScoreValue::Mate(number(tokens.next()))),
+                    _ => value,
+                };
+            }
+            "wdl" => {
+                let (w, d, l) = (tokens.next(), tokens.next(), tokens.next());
+                wdl = Some((number(w), number(d), number(l)));
+            }
+            "pv" => {
+                let Some(&first) = tokens.peek() else {
+                    continue;
+                };
+                let mut last = first;
+                while let Some(&token) = tokens.peek() {
+                    if KEYWORDS.contains(&token) || token == "string" {
+                        break;
+                    }
+                    last = token;
+                    tokens.next();
+                }
+                info.pv = &line[offset(line, first)..offset(line, last) + last.len()];
+            }
+            "string" => break,
+            _ => {}
+        }
+    }
+    info.score = value.map(|value| Score { value, wdl });
+    Some(info)
+}
+
+/// A line of the engine as it sent it. Its moves are only converted to SAN when the
+/// set it's part of is used, which leaves the reader of the engine's output free of it.
+#[derive(Debug)]
+pub struct EngineLine {
+    pub depth: u32,
+    pub multipv: u16,
+    pub nodes: u32,
+    pub nps: u32,
+    pub score: Score,
+    /// Moves in UCI, separated by spaces
+    pub pv: String,
+}
+
+impl EngineLine {
+    /// The line of an `info` line, None when it has no moves
+    pub fn from_info(info: InfoLine) -> Option<Self> {
+        if info.pv.is_empty() {
+            return None;
+        }
+        Some(EngineLine {
+            depth: info.depth,
+            multipv: info.multipv,
+            nodes: info.nodes as u32,
+            nps: info.nps as u32,
+            score: info.score.unwrap_or_default(),
+            pv: info.pv.to_string(),
+        })
+    }
+}
+
+/// The MultiPV sets of lines of a search, of which the last complete one is kept
+#[derive(Debug, Default)]
+pub struct LineSets {
+    /// Lines of the set being sent
+    pub current: Vec<EngineLine>,
+    /// Lines of a complete set
+    multipv: u16,
+    /// Depth of the last complete set
+    depth: u32,
+    complete: Option<Vec<EngineLine>>,
+}
+
+impl LineSets {
+    pub fn new(multipv: u16) -> Self {
+        LineSets {
+            multipv,
+            ..Default::default()
+        }
+    }
+
+    /// Adds a line to the current set. Returns true when it completes a set at a new
+    /// depth, which is kept until `take_complete`, the others being dropped.
+    pub fn push(&mut self, line: EngineLine) -> bool {
+        let multipv = line.multipv;
+        let depth = line.depth;
+        let mut completed = false;
+        if multipv as usize == self.current.len() + 1 {
+            self.current.push(line);
+            if multipv == self.multipv {
+                if self.current.iter().all(|x| x.depth == depth) && depth >= self.depth {
+                    self.depth = depth;
+                    let lines = Vec::with_capacity(self.multipv as usize);
+                    self.complete = Some(std::mem::replace(&mut self.current, lines));
+                    completed = true;
+                }
+                self.current.clear();
+            }
+        }
+        completed
+    }
+
+    /// The last complete set, if there's one since it was last taken
+    pub fn take_complete(&mut self) -> Option<Vec<EngineLine>> {
+        self.complete.take()
+    }
+}
+
+/// Plays the first `max_length` moves of `pv` from `pos`, giving them in UCI and in
+/// SAN. None when one of them isn't legal.
+pub fn pv_moves(pos: &Chess, pv: &str, max_length: usize) -> Option<(Vec<String>, Vec<String>)> {
+    let mut pos = pos.clone();
+    let mut uci_moves = Vec::new();
+    let mut san_moves = Vec::new();
+    for token in pv.split_ascii_whitespace().take(max_length) {
+        let m = Uci::from_ascii(token.as_bytes()).ok()?.to_move(&pos).ok()?;
+        san_moves.push(SanPlus::from_move_and_play_unchecked(&mut pos, &m).to_string());
+        uci_moves.push(token.to_string());
+    }
+    Some((uci_moves, san_moves))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_info_lines() {
+        let info = parse_info(
+            "info depth 24 seldepth 30 multipv 2 score cp -35 upperbound wdl 10 900 90 \
+             nodes 1234567 nps 987654 hashfull 120 tbhits 0 time 1250 pv e7e5 g1f3 b8c6",
+        )
+        .unwrap();
+        assert_eq!((info.depth, info.multipv), (24, 2));
+        assert_eq!((info.nodes, info.nps), (1_234_567, 987_654));
+        let score = info.score.unwrap();
+        assert!(matches!(score.value, ScoreValue::Cp(-35)));
+        assert_eq!(score.wdl, Some((10, 900, 90)));
+        assert_eq!(info.pv, "e7e5 g1f3 b8c6");
+
+        let info = parse_info("info multipv 1 pv e2e4  e7e5 score mate 3 string pv d2d4").unwrap();
+        assert_eq!(info.pv, "e2e4  e7e5");
+        assert!(matches!(info.score.unwrap().value, ScoreValue::Mate(3)));
+
+        let info = parse_info("info depth 5 currmove e2e4 currmovenumber 1").unwrap();
+        assert_eq!((info.pv, info.multipv), ("", 1));
+        assert!(info.score.is_none());
+        assert!(parse_info("bestmove e2e4 ponder e7e5").is_none());
+        assert!(parse_info("information").is_none());
+    }
+
+    fn line(depth: u32, multipv: u16) -> EngineLine {
+        let info = format!("info depth {depth} multipv {multipv} score cp 20 pv e2e4");
+        EngineLine::from_info(parse_info(&info).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn keeps_complete_sets() {
+        let mut sets = LineSets::new(2);
+        assert!(!sets.push(line(10, 1)));
+        assert!(sets.push(line(10, 2)));
+        // Sets of lines at different depths, or shallower ones, aren't complete
+        assert!(!sets.push(line(11, 1)));
+        assert!(!sets.push(line(10, 2)));
+        assert!(!sets.push(line(9, 1)));
+        assert!(!sets.push(line(9, 2)));
+        assert!(!sets.push(line(12, 2)));
+        assert!(sets.current.is_empty());
+
+        let complete = sets.take_complete().unwrap();
+        assert_eq!(
+            complete.iter().map(|l| l.depth).collect::<Vec<_>>(),
+            [10, 10]
+        );
+        assert!(sets.take_complete().is_none());
+        assert!(EngineLine::from_info(parse_info("info depth 3 nodes 10").unwrap()).is_none());
+    }
+
+    #[test]
+    fn plays_pv_moves() {
+        let (uci, san) = pv_moves(&Chess::default(), "e2e4 e7e5 g1f3", usize::MAX).unwrap();
+        assert_eq!(uci, ["e2e4", "e7e5", "g1f3"]);
+        assert_eq!(san, ["e4", "e5", "Nf3"]);
+        assert_eq!(pv_moves(&Chess::default(), "e2e4 e2e4", usize::MAX), None);
+
+        let (uci, san) = pv_moves(&Chess::default(), "e2e4 e7e5 e2e4", 2).unwrap();
+        assert_eq!((uci.len(), san.len()), (2, 2));
+    }
+}
@@ -19,6 +19,7 @@ import {
   activeTabAtom,
   fontSizeAtom,
   nativeBarAtom,
+  offlineModeAtom,
   pieceSetAtom,
   primaryColorAtom,
   spellCheckAtom,
@@ -82,6 +83,7 @@ export default function App() {
   const [, setTabs] = useAtom(tabsAtom);
   const [, setActiveTab] = useAtom(activeTabAtom);
   const isNative = useAtomValue(nativeBarAtom);
+  const offlineMode = useAtomValue(offlineModeAtom);
 
   useEffect(() => {
     setTimeout(() => {
@@ -89,6 +91,10 @@ export default function App() {
     }, 100);
   }, [isNative]);
 
+  useEffect(() => {
+    commands.setOfflineMode(offlineMode);
+  }, [offlineMode]);
+
   useEffect(() => {
     (async () => {
       await commands.closeSplashscreen();
@@ -68,9 +68,9 @@ try {
 async memorySize() : Promise<number> {
 return await TAURI_INVOKE("plugin:tauri-specta|memory_size");
 },
-async getPuzzle(file: string, minRating: number, maxRating: number) : Promise<__Result__<{ id: number; fen: string; moves: string; rating: number; rating_deviation: number; popularity: number; nb_plays: number }, string>> {
+async getPuzzle(file: string, query: { minRating?: number; maxRating?: number; themes?: string[]; excludeSeen?: boolean }) : Promise<__Result__<{ id: number; fen: string; moves: string; rating: number; rating_deviation: number; popularity: number; nb_plays: number; themes: string; san: string[] }, string>> {
 try {
-    return { status: "ok", data: await TAURI_INVOKE("plugin:tauri-specta|get_puzzle", { file, minRating, maxRating }) };
+    return { status: "ok", data: await TAURI_INVOKE("plugin:tauri-specta|get_puzzle", { file, query }) };
 } catch (e) {
     if(e instanceof Error) throw e;
     else return { status: "error", error: e  as any };
@@ -82,6 +82,9 @@ return await TAURI_INVOKE("plugin:tauri-specta|set_menu_visisble", { state });
 async isMenuVisisble() : Promise<boolean> {
 return await TAURI_INVOKE("plugin:tauri-specta|is_menu_visisble");
 },
+async setOfflineMode(offline: boolean) : Promise<null> {
+return await TAURI_INVOKE("plugin:tauri-specta|set_offline_mode", { offline });
+},
 async getOpeningFromFen(fen: string) : Promise<__Result__<string, string>> {
 try {
     return { status: "ok", data: await TAURI_INVOKE("plugin:tauri-specta|get_opening_from_fen", { fen }) };
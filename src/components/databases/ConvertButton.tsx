@@ -8,6 +8,7 @@ import { useTranslation } from "react-i18next";
 type Progress = {
   total: number;
   elapsed: number;
+  percent: number;
 };
 
 function ConvertButton({
@@ -24,7 +25,11 @@ function ConvertButton({
     async function getProgress() {
       await listen<number[]>("convert_progress", (event) => {
         const progress = event.payload;
-        setProgress({ total: progress[0], elapsed: progress[1] / 1000 });
+        setProgress({
+          total: progress[0],
+          elapsed: progress[1] / 1000,
+          percent: progress[2],
+        });
       });
     }
     getProgress();
@@ -49,6 +54,7 @@ function ConvertButton({
         {progress && loading && (
           <Box style={{ display: "flex", justifyContent: "space-around" }}>
             <Text fz="xs">{progress.total} games</Text>
+            <Text fz="xs">{progress.percent.toFixed(1)}%</Text>
             <Text fz="xs" mb={10}>
               {(progress.total / progress.elapsed).toFixed(1)} games/s
             </Text>
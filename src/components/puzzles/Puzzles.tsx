@@ -101,19 +101,21 @@ function Puzzles({ id }: { id: string }) {
         setRatingRange([rating + 50, rating + 100]);
       }
     }
-    commands.getPuzzle(db, range[0], range[1]).then((res) => {
-      const puzzle = unwrap(res);
-      const newPuzzle: Puzzle = {
-        ...puzzle,
-        moves: puzzle.moves.split(" "),
-        completion: "incomplete",
-      };
-      setPuzzles((puzzles) => {
-        return [...puzzles, newPuzzle];
+    commands
+      .getPuzzle(db, { minRating: range[0], maxRating: range[1] })
+      .then((res) => {
+        const puzzle = unwrap(res);
+        const newPuzzle: Puzzle = {
+          ...puzzle,
+          moves: puzzle.moves.split(" "),
+          completion: "incomplete",
+        };
+        setPuzzles((puzzles) => {
+          return [...puzzles, newPuzzle];
+        });
+        setCurrentPuzzle(puzzles.length);
+        setPuzzle(newPuzzle);
       });
-      setCurrentPuzzle(puzzles.length);
-      setPuzzle(newPuzzle);
-    });
   }
 
   function changeCompletion(completion: Completion) {
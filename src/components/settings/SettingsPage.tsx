@@ -4,11 +4,13 @@ import {
   autoSaveAtom,
   enableBoardScrollAtom,
   forcedEnPassantAtom,
+  gaviotaDirAtom,
   minimumGamesAtom,
   moveInputAtom,
   moveMethodAtom,
   moveNotationTypeAtom,
   nativeBarAtom,
+  offlineModeAtom,
   percentageCoverageAtom,
   previewBoardOnHoverAtom,
   showArrowsAtom,
@@ -73,6 +75,7 @@ export default function Page() {
   } = useLoaderData({ from: "/settings" });
   let [filesDirectory, setFilesDirectory] = useAtom(storedDocumentDirAtom);
   filesDirectory = filesDirectory || documentDir;
+  const [gaviotaDirectory, setGaviotaDirectory] = useAtom(gaviotaDirAtom);
 
   const [moveMethod, setMoveMethod] = useAtom(moveMethodAtom);
   const [moveNotationType, setMoveNotationType] = useAtom(moveNotationTypeAtom);
@@ -293,6 +296,20 @@ export default function Page() {
                 </div>
                 <SettingsSwitch atom={enableBoardScrollAtom} />
               </Group>
+              <Group
+                justify="space-between"
+                wrap="nowrap"
+                gap="xl"
+                className={classes.item}
+              >
+                <div>
+                  <Text>{t("Settings.OfflineMode")}</Text>
+                  <Text size="xs" c="dimmed">
+                    {t("Settings.OfflineMode.Desc")}
+                  </Text>
+                </div>
+                <SettingsSwitch atom={offlineModeAtom} />
+              </Group>
             </Tabs.Panel>
 
             <Tabs.Panel value="inputs">
@@ -638,6 +655,30 @@ export default function Page() {
                   filename={filesDirectory || null}
                 />
               </Group>
+              <Group
+                justify="space-between"
+                wrap="nowrap"
+                gap="xl"
+                className={classes.item}
+              >
+                <div>
+                  <Text>{t("Settings.Directories.Gaviota")}</Text>
+                  <Text size="xs" c="dimmed">
+                    {t("Settings.Directories.Gaviota.Desc")}
+                  </Text>
+                </div>
+                <FileInput
+                  onClick={async () => {
+                    const selected = await open({
+                      multiple: false,
+                      directory: true,
+                    });
+                    if (!selected || typeof selected !== "string") return;
+                    setGaviotaDirectory(selected);
+                  }}
+                  filename={gaviotaDirectory || null}
+                />
+              </Group>
             </Tabs.Panel>
           </Card>
         </ScrollArea>
@@ -105,6 +105,7 @@ export const storedDocumentDirAtom = atomWithStorage<string>(
   undefined,
   { getOnInit: true },
 );
+export const gaviotaDirAtom = atomWithStorage<string>("gaviota-dir", "");
 
 // Settings
 
@@ -178,6 +179,7 @@ export const primaryColorAtom = atomWithStorage<MantineColor>(
 );
 export const sessionsAtom = atomWithStorage<Session[]>("sessions", []);
 export const nativeBarAtom = atomWithStorage<boolean>("native-bar", false);
+export const offlineModeAtom = atomWithStorage<boolean>("offline-mode", false);
 
 // Database
 
@@ -299,6 +299,9 @@ export const en_US = {
     "Settings.ScrollThroughMoves": "Scroll Through Moves",
     "Settings.ScrollThroughMoves.Desc":
       "Enable or disable scrolling through moves on the chessboard",
+    "Settings.OfflineMode": "Offline Mode",
+    "Settings.OfflineMode.Desc":
+      "Disable the lichess cloud evaluations, explorer and tablebase, online imports and downloads",
     "Settings.Inputs.Desc": "Customize the input settings",
     "Settings.Inputs.TextInput": "Text Move Input",
     "Settings.Inputs.TextInput.Desc": "Enter moves in text format",
@@ -346,6 +349,9 @@ export const en_US = {
     "Settings.Directories.Files": "Files directory",
     "Settings.Directories.Files.Desc":
       "This is where your games in the Files page are stored",
+    "Settings.Directories.Gaviota": "Gaviota tablebases",
+    "Settings.Directories.Gaviota.Desc":
+      "Uncompressed Gaviota tables, for the distance to mate",
 
     "PgnInput.Comments": "Comments",
     "PgnInput.Glyphs": "Glyphs",
@@ -25,6 +25,12 @@ export interface DatabaseInfo {
   error?: string;
   file: string;
   indexed: boolean;
+  first_date?: string | null;
+  last_date?: string | null;
+  version?: string;
+  position_index?: boolean;
+  position_plies?: number | null;
+  malformed_games?: number;
 }
 
 interface Query {